@@ -0,0 +1,319 @@
+// Generated from the Rust definitions by rmt-core (src/ts.rs). Do not edit by hand.
+// Regenerate with: UPDATE_TS_BINDINGS=1 cargo test ts::
+
+export interface SimpleFraction {
+  s: number;
+  n: number;
+  d: number;
+}
+
+export interface PowerTermData {
+  base: number;
+  exp: SimpleFraction;
+}
+
+export interface SymbolicPowerData {
+  coefficient: SimpleFraction;
+  powers: PowerTermData[];
+}
+
+export interface FractionData {
+  s: number;
+  n: number;
+  d: number;
+  f?: number;
+  err?: number;
+  corrupted: boolean;
+  symbolic?: SymbolicPowerData;
+}
+
+export interface CorruptionSource {
+  variable: string;
+  op: string;
+  pc: number;
+  lossy?: boolean;
+}
+
+export interface EvaluatedNote {
+  startTime?: FractionData;
+  duration?: FractionData;
+  frequency?: FractionData;
+  tempo?: FractionData;
+  beatsPerMeasure?: FractionData;
+  measureLength?: FractionData;
+  corruptionFlags: number;
+  customVars?: Map<number, FractionData>;
+  customCorruptionFlags?: number;
+  corruptionSources?: CorruptionSource[];
+  instrument?: number;
+}
+
+export interface ValueData {
+  s?: number;
+  n?: number;
+  d?: number;
+  f?: number;
+  err?: number;
+  corrupted: boolean;
+  symbolic?: SymbolicPowerData;
+}
+
+export interface CompiledExpression {
+  bytecode: number[];
+  dependencies: number[];
+  referencesBase: boolean;
+  sourceText: string;
+}
+
+export interface ValidationInfo {
+  maxDepth: number;
+  noteIds: number[];
+  referencesBase: boolean;
+}
+
+export interface OpcodeCount {
+  op: string;
+  opcode: number;
+  count: number;
+}
+
+export interface BytecodeStats {
+  expressions: number;
+  instructions: number;
+  totalBytes: number;
+  constants: number;
+  maxDepth: number;
+  opcodes: OpcodeCount[];
+}
+
+export interface JsExpression {
+  bytecode: number[];
+  length: number;
+}
+
+export interface JsExpressions {
+  startTime?: JsExpression;
+  duration?: JsExpression;
+  frequency?: JsExpression;
+  tempo?: JsExpression;
+  beatsPerMeasure?: JsExpression;
+  measureLength?: JsExpression;
+}
+
+export interface GraphStats {
+  noteCount: number;
+  totalDependencies: number;
+  avgDependencies: number;
+  maxDependencies: number;
+  maxDependents: number;
+  baseNoteDependents: number;
+}
+
+export interface GraphSyncNote {
+  id: number;
+  deps: number[];
+  referencesBase: boolean;
+}
+
+export interface GraphSyncData {
+  notes: GraphSyncNote[];
+}
+
+export interface GraphMemoryStats {
+  nodeCount: number;
+  edgeCount: number;
+  estimatedBytes: number;
+}
+
+export interface EvaluatorMemoryStats {
+  cacheEntries: number;
+  cacheBytes: number;
+  bytecodeEntries: number;
+  bytecodeBytes: number;
+  dirtyEntries: number;
+  dirtyBytes: number;
+  stackBytes: number;
+  totalBytes: number;
+}
+
+export interface InternStats {
+  expressions: number;
+  uniquePrograms: number;
+  savedBytes: number;
+}
+
+export interface NoteBytecodeSize {
+  startTime?: number;
+  duration?: number;
+  frequency?: number;
+  tempo?: number;
+  beatsPerMeasure?: number;
+  measureLength?: number;
+  totalBytes: number;
+}
+
+export interface MemoryReport {
+  wasmPages: number;
+  wasmBytes: number;
+  evaluator: EvaluatorMemoryStats;
+  graph: GraphMemoryStats;
+  totalEstimatedBytes: number;
+}
+
+export interface MidiPosition {
+  note: number;
+  cents: number;
+}
+
+export interface PitchDescription {
+  midiNote: number;
+  name: string;
+  centsOffset: number;
+}
+
+export interface AudioEvents {
+  ids: number[];
+  startSamples: number[];
+  durationSamples: number[];
+  frequencies: number[];
+}
+
+export interface NoteTiming {
+  id: number;
+  startTime: number;
+  duration: number;
+  frequency?: number;
+}
+
+export interface CacheMerge {
+  imported: number[];
+  skipped: number[];
+  invalid: Map<string, string>;
+}
+
+export interface CacheProblem {
+  id: number;
+  var: string;
+  problem: string;
+}
+
+export interface CorruptedNote {
+  id: number;
+  corruptionFlags: number;
+  customCorruptionFlags?: number;
+}
+
+export interface CorruptionSummary {
+  notes: number;
+  startTime: number;
+  duration: number;
+  frequency: number;
+  tempo: number;
+  beatsPerMeasure: number;
+  measureLength: number;
+  custom: number;
+}
+
+export interface ModuleExtent {
+  start: number;
+  end: number;
+  lastNoteId: number;
+}
+
+export interface ModuleExtentExact {
+  start: FractionData;
+  end: FractionData;
+  lastNoteId: number;
+}
+
+export interface StrictViolation {
+  noteId: number;
+  variable: string;
+  op: string;
+  pc: number;
+}
+
+export interface EvalErrorData {
+  code: string;
+  message: string;
+  pc: number;
+  op?: string;
+  noteId?: number;
+  var?: string;
+}
+
+export interface EvaluationStats {
+  expressions: number;
+  instructions: number;
+  maxInstructions: number;
+}
+
+export interface SignedInput {
+  s?: number;
+  n: number;
+  d: number;
+  f?: number;
+  corrupted: boolean;
+}
+
+export interface DefaultsData {
+  startTime?: SignedInput;
+  duration?: SignedInput;
+  frequency?: SignedInput;
+  tempo?: SignedInput;
+  beatsPerMeasure?: SignedInput;
+  measureLength?: SignedInput;
+}
+
+export interface TraceStep {
+  pc: number;
+  op: string;
+  operands: string[];
+  stack: string[];
+}
+
+export interface TracedEvaluation {
+  result?: FractionData;
+  error?: EvalErrorData;
+  trace: TraceStep[];
+}
+
+export interface ModuleError {
+  noteId?: number;
+  variable?: string;
+  message: string;
+}
+
+export interface ModuleExpression {
+  noteId: number;
+  variable: string;
+  text: string;
+}
+
+export interface ModuleNote {
+  id: number;
+  color?: string;
+  instrument?: string;
+}
+
+export interface ParsedModule {
+  version: number;
+  notes: ModuleNote[];
+  expressions: ModuleExpression[];
+  errors: ModuleError[];
+}
+
+export interface DiagnosticTimings {
+  fraction: number;
+  compile: number;
+  graph: number;
+  evaluator: number;
+  total: number;
+}
+
+export interface DiagnosticsReport {
+  version: string;
+  passed: boolean;
+  failures: string[];
+  timingsMs: DiagnosticTimings;
+}
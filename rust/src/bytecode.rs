@@ -3,17 +3,27 @@
 //! Defines opcodes and variable indices that match the JavaScript implementation
 //! in binary-note.js for full compatibility.
 
+use crate::fraction::Fraction;
+use crate::value::Value;
 use num_bigint::{BigInt, Sign};
+use num_traits::{Signed, ToPrimitive};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use wasm_bindgen::prelude::*;
 
 /// Bytecode opcodes matching JavaScript OP constants
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Op {
     // Load operations
     LoadConst = 0x01,      // Push Fraction constant: [num_hi, num_lo, num_lo2, num_lo3, den_hi, den_lo, den_lo2, den_lo3]
     LoadRef = 0x02,        // Push note reference: [noteId_hi, noteId_lo, varIndex]
     LoadBase = 0x03,       // Push baseNote variable: [varIndex]
     LoadConstBig = 0x04,   // Push BigInt Fraction: [sign(1), num_len(2), num_bytes(n), den_len(2), den_bytes(n)]
+    LoadRefWide = 0x05,    // Push note reference for ids above 65535: [noteId(4), varIndex]
 
     // Arithmetic operations
     Add = 0x10,            // Pop 2, push sum
@@ -22,15 +32,33 @@ pub enum Op {
     Div = 0x13,            // Pop 2, push quotient
     Neg = 0x14,            // Pop 1, push negation
     Pow = 0x15,            // Pop 2 (base, exponent), push base^exponent (may corrupt to irrational)
+    Mod = 0x16,            // Pop 2, push remainder (sign of dividend)
+    Min = 0x1F,            // Pop 2, push the smaller
+    Max = 0x24,            // Pop 2, push the larger
+    Sqrt = 0x17,           // Pop 1, push square root (stays rational for perfect squares)
+    Root = 0x18,           // Pop 2 (value, integer n), push n-th root
+    Log = 0x19,            // Pop 2 (value, base), push log_base(value) (exact for matching powers)
+    Abs = 0x1A,            // Pop 1, push absolute value (symbolic stays symbolic)
+    Sign = 0x1B,           // Pop 1, push -1, 0 or 1 as a rational
 
     // Module lookup operations
     FindTempo = 0x20,      // Pop noteRef, push tempo lookup result
     FindMeasure = 0x21,    // Pop noteRef, push measureLength lookup result
     FindInstrument = 0x22, // Pop noteRef, push instrument lookup result
+    FindBeat = 0x23,       // Pop noteRef, push 60/tempo (one beat in seconds)
 
     // Stack operations
     Dup = 0x30,            // Duplicate top of stack
     Swap = 0x31,           // Swap top two stack values
+    Over = 0x33,           // Copy second value to top (a b -> a b a)
+    Rot = 0x34,            // Rotate top three values (a b c -> b c a)
+    Drop = 0x35,           // Discard top of stack
+    CallMacro = 0x36,      // Run a registered macro on the stack [macroId(2)]
+
+    // Rounding operations (results are always rational integers)
+    Floor = 0x1C,          // Pop 1, push largest integer <= value
+    Ceil = 0x1D,           // Pop 1, push smallest integer >= value
+    Round = 0x1E,          // Pop 1, push nearest integer (halves away from zero)
 }
 
 impl Op {
@@ -41,17 +69,34 @@ impl Op {
             0x02 => Some(Op::LoadRef),
             0x03 => Some(Op::LoadBase),
             0x04 => Some(Op::LoadConstBig),
+            0x05 => Some(Op::LoadRefWide),
             0x10 => Some(Op::Add),
             0x11 => Some(Op::Sub),
             0x12 => Some(Op::Mul),
             0x13 => Some(Op::Div),
             0x14 => Some(Op::Neg),
             0x15 => Some(Op::Pow),
+            0x16 => Some(Op::Mod),
+            0x17 => Some(Op::Sqrt),
+            0x18 => Some(Op::Root),
+            0x19 => Some(Op::Log),
+            0x1A => Some(Op::Abs),
+            0x1B => Some(Op::Sign),
+            0x1C => Some(Op::Floor),
+            0x1D => Some(Op::Ceil),
+            0x1E => Some(Op::Round),
+            0x1F => Some(Op::Min),
             0x20 => Some(Op::FindTempo),
             0x21 => Some(Op::FindMeasure),
             0x22 => Some(Op::FindInstrument),
+            0x23 => Some(Op::FindBeat),
+            0x24 => Some(Op::Max),
             0x30 => Some(Op::Dup),
             0x31 => Some(Op::Swap),
+            0x33 => Some(Op::Over),
+            0x34 => Some(Op::Rot),
+            0x35 => Some(Op::Drop),
+            0x36 => Some(Op::CallMacro),
             _ => None,
         }
     }
@@ -109,6 +154,47 @@ impl Var {
     }
 }
 
+/// First variable index reserved for custom per-note variables
+pub const CUSTOM_VAR_FIRST: u8 = 32;
+/// Last variable index reserved for custom per-note variables
+pub const CUSTOM_VAR_LAST: u8 = 63;
+/// Prefix of custom variable names in `getVariable('custom:velocity')`
+pub const CUSTOM_VAR_PREFIX: &str = "custom:";
+
+/// Is `index` in the range reserved for custom variables?
+pub fn is_custom_var(index: u8) -> bool {
+    (CUSTOM_VAR_FIRST..=CUSTOM_VAR_LAST).contains(&index)
+}
+
+/// Name `getVariable` takes for variable `index`: the built-in name, or
+/// "custom:<index>" for a custom variable
+pub fn var_label(index: u8) -> Option<String> {
+    match Var::from_byte(index) {
+        Some(var) => Some(var.name().to_string()),
+        None => is_custom_var(index).then(|| format!("{}{}", CUSTOM_VAR_PREFIX, index)),
+    }
+}
+
+/// Method chains that bind like multiplication in expression text, with the
+/// opcode each emits (read by the compiler and by `decompile`)
+pub(crate) const PRODUCT_METHODS: [(&str, Op); 15] = [
+    ("mul", Op::Mul),
+    ("div", Op::Div),
+    ("mod", Op::Mod),
+    ("min", Op::Min),
+    ("max", Op::Max),
+    ("sqrt", Op::Sqrt),
+    ("root", Op::Root),
+    ("log", Op::Log),
+    ("abs", Op::Abs),
+    ("sign", Op::Sign),
+    ("floor", Op::Floor),
+    ("ceil", Op::Ceil),
+    ("round", Op::Round),
+    ("pow", Op::Pow),
+    ("neg", Op::Neg),
+];
+
 /// Read a 16-bit unsigned integer from bytecode (big-endian)
 #[inline]
 pub fn read_u16(bytecode: &[u8], offset: usize) -> u16 {
@@ -124,6 +210,12 @@ pub fn read_i32(bytecode: &[u8], offset: usize) -> i32 {
         | (bytecode[offset + 3] as i32)
 }
 
+/// Read a 32-bit unsigned integer from bytecode (big-endian)
+#[inline]
+pub fn read_u32(bytecode: &[u8], offset: usize) -> u32 {
+    read_i32(bytecode, offset) as u32
+}
+
 /// Write a 16-bit unsigned integer to a buffer (big-endian)
 #[inline]
 pub fn write_u16(buffer: &mut Vec<u8>, value: u16) {
@@ -140,6 +232,12 @@ pub fn write_i32(buffer: &mut Vec<u8>, value: i32) {
     buffer.push(value as u8);
 }
 
+/// Write a 32-bit unsigned integer to a buffer (big-endian)
+#[inline]
+pub fn write_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
 /// Read a variable-length signed BigInt from bytecode
 /// Format: [sign(1)] [len(2)] [bytes(n)]
 /// Returns (BigInt, bytes_consumed) or error
@@ -178,9 +276,1147 @@ pub fn read_big_int_unsigned(bytecode: &[u8], offset: usize) -> Result<(BigInt,
     Ok((value, 2 + len))
 }
 
+/// Big-endian magnitude bytes of a BigInt, if their count fits the 16-bit length prefix
+fn magnitude_bytes(value: &BigInt) -> Result<Vec<u8>, String> {
+    let bytes = value.magnitude().to_bytes_be();
+    if bytes.len() > u16::MAX as usize {
+        return Err(format!("BigInt of {} bytes exceeds the {}-byte limit", bytes.len(), u16::MAX));
+    }
+    Ok(bytes)
+}
+
+/// Write a variable-length signed BigInt to a buffer
+/// Format: [sign(1)] [len(2)] [bytes(n)]
+/// Returns an error, writing nothing, if the magnitude is over 65535 bytes
+pub fn write_big_int_signed(buffer: &mut Vec<u8>, value: &BigInt) -> Result<(), String> {
+    let bytes = magnitude_bytes(value)?;
+    buffer.push(if value.is_negative() { 0x01 } else { 0x00 });
+    write_u16(buffer, bytes.len() as u16);
+    buffer.extend_from_slice(&bytes);
+    Ok(())
+}
+
+/// Write a variable-length unsigned BigInt to a buffer (the sign is dropped)
+/// Format: [len(2)] [bytes(n)]
+/// Returns an error, writing nothing, if the magnitude is over 65535 bytes
+pub fn write_big_int_unsigned(buffer: &mut Vec<u8>, value: &BigInt) -> Result<(), String> {
+    let bytes = magnitude_bytes(value)?;
+    write_u16(buffer, bytes.len() as u16);
+    buffer.extend_from_slice(&bytes);
+    Ok(())
+}
+
+/// Append a constant, using LOAD_CONST when it fits in i32 and LOAD_CONST_BIG otherwise
+/// Returns an error, writing nothing, if a part is too large for LOAD_CONST_BIG
+pub fn write_const(buffer: &mut Vec<u8>, value: &Fraction) -> Result<(), String> {
+    let big = value.to_big_rational();
+    match (big.numer().to_i32(), big.denom().to_i32()) {
+        (Some(num), Some(den)) => {
+            buffer.push(Op::LoadConst as u8);
+            write_i32(buffer, num);
+            write_i32(buffer, den);
+        }
+        _ => {
+            let mut operands = Vec::new();
+            write_big_int_signed(&mut operands, big.numer())?;
+            write_big_int_unsigned(&mut operands, big.denom())?;
+            buffer.push(Op::LoadConstBig as u8);
+            buffer.extend_from_slice(&operands);
+        }
+    }
+    Ok(())
+}
+
+/// Write a note reference, as LOAD_REF when the id fits in 16 bits and
+/// LOAD_REF_WIDE otherwise
+pub fn write_load_ref(buffer: &mut Vec<u8>, note_id: u32, var_index: u8) {
+    match u16::try_from(note_id) {
+        Ok(id) => {
+            buffer.push(Op::LoadRef as u8);
+            write_u16(buffer, id);
+        }
+        Err(_) => {
+            buffer.push(Op::LoadRefWide as u8);
+            write_u32(buffer, note_id);
+        }
+    }
+    buffer.push(var_index);
+}
+
+/// Note id and variable index of a LOAD_REF or LOAD_REF_WIDE, given the
+/// operand bytes that follow the opcode
+pub fn note_ref_operands(op: Op, operands: &[u8]) -> Option<(u32, u8)> {
+    match op {
+        Op::LoadRef if operands.len() >= 3 => Some((read_u16(operands, 0) as u32, operands[2])),
+        Op::LoadRefWide if operands.len() >= 5 => Some((read_u32(operands, 0), operands[4])),
+        _ => None,
+    }
+}
+
+/// Note id pushed by the LOAD_CONST at `pc` when the instruction after it
+/// pops it as a note reference (see `Op::pops_note_ref`)
+pub fn constant_note_ref(bytecode: &[u8], pc: usize, length: usize) -> Option<u32> {
+    if bytecode.get(pc) != Some(&(Op::LoadConst as u8)) || pc + 10 > length.min(bytecode.len()) {
+        return None;
+    }
+    Op::from_byte(bytecode[pc + 9]).filter(|op| op.pops_note_ref())?;
+    let (num, den) = (read_i32(bytecode, pc + 1), read_i32(bytecode, pc + 5));
+    u32::try_from(num).ok().filter(|_| den == 1)
+}
+
+/// Length in bytes of the instruction at `pc`, including its operands
+pub fn instruction_len(bytecode: &[u8], pc: usize) -> Result<usize, String> {
+    let op_byte = *bytecode
+        .get(pc)
+        .ok_or_else(|| format!("Unexpected end of bytecode at pc={}", pc))?;
+    let op = Op::from_byte(op_byte)
+        .ok_or_else(|| format!("Unknown opcode: 0x{:02x} at pc={}", op_byte, pc))?;
+    Ok(match op {
+        Op::LoadConst => 9,
+        Op::LoadRef => 4,
+        Op::LoadRefWide => 6,
+        Op::LoadBase => 2,
+        Op::CallMacro => 3,
+        Op::LoadConstBig => {
+            let (_, num_bytes) = read_big_int_signed(bytecode, pc + 1)?;
+            let (_, den_bytes) = read_big_int_unsigned(bytecode, pc + 1 + num_bytes)?;
+            1 + num_bytes + den_bytes
+        }
+        _ => 1,
+    })
+}
+
+/// Operands of one instruction (`instruction` starts at its opcode) as
+/// display text: a constant as "n/d", a note reference as its id and
+/// variable name, a base reference as the variable name, a macro call as
+/// the macro id
+pub fn operand_text(instruction: &[u8]) -> Vec<String> {
+    let var_name = |index: u8| var_label(index).unwrap_or_else(|| index.to_string());
+    let Some(op) = instruction.first().copied().and_then(Op::from_byte) else {
+        return Vec::new();
+    };
+    let operands = &instruction[1..];
+    match op {
+        Op::LoadConst if operands.len() >= 8 => {
+            vec![Fraction::new(read_i32(operands, 0), read_i32(operands, 4)).to_string()]
+        }
+        Op::LoadConstBig => {
+            let constant = read_big_int_signed(instruction, 1).and_then(|(num, num_bytes)| {
+                read_big_int_unsigned(instruction, 1 + num_bytes).map(|(den, _)| Fraction::from_big_ints(num, den))
+            });
+            constant.map(|c| vec![c.to_string()]).unwrap_or_default()
+        }
+        Op::LoadRef | Op::LoadRefWide => note_ref_operands(op, operands)
+            .map(|(note_id, index)| vec![note_id.to_string(), var_name(index)])
+            .unwrap_or_default(),
+        Op::LoadBase if !operands.is_empty() => vec![var_name(operands[0])],
+        Op::CallMacro if operands.len() >= 2 => vec![read_u16(operands, 0).to_string()],
+        _ => Vec::new(),
+    }
+}
+
+impl Op {
+    /// Does the instruction pop a note reference (a note id pushed as an
+    /// integer constant just before it)?
+    pub fn pops_note_ref(self) -> bool {
+        matches!(self, Op::FindTempo | Op::FindMeasure | Op::FindInstrument | Op::FindBeat)
+    }
+
+    /// Number of values the instruction pops and pushes (for CALL_MACRO
+    /// that depends on the macro, see `Macro::effect`)
+    pub fn stack_effect(self) -> (usize, usize) {
+        match self {
+            Op::LoadConst | Op::LoadRef | Op::LoadRefWide | Op::LoadBase | Op::LoadConstBig => (0, 1),
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Pow | Op::Mod | Op::Min | Op::Max | Op::Root | Op::Log => (2, 1),
+            Op::Neg | Op::Sqrt | Op::Abs | Op::Sign | Op::Floor | Op::Ceil | Op::Round | Op::FindTempo | Op::FindMeasure | Op::FindInstrument | Op::FindBeat => (1, 1),
+            Op::Dup => (1, 2),
+            Op::Swap => (2, 2),
+            Op::Over => (2, 3),
+            Op::Rot => (3, 3),
+            Op::Drop => (1, 0),
+            Op::CallMacro => (0, 0),
+        }
+    }
+}
+
+/// What `validate` learned about a well-formed expression
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationInfo {
+    /// Deepest the stack gets while evaluating
+    #[serde(rename = "maxDepth")]
+    pub max_depth: usize,
+    /// Note ids read through LOAD_REF, LOAD_REF_WIDE or a FIND_* lookup,
+    /// sorted and without duplicates
+    #[serde(rename = "noteIds")]
+    pub note_ids: Vec<u32>,
+    /// Does the expression read the base note through LOAD_BASE or a
+    /// FIND_* lookup of note 0?
+    #[serde(rename = "referencesBase")]
+    pub references_base: bool,
+}
+
+/// Why `validate` rejected an expression
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The declared length runs past the end of the buffer
+    LengthOutOfRange { length: usize, size: usize },
+    /// A byte that is not an opcode
+    UnknownOpcode { pc: usize, byte: u8 },
+    /// An instruction whose operands run past the declared length
+    Truncated { pc: usize, op: Op },
+    /// A LOAD_REF, LOAD_REF_WIDE or LOAD_BASE variable index outside `Var`
+    InvalidVariable { pc: usize, index: u8 },
+    /// An instruction that pops more values than the stack holds
+    StackUnderflow { pc: usize, op: Op, depth: usize },
+    /// The expression does not leave exactly one value
+    FinalDepth { depth: usize },
+    /// A CALL_MACRO of a macro that is not registered
+    UnknownMacro { pc: usize, macro_id: u16 },
+    /// A CALL_MACRO inside a macro body
+    NestedMacro { pc: usize },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::LengthOutOfRange { length, size } => {
+                write!(f, "Length {} exceeds bytecode size {}", length, size)
+            }
+            ValidationError::UnknownOpcode { pc, byte } => write!(f, "Unknown opcode: 0x{:02x} at pc={}", byte, pc),
+            ValidationError::Truncated { pc, op } => write!(f, "Truncated {:?} at pc={}", op, pc),
+            ValidationError::InvalidVariable { pc, index } => write!(f, "Invalid variable index {} at pc={}", index, pc),
+            ValidationError::StackUnderflow { pc, op, depth } => {
+                write!(f, "Stack underflow at pc={}: {:?} with {} values on the stack", pc, op, depth)
+            }
+            ValidationError::FinalDepth { depth } => write!(f, "Expression leaves {} values on the stack", depth),
+            ValidationError::UnknownMacro { pc, macro_id } => write!(f, "Unknown macro {} at pc={}", macro_id, pc),
+            ValidationError::NestedMacro { pc } => write!(f, "Macro calls another macro at pc={}", pc),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Check bytecode without running it: every opcode is known, every operand
+/// is present within `length`, variable indices are valid, the stack never
+/// underflows and exactly one value is left
+pub fn validate(bytecode: &[u8], length: usize) -> Result<ValidationInfo, ValidationError> {
+    validate_with_macros(bytecode, length, &MacroTable::default())
+}
+
+/// `validate` for bytecode that may CALL_MACRO one of `macros`
+///
+/// A call counts as its macro's stack effect, and the notes the macro
+/// reads are reported as read by the caller.
+pub fn validate_with_macros(bytecode: &[u8], length: usize, macros: &MacroTable) -> Result<ValidationInfo, ValidationError> {
+    let (info, (_, depth)) = walk(bytecode, length, Walk::Program(macros), &mut |_, _| {})?;
+    if depth != 1 {
+        return Err(ValidationError::FinalDepth { depth });
+    }
+    Ok(info)
+}
+
+/// What the validator's walk is checking
+#[derive(Clone, Copy)]
+enum Walk<'a> {
+    /// An expression, which may call these macros
+    Program(&'a MacroTable),
+    /// A macro body, which may take values from its caller's stack but
+    /// not call a macro itself
+    Body,
+}
+
+/// Walk every instruction once, returning what `validate` reports and the
+/// values taken from below the starting stack and left on it
+///
+/// `visit` sees each checked instruction with its operand bytes.
+fn walk(
+    bytecode: &[u8],
+    length: usize,
+    mode: Walk,
+    visit: &mut dyn FnMut(Op, &[u8]),
+) -> Result<(ValidationInfo, (usize, usize)), ValidationError> {
+    let bytecode = bytecode
+        .get(..length)
+        .ok_or(ValidationError::LengthOutOfRange { length, size: bytecode.len() })?;
+    let mut info = ValidationInfo::default();
+    let mut depth: isize = 0;
+    let mut taken: usize = 0;
+    let mut pc = 0;
+    while pc < length {
+        let byte = bytecode[pc];
+        let op = Op::from_byte(byte).ok_or(ValidationError::UnknownOpcode { pc, byte })?;
+        let len = instruction_len(bytecode, pc).map_err(|_| ValidationError::Truncated { pc, op })?;
+        let operands = bytecode.get(pc + 1..pc + len).ok_or(ValidationError::Truncated { pc, op })?;
+
+        let var_index = match op {
+            Op::LoadRef | Op::LoadRefWide => {
+                let (note_id, index) = note_ref_operands(op, operands).ok_or(ValidationError::Truncated { pc, op })?;
+                info.note_ids.push(note_id);
+                Some(index)
+            }
+            Op::LoadBase => {
+                info.references_base = true;
+                Some(operands[0])
+            }
+            Op::LoadConst => {
+                match constant_note_ref(bytecode, pc, length) {
+                    Some(0) => info.references_base = true,
+                    Some(note_id) => info.note_ids.push(note_id),
+                    None => {}
+                }
+                None
+            }
+            _ => None,
+        };
+        if let Some(index) = var_index.filter(|&index| Var::from_byte(index).is_none() && !is_custom_var(index)) {
+            return Err(ValidationError::InvalidVariable { pc, index });
+        }
+
+        // (pops, pushes, highest point above the values popped)
+        let (pops, pushes, peak) = match (op, mode) {
+            (Op::CallMacro, Walk::Body) => return Err(ValidationError::NestedMacro { pc }),
+            (Op::CallMacro, Walk::Program(macros)) => {
+                let macro_id = read_u16(operands, 0);
+                let body = macros.get(macro_id).ok_or(ValidationError::UnknownMacro { pc, macro_id })?;
+                info.note_ids.extend_from_slice(&body.info.note_ids);
+                info.references_base |= body.info.references_base;
+                (body.effect.0, body.effect.1, body.effect.0 + body.info.max_depth)
+            }
+            _ => {
+                let (pops, pushes) = op.stack_effect();
+                (pops, pushes, pushes)
+            }
+        };
+        let missing = pops as isize - depth;
+        if missing > 0 {
+            match mode {
+                Walk::Body => taken = taken.max(missing as usize),
+                Walk::Program(_) => return Err(ValidationError::StackUnderflow { pc, op, depth: depth as usize }),
+            }
+        }
+        info.max_depth = info.max_depth.max((depth - pops as isize + peak as isize).max(0) as usize);
+        depth = depth - pops as isize + pushes as isize;
+        visit(op, &bytecode[pc..pc + len]);
+        pc += len;
+    }
+    info.note_ids.sort_unstable();
+    info.note_ids.dedup();
+    Ok((info, (taken, (depth + taken as isize) as usize)))
+}
+
+/// A macro body accepted by `MacroTable::register`
+#[derive(Clone, Debug)]
+pub struct Macro {
+    bytecode: Vec<u8>,
+    /// Values taken from the caller's stack and values left on it
+    effect: (usize, usize),
+    /// Stack height above the call and the notes read, relative to the call
+    info: ValidationInfo,
+}
+
+impl Macro {
+    pub fn bytecode(&self) -> &[u8] {
+        &self.bytecode
+    }
+
+    /// Values the macro pops from and pushes to its caller's stack
+    pub fn effect(&self) -> (usize, usize) {
+        self.effect
+    }
+
+    /// Note ids the macro reads (these are dependencies of every caller)
+    pub fn note_ids(&self) -> &[u32] {
+        &self.info.note_ids
+    }
+}
+
+/// Bytecode shared between expressions through CALL_MACRO
+#[derive(Clone, Debug, Default)]
+pub struct MacroTable {
+    macros: HashMap<u16, Macro>,
+}
+
+impl MacroTable {
+    /// Check a macro body and store it under `macro_id`, replacing any
+    /// macro with that id
+    ///
+    /// A body may use values already on its caller's stack and leave any
+    /// number behind, but may not call a macro itself.
+    pub fn register(&mut self, macro_id: u16, bytecode: &[u8], length: usize) -> Result<(), ValidationError> {
+        let (info, effect) = walk(bytecode, length, Walk::Body, &mut |_, _| {})?;
+        let bytecode = bytecode[..length].to_vec();
+        self.macros.insert(macro_id, Macro { bytecode, effect, info });
+        Ok(())
+    }
+
+    pub fn get(&self, macro_id: u16) -> Option<&Macro> {
+        self.macros.get(&macro_id)
+    }
+
+    pub fn clear(&mut self) {
+        self.macros.clear();
+    }
+
+    /// Rewrite the note references of every body (see `remap_note_ids`)
+    pub fn remap_note_ids(&self, mapping: &HashMap<u32, u32>) -> Result<MacroTable, String> {
+        let mut remapped = MacroTable::default();
+        for (&macro_id, body) in &self.macros {
+            let bytecode = remap_note_ids(&body.bytecode, body.bytecode.len(), mapping)
+                .map_err(|e| format!("Macro {}: {}", macro_id, e))?;
+            remapped.register(macro_id, &bytecode, bytecode.len()).map_err(|e| format!("Macro {}: {}", macro_id, e))?;
+        }
+        Ok(remapped)
+    }
+}
+
+/// Validate bytecode from JavaScript, returning
+/// `{ maxDepth, noteIds, referencesBase }` or throwing the reason
+#[wasm_bindgen(js_name = validateBytecode)]
+pub fn validate_bytecode_js(bytecode: &[u8]) -> Result<JsValue, JsValue> {
+    let info = validate(bytecode, bytecode.len()).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    serde_wasm_bindgen::to_value(&info).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Instruction mix and size of one or more expressions (see `analyze`)
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BytecodeStats {
+    /// Expressions counted
+    pub expressions: usize,
+    pub instructions: usize,
+    /// Bytes of bytecode, operands included
+    #[serde(rename = "totalBytes")]
+    pub total_bytes: usize,
+    /// LOAD_CONST and LOAD_CONST_BIG instructions
+    pub constants: usize,
+    /// Deepest stack reached by any of the expressions
+    #[serde(rename = "maxDepth")]
+    pub max_depth: usize,
+    /// Instructions per opcode, in opcode order, without unused opcodes
+    pub opcodes: Vec<OpcodeCount>,
+}
+
+/// How often one opcode occurs (see `BytecodeStats`)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpcodeCount {
+    /// Opcode name, e.g. "LoadConst"
+    pub op: String,
+    pub opcode: u8,
+    pub count: usize,
+}
+
+impl BytecodeStats {
+    fn add_instructions(&mut self, opcode: u8, count: usize, name: impl FnOnce() -> String) {
+        self.instructions += count;
+        match self.opcodes.binary_search_by_key(&opcode, |entry| entry.opcode) {
+            Ok(i) => self.opcodes[i].count += count,
+            Err(i) => self.opcodes.insert(i, OpcodeCount { op: name(), opcode, count }),
+        }
+    }
+
+    /// Add the counts of `other`, as if its expressions were analyzed too
+    pub fn merge(&mut self, other: &BytecodeStats) {
+        self.expressions += other.expressions;
+        self.total_bytes += other.total_bytes;
+        self.constants += other.constants;
+        self.max_depth = self.max_depth.max(other.max_depth);
+        for entry in &other.opcodes {
+            self.add_instructions(entry.opcode, entry.count, || entry.op.clone());
+        }
+    }
+}
+
+/// Count the instructions of a valid expression, by the same walk as `validate`
+pub fn analyze(bytecode: &[u8], length: usize) -> Result<BytecodeStats, ValidationError> {
+    analyze_with_macros(bytecode, length, &MacroTable::default())
+}
+
+/// `analyze` for bytecode that may CALL_MACRO one of `macros`
+///
+/// Only the caller's instructions are counted; `max_depth` includes the
+/// macro bodies.
+pub fn analyze_with_macros(bytecode: &[u8], length: usize, macros: &MacroTable) -> Result<BytecodeStats, ValidationError> {
+    let mut stats = BytecodeStats { expressions: 1, ..BytecodeStats::default() };
+    let (info, (_, depth)) = walk(bytecode, length, Walk::Program(macros), &mut |op, instruction| {
+        stats.total_bytes += instruction.len();
+        if matches!(op, Op::LoadConst | Op::LoadConstBig) {
+            stats.constants += 1;
+        }
+        stats.add_instructions(op as u8, 1, || format!("{:?}", op));
+    })?;
+    if depth != 1 {
+        return Err(ValidationError::FinalDepth { depth });
+    }
+    stats.max_depth = info.max_depth;
+    Ok(stats)
+}
+
+/// Why `decompile` could not produce source text
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecompileError {
+    /// The bytecode fails `validate`
+    Invalid(ValidationError),
+    /// An instruction no expression text compiles to: FIND_TEMPO,
+    /// FIND_MEASURE, FIND_INSTRUMENT, CALL_MACRO, or FIND_BEAT of a
+    /// computed note id
+    NoSourceForm { pc: usize, op: Op },
+}
+
+impl fmt::Display for DecompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecompileError::Invalid(e) => write!(f, "Invalid bytecode: {}", e),
+            DecompileError::NoSourceForm { pc, op } => write!(f, "No source text for {:?} at pc={}", op, pc),
+        }
+    }
+}
+
+impl std::error::Error for DecompileError {}
+
+impl From<ValidationError> for DecompileError {
+    fn from(e: ValidationError) -> Self {
+        DecompileError::Invalid(e)
+    }
+}
+
+/// Where a decompiled operand's text may go without parentheses: a sum
+/// is wrapped when it is the target of a method or an `.add`/`.sub` argument
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Form {
+    Atom,
+    Product,
+    Sum,
+}
+
+#[derive(Clone)]
+struct Source {
+    text: String,
+    form: Form,
+    /// Integer constant that a FIND_BEAT can read as a note id
+    note_id: Option<u32>,
+}
+
+impl Source {
+    fn new(text: String, form: Form) -> Source {
+        Source { text, form, note_id: None }
+    }
+
+    fn literal(num: &BigInt, den: &BigInt) -> Source {
+        let text = if den == &BigInt::from(1) {
+            format!("new Fraction({})", num)
+        } else {
+            format!("new Fraction({}, {})", num, den)
+        };
+        Source::new(text, Form::Atom)
+    }
+
+    fn wrapped(&self) -> String {
+        match self.form {
+            Form::Sum => format!("({})", self.text),
+            Form::Atom | Form::Product => self.text.clone(),
+        }
+    }
+}
+
+/// Reconstruct expression text that `ExpressionCompiler` compiles back to
+/// this bytecode
+///
+/// Every instruction the compiler emits has a text form, so compiler output
+/// round-trips byte for byte. Other bytecode comes back normalized:
+/// constants are reduced, and DUP, OVER, SWAP, ROT and DROP are expanded
+/// by repeating or dropping the text of their operands.
+pub fn decompile(bytecode: &[u8], length: usize) -> Result<String, DecompileError> {
+    validate(bytecode, length)?;
+    let truncated = |pc, op| DecompileError::Invalid(ValidationError::Truncated { pc, op });
+
+    let mut stack: Vec<Source> = Vec::new();
+    let mut pc = 0;
+    while pc < length {
+        let op = Op::from_byte(bytecode[pc]).ok_or(truncated(pc, Op::LoadConst))?;
+        let len = instruction_len(bytecode, pc).map_err(|_| truncated(pc, op))?;
+        let operands = &bytecode[pc + 1..pc + len];
+        let mut args = stack.split_off(stack.len() - op.stack_effect().0);
+        let var_name = |index: u8| var_label(index).unwrap_or_default();
+
+        match op {
+            Op::LoadConst => {
+                let (num, den) = (read_i32(operands, 0), read_i32(operands, 4));
+                let mut literal = Source::literal(&num.into(), &den.into());
+                literal.note_id = u32::try_from(num).ok().filter(|_| den == 1);
+                stack.push(literal);
+            }
+            Op::LoadConstBig => {
+                let (num, num_bytes) = read_big_int_signed(bytecode, pc + 1).map_err(|_| truncated(pc, op))?;
+                let (den, _) = read_big_int_unsigned(bytecode, pc + 1 + num_bytes).map_err(|_| truncated(pc, op))?;
+                stack.push(Source::literal(&num, &den));
+            }
+            Op::LoadRef | Op::LoadRefWide => {
+                let (note_id, index) = note_ref_operands(op, operands).ok_or(truncated(pc, op))?;
+                let text = format!("module.getNoteById({}).getVariable('{}')", note_id, var_name(index));
+                stack.push(Source::new(text, Form::Atom));
+            }
+            Op::LoadBase => {
+                let text = format!("module.baseNote.getVariable('{}')", var_name(operands[0]));
+                stack.push(Source::new(text, Form::Atom));
+            }
+            Op::FindBeat => {
+                let note = match args[0].note_id {
+                    Some(0) => "module.baseNote".to_string(),
+                    Some(note_id) => format!("module.getNoteById({})", note_id),
+                    None => return Err(DecompileError::NoSourceForm { pc, op }),
+                };
+                let text = format!("new Fraction(60).div(module.findTempo({}))", note);
+                stack.push(Source::new(text, Form::Product));
+            }
+            Op::FindTempo | Op::FindMeasure | Op::FindInstrument | Op::CallMacro => {
+                return Err(DecompileError::NoSourceForm { pc, op });
+            }
+            Op::Add | Op::Sub => {
+                let method = if op == Op::Add { "add" } else { "sub" };
+                let text = format!("{}.{}({})", args[0].text, method, args[1].wrapped());
+                stack.push(Source::new(text, Form::Sum));
+            }
+            Op::Dup | Op::Over => {
+                let copy = args[0].clone();
+                stack.extend(args);
+                stack.push(copy);
+            }
+            Op::Swap | Op::Rot => {
+                args.rotate_left(1);
+                stack.extend(args);
+            }
+            Op::Drop => {}
+            _ => {
+                let method = PRODUCT_METHODS.iter().find(|&&(_, method_op)| method_op == op).map_or("", |&(name, _)| name);
+                let argument = args.get(1).map_or(String::new(), |b| b.text.clone());
+                let text = format!("{}.{}({})", args[0].wrapped(), method, argument);
+                stack.push(Source::new(text, Form::Product));
+            }
+        }
+        pc += len;
+    }
+    Ok(stack.pop().map(|source| source.text).unwrap_or_default())
+}
+
+/// Decompile bytecode from JavaScript, throwing when it has no text form
+#[wasm_bindgen(js_name = decompileBytecode)]
+pub fn decompile_js(bytecode: &[u8]) -> Result<String, JsValue> {
+    decompile(bytecode, bytecode.len()).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// An instruction with its operands read the way the evaluator reads
+/// them, so different encodings of one program compare equal
+#[derive(Hash, PartialEq, Eq)]
+enum Decoded {
+    /// LOAD_CONST or LOAD_CONST_BIG, reduced
+    Const(BigInt, BigInt),
+    /// LOAD_REF or LOAD_REF_WIDE
+    Ref(u32, u8),
+    Base(u8),
+    Op(Op),
+}
+
+/// The instructions of valid bytecode, or None if it fails `validate`
+fn decode(bytecode: &[u8], length: usize) -> Option<Vec<Decoded>> {
+    validate(bytecode, length).ok()?;
+    let mut decoded = Vec::new();
+    let mut pc = 0;
+    while pc < length {
+        let op = Op::from_byte(bytecode[pc])?;
+        let len = instruction_len(bytecode, pc).ok()?;
+        let operands = &bytecode[pc + 1..pc + len];
+        let constant = match op {
+            Op::LoadConst => Some(Fraction::new(read_i32(operands, 0), read_i32(operands, 4))),
+            Op::LoadConstBig => {
+                let (num, num_bytes) = read_big_int_signed(bytecode, pc + 1).ok()?;
+                let (den, _) = read_big_int_unsigned(bytecode, pc + 1 + num_bytes).ok()?;
+                Some(Fraction::from_big_ints(num, den))
+            }
+            _ => None,
+        };
+        decoded.push(match (op, constant) {
+            (_, Some(constant)) => {
+                let big = constant.to_big_rational();
+                Decoded::Const(big.numer().clone(), big.denom().clone())
+            }
+            (Op::LoadRef | Op::LoadRefWide, _) => {
+                let (note_id, var_index) = note_ref_operands(op, operands)?;
+                Decoded::Ref(note_id, var_index)
+            }
+            (Op::LoadBase, _) => Decoded::Base(operands[0]),
+            _ => Decoded::Op(op),
+        });
+        pc += len;
+    }
+    Some(decoded)
+}
+
+/// Hash of the program in the first `length` bytes, equal for bytecode that
+/// `equivalent` considers the same
+///
+/// Constants are hashed reduced (LOAD_CONST 2/4, LOAD_CONST 1/2 and a
+/// LOAD_CONST_BIG of 1/2 hash alike) and LOAD_REF like LOAD_REF_WIDE of
+/// the same note. Bytecode that fails `validate` is hashed byte for byte.
+pub fn canonical_hash(bytecode: &[u8], length: usize) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    match decode(bytecode, length) {
+        Some(decoded) => decoded.hash(&mut hasher),
+        None => bytecode.get(..length).unwrap_or(bytecode).hash(&mut hasher),
+    }
+    hasher.finish()
+}
+
+/// Do two programs run the same instructions on the same constants and
+/// references? Invalid bytecode is only equivalent to identical bytes.
+pub fn equivalent(a: &[u8], b: &[u8]) -> bool {
+    match (decode(a, a.len()), decode(b, b.len())) {
+        (Some(a), Some(b)) => a == b,
+        (None, None) => a == b,
+        _ => false,
+    }
+}
+
+/// Bytecode as base64 with a CRC-32 (see `codec::seal`)
+pub fn to_base64(bytecode: &[u8]) -> String {
+    crate::codec::seal(bytecode)
+}
+
+/// Bytecode written by `to_base64`; truncated or corrupted text is an error
+pub fn from_base64(text: &str) -> Result<Vec<u8>, String> {
+    crate::codec::unseal(text)
+}
+
+/// Encode bytecode for saving as a string
+#[wasm_bindgen(js_name = bytecodeToBase64)]
+pub fn bytecode_to_base64_js(bytecode: &[u8]) -> String {
+    to_base64(bytecode)
+}
+
+/// Decode bytecode saved by `bytecodeToBase64` (a Uint8Array in JS),
+/// throwing if the string is truncated or corrupted
+#[wasm_bindgen(js_name = bytecodeFromBase64)]
+pub fn bytecode_from_base64_js(text: &str) -> Result<Vec<u8>, JsValue> {
+    from_base64(text).map_err(|e| JsValue::from_str(&e))
+}
+
+/// Copy the first `length` bytes of an expression with the note id of
+/// every LOAD_REF and LOAD_REF_WIDE, and every note id constant popped by
+/// a FIND_* lookup, rewritten through `mapping`
+///
+/// Ids missing from the mapping keep their value and all other
+/// instructions are copied unchanged. A remapped reference is re-encoded
+/// with `write_load_ref` or `write_const`, so it can change width and
+/// shift later offsets.
+pub fn remap_note_ids(
+    bytecode: &[u8],
+    length: usize,
+    mapping: &HashMap<u32, u32>,
+) -> Result<Vec<u8>, ValidationError> {
+    let bytecode = bytecode
+        .get(..length)
+        .ok_or(ValidationError::LengthOutOfRange { length, size: bytecode.len() })?;
+    let mut remapped = Vec::with_capacity(length);
+    let mut pc = 0;
+    while pc < length {
+        let byte = bytecode[pc];
+        let op = Op::from_byte(byte).ok_or(ValidationError::UnknownOpcode { pc, byte })?;
+        let len = instruction_len(bytecode, pc).map_err(|_| ValidationError::Truncated { pc, op })?;
+        let instruction = bytecode.get(pc..pc + len).ok_or(ValidationError::Truncated { pc, op })?;
+        match note_ref_operands(op, &instruction[1..]) {
+            Some((note_id, var_index)) if mapping.contains_key(&note_id) => {
+                write_load_ref(&mut remapped, mapping[&note_id], var_index);
+            }
+            _ => match constant_note_ref(bytecode, pc, length).and_then(|note_id| mapping.get(&note_id)) {
+                Some(&new_id) => write_const(&mut remapped, &Fraction::new_raw(new_id as i64, 1))
+                    .expect("note ids fit in LOAD_CONST_BIG"),
+                None => remapped.extend_from_slice(instruction),
+            },
+        }
+        pc += len;
+    }
+    Ok(remapped)
+}
+
+/// Collect the (note, variable index) pairs read by an expression,
+/// custom variables included
+/// LOAD_BASE is reported as a reference to note 0, FIND_TEMPO as a read of
+/// the base note's tempo, FIND_BEAT as a read of its note's tempo and
+/// FIND_MEASURE as one of its note's beats per measure and tempo.
+pub fn note_references(bytecode: &[u8], length: usize) -> Result<Vec<(u32, u8)>, String> {
+    note_references_with_macros(bytecode, length, &MacroTable::default())
+}
+
+/// `note_references` for bytecode that may CALL_MACRO one of `macros`
+///
+/// A call reads whatever its macro's body reads; calls of unknown macros
+/// read nothing.
+pub fn note_references_with_macros(bytecode: &[u8], length: usize, macros: &MacroTable) -> Result<Vec<(u32, u8)>, String> {
+    let mut refs = Vec::new();
+    let mut pc = 0;
+    while pc < length {
+        match Op::from_byte(bytecode[pc]) {
+            Some(op @ (Op::LoadRef | Op::LoadRefWide)) => {
+                let operands = bytecode.get(pc + 1..length).unwrap_or_default();
+                refs.extend(note_ref_operands(op, operands));
+            }
+            Some(Op::LoadBase) if pc + 2 <= length => refs.push((0, bytecode[pc + 1])),
+            Some(Op::LoadConst) => {
+                let note_id = constant_note_ref(bytecode, pc, length);
+                match (note_id, Op::from_byte(bytecode.get(pc + 9).copied().unwrap_or_default())) {
+                    (Some(note_id), Some(Op::FindBeat)) => refs.push((note_id, Var::Tempo as u8)),
+                    (Some(note_id), Some(Op::FindMeasure)) => {
+                        refs.extend([(note_id, Var::BeatsPerMeasure as u8), (note_id, Var::Tempo as u8)]);
+                    }
+                    _ => {}
+                }
+            }
+            Some(Op::FindTempo) => refs.push((0, Var::Tempo as u8)),
+            Some(Op::CallMacro) if pc + 3 <= length => {
+                if let Some(body) = macros.get(read_u16(bytecode, pc + 1)) {
+                    refs.extend(note_references(body.bytecode(), body.bytecode().len())?);
+                }
+            }
+            _ => {}
+        }
+        pc += instruction_len(bytecode, pc)?;
+    }
+    Ok(refs)
+}
+
+/// A value on the optimizer's stack that has not been written out yet:
+/// the code that pushes it and, for constant subexpressions, its value
+struct Slot {
+    code: Vec<u8>,
+    value: Option<Value>,
+    /// `code` is some other slot's code followed by NEG
+    negated: bool,
+}
+
+impl Slot {
+    fn opaque(code: Vec<u8>) -> Slot {
+        Slot { code, value: None, negated: false }
+    }
+
+    /// A constant slot, if the value is rational and encodable
+    fn constant(value: Value) -> Option<Slot> {
+        let frac = value.as_fraction()?;
+        let mut code = Vec::new();
+        write_const(&mut code, frac).ok()?;
+        Some(Slot { code, value: Some(value), negated: false })
+    }
+
+    fn is_zero(&self) -> bool {
+        self.value.as_ref().and_then(Value::as_fraction).is_some_and(Fraction::is_zero)
+    }
+
+    fn is_one(&self) -> bool {
+        self.value.as_ref().and_then(Value::as_fraction).is_some_and(Fraction::is_one)
+    }
+}
+
+/// Evaluate an arithmetic instruction on constants, keeping only rational results
+fn fold(op: Op, args: &[&Value]) -> Option<Value> {
+    let result = match (op, args) {
+        (Op::Add, [a, b]) => a.add(b),
+        (Op::Sub, [a, b]) => a.sub(b),
+        (Op::Mul, [a, b]) => a.mul(b),
+        (Op::Div, [a, b]) => a.div(b),
+        (Op::Pow, [a, b]) => a.pow(b),
+        (Op::Mod, [a, b]) => a.rem(b),
+        (Op::Min, [a, b]) => a.min(b),
+        (Op::Max, [a, b]) => a.max(b),
+        (Op::Log, [a, b]) => a.log(b),
+        (Op::Root, [a, n]) => {
+            let n = n.as_fraction()?.to_i64().and_then(|n| u32::try_from(n).ok()).filter(|&n| n > 0)?;
+            a.nth_root(n)
+        }
+        (Op::Neg, [a]) => a.neg(),
+        (Op::Sqrt, [a]) => a.sqrt(),
+        (Op::Abs, [a]) => a.abs(),
+        (Op::Sign, [a]) => a.signum(),
+        (Op::Floor, [a]) => a.floor(),
+        (Op::Ceil, [a]) => a.ceil(),
+        (Op::Round, [a]) => a.round(),
+        _ => return None,
+    };
+    Some(result).filter(Value::is_rational)
+}
+
+/// Fold constant subexpressions and drop no-op arithmetic
+///
+/// Runs the program over a stack of pending code fragments: instructions
+/// whose operands are all constants are replaced by the rational result,
+/// and x*1, 1*x, x/1, x+0, 0+x, x-0 and double negation are simplified.
+/// Note lookups (LOAD_REF, LOAD_BASE, FIND_*) are opaque, and stack
+/// operations on computed values write the pending code out unchanged. Bytecode
+/// that fails `validate` is returned as is.
+pub fn optimize(bytecode: &[u8], length: usize) -> (Vec<u8>, usize) {
+    let code = bytecode.get(..length).unwrap_or(bytecode);
+    if validate(code, code.len()).is_err() {
+        return (code.to_vec(), code.len());
+    }
+
+    // Values below `pending` are already in `out`
+    let mut out = Vec::with_capacity(code.len());
+    let mut pending: Vec<Slot> = Vec::new();
+    let flush = |out: &mut Vec<u8>, pending: &mut Vec<Slot>| {
+        for slot in pending.drain(..) {
+            out.extend(slot.code);
+        }
+    };
+
+    let mut pc = 0;
+    while pc < code.len() {
+        let len = instruction_len(code, pc).unwrap_or(1);
+        let instruction = &code[pc..pc + len];
+        let op = Op::from_byte(code[pc]).unwrap_or(Op::LoadConst);
+        pc += len;
+
+        match op {
+            Op::LoadConst => {
+                let value = Value::rational(read_i32(instruction, 1), read_i32(instruction, 5));
+                pending.push(Slot { code: instruction.to_vec(), value: Some(value), negated: false });
+            }
+            Op::LoadConstBig => {
+                let value = read_big_int_signed(instruction, 1).and_then(|(num, num_bytes)| {
+                    let (den, _) = read_big_int_unsigned(instruction, 1 + num_bytes)?;
+                    Ok(Value::Rational(Fraction::from_big_ints(num, den)))
+                });
+                pending.push(Slot { code: instruction.to_vec(), value: value.ok(), negated: false });
+            }
+            Op::LoadRef | Op::LoadRefWide | Op::LoadBase => pending.push(Slot::opaque(instruction.to_vec())),
+            // Constant code is a single load, so copying it keeps it foldable
+            Op::Dup if pending.last().is_some_and(|slot| slot.value.is_some()) => {
+                let top = &pending[pending.len() - 1];
+                let copy = Slot { code: top.code.clone(), value: top.value.clone(), negated: false };
+                pending.push(copy);
+            }
+            Op::Swap if pending.len() >= 2 => {
+                let top = pending.len() - 1;
+                pending.swap(top - 1, top);
+            }
+            Op::Over if pending.len() >= 2 && pending[pending.len() - 2].value.is_some() => {
+                let second = &pending[pending.len() - 2];
+                let copy = Slot { code: second.code.clone(), value: second.value.clone(), negated: false };
+                pending.push(copy);
+            }
+            Op::Rot if pending.len() >= 3 => {
+                let third = pending.len() - 3;
+                pending[third..].rotate_left(1);
+            }
+            _ if op.stack_effect() == (1, 1) && !pending.is_empty() => {
+                let a = pending.pop().expect("operand is pending");
+                let folded = a.value.as_ref().and_then(|a| fold(op, &[a])).and_then(Slot::constant);
+                pending.push(match folded {
+                    Some(folded) => folded,
+                    None if op == Op::Neg && a.negated => {
+                        let mut code = a.code;
+                        code.pop();
+                        Slot::opaque(code)
+                    }
+                    None => {
+                        let mut code = a.code;
+                        code.push(op as u8);
+                        Slot { code, value: None, negated: op == Op::Neg }
+                    }
+                });
+            }
+            _ if op.stack_effect() == (2, 1) && pending.len() >= 2 => {
+                let b = pending.pop().expect("operands are pending");
+                let a = pending.pop().expect("operands are pending");
+                let folded = match (&a.value, &b.value) {
+                    (Some(x), Some(y)) => fold(op, &[x, y]).and_then(Slot::constant),
+                    _ => None,
+                };
+                pending.push(match (folded, op) {
+                    (Some(folded), _) => folded,
+                    (None, Op::Mul | Op::Div) if b.is_one() => a,
+                    (None, Op::Add | Op::Sub) if b.is_zero() => a,
+                    (None, Op::Mul) if a.is_one() => b,
+                    (None, Op::Add) if a.is_zero() => b,
+                    (None, _) => {
+                        let mut code = a.code;
+                        code.extend(b.code);
+                        code.push(op as u8);
+                        Slot::opaque(code)
+                    }
+                });
+            }
+            // An operand is already written out: a pending identity can
+            // still be dropped, anything else is written out as is
+            _ if op.stack_effect() == (2, 1)
+                && pending.last().is_some_and(|b| match op {
+                    Op::Mul | Op::Div => b.is_one(),
+                    Op::Add | Op::Sub => b.is_zero(),
+                    _ => false,
+                }) =>
+            {
+                pending.pop();
+            }
+            _ => {
+                flush(&mut out, &mut pending);
+                out.push(op as u8);
+            }
+        }
+    }
+    flush(&mut out, &mut pending);
+    let length = out.len();
+    (out, length)
+}
+
+/// Evaluate repeated subexpressions once and reuse the value
+///
+/// A subtree whose code appears again later is kept on the stack instead of
+/// being recomputed: if its value is still on top (or second from top) when
+/// the repeat starts, the repeat becomes DUP (or OVER); otherwise a DUP right
+/// after the first occurrence leaves a copy underneath, which SWAP or ROT
+/// brings back up in place of the repeat. Every opcode is a pure function of
+/// the note cache, so a copy has the same value, corruption included.
+/// Constants feeding a FIND_* are left in place so `validate` still sees the
+/// note they name. Rewrites are applied while they shrink the bytecode;
+/// bytecode that fails `validate` is returned as is.
+pub fn eliminate_common_subexpressions(bytecode: &[u8], length: usize) -> (Vec<u8>, usize) {
+    let code = bytecode.get(..length).unwrap_or(bytecode);
+    if validate(code, code.len()).is_err() {
+        return (code.to_vec(), code.len());
+    }
+
+    let mut instructions = Vec::new();
+    let mut pc = 0;
+    while pc < code.len() {
+        let len = instruction_len(code, pc).unwrap_or(1);
+        instructions.push(code[pc..pc + len].to_vec());
+        pc += len;
+    }
+    while let Some(shared) = share_subexpression(&instructions) {
+        instructions = shared;
+    }
+
+    let out = instructions.concat();
+    if validate(&out, out.len()).is_err() {
+        return (code.to_vec(), code.len());
+    }
+    let length = out.len();
+    (out, length)
+}
+
+fn instruction_op(instruction: &[u8]) -> Op {
+    Op::from_byte(instruction[0]).unwrap_or(Op::LoadConst)
+}
+
+fn is_stack_op(op: Op) -> bool {
+    matches!(op, Op::Dup | Op::Swap | Op::Over | Op::Rot | Op::Drop)
+}
+
+/// Rewrite one repeated subtree, largest first; None when nothing shrinks
+fn share_subexpression(instructions: &[Vec<u8>]) -> Option<Vec<Vec<u8>>> {
+    // (first, last) instruction of every subtree built without stack operations
+    let mut starts: Vec<Option<usize>> = Vec::new();
+    let mut subtrees = Vec::new();
+    for (i, instruction) in instructions.iter().enumerate() {
+        let op = instruction_op(instruction);
+        let (pops, pushes) = op.stack_effect();
+        let popped = starts.split_off(starts.len() - pops);
+        if is_stack_op(op) {
+            starts.extend(std::iter::repeat_n(None, pushes));
+            continue;
+        }
+        let start = popped.iter().try_fold(i, |first, start| start.map(|start| start.min(first)));
+        if let Some(start) = start {
+            subtrees.push((start, i));
+        }
+        starts.push(start);
+    }
+
+    let bytes = |(start, end): (usize, usize)| instructions[start..=end].concat();
+    let feeds_lookup = |end: usize| instructions.get(end + 1).is_some_and(|next| instruction_op(next).pops_note_ref());
+    let mut repeats: Vec<_> = subtrees.iter().map(|&subtree| (subtree, bytes(subtree))).collect();
+    repeats.sort_by_key(|((start, _), code)| (std::cmp::Reverse(code.len()), *start));
+
+    for ((start, end), code) in &repeats {
+        if feeds_lookup(*end) {
+            continue;
+        }
+        let earlier = subtrees.iter().rev().filter(|&&(_, first_end)| first_end < *start);
+        for &(first_start, first_end) in earlier {
+            if bytes((first_start, first_end)) != *code {
+                continue;
+            }
+            // The copy costs a DUP plus a SWAP or ROT, so it must replace more than two bytes
+            let reused = reuse_live_value(instructions, first_end, *start).or_else(|| {
+                if code.len() > 2 && !feeds_lookup(first_end) {
+                    reuse_copy(instructions, first_end, *start)
+                } else {
+                    None
+                }
+            });
+            if let Some((after_first, replacement)) = reused {
+                let mut shared = instructions[..=first_end].to_vec();
+                shared.extend(after_first);
+                shared.extend_from_slice(&instructions[first_end + 1..*start]);
+                shared.push(vec![replacement as u8]);
+                shared.extend_from_slice(&instructions[end + 1..]);
+                return Some(shared);
+            }
+        }
+    }
+    None
+}
+
+/// Which instruction last wrote each stack slot just before `until`,
+/// with `copy` standing in for an extra DUP after instruction `copy`
+fn stack_sources(instructions: &[Vec<u8>], until: usize, copy: Option<usize>) -> Option<Vec<Option<usize>>> {
+    // None marks the copy, which nothing before `until` may consume
+    let mut stack: Vec<Option<usize>> = Vec::new();
+    for (i, instruction) in instructions[..until].iter().enumerate() {
+        let op = instruction_op(instruction);
+        let depth = stack.len();
+        if stack[depth - op.stack_effect().0..].contains(&None) {
+            return None;
+        }
+        match op {
+            Op::Dup => stack.push(stack[depth - 1]),
+            Op::Over => stack.push(stack[depth - 2]),
+            Op::Swap => stack.swap(depth - 2, depth - 1),
+            Op::Rot => stack[depth - 3..].rotate_left(1),
+            _ => {
+                stack.truncate(depth - op.stack_effect().0);
+                stack.extend(std::iter::repeat_n(Some(i), op.stack_effect().1));
+            }
+        }
+        if copy == Some(i) {
+            let top = stack.len() - 1;
+            stack.insert(top, None);
+        }
+    }
+    Some(stack)
+}
+
+/// The value of `first` is still on the stack at `repeat`: DUP or OVER it
+fn reuse_live_value(instructions: &[Vec<u8>], first: usize, repeat: usize) -> Option<(Vec<Vec<u8>>, Op)> {
+    let stack = stack_sources(instructions, repeat, None)?;
+    match stack.iter().rev().position(|&source| source == Some(first)) {
+        Some(0) => Some((Vec::new(), Op::Dup)),
+        Some(1) => Some((Vec::new(), Op::Over)),
+        _ => None,
+    }
+}
+
+/// Keep a copy of `first` under the stack and bring it up at `repeat`
+fn reuse_copy(instructions: &[Vec<u8>], first: usize, repeat: usize) -> Option<(Vec<Vec<u8>>, Op)> {
+    let stack = stack_sources(instructions, repeat, Some(first))?;
+    match stack.iter().rev().position(Option::is_none) {
+        Some(1) => Some((vec![vec![Op::Dup as u8]], Op::Swap)),
+        Some(2) => Some((vec![vec![Op::Dup as u8]], Op::Rot)),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::evaluator::{EvaluatedNote, Evaluator, FractionData};
+    use std::collections::HashMap;
 
     #[test]
     fn test_op_from_byte() {
@@ -189,6 +1425,52 @@ mod tests {
         assert_eq!(Op::from_byte(0xFF), None);
     }
 
+    #[test]
+    fn test_opcodes_match_binary_note_js() {
+        // Every `NAME: 0xNN` line of the JavaScript OP table
+        let js = include_str!("../../src/binary-note.js");
+        let table = &js[js.find("export const OP = {").unwrap()..];
+        let table = &table[..table.find("};").unwrap()];
+        let js_ops: Vec<(String, u8)> = table
+            .lines()
+            .filter_map(|line| {
+                let (name, rest) = line.trim().split_once(':')?;
+                let hex = rest.trim().strip_prefix("0x")?.get(..2)?;
+                let camel: String = name
+                    .split('_')
+                    .map(|word| word[..1].to_string() + &word[1..].to_lowercase())
+                    .collect();
+                Some((camel, u8::from_str_radix(hex, 16).ok()?))
+            })
+            .collect();
+
+        for (name, byte) in &js_ops {
+            let op = Op::from_byte(*byte).unwrap_or_else(|| panic!("OP.{} = {:#04x} is not an opcode", name, byte));
+            assert_eq!(&format!("{:?}", op), name, "{:#04x}", byte);
+        }
+        let rust_ops = (0..=u8::MAX).filter(|&b| Op::from_byte(b).is_some()).count();
+        assert_eq!(js_ops.len(), rust_ops);
+    }
+
+    #[test]
+    fn test_opcode_bytes() {
+        let expected = [
+            (Op::LoadConst, 0x01), (Op::LoadRef, 0x02), (Op::LoadBase, 0x03), (Op::LoadConstBig, 0x04),
+            (Op::LoadRefWide, 0x05), (Op::Add, 0x10), (Op::Sub, 0x11), (Op::Mul, 0x12), (Op::Div, 0x13),
+            (Op::Neg, 0x14), (Op::Pow, 0x15), (Op::Mod, 0x16), (Op::Sqrt, 0x17), (Op::Root, 0x18),
+            (Op::Log, 0x19), (Op::Abs, 0x1A), (Op::Sign, 0x1B), (Op::Floor, 0x1C), (Op::Ceil, 0x1D),
+            (Op::Round, 0x1E), (Op::Min, 0x1F), (Op::FindTempo, 0x20), (Op::FindMeasure, 0x21),
+            (Op::FindInstrument, 0x22), (Op::FindBeat, 0x23), (Op::Max, 0x24), (Op::Dup, 0x30),
+            (Op::Swap, 0x31), (Op::Over, 0x33), (Op::Rot, 0x34), (Op::Drop, 0x35), (Op::CallMacro, 0x36),
+        ];
+        for (op, byte) in expected {
+            assert_eq!(op as u8, byte, "{:?}", op);
+            assert_eq!(Op::from_byte(byte), Some(op));
+        }
+        let opcodes = (0..=u8::MAX).filter(|&b| Op::from_byte(b).is_some()).count();
+        assert_eq!(opcodes, expected.len());
+    }
+
     #[test]
     fn test_var_from_byte() {
         assert_eq!(Var::from_byte(0), Some(Var::StartTime));
@@ -264,4 +1546,466 @@ mod tests {
         let (value, _) = read_big_int_signed(&bytecode, 0).unwrap();
         assert_eq!(value, BigInt::from(large_num));
     }
+
+    #[test]
+    fn test_write_big_int_round_trip() {
+        for value in [BigInt::from(0), BigInt::from(-42), BigInt::from(3936588805702081i64), -(BigInt::from(1) << 200usize)] {
+            let mut bytecode = Vec::new();
+            write_big_int_signed(&mut bytecode, &value).unwrap();
+            assert_eq!(read_big_int_signed(&bytecode, 0).unwrap(), (value.clone(), bytecode.len()));
+
+            let mut bytecode = Vec::new();
+            write_big_int_unsigned(&mut bytecode, &value).unwrap();
+            assert_eq!(read_big_int_unsigned(&bytecode, 0).unwrap(), (value.abs(), bytecode.len()));
+        }
+    }
+
+    #[test]
+    fn test_write_oversized_big_int() {
+        // 65535 magnitude bytes is the most a 16-bit length prefix can describe
+        let largest: BigInt = (BigInt::from(1) << (8 * 65535usize)) - 1;
+        let mut bytecode = Vec::new();
+        write_big_int_signed(&mut bytecode, &-largest.clone()).unwrap();
+        assert_eq!(read_big_int_signed(&bytecode, 0).unwrap(), (-largest.clone(), 3 + 65535));
+
+        let too_big = largest + 1;
+        let mut bytecode = vec![Op::Add as u8];
+        assert!(write_big_int_signed(&mut bytecode, &too_big).unwrap_err().contains("65536 bytes"));
+        assert!(write_big_int_unsigned(&mut bytecode, &too_big).is_err());
+        assert!(write_const(&mut bytecode, &Fraction::from_big_ints(BigInt::from(1), too_big)).is_err());
+        assert_eq!(bytecode, vec![Op::Add as u8]);
+    }
+
+    #[test]
+    fn test_write_const_and_validate() {
+        let mut bc = Vec::new();
+        write_const(&mut bc, &Fraction::new(3, 2)).unwrap();
+        assert_eq!(bc[0], Op::LoadConst as u8);
+        assert_eq!(instruction_len(&bc, 0), Ok(9));
+
+        let big = Fraction::new_raw(-(1i64 << 40), 3);
+        write_const(&mut bc, &big).unwrap();
+        bc.push(Op::Mul as u8);
+        assert_eq!(bc[9], Op::LoadConstBig as u8);
+        assert!(validate(&bc, bc.len()).is_ok());
+
+        assert!(validate(&bc[..9], 9).is_ok());
+        assert!(validate(&bc[..bc.len() - 1], bc.len() - 1).is_err());
+        assert!(validate(&[Op::Add as u8], 1).is_err());
+    }
+
+    #[test]
+    fn test_validate_errors() {
+        let mut bc = Vec::new();
+        write_const(&mut bc, &Fraction::new(3, 2)).unwrap();
+
+        // Truncated LOAD_CONST, and a length past the buffer
+        assert_eq!(validate(&bc[..5], 5), Err(ValidationError::Truncated { pc: 0, op: Op::LoadConst }));
+        assert_eq!(validate(&bc, 10), Err(ValidationError::LengthOutOfRange { length: 10, size: 9 }));
+
+        // A lone Add underflows, and two constants are one too many
+        let err = validate(&[Op::Add as u8], 1).unwrap_err();
+        assert_eq!(err, ValidationError::StackUnderflow { pc: 0, op: Op::Add, depth: 0 });
+
+        // OVER and ROT need two and three values, DROP needs one
+        for (op, depth) in [(Op::Over, 1), (Op::Rot, 2), (Op::Drop, 0)] {
+            let mut bc = Vec::new();
+            for _ in 0..depth {
+                write_const(&mut bc, &Fraction::new(1, 1)).unwrap();
+            }
+            bc.push(op as u8);
+            let pc = bc.len() - 1;
+            assert_eq!(validate(&bc, bc.len()), Err(ValidationError::StackUnderflow { pc, op, depth }));
+        }
+        assert_eq!(err.to_string(), "Stack underflow at pc=0: Add with 0 values on the stack");
+        let twice = [bc.as_slice(), bc.as_slice()].concat();
+        assert_eq!(validate(&twice, twice.len()), Err(ValidationError::FinalDepth { depth: 2 }));
+        assert_eq!(validate(&[], 0), Err(ValidationError::FinalDepth { depth: 0 }));
+
+        // Unknown opcodes and variable indices
+        assert_eq!(validate(&[0x7f], 1), Err(ValidationError::UnknownOpcode { pc: 0, byte: 0x7f }));
+        let bad_var = [Op::LoadRef as u8, 0, 3, 9];
+        assert_eq!(validate(&bad_var, 4), Err(ValidationError::InvalidVariable { pc: 0, index: 9 }));
+        let past_custom = [Op::LoadBase as u8, CUSTOM_VAR_LAST + 1];
+        assert_eq!(validate(&past_custom, 2), Err(ValidationError::InvalidVariable { pc: 0, index: 64 }));
+        let big = [Op::LoadConstBig as u8, 0, 0, 4, 1];
+        assert_eq!(validate(&big, big.len()), Err(ValidationError::Truncated { pc: 0, op: Op::LoadConstBig }));
+    }
+
+    #[test]
+    fn test_custom_vars() {
+        assert!(!is_custom_var(5) && is_custom_var(CUSTOM_VAR_FIRST) && is_custom_var(CUSTOM_VAR_LAST));
+        assert_eq!(var_label(2).as_deref(), Some("frequency"));
+        assert_eq!(var_label(40).as_deref(), Some("custom:40"));
+        assert_eq!(var_label(6), None);
+
+        let mut bc = Vec::new();
+        write_load_ref(&mut bc, 7, 40);
+        bc.extend_from_slice(&[Op::LoadBase as u8, 33, Op::Add as u8]);
+        assert!(validate(&bc, bc.len()).is_ok());
+        assert_eq!(
+            decompile(&bc, bc.len()).unwrap(),
+            "module.getNoteById(7).getVariable('custom:40').add(module.baseNote.getVariable('custom:33'))"
+        );
+        assert_eq!(operand_text(&bc[..4]), vec!["7".to_string(), "custom:40".to_string()]);
+        // Custom reads are reported by index
+        assert_eq!(note_references(&bc, bc.len()).unwrap(), vec![(7, 40), (0, 33)]);
+    }
+
+    #[test]
+    fn test_optimize() {
+        let konst = |num, den| {
+            let mut bc = Vec::new();
+            write_const(&mut bc, &Fraction::new(num, den)).unwrap();
+            bc
+        };
+        let load_ref = [Op::LoadRef as u8, 0, 4, Var::Duration as u8];
+        let program = |parts: &[&[u8]]| parts.concat();
+
+        // 1/2 * 2/3 folds to a single constant
+        let bc = program(&[&konst(1, 2), &konst(2, 3), &[Op::Mul as u8]]);
+        assert_eq!(optimize(&bc, bc.len()), (konst(1, 3), 9));
+
+        // Identities around a note reference disappear
+        for (tail, op) in [(konst(1, 1), Op::Mul), (konst(1, 1), Op::Div), (konst(0, 1), Op::Add), (konst(0, 1), Op::Sub)] {
+            let bc = program(&[&load_ref, &tail, &[op as u8]]);
+            assert_eq!(optimize(&bc, bc.len()).0, load_ref, "{:?}", op);
+        }
+        let bc = program(&[&konst(1, 1), &load_ref, &[Op::Mul as u8, Op::Neg as u8, Op::Neg as u8]]);
+        assert_eq!(optimize(&bc, bc.len()).0, load_ref);
+
+        // Dropping identities never changes the result, whatever the
+        // reference holds: symbolic and irrational values stay as they are
+        let semitone = Value::rational(2, 1).pow(&Value::rational(1, 12));
+        for referenced in [semitone, Value::irrational(0.1), Value::rational(5, 3)] {
+            let note = EvaluatedNote { duration: Some(FractionData::from_value(&referenced)), ..Default::default() };
+            let cache = HashMap::from([(4, note)]);
+            let mut evaluator = Evaluator::new();
+            let identities = [(konst(1, 1), Op::Mul), (konst(1, 1), Op::Div), (konst(0, 1), Op::Add), (konst(0, 1), Op::Sub)];
+            for (operand, op) in identities {
+                for bc in [program(&[&load_ref, &operand, &[op as u8]]), program(&[&operand, &load_ref, &[op as u8]])] {
+                    let (optimized, length) = optimize(&bc, bc.len());
+                    let expected = evaluator.evaluate(&bc, bc.len(), &cache).unwrap();
+                    let actual = evaluator.evaluate(&optimized, length, &cache).unwrap();
+                    assert!(actual == expected, "{:?} of {}: {} vs {}", op, referenced, actual, expected);
+                }
+            }
+        }
+
+        // Constant subtrees fold on either side of an opaque operand
+        let bc = program(&[&load_ref, &konst(3, 1), &konst(4, 1), &[Op::Add as u8, Op::Mul as u8]]);
+        assert_eq!(optimize(&bc, bc.len()).0, program(&[&load_ref, &konst(7, 1), &[Op::Mul as u8]]));
+
+        // Irrational results and lookups stay as they are
+        let bc = program(&[&konst(2, 1), &[Op::Sqrt as u8]]);
+        assert_eq!(optimize(&bc, bc.len()).0, bc);
+        let bc = program(&[&konst(1, 1), &[Op::FindTempo as u8]]);
+        assert_eq!(optimize(&bc, bc.len()).0, bc);
+
+        // DUP of a constant folds; SWAP just reorders pending operands
+        let bc = program(&[&konst(3, 2), &[Op::Dup as u8, Op::Mul as u8]]);
+        assert_eq!(optimize(&bc, bc.len()).0, konst(9, 4));
+        let bc = program(&[&konst(1, 1), &konst(3, 1), &[Op::Swap as u8, Op::Sub as u8]]);
+        assert_eq!(optimize(&bc, bc.len()).0, konst(2, 1));
+        let bc = program(&[&load_ref, &[Op::Dup as u8, Op::Mul as u8]]);
+        assert_eq!(optimize(&bc, bc.len()).0, bc);
+
+        // OVER copies a constant second operand, ROT reorders three
+        let bc = program(&[&konst(2, 1), &konst(5, 1), &[Op::Over as u8, Op::Sub as u8, Op::Mul as u8]]);
+        assert_eq!(optimize(&bc, bc.len()).0, konst(6, 1));
+        let bc = program(&[&konst(1, 1), &konst(2, 1), &konst(3, 1), &[Op::Rot as u8, Op::Sub as u8, Op::Div as u8]]);
+        assert_eq!(optimize(&bc, bc.len()).0, konst(1, 1));
+        let bc = program(&[&load_ref, &konst(1, 1), &[Op::Drop as u8]]);
+        assert_eq!(optimize(&bc, bc.len()).0, bc);
+
+        // Results too large for LOAD_CONST use LOAD_CONST_BIG
+        let bc = program(&[&konst(i32::MAX, 1), &konst(i32::MAX, 1), &[Op::Mul as u8]]);
+        let (optimized, length) = optimize(&bc, bc.len());
+        assert_eq!(optimized[0], Op::LoadConstBig as u8);
+        assert!(validate(&optimized, length).is_ok());
+
+        // Invalid bytecode is left alone
+        assert_eq!(optimize(&[Op::Add as u8], 1), (vec![Op::Add as u8], 1));
+    }
+
+    #[test]
+    fn test_eliminate_common_subexpressions() {
+        let konst = |num| {
+            let mut bc = Vec::new();
+            write_const(&mut bc, &Fraction::new(num, 1)).unwrap();
+            bc
+        };
+        let load_ref = [Op::LoadRef as u8, 0, 4, Var::Duration as u8];
+        let program = |parts: &[&[u8]]| parts.concat();
+
+        // d*2 + (3 + d): the copy is two deep when d repeats, so ROT fetches it
+        let bc = program(&[&load_ref, &konst(2), &[Op::Mul as u8], &konst(3), &load_ref, &[Op::Add as u8, Op::Add as u8]]);
+        let expected = program(&[&load_ref, &[Op::Dup as u8], &konst(2), &[Op::Mul as u8], &konst(3), &[Op::Rot as u8, Op::Add as u8, Op::Add as u8]]);
+        assert_eq!(eliminate_common_subexpressions(&bc, bc.len()), (expected.clone(), expected.len()));
+
+        // A copy three deep is out of reach
+        let bc = program(&[&load_ref, &konst(2), &[Op::Mul as u8], &konst(3), &konst(4), &load_ref, &[Op::Add as u8, Op::Add as u8, Op::Add as u8]]);
+        assert_eq!(eliminate_common_subexpressions(&bc, bc.len()).0, bc);
+
+        // The note id read by FIND_TEMPO stays a constant
+        let bc = program(&[&konst(4), &konst(4), &[Op::FindTempo as u8, Op::Add as u8]]);
+        assert_eq!(eliminate_common_subexpressions(&bc, bc.len()).0, bc);
+        assert_eq!(validate(&bc, bc.len()).unwrap().note_ids, vec![4]);
+
+        // Invalid bytecode is left alone
+        assert_eq!(eliminate_common_subexpressions(&[Op::Add as u8], 1), (vec![Op::Add as u8], 1));
+    }
+
+    #[test]
+    fn test_decompile() {
+        let mut bc = Vec::new();
+        write_load_ref(&mut bc, 4, Var::Duration as u8);
+        write_const(&mut bc, &Fraction::new(1, 2)).unwrap();
+        bc.extend([Op::Over as u8, Op::Add as u8, Op::Mul as u8]);
+        assert_eq!(
+            decompile(&bc, bc.len()).unwrap(),
+            "module.getNoteById(4).getVariable('duration').mul(new Fraction(1, 2).add(module.getNoteById(4).getVariable('duration')))"
+        );
+
+        // Sums are wrapped where a method or argument would split them
+        let mut bc = Vec::new();
+        bc.extend([Op::LoadBase as u8, Var::StartTime as u8]);
+        bc.extend([Op::Dup as u8, Op::Dup as u8, Op::Sub as u8, Op::Sub as u8, Op::Abs as u8]);
+        let text = "module.baseNote.getVariable('startTime')";
+        assert_eq!(decompile(&bc, bc.len()).unwrap(), format!("({t}.sub(({t}.sub({t})))).abs()", t = text));
+
+        let mut beat = Vec::new();
+        write_const(&mut beat, &Fraction::new(0, 1)).unwrap();
+        beat.push(Op::FindBeat as u8);
+        assert_eq!(decompile(&beat, beat.len()).unwrap(), "new Fraction(60).div(module.findTempo(module.baseNote))");
+        assert_eq!(note_references(&beat, beat.len()).unwrap(), vec![(0, Var::Tempo as u8)]);
+        let mut lookups = Vec::new();
+        write_const(&mut lookups, &Fraction::new(5, 1)).unwrap();
+        lookups.extend([Op::FindMeasure as u8, Op::Dup as u8, Op::FindTempo as u8, Op::Add as u8]);
+        let reads = note_references(&lookups, lookups.len()).unwrap();
+        assert_eq!(reads, vec![(5, Var::BeatsPerMeasure as u8), (5, Var::Tempo as u8), (0, Var::Tempo as u8)]);
+
+        // Lookups without a text form, and invalid bytecode
+        beat.insert(9, Op::Neg as u8);
+        assert_eq!(decompile(&beat, beat.len()), Err(DecompileError::NoSourceForm { pc: 10, op: Op::FindBeat }));
+        let mut instrument = Vec::new();
+        write_const(&mut instrument, &Fraction::new(2, 1)).unwrap();
+        instrument.push(Op::FindInstrument as u8);
+        assert_eq!(decompile(&instrument, instrument.len()), Err(DecompileError::NoSourceForm { pc: 9, op: Op::FindInstrument }));
+        assert_eq!(
+            decompile(&[Op::Add as u8], 1),
+            Err(DecompileError::Invalid(ValidationError::StackUnderflow { pc: 0, op: Op::Add, depth: 0 }))
+        );
+    }
+
+    #[test]
+    fn test_canonical_hash_and_equivalent() {
+        let with = |parts: &[&[u8]]| parts.concat();
+        let mut half = Vec::new();
+        write_const(&mut half, &Fraction::new(1, 2)).unwrap();
+        let mut two_quarters = vec![Op::LoadConst as u8];
+        write_i32(&mut two_quarters, 2);
+        write_i32(&mut two_quarters, 4);
+        let mut big_half = vec![Op::LoadConstBig as u8];
+        write_big_int_signed(&mut big_half, &BigInt::from(1)).unwrap();
+        write_big_int_unsigned(&mut big_half, &BigInt::from(2)).unwrap();
+        let narrow = [Op::LoadRef as u8, 0, 5, Var::Duration as u8];
+        let wide = [Op::LoadRefWide as u8, 0, 0, 0, 5, Var::Duration as u8];
+        let mul = [Op::Mul as u8];
+
+        let program = with(&[&narrow, &half, &mul]);
+        for same in [with(&[&narrow, &two_quarters, &mul]), with(&[&wide, &big_half, &mul])] {
+            assert!(equivalent(&program, &same));
+            assert_eq!(canonical_hash(&program, program.len()), canonical_hash(&same, same.len()));
+        }
+        // Bytes past the length are not part of the program
+        let padded = with(&[&program, &[0xFF, 0xFF]]);
+        assert_eq!(canonical_hash(&padded, program.len()), canonical_hash(&program, program.len()));
+
+        let other_note = with(&[&[Op::LoadRef as u8, 0, 6, Var::Duration as u8], &half, &mul]);
+        let other_op = with(&[&narrow, &half, &[Op::Div as u8]]);
+        let mut third = Vec::new();
+        write_const(&mut third, &Fraction::new(1, 3)).unwrap();
+        let other_constant = with(&[&narrow, &third, &mul]);
+        for different in [other_note, other_op, other_constant] {
+            assert!(!equivalent(&program, &different));
+            assert_ne!(canonical_hash(&program, program.len()), canonical_hash(&different, different.len()));
+        }
+
+        // Invalid bytecode only matches itself
+        assert!(equivalent(&[Op::Add as u8], &[Op::Add as u8]));
+        assert!(!equivalent(&[Op::Add as u8], &[Op::Sub as u8]));
+    }
+
+    #[test]
+    fn test_macro_table() {
+        // Body: (caller's value + note 4's duration) * 2, taking one value
+        let mut body = vec![Op::LoadRef as u8, 0, 4, Var::Duration as u8, Op::Add as u8];
+        write_const(&mut body, &Fraction::new(2, 1)).unwrap();
+        body.push(Op::Mul as u8);
+        let mut macros = MacroTable::default();
+        macros.register(3, &body, body.len()).unwrap();
+        let registered = macros.get(3).unwrap();
+        assert_eq!(registered.effect(), (1, 1));
+        assert_eq!(registered.note_ids(), &[4]);
+
+        // Bodies may leave several values, or take more than they leave
+        macros.register(5, &[Op::Dup as u8], 1).unwrap();
+        assert_eq!(macros.get(5).unwrap().effect(), (1, 2));
+        macros.register(6, &[Op::Add as u8, Op::Add as u8], 2).unwrap();
+        assert_eq!(macros.get(6).unwrap().effect(), (3, 1));
+
+        let call = |id: u16| {
+            let mut bc = vec![Op::CallMacro as u8];
+            write_u16(&mut bc, id);
+            bc
+        };
+        assert_eq!(macros.register(7, &call(3), 3), Err(ValidationError::NestedMacro { pc: 0 }));
+        assert!(macros.get(7).is_none());
+
+        // A call contributes its body's effect, depth and note references
+        let mut program = vec![Op::LoadRef as u8, 0, 9, Var::StartTime as u8];
+        program.extend(call(3));
+        let info = validate_with_macros(&program, program.len(), &macros).unwrap();
+        assert_eq!(info, ValidationInfo { max_depth: 2, note_ids: vec![4, 9], references_base: false });
+        assert_eq!(validate(&program, program.len()), Err(ValidationError::UnknownMacro { pc: 4, macro_id: 3 }));
+        let reads = note_references_with_macros(&program, program.len(), &macros).unwrap();
+        assert_eq!(reads, vec![(9, Var::StartTime as u8), (4, Var::Duration as u8)]);
+        assert_eq!(note_references(&program, program.len()).unwrap(), vec![(9, Var::StartTime as u8)]);
+        assert!(matches!(
+            validate_with_macros(&call(3), 3, &macros),
+            Err(ValidationError::StackUnderflow { pc: 0, op: Op::CallMacro, .. })
+        ));
+
+        let remapped = macros.remap_note_ids(&HashMap::from([(4, 8)])).unwrap();
+        assert_eq!(remapped.get(3).unwrap().note_ids(), &[8]);
+        assert_eq!(remapped.get(6).unwrap().effect(), (3, 1));
+    }
+
+    #[test]
+    fn test_operand_text() {
+        let mut constant = Vec::new();
+        write_const(&mut constant, &Fraction::new(-3, 4)).unwrap();
+        assert_eq!(operand_text(&constant), vec![Fraction::new(-3, 4).to_string()]);
+        let mut big = Vec::new();
+        write_const(&mut big, &Fraction::new(1, 1 << 30).mul(&Fraction::new(1, 1 << 30))).unwrap();
+        assert_eq!(big[0], Op::LoadConstBig as u8);
+        assert_eq!(operand_text(&big), vec![Fraction::new(1, 1 << 30).mul(&Fraction::new(1, 1 << 30)).to_string()]);
+        assert_eq!(
+            operand_text(&[Op::LoadRef as u8, 0, 4, Var::Duration as u8]),
+            vec!["4".to_string(), Var::Duration.name().to_string()]
+        );
+        assert_eq!(operand_text(&[Op::LoadBase as u8, Var::Tempo as u8]), vec![Var::Tempo.name().to_string()]);
+        assert_eq!(operand_text(&[Op::CallMacro as u8, 1, 2]), vec!["258".to_string()]);
+        assert!(operand_text(&[Op::Add as u8]).is_empty());
+        assert!(operand_text(&[Op::LoadRef as u8, 0]).is_empty());
+        assert!(operand_text(&[0xFF]).is_empty());
+    }
+
+    #[test]
+    fn test_analyze() {
+        // (note 7 start + 1/3) * (note 7 start + 1/3) - base tempo * 2^70
+        let mut bc = vec![Op::LoadRef as u8, 0, 7, Var::StartTime as u8];
+        write_const(&mut bc, &Fraction::new(1, 3)).unwrap();
+        bc.extend([Op::Add as u8, Op::Dup as u8, Op::Mul as u8, Op::LoadBase as u8, Var::Tempo as u8]);
+        write_const(&mut bc, &Fraction::from_big_ints(BigInt::from(1) << 70usize, BigInt::from(1))).unwrap();
+        bc.extend([Op::Mul as u8, Op::Sub as u8]);
+
+        let stats = analyze(&bc, bc.len()).unwrap();
+        let count = |op: Op, count: usize| OpcodeCount { op: format!("{:?}", op), opcode: op as u8, count };
+        assert_eq!(
+            stats,
+            BytecodeStats {
+                expressions: 1,
+                instructions: 9,
+                total_bytes: bc.len(),
+                constants: 2,
+                max_depth: 3,
+                opcodes: vec![
+                    count(Op::LoadConst, 1),
+                    count(Op::LoadRef, 1),
+                    count(Op::LoadBase, 1),
+                    count(Op::LoadConstBig, 1),
+                    count(Op::Add, 1),
+                    count(Op::Sub, 1),
+                    count(Op::Mul, 2),
+                    count(Op::Dup, 1),
+                ],
+            }
+        );
+        assert_eq!(stats.max_depth, validate(&bc, bc.len()).unwrap().max_depth);
+
+        let mut twice = stats.clone();
+        twice.merge(&analyze(&[Op::LoadBase as u8, 0, Op::Neg as u8], 3).unwrap());
+        assert_eq!((twice.expressions, twice.instructions, twice.total_bytes), (2, 11, bc.len() + 3));
+        assert_eq!(twice.opcodes[2], count(Op::LoadBase, 2));
+        assert_eq!(twice.opcodes[7], count(Op::Neg, 1));
+        assert_eq!(twice.max_depth, 3);
+
+        assert_eq!(analyze(&bc, bc.len() - 1), Err(ValidationError::FinalDepth { depth: 2 }));
+        assert_eq!(analyze(&[0xEE], 1), Err(ValidationError::UnknownOpcode { pc: 0, byte: 0xEE }));
+    }
+
+    #[test]
+    fn test_validate_reports_depth_and_references() {
+        // (a + b) * (c - base.tempo), with a, b, c read from notes 7, 3 and 7
+        let mut bc = Vec::new();
+        for id in [7u16, 3] {
+            bc.push(Op::LoadRef as u8);
+            write_u16(&mut bc, id);
+            bc.push(Var::StartTime as u8);
+        }
+        bc.push(Op::Add as u8);
+        bc.extend([Op::LoadRef as u8, 0, 7, Var::Duration as u8]);
+        bc.extend([Op::LoadBase as u8, Var::Tempo as u8]);
+        bc.push(Op::Sub as u8);
+        bc.push(Op::Mul as u8);
+
+        let info = validate(&bc, bc.len()).unwrap();
+        assert_eq!(info, ValidationInfo { max_depth: 3, note_ids: vec![3, 7], references_base: true });
+
+        // Wide references count too, and must carry all six bytes
+        let mut wide = Vec::new();
+        write_load_ref(&mut wide, 100_000, Var::Frequency as u8);
+        assert_eq!(wide, [Op::LoadRefWide as u8, 0, 1, 0x86, 0xa0, Var::Frequency as u8]);
+        assert_eq!(validate(&wide, wide.len()).unwrap().note_ids, vec![100_000]);
+        assert_eq!(note_references(&wide, wide.len()).unwrap(), vec![(100_000, Var::Frequency as u8)]);
+        assert_eq!(validate(&wide, 5), Err(ValidationError::Truncated { pc: 0, op: Op::LoadRefWide }));
+
+        let mut dup = Vec::new();
+        write_const(&mut dup, &Fraction::new(1, 3)).unwrap();
+        dup.push(Op::Dup as u8);
+        dup.push(Op::Mul as u8);
+        assert_eq!(validate(&dup, dup.len()).unwrap().max_depth, 2);
+    }
+
+    #[test]
+    fn test_remap_note_ids() {
+        // note a + (big constant) + note b, times the base tempo
+        let program = |a: u16, b: u16| {
+            let mut bc = vec![Op::LoadRef as u8];
+            write_u16(&mut bc, a);
+            bc.push(Var::StartTime as u8);
+            write_const(&mut bc, &Fraction::from_big_ints(BigInt::from(1) << 40usize, BigInt::from(3))).unwrap();
+            bc.extend([Op::Add as u8, Op::LoadRef as u8]);
+            write_u16(&mut bc, b);
+            bc.extend([Var::Duration as u8, Op::Add as u8, Op::LoadBase as u8, Var::Tempo as u8, Op::Mul as u8]);
+            bc
+        };
+        let bc = program(7, 3);
+
+        // Ids missing from the mapping keep their value
+        let mapping = HashMap::from([(7, 300), (3, 7), (5, 1)]);
+        assert_eq!(remap_note_ids(&bc, bc.len(), &mapping).unwrap(), program(300, 7));
+        assert_eq!(remap_note_ids(&bc, bc.len(), &HashMap::from([(3, 9)])).unwrap(), program(7, 9));
+        assert_eq!(remap_note_ids(&bc, bc.len(), &HashMap::new()).unwrap(), bc);
+
+
+        // Ids past 65535 widen the reference, and narrow again on the way back
+        let wide = remap_note_ids(&bc, bc.len(), &HashMap::from([(3, 100_000)])).unwrap();
+        assert_eq!(wide.len(), bc.len() + 2);
+        assert_eq!(note_references(&wide, wide.len()).unwrap()[1], (100_000, Var::Duration as u8));
+        assert_eq!(remap_note_ids(&wide, wide.len(), &HashMap::from([(100_000, 3)])).unwrap(), bc);
+        assert!(matches!(remap_note_ids(&bc, 2, &mapping), Err(ValidationError::Truncated { pc: 0, .. })));
+    }
 }
@@ -0,0 +1,973 @@
+//! Compact binary encoding of evaluated values and caches
+//!
+//! A much cheaper alternative to `exportCache`/`importCache` for large
+//! modules. Every value starts with a tag byte saying which of its fields
+//! follow:
+//!
+//! - `TAG_RATIONAL_SMALL`: sign, numerator and denominator
+//! - `TAG_RATIONAL_BIG`: presence flags, then every field written out;
+//!   used for rationals too large for u32 parts (stored as a float plus the
+//!   closest fitting fraction), tracked error bounds and anything else
+//!   outside the other forms
+//! - `TAG_IRRATIONAL`: the float alone, since the n/d approximation can be
+//!   recomputed from it (with the variable's default denominator from
+//!   `ApproximationConfig`, or `DEFAULT_APPROXIMATION_DENOMINATOR` outside
+//!   a note)
+//! - `TAG_SYMBOLIC`: the float and the symbolic terms
+//!
+//! Notes are a presence byte with bit i for variable index i,
+//! `NOTE_INSTRUMENT` for the instrument and `NOTE_CUSTOM` for custom
+//! variables, the present values, the instrument, the corruption flags and
+//! the corruption sources, then any custom variables as a count, index and
+//! value pairs and their corruption flags.
+//!
+//! Unsigned integers are LEB128 varints, signs a single signed byte and
+//! floats 8 bytes little-endian. Readers take a byte offset and return the
+//! value with the number of bytes consumed, like the bytecode readers.
+//!
+//! Text containers (`seal`/`unseal`) are standard padded base64 of the
+//! payload followed by its CRC-32 (little-endian), so truncated or
+//! corrupted strings are rejected. Compiled expressions are sealed as
+//! `EXPRESSION_MAGIC`, `EXPRESSION_VERSION`, a flags byte, the dependency
+//! ids, the source text and the bytecode.
+//!
+//! Registered bytecode (`encode_bytecode`) is `BYTECODE_MAGIC`,
+//! `BYTECODE_VERSION` and a note count, then per note its id, a presence
+//! byte with bit i for variable index i and `NOTE_CUSTOM` for custom
+//! variables, and each present expression as a length-prefixed blob,
+//! followed by any custom variables as a count and index and blob pairs.
+
+use crate::bytecode::{is_custom_var, Var};
+use crate::compiler::CompiledExpression;
+use crate::evaluator::{
+    approximate_parts_with, ApproximationConfig, CorruptionSource, EvaluatedNote, FractionData, NoteBytecode,
+    DEFAULT_APPROXIMATION_DENOMINATOR,
+};
+use crate::value::{PowerTermData, SimpleFraction, SymbolicPowerData, ValueData};
+use std::collections::HashMap;
+
+/// Sign, numerator and denominator that fit u32
+pub const TAG_RATIONAL_SMALL: u8 = 0;
+/// Every field written out, with presence flags
+pub const TAG_RATIONAL_BIG: u8 = 1;
+/// A float whose n/d approximation is derived from it
+pub const TAG_IRRATIONAL: u8 = 2;
+/// A float plus exact symbolic terms
+pub const TAG_SYMBOLIC: u8 = 3;
+
+/// First bytes of an encoded cache, followed by `CACHE_VERSION`
+pub const CACHE_MAGIC: &[u8; 4] = b"RMTC";
+/// Version of the cache layout
+pub const CACHE_VERSION: u8 = 5;
+
+/// First bytes of encoded bytecode, followed by `BYTECODE_VERSION`
+pub const BYTECODE_MAGIC: &[u8; 4] = b"RMTB";
+/// Version of the bytecode layout
+pub const BYTECODE_VERSION: u8 = 2;
+
+/// Presence bit for `EvaluatedNote::instrument`, above the variable bits
+const NOTE_INSTRUMENT: u8 = 1 << 6;
+/// Presence bit for custom variables (`EvaluatedNote::custom` and
+/// `NoteBytecode::custom`)
+const NOTE_CUSTOM: u8 = 1 << 7;
+/// Presence bits of the variables
+const NOTE_VARS: u8 = 0x3f;
+
+/// First bytes of a sealed compiled expression, followed by `EXPRESSION_VERSION`
+pub const EXPRESSION_MAGIC: &[u8; 4] = b"RMTE";
+/// Version of the compiled expression layout
+pub const EXPRESSION_VERSION: u8 = 1;
+
+/// Expression flag: references the base note
+const EXPRESSION_REFERENCES_BASE: u8 = 1 << 0;
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Presence flags for `TAG_RATIONAL_BIG`
+const FLAG_CORRUPTED: u8 = 1 << 0;
+const FLAG_FLOAT: u8 = 1 << 1;
+const FLAG_SYMBOLIC: u8 = 1 << 2;
+const FLAG_PARTS: u8 = 1 << 3;
+const FLAG_ERROR: u8 = 1 << 4;
+
+/// Append an unsigned LEB128 varint
+pub fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// Read an unsigned LEB128 varint
+/// Returns (value, bytes_consumed) or error
+pub fn read_varint(bytes: &[u8], offset: usize) -> Result<(u64, usize), String> {
+    let mut reader = Reader::new(bytes, offset);
+    let value = reader.varint()?;
+    Ok((value, reader.consumed()))
+}
+
+/// Cursor over an encoded buffer
+struct Reader<'a> {
+    bytes: &'a [u8],
+    start: usize,
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], offset: usize) -> Reader<'a> {
+        Reader { bytes, start: offset, pos: offset }
+    }
+
+    fn consumed(&self) -> usize {
+        self.pos - self.start
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.bytes.len());
+        let end = end.ok_or_else(|| format!("Unexpected end of data at offset {}", self.pos))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn sign(&mut self) -> Result<i32, String> {
+        match self.byte()? as i8 {
+            s @ -1..=1 => Ok(s as i32),
+            s => Err(format!("Invalid sign {} at offset {}", s, self.pos - 1)),
+        }
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let start = self.pos;
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(format!("Varint too long at offset {}", start))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let start = self.pos;
+        let value = self.varint()?;
+        u32::try_from(value).map_err(|_| format!("Value {} out of range at offset {}", value, start))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        let bytes = self.take(8)?;
+        Ok(f64::from_le_bytes(bytes.try_into().expect("8 bytes")))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let start = self.pos;
+        let len = self.varint()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| format!("Invalid UTF-8 string at offset {}", start))
+    }
+
+    fn tag(&mut self) -> Result<u8, String> {
+        match self.byte()? {
+            tag @ TAG_RATIONAL_SMALL..=TAG_SYMBOLIC => Ok(tag),
+            tag => Err(format!("Unknown value tag {} at offset {}", tag, self.pos - 1)),
+        }
+    }
+}
+
+fn write_sign(buffer: &mut Vec<u8>, s: i32) {
+    buffer.push(s.clamp(-1, 1) as i8 as u8);
+}
+
+fn write_string(buffer: &mut Vec<u8>, s: &str) {
+    write_varint(buffer, s.len() as u64);
+    buffer.extend_from_slice(s.as_bytes());
+}
+
+fn write_simple_fraction(buffer: &mut Vec<u8>, f: &SimpleFraction) {
+    write_sign(buffer, f.s);
+    write_varint(buffer, f.n as u64);
+    write_varint(buffer, f.d as u64);
+}
+
+fn read_simple_fraction(reader: &mut Reader) -> Result<SimpleFraction, String> {
+    Ok(SimpleFraction { s: reader.sign()?, n: reader.u32()?, d: reader.u32()? })
+}
+
+fn write_symbolic(buffer: &mut Vec<u8>, sp: &SymbolicPowerData) {
+    write_simple_fraction(buffer, &sp.coefficient);
+    write_varint(buffer, sp.powers.len() as u64);
+    for p in &sp.powers {
+        write_varint(buffer, p.base as u64);
+        write_simple_fraction(buffer, &p.exp);
+    }
+}
+
+fn read_symbolic(reader: &mut Reader) -> Result<SymbolicPowerData, String> {
+    let coefficient = read_simple_fraction(reader)?;
+    let count = reader.varint()?;
+    let mut powers = Vec::new();
+    for _ in 0..count {
+        powers.push(PowerTermData { base: reader.u32()?, exp: read_simple_fraction(reader)? });
+    }
+    Ok(SymbolicPowerData { coefficient, powers })
+}
+
+impl FractionData {
+    /// Append the binary encoding (see the `codec` module)
+    pub fn write_to(&self, buffer: &mut Vec<u8>) {
+        self.write_with(buffer, DEFAULT_APPROXIMATION_DENOMINATOR);
+    }
+
+    /// `write_to`, deriving n/d with the given approximation denominator
+    fn write_with(&self, buffer: &mut Vec<u8>, denominator: u32) {
+        let derived = |v: f64| approximate_parts_with(v, denominator) == (self.s, self.n, self.d);
+        match (self.corrupted, self.f, &self.symbolic) {
+            _ if self.err.is_some() => self.write_all_fields(buffer),
+            (false, None, None) => {
+                buffer.push(TAG_RATIONAL_SMALL);
+                write_sign(buffer, self.s);
+                write_varint(buffer, self.n as u64);
+                write_varint(buffer, self.d as u64);
+            }
+            (true, Some(f), None) if derived(f) => {
+                buffer.push(TAG_IRRATIONAL);
+                buffer.extend_from_slice(&f.to_le_bytes());
+            }
+            (true, Some(f), Some(sp)) if derived(f) => {
+                buffer.push(TAG_SYMBOLIC);
+                buffer.extend_from_slice(&f.to_le_bytes());
+                write_symbolic(buffer, sp);
+            }
+            _ => self.write_all_fields(buffer),
+        }
+    }
+
+    /// `TAG_RATIONAL_BIG`: flags, then every present field
+    fn write_all_fields(&self, buffer: &mut Vec<u8>) {
+        buffer.push(TAG_RATIONAL_BIG);
+        let mut flags = 0;
+        flags |= if self.corrupted { FLAG_CORRUPTED } else { 0 };
+        flags |= if self.f.is_some() { FLAG_FLOAT } else { 0 };
+        flags |= if self.symbolic.is_some() { FLAG_SYMBOLIC } else { 0 };
+        flags |= if self.err.is_some() { FLAG_ERROR } else { 0 };
+        buffer.push(flags);
+        write_sign(buffer, self.s);
+        write_varint(buffer, self.n as u64);
+        write_varint(buffer, self.d as u64);
+        if let Some(f) = self.f {
+            buffer.extend_from_slice(&f.to_le_bytes());
+        }
+        if let Some(err) = self.err {
+            buffer.extend_from_slice(&err.to_le_bytes());
+        }
+        if let Some(sp) = &self.symbolic {
+            write_symbolic(buffer, sp);
+        }
+    }
+
+    /// Read a value written by `write_to`
+    /// Returns (FractionData, bytes_consumed) or error
+    pub fn read_from(bytes: &[u8], offset: usize) -> Result<(FractionData, usize), String> {
+        let mut reader = Reader::new(bytes, offset);
+        let data = FractionData::read(&mut reader, DEFAULT_APPROXIMATION_DENOMINATOR)?;
+        Ok((data, reader.consumed()))
+    }
+
+    fn read(reader: &mut Reader, denominator: u32) -> Result<FractionData, String> {
+        let float = |f: f64, symbolic: Option<SymbolicPowerData>| {
+            let (s, n, d) = approximate_parts_with(f, denominator);
+            FractionData { s, n, d, f: Some(f), err: None, corrupted: true, symbolic }
+        };
+        Ok(match reader.tag()? {
+            TAG_RATIONAL_SMALL => FractionData {
+                s: reader.sign()?,
+                n: reader.u32()?,
+                d: reader.u32()?,
+                f: None,
+                err: None,
+                corrupted: false,
+                symbolic: None,
+            },
+            TAG_IRRATIONAL => float(reader.f64()?, None),
+            TAG_SYMBOLIC => {
+                let f = reader.f64()?;
+                float(f, Some(read_symbolic(reader)?))
+            }
+            _ => {
+                let flags = reader.byte()?;
+                FractionData {
+                    s: reader.sign()?,
+                    n: reader.u32()?,
+                    d: reader.u32()?,
+                    f: if flags & FLAG_FLOAT != 0 { Some(reader.f64()?) } else { None },
+                    err: if flags & FLAG_ERROR != 0 { Some(reader.f64()?) } else { None },
+                    corrupted: flags & FLAG_CORRUPTED != 0,
+                    symbolic: if flags & FLAG_SYMBOLIC != 0 { Some(read_symbolic(reader)?) } else { None },
+                }
+            }
+        })
+    }
+}
+
+impl ValueData {
+    /// Append the binary encoding (see the `codec` module)
+    pub fn write_to(&self, buffer: &mut Vec<u8>) {
+        match (self.s, self.n, self.d, self.f, self.corrupted, &self.symbolic) {
+            _ if self.err.is_some() => self.write_all_fields(buffer),
+            (Some(s), Some(n), Some(d), None, false, None) => {
+                buffer.push(TAG_RATIONAL_SMALL);
+                write_sign(buffer, s);
+                write_varint(buffer, n as u64);
+                write_varint(buffer, d as u64);
+            }
+            (None, None, None, Some(f), true, None) => {
+                buffer.push(TAG_IRRATIONAL);
+                buffer.extend_from_slice(&f.to_le_bytes());
+            }
+            (None, None, None, Some(f), true, Some(sp)) => {
+                buffer.push(TAG_SYMBOLIC);
+                buffer.extend_from_slice(&f.to_le_bytes());
+                write_symbolic(buffer, sp);
+            }
+            _ => self.write_all_fields(buffer),
+        }
+    }
+
+    /// `TAG_RATIONAL_BIG`: flags, then every present field
+    fn write_all_fields(&self, buffer: &mut Vec<u8>) {
+        buffer.push(TAG_RATIONAL_BIG);
+        let parts = self.s.is_some() && self.n.is_some() && self.d.is_some();
+        let mut flags = 0;
+        flags |= if self.corrupted { FLAG_CORRUPTED } else { 0 };
+        flags |= if self.f.is_some() { FLAG_FLOAT } else { 0 };
+        flags |= if self.symbolic.is_some() { FLAG_SYMBOLIC } else { 0 };
+        flags |= if parts { FLAG_PARTS } else { 0 };
+        flags |= if self.err.is_some() { FLAG_ERROR } else { 0 };
+        buffer.push(flags);
+        if let (Some(s), Some(n), Some(d)) = (self.s, self.n, self.d) {
+            write_sign(buffer, s);
+            write_varint(buffer, n as u64);
+            write_varint(buffer, d as u64);
+        }
+        if let Some(f) = self.f {
+            buffer.extend_from_slice(&f.to_le_bytes());
+        }
+        if let Some(err) = self.err {
+            buffer.extend_from_slice(&err.to_le_bytes());
+        }
+        if let Some(sp) = &self.symbolic {
+            write_symbolic(buffer, sp);
+        }
+    }
+
+    /// Read a value written by `write_to`
+    /// Returns (ValueData, bytes_consumed) or error
+    pub fn read_from(bytes: &[u8], offset: usize) -> Result<(ValueData, usize), String> {
+        let mut reader = Reader::new(bytes, offset);
+        let empty = ValueData { s: None, n: None, d: None, f: None, err: None, corrupted: true, symbolic: None };
+        let data = match reader.tag()? {
+            TAG_RATIONAL_SMALL => ValueData {
+                s: Some(reader.sign()?),
+                n: Some(reader.u32()?),
+                d: Some(reader.u32()?),
+                corrupted: false,
+                ..empty
+            },
+            TAG_IRRATIONAL => ValueData { f: Some(reader.f64()?), ..empty },
+            TAG_SYMBOLIC => {
+                let f = reader.f64()?;
+                ValueData { f: Some(f), symbolic: Some(read_symbolic(&mut reader)?), ..empty }
+            }
+            _ => {
+                let flags = reader.byte()?;
+                let (s, n, d) = if flags & FLAG_PARTS != 0 {
+                    (Some(reader.sign()?), Some(reader.u32()?), Some(reader.u32()?))
+                } else {
+                    (None, None, None)
+                };
+                ValueData {
+                    s,
+                    n,
+                    d,
+                    f: if flags & FLAG_FLOAT != 0 { Some(reader.f64()?) } else { None },
+                    err: if flags & FLAG_ERROR != 0 { Some(reader.f64()?) } else { None },
+                    corrupted: flags & FLAG_CORRUPTED != 0,
+                    symbolic: if flags & FLAG_SYMBOLIC != 0 { Some(read_symbolic(&mut reader)?) } else { None },
+                }
+            }
+        };
+        Ok((data, reader.consumed()))
+    }
+}
+
+/// Variables in the order their presence bits are written
+fn all_vars() -> impl Iterator<Item = Var> {
+    (0..6).filter_map(Var::from_byte)
+}
+
+impl EvaluatedNote {
+    /// Append the binary encoding: a presence bit per variable, for the
+    /// instrument and for custom variables, the present values, the
+    /// instrument, the corruption flags, the corruption sources and the
+    /// custom variables
+    pub fn write_to(&self, buffer: &mut Vec<u8>) {
+        let mut present = all_vars()
+            .filter(|&var| self.get_var(var).is_some())
+            .fold(0u8, |bits, var| bits | (1 << var as u8));
+        if self.instrument.is_some() {
+            present |= NOTE_INSTRUMENT;
+        }
+        if !self.custom.is_empty() {
+            present |= NOTE_CUSTOM;
+        }
+        buffer.push(present);
+        let approximation = ApproximationConfig::default();
+        for var in all_vars() {
+            if let Some(value) = self.get_var(var) {
+                value.write_with(buffer, approximation.denominator(var));
+            }
+        }
+        if let Some(instrument) = self.instrument {
+            write_varint(buffer, instrument as u64);
+        }
+        write_varint(buffer, self.corruption_flags as u64);
+        write_varint(buffer, self.corruption_sources.len() as u64);
+        for source in &self.corruption_sources {
+            write_string(buffer, &source.variable);
+            write_string(buffer, &source.op);
+            write_varint(buffer, source.pc as u64);
+            buffer.push(source.lossy as u8);
+        }
+        if !self.custom.is_empty() {
+            let mut custom: Vec<_> = self.custom.iter().collect();
+            custom.sort_unstable_by_key(|&(&index, _)| index);
+            write_varint(buffer, custom.len() as u64);
+            for (&index, value) in custom {
+                buffer.push(index);
+                value.write_with(buffer, DEFAULT_APPROXIMATION_DENOMINATOR);
+            }
+            write_varint(buffer, self.custom_corruption_flags as u64);
+        }
+    }
+
+    /// Read a note written by `write_to`
+    /// Returns (EvaluatedNote, bytes_consumed) or error
+    pub fn read_from(bytes: &[u8], offset: usize) -> Result<(EvaluatedNote, usize), String> {
+        let mut reader = Reader::new(bytes, offset);
+        let note = EvaluatedNote::read(&mut reader)?;
+        Ok((note, reader.consumed()))
+    }
+
+    fn read(reader: &mut Reader) -> Result<EvaluatedNote, String> {
+        let mut note = EvaluatedNote::default();
+        let present = reader.byte()?;
+        let approximation = ApproximationConfig::default();
+        for var in all_vars().filter(|&var| present & (1 << var as u8) != 0) {
+            note.set_var(var, FractionData::read(reader, approximation.denominator(var))?);
+        }
+        if present & NOTE_INSTRUMENT != 0 {
+            note.instrument = Some(reader.u32()?);
+        }
+        let start = reader.pos;
+        let flags = reader.varint()?;
+        note.corruption_flags =
+            u16::try_from(flags).map_err(|_| format!("Corruption flags {} out of range at offset {}", flags, start))?;
+        let count = reader.varint()?;
+        for _ in 0..count {
+            note.corruption_sources.push(CorruptionSource {
+                variable: reader.string()?,
+                op: reader.string()?,
+                pc: reader.u32()?,
+                lossy: reader.byte()? != 0,
+            });
+        }
+        if present & NOTE_CUSTOM != 0 {
+            let count = reader.varint()?;
+            for _ in 0..count {
+                let index = custom_index(reader)?;
+                note.custom.insert(index, FractionData::read(reader, DEFAULT_APPROXIMATION_DENOMINATOR)?);
+            }
+            note.custom_corruption_flags = reader.u32()?;
+        }
+        Ok(note)
+    }
+}
+
+/// Read a custom variable index, rejecting any outside the custom range
+fn custom_index(reader: &mut Reader) -> Result<u8, String> {
+    let index = reader.byte()?;
+    if !is_custom_var(index) {
+        return Err(format!("Invalid custom variable index {} at offset {}", index, reader.pos - 1));
+    }
+    Ok(index)
+}
+
+/// CRC-32 (IEEE 802.3, as used by zip and PNG) of a byte slice
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Standard base64 with padding
+pub fn encode_base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &b)| group | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(group >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decode standard padded base64, rejecting anything `encode_base64`
+/// would not produce
+pub fn decode_base64(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.as_bytes();
+    if !text.len().is_multiple_of(4) {
+        return Err(format!("Base64 length {} is not a multiple of 4", text.len()));
+    }
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    for (index, chunk) in text.chunks(4).enumerate() {
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && (index + 1) * 4 != text.len()) {
+            return Err(format!("Misplaced base64 padding at offset {}", index * 4));
+        }
+        let mut group = 0u32;
+        for (i, &c) in chunk[..4 - padding].iter().enumerate() {
+            let sextet = BASE64_ALPHABET
+                .iter()
+                .position(|&a| a == c)
+                .ok_or_else(|| format!("Invalid base64 character {:?} at offset {}", c as char, index * 4 + i))?;
+            group |= (sextet as u32) << (18 - 6 * i);
+        }
+        out.extend_from_slice(&group.to_be_bytes()[1..4 - padding]);
+    }
+    Ok(out)
+}
+
+/// Base64 of `payload` followed by its CRC-32
+pub fn seal(payload: &[u8]) -> String {
+    let mut bytes = payload.to_vec();
+    bytes.extend_from_slice(&crc32(payload).to_le_bytes());
+    encode_base64(&bytes)
+}
+
+/// The payload of a string written by `seal`, checking its CRC-32
+pub fn unseal(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = decode_base64(text)?;
+    let split = bytes.len().checked_sub(4).ok_or_else(|| "Sealed data too short".to_string())?;
+    let stored = u32::from_le_bytes(bytes[split..].try_into().expect("4 bytes"));
+    bytes.truncate(split);
+    if crc32(&bytes) != stored {
+        return Err("CRC mismatch in sealed data".to_string());
+    }
+    Ok(bytes)
+}
+
+impl CompiledExpression {
+    /// Seal the bytecode with its dependencies, base-note flag and source
+    /// text (see the `codec` module)
+    pub fn to_base64(&self) -> String {
+        let mut buffer = Vec::with_capacity(8 + self.bytecode.len() + self.source_text.len());
+        buffer.extend_from_slice(EXPRESSION_MAGIC);
+        buffer.push(EXPRESSION_VERSION);
+        buffer.push(if self.references_base { EXPRESSION_REFERENCES_BASE } else { 0 });
+        write_varint(&mut buffer, self.dependencies.len() as u64);
+        for &id in &self.dependencies {
+            write_varint(&mut buffer, id as u64);
+        }
+        write_string(&mut buffer, &self.source_text);
+        write_varint(&mut buffer, self.bytecode.len() as u64);
+        buffer.extend_from_slice(&self.bytecode);
+        seal(&buffer)
+    }
+
+    /// Read an expression written by `to_base64`
+    pub fn from_base64(text: &str) -> Result<CompiledExpression, String> {
+        let bytes = unseal(text)?;
+        let mut reader = Reader::new(&bytes, 0);
+        if reader.take(EXPRESSION_MAGIC.len()).ok() != Some(&EXPRESSION_MAGIC[..]) {
+            return Err("Not a compiled expression".to_string());
+        }
+        let version = reader.byte()?;
+        if version != EXPRESSION_VERSION {
+            return Err(format!("Unsupported expression version {}", version));
+        }
+
+        let flags = reader.byte()?;
+        let count = reader.varint()?;
+        let mut dependencies = Vec::new();
+        for _ in 0..count {
+            dependencies.push(reader.u32()?);
+        }
+        let source_text = reader.string()?;
+        let len = reader.varint()? as usize;
+        let bytecode = reader.take(len)?.to_vec();
+        if reader.pos != bytes.len() {
+            return Err(format!("Trailing data at offset {}", reader.pos));
+        }
+        Ok(CompiledExpression {
+            bytecode,
+            dependencies,
+            references_base: flags & EXPRESSION_REFERENCES_BASE != 0,
+            source_text,
+        })
+    }
+}
+
+/// Encode a whole cache, notes in id order
+pub fn encode_cache(cache: &HashMap<u32, EvaluatedNote>) -> Vec<u8> {
+    let mut ids: Vec<u32> = cache.keys().copied().collect();
+    ids.sort_unstable();
+
+    let mut buffer = Vec::with_capacity(8 + cache.len() * 24);
+    buffer.extend_from_slice(CACHE_MAGIC);
+    buffer.push(CACHE_VERSION);
+    write_varint(&mut buffer, ids.len() as u64);
+    for id in ids {
+        write_varint(&mut buffer, id as u64);
+        cache[&id].write_to(&mut buffer);
+    }
+    buffer
+}
+
+/// Decode a cache written by `encode_cache`
+pub fn decode_cache(bytes: &[u8]) -> Result<HashMap<u32, EvaluatedNote>, String> {
+    let mut reader = Reader::new(bytes, 0);
+    if reader.take(CACHE_MAGIC.len()).ok() != Some(&CACHE_MAGIC[..]) {
+        return Err("Not an encoded cache".to_string());
+    }
+    let version = reader.byte()?;
+    if version != CACHE_VERSION {
+        return Err(format!("Unsupported cache version {}", version));
+    }
+
+    let count = reader.varint()?;
+    let mut cache = HashMap::new();
+    for _ in 0..count {
+        let id = reader.u32()?;
+        cache.insert(id, EvaluatedNote::read(&mut reader)?);
+    }
+    if reader.pos != bytes.len() {
+        return Err(format!("Trailing data at offset {}", reader.pos));
+    }
+    Ok(cache)
+}
+
+/// Encode registered bytecode, notes in id order
+pub fn encode_bytecode(store: &HashMap<u32, NoteBytecode>) -> Vec<u8> {
+    let mut ids: Vec<u32> = store.keys().copied().collect();
+    ids.sort_unstable();
+
+    let mut buffer = Vec::with_capacity(8 + store.len() * 32);
+    buffer.extend_from_slice(BYTECODE_MAGIC);
+    buffer.push(BYTECODE_VERSION);
+    write_varint(&mut buffer, ids.len() as u64);
+    for id in ids {
+        let note = &store[&id];
+        write_varint(&mut buffer, id as u64);
+        let present = all_vars()
+            .filter(|&var| note.get_expr(var).is_some())
+            .fold(0u8, |bits, var| bits | (1 << var as u8));
+        buffer.push(present | if note.custom.is_empty() { 0 } else { NOTE_CUSTOM });
+        for (bytecode, length) in all_vars().filter_map(|var| note.get_expr(var)) {
+            write_varint(&mut buffer, length as u64);
+            buffer.extend_from_slice(&bytecode[..length]);
+        }
+        if !note.custom.is_empty() {
+            write_varint(&mut buffer, note.custom.len() as u64);
+            for (&index, (bytecode, length)) in &note.custom {
+                buffer.push(index);
+                write_varint(&mut buffer, *length as u64);
+                buffer.extend_from_slice(&bytecode[..*length]);
+            }
+        }
+    }
+    buffer
+}
+
+/// Decode bytecode written by `encode_bytecode`, notes in the order written
+pub fn decode_bytecode(bytes: &[u8]) -> Result<Vec<(u32, NoteBytecode)>, String> {
+    let mut reader = Reader::new(bytes, 0);
+    if reader.take(BYTECODE_MAGIC.len()).ok() != Some(&BYTECODE_MAGIC[..]) {
+        return Err("Not encoded bytecode".to_string());
+    }
+    let version = reader.byte()?;
+    if version != BYTECODE_VERSION {
+        return Err(format!("Unsupported bytecode version {}", version));
+    }
+
+    let count = reader.varint()?;
+    let mut notes = Vec::new();
+    for _ in 0..count {
+        let id = reader.u32()?;
+        let present = reader.byte()?;
+        if present & !(NOTE_VARS | NOTE_CUSTOM) != 0 {
+            return Err(format!("Invalid presence bits {:#04x} at offset {}", present, reader.pos - 1));
+        }
+        let mut note = NoteBytecode::default();
+        for var in all_vars().filter(|&var| present & (1 << var as u8) != 0) {
+            let length = reader.varint()? as usize;
+            let bytecode = reader.take(length)?;
+            note.set_expr(var, bytecode, length);
+        }
+        if present & NOTE_CUSTOM != 0 {
+            let count = reader.varint()?;
+            for _ in 0..count {
+                let index = custom_index(&mut reader)?;
+                let length = reader.varint()? as usize;
+                note.set_custom(index, reader.take(length)?, length);
+            }
+        }
+        notes.push((id, note));
+    }
+    if reader.pos != bytes.len() {
+        return Err(format!("Trailing data at offset {}", reader.pos));
+    }
+    Ok(notes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fraction::Fraction;
+    use crate::value::{custom_flag_for_var, Value};
+
+    fn json<T: serde::Serialize>(value: &T) -> String {
+        serde_json::to_string(value).unwrap()
+    }
+
+    fn semitones(coefficient: i32) -> Value {
+        let third = Value::rational(2, 1).pow(&Value::rational(1, 3));
+        let fifth = Value::rational(3, 1).pow(&Value::rational(1, 5));
+        Value::rational(coefficient, 1).mul(&third).mul(&fifth)
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buffer = vec![0xff];
+            write_varint(&mut buffer, value);
+            assert_eq!(read_varint(&buffer, 1), Ok((value, buffer.len() - 1)));
+        }
+        assert!(read_varint(&[0x80, 0x80], 0).is_err());
+        assert!(read_varint(&[0xff; 11], 0).is_err());
+    }
+
+    #[test]
+    fn test_fraction_data_round_trip() {
+        let big = Fraction::from_string("123456789012345678901/123456789012345678900").unwrap();
+        let values = [
+            (FractionData::from_fraction(&Fraction::new(-3, 4)), TAG_RATIONAL_SMALL),
+            (FractionData::default(), TAG_RATIONAL_SMALL),
+            (FractionData::from_fraction(&big), TAG_RATIONAL_BIG),
+            (FractionData::from_value(&Value::irrational(std::f64::consts::PI)), TAG_IRRATIONAL),
+            (FractionData::from_value(&Value::irrational(-1e12)), TAG_IRRATIONAL),
+            (FractionData::from_value(&semitones(-440)), TAG_SYMBOLIC),
+            (FractionData::from_value(&Value::irrational(0.1).track_error()), TAG_RATIONAL_BIG),
+        ];
+        let mut buffer = Vec::new();
+        for (data, tag) in &values {
+            let start = buffer.len();
+            data.write_to(&mut buffer);
+            assert_eq!(buffer[start], *tag, "tag for {}", json(data));
+        }
+
+        let mut offset = 0;
+        let mut last = 0;
+        for (data, _) in &values {
+            let (decoded, used) = FractionData::read_from(&buffer, offset).unwrap();
+            assert_eq!(json(&decoded), json(data));
+            last = offset;
+            offset += used;
+        }
+        assert_eq!(offset, buffer.len());
+        assert!(values[5].0.to_value() == semitones(-440));
+
+        assert!(FractionData::read_from(&buffer[..buffer.len() - 1], last).is_err());
+        assert!(FractionData::read_from(&[7], 0).is_err());
+    }
+
+    #[test]
+    fn test_value_data_round_trip() {
+        let big = Value::Rational(Fraction::from_string("98765432109876543210").unwrap());
+        let legacy = ValueData { s: Some(1), n: Some(3), d: Some(2), f: Some(1.5), err: None, corrupted: true, symbolic: None };
+        let values = [
+            ValueData::from_value(&Value::rational(7, 12)),
+            ValueData::from_value(&big),
+            ValueData::from_value(&Value::irrational(0.1)),
+            ValueData::from_value(&semitones(3)),
+            ValueData::from_value(&Value::irrational(0.1).track_error()),
+            legacy,
+        ];
+        for data in &values {
+            let mut buffer = Vec::new();
+            data.write_to(&mut buffer);
+            let (decoded, used) = ValueData::read_from(&buffer, 0).unwrap();
+            assert_eq!((json(&decoded), used), (json(data), buffer.len()));
+        }
+    }
+
+    #[test]
+    fn test_cache_round_trip_and_size() {
+        let mut cache = HashMap::new();
+        for id in 0..1000u32 {
+            let mut note = EvaluatedNote::default();
+            note.set_var(Var::StartTime, FractionData::from_fraction(&Fraction::new(id as i32, 4)));
+            note.set_var(Var::Duration, FractionData::from_fraction(&Fraction::new(1, 4)));
+            if id % 10 == 0 {
+                note.set_var(Var::Frequency, FractionData::from_value(&semitones(440)));
+                note.set_corrupted(Var::Frequency);
+                note.corruption_sources.push(CorruptionSource {
+                    variable: "frequency".to_string(),
+                    op: "Pow".to_string(),
+                    pc: 18,
+                    lossy: id % 20 == 0,
+                });
+            } else {
+                note.set_var(Var::Frequency, FractionData::from_fraction(&Fraction::new(440 + id as i32, 1)));
+            }
+            if id % 3 == 0 {
+                note.instrument = Some(id / 3);
+            }
+            cache.insert(id, note);
+        }
+
+        let bytes = encode_cache(&cache);
+        // Budget: 24 bytes per note on average
+        assert!(bytes.len() < 24 * 1000, "{} bytes", bytes.len());
+        assert!(bytes.len() * 4 < json(&cache).len());
+
+        let decoded = decode_cache(&bytes).unwrap();
+        assert_eq!(decoded.len(), cache.len());
+        for (id, note) in &cache {
+            assert_eq!(json(&decoded[id]), json(note));
+        }
+
+        assert!(decode_cache(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode_cache(b"RMTX\x01\x00").is_err());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(decode_cache(&trailing).is_err());
+    }
+
+    #[test]
+    fn test_note_custom_round_trip() {
+        let mut note = EvaluatedNote::default();
+        note.set_var(Var::Frequency, FractionData::from_fraction(&Fraction::new(440, 1)));
+        note.custom.insert(40, FractionData::from_fraction(&Fraction::new(100, 127)));
+        note.custom.insert(41, FractionData::from_value(&semitones(2)));
+        note.custom_corruption_flags = custom_flag_for_var(41);
+
+        let mut bytes = Vec::new();
+        note.write_to(&mut bytes);
+        let (decoded, consumed) = EvaluatedNote::read_from(&bytes, 0).unwrap();
+        assert_eq!(consumed, bytes.len());
+        assert_eq!(json(&decoded.frequency), json(&note.frequency));
+        assert_eq!(decoded.custom_corruption_flags, note.custom_corruption_flags);
+        assert_eq!(decoded.custom.len(), 2);
+        for index in [40, 41] {
+            assert_eq!(json(&decoded.custom[&index]), json(&note.custom[&index]));
+        }
+
+        // Presence, frequency (5 bytes), flags, source count, custom count
+        let mut bad_index = bytes.clone();
+        assert_eq!(bad_index[9], 40);
+        bad_index[9] = 64;
+        assert_eq!(
+            EvaluatedNote::read_from(&bad_index, 0).err().unwrap(),
+            "Invalid custom variable index 64 at offset 9"
+        );
+    }
+
+    #[test]
+    fn test_base64_and_crc() {
+        // RFC 4648 and the standard CRC-32 check value
+        for (bytes, text) in [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foobar", "Zm9vYmFy")] {
+            assert_eq!(encode_base64(bytes.as_bytes()), text);
+            assert_eq!(decode_base64(text).unwrap(), bytes.as_bytes());
+        }
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        let all: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode_base64(&encode_base64(&all)).unwrap(), all);
+
+        for bad in ["Zm9", "Zg=a", "Z===", "Zg==Zm9v", "Zm9*"] {
+            assert!(decode_base64(bad).is_err(), "{}", bad);
+        }
+        let sealed = seal(b"bytecode");
+        assert_eq!(unseal(&sealed).unwrap(), b"bytecode");
+        assert!(unseal(&encode_base64(b"byt")).is_err());
+    }
+
+    #[test]
+    fn test_compiled_expression_round_trip() {
+        use crate::bytecode::{instruction_len, write_const, write_load_ref, Op};
+
+        // One instruction for every opcode
+        let mut bytecode = Vec::new();
+        for op in (0..=255).filter_map(Op::from_byte) {
+            match op {
+                Op::LoadConst => write_const(&mut bytecode, &Fraction::new(-3, 4)).unwrap(),
+                Op::LoadConstBig => write_const(&mut bytecode, &Fraction::from_string("12345678901234567890/7").unwrap()).unwrap(),
+                Op::LoadRef => write_load_ref(&mut bytecode, 42, Var::Duration as u8),
+                Op::LoadRefWide => write_load_ref(&mut bytecode, 100_000, Var::StartTime as u8),
+                Op::LoadBase => bytecode.extend([op as u8, Var::Tempo as u8]),
+                Op::CallMacro => bytecode.extend([op as u8, 0, 7]),
+                _ => bytecode.push(op as u8),
+            }
+        }
+        let mut ops = Vec::new();
+        let mut pc = 0;
+        while pc < bytecode.len() {
+            ops.push(Op::from_byte(bytecode[pc]).unwrap());
+            pc += instruction_len(&bytecode, pc).unwrap();
+        }
+        assert_eq!(ops.len(), (0..=255).filter_map(Op::from_byte).count());
+        assert!(ops.contains(&Op::LoadConstBig) && ops.contains(&Op::LoadRefWide));
+
+        let expression = CompiledExpression {
+            bytecode,
+            dependencies: vec![42, 100_000],
+            references_base: true,
+            source_text: "module.getNoteById(42).getVariable('duration') · ♪".to_string(),
+        };
+        let text = expression.to_base64();
+        let decoded = CompiledExpression::from_base64(&text).unwrap();
+        assert_eq!(json(&decoded), json(&expression));
+        let bare = crate::bytecode::to_base64(&expression.bytecode);
+        assert_eq!(crate::bytecode::from_base64(&bare).unwrap(), expression.bytecode);
+        assert!(bare.len() < json(&expression.bytecode).len());
+
+        // Truncated, corrupted or foreign strings are errors, not panics
+        for len in 0..text.len() {
+            assert!(CompiledExpression::from_base64(&text[..len]).is_err(), "{}", len);
+        }
+        let mut corrupted = text.clone().into_bytes();
+        corrupted[10] = if corrupted[10] == b'A' { b'B' } else { b'A' };
+        assert!(CompiledExpression::from_base64(std::str::from_utf8(&corrupted).unwrap()).is_err());
+        assert!(CompiledExpression::from_base64(&seal(b"RMTC\x04")).is_err());
+        assert!(CompiledExpression::from_base64("not base64!").is_err());
+
+        let empty = CompiledExpression::default();
+        assert_eq!(json(&CompiledExpression::from_base64(&empty.to_base64()).unwrap()), json(&empty));
+    }
+}
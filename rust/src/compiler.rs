@@ -3,9 +3,14 @@
 //! Compiles text-based expressions into compact binary bytecode
 //! that can be evaluated without runtime string compilation.
 
-use crate::bytecode::{write_i32, write_u16, Op, Var};
+use crate::bytecode::{
+    eliminate_common_subexpressions, is_custom_var, optimize, write_const, write_load_ref, Op, Var, CUSTOM_VAR_FIRST, CUSTOM_VAR_LAST, CUSTOM_VAR_PREFIX, PRODUCT_METHODS,
+};
+use crate::fraction::Fraction;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use wasm_bindgen::prelude::*;
 
 /// Compiled expression result
@@ -30,6 +35,14 @@ pub struct ExpressionCompiler {
     bytecode: Vec<u8>,
     dependencies: HashSet<u32>,
     references_base: bool,
+    /// First error hit by the last compile (the bytecode fell back to zero)
+    last_error: Option<String>,
+    /// Run `bytecode::optimize` over compiled expressions
+    optimize: bool,
+    /// Run `bytecode::eliminate_common_subexpressions` over compiled expressions
+    share_subexpressions: bool,
+    /// Custom variable indices by the name after "custom:"
+    custom_vars: HashMap<String, u8>,
 }
 
 #[wasm_bindgen]
@@ -41,9 +54,32 @@ impl ExpressionCompiler {
             bytecode: Vec::new(),
             dependencies: HashSet::new(),
             references_base: false,
+            last_error: None,
+            optimize: false,
+            share_subexpressions: false,
+            custom_vars: HashMap::new(),
         }
     }
 
+    /// Turn constant folding of compiled bytecode on or off (see `bytecode::optimize`)
+    #[wasm_bindgen(js_name = setOptimize)]
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// Turn reuse of repeated subexpressions on or off (see
+    /// `bytecode::eliminate_common_subexpressions`)
+    #[wasm_bindgen(js_name = setShareSubexpressions)]
+    pub fn set_share_subexpressions(&mut self, share: bool) {
+        self.share_subexpressions = share;
+    }
+
+    /// Compile `getVariable('custom:<name>')` to custom variable `index`
+    #[wasm_bindgen(js_name = registerCustomVariable)]
+    pub fn register_custom_variable_js(&mut self, name: &str, index: u8) -> Result<(), JsValue> {
+        self.register_custom_variable(name, index).map_err(|e| JsValue::from_str(&e))
+    }
+
     /// Compile a text expression to binary bytecode from JavaScript
     #[wasm_bindgen(js_name = compile)]
     pub fn compile_js(&mut self, text_expr: &str) -> JsValue {
@@ -52,6 +88,23 @@ impl ExpressionCompiler {
     }
 }
 
+/// Encode a compiled expression (as returned by `compile`) for saving as
+/// a string; see `CompiledExpression::to_base64`
+#[wasm_bindgen(js_name = compiledExpressionToBase64)]
+pub fn compiled_expression_to_base64_js(expression: JsValue) -> Result<String, JsValue> {
+    let expression: CompiledExpression = serde_wasm_bindgen::from_value(expression)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse expression: {}", e)))?;
+    Ok(expression.to_base64())
+}
+
+/// Decode a string written by `compiledExpressionToBase64`, throwing if it
+/// is truncated or corrupted
+#[wasm_bindgen(js_name = compiledExpressionFromBase64)]
+pub fn compiled_expression_from_base64_js(text: &str) -> Result<JsValue, JsValue> {
+    let expression = CompiledExpression::from_base64(text).map_err(|e| JsValue::from_str(&e))?;
+    serde_wasm_bindgen::to_value(&expression).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
 impl Default for ExpressionCompiler {
     fn default() -> Self {
         ExpressionCompiler::new()
@@ -59,18 +112,37 @@ impl Default for ExpressionCompiler {
 }
 
 impl ExpressionCompiler {
+    /// Compile `getVariable('custom:<name>')` to custom variable `index`,
+    /// which must be in `CUSTOM_VAR_FIRST..=CUSTOM_VAR_LAST`
+    ///
+    /// Unregistered names may still give the index itself, as in 'custom:40'.
+    pub fn register_custom_variable(&mut self, name: &str, index: u8) -> Result<(), String> {
+        if !is_custom_var(index) {
+            return Err(format!(
+                "Custom variable index {} is outside {}..={}",
+                index, CUSTOM_VAR_FIRST, CUSTOM_VAR_LAST
+            ));
+        }
+        if !is_identifier(name) {
+            return Err(format!("Invalid custom variable name: {}", name));
+        }
+        self.custom_vars.insert(name.to_string(), index);
+        Ok(())
+    }
+
     /// Compile a text expression to binary bytecode
     pub fn compile(&mut self, text_expr: &str) -> CompiledExpression {
         // Reset state
         self.bytecode.clear();
         self.dependencies.clear();
         self.references_base = false;
+        self.last_error = None;
 
         let source_text = text_expr.to_string();
         let trimmed = text_expr.trim();
 
         if trimmed.is_empty() {
-            self.emit_constant(0, 1);
+            self.emit_zero();
             return self.build_result(source_text);
         }
 
@@ -79,20 +151,38 @@ impl ExpressionCompiler {
             Ok(()) => {}
             Err(e) => {
                 // If parsing fails, emit a constant 0
-                eprintln!("Failed to compile expression '{}': {}", trimmed, e);
+                log_error!("Failed to compile expression '{}': {}", trimmed, e);
+                self.last_error = Some(e);
                 self.bytecode.clear();
                 self.dependencies.clear();
                 self.references_base = false;
-                self.emit_constant(0, 1);
+                self.emit_zero();
             }
         }
 
         self.build_result(source_text)
     }
 
+    /// Compile a text expression, failing instead of falling back to zero
+    pub fn try_compile(&mut self, text_expr: &str) -> Result<CompiledExpression, String> {
+        let result = self.compile(text_expr);
+        match self.last_error.take() {
+            Some(e) => Err(e),
+            None => Ok(result),
+        }
+    }
+
     fn build_result(&self, source_text: String) -> CompiledExpression {
+        let mut bytecode = if self.optimize {
+            optimize(&self.bytecode, self.bytecode.len()).0
+        } else {
+            self.bytecode.clone()
+        };
+        if self.share_subexpressions {
+            bytecode = eliminate_common_subexpressions(&bytecode, bytecode.len()).0;
+        }
         CompiledExpression {
-            bytecode: self.bytecode.clone(),
+            bytecode,
             dependencies: self.dependencies.iter().copied().collect(),
             references_base: self.references_base,
             source_text,
@@ -103,35 +193,10 @@ impl ExpressionCompiler {
     fn parse_and_emit(&mut self, expr: &str) -> Result<(), String> {
         let trimmed = expr.trim();
 
-        // Try to parse as a sum (handles .add/.sub chains)
-        if let Some(terms) = self.try_split_add_sub(trimmed) {
-            if terms.len() > 1 {
-                return self.emit_sum(&terms);
-            }
-        }
-
-        // Single term (possibly a product)
-        self.parse_and_emit_product(trimmed)
-    }
-
-    /// Parse and emit a product expression
-    fn parse_and_emit_product(&mut self, expr: &str) -> Result<(), String> {
-        let trimmed = expr.trim();
-
-        // Try to split by .mul/.div
-        if let Some((base, operations)) = self.try_split_mul_div(trimmed) {
-            if !operations.is_empty() {
-                self.parse_and_emit_atomic(&base)?;
-                for (op, operand) in operations {
-                    self.parse_and_emit_atomic(&operand)?;
-                    match op.as_str() {
-                        "mul" => self.bytecode.push(Op::Mul as u8),
-                        "div" => self.bytecode.push(Op::Div as u8),
-                        _ => return Err(format!("Unknown operation: {}", op)),
-                    }
-                }
-                return Ok(());
-            }
+        // A method chain (.add/.sub/.mul/.abs/...) applies its calls left
+        // to right, as Fraction.js does: a.sub(b).abs().mul(c) is |a - b| * c
+        if let Some((base, calls)) = self.split_chain(trimmed)? {
+            return self.emit_chain(&base, calls);
         }
 
         // Single atomic
@@ -169,39 +234,21 @@ impl ExpressionCompiler {
 
         // 6. Try beat unit pattern: new Fraction(60).div(module.findTempo(ref))
         if let Some(ref_kind) = self.match_beat_unit(&trimmed) {
-            self.emit_constant(60, 1);
-            self.emit_find_tempo(&ref_kind)?;
-            self.bytecode.push(Op::Div as u8);
-            return Ok(());
+            return self.emit_beat_unit(&ref_kind);
         }
 
         // 7. Try simple number literal
+        if let Ok(num) = trimmed.parse::<BigInt>() {
+            return self.emit_constant(num, 1);
+        }
         if let Ok(num) = trimmed.parse::<f64>() {
             let frac = self.decimal_to_fraction(num);
-            self.emit_constant(frac.0, frac.1);
-            return Ok(());
+            return self.emit_constant(frac.0, frac.1);
         }
 
         // 8. Handle nested expressions with method chains
-        if let Some(terms) = self.try_split_add_sub(&trimmed) {
-            if terms.len() > 1 {
-                return self.emit_sum(&terms);
-            }
-        }
-
-        if let Some((base, operations)) = self.try_split_mul_div(&trimmed) {
-            if !operations.is_empty() {
-                self.parse_and_emit_atomic(&base)?;
-                for (op, operand) in operations {
-                    self.parse_and_emit_atomic(&operand)?;
-                    match op.as_str() {
-                        "mul" => self.bytecode.push(Op::Mul as u8),
-                        "div" => self.bytecode.push(Op::Div as u8),
-                        _ => return Err(format!("Unknown operation: {}", op)),
-                    }
-                }
-                return Ok(());
-            }
+        if let Some((base, calls)) = self.split_chain(&trimmed)? {
+            return self.emit_chain(&base, calls);
         }
 
         // 9. Handle bare variable names (legacy compatibility)
@@ -218,14 +265,17 @@ impl ExpressionCompiler {
         }
 
         // Fallback: emit zero
-        eprintln!("Unable to parse expression: {}", trimmed);
-        self.emit_constant(0, 1);
+        log_warn!("Unable to parse expression: {}", trimmed);
+        if self.last_error.is_none() {
+            self.last_error = Some(format!("Unable to parse expression: {}", trimmed));
+        }
+        self.emit_zero();
         Ok(())
     }
 
     // === Pattern matching helpers ===
 
-    fn match_fraction_literal(&self, s: &str) -> Option<(i32, i32)> {
+    fn match_fraction_literal(&self, s: &str) -> Option<(BigInt, BigInt)> {
         // Match: new Fraction(n) or new Fraction(n, d)
         let s = s.trim();
         if !s.starts_with("new") {
@@ -248,15 +298,19 @@ impl ExpressionCompiler {
         let args_str = &s[start + 9..end];
         let args: Vec<&str> = args_str.split(',').map(|s| s.trim()).collect();
 
+        // Integer arguments are exact at any size; a lone decimal is approximated
         match args.len() {
-            1 => {
-                let num: f64 = args[0].parse().ok()?;
-                let (n, d) = self.decimal_to_fraction(num);
-                Some((n, d))
-            }
+            1 => match args[0].parse::<BigInt>() {
+                Ok(num) => Some((num, BigInt::from(1))),
+                Err(_) => {
+                    let num: f64 = args[0].parse().ok()?;
+                    let (n, d) = self.decimal_to_fraction(num);
+                    Some((n.into(), d.into()))
+                }
+            },
             2 => {
-                let num: i32 = args[0].parse().ok()?;
-                let den: i32 = args[1].parse().ok()?;
+                let num: BigInt = args[0].parse().ok()?;
+                let den: BigInt = args[1].parse().ok()?;
                 Some((num, den))
             }
             _ => None,
@@ -270,7 +324,7 @@ impl ExpressionCompiler {
 
         if s.starts_with(prefix) && s.ends_with(suffix) {
             let var_name = &s[prefix.len()..s.len() - suffix.len()];
-            return Some(var_name.to_string());
+            return is_var_name(var_name).then(|| var_name.to_string());
         }
         None
     }
@@ -293,7 +347,7 @@ impl ExpressionCompiler {
 
         if after_id.starts_with(var_prefix) && after_id.ends_with(var_suffix) {
             let var_name = &after_id[var_prefix.len()..after_id.len() - var_suffix.len()];
-            return Some((note_id, var_name.to_string()));
+            return is_var_name(var_name).then(|| (note_id, var_name.to_string()));
         }
 
         None
@@ -333,8 +387,28 @@ impl ExpressionCompiler {
         None
     }
 
+    /// Match a chain that starts `new Fraction(60).div(module.findTempo(ref))`,
+    /// returning the reference
+    ///
+    /// Other numerators are left to the plain expansion: at tempo 0, k/0 is
+    /// 1 but FIND_BEAT times k/60 would be k/60.
+    fn match_chained_beat_unit(&self, base: &str, first: Option<&(String, String)>) -> Option<RefKind> {
+        let (op, operand) = first?;
+        if op != "div" {
+            return None;
+        }
+        let ref_kind = self.match_find_tempo(operand.trim())?;
+        let (num, den) = self.match_fraction_literal(base)?;
+        (Fraction::from_big_ints(num, den) == Fraction::new(60, 1)).then_some(ref_kind)
+    }
+
     fn parse_ref_arg(&self, s: &str) -> Option<RefKind> {
         let s = s.trim();
+        // A prefix/suffix match spanning two calls, as in
+        // module.findTempo(a).mul(module.findTempo(b)), is not one argument
+        if !is_balanced(s) {
+            return None;
+        }
         if s == "module.baseNote" {
             return Some(RefKind::Base);
         }
@@ -352,36 +426,49 @@ impl ExpressionCompiler {
 
     // === Bytecode emission ===
 
-    fn emit_constant(&mut self, num: i32, den: i32) {
-        // Normalize using simple GCD
-        let (n, d) = self.normalize_fraction(num, den);
-        self.bytecode.push(Op::LoadConst as u8);
-        write_i32(&mut self.bytecode, n);
-        write_i32(&mut self.bytecode, d);
+    /// Emit a reduced constant (see `write_const`); a zero denominator gives 0
+    fn emit_constant(&mut self, num: impl Into<BigInt>, den: impl Into<BigInt>) -> Result<(), String> {
+        write_const(&mut self.bytecode, &Fraction::from_big_ints(num.into(), den.into()))
+            .map_err(|e| format!("Constant too large: {}", e))
     }
 
-    fn emit_fraction_literal(&mut self, (num, den): &(i32, i32)) -> Result<(), String> {
-        self.emit_constant(*num, *den);
-        Ok(())
+    /// Emit the constant 0, the fallback for expressions that fail to compile
+    fn emit_zero(&mut self) {
+        self.emit_constant(0, 1).expect("zero is encodable");
+    }
+
+    fn emit_fraction_literal(&mut self, (num, den): &(BigInt, BigInt)) -> Result<(), String> {
+        self.emit_constant(num.clone(), den.clone())
+    }
+
+    /// Variable index of a `getVariable` name: a built-in variable, or
+    /// "custom:" followed by a registered name or a custom index
+    fn var_index(&self, var_name: &str) -> Result<u8, String> {
+        if let Some(var) = Var::from_name(var_name) {
+            return Ok(var as u8);
+        }
+        var_name
+            .strip_prefix(CUSTOM_VAR_PREFIX)
+            .and_then(|name| {
+                let index = name.parse().ok().filter(|&index| is_custom_var(index));
+                self.custom_vars.get(name).copied().or(index)
+            })
+            .ok_or_else(|| format!("Unknown variable: {}", var_name))
     }
 
     fn emit_base_ref(&mut self, var_name: &str) -> Result<(), String> {
-        let var_index = Var::from_name(var_name)
-            .ok_or_else(|| format!("Unknown variable: {}", var_name))?;
+        let var_index = self.var_index(var_name)?;
 
         self.bytecode.push(Op::LoadBase as u8);
-        self.bytecode.push(var_index as u8);
+        self.bytecode.push(var_index);
         self.references_base = true;
         Ok(())
     }
 
     fn emit_note_ref(&mut self, note_id: u32, var_name: &str) -> Result<(), String> {
-        let var_index = Var::from_name(var_name)
-            .ok_or_else(|| format!("Unknown variable: {}", var_name))?;
+        let var_index = self.var_index(var_name)?;
 
-        self.bytecode.push(Op::LoadRef as u8);
-        write_u16(&mut self.bytecode, note_id as u16);
-        self.bytecode.push(var_index as u8);
+        write_load_ref(&mut self.bytecode, note_id, var_index);
         self.dependencies.insert(note_id);
         Ok(())
     }
@@ -394,162 +481,109 @@ impl ExpressionCompiler {
                 self.references_base = true;
             }
             RefKind::Note(id) => {
-                self.bytecode.push(Op::LoadRef as u8);
-                write_u16(&mut self.bytecode, *id as u16);
-                self.bytecode.push(Var::Tempo as u8);
+                write_load_ref(&mut self.bytecode, *id, Var::Tempo as u8);
                 self.dependencies.insert(*id);
             }
         }
         Ok(())
     }
 
-    fn emit_find_measure(&mut self, ref_kind: &RefKind) -> Result<(), String> {
+    /// Emit the beat length of a note: the note id as a constant and FIND_BEAT
+    fn emit_beat_unit(&mut self, ref_kind: &RefKind) -> Result<(), String> {
         match ref_kind {
             RefKind::Base => {
-                self.bytecode.push(Op::LoadBase as u8);
-                self.bytecode.push(Var::MeasureLength as u8);
+                self.emit_constant(0, 1)?;
                 self.references_base = true;
             }
             RefKind::Note(id) => {
-                self.bytecode.push(Op::LoadRef as u8);
-                write_u16(&mut self.bytecode, *id as u16);
-                self.bytecode.push(Var::MeasureLength as u8);
+                self.emit_constant(*id, 1)?;
                 self.dependencies.insert(*id);
             }
         }
+        self.bytecode.push(Op::FindBeat as u8);
         Ok(())
     }
 
-    fn emit_sum(&mut self, terms: &[(i32, String)]) -> Result<(), String> {
-        if terms.is_empty() {
-            self.emit_constant(0, 1);
-            return Ok(());
-        }
-
-        // Emit first term
-        let (sign, ref expr) = terms[0];
-        self.parse_and_emit_product(expr)?;
-        if sign < 0 {
-            self.bytecode.push(Op::Neg as u8);
-        }
-
-        // Emit remaining terms
-        for (sign, ref expr) in &terms[1..] {
-            self.parse_and_emit_product(expr)?;
-            if *sign < 0 {
-                self.bytecode.push(Op::Sub as u8);
-            } else {
-                self.bytecode.push(Op::Add as u8);
+    fn emit_find_measure(&mut self, ref_kind: &RefKind) -> Result<(), String> {
+        match ref_kind {
+            RefKind::Base => {
+                self.bytecode.push(Op::LoadBase as u8);
+                self.bytecode.push(Var::MeasureLength as u8);
+                self.references_base = true;
+            }
+            RefKind::Note(id) => {
+                write_load_ref(&mut self.bytecode, *id, Var::MeasureLength as u8);
+                self.dependencies.insert(*id);
             }
         }
-
         Ok(())
     }
 
     // === Expression splitting ===
 
-    fn try_split_add_sub(&self, expr: &str) -> Option<Vec<(i32, String)>> {
-        let mut terms = Vec::new();
-        let mut depth = 0;
-        let mut i = 0;
-        let bytes = expr.as_bytes();
-        let mut last_split = 0;
-        let mut found_first = false;
-
-        while i < bytes.len() {
-            match bytes[i] {
-                b'(' => depth += 1,
-                b')' => depth -= 1,
-                _ if depth == 0 => {
-                    if expr[i..].starts_with(".add(") {
-                        if !found_first {
-                            terms.push((1, expr[last_split..i].trim().to_string()));
-                            found_first = true;
-                        }
-                        let (arg, next_idx) = self.read_call_argument(expr, i + 5);
-                        terms.push((1, arg));
-                        i = next_idx;
-                        last_split = i;
-                        continue;
-                    } else if expr[i..].starts_with(".sub(") {
-                        if !found_first {
-                            terms.push((1, expr[last_split..i].trim().to_string()));
-                            found_first = true;
-                        }
-                        let (arg, next_idx) = self.read_call_argument(expr, i + 5);
-                        terms.push((-1, arg));
-                        i = next_idx;
-                        last_split = i;
-                        continue;
-                    }
-                }
-                _ => {}
+    /// Emit `base` followed by each call of a method chain, in order
+    fn emit_chain(&mut self, base: &str, calls: Vec<MethodCall>) -> Result<(), String> {
+        let mut calls = calls.as_slice();
+        // 60/tempo is one beat
+        match self.match_chained_beat_unit(base, calls.first()) {
+            Some(ref_kind) => {
+                self.emit_beat_unit(&ref_kind)?;
+                calls = &calls[1..];
             }
-            i += 1;
+            None => self.parse_and_emit_atomic(base)?,
         }
-
-        if !found_first {
-            return None;
-        }
-
-        Some(terms)
-    }
-
-    fn try_split_mul_div(&self, expr: &str) -> Option<(String, Vec<(String, String)>)> {
-        let mut operations = Vec::new();
-        let mut depth = 0;
-        let mut i = 0;
-        let bytes = expr.as_bytes();
-        let mut first_op = None;
-
-        // Find first .mul or .div at depth 0
-        while i < bytes.len() {
-            match bytes[i] {
-                b'(' => depth += 1,
-                b')' => depth -= 1,
-                _ if depth == 0 => {
-                    if expr[i..].starts_with(".mul(") || expr[i..].starts_with(".div(") {
-                        first_op = Some(i);
-                        break;
-                    }
+        for (method, operand) in calls {
+            let opcode = chain_method(method).ok_or_else(|| format!("Unknown operation: {}", method))?;
+            // .sqrt(), .abs(), .floor() and the like apply to the value so far and take no argument
+            if takes_no_argument(opcode) {
+                if !operand.is_empty() {
+                    return Err(format!("{}() takes no argument, got: {}", method, operand));
                 }
-                _ => {}
+            } else {
+                self.parse_and_emit(operand)?;
             }
-            i += 1;
+            self.bytecode.push(opcode as u8);
         }
+        Ok(())
+    }
 
-        let first_op = first_op?;
-        let base = expr[..first_op].trim().to_string();
-        i = first_op;
-        depth = 0;
-
-        while i < bytes.len() {
-            match bytes[i] {
+    /// Split `expr` into the value a method chain starts from and its
+    /// calls as (method, argument), or None when there is no chain
+    ///
+    /// The chain starts at the first `.add(`, `.sub(` or product-level
+    /// method call outside parentheses; after that only further calls may
+    /// follow.
+    fn split_chain(&self, expr: &str) -> Result<Option<(String, Vec<MethodCall>)>, String> {
+        let bytes = expr.as_bytes();
+        let mut depth = 0;
+        let mut first_call = None;
+        for (i, &byte) in bytes.iter().enumerate() {
+            match byte {
                 b'(' => depth += 1,
                 b')' => depth -= 1,
-                _ if depth == 0 => {
-                    if expr[i..].starts_with(".mul(") {
-                        let (arg, next_idx) = self.read_call_argument(expr, i + 5);
-                        operations.push(("mul".to_string(), arg));
-                        i = next_idx;
-                        continue;
-                    } else if expr[i..].starts_with(".div(") {
-                        let (arg, next_idx) = self.read_call_argument(expr, i + 5);
-                        operations.push(("div".to_string(), arg));
-                        i = next_idx;
-                        continue;
-                    }
+                _ if depth == 0 && chain_method_at(expr, i).is_some() => {
+                    first_call = Some(i);
+                    break;
                 }
                 _ => {}
             }
-            i += 1;
         }
-
-        if operations.is_empty() {
-            return None;
+        let Some(mut i) = first_call else {
+            return Ok(None);
+        };
+        let base = expr[..i].trim().to_string();
+
+        let mut calls = Vec::new();
+        while i < expr.len() {
+            let Some(name) = chain_method_at(expr, i) else {
+                return Err(format!("Unexpected '{}' in method chain: {}", expr[i..].trim(), expr));
+            };
+            let (arg, next_idx) = self.read_call_argument(expr, i + name.len() + 2);
+            calls.push((name.to_string(), arg));
+            i = next_idx;
+            i += expr[i..].len() - expr[i..].trim_start().len();
         }
-
-        Some((base, operations))
+        Ok(Some((base, calls)))
     }
 
     fn read_call_argument(&self, expr: &str, start_index: usize) -> (String, usize) {
@@ -604,92 +638,66 @@ impl ExpressionCompiler {
     // === Utility functions ===
 
     fn decimal_to_fraction(&self, value: f64) -> (i32, i32) {
-        if !value.is_finite() {
-            return (0, 1);
-        }
-
-        let sign = if value < 0.0 { -1 } else { 1 };
-        let abs_value = value.abs();
-
-        if abs_value == 0.0 {
-            return (0, 1);
-        }
-
-        // Handle integers
-        if (abs_value - abs_value.round()).abs() < 1e-10 {
-            return (sign * abs_value.round() as i32, 1);
-        }
-
-        // Check common simple fractions
-        let simple_tests: [(f64, i32, i32); 20] = [
-            (0.25, 1, 4),
-            (0.5, 1, 2),
-            (0.75, 3, 4),
-            (0.125, 1, 8),
-            (0.375, 3, 8),
-            (0.625, 5, 8),
-            (0.875, 7, 8),
-            (0.2, 1, 5),
-            (0.4, 2, 5),
-            (0.6, 3, 5),
-            (0.8, 4, 5),
-            (0.333333, 1, 3),
-            (0.666666, 2, 3),
-            (0.1666666, 1, 6),
-            (0.8333333, 5, 6),
-            (1.25, 5, 4),
-            (1.5, 3, 2),
-            (1.75, 7, 4),
-            (2.5, 5, 2),
-            (0.1, 1, 10),
-        ];
-
-        for (dec, n, d) in simple_tests {
-            if (abs_value - dec).abs() < 1e-6 {
-                return (sign * n, d);
-            }
+        // Simplest fraction that round-trips, limited to what LoadConst can hold
+        let fraction = Fraction::from_f64_with(value, 0.0, i32::MAX as u64);
+        let big = fraction.to_big_rational();
+        match (big.numer().to_i32(), big.denom().to_i32()) {
+            (Some(num), Some(den)) => (num, den),
+            _ => (if value < 0.0 { i32::MIN } else { i32::MAX }, 1),
         }
+    }
+}
 
-        // Continued fraction approximation
-        let mut best_num = abs_value.round() as i32;
-        let mut best_den = 1;
-        let mut best_err = (abs_value - best_num as f64).abs();
+/// Is `s` a plain variable name, rather than the middle of two calls?
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
 
-        for d in 1..=10000 {
-            let n = (abs_value * d as f64).round() as i32;
-            let err = (abs_value - n as f64 / d as f64).abs();
-            if err < best_err {
-                best_num = n;
-                best_den = d;
-                best_err = err;
-                if err < 1e-10 {
-                    break;
-                }
-            }
-        }
+/// Is `s` a variable name, optionally prefixed with "custom:"?
+fn is_var_name(s: &str) -> bool {
+    is_identifier(s.strip_prefix(CUSTOM_VAR_PREFIX).unwrap_or(s))
+}
 
-        (sign * best_num, best_den)
+/// Does every parenthesis in `s` close, in order?
+fn is_balanced(s: &str) -> bool {
+    let mut depth = 0usize;
+    for c in s.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return false,
+            },
+            _ => {}
+        }
     }
+    depth == 0
+}
 
-    fn normalize_fraction(&self, num: i32, den: i32) -> (i32, i32) {
-        if den == 0 {
-            return (0, 1);
-        }
+/// Product-level methods that act on the value so far and take no argument
+fn takes_no_argument(opcode: Op) -> bool {
+    matches!(opcode, Op::Sqrt | Op::Abs | Op::Sign | Op::Floor | Op::Ceil | Op::Round | Op::Neg)
+}
 
-        let gcd = self.gcd(num.abs(), den.abs());
-        let sign = if (num < 0) != (den < 0) { -1 } else { 1 };
+/// One call of a method chain: the method name and its argument text
+type MethodCall = (String, String);
 
-        (sign * (num.abs() / gcd), den.abs() / gcd)
+/// Opcode of a chain method: .add and .sub, or a product-level method
+fn chain_method(name: &str) -> Option<Op> {
+    match name {
+        "add" => Some(Op::Add),
+        "sub" => Some(Op::Sub),
+        _ => PRODUCT_METHODS.iter().find(|(method, _)| *method == name).map(|&(_, opcode)| opcode),
     }
+}
 
-    fn gcd(&self, mut a: i32, mut b: i32) -> i32 {
-        while b != 0 {
-            let t = b;
-            b = a % b;
-            a = t;
-        }
-        a
-    }
+/// The chain method whose `.name(` call starts at byte `i`, if any
+fn chain_method_at(expr: &str, i: usize) -> Option<&'static str> {
+    let rest = expr[i..].strip_prefix('.')?;
+    ["add", "sub"]
+        .into_iter()
+        .chain(PRODUCT_METHODS.iter().map(|&(name, _)| name))
+        .find(|name| rest.strip_prefix(name).is_some_and(|r| r.starts_with('(')))
 }
 
 /// Reference kind for module lookups
@@ -698,6 +706,39 @@ enum RefKind {
     Note(u32),
 }
 
+/// An expression `compile_expressions_par` could not compile
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompileError {
+    /// The expression text
+    pub text: String,
+    /// Why it failed, as `ExpressionCompiler::try_compile` reports it
+    pub message: String,
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// Compile many expressions in parallel (native builds only)
+///
+/// Each rayon worker uses its own compiler, so no shared compiler is
+/// needed. Results are in the same order as `texts`.
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+pub fn compile_expressions_par(texts: &[&str]) -> Vec<Result<CompiledExpression, CompileError>> {
+    use rayon::prelude::*;
+
+    texts
+        .par_iter()
+        .map_init(ExpressionCompiler::new, |compiler, text| {
+            compiler.try_compile(text).map_err(|message| CompileError { text: text.to_string(), message })
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -712,6 +753,38 @@ mod tests {
         assert!(!result.references_base);
     }
 
+    #[test]
+    fn test_compile_big_fraction_literal() {
+        let evaluate = |text: &str| {
+            let result = ExpressionCompiler::new().try_compile(text).unwrap();
+            assert_eq!(result.bytecode[0], Op::LoadConstBig as u8, "{}", text);
+            crate::evaluator::Evaluator::new()
+                .evaluate(&result.bytecode, result.bytecode.len(), &std::collections::HashMap::new())
+                .unwrap()
+                .to_fraction()
+        };
+
+        // A 60-bit accumulated time, as the JS side produces
+        let big = (1u64 << 60) - 93;
+        assert_eq!(evaluate(&format!("new Fraction({}, 1)", big)), Fraction::from_string(&big.to_string()).unwrap());
+        assert_eq!(evaluate("new Fraction(3936588805702081)"), Fraction::from_string("3936588805702081").unwrap());
+        assert_eq!(
+            evaluate("new Fraction(-3936588805702081, 4398046511104)"),
+            Fraction::from_string("-3936588805702081/4398046511104").unwrap()
+        );
+        assert_eq!(evaluate("3936588805702081"), Fraction::from_string("3936588805702081").unwrap());
+
+        // Reduced constants that fit stay as LOAD_CONST
+        let result = ExpressionCompiler::new().try_compile("new Fraction(4294967296, 8589934592)").unwrap();
+        assert_eq!(result.bytecode[0], Op::LoadConst as u8);
+        assert_eq!(result.bytecode.len(), 9);
+
+        // 10^160000 needs more magnitude bytes than LOAD_CONST_BIG can hold
+        let huge = format!("1{}", "0".repeat(160_000));
+        let error = ExpressionCompiler::new().try_compile(&huge).err().unwrap_or_default();
+        assert!(error.starts_with("Constant too large"), "{}", error);
+    }
+
     #[test]
     fn test_compile_base_ref() {
         let mut compiler = ExpressionCompiler::new();
@@ -730,6 +803,146 @@ mod tests {
         assert!(result.dependencies.contains(&42));
     }
 
+    #[test]
+    fn test_compile_custom_ref() {
+        let mut compiler = ExpressionCompiler::new();
+        compiler.register_custom_variable("velocity", 40).unwrap();
+        let result = compiler.try_compile("module.getNoteById(3).getVariable('custom:velocity')").unwrap();
+        assert_eq!(result.bytecode, vec![Op::LoadRef as u8, 0, 3, 40]);
+        assert!(result.dependencies.contains(&3));
+
+        // Indices work without a registered name, and round-trip through decompile
+        let text = "module.baseNote.getVariable('custom:33')";
+        let result = compiler.try_compile(text).unwrap();
+        assert_eq!(result.bytecode, vec![Op::LoadBase as u8, 33]);
+        assert_eq!(crate::bytecode::decompile(&result.bytecode, result.bytecode.len()).unwrap(), text);
+
+        for bad in ["custom:pan", "custom:64", "custom:5", "pan"] {
+            let text = format!("module.baseNote.getVariable('{}')", bad);
+            assert_eq!(compiler.try_compile(&text).err(), Some(format!("Unknown variable: {}", bad)));
+        }
+        assert!(compiler.register_custom_variable("pan", 6).is_err());
+        assert!(compiler.register_custom_variable("a b", 41).is_err());
+    }
+
+    #[test]
+    fn test_compile_wide_note_ref() {
+        use crate::evaluator::{EvaluatedNote, Evaluator, FractionData, PersistentEvaluator};
+
+        let text = "module.getNoteById(100000).getVariable('startTime')\
+            .add(new Fraction(60).div(module.findTempo(module.getNoteById(100000))))";
+        let result = ExpressionCompiler::new().try_compile(text).unwrap();
+        assert_eq!(result.bytecode[0], Op::LoadRefWide as u8);
+        assert!(result.dependencies.contains(&100_000));
+        assert_eq!(crate::bytecode::validate(&result.bytecode, result.bytecode.len()).unwrap().note_ids, vec![100_000]);
+
+        let note = EvaluatedNote {
+            start_time: Some(FractionData::from_fraction(&Fraction::new(5, 2))),
+            tempo: Some(FractionData::from_fraction(&Fraction::new(90, 1))),
+            ..Default::default()
+        };
+        let cache = std::collections::HashMap::from([(100_000, note)]);
+        let value = Evaluator::new().evaluate(&result.bytecode, result.bytecode.len(), &cache).unwrap();
+        assert_eq!(value.to_fraction(), Fraction::new(19, 6));
+
+        let mut evaluator = PersistentEvaluator::new();
+        for (id, var, text) in [
+            (100_000, Var::StartTime, "new Fraction(5, 2)"),
+            (100_000, Var::Tempo, "new Fraction(90)"),
+            (100_001, Var::StartTime, text),
+        ] {
+            let compiled = ExpressionCompiler::new().try_compile(text).unwrap();
+            evaluator.register_expression(id, var as u8, &compiled.bytecode, compiled.bytecode.len());
+        }
+        evaluator.evaluate_dirty(&[100_000, 100_001]);
+        let start = evaluator.cached_note(100_001).unwrap().start_time.as_ref().unwrap().to_fraction();
+        assert_eq!(start, Fraction::new(19, 6));
+    }
+
+    #[test]
+    fn test_compile_beat_unit() {
+        use crate::bytecode::{remap_note_ids, validate, write_const};
+        use crate::evaluator::{EvaluatedNote, Evaluator, FractionData, PersistentEvaluator};
+        use std::collections::HashMap;
+
+        // The expansion emitted before FIND_BEAT: k, the tempo, DIV
+        let expansion = |k: Fraction, tempo: Vec<u8>| {
+            let mut bc = Vec::new();
+            write_const(&mut bc, &k).unwrap();
+            bc.extend(tempo);
+            bc.push(Op::Div as u8);
+            bc
+        };
+        let note_tempo = vec![Op::LoadRef as u8, 0, 5, Var::Tempo as u8];
+        let base_tempo = vec![Op::LoadBase as u8, Var::Tempo as u8];
+
+        let with_tempo = |tempo: Option<(i32, i32)>| EvaluatedNote {
+            tempo: tempo.map(|(n, d)| FractionData::from_fraction(&Fraction::new(n, d))),
+            ..Default::default()
+        };
+        // Tempo 0 divides by zero, which gives 1 either way
+        let caches = [
+            HashMap::from([(0, with_tempo(Some((120, 1)))), (5, with_tempo(Some((90, 1))))]),
+            HashMap::from([(0, with_tempo(Some((135, 2)))), (5, with_tempo(None))]),
+            HashMap::from([(0, with_tempo(Some((0, 1)))), (5, with_tempo(Some((0, 1))))]),
+            HashMap::from([(0, with_tempo(Some((0, 1)))), (5, with_tempo(None))]),
+            HashMap::new(),
+        ];
+
+        // Only 60/tempo becomes FIND_BEAT; k/tempo stays k, the tempo, DIV
+        let cases = [
+            ("new Fraction(60).div(module.findTempo(module.getNoteById(5)))", (60, 1), &note_tempo, (14, 10)),
+            ("new Fraction(60).div(module.findTempo(module.baseNote))", (60, 1), &base_tempo, (12, 10)),
+            ("new Fraction(30).div(module.findTempo(module.getNoteById(5)))", (30, 1), &note_tempo, (14, 14)),
+            ("new Fraction(45, 2).div(module.findTempo(module.baseNote))", (45, 2), &base_tempo, (12, 12)),
+        ];
+        for (text, (num, den), tempo, sizes) in cases {
+            let before = expansion(Fraction::new(num, den), tempo.clone());
+            let result = ExpressionCompiler::new().try_compile(text).unwrap();
+            assert_eq!(result.bytecode.contains(&(Op::FindBeat as u8)), num == 60, "{}", text);
+            assert_eq!((before.len(), result.bytecode.len()), sizes, "{}", text);
+            for cache in &caches {
+                let expected = Evaluator::new().evaluate(&before, before.len(), cache).unwrap();
+                let actual = Evaluator::new().evaluate(&result.bytecode, result.bytecode.len(), cache).unwrap();
+                assert!(actual == expected, "{}: {:?} != {:?}", text, actual, expected);
+            }
+        }
+
+        // The reference is still a dependency, and follows a remap
+        let result = ExpressionCompiler::new().try_compile(cases[0].0).unwrap();
+        assert!(result.dependencies.contains(&5));
+        assert_eq!(validate(&result.bytecode, result.bytecode.len()).unwrap().note_ids, vec![5]);
+        let remapped = remap_note_ids(&result.bytecode, result.bytecode.len(), &HashMap::from([(5, 8)])).unwrap();
+        assert_eq!(validate(&remapped, remapped.len()).unwrap().note_ids, vec![8]);
+        assert!(validate(&ExpressionCompiler::new().try_compile(cases[1].0).unwrap().bytecode, 10).unwrap().references_base);
+
+        // Duration of note 6 is two beats of note 5, which inherits the base tempo
+        let mut evaluator = PersistentEvaluator::new();
+        for (id, var, text) in [
+            (0, Var::Tempo, "new Fraction(100)"),
+            (6, Var::Duration, "new Fraction(60).div(module.findTempo(module.getNoteById(5))).mul(new Fraction(2))"),
+        ] {
+            let compiled = ExpressionCompiler::new().try_compile(text).unwrap();
+            evaluator.register_expression(id, var as u8, &compiled.bytecode, compiled.bytecode.len());
+        }
+        evaluator.evaluate_dirty(&[0, 6]);
+        let duration = evaluator.cached_note(6).unwrap().duration.as_ref().unwrap().to_fraction();
+        assert_eq!(duration, Fraction::new(6, 5));
+
+        // At tempo 0 a chained beat unit matches the expansion too
+        let chained = ExpressionCompiler::new()
+            .try_compile("new Fraction(60).div(module.findTempo(module.baseNote)).mul(new Fraction(2))")
+            .unwrap();
+        assert!(chained.bytecode.contains(&(Op::FindBeat as u8)));
+        let mut before = expansion(Fraction::new(60, 1), base_tempo.clone());
+        write_const(&mut before, &Fraction::new(2, 1)).unwrap();
+        before.push(Op::Mul as u8);
+        let stopped = HashMap::from([(0, with_tempo(Some((0, 1))))]);
+        let expected = Evaluator::new().evaluate(&before, before.len(), &stopped).unwrap();
+        let actual = Evaluator::new().evaluate(&chained.bytecode, chained.bytecode.len(), &stopped).unwrap();
+        assert!(actual == expected && actual == crate::value::Value::rational(2, 1));
+    }
+
     #[test]
     fn test_compile_addition() {
         let mut compiler = ExpressionCompiler::new();
@@ -742,6 +955,203 @@ mod tests {
         assert_eq!(result.bytecode.last(), Some(&(Op::Add as u8)));
     }
 
+    #[test]
+    fn test_compile_mod_chain() {
+        let mut compiler = ExpressionCompiler::new();
+        let result = compiler
+            .try_compile("new Fraction(-7, 6).mod(new Fraction(1, 3)).mul(new Fraction(2))")
+            .unwrap();
+        assert_eq!(result.bytecode.iter().filter(|&&b| b == Op::Mod as u8).count(), 1);
+        assert_eq!(result.bytecode.last(), Some(&(Op::Mul as u8)));
+
+        let value = crate::evaluator::Evaluator::new()
+            .evaluate(&result.bytecode, result.bytecode.len(), &std::collections::HashMap::new())
+            .unwrap();
+        assert_eq!(value.to_fraction(), Fraction::new(-1, 3));
+    }
+
+    #[test]
+    fn test_compile_min_max_chain() {
+        let mut compiler = ExpressionCompiler::new();
+        let result = compiler
+            .try_compile("new Fraction(3, 4).min(new Fraction(1, 2)).max(new Fraction(5, 8))")
+            .unwrap();
+        assert!(result.bytecode.contains(&(Op::Min as u8)));
+        assert_eq!(result.bytecode.last(), Some(&(Op::Max as u8)));
+
+        let value = crate::evaluator::Evaluator::new()
+            .evaluate(&result.bytecode, result.bytecode.len(), &std::collections::HashMap::new())
+            .unwrap();
+        assert_eq!(value.to_fraction(), Fraction::new(5, 8));
+    }
+
+    #[test]
+    fn test_compile_sqrt_and_root_chain() {
+        let evaluate = |text: &str| {
+            let result = ExpressionCompiler::new().try_compile(text).unwrap();
+            crate::evaluator::Evaluator::new()
+                .evaluate(&result.bytecode, result.bytecode.len(), &std::collections::HashMap::new())
+                .unwrap()
+        };
+
+        let value = evaluate("new Fraction(9, 4).sqrt()");
+        assert!(value.is_rational());
+        assert_eq!(value.to_fraction(), Fraction::new(3, 2));
+        assert_eq!(evaluate("new Fraction(8).root(3)").to_fraction(), Fraction::new(2, 1));
+        let chained = evaluate("new Fraction(81).sqrt().root(new Fraction(2)).mul(new Fraction(1, 3))");
+        assert_eq!(chained.to_fraction(), Fraction::one());
+        assert!(evaluate("new Fraction(2).sqrt()").is_symbolic());
+
+        let mut compiler = ExpressionCompiler::new();
+        assert!(compiler.try_compile("new Fraction(2).sqrt(new Fraction(3))").is_err());
+    }
+
+    #[test]
+    fn test_compile_abs_and_sign() {
+        use crate::value::Value;
+
+        let evaluate = |text: &str| {
+            let result = ExpressionCompiler::new().try_compile(text).unwrap();
+            crate::evaluator::Evaluator::new()
+                .evaluate(&result.bytecode, result.bytecode.len(), &std::collections::HashMap::new())
+                .unwrap()
+        };
+
+        // |a - b| in both orders
+        let a = "new Fraction(3, 4)";
+        let b = "new Fraction(5, 4)";
+        assert!(evaluate(&format!("{}.sub({}).abs()", a, b)) == Value::rational(1, 2));
+        assert!(evaluate(&format!("{}.sub({}).abs()", b, a)) == Value::rational(1, 2));
+        assert!(evaluate(&format!("{}.sub({}).sign()", a, b)) == Value::rational(-1, 1));
+        assert!(evaluate(&format!("{}.sub({}).sign()", a, a)) == Value::zero());
+        let result = ExpressionCompiler::new().try_compile(&format!("{}.sub({}).abs()", a, b)).unwrap();
+        assert_eq!(result.bytecode.last(), Some(&(Op::Abs as u8)));
+
+        // Within product chains, and symbolic values keep their form
+        assert!(evaluate("new Fraction(-3).abs().mul(new Fraction(2))") == Value::rational(6, 1));
+        let tritone = evaluate("new Fraction(2).sqrt().mul(new Fraction(-1)).abs()");
+        assert!(tritone.is_symbolic());
+        assert!(tritone == Value::rational(2, 1).sqrt());
+        assert!(evaluate("new Fraction(2).sqrt().mul(new Fraction(-1)).sign()") == Value::rational(-1, 1));
+
+        // Mid-chain calls apply to everything before them, left to right
+        assert!(evaluate(&format!("{}.sub({}).abs().mul(new Fraction(4))", a, b)) == Value::rational(2, 1));
+        assert!(evaluate(&format!("{}.sub({}).sign().add(new Fraction(3))", a, b)) == Value::rational(2, 1));
+        assert!(evaluate("new Fraction(1).add(new Fraction(2)).mul(new Fraction(5)).sub(new Fraction(1))") == Value::rational(14, 1));
+        assert!(evaluate("new Fraction(1).sub(new Fraction(3)).abs().add(new Fraction(1)).neg()") == Value::rational(-3, 1));
+
+        let mut compiler = ExpressionCompiler::new();
+        assert!(compiler.try_compile("new Fraction(2).abs(new Fraction(3))").is_err());
+        assert!(compiler.try_compile("new Fraction(2).add(new Fraction(3)).toFraction()").is_err());
+    }
+
+    #[test]
+    fn test_compile_measure_snapping() {
+        use crate::evaluator::{EvaluatedNote, Evaluator, FractionData};
+        use crate::value::Value;
+
+        // Note 1 sits in 25/6-second measures (5 beats at 72 BPM)
+        let measure_length = FractionData::from_fraction(&Fraction::new(25, 6));
+        let measure = "module.findMeasureLength(module.getNoteById(1))";
+        let text = format!("module.getNoteById(1).getVariable('startTime').div({m}).floor().mul({m})", m = measure);
+        let result = ExpressionCompiler::new().try_compile(&text).unwrap();
+        assert!(result.bytecode.contains(&(Op::Floor as u8)));
+
+        // Mid-measure, exactly on a boundary, and before zero
+        for (start, snapped) in [((37, 3), (25, 3)), ((25, 3), (25, 3)), ((-1, 3), (-25, 6)), ((1, 7), (0, 1))] {
+            let note = EvaluatedNote {
+                start_time: Some(FractionData::from_fraction(&Fraction::new(start.0, start.1))),
+                measure_length: Some(measure_length.clone()),
+                ..Default::default()
+            };
+            let cache = std::collections::HashMap::from([(1, note)]);
+            let value = Evaluator::new().evaluate(&result.bytecode, result.bytecode.len(), &cache).unwrap();
+            assert!(value == Value::rational(snapped.0, snapped.1), "{:?} snapped to {:?}", start, value);
+        }
+
+        // ceil and round chain the same way
+        let evaluate = |text: &str| {
+            let result = ExpressionCompiler::new().try_compile(text).unwrap();
+            Evaluator::new().evaluate(&result.bytecode, result.bytecode.len(), &std::collections::HashMap::new()).unwrap()
+        };
+        assert!(evaluate("new Fraction(-7, 2).ceil()") == Value::rational(-3, 1));
+        assert!(evaluate("new Fraction(2).sqrt().mul(new Fraction(10)).round()") == Value::rational(14, 1));
+        assert!(evaluate("new Fraction(1, 3).sub(new Fraction(2)).floor()") == Value::rational(-2, 1));
+        assert!(evaluate("new Fraction(1, 3).add(new Fraction(1, 2)).ceil().mul(new Fraction(3))") == Value::rational(3, 1));
+        assert!(evaluate("new Fraction(7, 4).sub(new Fraction(1, 2)).round().add(new Fraction(1, 2))") == Value::rational(3, 2));
+        assert!(evaluate("new Fraction(5, 2).add(new Fraction(1, 3)).floor().div(new Fraction(4))") == Value::rational(1, 2));
+    }
+
+    #[test]
+    fn test_compile_log_chain() {
+        let mut compiler = ExpressionCompiler::new();
+        let result = compiler.try_compile("new Fraction(27, 8).log(new Fraction(3, 2))").unwrap();
+        assert_eq!(result.bytecode.last(), Some(&(Op::Log as u8)));
+        let value = crate::evaluator::Evaluator::new()
+            .evaluate(&result.bytecode, result.bytecode.len(), &std::collections::HashMap::new())
+            .unwrap();
+        assert!(value.is_rational());
+        assert_eq!(value.to_fraction(), Fraction::new(3, 1));
+
+        // Semitones in a tritone: 12·log2(√2)
+        let result = compiler
+            .try_compile("new Fraction(2).sqrt().log(new Fraction(2)).mul(new Fraction(12))")
+            .unwrap();
+        let value = crate::evaluator::Evaluator::new()
+            .evaluate(&result.bytecode, result.bytecode.len(), &std::collections::HashMap::new())
+            .unwrap();
+        assert_eq!(value.to_fraction(), Fraction::new(6, 1));
+    }
+
+    #[test]
+    fn test_compile_pow_and_neg_chain() {
+        use crate::evaluator::{EvaluatedNote, FractionData};
+
+        let data = |n: i32, d: i32| Some(FractionData::from_fraction(&Fraction::new(n, d)));
+        let cache = std::collections::HashMap::from([
+            (0, EvaluatedNote { tempo: data(120, 1), duration: data(1, 2), ..Default::default() }),
+            (1, EvaluatedNote { tempo: data(60, 1), ..Default::default() }),
+            (2, EvaluatedNote { tempo: data(90, 1), ..Default::default() }),
+        ]);
+        let evaluate = |text: &str| {
+            let result = ExpressionCompiler::new().try_compile(text).unwrap();
+            crate::evaluator::Evaluator::new().evaluate(&result.bytecode, result.bytecode.len(), &cache).unwrap()
+        };
+
+        // .pow and .neg bind like .mul, applying to everything before them
+        let result = ExpressionCompiler::new().try_compile("new Fraction(3, 2).pow(new Fraction(2)).neg()").unwrap();
+        assert_eq!(result.bytecode.last(), Some(&(Op::Neg as u8)));
+        assert!(result.bytecode.contains(&(Op::Pow as u8)));
+        assert_eq!(evaluate("new Fraction(3, 2).pow(new Fraction(2)).neg()").to_fraction(), Fraction::new(-9, 4));
+        assert_eq!(evaluate("new Fraction(1).add(new Fraction(1)).pow(new Fraction(3))").to_fraction(), Fraction::new(8, 1));
+        assert!(ExpressionCompiler::new().try_compile("new Fraction(2).neg(new Fraction(3))").is_err());
+
+        // Two calls in parentheses are not one reference with a long
+        // variable name or argument
+        let sum = evaluate("(module.baseNote.getVariable('tempo').add(module.baseNote.getVariable('duration')))");
+        assert_eq!(sum.to_fraction(), Fraction::new(241, 2));
+        let product = evaluate("(module.findTempo(module.getNoteById(1)).mul(module.findTempo(module.getNoteById(2))))");
+        assert_eq!(product.to_fraction(), Fraction::new(5400, 1));
+    }
+
+    #[test]
+    fn test_unknown_pattern_logs_one_warning() {
+        let records = crate::log::test_support::capture();
+        crate::log::set_max_level(Some(crate::log::LogLevel::Warn));
+
+        let mut compiler = ExpressionCompiler::new();
+        let result = compiler.compile("someUnknownThing(1, 2)");
+        crate::log::clear_sink();
+
+        // Falls back to a zero constant
+        assert_eq!(result.bytecode[0], Op::LoadConst as u8);
+
+        let records = records.borrow();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].0, crate::log::LogLevel::Warn);
+        assert!(records[0].1.contains("someUnknownThing(1, 2)"));
+    }
+
     #[test]
     fn test_decimal_to_fraction() {
         let compiler = ExpressionCompiler::new();
@@ -750,5 +1160,212 @@ mod tests {
         assert_eq!(compiler.decimal_to_fraction(0.25), (1, 4));
         assert_eq!(compiler.decimal_to_fraction(-1.5), (-3, 2));
         assert_eq!(compiler.decimal_to_fraction(5.0), (5, 1));
+        assert_eq!(compiler.decimal_to_fraction(1.0 / 7.0), (1, 7));
+        // Decimals are taken literally, as fraction.js does
+        assert_eq!(compiler.decimal_to_fraction(0.333333), (333_333, 1_000_000));
+    }
+
+    #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+    #[test]
+    fn test_compile_expressions_par_matches_serial() {
+        let texts: Vec<String> = (1..200)
+            .map(|i| format!("module.getNoteById({}).getVariable('startTime').add(new Fraction({}, 7))", i, i))
+            .chain(std::iter::once("not an expression".to_string()))
+            .collect();
+        let refs: Vec<&str> = texts.iter().map(String::as_str).collect();
+
+        let parallel = compile_expressions_par(&refs);
+        let mut compiler = ExpressionCompiler::new();
+        for (text, result) in refs.iter().zip(&parallel) {
+            match (compiler.try_compile(text), result) {
+                (Ok(a), Ok(b)) => assert_eq!(a.bytecode, b.bytecode),
+                (Err(a), Err(b)) => assert_eq!((a, *text), (b.message.clone(), b.text.as_str())),
+                _ => panic!("serial and parallel results differ for {}", text),
+            }
+        }
+        assert!(parallel.last().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_optimize_preserves_results() {
+        use crate::evaluator::{EvaluatedNote, Evaluator, FractionData};
+
+        let note = |start: (i32, i32), duration: (i32, i32)| EvaluatedNote {
+            start_time: Some(FractionData::from_fraction(&Fraction::new(start.0, start.1))),
+            duration: Some(FractionData::from_fraction(&Fraction::new(duration.0, duration.1))),
+            tempo: Some(FractionData::from_fraction(&Fraction::new(90, 1))),
+            ..Default::default()
+        };
+        let cache = std::collections::HashMap::from([(0, note((0, 1), (1, 1))), (1, note((3, 2), (2, 3)))]);
+
+        let corpus = [
+            "new Fraction(1, 2).mul(new Fraction(2, 3))",
+            "new Fraction(60).div(module.findTempo(module.baseNote)).mul(new Fraction(1, 4))",
+            "module.getNoteById(1).getVariable('startTime').add(new Fraction(0))",
+            "module.getNoteById(1).getVariable('duration').mul(new Fraction(1)).mul(new Fraction(3, 2).add(new Fraction(1, 2)))",
+            "module.baseNote.getVariable('frequency').mul(new Fraction(2).root(new Fraction(12)))",
+            "module.baseNote.getVariable('frequency').mul(new Fraction(9, 4).sqrt())",
+            "new Fraction(7, 2).floor().sub(new Fraction(5, 3).ceil()).abs()",
+            "new Fraction(8).root(new Fraction(3)).add(module.getNoteById(1).getVariable('startTime'))",
+            "new Fraction(2).sqrt().mul(new Fraction(2).sqrt())",
+        ];
+        let mut optimizer = ExpressionCompiler::new();
+        optimizer.set_optimize(true);
+        let mut shrunk = 0;
+        for text in corpus {
+            let plain = ExpressionCompiler::new().try_compile(text).unwrap();
+            let optimized = optimizer.try_compile(text).unwrap();
+            assert!(optimized.bytecode.len() <= plain.bytecode.len(), "{} grew", text);
+            if optimized.bytecode.len() < plain.bytecode.len() {
+                shrunk += 1;
+            }
+
+            let expected = Evaluator::new().evaluate(&plain.bytecode, plain.bytecode.len(), &cache).unwrap();
+            let actual = Evaluator::new().evaluate(&optimized.bytecode, optimized.bytecode.len(), &cache).unwrap();
+            assert!(actual == expected, "{}: {:?} != {:?}", text, actual, expected);
+        }
+        assert!(shrunk >= 6, "only {} expressions shrank", shrunk);
+
+        // Fully constant expressions become a single load
+        let folded = optimizer.try_compile(corpus[0]).unwrap();
+        assert_eq!(folded.bytecode.len(), 9);
+    }
+
+    #[test]
+    fn test_share_subexpressions_preserves_results() {
+        use crate::bytecode::{instruction_len, Op};
+        use crate::evaluator::{EvaluatedNote, Evaluator, FractionData};
+        use crate::value::Value;
+
+        // Note 2's frequency is irrational, so reusing it must keep the corruption
+        let fifth = Value::rational(2, 1).pow(&Value::rational(7, 12));
+        let note = |start: (i32, i32), duration: (i32, i32)| EvaluatedNote {
+            start_time: Some(FractionData::from_fraction(&Fraction::new(start.0, start.1))),
+            duration: Some(FractionData::from_fraction(&Fraction::new(duration.0, duration.1))),
+            tempo: Some(FractionData::from_fraction(&Fraction::new(90, 1))),
+            frequency: Some(FractionData::from_value(&fifth)),
+            ..Default::default()
+        };
+        let cache = std::collections::HashMap::from([(0, note((0, 1), (1, 1))), (7, note((1, 2), (3, 4))), (2, note((1, 1), (1, 3)))]);
+        let counts = |bytecode: &[u8]| {
+            let (mut instructions, mut lookups, mut pc) = (0, 0, 0);
+            while pc < bytecode.len() {
+                let op = Op::from_byte(bytecode[pc]).unwrap();
+                instructions += 1;
+                if matches!(op, Op::LoadRef | Op::LoadRefWide | Op::LoadBase) || op.pops_note_ref() {
+                    lookups += 1;
+                }
+                pc += instruction_len(bytecode, pc).unwrap();
+            }
+            (instructions, lookups)
+        };
+
+        let duration = "module.getNoteById(7).getVariable('duration')";
+        let frequency = "module.getNoteById(2).getVariable('frequency')";
+        let tempo = "module.findTempo(module.getNoteById(7))";
+        let corpus = [
+            format!("{d}.mul(new Fraction(1, 3)).add({d}.mul(new Fraction(2, 5)))", d = duration),
+            format!("{d}.add({d})", d = duration),
+            format!("{d}.mul(new Fraction(3).add({d}))", d = duration),
+            format!("{f}.mul({f}).add({f})", f = frequency),
+            format!("{f}.sub(new Fraction(1)).div({f}.add(new Fraction(1)))", f = frequency),
+            format!("{t}.mul({d}).add({t}.mul({t}))", t = tempo, d = duration),
+            format!("new Fraction(60).div({t}).mul({t}).add({d}.mul({t}))", t = tempo, d = duration),
+            format!("new Fraction(7).add({})", tempo),
+            "module.baseNote.getVariable('startTime').add(module.baseNote.getVariable('startTime'))".to_string(),
+            "new Fraction(1, 2).add(new Fraction(3))".to_string(),
+        ];
+        let mut sharing = ExpressionCompiler::new();
+        sharing.set_share_subexpressions(true);
+        for text in &corpus {
+            let plain = ExpressionCompiler::new().try_compile(text).unwrap();
+            let shared = sharing.try_compile(text).unwrap();
+            assert!(shared.bytecode.len() <= plain.bytecode.len(), "{} grew", text);
+            assert_eq!(crate::bytecode::validate(&shared.bytecode, shared.bytecode.len()).unwrap().note_ids.len(), plain.dependencies.len(), "{}", text);
+
+            let expected = Evaluator::new().evaluate(&plain.bytecode, plain.bytecode.len(), &cache).unwrap();
+            let actual = Evaluator::new().evaluate(&shared.bytecode, shared.bytecode.len(), &cache).unwrap();
+            assert!(actual == expected, "{}: {:?} != {:?}", text, actual, expected);
+            assert_eq!(actual.is_corrupted(), expected.is_corrupted(), "{}", text);
+        }
+
+        // d*x + d*y keeps a copy of d under the first product
+        let example = |compiler: &mut ExpressionCompiler| counts(&compiler.try_compile(&corpus[0]).unwrap().bytecode);
+        assert_eq!(example(&mut ExpressionCompiler::new()), (7, 2));
+        assert_eq!(example(&mut sharing), (8, 1));
+        // d + d and d * (3 + d) reuse the live value with DUP and OVER
+        let shared = sharing.try_compile(&corpus[1]).unwrap().bytecode;
+        assert_eq!(shared[4..], [Op::Dup as u8, Op::Add as u8]);
+        let shared = sharing.try_compile(&corpus[2]).unwrap().bytecode;
+        assert_eq!(shared[13..], [Op::Over as u8, Op::Add as u8, Op::Mul as u8]);
+        // f*f + f loads the frequency once
+        assert_eq!(counts(&sharing.try_compile(&corpus[3]).unwrap().bytecode).1, 1);
+        // Three tempo lookups become one
+        assert_eq!(counts(&sharing.try_compile(&corpus[5]).unwrap().bytecode).1, 2);
+        // Nothing repeats
+        let plain = ExpressionCompiler::new().try_compile(&corpus[9]).unwrap();
+        assert_eq!(sharing.try_compile(&corpus[9]).unwrap().bytecode, plain.bytecode);
+    }
+
+    #[test]
+    fn test_decompile_round_trip() {
+        use crate::bytecode::decompile;
+
+        let round_trip = |text: &str| {
+            let compiled = ExpressionCompiler::new().try_compile(text).unwrap_or_else(|e| panic!("{}: {}", text, e));
+            let source = decompile(&compiled.bytecode, compiled.bytecode.len()).unwrap();
+            let recompiled = ExpressionCompiler::new().try_compile(&source).unwrap_or_else(|e| panic!("{}: {}", source, e));
+            assert_eq!(recompiled.bytecode, compiled.bytecode, "{} -> {}", text, source);
+            source
+        };
+
+        assert_eq!(round_trip("new Fraction(3, 2)"), "new Fraction(3, 2)");
+        assert_eq!(
+            round_trip("module.getNoteById(7).getVariable('startTime').add(module.getNoteById(7).getVariable('duration'))"),
+            "module.getNoteById(7).getVariable('startTime').add(module.getNoteById(7).getVariable('duration'))"
+        );
+        assert_eq!(round_trip("module.findTempo(module.baseNote)"), "module.baseNote.getVariable('tempo')");
+        assert_eq!(
+            round_trip("new Fraction(60).div(module.findTempo(module.getNoteById(3))).mul(new Fraction(1, 2))"),
+            "new Fraction(60).div(module.findTempo(module.getNoteById(3))).mul(new Fraction(1, 2))"
+        );
+        assert_eq!(
+            round_trip("(module.baseNote.getVariable('frequency').add(new Fraction(1))).mul(new Fraction(2).pow(new Fraction(1, 12)).neg())"),
+            "(module.baseNote.getVariable('frequency').add(new Fraction(1))).mul(new Fraction(2).pow(new Fraction(1, 12)).neg())"
+        );
+
+        // Random expressions over every construct the compiler accepts
+        fn generate(state: &mut u64, depth: u32) -> String {
+            let mut next = |n: u64| crate::xorshift(state) % n;
+            let note = |i: u64| match i {
+                0 => "module.baseNote".to_string(),
+                1 => "module.getNoteById(70000)".to_string(),
+                i => format!("module.getNoteById({})", i),
+            };
+            let vars = ["startTime", "duration", "frequency", "tempo", "beatsPerMeasure", "measureLength"];
+            match next(if depth == 0 { 6 } else { 9 }) {
+                0 => format!("new Fraction({}, {})", next(40) as i64 - 20, next(12) + 1),
+                1 => "new Fraction(-12345678901234567890123, 7)".to_string(),
+                2 => format!("module.baseNote.getVariable('{}')", vars[next(6) as usize]),
+                3 => format!("{}.getVariable('{}')", note(next(5) + 1), vars[next(6) as usize]),
+                4 => format!("module.findMeasureLength({})", note(next(4))),
+                5 => format!("new Fraction({}).div(module.findTempo({}))", [60, 30, 90][next(3) as usize], note(next(4))),
+                choice => {
+                    let (a, b) = (generate(state, depth - 1), generate(state, depth - 1));
+                    let methods = ["mul", "div", "mod", "min", "max", "root", "log", "pow", "add", "sub"];
+                    let unary = ["sqrt", "abs", "sign", "floor", "ceil", "round", "neg"];
+                    match choice {
+                        6 | 7 => format!("({}).{}(({}))", a, methods[b.len() % methods.len()], b),
+                        _ => format!("({}).{}()", a, unary[b.len() % unary.len()]),
+                    }
+                }
+            }
+        }
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        for _ in 0..500 {
+            let depth = (state % 4) as u32;
+            let text = generate(&mut state, depth);
+            round_trip(&text);
+        }
     }
 }
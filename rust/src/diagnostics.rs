@@ -0,0 +1,252 @@
+//! Built-in self-test and micro-benchmark
+//!
+//! `run_diagnostics` exercises each core component on canned inputs, checks
+//! the results against embedded expected values, and times each phase. It
+//! only uses its own temporary instances, so it is safe to call at any time
+//! (e.g. from a support page) without disturbing the loaded module.
+
+use crate::bytecode::{write_i32, write_u16, Op, Var};
+use crate::compiler::ExpressionCompiler;
+use crate::evaluator::{EvaluatedNote, Evaluator, FractionData, PersistentEvaluator};
+use crate::fraction::Fraction;
+use crate::graph::DependencyGraph;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Number of synthetic notes used by the graph and evaluator phases
+const SYNTHETIC_NOTES: u32 = 1000;
+
+/// Time spent in each phase, in milliseconds
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct DiagnosticTimings {
+    pub fraction: f64,
+    pub compile: f64,
+    pub graph: f64,
+    pub evaluator: f64,
+    pub total: f64,
+}
+
+/// Result of a diagnostics run
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct DiagnosticsReport {
+    pub version: String,
+    pub passed: bool,
+    /// One message per failed check, prefixed with the phase name
+    pub failures: Vec<String>,
+    #[serde(rename = "timingsMs")]
+    pub timings_ms: DiagnosticTimings,
+}
+
+/// Current time in milliseconds (performance.now, falling back to Date.now)
+#[cfg(target_arch = "wasm32")]
+fn now_ms() -> f64 {
+    use wasm_bindgen::JsCast;
+    let performance_now = || -> Option<f64> {
+        let performance = js_sys::Reflect::get(&js_sys::global(), &"performance".into()).ok()?;
+        let now = js_sys::Reflect::get(&performance, &"now".into())
+            .ok()?
+            .dyn_into::<js_sys::Function>()
+            .ok()?;
+        now.call0(&performance).ok()?.as_f64()
+    };
+    performance_now().unwrap_or_else(js_sys::Date::now)
+}
+
+/// Current time in milliseconds (monotonic, relative to first use)
+#[cfg(not(target_arch = "wasm32"))]
+fn now_ms() -> f64 {
+    use std::time::Instant;
+    thread_local! {
+        static START: Instant = Instant::now();
+    }
+    START.with(|start| start.elapsed().as_secs_f64() * 1000.0)
+}
+
+/// Collects failures for one phase
+struct Checks<'a> {
+    phase: &'static str,
+    failures: &'a mut Vec<String>,
+}
+
+impl Checks<'_> {
+    fn expect(&mut self, ok: bool, what: impl FnOnce() -> String) {
+        if !ok {
+            self.failures.push(format!("{}: {}", self.phase, what()));
+        }
+    }
+
+    fn expect_eq<T: PartialEq + std::fmt::Debug>(&mut self, label: &str, actual: T, expected: T) {
+        self.expect(actual == expected, || {
+            format!("{} = {:?}, expected {:?}", label, actual, expected)
+        });
+    }
+}
+
+/// Time a phase, returning elapsed milliseconds
+fn timed(phase: impl FnOnce()) -> f64 {
+    let start = now_ms();
+    phase();
+    now_ms() - start
+}
+
+fn check_fractions(checks: &mut Checks) {
+    let third = Fraction::new(1, 3);
+    let sixth = Fraction::new(1, 6);
+    checks.expect_eq("1/3 + 1/6", third.add(&sixth), Fraction::new(1, 2));
+    checks.expect_eq("1/3 - 1/6", third.sub(&sixth), sixth.clone());
+    checks.expect_eq("(1/3 * 6) / 6", third.mul(&Fraction::new(6, 1)).div(&Fraction::new(6, 1)), third.clone());
+    checks.expect_eq("inverse(3/4)", Fraction::new(3, 4).inverse(), Fraction::new(4, 3));
+
+    let big = Fraction::new_raw(i64::MAX, 3).mul(&Fraction::new_raw(i64::MAX, 7));
+    let reparsed = big.to_string_repr().parse::<Fraction>().ok();
+    checks.expect_eq("big fraction string round-trip", reparsed, Some(big));
+
+    let data = FractionData::from_fraction(&Fraction::new(-5, 12));
+    checks.expect_eq("FractionData round-trip", data.to_fraction(), Fraction::new(-5, 12));
+}
+
+fn check_compile_and_evaluate(checks: &mut Checks) {
+    let base = EvaluatedNote {
+        frequency: Some(FractionData::from_fraction(&Fraction::new(440, 1))),
+        tempo: Some(FractionData::from_fraction(&Fraction::new(120, 1))),
+        start_time: Some(FractionData::from_fraction(&Fraction::zero())),
+        ..Default::default()
+    };
+    let cache: HashMap<u32, EvaluatedNote> = HashMap::from([(0, base)]);
+
+    let cases: [(&str, Fraction); 4] = [
+        ("new Fraction(3, 2)", Fraction::new(3, 2)),
+        ("module.baseNote.getVariable('frequency').mul(new Fraction(5, 4))", Fraction::new(550, 1)),
+        ("new Fraction(60).div(module.findTempo(module.baseNote))", Fraction::new(1, 2)),
+        (
+            "module.baseNote.getVariable('startTime').add(new Fraction(1, 3)).sub(new Fraction(1, 12))",
+            Fraction::new(1, 4),
+        ),
+    ];
+
+    let mut compiler = ExpressionCompiler::new();
+    let mut evaluator = Evaluator::new();
+    for (text, expected) in cases {
+        match compiler.try_compile(text) {
+            Ok(compiled) => {
+                let result = evaluator
+                    .evaluate(&compiled.bytecode, compiled.bytecode.len(), &cache)
+                    .map(|v| v.to_fraction());
+                checks.expect_eq(text, result, Ok(expected));
+            }
+            Err(e) => checks.expect(false, || format!("{} failed to compile: {}", text, e)),
+        }
+    }
+}
+
+fn check_graph(checks: &mut Checks) {
+    // Note i depends on i - 1 and i / 2
+    let mut graph = DependencyGraph::new();
+    for id in 2..=SYNTHETIC_NOTES {
+        graph.update_dependencies(id, HashSet::from([id - 1, id / 2]), false);
+    }
+    graph.update_dependencies(1, HashSet::new(), true);
+
+    let ids: HashSet<u32> = (1..=SYNTHETIC_NOTES).collect();
+    let order = graph.get_evaluation_order(&ids);
+    checks.expect_eq("evaluation order length", order.len(), SYNTHETIC_NOTES as usize);
+
+    let mut position = vec![0usize; SYNTHETIC_NOTES as usize + 1];
+    for (i, &id) in order.iter().enumerate() {
+        position[id as usize] = i;
+    }
+    let misordered = (2..=SYNTHETIC_NOTES)
+        .filter(|&id| position[(id - 1) as usize] > position[id as usize] || position[(id / 2) as usize] > position[id as usize])
+        .count();
+    checks.expect_eq("notes ordered before a dependency", misordered, 0);
+    checks.expect_eq("cycles", graph.detect_cycles().len(), 0);
+    checks.expect_eq("dependents of note 1", graph.get_all_dependents(1).len(), SYNTHETIC_NOTES as usize - 1);
+}
+
+fn check_persistent_evaluator(checks: &mut Checks) {
+    // Note i starts where note i - 1 ends; every note lasts 1/4
+    let mut evaluator = PersistentEvaluator::new();
+    let constant = |num: i32, den: i32| {
+        let mut bc = vec![Op::LoadConst as u8];
+        write_i32(&mut bc, num);
+        write_i32(&mut bc, den);
+        bc
+    };
+    let duration = constant(1, 4);
+    for id in 1..=SYNTHETIC_NOTES {
+        let start = if id == 1 {
+            constant(0, 1)
+        } else {
+            let mut bc = Vec::new();
+            for var in [Var::StartTime, Var::Duration] {
+                bc.push(Op::LoadRef as u8);
+                write_u16(&mut bc, (id - 1) as u16);
+                bc.push(var as u8);
+            }
+            bc.push(Op::Add as u8);
+            bc
+        };
+        evaluator.register_expression(id, Var::StartTime as u8, &start, start.len());
+        evaluator.register_expression(id, Var::Duration as u8, &duration, duration.len());
+    }
+
+    let order: Vec<u32> = (1..=SYNTHETIC_NOTES).collect();
+    checks.expect_eq("notes evaluated", evaluator.evaluate_dirty(&order).evaluated.len(), SYNTHETIC_NOTES as usize);
+
+    let last_start = evaluator
+        .cached_note(SYNTHETIC_NOTES)
+        .and_then(|note| note.start_time.as_ref())
+        .map(|start| start.to_fraction());
+    checks.expect_eq(
+        "start of last note",
+        last_start,
+        Some(Fraction::new(SYNTHETIC_NOTES as i32 - 1, 4)),
+    );
+}
+
+/// Run the self-test suite and time each phase
+pub fn run_diagnostics() -> DiagnosticsReport {
+    let mut failures = Vec::new();
+    let mut timings = DiagnosticTimings::default();
+    let start = now_ms();
+
+    timings.fraction = timed(|| check_fractions(&mut Checks { phase: "fraction", failures: &mut failures }));
+    timings.compile =
+        timed(|| check_compile_and_evaluate(&mut Checks { phase: "compile", failures: &mut failures }));
+    timings.graph = timed(|| check_graph(&mut Checks { phase: "graph", failures: &mut failures }));
+    timings.evaluator =
+        timed(|| check_persistent_evaluator(&mut Checks { phase: "evaluator", failures: &mut failures }));
+    timings.total = now_ms() - start;
+
+    DiagnosticsReport {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        passed: failures.is_empty(),
+        failures,
+        timings_ms: timings,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_phases_pass() {
+        let report = run_diagnostics();
+        assert!(report.passed, "{:?}", report.failures);
+        assert_eq!(report.version, env!("CARGO_PKG_VERSION"));
+
+        let t = &report.timings_ms;
+        for phase in [t.fraction, t.compile, t.graph, t.evaluator] {
+            assert!(phase >= 0.0 && phase <= t.total);
+        }
+    }
+
+    #[test]
+    fn test_failures_are_reported_per_phase() {
+        let mut failures = Vec::new();
+        let mut checks = Checks { phase: "graph", failures: &mut failures };
+        checks.expect_eq("answer", 41, 42);
+        assert_eq!(failures, vec!["graph: answer = 41, expected 42".to_string()]);
+    }
+}
@@ -6,11 +6,14 @@
 //! Supports both rational (exact) and irrational (f64) values via the Value type.
 //! Operations like Pow may produce irrational results, which "corrupt" the value.
 
-use crate::bytecode::{read_i32, read_u16, read_big_int_signed, read_big_int_unsigned, Op, Var};
+use crate::bytecode::{analyze_with_macros, canonical_hash, equivalent, is_custom_var, operand_text, var_label, write_const, BytecodeStats, read_i32, read_u16, read_u32, read_big_int_signed, read_big_int_unsigned, note_references_with_macros, remap_note_ids, validate_with_macros, MacroTable, Op, Var};
 use crate::fraction::Fraction;
-use crate::value::{Value, corruption_flag_for_var};
+use crate::format::format_f64_js;
+use crate::value::{error_field, Value, NonFinitePolicy, PowError, SymbolicPower, SymbolicPowerData, corruption_flag_for_var, custom_flag_for_var};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
 use wasm_bindgen::prelude::*;
 
 /// Evaluated values for a single note
@@ -28,16 +31,402 @@ pub struct EvaluatedNote {
     pub beats_per_measure: Option<FractionData>,
     #[serde(rename = "measureLength")]
     pub measure_length: Option<FractionData>,
-    /// Bitmask of corruption flags indicating which properties are irrational
+    /// Bitmask of corruption flags indicating which properties are irrational,
+    /// with bit i for variable index i (payloads from when this was a u8 still load)
     /// See value.rs for flag constants (CORRUPT_START_TIME, CORRUPT_FREQUENCY, etc.)
     #[serde(default, rename = "corruptionFlags")]
-    pub corruption_flags: u8,
+    pub corruption_flags: u16,
+    /// Custom variables by index (see `bytecode::CUSTOM_VAR_FIRST`), only
+    /// serialized when there is at least one
+    #[serde(default, rename = "customVars", skip_serializing_if = "HashMap::is_empty")]
+    pub custom: HashMap<u8, FractionData>,
+    /// Bitmask of irrational custom variables, with bit i - 32 for variable
+    /// index i (see `custom_flag_for_var`; only serialized when set)
+    #[serde(default, rename = "customCorruptionFlags", skip_serializing_if = "is_zero")]
+    pub custom_corruption_flags: u32,
+    /// Where each irrational property first became irrational (only
+    /// serialized when there is at least one)
+    #[serde(default, rename = "corruptionSources", skip_serializing_if = "Vec::is_empty")]
+    pub corruption_sources: Vec<CorruptionSource>,
+    /// Instrument id from the evaluator's instrument table (only
+    /// serialized when set)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instrument: Option<u32>,
+}
+
+/// Whether `custom_corruption_flags` can be left out of the serialized note
+fn is_zero(flags: &u32) -> bool {
+    *flags == 0
+}
+
+/// The instruction that first produced a non-rational value while
+/// evaluating one property of a note
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CorruptionSource {
+    /// Property name, e.g. "frequency"
+    pub variable: String,
+    /// Opcode name, e.g. "Pow" (or "LoadRef" for an already irrational note)
+    pub op: String,
+    /// Byte offset of the instruction in the property's bytecode
+    pub pc: u32,
+    /// The instruction fell back to an f64 approximation rather than an
+    /// exact symbolic value (only serialized when set)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lossy: bool,
+}
+
+impl CorruptionSource {
+    fn new(variable: &str, (op, pc): (Op, usize), lossy: bool) -> CorruptionSource {
+        CorruptionSource {
+            variable: variable.to_string(),
+            op: format!("{:?}", op),
+            pc: pc as u32,
+            lossy,
+        }
+    }
+
+    /// Sources for one property: where it first became non-rational and,
+    /// when that was still exact, where it later fell back to f64
+    fn for_var(variable: &str, first: Option<(Op, usize)>, lossy: Option<(Op, usize)>) -> Vec<CorruptionSource> {
+        let mut sources: Vec<CorruptionSource> =
+            first.map(|source| CorruptionSource::new(variable, source, lossy == Some(source))).into_iter().collect();
+        if let Some(source) = lossy.filter(|&source| first != Some(source)) {
+            sources.push(CorruptionSource::new(variable, source, true));
+        }
+        sources
+    }
+}
+
+/// One executed instruction of a traced evaluation (see
+/// `Evaluator::evaluate_traced`)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TraceStep {
+    /// Byte offset of the instruction; inside a macro, in the macro body
+    pub pc: u32,
+    /// Opcode name, e.g. "LoadRef"
+    pub op: String,
+    /// Decoded operands (see `bytecode::operand_text`)
+    pub operands: Vec<String>,
+    /// The stack after the instruction, bottom first
+    pub stack: Vec<String>,
+}
+
+/// A traced evaluation as passed to JavaScript: `result` or `error` is set
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TracedEvaluation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<FractionData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<EvalErrorData>,
+    pub trace: Vec<TraceStep>,
+}
+
+impl TracedEvaluation {
+    fn new(result: Result<Value, EvalError>, trace: Vec<TraceStep>, context: Option<(u32, u8)>) -> TracedEvaluation {
+        match result {
+            Ok(value) => TracedEvaluation { result: Some(FractionData::from_value(&value)), error: None, trace },
+            Err(e) => TracedEvaluation { result: None, error: Some(e.to_data(context)), trace },
+        }
+    }
+}
+
+/// Receives each instruction an evaluation executes
+///
+/// The evaluation loops are generic over this, so untraced evaluation
+/// (`NoTrace`) compiles to the same code as before tracing existed.
+trait Tracer {
+    fn step(&mut self, pc: usize, instruction: &[u8], stack: &[Value]);
+}
+
+struct NoTrace;
+
+impl Tracer for NoTrace {
+    #[inline(always)]
+    fn step(&mut self, _: usize, _: &[u8], _: &[Value]) {}
+}
+
+impl Tracer for Vec<TraceStep> {
+    fn step(&mut self, pc: usize, instruction: &[u8], stack: &[Value]) {
+        self.push(TraceStep {
+            pc: pc as u32,
+            op: Op::from_byte(instruction[0]).map_or_else(String::new, |op| format!("{:?}", op)),
+            operands: operand_text(instruction),
+            stack: stack.iter().map(|value| value.to_string()).collect(),
+        });
+    }
+}
+
+/// Why evaluating an expression failed
+///
+/// `pc` is the byte offset of the failing instruction in the bytecode being
+/// run, which for an instruction inside a macro is the macro body.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EvalError {
+    /// A byte that is not an opcode
+    UnknownOpcode { pc: usize, byte: u8 },
+    /// An instruction whose operands run past the end of the bytecode
+    TruncatedOperand { pc: usize, op: Op },
+    /// An instruction that pops more values than the stack holds
+    StackUnderflow { pc: usize, op: Op },
+    /// An instruction that pushes past the stack limit
+    StackOverflow { pc: usize, op: Op, limit: usize },
+    /// A LOAD_REF, LOAD_REF_WIDE or LOAD_BASE variable index outside `Var`
+    InvalidVar { pc: usize, op: Op, index: u8 },
+    /// A ROOT degree that is not a positive integer
+    InvalidRootDegree { pc: usize, degree: String },
+    /// A NaN or infinite result with `NonFinitePolicy::Error`
+    NonFinite { pc: usize, op: Op, reason: String },
+    /// A non-rational result in strict mode, with the reason when POW
+    /// has no real result at all
+    Strict { pc: usize, op: Op, pow: Option<PowError> },
+    /// A CALL_MACRO of a macro that is not registered
+    UnknownMacro { pc: usize, macro_id: u16 },
+    /// A CALL_MACRO inside a macro body
+    NestedMacro { pc: usize, macro_id: u16 },
+    /// A CALL_MACRO run by an `Evaluator`, which has no macro table
+    NoMacroTable { pc: usize },
+    /// The instruction limit ran out (see `Evaluator::set_max_instructions`)
+    FuelExhausted { pc: usize, op: Op, executed: u32 },
+    /// A LOAD_REF, LOAD_REF_WIDE or LOAD_BASE of a note variable that is
+    /// still being evaluated: the one the expression computes, or one on
+    /// the same reference cycle
+    SelfReference { pc: usize, op: Op, note_id: u32, var: u8 },
+}
+
+impl EvalError {
+    /// Stable identifier of the error class, e.g. "STACK_UNDERFLOW"
+    pub fn code(&self) -> &'static str {
+        match self {
+            EvalError::UnknownOpcode { .. } => "UNKNOWN_OPCODE",
+            EvalError::TruncatedOperand { .. } => "TRUNCATED_OPERAND",
+            EvalError::StackUnderflow { .. } => "STACK_UNDERFLOW",
+            EvalError::StackOverflow { .. } => "STACK_OVERFLOW",
+            EvalError::InvalidVar { .. } => "INVALID_VAR",
+            EvalError::InvalidRootDegree { .. } => "INVALID_ROOT_DEGREE",
+            EvalError::NonFinite { .. } => "NON_FINITE",
+            EvalError::Strict { .. } => "STRICT",
+            EvalError::UnknownMacro { .. } => "UNKNOWN_MACRO",
+            EvalError::NestedMacro { .. } => "NESTED_MACRO",
+            EvalError::NoMacroTable { .. } => "NO_MACRO_TABLE",
+            EvalError::FuelExhausted { .. } => "FUEL_EXHAUSTED",
+            EvalError::SelfReference { .. } => "SELF_REFERENCE",
+        }
+    }
+
+    /// Byte offset of the failing instruction
+    pub fn pc(&self) -> usize {
+        match *self {
+            EvalError::UnknownOpcode { pc, .. }
+            | EvalError::TruncatedOperand { pc, .. }
+            | EvalError::StackUnderflow { pc, .. }
+            | EvalError::StackOverflow { pc, .. }
+            | EvalError::InvalidVar { pc, .. }
+            | EvalError::InvalidRootDegree { pc, .. }
+            | EvalError::NonFinite { pc, .. }
+            | EvalError::Strict { pc, .. }
+            | EvalError::UnknownMacro { pc, .. }
+            | EvalError::NestedMacro { pc, .. }
+            | EvalError::NoMacroTable { pc }
+            | EvalError::FuelExhausted { pc, .. }
+            | EvalError::SelfReference { pc, .. } => pc,
+        }
+    }
+
+    /// The failing instruction's opcode (None for an unknown opcode)
+    pub fn op(&self) -> Option<Op> {
+        match *self {
+            EvalError::UnknownOpcode { .. } => None,
+            EvalError::TruncatedOperand { op, .. }
+            | EvalError::StackUnderflow { op, .. }
+            | EvalError::StackOverflow { op, .. }
+            | EvalError::InvalidVar { op, .. }
+            | EvalError::NonFinite { op, .. }
+            | EvalError::Strict { op, .. }
+            | EvalError::FuelExhausted { op, .. }
+            | EvalError::SelfReference { op, .. } => Some(op),
+            EvalError::InvalidRootDegree { .. } => Some(Op::Root),
+            EvalError::UnknownMacro { .. } | EvalError::NestedMacro { .. } | EvalError::NoMacroTable { .. } => {
+                Some(Op::CallMacro)
+            }
+        }
+    }
+
+    /// Serializable form, with the note and variable being evaluated if known
+    pub fn to_data(&self, context: Option<(u32, u8)>) -> EvalErrorData {
+        EvalErrorData {
+            code: self.code().to_string(),
+            message: self.to_string(),
+            pc: self.pc() as u32,
+            op: self.op().map(|op| format!("{:?}", op)),
+            note_id: context.map(|(note_id, _)| note_id),
+            var: context.and_then(|(_, var)| var_label(var)),
+        }
+    }
+
+    /// `{ code, message, pc, op }` as a JavaScript object
+    pub fn to_js(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.to_data(None)).unwrap_or_else(|_| JsValue::from_str(&self.to_string()))
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::UnknownOpcode { pc, byte } => write!(f, "Unknown opcode: 0x{:02x} at pc={}", byte, pc),
+            EvalError::TruncatedOperand { pc, op } => write!(f, "Unexpected end of bytecode in {:?} at pc={}", op, pc),
+            EvalError::StackUnderflow { pc, op } => write!(f, "Stack underflow in {:?} at pc={}", op, pc),
+            EvalError::StackOverflow { pc, op, limit } => {
+                write!(f, "Stack overflow in {:?} at pc={} (limit {})", op, pc, limit)
+            }
+            EvalError::InvalidVar { pc, op, index } => {
+                write!(f, "Invalid variable index {} in {:?} at pc={}", index, op, pc)
+            }
+            EvalError::InvalidRootDegree { pc, degree } => {
+                write!(f, "Root degree must be a positive integer, got {} at pc={}", degree, pc)
+            }
+            EvalError::NonFinite { pc, op, reason } => write!(f, "{} from {:?} at pc={}", reason, op, pc),
+            EvalError::Strict { pc, op, pow: Some(e) } => write!(f, "Strict mode: {:?} at pc={}: {}", op, pc, e),
+            EvalError::Strict { pc, op, pow: None } => {
+                write!(f, "Strict mode: {:?} at pc={} produced a non-rational value", op, pc)
+            }
+            EvalError::UnknownMacro { pc, macro_id } => write!(f, "Unknown macro {} at pc={}", macro_id, pc),
+            EvalError::NestedMacro { pc, macro_id } => {
+                write!(f, "Macro {} called from inside a macro at pc={}", macro_id, pc)
+            }
+            EvalError::NoMacroTable { pc } => {
+                write!(f, "CALL_MACRO at pc={} needs a PersistentEvaluator macro table", pc)
+            }
+            EvalError::FuelExhausted { pc, op, executed } => {
+                write!(f, "Instruction limit reached after {} instructions, at {:?} pc={}", executed, op, pc)
+            }
+            EvalError::SelfReference { pc, op, note_id, var } => {
+                write!(f, "{:?} at pc={} reads {} of note {}, which is still being evaluated", op, pc, label(*var), note_id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// An `EvalError` as passed to JavaScript
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EvalErrorData {
+    /// `EvalError::code`, e.g. "STACK_UNDERFLOW"
+    pub code: String,
+    pub message: String,
+    /// Byte offset of the failing instruction
+    pub pc: u32,
+    /// Opcode name, e.g. "Add" (absent for an unknown opcode)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op: Option<String>,
+    /// Note being evaluated (only set by `PersistentEvaluator`)
+    #[serde(default, rename = "noteId", skip_serializing_if = "Option::is_none")]
+    pub note_id: Option<u32>,
+    /// Property being evaluated, e.g. "frequency"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub var: Option<String>,
+}
+
+/// An evaluation failure recorded by `PersistentEvaluator`
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoteEvalError {
+    pub note_id: u32,
+    /// Index of the variable, built-in or custom (see `var_label`)
+    pub var: u8,
+    pub error: EvalError,
+}
+
+/// `var_label` of a variable index, or the bare index if it has none
+fn label(index: u8) -> String {
+    var_label(index).unwrap_or_else(|| index.to_string())
+}
+
+/// A note that produced a non-rational value while evaluating in strict mode
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StrictViolation {
+    #[serde(rename = "noteId")]
+    pub note_id: u32,
+    /// Property name, e.g. "frequency"
+    pub variable: String,
+    /// Opcode name, e.g. "Pow" ("Derived" for a measure length computed
+    /// from tempo and beats per measure)
+    pub op: String,
+    /// Byte offset of the instruction in the property's bytecode
+    pub pc: u32,
+}
+
+impl StrictViolation {
+    /// The first corrupted property of an evaluated note, if any
+    fn for_note(note_id: u32, note: &EvaluatedNote) -> Option<StrictViolation> {
+        if note.corruption_flags == 0 && note.custom_corruption_flags == 0 {
+            return None;
+        }
+        let source = note.corruption_sources.first().cloned().unwrap_or_else(|| CorruptionSource {
+            variable: Var::MeasureLength.name().to_string(),
+            op: "Derived".to_string(),
+            pc: 0,
+            lossy: false,
+        });
+        Some(StrictViolation { note_id, variable: source.variable, op: source.op, pc: source.pc })
+    }
+}
+
+/// A cached value no note can play, as returned by `validateCache`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheProblem {
+    pub id: u32,
+    /// Property name, e.g. "duration", or "custom:N"
+    pub var: String,
+    /// "NEGATIVE" (startTime, duration), "NOT_POSITIVE" (frequency, tempo)
+    /// or "NON_FINITE" (a NaN or infinite float in any property)
+    pub problem: String,
+}
+
+impl CacheProblem {
+    /// Add the problems of a cached note to `problems`, in variable order
+    ///
+    /// Rational values are checked exactly, by sign and numerator; others by
+    /// their float.
+    fn for_note(note_id: u32, note: &EvaluatedNote, problems: &mut Vec<CacheProblem>) {
+        let mut report = |var: String, problem: &str| {
+            problems.push(CacheProblem { id: note_id, var, problem: problem.to_string() });
+        };
+        for var in (0..6).filter_map(Var::from_byte) {
+            let Some(data) = note.get_var(var) else {
+                continue;
+            };
+            let value = data.to_f64();
+            if data.corrupted && !value.is_finite() {
+                report(var.name().to_string(), "NON_FINITE");
+                continue;
+            }
+            let (negative, zero) = if data.corrupted {
+                (value < 0.0, value == 0.0)
+            } else {
+                (data.s < 0 && data.n != 0, data.s == 0 || data.n == 0)
+            };
+            match var {
+                Var::StartTime | Var::Duration if negative => report(var.name().to_string(), "NEGATIVE"),
+                Var::Frequency | Var::Tempo if negative || zero => report(var.name().to_string(), "NOT_POSITIVE"),
+                _ => {}
+            }
+        }
+        let mut custom: Vec<(&u8, &FractionData)> = note.custom.iter().collect();
+        custom.sort_unstable_by_key(|&(index, _)| *index);
+        for (&index, data) in custom {
+            if data.corrupted && !data.to_f64().is_finite() {
+                report(var_label(index).unwrap_or_default(), "NON_FINITE");
+            }
+        }
+    }
 }
 
 /// Serializable fraction data for JS interop
 ///
 /// Supports both rational values (s/n/d fields) and irrational values (f field).
 /// The corrupted field indicates whether this is an irrational approximation.
+/// Symbolic values also carry their exact structure, so a note that
+/// references them keeps combining like-base powers.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct FractionData {
     /// Sign: -1, 0, or 1 (for rational values)
@@ -52,9 +441,24 @@ pub struct FractionData {
     /// Float value (for irrational values, or as convenience)
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub f: Option<f64>,
+    /// Error bound of `f` (for tracked irrational values, see
+    /// `Value::track_error`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub err: Option<f64>,
     /// Is this value corrupted (irrational)?
     #[serde(default)]
     pub corrupted: bool,
+    /// Symbolic power data (if symbolic)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbolic: Option<SymbolicPowerData>,
+}
+
+#[cfg(test)]
+thread_local! {
+    /// `FractionData::to_value` calls made on this thread
+    static TO_VALUE_CALLS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    /// `cache_from_js` calls made on this thread
+    static CACHE_FROM_JS_CALLS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
 }
 
 fn default_denominator() -> u32 {
@@ -67,75 +471,76 @@ impl FractionData {
     /// For fractions with numerator or denominator larger than u32::MAX,
     /// we store the float value and mark as "corrupted" to preserve precision.
     pub fn from_fraction(f: &Fraction) -> Self {
-        let n_val = f.n();
-        let d_val = f.d();
-
-        // Check if values fit in u32 by comparing with the original BigInt values
-        // If n() or d() returned u32::MAX, check if that's the actual value
-        let n_overflow = n_val == u32::MAX && f.numerator_str().parse::<u64>().unwrap_or(0) > u32::MAX as u64;
-        let d_overflow = d_val == u32::MAX && f.denominator_str().parse::<u64>().unwrap_or(0) > u32::MAX as u64;
-
-        if n_overflow || d_overflow {
-            // Value is too large for u32 - store as float to preserve precision
+        if !f.fits_u32() {
+            // Value is too large for u32 - store as float to preserve precision,
+            // with the closest fraction that fits as the n/d approximation
             let float_val = f.to_f64();
-            let abs_val = float_val.abs();
-            let sign = if float_val < 0.0 { -1 } else if float_val > 0.0 { 1 } else { 0 };
-            let denom = 1_000_000u32;
-            let numer = (abs_val * (denom as f64)).round() as u32;
+            let approx = f.abs().limit_denominator(u32::MAX as u64);
+            let (numer, denom) = if approx.fits_u32() { (approx.n(), approx.d()) } else { (u32::MAX, 1) };
             FractionData {
-                s: sign,
+                s: f.s(),
                 n: numer,
                 d: denom,
                 f: Some(float_val),
+                err: None,
                 corrupted: true, // Mark as corrupted so JS uses float value
+                symbolic: None,
             }
         } else {
             FractionData {
                 s: f.s(),
-                n: n_val,
-                d: d_val,
+                n: f.n(),
+                d: f.d(),
                 f: None,
+                err: None,
                 corrupted: false,
+                symbolic: None,
             }
         }
     }
 
     /// Create from a Value (may be rational, irrational, or symbolic)
+    ///
+    /// Non-rational values get an n/d approximation with denominator
+    /// `DEFAULT_APPROXIMATION_DENOMINATOR` (see `from_value_with`).
     pub fn from_value(v: &Value) -> Self {
+        Self::from_value_with(v, DEFAULT_APPROXIMATION_DENOMINATOR)
+    }
+
+    /// Create from a Value, approximating non-rational values as n/d with
+    /// the given denominator (see `approximate_parts_with`)
+    pub fn from_value_with(v: &Value, denominator: u32) -> Self {
         match v {
             Value::Rational(frac) => {
                 // Use from_fraction to handle overflow cases
                 Self::from_fraction(frac)
             }
-            Value::Irrational(val) => {
+            Value::Irrational { value, err } => {
                 // Approximate irrational as a fraction so valueOf() works correctly
-                // Use a denominator of 1_000_000 for microsecond precision
-                let abs_val = val.abs();
-                let sign = if *val < 0.0 { -1 } else if *val > 0.0 { 1 } else { 0 };
-                let denom = 1_000_000u32;
-                let numer = (abs_val * (denom as f64)).round() as u32;
+                let (sign, numer, denom) = approximate_parts_with(*value, denominator);
                 FractionData {
                     s: sign,
                     n: numer,
                     d: denom,
-                    f: Some(*val),
+                    f: Some(*value),
+                    err: error_field(*err),
                     corrupted: true,
+                    symbolic: None,
                 }
             }
             Value::Symbolic(sp) => {
                 // Approximate symbolic as a fraction for valueOf() compatibility
                 // The f64 value preserves accuracy for playback
                 let val = sp.to_f64();
-                let abs_val = val.abs();
-                let sign = if val < 0.0 { -1 } else if val > 0.0 { 1 } else { 0 };
-                let denom = 1_000_000u32;
-                let numer = (abs_val * (denom as f64)).round() as u32;
+                let (sign, numer, denom) = approximate_parts_with(val, denominator);
                 FractionData {
                     s: sign,
                     n: numer,
                     d: denom,
                     f: Some(val),
+                    err: None,
                     corrupted: true,
+                    symbolic: symbolic_data(sp),
                 }
             }
         }
@@ -147,19 +552,44 @@ impl FractionData {
             // Approximate irrational as fraction
             Fraction::from_f64(self.f.unwrap_or(0.0))
         } else {
-            let num = (self.s as i32) * (self.n as i32);
-            Fraction::new(num, self.d as i32)
+            self.rational()
         }
     }
 
-    /// Convert to Value
+    /// Convert to Value (symbolic values are restored exactly)
     pub fn to_value(&self) -> Value {
+        #[cfg(test)]
+        TO_VALUE_CALLS.with(|calls| calls.set(calls.get() + 1));
+        if let Some(symbolic) = &self.symbolic {
+            return Value::Symbolic(symbolic.to_symbolic());
+        }
         if self.corrupted {
-            Value::Irrational(self.f.unwrap_or(0.0))
+            Value::inexact(self.f.unwrap_or(0.0), self.err.unwrap_or(0.0))
         } else {
-            let num = (self.s as i32) * (self.n as i32);
-            Value::Rational(Fraction::new(num, self.d as i32))
+            Value::Rational(self.rational())
+        }
+    }
+
+    /// s·n/d, exact for every u32 numerator and denominator (0 for d = 0)
+    fn rational(&self) -> Fraction {
+        if self.d == 0 {
+            return Fraction::zero();
         }
+        Fraction::new_raw(self.s.signum() as i64 * self.n as i64, self.d as i64)
+    }
+
+    /// The rational given for a default or override of `var`
+    ///
+    /// Must be an uncorrupted {s, n, d} with a non-zero denominator and a
+    /// sign that agrees with the numerator.
+    fn checked_rational(&self, what: &str, var: Var) -> Result<Fraction, String> {
+        if self.corrupted || self.d == 0 {
+            return Err(format!("{} {} must be a rational {{n, d}}", what, var.name()));
+        }
+        if !(-1..=1).contains(&self.s) || (self.s == 0) != (self.n == 0) {
+            return Err(format!("{} {} has sign {} for numerator {}", what, var.name(), self.s, self.n));
+        }
+        Ok(self.rational())
     }
 
     /// Get the f64 representation
@@ -170,6 +600,22 @@ impl FractionData {
             (self.s as f64) * (self.n as f64) / (self.d as f64)
         }
     }
+
+    /// Do both hold the same value?
+    ///
+    /// Rational values must be equal fractions. Corrupted values only match
+    /// other corrupted values, and do so when their floats differ by at most
+    /// `epsilon`.
+    pub fn same_value(&self, other: &FractionData, epsilon: f64) -> bool {
+        match (self.corrupted, other.corrupted) {
+            (false, false) => self.s == other.s && self.n == other.n && self.d == other.d,
+            (true, true) => {
+                let (a, b) = (self.to_f64(), other.to_f64());
+                a == b || a.to_bits() == b.to_bits() || (a - b).abs() <= epsilon
+            }
+            _ => false,
+        }
+    }
 }
 
 impl Default for FractionData {
@@ -179,12 +625,304 @@ impl Default for FractionData {
             n: 0,
             d: 1,
             f: None,
+            err: None,
             corrupted: false,
+            symbolic: None,
+        }
+    }
+}
+
+/// Denominator for approximating floats outside any variable (1_000_000,
+/// microsecond precision)
+pub const DEFAULT_APPROXIMATION_DENOMINATOR: u32 = 1_000_000;
+
+/// Sign, numerator and denominator approximating a float
+///
+/// Rounds to a multiple of 1/denominator while the numerator fits a u32.
+/// Beyond that the denominator is lowered until it does, taking the best
+/// approximation (`Fraction::limit_denominator`) rather than rounding, and
+/// values too large for any denominator get the closest fitting fraction.
+/// A denominator of 0 is treated as 1. NaN and infinities have no meaningful
+/// approximation and give 0/1.
+pub(crate) fn approximate_parts_with(val: f64, denominator: u32) -> (i32, u32, u32) {
+    if !val.is_finite() {
+        return (0, 0, 1);
+    }
+    let sign = if val < 0.0 { -1 } else if val > 0.0 { 1 } else { 0 };
+    let denom = denominator.max(1);
+    let scaled = (val.abs() * denom as f64).round();
+    if scaled <= u32::MAX as f64 {
+        return (sign, scaled as u32, denom);
+    }
+    let max_den = (u32::MAX as f64 / val.abs()).floor().min(denom as f64);
+    if max_den >= 1.0 {
+        if let Ok(exact) = Fraction::from_f64_exact(val.abs()) {
+            let approx = exact.limit_denominator(max_den as u64);
+            if approx.fits_u32() {
+                return (sign, approx.n(), approx.d());
+            }
+        }
+    }
+    let approx = FractionData::from_fraction(&Fraction::from_f64(val.abs()));
+    (sign, approx.n, approx.d)
+}
+
+/// Values a reference falls back to when neither the referenced note nor
+/// the base note defines the variable
+///
+/// Used by LOAD_REF, LOAD_BASE and the FIND_* lookups. By default startTime
+/// is 0, duration 1, frequency 440, tempo 60 and beatsPerMeasure and
+/// measureLength 4, matching the JavaScript fallback.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VariableDefaults {
+    /// Value per variable index
+    values: [Fraction; 6],
+}
+
+impl Default for VariableDefaults {
+    fn default() -> Self {
+        VariableDefaults {
+            values: [
+                Fraction::zero(),
+                Fraction::one(),
+                Fraction::new(440, 1),
+                Fraction::new(60, 1),
+                Fraction::new(4, 1),
+                Fraction::new(4, 1),
+            ],
+        }
+    }
+}
+
+impl VariableDefaults {
+    /// Default of a variable
+    pub fn get(&self, var: Var) -> &Fraction {
+        &self.values[var as usize]
+    }
+
+    /// Change the default of a variable
+    pub fn set(&mut self, var: Var, value: Fraction) {
+        self.values[var as usize] = value;
+    }
+
+    /// Default of a variable as a Value (always rational)
+    pub fn value(&self, var: Var) -> Value {
+        Value::Rational(self.get(var).clone())
+    }
+
+    /// Override the variables given in `data`, keeping the others
+    ///
+    /// Nothing changes unless every given value is a rational with a
+    /// non-zero denominator and a matching sign (see `DefaultsData`).
+    pub fn apply(&mut self, data: &DefaultsData) -> Result<(), String> {
+        let mut updated = self.clone();
+        for (var, value) in data.entries() {
+            if let Some(value) = value {
+                updated.set(var, value.checked_rational("Default", var)?);
+            }
+        }
+        *self = updated;
+        Ok(())
+    }
+
+    /// Every default, as set by `apply`
+    pub fn data(&self) -> DefaultsData {
+        let data = |var| Some(FractionData::from_fraction(self.get(var)));
+        DefaultsData {
+            start_time: data(Var::StartTime),
+            duration: data(Var::Duration),
+            frequency: data(Var::Frequency),
+            tempo: data(Var::Tempo),
+            beats_per_measure: data(Var::BeatsPerMeasure),
+            measure_length: data(Var::MeasureLength),
+        }
+    }
+}
+
+/// Default values for `setDefaults` (any subset) and `getDefaults` (all),
+/// and base-note values for `evaluateWhatIf`
+///
+/// Values read from JavaScript may leave out the sign: `{n: 120, d: 1}` is
+/// 120 (see `SignedInput`).
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DefaultsData {
+    #[serde(rename = "startTime", default, skip_serializing_if = "Option::is_none", deserialize_with = "signed_input")]
+    pub start_time: Option<FractionData>,
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "signed_input")]
+    pub duration: Option<FractionData>,
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "signed_input")]
+    pub frequency: Option<FractionData>,
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "signed_input")]
+    pub tempo: Option<FractionData>,
+    #[serde(rename = "beatsPerMeasure", default, skip_serializing_if = "Option::is_none", deserialize_with = "signed_input")]
+    pub beats_per_measure: Option<FractionData>,
+    #[serde(rename = "measureLength", default, skip_serializing_if = "Option::is_none", deserialize_with = "signed_input")]
+    pub measure_length: Option<FractionData>,
+}
+
+/// A FractionData whose sign may be missing, as written by hand in
+/// `setDefaults` and `evaluateWhatIf` calls
+///
+/// A missing sign is 1 for a non-zero numerator rather than the 0
+/// `FractionData` would default to, which silently turned `{n: 120, d: 1}`
+/// into 0.
+#[derive(Deserialize)]
+struct SignedInput {
+    #[serde(default)]
+    s: Option<i32>,
+    #[serde(default)]
+    n: u32,
+    #[serde(default = "default_denominator")]
+    d: u32,
+    #[serde(default)]
+    f: Option<f64>,
+    #[serde(default)]
+    corrupted: bool,
+}
+
+fn signed_input<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<FractionData>, D::Error> {
+    let Some(input) = Option::<SignedInput>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    Ok(Some(FractionData {
+        s: input.s.unwrap_or(if input.n == 0 { 0 } else { 1 }),
+        n: input.n,
+        d: input.d,
+        f: input.f,
+        corrupted: input.corrupted,
+        ..FractionData::default()
+    }))
+}
+
+impl DefaultsData {
+    fn entries(&self) -> [(Var, &Option<FractionData>); 6] {
+        [
+            (Var::StartTime, &self.start_time),
+            (Var::Duration, &self.duration),
+            (Var::Frequency, &self.frequency),
+            (Var::Tempo, &self.tempo),
+            (Var::BeatsPerMeasure, &self.beats_per_measure),
+            (Var::MeasureLength, &self.measure_length),
+        ]
+    }
+}
+
+/// Denominators for the n/d approximation of irrational and symbolic
+/// results, per variable
+///
+/// The f64 is always kept next to the approximation, so this only matters
+/// to consumers of n/d. By default frequencies use 10^8 (ratios within about
+/// 1e-8), startTime, duration and measureLength 10^5 (10 µs, under one
+/// sample at 48 kHz) and tempo and beatsPerMeasure 10^6.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ApproximationConfig {
+    /// Denominator per variable index
+    denominators: [u32; 6],
+}
+
+impl Default for ApproximationConfig {
+    fn default() -> Self {
+        let mut config = ApproximationConfig { denominators: [DEFAULT_APPROXIMATION_DENOMINATOR; 6] };
+        config.set_denominator(Var::Frequency, 100_000_000);
+        for var in [Var::StartTime, Var::Duration, Var::MeasureLength] {
+            config.set_denominator(var, 100_000);
         }
+        config
+    }
+}
+
+impl ApproximationConfig {
+    /// Denominator used for a variable
+    pub fn denominator(&self, var: Var) -> u32 {
+        self.denominators[var as usize]
+    }
+
+    /// Change the denominator used for a variable (0 is treated as 1)
+    pub fn set_denominator(&mut self, var: Var, denominator: u32) {
+        self.denominators[var as usize] = denominator.max(1);
     }
+
+    /// FractionData for a variable's value
+    pub fn data(&self, var: Var, v: &Value) -> FractionData {
+        FractionData::from_value_with(v, self.denominator(var))
+    }
+
+    /// Change the denominator of a variable given by name ("frequency", ...)
+    pub fn set_by_name(&mut self, variable: &str, denominator: u32) -> Result<(), String> {
+        let var = Var::from_name(variable).ok_or_else(|| format!("Unknown variable: {}", variable))?;
+        self.set_denominator(var, denominator);
+        Ok(())
+    }
+}
+
+/// Symbolic data for a value whose parts all fit the u32 fields of
+/// SymbolicPowerData; larger values keep only their f64 approximation
+fn symbolic_data(sp: &SymbolicPower) -> Option<SymbolicPowerData> {
+    let fits = sp.coefficient.fits_u32() && sp.powers.iter().all(|p| p.exponent.fits_u32());
+    fits.then(|| SymbolicPowerData::from_symbolic(sp))
 }
 
 impl EvaluatedNote {
+    /// Check whether a property holds an irrational or symbolic value
+    pub fn is_corrupted(&self, var: Var) -> bool {
+        self.corruption_flags & corruption_flag_for_var(var as u8) != 0
+    }
+
+    /// Mark a property as holding an irrational or symbolic value
+    pub fn set_corrupted(&mut self, var: Var) {
+        self.corruption_flags |= corruption_flag_for_var(var as u8);
+    }
+
+    /// Bitmask, laid out like `corruption_flags`, of the properties whose
+    /// values differ from `previous` (see `FractionData::same_value`)
+    ///
+    /// Every property that is set counts as changed when there is no
+    /// previous note, and so does one whose corruption flag changed.
+    /// `CHANGED_CUSTOM` stands for every custom variable and
+    /// `CHANGED_INSTRUMENT` for the instrument.
+    pub fn changed_vars(&self, previous: Option<&EvaluatedNote>, epsilon: f64) -> u16 {
+        let mut mask = 0;
+        let old_flags = previous.map_or(0, |note| note.corruption_flags);
+        for var in (0..6).filter_map(Var::from_byte) {
+            let flag = corruption_flag_for_var(var as u8);
+            let old = previous.and_then(|note| note.get_var(var));
+            let same = match (old, self.get_var(var)) {
+                (None, None) => true,
+                (Some(old), Some(new)) => old.same_value(new, epsilon),
+                _ => false,
+            };
+            if !same || (old_flags ^ self.corruption_flags) & flag != 0 {
+                mask |= flag;
+            }
+        }
+        let no_custom = HashMap::new();
+        let old_custom = previous.map_or(&no_custom, |note| &note.custom);
+        let custom_same = old_custom.len() == self.custom.len()
+            && self.custom.iter().all(|(index, new)| old_custom.get(index).is_some_and(|old| old.same_value(new, epsilon)))
+            && previous.map_or(0, |note| note.custom_corruption_flags) == self.custom_corruption_flags;
+        if !custom_same {
+            mask |= CHANGED_CUSTOM;
+        }
+        if previous.and_then(|note| note.instrument) != self.instrument {
+            mask |= CHANGED_INSTRUMENT;
+        }
+        mask
+    }
+
+    /// Check whether a custom variable holds an irrational or symbolic value
+    pub fn is_custom_corrupted(&self, index: u8) -> bool {
+        self.custom_corruption_flags & custom_flag_for_var(index) != 0
+    }
+
+    /// A property by variable index, built-in or custom
+    pub fn value_at(&self, index: u8) -> Option<&FractionData> {
+        match Var::from_byte(index) {
+            Some(var) => self.get_var(var),
+            None => self.custom.get(&index),
+        }
+    }
+
     pub fn get_var(&self, var: Var) -> Option<&FractionData> {
         match var {
             Var::StartTime => self.start_time.as_ref(),
@@ -208,6 +946,13 @@ impl EvaluatedNote {
     }
 }
 
+/// Bit of `EvaluatedNote::changed_vars` set when any custom variable
+/// changed, appeared or went away
+pub const CHANGED_CUSTOM: u16 = 1 << 15;
+
+/// Bit of `EvaluatedNote::changed_vars` set when the instrument changed
+pub const CHANGED_INSTRUMENT: u16 = 1 << 14;
+
 /// Stack-based evaluator for binary expressions
 ///
 /// Now supports both rational (Fraction) and irrational (f64) values via the Value type.
@@ -218,6 +963,22 @@ pub struct Evaluator {
     stack: Vec<Value>,
     /// Maximum stack size (for safety)
     max_stack_size: usize,
+    /// What the last evaluation observed besides its result
+    last_run: RunInfo,
+    /// Reject any instruction that produces a non-rational value
+    strict: bool,
+    /// Handling of NaN and infinite results
+    non_finite: NonFinitePolicy,
+    /// n/d approximation of non-rational note properties
+    approximation: ApproximationConfig,
+    /// Track error bounds of irrational results (see `Value::track_error`)
+    track_errors: bool,
+    /// Fallback values of missing references
+    defaults: VariableDefaults,
+    /// Instructions one evaluation may execute
+    max_instructions: u32,
+    /// Custom variables that fall back to the base note's value
+    inherited_custom: u32,
 }
 
 #[wasm_bindgen]
@@ -228,9 +989,97 @@ impl Evaluator {
         Evaluator {
             stack: Vec::with_capacity(32),
             max_stack_size: 1024,
+            last_run: RunInfo::default(),
+            strict: false,
+            non_finite: NonFinitePolicy::default(),
+            approximation: ApproximationConfig::default(),
+            track_errors: false,
+            defaults: VariableDefaults::default(),
+            max_instructions: DEFAULT_MAX_INSTRUCTIONS,
+            inherited_custom: 0,
         }
     }
 
+    /// Whether strict-rational mode is on (see `set_strict`)
+    #[wasm_bindgen(getter)]
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Turn strict-rational mode on or off
+    ///
+    /// In strict mode `evaluate` fails at the first instruction that
+    /// produces an irrational or symbolic value, naming the opcode and pc,
+    /// and `evaluate_note` leaves such properties unset.
+    #[wasm_bindgen(js_name = setStrict)]
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Choose what happens when an instruction produces NaN or an infinity:
+    /// "zero" (the default) replaces it with 0, "error" fails the evaluation
+    #[wasm_bindgen(js_name = setNonFinitePolicy)]
+    pub fn set_non_finite_policy(&mut self, policy: &str) -> Result<(), JsValue> {
+        self.non_finite = policy.parse().map_err(|e: String| JsValue::from_str(&e))?;
+        Ok(())
+    }
+
+    /// Set the denominator `evaluateNote` uses to approximate irrational and
+    /// symbolic values of a variable ("frequency", "startTime", ...) as n/d
+    /// (see `ApproximationConfig`)
+    #[wasm_bindgen(js_name = setApproximationDenominator)]
+    pub fn set_approximation_denominator(&mut self, variable: &str, denominator: u32) -> Result<(), JsValue> {
+        self.approximation.set_by_name(variable, denominator).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Override the values missing references fall back to, e.g.
+    /// `{ frequency: { n: 432, d: 1 }, tempo: { n: 120, d: 1 } }`; variables
+    /// left out keep their current default (see `VariableDefaults`)
+    #[wasm_bindgen(js_name = setDefaults)]
+    pub fn set_defaults_js(&mut self, defaults: JsValue) -> Result<(), JsValue> {
+        let data = defaults_from_js(defaults)?;
+        self.set_defaults(&data).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Every variable's fallback value, as `{ startTime: { s, n, d }, ... }`
+    #[wasm_bindgen(js_name = getDefaults)]
+    pub fn get_defaults_js(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.defaults.data()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Choose whether a custom variable a note does not set reads the base
+    /// note's value (see `set_custom_inherited`)
+    #[wasm_bindgen(js_name = setCustomVariableInherited)]
+    pub fn set_custom_variable_inherited_js(&mut self, index: u8, inherited: bool) -> Result<(), JsValue> {
+        set_inherited_flag(&mut self.inherited_custom, index, inherited).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Turn error-bound tracking on or off
+    ///
+    /// When on, every irrational result carries a bound on its distance
+    /// from the exact answer, serialized as the `err` field.
+    #[wasm_bindgen(js_name = setErrorTracking)]
+    pub fn set_error_tracking(&mut self, track: bool) {
+        self.track_errors = track;
+    }
+
+    /// Limit the instructions one `evaluate` may execute, counting those
+    /// run inside macros (default 1,000,000)
+    ///
+    /// An evaluation that reaches the limit fails with
+    /// `EvalError::FuelExhausted`, so corrupted or runaway bytecode cannot
+    /// hang the caller.
+    #[wasm_bindgen(js_name = setMaxInstructions)]
+    pub fn set_max_instructions(&mut self, max: u32) {
+        self.max_instructions = max;
+    }
+
+    /// Instructions executed by the last evaluation
+    #[wasm_bindgen(getter, js_name = instructionsExecuted)]
+    pub fn instructions_executed(&self) -> u32 {
+        self.last_run.executed
+    }
+
     /// Get current stack size (for debugging)
     #[wasm_bindgen(getter, js_name = stackSize)]
     pub fn stack_size(&self) -> usize {
@@ -245,44 +1094,26 @@ impl Default for Evaluator {
 }
 
 impl Evaluator {
-    /// Push a value onto the stack
-    fn push(&mut self, value: Value) -> Result<(), String> {
-        if self.stack.len() >= self.max_stack_size {
-            return Err("Stack overflow in evaluator".to_string());
-        }
-        self.stack.push(value);
-        Ok(())
+    /// Sources of non-rational values in the last evaluation
+    fn corruption_sources(&self, var: Var) -> Vec<CorruptionSource> {
+        self.last_run.corruption_sources(var)
     }
 
-    /// Pop a value from the stack
-    fn pop(&mut self) -> Result<Value, String> {
-        self.stack
-            .pop()
-            .ok_or_else(|| "Stack underflow in evaluator".to_string())
+    /// Override the values missing references fall back to (see
+    /// `VariableDefaults::apply`)
+    pub fn set_defaults(&mut self, defaults: &DefaultsData) -> Result<(), String> {
+        self.defaults.apply(defaults)
     }
 
-    /// Peek at the top of the stack
-    fn peek(&self) -> Result<&Value, String> {
-        self.stack
-            .last()
-            .ok_or_else(|| "Stack empty in evaluator".to_string())
-    }
-
-    /// Clear the stack
-    fn clear_stack(&mut self) {
-        self.stack.clear();
-    }
-
-    /// Get a default value for a variable (always rational)
-    fn default_value(var: Var) -> Value {
-        Value::Rational(match var {
-            Var::StartTime => Fraction::new(0, 1),
-            Var::Duration => Fraction::new(1, 1),
-            Var::Frequency => Fraction::new(440, 1),
-            Var::Tempo => Fraction::new(60, 1),
-            Var::BeatsPerMeasure => Fraction::new(4, 1),
-            Var::MeasureLength => Fraction::new(4, 1),
-        })
+    /// Choose whether a custom variable a note does not set reads the base
+    /// note's value rather than 0
+    pub fn set_custom_inherited(&mut self, index: u8, inherited: bool) -> Result<(), String> {
+        set_inherited_flag(&mut self.inherited_custom, index, inherited)
+    }
+
+    /// Values missing references fall back to
+    pub fn defaults(&self) -> &VariableDefaults {
+        &self.defaults
     }
 
     /// Evaluate a binary expression
@@ -299,210 +1130,78 @@ impl Evaluator {
         bytecode: &[u8],
         length: usize,
         eval_cache: &HashMap<u32, EvaluatedNote>,
-    ) -> Result<Value, String> {
-        if length == 0 {
-            return Ok(Value::rational(0, 1));
-        }
+    ) -> Result<Value, EvalError> {
+        self.evaluate_with_instruments(bytecode, length, eval_cache, None)
+    }
 
-        self.clear_stack();
-        let mut pc = 0;
+    /// Evaluate a binary expression, resolving FIND_INSTRUMENT through
+    /// `instruments` (note id -> instrument id) when given and through the
+    /// `instrument` of cached notes otherwise
+    ///
+    /// Notes without an instrument fall back to the base note's, then 0.
+    pub fn evaluate_with_instruments(
+        &mut self,
+        bytecode: &[u8],
+        length: usize,
+        eval_cache: &HashMap<u32, EvaluatedNote>,
+        instruments: Option<&HashMap<u32, u32>>,
+    ) -> Result<Value, EvalError> {
+        self.evaluate_with(bytecode, length, eval_cache, instruments, &mut NoTrace)
+    }
 
-        while pc < length {
-            let op_byte = bytecode[pc];
-            pc += 1;
-
-            let op = Op::from_byte(op_byte)
-                .ok_or_else(|| format!("Unknown opcode: 0x{:02x} at pc={}", op_byte, pc - 1))?;
-
-            match op {
-                Op::LoadConst => {
-                    if pc + 8 > length {
-                        return Err("Unexpected end of bytecode in LOAD_CONST".to_string());
-                    }
-                    let num = read_i32(bytecode, pc);
-                    pc += 4;
-                    let den = read_i32(bytecode, pc);
-                    pc += 4;
-                    self.push(Value::rational(num, den))?;
-                }
-
-                Op::LoadConstBig => {
-                    // Read signed numerator (variable length)
-                    let (num, num_bytes) = read_big_int_signed(bytecode, pc)
-                        .map_err(|e| format!("Error reading big numerator: {}", e))?;
-                    pc += num_bytes;
-
-                    // Read unsigned denominator (variable length)
-                    let (den, den_bytes) = read_big_int_unsigned(bytecode, pc)
-                        .map_err(|e| format!("Error reading big denominator: {}", e))?;
-                    pc += den_bytes;
-
-                    // Create Fraction from BigInts
-                    let frac = Fraction::from_big_ints(num, den);
-                    self.push(Value::Rational(frac))?;
-                }
-
-                Op::LoadRef => {
-                    if pc + 3 > length {
-                        return Err("Unexpected end of bytecode in LOAD_REF".to_string());
-                    }
-                    let note_id = read_u16(bytecode, pc) as u32;
-                    pc += 2;
-                    let var_idx = bytecode[pc];
-                    pc += 1;
-
-                    let var = Var::from_byte(var_idx)
-                        .ok_or_else(|| format!("Invalid variable index: {}", var_idx))?;
-
-                    // Look up in evaluation cache (preserves corruption status)
-                    let value = eval_cache
-                        .get(&note_id)
-                        .and_then(|note| note.get_var(var))
-                        .map(|fd| fd.to_value());
-
-                    // For inheritable properties, fall back to base note
-                    let value = value.or_else(|| {
-                        if matches!(var, Var::Tempo | Var::BeatsPerMeasure | Var::MeasureLength) {
-                            eval_cache
-                                .get(&0)
-                                .and_then(|note| note.get_var(var))
-                                .map(|fd| fd.to_value())
-                        } else {
-                            None
-                        }
-                    });
-
-                    let value = value.unwrap_or_else(|| Self::default_value(var));
-                    self.push(value)?;
-                }
-
-                Op::LoadBase => {
-                    if pc + 1 > length {
-                        return Err("Unexpected end of bytecode in LOAD_BASE".to_string());
-                    }
-                    let var_idx = bytecode[pc];
-                    pc += 1;
-
-                    let var = Var::from_byte(var_idx)
-                        .ok_or_else(|| format!("Invalid variable index: {}", var_idx))?;
-
-                    // Look up base note (ID 0)
-                    let value = eval_cache
-                        .get(&0)
-                        .and_then(|note| note.get_var(var))
-                        .map(|fd| fd.to_value())
-                        .unwrap_or_else(|| Self::default_value(var));
-
-                    self.push(value)?;
-                }
-
-                Op::Add => {
-                    let b = self.pop()?;
-                    let a = self.pop()?;
-                    self.push(a.add(&b))?;
-                }
-
-                Op::Sub => {
-                    let b = self.pop()?;
-                    let a = self.pop()?;
-                    self.push(a.sub(&b))?;
-                }
-
-                Op::Mul => {
-                    let b = self.pop()?;
-                    let a = self.pop()?;
-                    self.push(a.mul(&b))?;
-                }
-
-                Op::Div => {
-                    let b = self.pop()?;
-                    let a = self.pop()?;
-                    self.push(a.div(&b))?;
-                }
-
-                Op::Neg => {
-                    let a = self.pop()?;
-                    self.push(a.neg())?;
-                }
-
-                Op::Pow => {
-                    // NEW: Power operation for TET support
-                    // May produce irrational result (corruption)
-                    let exp = self.pop()?;
-                    let base = self.pop()?;
-                    self.push(base.pow(&exp))?;
-                }
-
-                Op::FindTempo => {
-                    // Pop note reference (not used in current impl, uses base note)
-                    let _ = self.pop()?;
-
-                    // Get tempo from base note
-                    let tempo = eval_cache
-                        .get(&0)
-                        .and_then(|note| note.tempo.as_ref())
-                        .map(|fd| fd.to_value())
-                        .unwrap_or_else(|| Value::rational(60, 1));
-
-                    self.push(tempo)?;
-                }
-
-                Op::FindMeasure => {
-                    // Pop note reference - the note ID whose measure length we want
-                    let note_ref = self.pop()?;
-                    let note_id = note_ref.to_f64().round() as u32;
-
-                    // Get beatsPerMeasure - try note first, then base note
-                    let beats_per_measure = eval_cache
-                        .get(&note_id)
-                        .and_then(|note| note.beats_per_measure.as_ref())
-                        .or_else(|| eval_cache.get(&0).and_then(|note| note.beats_per_measure.as_ref()))
-                        .map(|fd| fd.to_value())
-                        .unwrap_or_else(|| Value::rational(4, 1));
-
-                    // Get tempo - try note first, then base note
-                    let tempo = eval_cache
-                        .get(&note_id)
-                        .and_then(|note| note.tempo.as_ref())
-                        .or_else(|| eval_cache.get(&0).and_then(|note| note.tempo.as_ref()))
-                        .map(|fd| fd.to_value())
-                        .unwrap_or_else(|| Value::rational(60, 1));
-
-                    // Compute measureLength = beatsPerMeasure / tempo * 60
-                    let sixty = Value::rational(60, 1);
-                    let measure = beats_per_measure.mul(&sixty).div(&tempo);
-
-                    self.push(measure)?;
-                }
-
-                Op::FindInstrument => {
-                    // Not fully implemented - return default
-                    let _ = self.pop()?;
-                    self.push(Value::rational(0, 1))?;
-                }
-
-                Op::Dup => {
-                    let top = self.peek()?.clone();
-                    self.push(top)?;
-                }
-
-                Op::Swap => {
-                    let a = self.pop()?;
-                    let b = self.pop()?;
-                    self.push(a)?;
-                    self.push(b)?;
-                }
-            }
-        }
+    /// `evaluate_with_instruments`, also returning every executed
+    /// instruction with the stack it left
+    ///
+    /// On failure the trace ends at the last instruction that succeeded.
+    pub fn evaluate_traced(
+        &mut self,
+        bytecode: &[u8],
+        length: usize,
+        eval_cache: &HashMap<u32, EvaluatedNote>,
+        instruments: Option<&HashMap<u32, u32>>,
+    ) -> (Result<Value, EvalError>, Vec<TraceStep>) {
+        let mut trace = Vec::new();
+        let result = self.evaluate_with(bytecode, length, eval_cache, instruments, &mut trace);
+        (result, trace)
+    }
 
-        if self.stack.len() != 1 {
-            // Warning but continue - return top of stack or zero
-            if self.stack.is_empty() {
-                return Ok(Value::rational(0, 1));
-            }
-        }
+    /// Evaluate several expressions against one cache, one result per
+    /// expression
+    ///
+    /// A failing expression does not stop the batch, and every evaluation
+    /// reuses the same stack.
+    pub fn evaluate_batch<'a>(
+        &mut self,
+        expressions: impl IntoIterator<Item = (&'a [u8], usize)>,
+        eval_cache: &HashMap<u32, EvaluatedNote>,
+        instruments: Option<&HashMap<u32, u32>>,
+    ) -> Vec<Result<Value, EvalError>> {
+        expressions
+            .into_iter()
+            .map(|(bytecode, length)| self.evaluate_with_instruments(bytecode, length, eval_cache, instruments))
+            .collect()
+    }
 
-        self.pop()
+    fn evaluate_with<T: Tracer>(
+        &mut self,
+        bytecode: &[u8],
+        length: usize,
+        eval_cache: &HashMap<u32, EvaluatedNote>,
+        instruments: Option<&HashMap<u32, u32>>,
+        tracer: &mut T,
+    ) -> Result<Value, EvalError> {
+        let cache = BorrowedCache { notes: eval_cache, instruments };
+        let config = EvalConfig {
+            max_stack_size: self.max_stack_size,
+            strict: self.strict,
+            non_finite: self.non_finite,
+            track_errors: self.track_errors,
+            defaults: &self.defaults,
+            max_instructions: self.max_instructions,
+            inherited_custom: self.inherited_custom,
+            in_progress: None,
+        };
+        run(&mut self.stack, bytecode, length, &cache, &config, &mut self.last_run, tracer)
     }
 
     /// Evaluate and return as Fraction (for backward compatibility)
@@ -512,11 +1211,11 @@ impl Evaluator {
         bytecode: &[u8],
         length: usize,
         eval_cache: &HashMap<u32, EvaluatedNote>,
-    ) -> Result<Fraction, String> {
+    ) -> Result<Fraction, EvalError> {
         let value = self.evaluate(bytecode, length, eval_cache)?;
         Ok(match value {
             Value::Rational(f) => f,
-            Value::Irrational(v) => Fraction::from_f64(v),
+            Value::Irrational { value, .. } => Fraction::from_f64(value),
             Value::Symbolic(sp) => {
                 // If symbolic is actually rational, return exact value
                 if let Some(rational) = sp.to_rational_fraction() {
@@ -536,7 +1235,7 @@ impl Evaluator {
         eval_cache: &HashMap<u32, EvaluatedNote>,
     ) -> EvaluatedNote {
         let mut result = EvaluatedNote::default();
-        let mut corruption_flags: u8 = 0;
+        let mut corruption_flags: u16 = 0;
 
         // Evaluate in dependency order
         // 1. Variables that don't typically depend on others
@@ -544,8 +1243,9 @@ impl Evaluator {
             if let Ok(val) = self.evaluate(bytecode, *len, eval_cache) {
                 if val.is_corrupted() {
                     corruption_flags |= corruption_flag_for_var(Var::Tempo as u8);
+                    result.corruption_sources.extend(self.corruption_sources(Var::Tempo));
                 }
-                result.tempo = Some(FractionData::from_value(&val));
+                result.tempo = Some(self.approximation.data(Var::Tempo, &val));
             }
         }
 
@@ -553,8 +1253,9 @@ impl Evaluator {
             if let Ok(val) = self.evaluate(bytecode, *len, eval_cache) {
                 if val.is_corrupted() {
                     corruption_flags |= corruption_flag_for_var(Var::BeatsPerMeasure as u8);
+                    result.corruption_sources.extend(self.corruption_sources(Var::BeatsPerMeasure));
                 }
-                result.beats_per_measure = Some(FractionData::from_value(&val));
+                result.beats_per_measure = Some(self.approximation.data(Var::BeatsPerMeasure, &val));
             }
         }
 
@@ -562,8 +1263,9 @@ impl Evaluator {
             if let Ok(val) = self.evaluate(bytecode, *len, eval_cache) {
                 if val.is_corrupted() {
                     corruption_flags |= corruption_flag_for_var(Var::Frequency as u8);
+                    result.corruption_sources.extend(self.corruption_sources(Var::Frequency));
                 }
-                result.frequency = Some(FractionData::from_value(&val));
+                result.frequency = Some(self.approximation.data(Var::Frequency, &val));
             }
         }
 
@@ -576,8 +1278,9 @@ impl Evaluator {
             if let Ok(val) = self.evaluate(bytecode, *len, &working_cache) {
                 if val.is_corrupted() {
                     corruption_flags |= corruption_flag_for_var(Var::MeasureLength as u8);
+                    result.corruption_sources.extend(self.corruption_sources(Var::MeasureLength));
                 }
-                result.measure_length = Some(FractionData::from_value(&val));
+                result.measure_length = Some(self.approximation.data(Var::MeasureLength, &val));
             }
         }
 
@@ -589,8 +1292,9 @@ impl Evaluator {
             if let Ok(val) = self.evaluate(bytecode, *len, &working_cache) {
                 if val.is_corrupted() {
                     corruption_flags |= corruption_flag_for_var(Var::StartTime as u8);
+                    result.corruption_sources.extend(self.corruption_sources(Var::StartTime));
                 }
-                result.start_time = Some(FractionData::from_value(&val));
+                result.start_time = Some(self.approximation.data(Var::StartTime, &val));
             }
         }
 
@@ -598,8 +1302,9 @@ impl Evaluator {
             if let Ok(val) = self.evaluate(bytecode, *len, &working_cache) {
                 if val.is_corrupted() {
                     corruption_flags |= corruption_flag_for_var(Var::Duration as u8);
+                    result.corruption_sources.extend(self.corruption_sources(Var::Duration));
                 }
-                result.duration = Some(FractionData::from_value(&val));
+                result.duration = Some(self.approximation.data(Var::Duration, &val));
             }
         }
 
@@ -608,7 +1313,17 @@ impl Evaluator {
     }
 }
 
+/// Degree of the `Op::Root` at `pc`, which must be a positive integer
+fn root_degree(n: &Value, pc: usize) -> Result<u32, EvalError> {
+    n.as_fraction()
+        .and_then(|f| f.to_i64())
+        .and_then(|n| u32::try_from(n).ok())
+        .filter(|&n| n > 0)
+        .ok_or_else(|| EvalError::InvalidRootDegree { pc, degree: n.to_string() })
+}
+
 /// Container for note expressions (bytecode + length for each variable)
+#[derive(Default)]
 pub struct NoteExpressions {
     pub start_time: Option<(Vec<u8>, usize)>,
     pub duration: Option<(Vec<u8>, usize)>,
@@ -618,19 +1333,6 @@ pub struct NoteExpressions {
     pub measure_length: Option<(Vec<u8>, usize)>,
 }
 
-impl Default for NoteExpressions {
-    fn default() -> Self {
-        NoteExpressions {
-            start_time: None,
-            duration: None,
-            frequency: None,
-            tempo: None,
-            beats_per_measure: None,
-            measure_length: None,
-        }
-    }
-}
-
 // WASM bindings for JavaScript interop
 
 #[wasm_bindgen]
@@ -640,7 +1342,8 @@ impl Evaluator {
     /// # Arguments
     /// * `bytecode` - Uint8Array of bytecode
     /// * `length` - Number of valid bytes
-    /// * `eval_cache` - JavaScript object mapping noteId to evaluated values
+    /// * `eval_cache` - Map (or object) from noteId to evaluated values
+    /// * `instruments` - Optional object mapping noteId to instrument id
     ///
     /// # Returns
     /// Object with { s, n, d } representing the fraction
@@ -650,34 +1353,68 @@ impl Evaluator {
         bytecode: &[u8],
         length: usize,
         eval_cache: JsValue,
+        instruments: JsValue,
     ) -> Result<JsValue, JsValue> {
-        // Deserialize the cache from JavaScript
-        // JS object keys are always strings, so deserialize as HashMap<String, ...>
-        // and then convert keys to u32
-        let string_cache: HashMap<String, EvaluatedNote> =
-            serde_wasm_bindgen::from_value(eval_cache).unwrap_or_default();
-
-        // Convert string keys to u32
-        let cache: HashMap<u32, EvaluatedNote> = string_cache
-            .into_iter()
-            .filter_map(|(k, v)| k.parse::<u32>().ok().map(|id| (id, v)))
-            .collect();
+        let cache = cache_from_js(eval_cache)?;
+        let instruments = instruments_from_js(instruments)?;
 
         // Evaluate
         let result = self
-            .evaluate(bytecode, length, &cache)
-            .map_err(|e| JsValue::from_str(&e))?;
+            .evaluate_with_instruments(bytecode, length, &cache, instruments.as_ref())
+            .map_err(|e| e.to_js())?;
 
         // Return as serialized object (now supports both rational and irrational)
         let data = FractionData::from_value(&result);
         serde_wasm_bindgen::to_value(&data).map_err(|e| JsValue::from_str(&e.to_string()))
     }
 
+    /// Evaluate a single expression like `evaluateExpression`, returning
+    /// `{ result, trace }` (or `{ error, trace }`) where `trace` lists every
+    /// executed instruction as `{ pc, op, operands, stack }`
+    #[wasm_bindgen(js_name = evaluateExpressionTraced)]
+    pub fn evaluate_expression_traced_js(
+        &mut self,
+        bytecode: &[u8],
+        length: usize,
+        eval_cache: JsValue,
+        instruments: JsValue,
+    ) -> Result<JsValue, JsValue> {
+        let cache = cache_from_js(eval_cache)?;
+        let instruments = instruments_from_js(instruments)?;
+        let (result, trace) = self.evaluate_traced(bytecode, length, &cache, instruments.as_ref());
+        serde_wasm_bindgen::to_value(&TracedEvaluation::new(result, trace, None))
+            .map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Evaluate an array of `{ bytecode, length }` expressions against one
+    /// cache, which is deserialized once for the whole batch
+    ///
+    /// # Returns
+    /// An array with, per expression, either `{ s, n, d }` or an error
+    /// object `{ code, message, pc, op? }`
+    #[wasm_bindgen(js_name = evaluateBatch)]
+    pub fn evaluate_batch_js(&mut self, expressions: JsValue, eval_cache: JsValue) -> Result<JsValue, JsValue> {
+        let exprs: Vec<JsExpression> = serde_wasm_bindgen::from_value(expressions)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse expressions: {}", e)))?;
+        let cache = cache_from_js(eval_cache)?;
+
+        let results = self.evaluate_batch(exprs.iter().map(|e| (e.bytecode.as_slice(), e.length)), &cache, None);
+        let array = js_sys::Array::new_with_length(results.len() as u32);
+        for (i, result) in results.iter().enumerate() {
+            let entry = match result {
+                Ok(value) => serde_wasm_bindgen::to_value(&FractionData::from_value(value)),
+                Err(e) => serde_wasm_bindgen::to_value(&e.to_data(None)),
+            };
+            array.set(i as u32, entry.map_err(|e| JsValue::from_str(&e.to_string()))?);
+        }
+        Ok(array.into())
+    }
+
     /// Evaluate all expressions for a note from JavaScript
     ///
     /// # Arguments
     /// * `expressions` - Object with expression bytecodes for each variable
-    /// * `eval_cache` - JavaScript object mapping noteId to evaluated values
+    /// * `eval_cache` - Map (or object) from noteId to evaluated values
     ///
     /// # Returns
     /// Object with evaluated values for each variable
@@ -687,16 +1424,7 @@ impl Evaluator {
         expressions: JsValue,
         eval_cache: JsValue,
     ) -> Result<JsValue, JsValue> {
-        // Deserialize inputs
-        // JS object keys are always strings, so deserialize as HashMap<String, ...>
-        let string_cache: HashMap<String, EvaluatedNote> =
-            serde_wasm_bindgen::from_value(eval_cache).unwrap_or_default();
-
-        // Convert string keys to u32
-        let cache: HashMap<u32, EvaluatedNote> = string_cache
-            .into_iter()
-            .filter_map(|(k, v)| k.parse::<u32>().ok().map(|id| (id, v)))
-            .collect();
+        let cache = cache_from_js(eval_cache)?;
 
         // Parse expressions from JS
         let exprs: JsExpressions =
@@ -719,478 +1447,391 @@ impl Evaluator {
     }
 }
 
+/// One entry of a cache from JavaScript: its key as text, and the note id
+/// and note or why the entry was rejected
+type CacheEntry = (String, Result<(u32, EvaluatedNote), String>);
+
+/// The entries of a cache from JavaScript: a Map from numeric note id or
+/// an object keyed by note id
+///
+/// Entries are read one by one, so Map keys are never turned into strings
+/// and back, nested Maps (as `customVars`) are read like objects, and a
+/// bad entry is reported against its key without failing the others.
+fn cache_entries_from_js(eval_cache: &JsValue) -> Result<Vec<CacheEntry>, JsValue> {
+    let note = |value: JsValue| serde_wasm_bindgen::from_value::<EvaluatedNote>(value).map_err(|e| e.to_string());
+    let mut entries = Vec::new();
+    if let Some(map) = eval_cache.dyn_ref::<js_sys::Map>() {
+        entries.reserve(map.size() as usize);
+        map.for_each(&mut |value, key| {
+            let id = key.as_f64().filter(|k| k.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(k));
+            let entry = match id {
+                Some(id) => note(value).map(|note| (id as u32, note)),
+                None => Err("Key is not a note id".to_string()),
+            };
+            entries.push((key.as_f64().map_or_else(|| format!("{:?}", key), format_f64_js), entry));
+        });
+    } else if eval_cache.is_object() {
+        for pair in js_sys::Object::entries(eval_cache.unchecked_ref()).iter() {
+            let pair: js_sys::Array = pair.unchecked_into();
+            let key = pair.get(0).as_string().unwrap_or_default();
+            let entry = match key.parse::<u32>() {
+                Ok(id) => note(pair.get(1)).map(|note| (id, note)),
+                Err(_) => Err("Key is not a note id".to_string()),
+            };
+            entries.push((key, entry));
+        }
+    } else {
+        return Err(JsValue::from_str("Failed to parse cache: expected a Map or an object"));
+    }
+    Ok(entries)
+}
+
+/// An evaluation cache from JavaScript (see `cache_entries_from_js`), or
+/// undefined/null for none
+///
+/// Any bad entry is an error naming its key.
+fn cache_from_js(eval_cache: JsValue) -> Result<HashMap<u32, EvaluatedNote>, JsValue> {
+    #[cfg(test)]
+    CACHE_FROM_JS_CALLS.with(|calls| calls.set(calls.get() + 1));
+    if eval_cache.is_undefined() || eval_cache.is_null() {
+        return Ok(HashMap::new());
+    }
+    cache_entries_from_js(&eval_cache)?
+        .into_iter()
+        .map(|(key, entry)| entry.map_err(|e| JsValue::from_str(&format!("Invalid cache entry {}: {}", key, e))))
+        .collect()
+}
+
+/// A `setDefaults` object from JavaScript
+fn defaults_from_js(defaults: JsValue) -> Result<DefaultsData, JsValue> {
+    serde_wasm_bindgen::from_value(defaults).map_err(|e| JsValue::from_str(&format!("Failed to parse defaults: {}", e)))
+}
+
+/// Set or clear custom variable `index` in a mask of inherited variables
+fn set_inherited_flag(mask: &mut u32, index: u8, inherited: bool) -> Result<(), String> {
+    let flag = custom_flag_for_var(index);
+    if flag == 0 {
+        return Err(format!("Variable {} is not a custom variable", index));
+    }
+    if inherited {
+        *mask |= flag;
+    } else {
+        *mask &= !flag;
+    }
+    Ok(())
+}
+
+/// An optional note id -> instrument id object from JavaScript; keys that
+/// are not note ids are reported, as in `cache_from_js`
+fn instruments_from_js(instruments: JsValue) -> Result<Option<HashMap<u32, u32>>, JsValue> {
+    if instruments.is_undefined() || instruments.is_null() {
+        return Ok(None);
+    }
+    let string_instruments: HashMap<String, u32> = serde_wasm_bindgen::from_value(instruments)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse instruments: {}", e)))?;
+    string_instruments
+        .into_iter()
+        .map(|(key, instrument)| match key.parse::<u32>() {
+            Ok(id) => Ok((id, instrument)),
+            Err(_) => Err(JsValue::from_str(&format!("Invalid instrument entry {}: Key is not a note id", key))),
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
 /// JavaScript expression input format
 #[derive(Deserialize, Default)]
-struct JsExpressions {
+pub(crate) struct JsExpressions {
     #[serde(rename = "startTime")]
-    start_time: Option<JsExpression>,
-    duration: Option<JsExpression>,
-    frequency: Option<JsExpression>,
-    tempo: Option<JsExpression>,
+    pub(crate) start_time: Option<JsExpression>,
+    pub(crate) duration: Option<JsExpression>,
+    pub(crate) frequency: Option<JsExpression>,
+    pub(crate) tempo: Option<JsExpression>,
     #[serde(rename = "beatsPerMeasure")]
-    beats_per_measure: Option<JsExpression>,
+    pub(crate) beats_per_measure: Option<JsExpression>,
     #[serde(rename = "measureLength")]
-    measure_length: Option<JsExpression>,
+    pub(crate) measure_length: Option<JsExpression>,
 }
 
 #[derive(Deserialize)]
-struct JsExpression {
-    bytecode: Vec<u8>,
-    length: usize,
+pub(crate) struct JsExpression {
+    pub(crate) bytecode: Vec<u8>,
+    pub(crate) length: usize,
 }
 
 // ============================================================================
-// PersistentEvaluator - WASM-resident cache for O(N) evaluation
+// Interpreter - the bytecode loop shared by Evaluator and PersistentEvaluator
 // ============================================================================
 
-use std::collections::HashSet;
+/// Evaluated notes as seen by running bytecode
+trait CacheLookup {
+    /// Evaluated value of a note's variable
+    fn value(&self, note_id: u32, var: Var) -> Option<Value>;
 
-/// Bytecode storage for a single note's expressions
-#[derive(Clone, Default)]
-pub struct NoteBytecode {
-    /// Bytecode for each variable type: [startTime, duration, frequency, tempo, beatsPerMeasure, measureLength]
-    pub expressions: [Option<(Vec<u8>, usize)>; 6],
+    /// Evaluated value of a note's custom variable
+    fn custom_value(&self, note_id: u32, index: u8) -> Option<Value>;
+
+    /// Instrument id FIND_INSTRUMENT pushes for a note
+    fn instrument(&self, note_id: u32) -> u32;
+
+    /// Macros CALL_MACRO may run, if there is a table
+    fn macros(&self) -> Option<&MacroTable>;
 }
 
-impl NoteBytecode {
-    pub fn get_expr(&self, var: Var) -> Option<(&[u8], usize)> {
-        let idx = var as usize;
-        self.expressions.get(idx)
-            .and_then(|opt| opt.as_ref())
-            .map(|(bytes, len)| (bytes.as_slice(), *len))
+/// A cache passed in by the caller of `Evaluator`
+struct BorrowedCache<'a> {
+    notes: &'a HashMap<u32, EvaluatedNote>,
+    /// Overrides the `instrument` of cached notes when given
+    instruments: Option<&'a HashMap<u32, u32>>,
+}
+
+impl CacheLookup for BorrowedCache<'_> {
+    fn value(&self, note_id: u32, var: Var) -> Option<Value> {
+        self.notes.get(&note_id).and_then(|note| note.get_var(var)).map(FractionData::to_value)
     }
 
-    pub fn set_expr(&mut self, var: Var, bytecode: Vec<u8>, length: usize) {
-        let idx = var as usize;
-        if idx < 6 {
-            self.expressions[idx] = Some((bytecode, length));
-        }
+    fn custom_value(&self, note_id: u32, index: u8) -> Option<Value> {
+        self.notes.get(&note_id).and_then(|note| note.custom.get(&index)).map(FractionData::to_value)
     }
 
-    pub fn clear_expr(&mut self, var: Var) {
-        let idx = var as usize;
-        if idx < 6 {
-            self.expressions[idx] = None;
-        }
+    /// Try note first, then base note
+    fn instrument(&self, note_id: u32) -> u32 {
+        let lookup = |id: u32| match self.instruments {
+            Some(instruments) => instruments.get(&id).copied(),
+            None => self.notes.get(&id).and_then(|note| note.instrument),
+        };
+        lookup(note_id).or_else(|| lookup(0)).unwrap_or(0)
+    }
+
+    fn macros(&self) -> Option<&MacroTable> {
+        None
     }
 }
 
-/// Persistent evaluator with WASM-resident cache
-///
-/// This evaluator keeps the evaluation cache in WASM memory to avoid
-/// O(N²) serialization overhead when evaluating large modules.
-/// Now supports both rational (Fraction) and irrational (f64) values via the Value type.
-#[wasm_bindgen]
-pub struct PersistentEvaluator {
-    /// Evaluation stack (supports both rational and irrational values)
-    stack: Vec<Value>,
-    /// Maximum stack size (for safety)
+/// Settings of the evaluator running the bytecode
+struct EvalConfig<'a> {
     max_stack_size: usize,
+    /// Fail at the first instruction that produces a non-rational value
+    strict: bool,
+    non_finite: NonFinitePolicy,
+    track_errors: bool,
+    defaults: &'a VariableDefaults,
+    max_instructions: u32,
+    /// Custom variables that fall back to the base note's value (see
+    /// `custom_flag_for_var`)
+    inherited_custom: u32,
+    /// Note variables the bytecode may not read, if checked
+    in_progress: Option<&'a InProgress>,
+}
 
-    /// PERSISTENT CACHE: Lives in WASM memory across calls
-    cache: HashMap<u32, EvaluatedNote>,
+/// Note variables being evaluated, which LOAD_REF and LOAD_BASE may not
+/// read: the one whose expression is running, and any other on the same
+/// reference cycle (see `PersistentEvaluator::reference_cycles`)
+///
+/// Reading one would return whatever the cache held before, so the result
+/// would depend on evaluation order and never settle.
+#[derive(Clone, Default)]
+struct InProgress {
+    /// The note variable being evaluated
+    current: Option<(u32, u8)>,
+    /// Cycle of each note variable on a reference cycle, by number
+    cycles: Arc<HashMap<(u32, u8), usize>>,
+    /// Fail such reads with `EvalError::SelfReference` instead of reading
+    /// the variable's default
+    strict: bool,
+}
 
-    /// Bytecode storage: noteId -> NoteBytecode
-    bytecode_store: HashMap<u32, NoteBytecode>,
+impl InProgress {
+    fn contains(&self, note_id: u32, var: u8) -> bool {
+        let Some(current) = self.current else {
+            return false;
+        };
+        current == (note_id, var)
+            || self.cycles.get(&current).is_some_and(|cycle| self.cycles.get(&(note_id, var)) == Some(cycle))
+    }
+}
 
-    /// Set of dirty note IDs
-    dirty: HashSet<u32>,
+/// What a run observed besides its result
+#[derive(Clone, Copy, Default)]
+struct RunInfo {
+    /// Instructions executed, counting those inside macros
+    executed: u32,
+    /// Opcode and pc of the first non-rational value
+    first_corruption: Option<(Op, usize)>,
+    /// Opcode and pc of the first f64 approximation
+    first_lossy: Option<(Op, usize)>,
+    /// Opcode, pc, note and variable of the first read of a variable being
+    /// evaluated, when the variable's default was read instead
+    self_reference: Option<(Op, usize, u32, u8)>,
+}
 
-    /// Generation counter for cache invalidation tracking
-    generation: u64,
+impl RunInfo {
+    fn corruption_sources(&self, var: Var) -> Vec<CorruptionSource> {
+        CorruptionSource::for_var(var.name(), self.first_corruption, self.first_lossy)
+    }
 }
 
-#[wasm_bindgen]
-impl PersistentEvaluator {
-    /// Create a new persistent evaluator
-    #[wasm_bindgen(constructor)]
-    pub fn new() -> PersistentEvaluator {
-        PersistentEvaluator {
-            stack: Vec::with_capacity(32),
-            max_stack_size: 1024,
-            cache: HashMap::new(),
-            bytecode_store: HashMap::new(),
-            dirty: HashSet::new(),
-            generation: 0,
-        }
+/// Evaluate bytecode on `stack`, which is cleared first
+///
+/// Returns the top of the stack, or 0 if the bytecode leaves nothing.
+fn run<C: CacheLookup, T: Tracer>(
+    stack: &mut Vec<Value>,
+    bytecode: &[u8],
+    length: usize,
+    cache: &C,
+    config: &EvalConfig,
+    info: &mut RunInfo,
+    tracer: &mut T,
+) -> Result<Value, EvalError> {
+    *info = RunInfo::default();
+    if length == 0 {
+        return Ok(Value::rational(0, 1));
     }
 
-    // === Cache Management ===
+    stack.clear();
+    let mut interpreter = Interpreter { stack, cache, config, info, tracer, at: (0, Op::LoadConst), fuel: config.max_instructions };
+    let result = interpreter.execute(bytecode, length, 0);
+    interpreter.info.executed = config.max_instructions - interpreter.fuel;
+    result?;
 
-    /// Get cache size
-    #[wasm_bindgen(getter, js_name = cacheSize)]
-    pub fn cache_size(&self) -> usize {
-        self.cache.len()
-    }
-
-    /// Get generation counter
-    #[wasm_bindgen(getter)]
-    pub fn generation(&self) -> u64 {
-        self.generation
+    if interpreter.stack.is_empty() {
+        return Ok(Value::rational(0, 1));
     }
 
-    /// Check if a note is in the cache
-    #[wasm_bindgen(js_name = hasCachedNote)]
-    pub fn has_cached_note(&self, note_id: u32) -> bool {
-        self.cache.contains_key(&note_id)
-    }
+    interpreter.pop()
+}
 
-    /// Mark a note as dirty (needs re-evaluation)
-    #[wasm_bindgen(js_name = markDirty)]
-    pub fn mark_dirty(&mut self, note_id: u32) {
-        self.dirty.insert(note_id);
-    }
+/// State of one `run`
+struct Interpreter<'r, C, T> {
+    stack: &'r mut Vec<Value>,
+    cache: &'r C,
+    config: &'r EvalConfig<'r>,
+    info: &'r mut RunInfo,
+    tracer: &'r mut T,
+    /// Pc and opcode of the instruction being executed, for error reports
+    at: (usize, Op),
+    /// Instructions left
+    fuel: u32,
+}
 
-    /// Mark multiple notes as dirty
-    #[wasm_bindgen(js_name = markDirtyBatch)]
-    pub fn mark_dirty_batch(&mut self, note_ids: &[u32]) {
-        for &id in note_ids {
-            self.dirty.insert(id);
+impl<C: CacheLookup, T: Tracer> Interpreter<'_, C, T> {
+    /// Push a value onto the stack
+    fn push(&mut self, value: Value) -> Result<(), EvalError> {
+        if self.stack.len() >= self.config.max_stack_size {
+            let (pc, op) = self.at;
+            return Err(EvalError::StackOverflow { pc, op, limit: self.config.max_stack_size });
         }
+        self.stack.push(value);
+        Ok(())
     }
 
-    /// Clear all dirty flags
-    #[wasm_bindgen(js_name = clearDirty)]
-    pub fn clear_dirty(&mut self) {
-        self.dirty.clear();
-    }
-
-    /// Invalidate a single note from the cache
-    #[wasm_bindgen(js_name = invalidateNote)]
-    pub fn invalidate_note(&mut self, note_id: u32) {
-        self.cache.remove(&note_id);
-        self.dirty.insert(note_id);
-        self.generation += 1;
-    }
-
-    /// Clear the entire cache and bytecode store
-    /// This must clear bytecode_store because when a module is replaced (e.g., after reorder),
-    /// notes with the same IDs may have different expressions/bytecode.
-    #[wasm_bindgen(js_name = invalidateAll)]
-    pub fn invalidate_all(&mut self) {
-        self.cache.clear();
-        self.dirty.clear();
-        self.bytecode_store.clear();
-        self.generation += 1;
-    }
-
-    /// Remove a note completely (when deleted from module)
-    #[wasm_bindgen(js_name = removeNote)]
-    pub fn remove_note(&mut self, note_id: u32) {
-        self.cache.remove(&note_id);
-        self.bytecode_store.remove(&note_id);
-        self.dirty.remove(&note_id);
-        self.generation += 1;
+    /// Pop a value from the stack
+    fn pop(&mut self) -> Result<Value, EvalError> {
+        let (pc, op) = self.at;
+        self.stack.pop().ok_or(EvalError::StackUnderflow { pc, op })
     }
 
-    // === Bytecode Registration ===
-
-    /// Register bytecode for a single expression
-    #[wasm_bindgen(js_name = registerExpression)]
-    pub fn register_expression(
-        &mut self,
-        note_id: u32,
-        var_index: u8,
-        bytecode: &[u8],
-        length: usize,
-    ) {
-        let entry = self.bytecode_store.entry(note_id).or_default();
-        if let Some(var) = Var::from_byte(var_index) {
-            entry.set_expr(var, bytecode.to_vec(), length);
-        }
+    /// Peek at the top of the stack
+    fn peek(&self) -> Result<&Value, EvalError> {
+        let (pc, op) = self.at;
+        self.stack.last().ok_or(EvalError::StackUnderflow { pc, op })
     }
 
-    /// Register all expressions for a note at once
-    #[wasm_bindgen(js_name = registerNote)]
-    pub fn register_note(&mut self, note_id: u32, expressions: JsValue) -> Result<(), JsValue> {
-        let exprs: JsExpressions = serde_wasm_bindgen::from_value(expressions)
-            .map_err(|e| JsValue::from_str(&format!("Failed to parse expressions: {}", e)))?;
-
-        let entry = self.bytecode_store.entry(note_id).or_default();
-
-        if let Some(e) = exprs.start_time {
-            entry.set_expr(Var::StartTime, e.bytecode, e.length);
-        }
-        if let Some(e) = exprs.duration {
-            entry.set_expr(Var::Duration, e.bytecode, e.length);
-        }
-        if let Some(e) = exprs.frequency {
-            entry.set_expr(Var::Frequency, e.bytecode, e.length);
-        }
-        if let Some(e) = exprs.tempo {
-            entry.set_expr(Var::Tempo, e.bytecode, e.length);
-        }
-        if let Some(e) = exprs.beats_per_measure {
-            entry.set_expr(Var::BeatsPerMeasure, e.bytecode, e.length);
+    /// Value a LOAD_REF or LOAD_BASE pushes for variable `index` of a note:
+    /// its evaluated value (preserving corruption status), the base note's
+    /// for inherited variables, or else the variable's default
+    ///
+    /// Tempo, beats per measure and measure length are always inherited;
+    /// custom variables only when set in `inherited_custom`, and default to 0.
+    /// A variable still being evaluated fails in strict mode and otherwise
+    /// reads as its default (see `InProgress`).
+    fn load_var(&mut self, note_id: u32, index: u8) -> Result<Value, EvalError> {
+        let (pc, op) = self.at;
+        if self.in_progress(note_id, index)? {
+            return match Var::from_byte(index) {
+                Some(var) => Ok(self.config.defaults.value(var)),
+                None if is_custom_var(index) => Ok(Value::rational(0, 1)),
+                None => Err(EvalError::InvalidVar { pc, op, index }),
+            };
         }
-        if let Some(e) = exprs.measure_length {
-            entry.set_expr(Var::MeasureLength, e.bytecode, e.length);
+        if is_custom_var(index) {
+            let inherited = self.config.inherited_custom & custom_flag_for_var(index) != 0;
+            let value = self
+                .cache
+                .custom_value(note_id, index)
+                .or_else(|| inherited.then(|| self.cache.custom_value(0, index)).flatten());
+            return Ok(value.unwrap_or_else(|| Value::rational(0, 1)));
         }
-
-        // Mark as dirty since bytecode changed
-        self.dirty.insert(note_id);
-        Ok(())
-    }
-
-    // === Evaluation ===
-
-    /// Evaluate all dirty notes in topological order
-    /// Returns the number of notes evaluated
-    #[wasm_bindgen(js_name = evaluateDirty)]
-    pub fn evaluate_dirty(&mut self, sorted_ids: &[u32]) -> u32 {
-        let mut count = 0;
-
-        for &note_id in sorted_ids {
-            if self.evaluate_note_internal(note_id) {
-                count += 1;
+        let var = Var::from_byte(index).ok_or(EvalError::InvalidVar { pc, op, index })?;
+        let value = self.cache.value(note_id, var).or_else(|| {
+            if matches!(var, Var::Tempo | Var::BeatsPerMeasure | Var::MeasureLength) {
+                self.cache.value(0, var)
+            } else {
+                None
             }
-        }
-
-        self.dirty.clear();
-        self.generation += 1;
-        count
+        });
+        Ok(value.unwrap_or_else(|| self.config.defaults.value(var)))
     }
 
-    /// Evaluate a single note using internal cache
-    /// Tracks corruption flags for each property
-    #[wasm_bindgen(js_name = evaluateNoteInternal)]
-    pub fn evaluate_note_internal(&mut self, note_id: u32) -> bool {
-        // Get bytecode for this note
-        let bytecode = match self.bytecode_store.get(&note_id) {
-            Some(bc) => bc.clone(),
-            None => return false,
+    /// Whether variable `index` of a note is still being evaluated: an error
+    /// in strict mode, otherwise noted as a self-reference (see `InProgress`)
+    fn in_progress(&mut self, note_id: u32, index: u8) -> Result<bool, EvalError> {
+        let Some(in_progress) = self.config.in_progress.filter(|p| p.contains(note_id, index)) else {
+            return Ok(false);
         };
-
-        let mut result = EvaluatedNote::default();
-        let mut corruption_flags: u8 = 0;
-
-        // Evaluate in dependency order
-        // 1. Variables that don't typically depend on others
-        if let Some((bc, len)) = bytecode.get_expr(Var::Tempo) {
-            if let Ok(val) = self.evaluate_with_cache(bc, len) {
-                if val.is_corrupted() {
-                    corruption_flags |= corruption_flag_for_var(Var::Tempo as u8);
-                }
-                result.tempo = Some(FractionData::from_value(&val));
-            }
-        }
-
-        if let Some((bc, len)) = bytecode.get_expr(Var::BeatsPerMeasure) {
-            if let Ok(val) = self.evaluate_with_cache(bc, len) {
-                if val.is_corrupted() {
-                    corruption_flags |= corruption_flag_for_var(Var::BeatsPerMeasure as u8);
-                }
-                result.beats_per_measure = Some(FractionData::from_value(&val));
-            }
-        }
-
-        if let Some((bc, len)) = bytecode.get_expr(Var::Frequency) {
-            if let Ok(val) = self.evaluate_with_cache(bc, len) {
-                if val.is_corrupted() {
-                    corruption_flags |= corruption_flag_for_var(Var::Frequency as u8);
-                }
-                result.frequency = Some(FractionData::from_value(&val));
-            }
-        }
-
-        // 2. measureLength depends on tempo/beatsPerMeasure
-        // Temporarily insert partial result for self-reference
-        result.corruption_flags = corruption_flags;
-        self.cache.insert(note_id, result.clone());
-
-        if let Some((bc, len)) = bytecode.get_expr(Var::MeasureLength) {
-            if let Ok(val) = self.evaluate_with_cache(bc, len) {
-                if val.is_corrupted() {
-                    corruption_flags |= corruption_flag_for_var(Var::MeasureLength as u8);
-                }
-                result.measure_length = Some(FractionData::from_value(&val));
-                result.corruption_flags = corruption_flags;
-                self.cache.insert(note_id, result.clone());
-            }
-        }
-
-        // 3. startTime and duration may depend on measureLength/tempo
-        if let Some((bc, len)) = bytecode.get_expr(Var::StartTime) {
-            if let Ok(val) = self.evaluate_with_cache(bc, len) {
-                if val.is_corrupted() {
-                    corruption_flags |= corruption_flag_for_var(Var::StartTime as u8);
-                }
-                result.start_time = Some(FractionData::from_value(&val));
-                result.corruption_flags = corruption_flags;
-                self.cache.insert(note_id, result.clone());
-            }
+        let (pc, op) = self.at;
+        if in_progress.strict {
+            return Err(EvalError::SelfReference { pc, op, note_id, var: index });
         }
+        self.info.self_reference.get_or_insert((op, pc, note_id, index));
+        Ok(true)
+    }
 
-        if let Some((bc, len)) = bytecode.get_expr(Var::Duration) {
-            if let Ok(val) = self.evaluate_with_cache(bc, len) {
-                if val.is_corrupted() {
-                    corruption_flags |= corruption_flag_for_var(Var::Duration as u8);
+    /// Value FIND_TEMPO, FIND_MEASURE and FIND_BEAT read for a variable of
+    /// a note: its evaluated value, else the base note's, else the default
+    ///
+    /// Values still being evaluated are skipped, as `load_var` does.
+    fn lookup_inherited(&mut self, note_id: u32, var: Var) -> Result<Value, EvalError> {
+        for id in [note_id, 0] {
+            if !self.in_progress(id, var as u8)? {
+                if let Some(value) = self.cache.value(id, var) {
+                    return Ok(value);
                 }
-                result.duration = Some(FractionData::from_value(&val));
-            }
-        }
-
-        // 4. If measureLength wasn't explicitly defined but this is a measure note,
-        // compute it from beatsPerMeasure and tempo
-        let is_measure_note = result.start_time.is_some()
-            && result.duration.is_none()
-            && result.frequency.is_none();
-
-        if result.measure_length.is_none() && (is_measure_note || note_id == 0) {
-            let beats = result
-                .beats_per_measure
-                .as_ref()
-                .map(|f| f.to_value())
-                .or_else(|| {
-                    self.cache
-                        .get(&0)
-                        .and_then(|c| c.beats_per_measure.as_ref())
-                        .map(|f| f.to_value())
-                })
-                .unwrap_or_else(|| Value::rational(4, 1));
-
-            let tempo = result
-                .tempo
-                .as_ref()
-                .map(|f| f.to_value())
-                .or_else(|| {
-                    self.cache
-                        .get(&0)
-                        .and_then(|c| c.tempo.as_ref())
-                        .map(|f| f.to_value())
-                })
-                .unwrap_or_else(|| Value::rational(60, 1));
-
-            // measureLength = beatsPerMeasure / tempo * 60
-            let sixty = Value::rational(60, 1);
-            let measure_len = beats.mul(&sixty).div(&tempo);
-            if measure_len.is_corrupted() {
-                corruption_flags |= corruption_flag_for_var(Var::MeasureLength as u8);
             }
-            result.measure_length = Some(FractionData::from_value(&measure_len));
         }
-
-        // Store final result with all corruption flags
-        result.corruption_flags = corruption_flags;
-        self.cache.insert(note_id, result);
-        true
-    }
-
-    // === Cache Read ===
-
-    /// Get a single cached value
-    #[wasm_bindgen(js_name = getCachedValue)]
-    pub fn get_cached_value(&self, note_id: u32, var_index: u8) -> JsValue {
-        let var = match Var::from_byte(var_index) {
-            Some(v) => v,
-            None => return JsValue::NULL,
-        };
-
-        self.cache
-            .get(&note_id)
-            .and_then(|note| note.get_var(var))
-            .map(|fd| {
-                serde_wasm_bindgen::to_value(fd).unwrap_or(JsValue::NULL)
-            })
-            .unwrap_or(JsValue::NULL)
-    }
-
-    /// Get all cached values for a note
-    #[wasm_bindgen(js_name = getCachedNote)]
-    pub fn get_cached_note(&self, note_id: u32) -> JsValue {
-        self.cache
-            .get(&note_id)
-            .map(|note| {
-                serde_wasm_bindgen::to_value(note).unwrap_or(JsValue::NULL)
-            })
-            .unwrap_or(JsValue::NULL)
-    }
-
-    /// Export entire cache (for persistence/debug)
-    #[wasm_bindgen(js_name = exportCache)]
-    pub fn export_cache(&self) -> JsValue {
-        serde_wasm_bindgen::to_value(&self.cache).unwrap_or(JsValue::NULL)
-    }
-
-    /// Import cache from JSON (for undo/redo snapshots)
-    #[wasm_bindgen(js_name = importCache)]
-    pub fn import_cache(&mut self, cache_json: JsValue) -> Result<(), JsValue> {
-        let string_cache: HashMap<String, EvaluatedNote> =
-            serde_wasm_bindgen::from_value(cache_json)
-                .map_err(|e| JsValue::from_str(&format!("Failed to parse cache: {}", e)))?;
-
-        // Convert string keys to u32
-        self.cache = string_cache
-            .into_iter()
-            .filter_map(|(k, v)| k.parse::<u32>().ok().map(|id| (id, v)))
-            .collect();
-
-        self.generation += 1;
-        Ok(())
-    }
-}
-
-impl Default for PersistentEvaluator {
-    fn default() -> Self {
-        PersistentEvaluator::new()
+        Ok(self.config.defaults.value(var))
     }
-}
 
-impl PersistentEvaluator {
-    /// Push a value onto the stack
-    fn push(&mut self, value: Value) -> Result<(), String> {
-        if self.stack.len() >= self.max_stack_size {
-            return Err("Stack overflow in evaluator".to_string());
+    /// Apply the non-finite policy to the result of the instruction at `pc`
+    fn check_finite(&mut self, op: Op, pc: usize) -> Result<(), EvalError> {
+        if let Some(top) = self.stack.last_mut().filter(|v| !v.is_finite()) {
+            let value = std::mem::take(top).enforce_finite(self.config.non_finite);
+            *top = value.map_err(|reason| EvalError::NonFinite { pc, op, reason })?;
         }
-        self.stack.push(value);
         Ok(())
     }
 
-    /// Pop a value from the stack
-    fn pop(&mut self) -> Result<Value, String> {
-        self.stack
-            .pop()
-            .ok_or_else(|| "Stack underflow in evaluator".to_string())
-    }
-
-    /// Clear the stack
-    fn clear_stack(&mut self) {
-        self.stack.clear();
-    }
-
-    /// Get a default value for a variable (always rational)
-    fn default_value(var: Var) -> Value {
-        Value::Rational(match var {
-            Var::StartTime => Fraction::new(0, 1),
-            Var::Duration => Fraction::new(1, 1),
-            Var::Frequency => Fraction::new(440, 1),
-            Var::Tempo => Fraction::new(60, 1),
-            Var::BeatsPerMeasure => Fraction::new(4, 1),
-            Var::MeasureLength => Fraction::new(4, 1),
-        })
-    }
-
-    /// Evaluate bytecode using the internal cache
-    /// Returns a Value which may be rational or irrational
-    fn evaluate_with_cache(&mut self, bytecode: &[u8], length: usize) -> Result<Value, String> {
-        if length == 0 {
-            return Ok(Value::rational(0, 1));
-        }
-
-        self.clear_stack();
+    /// Run instructions on the current stack; `macro_depth` counts the
+    /// CALL_MACROs this bytecode is running inside
+    fn execute(&mut self, bytecode: &[u8], length: usize, macro_depth: usize) -> Result<(), EvalError> {
         let mut pc = 0;
 
         while pc < length {
+            let op_pc = pc;
             let op_byte = bytecode[pc];
             pc += 1;
 
-            let op = Op::from_byte(op_byte)
-                .ok_or_else(|| format!("Unknown opcode: 0x{:02x} at pc={}", op_byte, pc - 1))?;
+            let op = Op::from_byte(op_byte).ok_or(EvalError::UnknownOpcode { pc: op_pc, byte: op_byte })?;
+            self.at = (op_pc, op);
+            if self.fuel == 0 {
+                return Err(EvalError::FuelExhausted { pc: op_pc, op, executed: self.config.max_instructions });
+            }
+            self.fuel -= 1;
 
             match op {
                 Op::LoadConst => {
                     if pc + 8 > length {
-                        return Err("Unexpected end of bytecode in LOAD_CONST".to_string());
+                        return Err(EvalError::TruncatedOperand { pc: op_pc, op });
                     }
                     let num = read_i32(bytecode, pc);
                     pc += 4;
@@ -1201,13 +1842,13 @@ impl PersistentEvaluator {
 
                 Op::LoadConstBig => {
                     // Read signed numerator (variable length)
-                    let (num, num_bytes) = read_big_int_signed(bytecode, pc)
-                        .map_err(|e| format!("Error reading big numerator: {}", e))?;
+                    let (num, num_bytes) = read_big_int_signed(&bytecode[..length], pc)
+                        .map_err(|_| EvalError::TruncatedOperand { pc: op_pc, op })?;
                     pc += num_bytes;
 
                     // Read unsigned denominator (variable length)
-                    let (den, den_bytes) = read_big_int_unsigned(bytecode, pc)
-                        .map_err(|e| format!("Error reading big denominator: {}", e))?;
+                    let (den, den_bytes) = read_big_int_unsigned(&bytecode[..length], pc)
+                        .map_err(|_| EvalError::TruncatedOperand { pc: op_pc, op })?;
                     pc += den_bytes;
 
                     // Create Fraction from BigInts
@@ -1215,57 +1856,30 @@ impl PersistentEvaluator {
                     self.push(Value::Rational(frac))?;
                 }
 
-                Op::LoadRef => {
-                    if pc + 3 > length {
-                        return Err("Unexpected end of bytecode in LOAD_REF".to_string());
+                Op::LoadRef | Op::LoadRefWide => {
+                    let wide = op == Op::LoadRefWide;
+                    let id_len = if wide { 4 } else { 2 };
+                    if pc + id_len + 1 > length {
+                        return Err(EvalError::TruncatedOperand { pc: op_pc, op });
                     }
-                    let note_id = read_u16(bytecode, pc) as u32;
-                    pc += 2;
+                    let note_id = if wide { read_u32(bytecode, pc) } else { read_u16(bytecode, pc) as u32 };
+                    pc += id_len;
                     let var_idx = bytecode[pc];
                     pc += 1;
 
-                    let var = Var::from_byte(var_idx)
-                        .ok_or_else(|| format!("Invalid variable index: {}", var_idx))?;
-
-                    // Look up in internal cache (preserves corruption status)
-                    let value = self.cache
-                        .get(&note_id)
-                        .and_then(|note| note.get_var(var))
-                        .map(|fd| fd.to_value());
-
-                    // For inheritable properties, fall back to base note
-                    let value = value.or_else(|| {
-                        if matches!(var, Var::Tempo | Var::BeatsPerMeasure | Var::MeasureLength) {
-                            self.cache
-                                .get(&0)
-                                .and_then(|note| note.get_var(var))
-                                .map(|fd| fd.to_value())
-                        } else {
-                            None
-                        }
-                    });
-
-                    let value = value.unwrap_or_else(|| Self::default_value(var));
+                    let value = self.load_var(note_id, var_idx)?;
                     self.push(value)?;
                 }
 
                 Op::LoadBase => {
                     if pc + 1 > length {
-                        return Err("Unexpected end of bytecode in LOAD_BASE".to_string());
+                        return Err(EvalError::TruncatedOperand { pc: op_pc, op });
                     }
                     let var_idx = bytecode[pc];
                     pc += 1;
 
-                    let var = Var::from_byte(var_idx)
-                        .ok_or_else(|| format!("Invalid variable index: {}", var_idx))?;
-
-                    // Look up base note (ID 0) in internal cache
-                    let value = self.cache
-                        .get(&0)
-                        .and_then(|note| note.get_var(var))
-                        .map(|fd| fd.to_value())
-                        .unwrap_or_else(|| Self::default_value(var));
-
+                    // Look up base note (ID 0)
+                    let value = self.load_var(0, var_idx)?;
                     self.push(value)?;
                 }
 
@@ -1303,61 +1917,125 @@ impl PersistentEvaluator {
                     // May produce irrational result (corruption)
                     let exp = self.pop()?;
                     let base = self.pop()?;
-                    self.push(base.pow(&exp))?;
+                    let result = if self.config.strict {
+                        base.checked_pow(&exp)
+                            .map_err(|e| EvalError::Strict { pc: op_pc, op, pow: Some(e) })?
+                    } else {
+                        base.pow(&exp)
+                    };
+                    self.push(result)?;
                 }
 
-                Op::FindTempo => {
-                    // Pop note reference (not used in current impl, uses base note)
-                    let _ = self.pop()?;
+                Op::Mod => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.push(a.rem(&b))?;
+                }
 
-                    // Get tempo from base note
-                    let tempo = self.cache
-                        .get(&0)
-                        .and_then(|note| note.tempo.as_ref())
-                        .map(|fd| fd.to_value())
-                        .unwrap_or_else(|| Value::rational(60, 1));
+                Op::Min => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.push(a.min(&b))?;
+                }
 
-                    self.push(tempo)?;
+                Op::Max => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.push(a.max(&b))?;
                 }
 
-                Op::FindMeasure => {
-                    // Pop note reference
-                    let note_ref = self.pop()?;
-                    let note_id = note_ref.to_f64().round() as u32;
+                Op::Sqrt => {
+                    let a = self.pop()?;
+                    self.push(a.sqrt())?;
+                }
 
-                    // Get beatsPerMeasure - try note first, then base note
-                    let beats_per_measure = self.cache
-                        .get(&note_id)
-                        .and_then(|note| note.beats_per_measure.as_ref())
-                        .or_else(|| self.cache.get(&0).and_then(|note| note.beats_per_measure.as_ref()))
-                        .map(|fd| fd.to_value())
-                        .unwrap_or_else(|| Value::rational(4, 1));
+                Op::Abs => {
+                    let a = self.pop()?;
+                    self.push(a.abs())?;
+                }
 
-                    // Get tempo - try note first, then base note
-                    let tempo = self.cache
-                        .get(&note_id)
-                        .and_then(|note| note.tempo.as_ref())
-                        .or_else(|| self.cache.get(&0).and_then(|note| note.tempo.as_ref()))
-                        .map(|fd| fd.to_value())
-                        .unwrap_or_else(|| Value::rational(60, 1));
+                Op::Sign => {
+                    let a = self.pop()?;
+                    self.push(a.signum())?;
+                }
 
-                    // Compute measureLength = beatsPerMeasure / tempo * 60
+                Op::Floor => {
+                    let a = self.pop()?;
+                    self.push(a.floor())?;
+                }
+
+                Op::Ceil => {
+                    let a = self.pop()?;
+                    self.push(a.ceil())?;
+                }
+
+                Op::Round => {
+                    let a = self.pop()?;
+                    self.push(a.round())?;
+                }
+
+                Op::Root => {
+                    let n = root_degree(&self.pop()?, op_pc)?;
+                    let a = self.pop()?;
+                    self.push(a.nth_root(n))?;
+                }
+
+                Op::Log => {
+                    let base = self.pop()?;
+                    let a = self.pop()?;
+                    self.push(a.log(&base))?;
+                }
+
+                Op::FindTempo => {
+                    // Pop note reference (not used in current impl, uses base note)
+                    let _ = self.pop()?;
+
+                    // Get tempo from base note
+                    let tempo = self.lookup_inherited(0, Var::Tempo)?;
+
+                    self.push(tempo)?;
+                }
+
+                Op::FindMeasure => {
+                    // Pop note reference
+                    let note_ref = self.pop()?;
+                    let note_id = note_ref.to_f64().round() as u32;
+
+                    // Get beatsPerMeasure - try note first, then base note
+                    let beats_per_measure = self.lookup_inherited(note_id, Var::BeatsPerMeasure)?;
+
+                    // Get tempo - try note first, then base note
+                    let tempo = self.lookup_inherited(note_id, Var::Tempo)?;
+
+                    // Compute measureLength = beatsPerMeasure / tempo * 60
                     let sixty = Value::rational(60, 1);
                     let measure = beats_per_measure.mul(&sixty).div(&tempo);
 
                     self.push(measure)?;
                 }
 
+                Op::FindBeat => {
+                    // Pop note reference - the note ID whose beat length we want
+                    let note_ref = self.pop()?;
+                    let note_id = note_ref.to_f64().round() as u32;
+
+                    // Get tempo - try note first, then base note
+                    let tempo = self.lookup_inherited(note_id, Var::Tempo)?;
+
+                    self.push(Value::rational(60, 1).div(&tempo))?;
+                }
+
                 Op::FindInstrument => {
-                    // Not fully implemented - return default
-                    let _ = self.pop()?;
-                    self.push(Value::rational(0, 1))?;
+                    // Pop note reference - the note ID whose instrument we want
+                    let note_ref = self.pop()?;
+                    let note_id = note_ref.to_f64().round() as u32;
+
+                    let instrument = self.cache.instrument(note_id);
+                    self.push(Value::Rational(Fraction::new_raw(instrument as i64, 1)))?;
                 }
 
                 Op::Dup => {
-                    let top = self.stack.last()
-                        .ok_or_else(|| "Stack empty in evaluator".to_string())?
-                        .clone();
+                    let top = self.peek()?.clone();
                     self.push(top)?;
                 }
 
@@ -1367,167 +2045,5413 @@ impl PersistentEvaluator {
                     self.push(a)?;
                     self.push(b)?;
                 }
+
+                Op::Over => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.push(a.clone())?;
+                    self.push(b)?;
+                    self.push(a)?;
+                }
+
+                Op::Rot => {
+                    let c = self.pop()?;
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.push(b)?;
+                    self.push(c)?;
+                    self.push(a)?;
+                }
+
+                Op::Drop => {
+                    self.pop()?;
+                }
+
+                Op::CallMacro => {
+                    let cache = self.cache;
+                    let macros = cache.macros().ok_or(EvalError::NoMacroTable { pc: op_pc })?;
+                    if pc + 2 > length {
+                        return Err(EvalError::TruncatedOperand { pc: op_pc, op });
+                    }
+                    let macro_id = read_u16(bytecode, pc);
+                    pc += 2;
+                    if macro_depth >= MAX_MACRO_DEPTH {
+                        return Err(EvalError::NestedMacro { pc: op_pc, macro_id });
+                    }
+                    let body = macros.get(macro_id).ok_or(EvalError::UnknownMacro { pc: op_pc, macro_id })?;
+                    // The macro's own instructions already tracked errors and corruption
+                    self.execute(body.bytecode(), body.bytecode().len(), macro_depth + 1)?;
+                    self.tracer.step(op_pc, &bytecode[op_pc..pc], self.stack);
+                    continue;
+                }
             }
-        }
 
-        if self.stack.len() != 1 {
-            if self.stack.is_empty() {
-                return Ok(Value::rational(0, 1));
+            self.check_finite(op, op_pc)?;
+            if self.config.track_errors {
+                if let Some(top) = self.stack.last_mut() {
+                    *top = std::mem::take(top).track_error();
+                }
             }
-        }
 
-        self.pop()
+            // Stack values are rational until some instruction produces
+            // otherwise, so the first non-rational top marks its source
+            if self.info.first_corruption.is_none() && self.stack.last().is_some_and(Value::is_corrupted) {
+                if self.config.strict {
+                    return Err(EvalError::Strict { pc: op_pc, op, pow: None });
+                }
+                self.info.first_corruption = Some((op, op_pc));
+            }
+            if self.info.first_lossy.is_none() && matches!(self.stack.last(), Some(Value::Irrational { .. })) {
+                self.info.first_lossy = Some((op, op_pc));
+            }
+            self.tracer.step(op_pc, &bytecode[op_pc..pc], self.stack);
+        }
+        Ok(())
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::bytecode::{write_i32, Op};
+// ============================================================================
+// PersistentEvaluator - WASM-resident cache for O(N) evaluation
+// ============================================================================
 
-    fn make_const_bytecode(num: i32, den: i32) -> Vec<u8> {
-        let mut bytecode = Vec::new();
-        bytecode.push(Op::LoadConst as u8);
-        write_i32(&mut bytecode, num);
-        write_i32(&mut bytecode, den);
-        bytecode
-    }
+use crate::graph::{cyclic_components, DependencyGraph};
+use std::collections::HashSet;
+use std::sync::{Arc, OnceLock};
 
-    #[test]
-    fn test_evaluate_constant() {
-        let mut evaluator = Evaluator::new();
-        let bytecode = make_const_bytecode(3, 4);
-        let cache = HashMap::new();
+/// Bytecode storage for a single note's expressions
+#[derive(Clone, Default)]
+pub struct NoteBytecode {
+    /// Bytecode for each variable type: [startTime, duration, frequency, tempo, beatsPerMeasure, measureLength]
+    ///
+    /// Shared between notes when the evaluator interns expressions.
+    pub expressions: [Option<(Arc<[u8]>, usize)>; 6],
+    /// Bytecode of custom variables by index (see `bytecode::CUSTOM_VAR_FIRST`)
+    pub custom: BTreeMap<u8, (Arc<[u8]>, usize)>,
+}
 
-        let result = evaluator.evaluate(&bytecode, bytecode.len(), &cache).unwrap();
-        assert_eq!(result.to_f64(), 0.75);
-        assert!(result.is_rational()); // Should be rational, not corrupted
+impl NoteBytecode {
+    pub fn get_expr(&self, var: Var) -> Option<(&[u8], usize)> {
+        let idx = var as usize;
+        self.expressions.get(idx)
+            .and_then(|opt| opt.as_ref())
+            .map(|(bytes, len)| (bytes.as_ref(), *len))
     }
 
-    #[test]
-    fn test_evaluate_addition() {
-        let mut evaluator = Evaluator::new();
-        let mut bytecode = Vec::new();
-
-        // Push 1/2
-        bytecode.push(Op::LoadConst as u8);
-        write_i32(&mut bytecode, 1);
-        write_i32(&mut bytecode, 2);
+    pub fn set_expr(&mut self, var: Var, bytecode: impl Into<Arc<[u8]>>, length: usize) {
+        let idx = var as usize;
+        if idx < 6 {
+            self.expressions[idx] = Some((bytecode.into(), length));
+        }
+    }
 
-        // Push 1/4
-        bytecode.push(Op::LoadConst as u8);
-        write_i32(&mut bytecode, 1);
-        write_i32(&mut bytecode, 4);
+    pub fn clear_expr(&mut self, var: Var) {
+        let idx = var as usize;
+        if idx < 6 {
+            self.expressions[idx] = None;
+        }
+    }
 
-        // Add
-        bytecode.push(Op::Add as u8);
+    pub fn get_custom(&self, index: u8) -> Option<(&[u8], usize)> {
+        self.custom.get(&index).map(|(bytes, len)| (bytes.as_ref(), *len))
+    }
 
-        let cache = HashMap::new();
-        let result = evaluator.evaluate(&bytecode, bytecode.len(), &cache).unwrap();
-        assert_eq!(result.to_f64(), 0.75);
-        assert!(result.is_rational());
+    /// Set a custom variable's bytecode; other indices are ignored
+    pub fn set_custom(&mut self, index: u8, bytecode: impl Into<Arc<[u8]>>, length: usize) {
+        if is_custom_var(index) {
+            self.custom.insert(index, (bytecode.into(), length));
+        }
     }
 
-    #[test]
-    fn test_evaluate_with_cache() {
-        let mut evaluator = Evaluator::new();
-        let mut bytecode = Vec::new();
+    /// Every expression, built-in variables first, then custom ones by index
+    pub fn all_exprs(&self) -> impl Iterator<Item = &(Arc<[u8]>, usize)> {
+        self.expressions.iter().flatten().chain(self.custom.values())
+    }
+}
 
-        // LOAD_BASE startTime
-        bytecode.push(Op::LoadBase as u8);
-        bytecode.push(Var::StartTime as u8);
+/// State saved by `PersistentEvaluator::snapshot`
+struct Snapshot {
+    notes: HashMap<u32, EvaluatedNote>,
+    bytecode: HashMap<u32, NoteBytecode>,
+    dirty: HashSet<u32>,
+    /// `snapshot_clock` when last taken or restored
+    last_used: u64,
+}
 
-        // Push 1
-        bytecode.push(Op::LoadConst as u8);
-        write_i32(&mut bytecode, 1);
-        write_i32(&mut bytecode, 1);
+/// State saved by `PersistentEvaluator::begin_staging`
+struct Staging {
+    saved: Snapshot,
+    cached_at: HashMap<u32, u64>,
+    removed_at: HashMap<u32, u64>,
+    errors: VecDeque<NoteEvalError>,
+    strict_violation: Option<StrictViolation>,
+    instruments: InstrumentTable,
+    macros: MacroTable,
+}
 
-        // Add
-        bytecode.push(Op::Add as u8);
+/// Instrument ids assigned to notes, for FIND_INSTRUMENT
+#[derive(Clone, Default)]
+pub struct InstrumentTable {
+    assigned: HashMap<u32, u32>,
+    /// Instrument of the base note, used by notes without one
+    default: u32,
+}
 
-        // Create cache with base note having startTime = 5
-        let mut cache = HashMap::new();
-        let mut base_note = EvaluatedNote::default();
-        base_note.start_time = Some(FractionData { s: 1, n: 5, d: 1, f: None, corrupted: false });
-        cache.insert(0, base_note);
+impl InstrumentTable {
+    /// The instrument of a note, else the base note's, else the default
+    ///
+    /// The same fallback a plain `Evaluator` applies to its instrument map.
+    pub fn instrument(&self, note_id: u32) -> u32 {
+        self.assigned
+            .get(&note_id)
+            .or_else(|| self.assigned.get(&0))
+            .copied()
+            .unwrap_or(self.default)
+    }
 
-        let result = evaluator.evaluate(&bytecode, bytecode.len(), &cache).unwrap();
-        assert_eq!(result.to_f64(), 6.0); // 5 + 1 = 6
-        assert!(result.is_rational());
+    /// Registered notes that fall back to the base note's instrument
+    fn unassigned<'a>(&'a self, note_ids: impl Iterator<Item = &'a u32> + 'a) -> impl Iterator<Item = u32> + 'a {
+        note_ids.copied().filter(|id| !self.assigned.contains_key(id))
     }
+}
 
-    #[test]
-    fn test_evaluate_pow_rational() {
-        let mut evaluator = Evaluator::new();
-        let mut bytecode = Vec::new();
+/// Estimated memory usage of a PersistentEvaluator
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct EvaluatorMemoryStats {
+    #[serde(rename = "cacheEntries")]
+    pub cache_entries: usize,
+    #[serde(rename = "cacheBytes")]
+    pub cache_bytes: usize,
+    #[serde(rename = "bytecodeEntries")]
+    pub bytecode_entries: usize,
+    #[serde(rename = "bytecodeBytes")]
+    pub bytecode_bytes: usize,
+    #[serde(rename = "dirtyEntries")]
+    pub dirty_entries: usize,
+    #[serde(rename = "dirtyBytes")]
+    pub dirty_bytes: usize,
+    #[serde(rename = "stackBytes")]
+    pub stack_bytes: usize,
+    #[serde(rename = "totalBytes")]
+    pub total_bytes: usize,
+}
 
-        // Push 2
-        bytecode.push(Op::LoadConst as u8);
-        write_i32(&mut bytecode, 2);
-        write_i32(&mut bytecode, 1);
+/// How much bytecode interning saves (see `PersistentEvaluator::set_intern_expressions`)
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct InternStats {
+    /// Registered expressions across all notes
+    pub expressions: usize,
+    /// Distinct bytecode blobs backing them
+    #[serde(rename = "uniquePrograms")]
+    pub unique_programs: usize,
+    /// Bytes not stored because expressions share a blob
+    #[serde(rename = "savedBytes")]
+    pub saved_bytes: usize,
+}
 
-        // Push 3 (exponent)
-        bytecode.push(Op::LoadConst as u8);
-        write_i32(&mut bytecode, 3);
-        write_i32(&mut bytecode, 1);
+/// Instructions executed by `PersistentEvaluator::evaluate_dirty`, for
+/// tuning `setMaxInstructions`
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EvaluationStats {
+    /// Expressions run
+    pub expressions: usize,
+    /// Instructions executed across all of them
+    pub instructions: usize,
+    /// Most instructions executed by a single expression
+    #[serde(rename = "maxInstructions")]
+    pub max_instructions: usize,
+}
 
-        // Pow: 2^3 = 8
-        bytecode.push(Op::Pow as u8);
+impl EvaluationStats {
+    fn record(&mut self, executed: u32) {
+        self.expressions += 1;
+        self.instructions += executed as usize;
+        self.max_instructions = self.max_instructions.max(executed as usize);
+    }
 
-        let cache = HashMap::new();
-        let result = evaluator.evaluate(&bytecode, bytecode.len(), &cache).unwrap();
-        assert_eq!(result.to_f64(), 8.0);
-        assert!(result.is_rational()); // 2^3 is rational
+    #[cfg_attr(not(all(feature = "parallel", not(target_arch = "wasm32"))), allow(dead_code))]
+    fn merge(&mut self, other: &EvaluationStats) {
+        self.expressions += other.expressions;
+        self.instructions += other.instructions;
+        self.max_instructions = self.max_instructions.max(other.max_instructions);
     }
+}
 
-    #[test]
-    fn test_evaluate_pow_irrational_tet() {
-        let mut evaluator = Evaluator::new();
-        let mut bytecode = Vec::new();
+/// What `PersistentEvaluator::evaluate_dirty` evaluated, and which of
+/// those notes now hold different values
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EvaluationReport {
+    /// Notes evaluated and cached, in evaluation order
+    pub evaluated: Vec<u32>,
+    /// Evaluated notes with at least one changed property, in evaluation order
+    pub changed: Vec<u32>,
+    /// Changed properties of each changed note (see `EvaluatedNote::changed_vars`)
+    #[serde(rename = "changedVars")]
+    pub changed_vars: BTreeMap<u32, u16>,
+    /// Problems of the evaluated notes, when `setValidateAfterEvaluate` is on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub problems: Vec<CacheProblem>,
+}
 
-        // Push 2 (base)
-        bytecode.push(Op::LoadConst as u8);
-        write_i32(&mut bytecode, 2);
-        write_i32(&mut bytecode, 1);
+impl EvaluationReport {
+    fn record(&mut self, note_id: u32, changed_vars: u16) {
+        self.evaluated.push(note_id);
+        if changed_vars != 0 {
+            if !self.changed_vars.contains_key(&note_id) {
+                self.changed.push(note_id);
+            }
+            *self.changed_vars.entry(note_id).or_default() |= changed_vars;
+        }
+    }
 
-        // Push 1/12 (exponent for TET semitone)
-        bytecode.push(Op::LoadConst as u8);
-        write_i32(&mut bytecode, 1);
-        write_i32(&mut bytecode, 12);
+    /// `{ evaluated, changed, changedVars }`, with `changedVars` as a plain
+    /// object keyed by note id
+    fn to_js(&self) -> JsValue {
+        let serializer = serde_wasm_bindgen::Serializer::new().serialize_maps_as_objects(true);
+        self.serialize(&serializer).unwrap_or(JsValue::NULL)
+    }
+}
 
-        // Pow: 2^(1/12) is irrational
-        bytecode.push(Op::Pow as u8);
+/// What `PersistentEvaluator::import_cache_merge` did with each entry
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheMerge {
+    /// Notes stored, in id order
+    pub imported: Vec<u32>,
+    /// Notes already cached and left alone because `overwrite` was off, in
+    /// id order
+    pub skipped: Vec<u32>,
+    /// Why each rejected entry was rejected, by its key
+    pub invalid: BTreeMap<String, String>,
+}
 
-        let cache = HashMap::new();
-        let result = evaluator.evaluate(&bytecode, bytecode.len(), &cache).unwrap();
+/// Bytecode size of each registered expression of a note, in bytes
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct NoteBytecodeSize {
+    #[serde(rename = "startTime")]
+    pub start_time: Option<usize>,
+    pub duration: Option<usize>,
+    pub frequency: Option<usize>,
+    pub tempo: Option<usize>,
+    #[serde(rename = "beatsPerMeasure")]
+    pub beats_per_measure: Option<usize>,
+    #[serde(rename = "measureLength")]
+    pub measure_length: Option<usize>,
+    #[serde(rename = "totalBytes")]
+    pub total_bytes: usize,
+}
 
-        // Should be approximately 1.059463...
-        let expected = 2.0_f64.powf(1.0 / 12.0);
-        assert!((result.to_f64() - expected).abs() < 1e-10);
-        assert!(result.is_corrupted()); // Should be irrational (corrupted)
+/// Interned bytecode blobs by `canonical_hash`
+type InternPool = HashMap<u64, Vec<Arc<[u8]>>>;
+
+/// The pooled blob equivalent to the first `length` bytes of `bytecode`,
+/// adding it to the pool if there is none yet
+fn intern(pool: &mut InternPool, bytecode: &[u8], length: usize) -> Arc<[u8]> {
+    let program = bytecode.get(..length).unwrap_or(bytecode);
+    let bucket = pool.entry(canonical_hash(program, program.len())).or_default();
+    if let Some(shared) = bucket.iter().find(|shared| equivalent(shared, program)) {
+        return shared.clone();
     }
+    let shared: Arc<[u8]> = program.into();
+    bucket.push(shared.clone());
+    shared
+}
 
-    #[test]
-    fn test_evaluate_pow_perfect_root() {
-        let mut evaluator = Evaluator::new();
-        let mut bytecode = Vec::new();
+/// Sample-accurate playback events for an audio worklet
+///
+/// Parallel arrays sorted by start sample, then note id. Sample positions
+/// are integers stored as f64 so they cross into JS as plain numbers.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct AudioEvents {
+    pub ids: Vec<u32>,
+    #[serde(rename = "startSamples")]
+    pub start_samples: Vec<f64>,
+    #[serde(rename = "durationSamples")]
+    pub duration_samples: Vec<f64>,
+    pub frequencies: Vec<f64>,
+}
 
-        // Push 4
-        bytecode.push(Op::LoadConst as u8);
-        write_i32(&mut bytecode, 4);
-        write_i32(&mut bytecode, 1);
+/// A cached note with a non-rational value, as returned by
+/// `getCorruptedNotes`
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CorruptedNote {
+    pub id: u32,
+    #[serde(rename = "corruptionFlags")]
+    pub corruption_flags: u16,
+    /// Only serialized when set
+    #[serde(default, rename = "customCorruptionFlags", skip_serializing_if = "is_zero")]
+    pub custom_corruption_flags: u32,
+}
 
-        // Push 1/2 (square root)
-        bytecode.push(Op::LoadConst as u8);
-        write_i32(&mut bytecode, 1);
-        write_i32(&mut bytecode, 2);
+/// How many cached notes hold a non-rational value of each variable, as
+/// returned by `getCorruptionSummary`
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CorruptionSummary {
+    /// Notes with at least one non-rational value
+    pub notes: u32,
+    #[serde(rename = "startTime")]
+    pub start_time: u32,
+    pub duration: u32,
+    pub frequency: u32,
+    pub tempo: u32,
+    #[serde(rename = "beatsPerMeasure")]
+    pub beats_per_measure: u32,
+    #[serde(rename = "measureLength")]
+    pub measure_length: u32,
+    /// Non-rational custom values, summed over every custom variable
+    pub custom: u32,
+}
 
-        // Pow: 4^(1/2) = 2 (perfect square root, stays rational)
-        bytecode.push(Op::Pow as u8);
+impl CorruptionSummary {
+    fn count(&mut self, note: &EvaluatedNote) {
+        if note.corruption_flags == 0 && note.custom_corruption_flags == 0 {
+            return;
+        }
+        self.notes += 1;
+        for (var, count) in [
+            (Var::StartTime, &mut self.start_time),
+            (Var::Duration, &mut self.duration),
+            (Var::Frequency, &mut self.frequency),
+            (Var::Tempo, &mut self.tempo),
+            (Var::BeatsPerMeasure, &mut self.beats_per_measure),
+            (Var::MeasureLength, &mut self.measure_length),
+        ] {
+            *count += note.is_corrupted(var) as u32;
+        }
+        self.custom += note.custom_corruption_flags.count_ones();
+    }
+}
 
-        let cache = HashMap::new();
-        let result = evaluator.evaluate(&bytecode, bytecode.len(), &cache).unwrap();
-        assert_eq!(result.to_f64(), 2.0);
+/// Timing of a cached note, as returned by `getNotesInRange`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NoteTiming {
+    pub id: u32,
+    #[serde(rename = "startTime")]
+    pub start_time: f64,
+    /// 0 for notes without a duration
+    pub duration: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<f64>,
+}
+
+/// Where the cached notes start and end, as returned by `getModuleExtent`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModuleExtent {
+    /// Earliest start time
+    pub start: f64,
+    /// Latest end (start time plus duration)
+    pub end: f64,
+    /// The note ending at `end`
+    #[serde(rename = "lastNoteId")]
+    pub last_note_id: u32,
+}
+
+/// `ModuleExtent` with exact times, as returned by `getModuleExtentExact`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ModuleExtentExact {
+    pub start: FractionData,
+    pub end: FractionData,
+    #[serde(rename = "lastNoteId")]
+    pub last_note_id: u32,
+}
+
+impl ModuleExtentExact {
+    /// The extent of the notes with both a start time and a duration, if
+    /// there are any; of notes ending together the lowest id is the last
+    fn new(notes: &HashMap<u32, EvaluatedNote>) -> Option<ModuleExtentExact> {
+        let mut extent: Option<ModuleExtentExact> = None;
+        for (&id, note) in notes {
+            let (Some(start), Some(duration)) = (&note.start_time, &note.duration) else {
+                continue;
+            };
+            let end = end_time(start, duration);
+            match &mut extent {
+                None => extent = Some(ModuleExtentExact { start: start.clone(), end, last_note_id: id }),
+                Some(extent) => {
+                    if compare_times(start, &extent.start).is_lt() {
+                        extent.start = start.clone();
+                    }
+                    match compare_times(&end, &extent.end) {
+                        Ordering::Greater => {
+                            extent.end = end;
+                            extent.last_note_id = id;
+                        }
+                        Ordering::Equal => extent.last_note_id = extent.last_note_id.min(id),
+                        Ordering::Less => {}
+                    }
+                }
+            }
+        }
+        extent
+    }
+
+    pub fn to_f64(&self) -> ModuleExtent {
+        ModuleExtent { start: self.start.to_f64(), end: self.end.to_f64(), last_note_id: self.last_note_id }
+    }
+}
+
+/// A note's end time, exact unless either part is corrupted
+fn end_time(start: &FractionData, duration: &FractionData) -> FractionData {
+    if start.corrupted || duration.corrupted {
+        FractionData::from_value(&Value::from(start.to_f64() + duration.to_f64()))
+    } else {
+        FractionData::from_fraction(&start.to_fraction().add(&duration.to_fraction()))
+    }
+}
+
+/// Order two times, exactly when both are rational
+fn compare_times(a: &FractionData, b: &FractionData) -> Ordering {
+    if a.corrupted || b.corrupted {
+        a.to_f64().total_cmp(&b.to_f64())
+    } else {
+        a.to_fraction().cmp(&b.to_fraction())
+    }
+}
+
+/// Cached notes with a start time, sorted for window queries
+struct Timeline {
+    /// (start, duration, id), sorted by start, then id
+    entries: Vec<(f64, f64, u32)>,
+    /// Latest end of each range of `entries`, as an implicit binary tree:
+    /// node 1 covers all of them, node i splits into 2i and 2i + 1, and the
+    /// leaves start at `leaves`
+    max_end: Vec<f64>,
+    leaves: usize,
+}
+
+#[cfg(test)]
+thread_local! {
+    /// `Timeline` tree nodes visited by `overlapping` on this thread
+    static TIMELINE_VISITS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+impl Timeline {
+    fn new(notes: &HashMap<u32, EvaluatedNote>) -> Timeline {
+        let mut entries: Vec<(f64, f64, u32)> = notes
+            .iter()
+            .filter_map(|(&id, note)| {
+                let start = note.start_time.as_ref()?.to_f64();
+                // Missing and negative durations count as 0
+                let duration = note.duration.as_ref().map_or(0.0, |d| d.to_f64().max(0.0));
+                start.is_finite().then_some((start, duration, id))
+            })
+            .collect();
+        entries.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.2.cmp(&b.2)));
+        let leaves = entries.len().next_power_of_two();
+        let mut max_end = vec![f64::NEG_INFINITY; 2 * leaves];
+        for (i, &(start, duration, _)) in entries.iter().enumerate() {
+            max_end[leaves + i] = start + duration;
+        }
+        for node in (1..leaves).rev() {
+            max_end[node] = max_end[2 * node].max(max_end[2 * node + 1]);
+        }
+        Timeline { entries, max_end, leaves }
+    }
+
+    /// Entries starting before `time`
+    fn before(&self, time: f64) -> usize {
+        self.entries.partition_point(|entry| entry.0 < time)
+    }
+
+    /// Notes starting in [from, to)
+    fn starting_in(&self, from: f64, to: f64) -> &[(f64, f64, u32)] {
+        let start = self.before(from);
+        &self.entries[start..self.before(to).max(start)]
+    }
+
+    /// Notes sounding at some point of [from, to): those starting earlier
+    /// that end after `from`, then those starting in it
+    ///
+    /// The earlier ones are found by descending only into ranges that end
+    /// after `from`, so a query costs O(log n) per note returned rather than
+    /// a scan back over every note that could still be sounding.
+    fn overlapping(&self, from: f64, to: f64) -> impl Iterator<Item = &(f64, f64, u32)> {
+        let earlier = self.before(from);
+        let mut sounding = Vec::new();
+        self.ending_after(1, 0..self.leaves, earlier, from, &mut sounding);
+        sounding
+            .into_iter()
+            .map(|i| &self.entries[i])
+            .chain(self.starting_in(from, to))
+    }
+
+    /// Push, in order, the indices below `limit` in `node`'s `range` of
+    /// entries whose notes end after `time`
+    fn ending_after(&self, node: usize, range: std::ops::Range<usize>, limit: usize, time: f64, out: &mut Vec<usize>) {
+        #[cfg(test)]
+        TIMELINE_VISITS.with(|visits| visits.set(visits.get() + 1));
+        if range.start >= limit || self.max_end[node] <= time {
+            return;
+        }
+        if range.len() == 1 {
+            out.push(range.start);
+            return;
+        }
+        let mid = range.start + range.len() / 2;
+        self.ending_after(2 * node, range.start..mid, limit, time, out);
+        self.ending_after(2 * node + 1, mid..range.end, limit, time, out);
+    }
+}
+
+/// Cached playback values as parallel arrays sorted by start time, then
+/// note id, for `exportPlaybackArrays`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlaybackArrays {
+    pub ids: Vec<u32>,
+    pub start_times: Vec<f64>,
+    pub durations: Vec<f64>,
+    pub frequencies: Vec<f64>,
+    /// Notes that stopped being playable: removed from the cache, or stored
+    /// without all three values, sorted
+    pub removed: Vec<u32>,
+}
+
+impl PlaybackArrays {
+    /// `{ ids: Uint32Array, startTimes, durations, frequencies: Float64Array,
+    /// removed: Uint32Array }`
+    fn to_js(&self) -> JsValue {
+        let object = js_sys::Object::new();
+        let set = |key: &str, value: JsValue| {
+            let _ = js_sys::Reflect::set(&object, &key.into(), &value);
+        };
+        set("ids", js_sys::Uint32Array::from(&self.ids[..]).into());
+        set("startTimes", js_sys::Float64Array::from(&self.start_times[..]).into());
+        set("durations", js_sys::Float64Array::from(&self.durations[..]).into());
+        set("frequencies", js_sys::Float64Array::from(&self.frequencies[..]).into());
+        set("removed", js_sys::Uint32Array::from(&self.removed[..]).into());
+        object.into()
+    }
+}
+
+/// Convert a time in seconds to the nearest sample index
+///
+/// Rational times are rounded exactly, so equal start times always land on
+/// the same sample; irrational values fall back to f64 rounding.
+fn time_to_sample(time: &FractionData, sample_rate: u32) -> Option<i64> {
+    if time.corrupted {
+        let samples = (time.to_f64() * sample_rate as f64).round();
+        return if samples.is_finite() { Some(samples as i64) } else { None };
+    }
+    time.to_fraction()
+        .mul(&Fraction::new_raw(sample_rate as i64, 1))
+        .round_to_i64()
+}
+
+/// Estimate the table size of a std HashMap with the given capacity
+/// (one entry slot plus one control byte per bucket)
+pub(crate) fn hash_map_bytes<K, V>(capacity: usize) -> usize {
+    capacity * (std::mem::size_of::<(K, V)>() + 1)
+}
+
+/// Persistent evaluator with WASM-resident cache
+///
+/// This evaluator keeps the evaluation cache in WASM memory to avoid
+/// O(N²) serialization overhead when evaluating large modules.
+/// Now supports both rational (Fraction) and irrational (f64) values via the Value type.
+#[wasm_bindgen]
+pub struct PersistentEvaluator {
+    /// Evaluation stack
+    machine: StackMachine,
+
+    /// PERSISTENT CACHE: Lives in WASM memory across calls
+    cache: NoteCache,
+
+    /// Bytecode storage: noteId -> NoteBytecode
+    bytecode_store: HashMap<u32, NoteBytecode>,
+
+    /// Set of dirty note IDs
+    dirty: HashSet<u32>,
+
+    /// Instruments looked up by FIND_INSTRUMENT
+    instruments: InstrumentTable,
+
+    /// Generation counter for cache invalidation tracking
+    generation: u64,
+
+    /// Refuse to cache notes with non-rational values
+    strict: bool,
+
+    /// The note that stopped the last strict `evaluate_dirty`
+    strict_violation: Option<StrictViolation>,
+
+    /// The last `MAX_EVALUATION_ERRORS` failed property evaluations, oldest first
+    errors: VecDeque<NoteEvalError>,
+
+    /// Reject malformed bytecode in `register_expression`
+    validate_on_register: bool,
+
+    /// Why the last `register_expression` was rejected, if it was
+    registration_error: Option<String>,
+
+    /// Share one blob between equivalent registered expressions
+    intern: bool,
+
+    /// Blobs available for sharing while `intern` is on
+    interned: InternPool,
+
+    /// Bodies run by CALL_MACRO
+    macros: MacroTable,
+
+    /// References between registered notes, kept in sync with the bytecode
+    graph: DependencyGraph,
+
+    /// How far a corrupted value's float may move before evaluation reports
+    /// count it as changed
+    change_epsilon: f64,
+
+    /// Check evaluated notes for unplayable values
+    validate_after_evaluate: bool,
+
+    /// Generation each cached note was last stored in, for
+    /// `exportPlaybackArraysSince`
+    ///
+    /// A note is stamped with the generation current while it is stored,
+    /// before any bump that follows.
+    cached_at: HashMap<u32, u64>,
+
+    /// Generation each note was last dropped from the cache in, until it is
+    /// stored again, for `exportPlaybackArraysSince`
+    removed_at: HashMap<u32, u64>,
+
+    /// Saved states for `restore`, by id
+    snapshots: BTreeMap<u32, Snapshot>,
+
+    /// Id of the next snapshot
+    next_snapshot_id: u32,
+
+    /// Snapshots kept before the least recently used is dropped
+    max_snapshots: usize,
+
+    /// Incremented whenever a snapshot is taken or restored, to order them
+    /// by last use
+    snapshot_clock: u64,
+
+    /// State to return to on `rollbackStaging`, while staging is active
+    staging: Option<Staging>,
+
+    /// The graph changed since the machine's reference cycles were found
+    cycles_stale: bool,
+}
+
+#[wasm_bindgen]
+impl PersistentEvaluator {
+    /// Create a new persistent evaluator
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> PersistentEvaluator {
+        PersistentEvaluator {
+            machine: StackMachine::new(),
+            cache: NoteCache::default(),
+            bytecode_store: HashMap::new(),
+            dirty: HashSet::new(),
+            instruments: InstrumentTable::default(),
+            generation: 0,
+            strict: false,
+            strict_violation: None,
+            errors: VecDeque::new(),
+            validate_on_register: false,
+            registration_error: None,
+            intern: false,
+            interned: HashMap::new(),
+            macros: MacroTable::default(),
+            graph: DependencyGraph::new(),
+            change_epsilon: 0.0,
+            validate_after_evaluate: false,
+            cached_at: HashMap::new(),
+            removed_at: HashMap::new(),
+            snapshots: BTreeMap::new(),
+            next_snapshot_id: 1,
+            max_snapshots: DEFAULT_MAX_SNAPSHOTS,
+            snapshot_clock: 0,
+            staging: None,
+            cycles_stale: false,
+        }
+    }
+
+    /// Whether strict-rational mode is on (see `set_strict`)
+    #[wasm_bindgen(getter)]
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Turn strict-rational mode on or off
+    ///
+    /// In strict mode a note with any irrational or symbolic property is not
+    /// cached: `evaluate_dirty` stops at the first such note and reports it
+    /// through `getStrictViolation`, leaving the remaining notes dirty. A
+    /// property that reads itself, or a variable on the same reference cycle,
+    /// also fails with SELF_REFERENCE instead of reading that variable's
+    /// default.
+    #[wasm_bindgen(js_name = setStrict)]
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+        self.machine.in_progress.strict = strict;
+    }
+
+    /// Choose what happens when an instruction produces NaN or an infinity
+    /// (see `Evaluator::set_non_finite_policy`)
+    #[wasm_bindgen(js_name = setNonFinitePolicy)]
+    pub fn set_non_finite_policy(&mut self, policy: &str) -> Result<(), JsValue> {
+        self.machine.non_finite = policy.parse().map_err(|e: String| JsValue::from_str(&e))?;
+        Ok(())
+    }
+
+    /// Set the denominator used to approximate irrational and symbolic values
+    /// of a variable as n/d for notes evaluated from now on (see
+    /// `Evaluator::set_approximation_denominator`)
+    #[wasm_bindgen(js_name = setApproximationDenominator)]
+    pub fn set_approximation_denominator(&mut self, variable: &str, denominator: u32) -> Result<(), JsValue> {
+        self.machine.approximation.set_by_name(variable, denominator).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Override the values missing references fall back to (see
+    /// `Evaluator::set_defaults_js`), marking every registered note dirty
+    #[wasm_bindgen(js_name = setDefaults)]
+    pub fn set_defaults_js(&mut self, defaults: JsValue) -> Result<(), JsValue> {
+        let data = defaults_from_js(defaults)?;
+        self.set_defaults(&data).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Every variable's fallback value, as `{ startTime: { s, n, d }, ... }`
+    #[wasm_bindgen(js_name = getDefaults)]
+    pub fn get_defaults_js(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.machine.defaults.data()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Choose whether a custom variable a note does not set reads the base
+    /// note's value (see `set_custom_inherited`)
+    #[wasm_bindgen(js_name = setCustomVariableInherited)]
+    pub fn set_custom_variable_inherited_js(&mut self, index: u8, inherited: bool) -> Result<(), JsValue> {
+        self.set_custom_inherited(index, inherited).map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Turn error-bound tracking on or off for notes evaluated from now on
+    /// (see `Evaluator::set_error_tracking`)
+    #[wasm_bindgen(js_name = setErrorTracking)]
+    pub fn set_error_tracking(&mut self, track: bool) {
+        self.machine.track_errors = track;
+    }
+
+    /// Let the floats of corrupted values move by up to `epsilon` before
+    /// `evaluateDirty` reports them as changed (default 0)
+    #[wasm_bindgen(js_name = setChangeEpsilon)]
+    pub fn set_change_epsilon(&mut self, epsilon: f64) {
+        self.change_epsilon = epsilon;
+    }
+
+    /// Check the notes each evaluation caches as `validateCache` does, and
+    /// list their problems in the report's `problems` (default off)
+    #[wasm_bindgen(js_name = setValidateAfterEvaluate)]
+    pub fn set_validate_after_evaluate(&mut self, validate: bool) {
+        self.validate_after_evaluate = validate;
+    }
+
+    /// Limit the instructions one expression may execute (see
+    /// `Evaluator::set_max_instructions`)
+    ///
+    /// `evaluate_dirty` skips a note with an expression that reaches the
+    /// limit, records the error and carries on with the other notes.
+    #[wasm_bindgen(js_name = setMaxInstructions)]
+    pub fn set_max_instructions(&mut self, max: u32) {
+        self.machine.max_instructions = max;
+    }
+
+    /// Instructions executed by the last `evaluateDirty` as
+    /// `{ expressions, instructions, maxInstructions }`
+    #[wasm_bindgen(js_name = getEvaluationStats)]
+    pub fn get_evaluation_stats(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.machine.stats).unwrap_or(JsValue::NULL)
+    }
+
+    /// Error bound of a cached property: 0 for exact (rational or
+    /// symbolic) values, the tracked bound for irrational ones, and
+    /// undefined when untracked or not cached
+    #[wasm_bindgen(js_name = getMaxError)]
+    pub fn get_max_error(&self, note_id: u32, var_index: u8) -> Option<f64> {
+        let data = self.cache.get(&note_id)?.value_at(var_index)?;
+        if !data.corrupted || data.symbolic.is_some() {
+            Some(0.0)
+        } else {
+            data.err
+        }
+    }
+
+    /// The note that stopped the last strict evaluation as
+    /// `{ noteId, variable, op, pc }`, or null
+    #[wasm_bindgen(js_name = getStrictViolation)]
+    pub fn get_strict_violation(&self) -> JsValue {
+        self.strict_violation
+            .as_ref()
+            .map(|violation| serde_wasm_bindgen::to_value(violation).unwrap_or(JsValue::NULL))
+            .unwrap_or(JsValue::NULL)
+    }
+
+    /// The most recent evaluation failures, oldest first, as an array of
+    /// `{ code, message, pc, op, noteId, var }`
+    ///
+    /// Outside strict mode SELF_REFERENCE entries are warnings: the
+    /// property was evaluated, reading the default in place of the variable.
+    #[wasm_bindgen(js_name = getEvaluationErrors)]
+    pub fn get_evaluation_errors(&self) -> JsValue {
+        let errors: Vec<EvalErrorData> =
+            self.errors.iter().map(|e| e.error.to_data(Some((e.note_id, e.var)))).collect();
+        serde_wasm_bindgen::to_value(&errors).unwrap_or(JsValue::NULL)
+    }
+
+    /// Forget the errors returned by `getEvaluationErrors`
+    #[wasm_bindgen(js_name = clearEvaluationErrors)]
+    pub fn clear_evaluation_errors(&mut self) {
+        self.errors.clear();
+    }
+
+    // === Cache Management ===
+
+    /// Get cache size
+    #[wasm_bindgen(getter, js_name = cacheSize)]
+    pub fn cache_size(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Get generation counter
+    #[wasm_bindgen(getter)]
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Check if a note is in the cache
+    #[wasm_bindgen(js_name = hasCachedNote)]
+    pub fn has_cached_note(&self, note_id: u32) -> bool {
+        self.cache.contains_key(&note_id)
+    }
+
+    /// Mark a note and every note that transitively depends on it as dirty
+    /// (needs re-evaluation)
+    #[wasm_bindgen(js_name = markDirty)]
+    pub fn mark_dirty(&mut self, note_id: u32) {
+        self.dirty.insert(note_id);
+        self.dirty.extend(self.graph.get_all_dependents(note_id));
+    }
+
+    /// Mark multiple notes, and their transitive dependents, as dirty
+    #[wasm_bindgen(js_name = markDirtyBatch)]
+    pub fn mark_dirty_batch(&mut self, note_ids: &[u32]) {
+        for &id in note_ids {
+            self.mark_dirty(id);
+        }
+    }
+
+    /// Clear all dirty flags
+    #[wasm_bindgen(js_name = clearDirty)]
+    pub fn clear_dirty(&mut self) {
+        self.dirty.clear();
+    }
+
+    /// Invalidate a single note from the cache
+    #[wasm_bindgen(js_name = invalidateNote)]
+    pub fn invalidate_note(&mut self, note_id: u32) {
+        self.uncache(note_id);
+        self.dirty.insert(note_id);
+        self.bump_generation();
+    }
+
+    /// Clear the entire cache, bytecode store, macros, instrument assignments
+    /// and recorded evaluation errors
+    /// This must clear bytecode_store because when a module is replaced (e.g., after reorder),
+    /// notes with the same IDs may have different expressions/bytecode.
+    #[wasm_bindgen(js_name = invalidateAll)]
+    pub fn invalidate_all(&mut self) {
+        let generation = self.generation;
+        self.removed_at.extend(self.cached_at.drain().map(|(id, _)| (id, generation)));
+        self.cache.clear();
+        self.dirty.clear();
+        self.bytecode_store.clear();
+        self.interned.clear();
+        self.macros.clear();
+        self.instruments.assigned.clear();
+        self.errors.clear();
+        self.graph.clear();
+        self.cycles_stale = true;
+        self.bump_generation();
+    }
+
+    /// Remove a note completely (when deleted from module)
+    #[wasm_bindgen(js_name = removeNote)]
+    pub fn remove_note(&mut self, note_id: u32) {
+        self.uncache(note_id);
+        self.bytecode_store.remove(&note_id);
+        self.dirty.remove(&note_id);
+        self.instruments.assigned.remove(&note_id);
+        self.graph.remove_note(note_id);
+        self.cycles_stale = true;
+        self.bump_generation();
+    }
+
+    // === Snapshots ===
+
+    /// Save the cache, registered bytecode and dirty set for `restore`,
+    /// returning the snapshot's id
+    ///
+    /// Bytecode blobs are shared with the live store, not copied. Taking
+    /// more than `setMaxSnapshots` snapshots drops the least recently taken
+    /// or restored one.
+    #[wasm_bindgen(js_name = snapshot)]
+    pub fn snapshot(&mut self) -> u32 {
+        let id = self.next_snapshot_id;
+        self.next_snapshot_id += 1;
+        self.snapshot_clock += 1;
+        let snapshot = self.capture(self.snapshot_clock);
+        self.snapshots.insert(id, snapshot);
+        self.evict_snapshots();
+        id
+    }
+
+    /// Return the cache, registered bytecode and dirty set to a snapshot,
+    /// which is kept; false if there is no such snapshot
+    #[wasm_bindgen(js_name = restore)]
+    pub fn restore(&mut self, id: u32) -> bool {
+        let Some(snapshot) = self.snapshots.get_mut(&id) else {
+            return false;
+        };
+        self.snapshot_clock += 1;
+        snapshot.last_used = self.snapshot_clock;
+        let cache = snapshot.notes.iter().map(|(&id, note)| (id, note.clone())).collect();
+        self.bytecode_store = snapshot.bytecode.clone();
+        self.dirty = snapshot.dirty.clone();
+        self.rebuild_graph();
+        self.replace_cache(cache);
+        true
+    }
+
+    /// Forget a snapshot; false if there is no such snapshot
+    #[wasm_bindgen(js_name = dropSnapshot)]
+    pub fn drop_snapshot(&mut self, id: u32) -> bool {
+        self.snapshots.remove(&id).is_some()
+    }
+
+    /// Ids of the kept snapshots, in the order they were taken
+    #[wasm_bindgen(js_name = listSnapshots)]
+    pub fn list_snapshots(&self) -> Vec<u32> {
+        self.snapshots.keys().copied().collect()
+    }
+
+    /// Keep at most `max` snapshots (default 32), dropping the least
+    /// recently used ones now if there are more
+    #[wasm_bindgen(js_name = setMaxSnapshots)]
+    pub fn set_max_snapshots(&mut self, max: u32) {
+        self.max_snapshots = max as usize;
+        self.evict_snapshots();
+    }
+
+    // === Staging ===
+
+    /// Start staging: changes from here on (registered expressions and
+    /// macros, instruments, dirty notes, evaluated results and their errors)
+    /// are seen by every read, but can be undone
+    /// with `rollbackStaging` or kept with `commitStaging`
+    ///
+    /// The generation stays put while staging. Staging again before
+    /// committing or rolling back is an error.
+    #[wasm_bindgen(js_name = beginStaging)]
+    pub fn begin_staging_js(&mut self) -> Result<(), JsValue> {
+        self.begin_staging().map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Keep the staged changes, bumping the generation once
+    #[wasm_bindgen(js_name = commitStaging)]
+    pub fn commit_staging_js(&mut self) -> Result<(), JsValue> {
+        self.commit_staging().map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Discard the staged changes, returning the cache, registered
+    /// bytecode and dirty set to where `beginStaging` found them
+    #[wasm_bindgen(js_name = rollbackStaging)]
+    pub fn rollback_staging_js(&mut self) -> Result<(), JsValue> {
+        self.rollback_staging().map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Whether staging is active
+    #[wasm_bindgen(getter, js_name = isStaging)]
+    pub fn is_staging(&self) -> bool {
+        self.staging.is_some()
+    }
+
+    // === Instruments ===
+
+    /// Assign an instrument to a note and mark it and its dependents dirty
+    ///
+    /// Assigning to the base note (0) also changes every note without an
+    /// instrument of its own.
+    #[wasm_bindgen(js_name = setInstrument)]
+    pub fn set_instrument(&mut self, note_id: u32, instrument_id: u32) {
+        let followers: Vec<u32> = match note_id {
+            0 => self.instruments.unassigned(self.bytecode_store.keys()).collect(),
+            _ => Vec::new(),
+        };
+        self.instruments.assigned.insert(note_id, instrument_id);
+        self.mark_dirty(note_id);
+        self.mark_dirty_batch(&followers);
+    }
+
+    /// Set the instrument used by notes without their own when the base
+    /// note has none either, and mark those notes and their dependents dirty
+    #[wasm_bindgen(js_name = setInstrumentDefault)]
+    pub fn set_instrument_default(&mut self, instrument_id: u32) {
+        self.instruments.default = instrument_id;
+        let unassigned: Vec<u32> = self.instruments.unassigned(self.bytecode_store.keys()).collect();
+        self.mark_dirty_batch(&unassigned);
+    }
+
+    /// Renumber notes after a reorder without recompiling (see `remap_note_ids`)
+    ///
+    /// `mapping` is an object from old note id to new note id; ids it does
+    /// not mention keep their value.
+    #[wasm_bindgen(js_name = remapNoteIds)]
+    pub fn remap_note_ids_js(&mut self, mapping: JsValue) -> Result<(), JsValue> {
+        let string_mapping: HashMap<String, u32> = serde_wasm_bindgen::from_value(mapping)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse mapping: {}", e)))?;
+        let mapping = string_mapping
+            .into_iter()
+            .map(|(k, v)| k.parse::<u32>().map(|id| (id, v)))
+            .collect::<Result<HashMap<u32, u32>, _>>()
+            .map_err(|e| JsValue::from_str(&format!("Invalid note id in mapping: {}", e)))?;
+        self.remap_note_ids(&mapping).map_err(|e| JsValue::from_str(&e))
+    }
+
+    // === Memory ===
+
+    /// Get estimated memory usage as a JavaScript object
+    #[wasm_bindgen(js_name = memoryStats)]
+    pub fn memory_stats_js(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.memory_stats()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Release unused capacity in the cache, bytecode store, and stack, and
+    /// drop interned blobs no expression uses any more
+    pub fn compact(&mut self) {
+        self.cache.shrink_to_fit();
+        self.dirty.shrink_to_fit();
+        self.bytecode_store.shrink_to_fit();
+        self.interned.retain(|_, bucket| {
+            bucket.retain(|shared| Arc::strong_count(shared) > 1);
+            !bucket.is_empty()
+        });
+        self.interned.shrink_to_fit();
+        self.machine.stack.shrink_to_fit();
+    }
+
+    /// Interning savings as a JavaScript object (see `intern_stats`)
+    #[wasm_bindgen(js_name = internStats)]
+    pub fn intern_stats_js(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.intern_stats()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Instruction statistics over every registered expression as a
+    /// JavaScript object (see `bytecode_stats`)
+    #[wasm_bindgen(js_name = getBytecodeStats)]
+    pub fn bytecode_stats_js(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.bytecode_stats()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Bytecode sizes of a note's expressions as a JavaScript object, or
+    /// null if the note has no bytecode
+    #[wasm_bindgen(js_name = getNoteBytecodeSize)]
+    pub fn note_bytecode_size_js(&self, note_id: u32) -> JsValue {
+        self.note_bytecode_size(note_id)
+            .and_then(|size| serde_wasm_bindgen::to_value(&size).ok())
+            .unwrap_or(JsValue::NULL)
+    }
+
+    // === Bytecode Registration ===
+
+    /// Turn bytecode validation in `register_expression` on or off
+    ///
+    /// With validation on, expressions that fail `bytecode::validate` are
+    /// not registered and the reason is kept for `lastRegistrationError`.
+    #[wasm_bindgen(js_name = setValidateOnRegister)]
+    pub fn set_validate_on_register(&mut self, validate: bool) {
+        self.validate_on_register = validate;
+    }
+
+    /// Turn interning of registered expressions on or off
+    ///
+    /// While on, an expression `bytecode::equivalent` to one already
+    /// registered shares its blob instead of storing a copy, so thousands of
+    /// generated notes with the same expression hold it once. Turning it off
+    /// keeps existing sharing but stops pooling new expressions.
+    #[wasm_bindgen(js_name = setInternExpressions)]
+    pub fn set_intern_expressions(&mut self, intern: bool) {
+        self.intern = intern;
+        if !intern {
+            self.interned.clear();
+        }
+    }
+
+    /// Why the last `registerExpression` call was rejected, or undefined
+    #[wasm_bindgen(getter, js_name = lastRegistrationError)]
+    pub fn last_registration_error(&self) -> Option<String> {
+        self.registration_error.clone()
+    }
+
+    /// Register bytecode for a single expression
+    #[wasm_bindgen(js_name = registerExpression)]
+    pub fn register_expression(
+        &mut self,
+        note_id: u32,
+        var_index: u8,
+        bytecode: &[u8],
+        length: usize,
+    ) {
+        self.registration_error = None;
+        if self.validate_on_register {
+            if let Err(e) = validate_with_macros(bytecode, length, &self.macros) {
+                log_warn!("Rejected expression for note {} variable {}: {}", note_id, var_index, e);
+                self.registration_error = Some(e.to_string());
+                return;
+            }
+        }
+        if let Some(var) = Var::from_byte(var_index) {
+            self.store_expression(note_id, var, bytecode, length);
+        } else if is_custom_var(var_index) {
+            self.store_custom_expression(note_id, var_index, bytecode, length);
+        }
+    }
+
+    /// Register a macro body for CALL_MACRO, replacing any with the same id
+    ///
+    /// The body runs inline on the caller's stack, so it may use values the
+    /// caller pushed; it may not call a macro itself. Every registered note
+    /// is marked dirty, since any of them may call the macro.
+    #[wasm_bindgen(js_name = registerMacro)]
+    pub fn register_macro(&mut self, macro_id: u16, bytecode: &[u8], length: usize) -> Result<(), JsValue> {
+        self.macros
+            .register(macro_id, bytecode, length)
+            .map_err(|e| JsValue::from_str(&format!("Rejected macro {}: {}", macro_id, e)))?;
+        self.dirty.extend(self.bytecode_store.keys());
+        self.rebuild_graph();
+        Ok(())
+    }
+
+    /// Register all expressions for a note at once
+    #[wasm_bindgen(js_name = registerNote)]
+    pub fn register_note(&mut self, note_id: u32, expressions: JsValue) -> Result<(), JsValue> {
+        let exprs: JsExpressions = serde_wasm_bindgen::from_value(expressions)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse expressions: {}", e)))?;
+
+        self.bytecode_store.entry(note_id).or_default();
+        if let Some(e) = exprs.start_time {
+            self.store_expression(note_id, Var::StartTime, &e.bytecode, e.length);
+        }
+        if let Some(e) = exprs.duration {
+            self.store_expression(note_id, Var::Duration, &e.bytecode, e.length);
+        }
+        if let Some(e) = exprs.frequency {
+            self.store_expression(note_id, Var::Frequency, &e.bytecode, e.length);
+        }
+        if let Some(e) = exprs.tempo {
+            self.store_expression(note_id, Var::Tempo, &e.bytecode, e.length);
+        }
+        if let Some(e) = exprs.beats_per_measure {
+            self.store_expression(note_id, Var::BeatsPerMeasure, &e.bytecode, e.length);
+        }
+        if let Some(e) = exprs.measure_length {
+            self.store_expression(note_id, Var::MeasureLength, &e.bytecode, e.length);
+        }
+
+        // Mark as dirty since bytecode changed
+        self.dirty.insert(note_id);
+        Ok(())
+    }
+
+    // === Evaluation ===
+
+    /// Evaluate all dirty notes in topological order, returning
+    /// `{ evaluated, changed, changedVars }` (see `evaluate_dirty`)
+    #[wasm_bindgen(js_name = evaluateDirty)]
+    pub fn evaluate_dirty_js(&mut self, sorted_ids: &[u32]) -> JsValue {
+        self.evaluate_dirty(sorted_ids).to_js()
+    }
+
+    /// Evaluate all dirty notes in dependency order (see
+    /// `evaluate_dirty_auto`), returning `{ evaluated, changed, changedVars }`
+    #[wasm_bindgen(js_name = evaluateDirtyAuto)]
+    pub fn evaluate_dirty_auto_js(&mut self) -> JsValue {
+        self.evaluate_dirty_auto().to_js()
+    }
+
+    /// Evaluate just what one note needs (see `evaluate_closure`), returning
+    /// its `EvaluatedNote`, or null if it could not be cached
+    #[wasm_bindgen(js_name = evaluateClosure)]
+    pub fn evaluate_closure_js(&mut self, target_id: u32) -> JsValue {
+        self.evaluate_closure(target_id);
+        self.cache
+            .get(&target_id)
+            .and_then(|note| serde_wasm_bindgen::to_value(note).ok())
+            .unwrap_or(JsValue::NULL)
+    }
+
+    /// Evaluate notes with note 0's values replaced by `base_override` (any
+    /// subset of `{ startTime, duration, frequency, tempo, beatsPerMeasure,
+    /// measureLength }` as `{ n, d }`), returning a Map of note id to the
+    /// would-be `EvaluatedNote` and leaving the cache alone (see
+    /// `evaluate_what_if`)
+    #[wasm_bindgen(js_name = evaluateWhatIf)]
+    pub fn evaluate_what_if_js(&self, base_override: JsValue, note_ids: &[u32]) -> Result<JsValue, JsValue> {
+        let data: DefaultsData = serde_wasm_bindgen::from_value(base_override)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse base override: {}", e)))?;
+        let results = self.evaluate_what_if(&data, note_ids).map_err(|e| JsValue::from_str(&e))?;
+        serde_wasm_bindgen::to_value(&results).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Evaluate a single note using internal cache
+    /// Tracks corruption flags for each property
+    ///
+    /// Returns false, leaving the cache alone, for unknown notes, strict
+    /// violations and notes that reach the instruction limit.
+    #[wasm_bindgen(js_name = evaluateNoteInternal)]
+    pub fn evaluate_note_internal(&mut self, note_id: u32) -> bool {
+        self.evaluate_note(note_id).is_some()
+    }
+
+    // === Cache Read ===
+
+    /// Get a single cached value
+    #[wasm_bindgen(js_name = getCachedValue)]
+    pub fn get_cached_value(&self, note_id: u32, var_index: u8) -> JsValue {
+        self.cache
+            .get(&note_id)
+            .and_then(|note| note.value_at(var_index))
+            .map(|fd| {
+                serde_wasm_bindgen::to_value(fd).unwrap_or(JsValue::NULL)
+            })
+            .unwrap_or(JsValue::NULL)
+    }
+
+    /// Get all cached values for a note
+    #[wasm_bindgen(js_name = getCachedNote)]
+    pub fn get_cached_note(&self, note_id: u32) -> JsValue {
+        self.cache
+            .get(&note_id)
+            .map(|note| {
+                serde_wasm_bindgen::to_value(note).unwrap_or(JsValue::NULL)
+            })
+            .unwrap_or(JsValue::NULL)
+    }
+
+    /// Re-run one registered expression of a note with tracing, returning
+    /// `{ result, trace }` or `{ error, trace }` (see
+    /// `Evaluator::evaluateExpressionTraced`), or null if there is none
+    #[wasm_bindgen(js_name = traceNote)]
+    pub fn trace_note_js(&self, note_id: u32, var_index: u8) -> JsValue {
+        let Some(var) = Var::from_byte(var_index) else {
+            return JsValue::NULL;
+        };
+        self.trace_note(note_id, var)
+            .and_then(|(result, trace)| {
+                serde_wasm_bindgen::to_value(&TracedEvaluation::new(result, trace, Some((note_id, var as u8)))).ok()
+            })
+            .unwrap_or(JsValue::NULL)
+    }
+
+    /// Where each irrational property of a note became irrational, as an
+    /// array of { variable, op, pc } (empty for rational or unknown notes)
+    #[wasm_bindgen(js_name = getCorruptionReport)]
+    pub fn get_corruption_report(&self, note_id: u32) -> JsValue {
+        let sources = self.cache.get(&note_id).map_or(&[][..], |note| &note.corruption_sources);
+        serde_wasm_bindgen::to_value(sources).unwrap_or(JsValue::NULL)
+    }
+
+    /// Cached values no note can play, as an array of `{ id, var, problem }`
+    /// sorted by id (see `CacheProblem`)
+    #[wasm_bindgen(js_name = validateCache)]
+    pub fn validate_cache_js(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.validate_cache()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Cached notes with any non-rational value, as an array of
+    /// `{ id, corruptionFlags }` sorted by id (see `corrupted_notes`)
+    #[wasm_bindgen(js_name = getCorruptedNotes)]
+    pub fn get_corrupted_notes(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.corrupted_notes()).unwrap_or(JsValue::NULL)
+    }
+
+    /// How many cached notes hold a non-rational value of each variable, as
+    /// `{ notes, startTime, duration, ..., custom }`
+    #[wasm_bindgen(js_name = getCorruptionSummary)]
+    pub fn get_corruption_summary(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.corruption_summary()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Whether a cached property (built-in or custom variable index) holds
+    /// an irrational or symbolic value; false for uncached notes
+    #[wasm_bindgen(js_name = isNoteCorrupted)]
+    pub fn is_note_corrupted(&self, note_id: u32, var_index: u8) -> bool {
+        self.cache.get(&note_id).is_some_and(|note| match Var::from_byte(var_index) {
+            Some(var) => note.is_corrupted(var),
+            None => note.is_custom_corrupted(var_index),
+        })
+    }
+
+    /// Export playback events within a time window as sample positions
+    ///
+    /// Notes starting in [from_time, to_time) are included. With `clip` set,
+    /// notes that overlap the window edges are included too and trimmed to
+    /// the window. The end sample is rounded separately from the start, so
+    /// back-to-back notes never leave a gap or overlap.
+    #[wasm_bindgen(js_name = exportAudioEvents)]
+    pub fn export_audio_events_js(
+        &self,
+        sample_rate: u32,
+        from_time: f64,
+        to_time: f64,
+        clip: bool,
+    ) -> JsValue {
+        let events = self.export_audio_events(sample_rate, from_time, to_time, clip);
+        serde_wasm_bindgen::to_value(&events).unwrap_or(JsValue::NULL)
+    }
+
+    /// Cached notes sounding at some point of [start_seconds, end_seconds),
+    /// as an array of `{ id, startTime, duration, frequency }` sorted by
+    /// start time, then id (see `notes_in_range`)
+    #[wasm_bindgen(js_name = getNotesInRange)]
+    pub fn get_notes_in_range(&self, start_seconds: f64, end_seconds: f64) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.notes_in_range(start_seconds, end_seconds)).unwrap_or(JsValue::NULL)
+    }
+
+    /// Cached notes starting in [start_seconds, end_seconds), in the form of
+    /// `getNotesInRange`
+    #[wasm_bindgen(js_name = getNotesStartingInRange)]
+    pub fn get_notes_starting_in_range(&self, start_seconds: f64, end_seconds: f64) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.notes_starting_in_range(start_seconds, end_seconds)).unwrap_or(JsValue::NULL)
+    }
+
+    /// Where the cached notes start and end as `{ start, end, lastNoteId }`,
+    /// or null when no note has both a start time and a duration
+    ///
+    /// Computed once per change to the cache.
+    #[wasm_bindgen(js_name = getModuleExtent)]
+    pub fn get_module_extent(&self) -> JsValue {
+        self.cache
+            .extent()
+            .and_then(|extent| serde_wasm_bindgen::to_value(&extent.to_f64()).ok())
+            .unwrap_or(JsValue::NULL)
+    }
+
+    /// `getModuleExtent` with `start` and `end` as `{ s, n, d, ... }`, exact
+    /// for rational times
+    #[wasm_bindgen(js_name = getModuleExtentExact)]
+    pub fn get_module_extent_exact(&self) -> JsValue {
+        self.cache
+            .extent()
+            .and_then(|extent| serde_wasm_bindgen::to_value(extent).ok())
+            .unwrap_or(JsValue::NULL)
+    }
+
+    /// Start time, duration and frequency of every cached note that has all
+    /// three, as typed arrays (see `playback_arrays`)
+    #[wasm_bindgen(js_name = exportPlaybackArrays)]
+    pub fn export_playback_arrays(&self) -> JsValue {
+        self.playback_arrays(0).to_js()
+    }
+
+    /// `exportPlaybackArrays` limited to notes stored since `generation`
+    /// was current, with `removed` listing the notes to stop playing
+    #[wasm_bindgen(js_name = exportPlaybackArraysSince)]
+    pub fn export_playback_arrays_since(&self, generation: u64) -> JsValue {
+        self.playback_arrays(generation).to_js()
+    }
+
+    /// Export entire cache (for persistence/debug)
+    #[wasm_bindgen(js_name = exportCache)]
+    pub fn export_cache(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(self.cache.notes()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Export the cache in the compact binary form of `codec` (a Uint8Array
+    /// in JS); much cheaper than `exportCache` for large modules
+    #[wasm_bindgen(js_name = exportCacheBinary)]
+    pub fn export_cache_binary(&self) -> Vec<u8> {
+        crate::codec::encode_cache(self.cache.notes())
+    }
+
+    /// Export every registered expression in the binary form of
+    /// `codec::encode_bytecode` (a Uint8Array in JS)
+    #[wasm_bindgen(js_name = exportBytecodeBinary)]
+    pub fn export_bytecode_binary(&self) -> Vec<u8> {
+        crate::codec::encode_bytecode(&self.bytecode_store)
+    }
+
+    /// Register every note of a buffer written by `exportBytecodeBinary`
+    /// (see `register_notes_binary`), returning how many it held
+    #[wasm_bindgen(js_name = registerNotesBinary)]
+    pub fn register_notes_binary_js(&mut self, buffer: &[u8]) -> Result<u32, JsValue> {
+        self.register_notes_binary(buffer)
+            .map_err(|e| JsValue::from_str(&format!("Failed to decode bytecode: {}", e)))
+    }
+
+    /// Replace the cache with one written by `exportCacheBinary`
+    #[wasm_bindgen(js_name = importCacheBinary)]
+    pub fn import_cache_binary(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
+        let cache = crate::codec::decode_cache(bytes)
+            .map_err(|e| JsValue::from_str(&format!("Failed to decode cache: {}", e)))?
+            .into_iter()
+            .collect();
+        self.replace_cache(cache);
+        Ok(())
+    }
+
+    /// Import cache from JSON (for undo/redo snapshots), as a Map from
+    /// numeric note id or an object keyed by note id
+    ///
+    /// An invalid key or entry fails the import, leaving the cache alone.
+    #[wasm_bindgen(js_name = importCache)]
+    pub fn import_cache(&mut self, cache_json: JsValue) -> Result<(), JsValue> {
+        if cache_json.is_undefined() || cache_json.is_null() {
+            return Err(JsValue::from_str("Failed to parse cache: expected a Map or an object"));
+        }
+        let cache = cache_from_js(cache_json)?.into_iter().collect();
+        self.replace_cache(cache);
+        Ok(())
+    }
+
+    /// Import some notes from JSON in the `importCache` shape, keeping the
+    /// rest of the cache (see `import_cache_merge`)
+    ///
+    /// Accepts a Map from numeric note id or an object, like `importCache`.
+    /// Returns `{ imported, skipped, invalid }`, with `invalid` a Map of each
+    /// rejected key to the reason.
+    #[wasm_bindgen(js_name = importCacheMerge)]
+    pub fn import_cache_merge_js(&mut self, cache_json: JsValue, overwrite: bool) -> Result<JsValue, JsValue> {
+        let merge = self.merge_cache_entries(cache_entries_from_js(&cache_json)?, overwrite);
+        serde_wasm_bindgen::to_value(&merge).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+}
+
+/// Evaluated notes of a PersistentEvaluator
+///
+/// A property is converted from FractionData to a Value the first time an
+/// expression reads it and kept, so a note that thousands of others
+/// reference is converted once per evaluation of it, not once per
+/// reference. `getCachedNote` and `exportCache` return the FractionData.
+///
+/// Window queries use a start-time index, and the module extent is
+/// computed, on first use after the cache changes.
+#[derive(Default)]
+struct NoteCache {
+    notes: HashMap<u32, EvaluatedNote>,
+    values: HashMap<u32, [OnceLock<Value>; 6]>,
+    timeline: OnceLock<Timeline>,
+    extent: OnceLock<Option<ModuleExtentExact>>,
+}
+
+impl NoteCache {
+    fn get(&self, note_id: &u32) -> Option<&EvaluatedNote> {
+        self.notes.get(note_id)
+    }
+
+    /// A property as a Value
+    fn value(&self, note_id: u32, var: Var) -> Option<&Value> {
+        let data = self.notes.get(&note_id)?.get_var(var)?;
+        Some(self.values[&note_id][var as usize].get_or_init(|| data.to_value()))
+    }
+
+    fn insert(&mut self, note_id: u32, note: EvaluatedNote) {
+        self.values.insert(note_id, Default::default());
+        self.notes.insert(note_id, note);
+        self.forget_derived();
+    }
+
+    fn remove(&mut self, note_id: &u32) {
+        self.notes.remove(note_id);
+        self.values.remove(note_id);
+        self.forget_derived();
+    }
+
+    fn contains_key(&self, note_id: &u32) -> bool {
+        self.notes.contains_key(note_id)
+    }
+
+    fn len(&self) -> usize {
+        self.notes.len()
+    }
+
+    fn clear(&mut self) {
+        self.notes.clear();
+        self.values.clear();
+        self.forget_derived();
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&u32, &EvaluatedNote)> {
+        self.notes.iter()
+    }
+
+    /// Remove every note, yielding them
+    fn drain(&mut self) -> impl Iterator<Item = (u32, EvaluatedNote)> + '_ {
+        self.values.clear();
+        self.forget_derived();
+        self.notes.drain()
+    }
+
+    /// Drop what was computed from the notes
+    fn forget_derived(&mut self) {
+        self.timeline.take();
+        self.extent.take();
+    }
+
+    fn extent(&self) -> Option<&ModuleExtentExact> {
+        self.extent.get_or_init(|| ModuleExtentExact::new(&self.notes)).as_ref()
+    }
+
+    fn timeline(&self) -> &Timeline {
+        self.timeline.get_or_init(|| Timeline::new(&self.notes))
+    }
+
+    fn timing(&self, &(start_time, duration, id): &(f64, f64, u32)) -> NoteTiming {
+        let frequency = self.notes[&id].frequency.as_ref().map(FractionData::to_f64);
+        NoteTiming { id, start_time, duration, frequency }
+    }
+
+    fn notes(&self) -> &HashMap<u32, EvaluatedNote> {
+        &self.notes
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.notes.shrink_to_fit();
+        self.values.shrink_to_fit();
+    }
+
+    /// Estimated heap size, from allocated capacity
+    fn heap_bytes(&self) -> usize {
+        hash_map_bytes::<u32, EvaluatedNote>(self.notes.capacity())
+            + hash_map_bytes::<u32, [OnceLock<Value>; 6]>(self.values.capacity())
+            + self.timeline.get().map_or(0, |timeline| timeline.entries.capacity() * std::mem::size_of::<(f64, f64, u32)>())
+    }
+}
+
+impl FromIterator<(u32, EvaluatedNote)> for NoteCache {
+    fn from_iter<I: IntoIterator<Item = (u32, EvaluatedNote)>>(notes: I) -> Self {
+        let mut cache = NoteCache::default();
+        for (note_id, note) in notes {
+            cache.insert(note_id, note);
+        }
+        cache
+    }
+}
+
+/// Read-only view of evaluated notes used while evaluating one note
+///
+/// The partial result of the note being evaluated shadows its cache entry,
+/// so later variables can read earlier ones from the same note.
+struct NoteView<'a> {
+    cache: &'a NoteCache,
+    current: Option<(u32, &'a EvaluatedNote)>,
+    instruments: &'a InstrumentTable,
+    macros: &'a MacroTable,
+}
+
+impl CacheLookup for NoteView<'_> {
+    fn value(&self, note_id: u32, var: Var) -> Option<Value> {
+        match self.current {
+            Some((id, note)) if id == note_id => note.get_var(var).map(FractionData::to_value),
+            _ => self.cache.value(note_id, var).cloned(),
+        }
+    }
+
+    fn custom_value(&self, note_id: u32, index: u8) -> Option<Value> {
+        let note = match self.current {
+            Some((id, note)) if id == note_id => Some(note),
+            _ => self.cache.get(&note_id),
+        };
+        note.and_then(|note| note.custom.get(&index)).map(FractionData::to_value)
+    }
+
+    fn instrument(&self, note_id: u32) -> u32 {
+        self.instruments.instrument(note_id)
+    }
+
+    fn macros(&self) -> Option<&MacroTable> {
+        Some(self.macros)
+    }
+}
+
+/// Instructions one evaluation may execute unless `setMaxInstructions`
+/// says otherwise
+const DEFAULT_MAX_INSTRUCTIONS: u32 = 1_000_000;
+
+/// Snapshots a `PersistentEvaluator` keeps unless `setMaxSnapshots` says
+/// otherwise
+const DEFAULT_MAX_SNAPSHOTS: usize = 32;
+
+/// Evaluation errors a `PersistentEvaluator` keeps for `getEvaluationErrors`
+const MAX_EVALUATION_ERRORS: usize = 64;
+
+/// CALL_MACROs that may be running at once: a macro cannot call another
+const MAX_MACRO_DEPTH: usize = 1;
+
+/// Value stack used by PersistentEvaluator
+///
+/// Kept separate from the cache so notes can be evaluated against a shared,
+/// immutable cache (see `compute_note`).
+struct StackMachine {
+    /// Evaluation stack (supports both rational and irrational values)
+    stack: Vec<Value>,
+    /// Maximum stack size (for safety)
+    max_stack_size: usize,
+    /// What the last run observed besides its result
+    last_run: RunInfo,
+    /// Handling of NaN and infinite results
+    non_finite: NonFinitePolicy,
+    /// n/d approximation of non-rational note properties
+    approximation: ApproximationConfig,
+    /// Track error bounds of irrational results
+    track_errors: bool,
+    /// Fallback values of missing references
+    defaults: VariableDefaults,
+    /// Instructions one evaluation may execute
+    max_instructions: u32,
+    /// Custom variables that fall back to the base note's value
+    inherited_custom: u32,
+    /// Note variables expressions may not read
+    in_progress: InProgress,
+    /// Instructions executed since the last `take_stats`
+    stats: EvaluationStats,
+}
+
+impl StackMachine {
+    fn new() -> StackMachine {
+        StackMachine {
+            stack: Vec::with_capacity(32),
+            max_stack_size: 1024,
+            last_run: RunInfo::default(),
+            non_finite: NonFinitePolicy::default(),
+            approximation: ApproximationConfig::default(),
+            track_errors: false,
+            defaults: VariableDefaults::default(),
+            max_instructions: DEFAULT_MAX_INSTRUCTIONS,
+            inherited_custom: 0,
+            in_progress: InProgress::default(),
+            stats: EvaluationStats::default(),
+        }
+    }
+
+    /// A new machine with this one's settings and an empty stack and stats
+    fn with_settings(&self) -> StackMachine {
+        StackMachine {
+            non_finite: self.non_finite,
+            approximation: self.approximation,
+            track_errors: self.track_errors,
+            defaults: self.defaults.clone(),
+            max_instructions: self.max_instructions,
+            inherited_custom: self.inherited_custom,
+            in_progress: InProgress { current: None, ..self.in_progress.clone() },
+            ..StackMachine::new()
+        }
+    }
+
+    /// Sources of non-rational values in the last evaluation
+    fn corruption_sources(&self, var: Var) -> Vec<CorruptionSource> {
+        self.last_run.corruption_sources(var)
+    }
+
+    /// Evaluate bytecode against a view of the evaluated notes
+    /// Returns a Value which may be rational or irrational
+    fn run(&mut self, bytecode: &[u8], length: usize, notes: &NoteView) -> Result<Value, EvalError> {
+        self.run_with(bytecode, length, notes, &mut NoTrace)
+    }
+
+    fn run_with<T: Tracer>(&mut self, bytecode: &[u8], length: usize, notes: &NoteView, tracer: &mut T) -> Result<Value, EvalError> {
+        // Empty expressions do not count towards the stats
+        if length == 0 {
+            return Ok(Value::rational(0, 1));
+        }
+
+        let config = EvalConfig {
+            max_stack_size: self.max_stack_size,
+            strict: false,
+            non_finite: self.non_finite,
+            track_errors: self.track_errors,
+            defaults: &self.defaults,
+            max_instructions: self.max_instructions,
+            inherited_custom: self.inherited_custom,
+            in_progress: Some(&self.in_progress),
+        };
+        let result = run(&mut self.stack, bytecode, length, notes, &config, &mut self.last_run, tracer);
+        self.stats.record(self.last_run.executed);
+        result
+    }
+
+    /// Evaluate variable `var` (an index, built-in or custom) of a note, or
+    /// None after adding why it failed to `errors`
+    ///
+    /// A read of a variable still being evaluated that fell back to its
+    /// default is added to `errors` too, though the value is kept.
+    fn run_var(
+        &mut self,
+        note_id: u32,
+        var: u8,
+        bytecode: &[u8],
+        length: usize,
+        notes: &NoteView,
+        errors: &mut Vec<NoteEvalError>,
+    ) -> Option<Value> {
+        self.in_progress.current = Some((note_id, var));
+        self.last_run.self_reference = None;
+        let result = self.run(bytecode, length, notes);
+        self.in_progress.current = None;
+        if let (Ok(_), Some((op, pc, ref_id, ref_var))) = (&result, self.last_run.self_reference) {
+            let error = EvalError::SelfReference { pc, op, note_id: ref_id, var: ref_var };
+            errors.push(NoteEvalError { note_id, var, error });
+        }
+        recorded(result, note_id, var, errors)
+    }
+}
+
+/// The value of an evaluation, or None after adding why it failed to `errors`
+fn recorded(result: Result<Value, EvalError>, note_id: u32, var: u8, errors: &mut Vec<NoteEvalError>) -> Option<Value> {
+    result.map_err(|error| errors.push(NoteEvalError { note_id, var, error })).ok()
+}
+
+/// Evaluate all expressions of one note against a read-only cache
+///
+/// The cache is never written, so notes whose dependencies are already
+/// evaluated can be computed independently of each other. Properties that
+/// fail to evaluate are left unset and their errors added to `errors`.
+fn compute_note(
+    machine: &mut StackMachine,
+    cache: &NoteCache,
+    instruments: &InstrumentTable,
+    macros: &MacroTable,
+    note_id: u32,
+    bytecode: &NoteBytecode,
+    errors: &mut Vec<NoteEvalError>,
+) -> EvaluatedNote {
+    let stale = NoteView { cache, current: None, instruments, macros };
+    let mut result = EvaluatedNote { instrument: Some(instruments.instrument(note_id)), ..Default::default() };
+    let mut corruption_flags: u16 = 0;
+
+    // Evaluate in dependency order
+    // 1. Variables that don't typically depend on others
+    if let Some((bc, len)) = bytecode.get_expr(Var::Tempo) {
+        if let Some(val) = machine.run_var(note_id, Var::Tempo as u8, bc, len, &stale, errors) {
+            if val.is_corrupted() {
+                corruption_flags |= corruption_flag_for_var(Var::Tempo as u8);
+                result.corruption_sources.extend(machine.corruption_sources(Var::Tempo));
+            }
+            result.tempo = Some(machine.approximation.data(Var::Tempo, &val));
+        }
+    }
+
+    if let Some((bc, len)) = bytecode.get_expr(Var::BeatsPerMeasure) {
+        if let Some(val) = machine.run_var(note_id, Var::BeatsPerMeasure as u8, bc, len, &stale, errors) {
+            if val.is_corrupted() {
+                corruption_flags |= corruption_flag_for_var(Var::BeatsPerMeasure as u8);
+                result.corruption_sources.extend(machine.corruption_sources(Var::BeatsPerMeasure));
+            }
+            result.beats_per_measure = Some(machine.approximation.data(Var::BeatsPerMeasure, &val));
+        }
+    }
+
+    if let Some((bc, len)) = bytecode.get_expr(Var::Frequency) {
+        if let Some(val) = machine.run_var(note_id, Var::Frequency as u8, bc, len, &stale, errors) {
+            if val.is_corrupted() {
+                corruption_flags |= corruption_flag_for_var(Var::Frequency as u8);
+                result.corruption_sources.extend(machine.corruption_sources(Var::Frequency));
+            }
+            result.frequency = Some(machine.approximation.data(Var::Frequency, &val));
+        }
+    }
+
+    // 2. measureLength depends on tempo/beatsPerMeasure
+    // From here on the partial result is visible for self-reference
+    result.corruption_flags = corruption_flags;
+
+    if let Some((bc, len)) = bytecode.get_expr(Var::MeasureLength) {
+        let notes = NoteView { cache, current: Some((note_id, &result)), instruments, macros };
+        if let Some(val) = machine.run_var(note_id, Var::MeasureLength as u8, bc, len, &notes, errors) {
+            if val.is_corrupted() {
+                corruption_flags |= corruption_flag_for_var(Var::MeasureLength as u8);
+                result.corruption_sources.extend(machine.corruption_sources(Var::MeasureLength));
+            }
+            result.measure_length = Some(machine.approximation.data(Var::MeasureLength, &val));
+            result.corruption_flags = corruption_flags;
+        }
+    }
+
+    // 3. startTime and duration may depend on measureLength/tempo
+    if let Some((bc, len)) = bytecode.get_expr(Var::StartTime) {
+        let notes = NoteView { cache, current: Some((note_id, &result)), instruments, macros };
+        if let Some(val) = machine.run_var(note_id, Var::StartTime as u8, bc, len, &notes, errors) {
+            if val.is_corrupted() {
+                corruption_flags |= corruption_flag_for_var(Var::StartTime as u8);
+                result.corruption_sources.extend(machine.corruption_sources(Var::StartTime));
+            }
+            result.start_time = Some(machine.approximation.data(Var::StartTime, &val));
+            result.corruption_flags = corruption_flags;
+        }
+    }
+
+    if let Some((bc, len)) = bytecode.get_expr(Var::Duration) {
+        let notes = NoteView { cache, current: Some((note_id, &result)), instruments, macros };
+        if let Some(val) = machine.run_var(note_id, Var::Duration as u8, bc, len, &notes, errors) {
+            if val.is_corrupted() {
+                corruption_flags |= corruption_flag_for_var(Var::Duration as u8);
+                result.corruption_sources.extend(machine.corruption_sources(Var::Duration));
+            }
+            result.duration = Some(machine.approximation.data(Var::Duration, &val));
+        }
+    }
+
+    // 4. If measureLength wasn't explicitly defined but this is a measure note,
+    // compute it from beatsPerMeasure and tempo
+    let is_measure_note = result.start_time.is_some()
+        && result.duration.is_none()
+        && result.frequency.is_none();
+
+    if result.measure_length.is_none() && (is_measure_note || note_id == 0) {
+        // The base note's own values are already in `result`
+        let base = |var| if note_id == 0 { None } else { cache.value(0, var).cloned() };
+        let beats = result
+            .beats_per_measure
+            .as_ref()
+            .map(|f| f.to_value())
+            .or_else(|| base(Var::BeatsPerMeasure))
+            .unwrap_or_else(|| machine.defaults.value(Var::BeatsPerMeasure));
+
+        let tempo = result
+            .tempo
+            .as_ref()
+            .map(|f| f.to_value())
+            .or_else(|| base(Var::Tempo))
+            .unwrap_or_else(|| machine.defaults.value(Var::Tempo));
+
+        // measureLength = beatsPerMeasure / tempo * 60
+        let sixty = Value::rational(60, 1);
+        let measure_len = beats.mul(&sixty).div(&tempo);
+        if measure_len.is_corrupted() {
+            corruption_flags |= corruption_flag_for_var(Var::MeasureLength as u8);
+        }
+        result.measure_length = Some(machine.approximation.data(Var::MeasureLength, &measure_len));
+    }
+
+    // Final result with all corruption flags
+    result.corruption_flags = corruption_flags;
+
+    // 5. Custom variables, in index order, see every built-in one
+    for (&index, (bc, len)) in &bytecode.custom {
+        let notes = NoteView { cache, current: Some((note_id, &result)), instruments, macros };
+        let Some(val) = machine.run_var(note_id, index, bc, *len, &notes, errors) else {
+            continue;
+        };
+        if val.is_corrupted() {
+            result.custom_corruption_flags |= custom_flag_for_var(index);
+            let run = &machine.last_run;
+            result.corruption_sources.extend(CorruptionSource::for_var(&label(index), run.first_corruption, run.first_lossy));
+        }
+        result.custom.insert(index, FractionData::from_value(&val));
+    }
+    result
+}
+
+/// Levels smaller than this are evaluated on the calling thread
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+const MIN_PARALLEL_LEVEL: usize = 64;
+
+#[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+impl PersistentEvaluator {
+    /// Evaluate notes level by level, computing each level in parallel
+    ///
+    /// `levels` must be topological layers (see
+    /// `DependencyGraph::get_evaluation_levels`): notes in one level may only
+    /// depend on notes in earlier levels. Each level is evaluated against the
+    /// cache as left by the previous levels and merged afterwards, so results
+    /// are identical to `evaluate_dirty` over the flattened order. Notes run
+    /// on the rayon thread pool, each worker with its own machine.
+    pub fn evaluate_levels_par(&mut self, levels: &[Vec<u32>]) -> EvaluationReport {
+        use rayon::prelude::*;
+
+        self.refresh_reference_cycles();
+        // Strict evaluation stops at the first offending note in order
+        if self.strict {
+            return self.evaluate_dirty(&levels.concat());
+        }
+        let mut report = EvaluationReport::default();
+        self.machine.stats = EvaluationStats::default();
+
+        for level in levels {
+            if level.len() < MIN_PARALLEL_LEVEL {
+                for &note_id in level {
+                    if let Some(changed_vars) = self.evaluate_note(note_id) {
+                        report.record(note_id, changed_vars);
+                    }
+                }
+                continue;
+            }
+
+            let cache = &self.cache;
+            let instruments = &self.instruments;
+            let macros = &self.macros;
+            let store = &self.bytecode_store;
+            let template = &self.machine;
+            let results: Vec<(u32, EvaluatedNote, Vec<NoteEvalError>, EvaluationStats)> = level
+                .par_iter()
+                .map_init(
+                    || template.with_settings(),
+                    |machine, &id| {
+                        let bytecode = store.get(&id)?;
+                        let mut errors = Vec::new();
+                        let note = compute_note(machine, cache, instruments, macros, id, bytecode, &mut errors);
+                        Some((id, note, errors, std::mem::take(&mut machine.stats)))
+                    },
+                )
+                .flatten()
+                .collect();
+
+            for (id, note, errors, stats) in results {
+                self.machine.stats.merge(&stats);
+                let exhausted = errors.iter().any(|e| matches!(e.error, EvalError::FuelExhausted { .. }));
+                self.record_errors(errors);
+                if !exhausted {
+                    let changed_vars = self.cache_note(id, note);
+                    report.record(id, changed_vars);
+                }
+            }
+        }
+
+        self.dirty.clear();
+        self.bump_generation();
+        self.validate_evaluated(&mut report);
+        report
+    }
+}
+
+impl Default for PersistentEvaluator {
+    fn default() -> Self {
+        PersistentEvaluator::new()
+    }
+}
+
+impl PersistentEvaluator {
+    /// Estimate memory held by this evaluator
+    ///
+    /// Estimates are based on allocated capacity (not just length), so they
+    /// drop after `compact()` releases unused space.
+    pub fn memory_stats(&self) -> EvaluatorMemoryStats {
+        let cache_bytes = self.cache.heap_bytes();
+        // Shared blobs are counted once
+        let blobs: HashMap<*const u8, usize> = self
+            .bytecode_store
+            .values()
+            .flat_map(NoteBytecode::all_exprs)
+            .map(|(bytes, _)| (bytes.as_ptr(), bytes.len()))
+            .collect();
+        let bytecode_bytes = hash_map_bytes::<u32, NoteBytecode>(self.bytecode_store.capacity())
+            + hash_map_bytes::<u64, Vec<Arc<[u8]>>>(self.interned.capacity())
+            + blobs.values().sum::<usize>();
+        let dirty_bytes = hash_map_bytes::<u32, ()>(self.dirty.capacity());
+        let stack_bytes = self.machine.stack.capacity() * std::mem::size_of::<Value>();
+
+        EvaluatorMemoryStats {
+            cache_entries: self.cache.len(),
+            cache_bytes,
+            bytecode_entries: self.bytecode_store.len(),
+            bytecode_bytes,
+            dirty_entries: self.dirty.len(),
+            dirty_bytes,
+            stack_bytes,
+            total_bytes: cache_bytes + bytecode_bytes + dirty_bytes + stack_bytes,
+        }
+    }
+
+    /// Register every note of a buffer written by `export_bytecode_binary`,
+    /// as `registerNote` would, returning how many it held
+    ///
+    /// The whole buffer is decoded first, so a malformed one registers
+    /// nothing.
+    pub fn register_notes_binary(&mut self, buffer: &[u8]) -> Result<u32, String> {
+        let notes = crate::codec::decode_bytecode(buffer)?;
+        let count = notes.len() as u32;
+        for (note_id, note) in notes {
+            self.bytecode_store.entry(note_id).or_default();
+            for var in (0..6).filter_map(Var::from_byte) {
+                if let Some((bytecode, length)) = note.get_expr(var) {
+                    self.store_expression(note_id, var, bytecode, length);
+                }
+            }
+            for (&index, (bytecode, length)) in &note.custom {
+                self.store_custom_expression(note_id, index, bytecode, *length);
+            }
+            self.dirty.insert(note_id);
+        }
+        Ok(count)
+    }
+
+    /// Evaluate all dirty notes in topological order
+    ///
+    /// A strict violation stops evaluation, leaving the dirty set alone.
+    pub fn evaluate_dirty(&mut self, sorted_ids: &[u32]) -> EvaluationReport {
+        let mut report = EvaluationReport::default();
+        self.strict_violation = None;
+        self.machine.stats = EvaluationStats::default();
+
+        for &note_id in sorted_ids {
+            if let Some(changed_vars) = self.evaluate_note(note_id) {
+                report.record(note_id, changed_vars);
+            }
+            if self.strict_violation.is_some() {
+                self.validate_evaluated(&mut report);
+                return report;
+            }
+        }
+
+        self.dirty.clear();
+        self.bump_generation();
+        self.validate_evaluated(&mut report);
+        report
+    }
+
+    /// Evaluate all dirty notes in an order computed from the dependency
+    /// graph, so every note is evaluated after the notes it references
+    ///
+    /// Notes on a dependency cycle are evaluated last, in id order.
+    pub fn evaluate_dirty_auto(&mut self) -> EvaluationReport {
+        let order = self.dirty_order();
+        self.evaluate_dirty(&order)
+    }
+
+    /// Evaluate the notes `target_id` transitively depends on, and the note
+    /// itself, that are dirty or missing from the cache
+    ///
+    /// Only the evaluated notes leave the dirty set; unrelated dirty notes
+    /// wait for the next `evaluate_dirty`. A strict violation stops
+    /// evaluation like it does there.
+    pub fn evaluate_closure(&mut self, target_id: u32) -> EvaluationReport {
+        let mut closure = self.graph.get_all_dependencies(target_id);
+        closure.insert(target_id);
+        let stale: HashSet<u32> = closure
+            .into_iter()
+            .filter(|id| self.bytecode_store.contains_key(id))
+            .filter(|id| self.dirty.contains(id) || self.cache.get(id).is_none())
+            .collect();
+
+        let mut report = EvaluationReport::default();
+        self.strict_violation = None;
+        self.machine.stats = EvaluationStats::default();
+        if stale.is_empty() {
+            return report;
+        }
+        for note_id in self.evaluation_order(&stale) {
+            if let Some(changed_vars) = self.evaluate_note(note_id) {
+                report.record(note_id, changed_vars);
+                self.dirty.remove(&note_id);
+            }
+            if self.strict_violation.is_some() {
+                break;
+            }
+        }
+        self.bump_generation();
+        self.validate_evaluated(&mut report);
+        report
+    }
+
+    /// Evaluate `note_ids` as they would come out with note 0's values
+    /// replaced by `base_override`, returning the results without touching
+    /// the cache, dirty set, generation or evaluation stats
+    ///
+    /// The overridden values stand in for the base note's expressions, which
+    /// is re-evaluated so a derived measure length follows an overridden
+    /// tempo. The notes are then evaluated in dependency order against a
+    /// shadow cache of the base note, the cached notes they reference and
+    /// their own results. Notes left out keep their cached values, so pass
+    /// every note the change may reach (e.g. `graph().get_all_dependents(0)`).
+    /// Properties that fail to evaluate are left unset, without being
+    /// recorded in `evaluation_errors`.
+    pub fn evaluate_what_if(
+        &self,
+        base_override: &DefaultsData,
+        note_ids: &[u32],
+    ) -> Result<HashMap<u32, EvaluatedNote>, String> {
+        let mut base = self.bytecode_store.get(&0).cloned().unwrap_or_default();
+        for (var, value) in base_override.entries() {
+            if let Some(value) = value {
+                let value = value.checked_rational("Override", var)?;
+                let mut bytecode = Vec::new();
+                write_const(&mut bytecode, &value).map_err(|e| format!("Override {}: {}", var.name(), e))?;
+                let length = bytecode.len();
+                base.set_expr(var, bytecode, length);
+            }
+        }
+
+        let requested: HashSet<u32> = note_ids.iter().copied().filter(|&id| id != 0).collect();
+        let mut shadow = NoteCache::default();
+        for &id in requested.iter().chain([0].iter()) {
+            for dep in self.graph.get_dependencies(id) {
+                if dep == 0 || requested.contains(&dep) {
+                    continue;
+                }
+                if let Some(note) = self.cache.get(&dep) {
+                    shadow.insert(dep, note.clone());
+                }
+            }
+        }
+
+        let mut machine = self.machine.with_settings();
+        if self.cycles_stale {
+            machine.in_progress.cycles = Arc::new(self.reference_cycles());
+        }
+        let mut errors = Vec::new();
+        let base_note = compute_note(&mut machine, &shadow, &self.instruments, &self.macros, 0, &base, &mut errors);
+        shadow.insert(0, base_note);
+        let mut results = HashMap::with_capacity(requested.len());
+        for note_id in self.evaluation_order(&requested) {
+            let Some(bytecode) = self.bytecode_store.get(&note_id) else {
+                continue;
+            };
+            let note = compute_note(&mut machine, &shadow, &self.instruments, &self.macros, note_id, bytecode, &mut errors);
+            shadow.insert(note_id, note.clone());
+            results.insert(note_id, note);
+        }
+        Ok(results)
+    }
+
+    /// Store the given notes over the cache, leaving other notes alone
+    ///
+    /// Notes already cached are skipped unless `overwrite` is set. Entries
+    /// whose key is not a note id or whose value is not an `EvaluatedNote`
+    /// are reported in `invalid` without stopping the rest. Every note
+    /// depending on an imported one, and not imported itself, is marked
+    /// dirty, and the generation advances once if anything was imported.
+    pub fn import_cache_merge(&mut self, entries: HashMap<String, serde_json::Value>, overwrite: bool) -> CacheMerge {
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| {
+                let entry = match key.parse::<u32>() {
+                    Ok(id) => serde_json::from_value::<EvaluatedNote>(value).map(|note| (id, note)).map_err(|e| e.to_string()),
+                    Err(_) => Err("Key is not a note id".to_string()),
+                };
+                (key, entry)
+            })
+            .collect();
+        self.merge_cache_entries(entries, overwrite)
+    }
+
+    /// `import_cache_merge` of entries already read
+    fn merge_cache_entries(&mut self, entries: Vec<CacheEntry>, overwrite: bool) -> CacheMerge {
+        let mut merge = CacheMerge::default();
+        let mut notes = Vec::with_capacity(entries.len());
+        for (key, entry) in entries {
+            match entry {
+                Ok((id, note)) if overwrite || self.cache.get(&id).is_none() => notes.push((id, note)),
+                Ok((id, _)) => merge.skipped.push(id),
+                Err(e) => {
+                    merge.invalid.insert(key, e);
+                }
+            }
+        }
+        merge.skipped.sort_unstable();
+        if notes.is_empty() {
+            return merge;
+        }
+        notes.sort_unstable_by_key(|&(id, _)| id);
+
+        let imported: HashSet<u32> = notes.iter().map(|&(id, _)| id).collect();
+        for (id, note) in notes {
+            self.cache_note(id, note);
+            merge.imported.push(id);
+        }
+        for &id in &imported {
+            let dependents = self.graph.get_all_dependents(id);
+            self.dirty.extend(dependents.difference(&imported));
+        }
+        self.bump_generation();
+        merge
+    }
+
+    /// Evaluate and cache one note, returning which of its properties changed
+    /// (see `evaluate_note_internal`)
+    fn evaluate_note(&mut self, note_id: u32) -> Option<u16> {
+        self.refresh_reference_cycles();
+        let bytecode = self.bytecode_store.get(&note_id)?.clone();
+
+        let mut errors = Vec::new();
+        let result =
+            compute_note(&mut self.machine, &self.cache, &self.instruments, &self.macros, note_id, &bytecode, &mut errors);
+        let exhausted = errors.iter().any(|e| matches!(e.error, EvalError::FuelExhausted { .. }));
+        self.record_errors(errors);
+        if exhausted {
+            return None;
+        }
+        if self.strict {
+            if let Some(violation) = StrictViolation::for_note(note_id, &result) {
+                self.strict_violation = Some(violation);
+                return None;
+            }
+        }
+        Some(self.cache_note(note_id, result))
+    }
+
+    /// Problems of every cached note, by id (see `CacheProblem::for_note`)
+    pub fn validate_cache(&self) -> Vec<CacheProblem> {
+        let mut ids: Vec<u32> = self.cache.iter().map(|(&id, _)| id).collect();
+        ids.sort_unstable();
+        let mut problems = Vec::new();
+        for id in ids {
+            if let Some(note) = self.cache.get(&id) {
+                CacheProblem::for_note(id, note, &mut problems);
+            }
+        }
+        problems
+    }
+
+    /// List the problems of the notes a report evaluated, if
+    /// `validate_after_evaluate` is on
+    fn validate_evaluated(&self, report: &mut EvaluationReport) {
+        if !self.validate_after_evaluate {
+            return;
+        }
+        for &id in &report.evaluated {
+            if let Some(note) = self.cache.get(&id) {
+                CacheProblem::for_note(id, note, &mut report.problems);
+            }
+        }
+    }
+
+    /// Replace a note's cached values, returning which properties changed
+    fn cache_note(&mut self, note_id: u32, note: EvaluatedNote) -> u16 {
+        let changed_vars = note.changed_vars(self.cache.get(&note_id), self.change_epsilon);
+        self.cache.insert(note_id, note);
+        self.cached_at.insert(note_id, self.generation);
+        self.removed_at.remove(&note_id);
+        changed_vars
+    }
+
+    /// Drop a note from the cache, recording its removal
+    fn uncache(&mut self, note_id: u32) {
+        self.cache.remove(&note_id);
+        if self.cached_at.remove(&note_id).is_some() {
+            self.removed_at.insert(note_id, self.generation);
+        }
+    }
+
+    /// Replace the whole cache, recording every note as stored and any note
+    /// left out as removed, then advance the generation
+    fn replace_cache(&mut self, cache: NoteCache) {
+        let generation = self.generation;
+        let cached_at: HashMap<u32, u64> = cache.iter().map(|(&id, _)| (id, generation)).collect();
+        let dropped = self.cached_at.keys().filter(|id| !cached_at.contains_key(id)).map(|&id| (id, generation));
+        self.removed_at.extend(dropped);
+        self.removed_at.retain(|id, _| !cached_at.contains_key(id));
+        self.cache = cache;
+        self.cached_at = cached_at;
+        self.bump_generation();
+    }
+
+    /// The cache, registered bytecode and dirty set as a snapshot
+    fn capture(&self, last_used: u64) -> Snapshot {
+        Snapshot {
+            notes: self.cache.notes().clone(),
+            bytecode: self.bytecode_store.clone(),
+            dirty: self.dirty.clone(),
+            last_used,
+        }
+    }
+
+    /// Advance the generation, unless staging holds it until the commit
+    fn bump_generation(&mut self) {
+        if self.staging.is_none() {
+            self.generation += 1;
+        }
+    }
+
+    /// Start staging (see `begin_staging_js`)
+    ///
+    /// Staged changes are made in place, over a copy of the state taken
+    /// here; bytecode blobs are shared rather than copied. Settings changed
+    /// while staging are kept on rollback.
+    pub fn begin_staging(&mut self) -> Result<(), String> {
+        if self.staging.is_some() {
+            return Err("Staging is already active".to_string());
+        }
+        self.staging = Some(Staging {
+            saved: self.capture(0),
+            cached_at: self.cached_at.clone(),
+            removed_at: self.removed_at.clone(),
+            errors: self.errors.clone(),
+            strict_violation: self.strict_violation.clone(),
+            instruments: self.instruments.clone(),
+            macros: self.macros.clone(),
+        });
+        Ok(())
+    }
+
+    /// Keep the staged changes, bumping the generation once
+    pub fn commit_staging(&mut self) -> Result<(), String> {
+        self.staging.take().ok_or_else(|| "Staging is not active".to_string())?;
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// Discard the staged changes; the generation is as `begin_staging`
+    /// left it
+    pub fn rollback_staging(&mut self) -> Result<(), String> {
+        let Staging { saved, cached_at, removed_at, errors, strict_violation, instruments, macros } =
+            self.staging.take().ok_or_else(|| "Staging is not active".to_string())?;
+        self.cache = saved.notes.into_iter().collect();
+        self.bytecode_store = saved.bytecode;
+        self.dirty = saved.dirty;
+        self.errors = errors;
+        self.strict_violation = strict_violation;
+        self.instruments = instruments;
+        self.macros = macros;
+        // Notes staging stored or dropped changed back, as of now
+        let staged = std::mem::replace(&mut self.cached_at, cached_at);
+        self.removed_at = removed_at;
+        let generation = self.generation;
+        let touched: Vec<u32> = staged
+            .iter()
+            .filter(|&(id, stamp)| self.cached_at.get(id) != Some(stamp))
+            .map(|(&id, _)| id)
+            .chain(self.cached_at.keys().filter(|id| !staged.contains_key(id)).copied())
+            .collect();
+        for id in touched {
+            if self.cache.contains_key(&id) {
+                self.cached_at.insert(id, generation);
+            } else {
+                self.removed_at.insert(id, generation);
+            }
+        }
+        self.rebuild_graph();
+        Ok(())
+    }
+
+    /// Drop the least recently used snapshots beyond `max_snapshots`
+    fn evict_snapshots(&mut self) {
+        while self.snapshots.len() > self.max_snapshots {
+            let oldest = self.snapshots.iter().min_by_key(|(_, snapshot)| snapshot.last_used).map(|(&id, _)| id);
+            if let Some(id) = oldest {
+                self.snapshots.remove(&id);
+            }
+        }
+    }
+
+    /// Store one expression, sharing an interned blob when interning is on
+    ///
+    /// The stored length is the blob's: an equivalent interned program may be
+    /// encoded differently (e.g. LOAD_REF_WIDE for LOAD_REF).
+    fn store_expression(&mut self, note_id: u32, var: Var, bytecode: &[u8], length: usize) {
+        let bytecode = self.share(bytecode, length);
+        let length = bytecode.len();
+        self.bytecode_store.entry(note_id).or_default().set_expr(var, bytecode, length);
+        self.update_dependencies(note_id);
+    }
+
+    /// Store a custom variable's expression, as `store_expression` does
+    fn store_custom_expression(&mut self, note_id: u32, index: u8, bytecode: &[u8], length: usize) {
+        let bytecode = self.share(bytecode, length);
+        let length = bytecode.len();
+        self.bytecode_store.entry(note_id).or_default().set_custom(index, bytecode, length);
+        self.update_dependencies(note_id);
+    }
+
+    /// The blob to store for the first `length` bytes of an expression: an
+    /// interned one when interning is on, else a copy
+    fn share(&mut self, bytecode: &[u8], length: usize) -> Arc<[u8]> {
+        if self.intern {
+            intern(&mut self.interned, bytecode, length)
+        } else {
+            bytecode.get(..length).unwrap_or(bytecode).into()
+        }
+    }
+
+    /// Recompute a note's edges in the dependency graph from its registered
+    /// expressions
+    ///
+    /// A base-note reference counts as a dependency on note 0. Expressions
+    /// that fail validation contribute no edges.
+    fn update_dependencies(&mut self, note_id: u32) {
+        let mut deps = HashSet::new();
+        let mut references_base = false;
+        if let Some(note) = self.bytecode_store.get(&note_id) {
+            for (bytecode, length) in note.all_exprs() {
+                if let Ok(info) = validate_with_macros(bytecode, *length, &self.macros) {
+                    deps.extend(info.note_ids);
+                    references_base |= info.references_base;
+                }
+            }
+        }
+        if references_base {
+            deps.insert(0);
+        }
+        deps.remove(&note_id);
+        self.graph.update_dependencies(note_id, deps, references_base);
+        self.cycles_stale = true;
+    }
+
+    /// Note variables on a reference cycle, numbered by cycle
+    ///
+    /// Only the expressions of notes the graph places on a cycle are read, so
+    /// this is cheap when there are none. Cycles within a note are found
+    /// only among those notes; reading the variable being evaluated itself
+    /// is caught anywhere. Reads include custom variables, lookups such as
+    /// FIND_MEASURE and the bodies of called macros (see
+    /// `note_references_with_macros`).
+    fn reference_cycles(&self) -> HashMap<(u32, u8), usize> {
+        let mut cycles = HashMap::new();
+        let mut count = 0;
+        for notes in self.graph.cyclic_components() {
+            let mut refs: HashMap<(u32, u8), Vec<(u32, u8)>> = HashMap::new();
+            for note_id in notes {
+                let Some(note) = self.bytecode_store.get(&note_id) else {
+                    continue;
+                };
+                let builtin = (0..6).filter_map(Var::from_byte).filter_map(|var| Some((var as u8, note.get_expr(var)?)));
+                let custom = note.custom.iter().map(|(&index, (bytecode, length))| (index, (&bytecode[..], *length)));
+                for (index, (bytecode, length)) in builtin.chain(custom) {
+                    let reads = note_references_with_macros(bytecode, length, &self.macros).unwrap_or_default();
+                    refs.insert((note_id, index), reads);
+                }
+            }
+            let slots: Vec<(u32, u8)> = refs.keys().copied().collect();
+            let reads = |slot| refs[&slot].iter().copied().filter(|read| refs.contains_key(read)).collect();
+            for cycle in cyclic_components(slots, reads) {
+                cycles.extend(cycle.into_iter().map(|slot| (slot, count)));
+                count += 1;
+            }
+        }
+        cycles
+    }
+
+    /// Find the machine's reference cycles again if the graph changed
+    fn refresh_reference_cycles(&mut self) {
+        if self.cycles_stale {
+            self.machine.in_progress.cycles = Arc::new(self.reference_cycles());
+            self.cycles_stale = false;
+        }
+    }
+
+    /// Recompute the dependency graph from scratch
+    fn rebuild_graph(&mut self) {
+        self.graph.clear();
+        let ids: Vec<u32> = self.bytecode_store.keys().copied().collect();
+        for id in ids {
+            self.update_dependencies(id);
+        }
+    }
+
+    /// The dirty notes in dependency order, followed by any notes on a
+    /// cycle in id order
+    fn dirty_order(&self) -> Vec<u32> {
+        self.evaluation_order(&self.dirty)
+    }
+
+    /// `note_ids` in dependency order, followed by any notes on a cycle in
+    /// id order
+    fn evaluation_order(&self, note_ids: &HashSet<u32>) -> Vec<u32> {
+        let mut order = self.graph.get_evaluation_order(note_ids);
+        if order.len() < note_ids.len() {
+            let ordered: HashSet<u32> = order.iter().copied().collect();
+            let mut cyclic: Vec<u32> = note_ids.difference(&ordered).copied().collect();
+            cyclic.sort_unstable();
+            order.extend(cyclic);
+        }
+        order
+    }
+
+    /// The registered dependency graph
+    pub fn graph(&self) -> &DependencyGraph {
+        &self.graph
+    }
+
+    /// Count registered expressions and the blobs behind them
+    pub fn intern_stats(&self) -> InternStats {
+        let mut blobs: HashMap<*const u8, (usize, usize)> = HashMap::new();
+        for (bytes, _) in self.bytecode_store.values().flat_map(NoteBytecode::all_exprs) {
+            blobs.entry(bytes.as_ptr()).or_insert((bytes.len(), 0)).1 += 1;
+        }
+        InternStats {
+            expressions: blobs.values().map(|&(_, uses)| uses).sum(),
+            unique_programs: blobs.len(),
+            saved_bytes: blobs.values().map(|&(size, uses)| size * (uses - 1)).sum(),
+        }
+    }
+
+    /// `bytecode::analyze` summed over every registered expression
+    ///
+    /// Interned expressions count once per note that uses them. Expressions
+    /// that fail validation (possible with `validate_on_register` off) are
+    /// left out.
+    pub fn bytecode_stats(&self) -> BytecodeStats {
+        let mut stats = BytecodeStats::default();
+        for note in self.bytecode_store.values() {
+            for (bytecode, length) in note.all_exprs() {
+                if let Ok(expression) = analyze_with_macros(bytecode, *length, &self.macros) {
+                    stats.merge(&expression);
+                }
+            }
+        }
+        stats
+    }
+
+    /// Bytecode sizes of a note's expressions, if it has any registered
+    pub fn note_bytecode_size(&self, note_id: u32) -> Option<NoteBytecodeSize> {
+        let note = self.bytecode_store.get(&note_id)?;
+        let size = |var: Var| note.get_expr(var).map(|(_, length)| length);
+        let mut sizes = NoteBytecodeSize {
+            start_time: size(Var::StartTime),
+            duration: size(Var::Duration),
+            frequency: size(Var::Frequency),
+            tempo: size(Var::Tempo),
+            beats_per_measure: size(Var::BeatsPerMeasure),
+            measure_length: size(Var::MeasureLength),
+            total_bytes: 0,
+        };
+        sizes.total_bytes = note.all_exprs().map(|(_, length)| length).sum();
+        Some(sizes)
+    }
+
+    /// Override the values missing references fall back to, marking every
+    /// registered note dirty (see `VariableDefaults::apply`)
+    pub fn set_defaults(&mut self, defaults: &DefaultsData) -> Result<(), String> {
+        self.machine.defaults.apply(defaults)?;
+        self.dirty.extend(self.bytecode_store.keys());
+        Ok(())
+    }
+
+    /// Choose whether a custom variable a note does not set reads the base
+    /// note's value rather than 0, marking every registered note dirty
+    pub fn set_custom_inherited(&mut self, index: u8, inherited: bool) -> Result<(), String> {
+        set_inherited_flag(&mut self.machine.inherited_custom, index, inherited)?;
+        self.dirty.extend(self.bytecode_store.keys());
+        Ok(())
+    }
+
+    /// Values missing references fall back to
+    pub fn defaults(&self) -> &VariableDefaults {
+        &self.machine.defaults
+    }
+
+    /// The note that stopped the last strict evaluation, if any
+    pub fn strict_violation(&self) -> Option<&StrictViolation> {
+        self.strict_violation.as_ref()
+    }
+
+    /// Re-run one registered expression of a note with tracing, against
+    /// the current cache
+    ///
+    /// Uses a separate machine with the same settings, so evaluation stats
+    /// are unaffected. Variables evaluated after the note's own tempo, beats
+    /// per measure and frequency see the note's cached values, as in
+    /// `evaluate_dirty`.
+    pub fn trace_note(&self, note_id: u32, var: Var) -> Option<(Result<Value, EvalError>, Vec<TraceStep>)> {
+        let (bytecode, length) = self.bytecode_store.get(&note_id)?.get_expr(var)?;
+        let own_values = matches!(var, Var::MeasureLength | Var::StartTime | Var::Duration);
+        let current = self.cache.get(&note_id).filter(|_| own_values).map(|note| (note_id, note));
+        let notes = NoteView { cache: &self.cache, current, instruments: &self.instruments, macros: &self.macros };
+
+        let mut machine = self.machine.with_settings();
+        machine.in_progress.current = Some((note_id, var as u8));
+        if self.cycles_stale {
+            machine.in_progress.cycles = Arc::new(self.reference_cycles());
+        }
+        let mut trace = Vec::new();
+        let result = machine.run_with(bytecode, length, &notes, &mut trace);
+        Some((result, trace))
+    }
+
+    /// Instructions executed since the last `evaluate_dirty` started
+    pub fn evaluation_stats(&self) -> &EvaluationStats {
+        &self.machine.stats
+    }
+
+    /// The most recent evaluation failures, oldest first
+    pub fn evaluation_errors(&self) -> &VecDeque<NoteEvalError> {
+        &self.errors
+    }
+
+    /// Keep `errors`, dropping the oldest beyond `MAX_EVALUATION_ERRORS`
+    fn record_errors(&mut self, errors: Vec<NoteEvalError>) {
+        for error in errors {
+            log_warn!("Note {} {}: {}", error.note_id, label(error.var), error.error);
+            if self.errors.len() == MAX_EVALUATION_ERRORS {
+                self.errors.pop_front();
+            }
+            self.errors.push_back(error);
+        }
+    }
+
+    /// Cached notes with any non-rational value, sorted by id
+    ///
+    /// Reads the corruption flags left by evaluation, without evaluating.
+    pub fn corrupted_notes(&self) -> Vec<CorruptedNote> {
+        let mut notes: Vec<CorruptedNote> = self
+            .cache
+            .iter()
+            .filter(|(_, note)| note.corruption_flags != 0 || note.custom_corruption_flags != 0)
+            .map(|(&id, note)| CorruptedNote {
+                id,
+                corruption_flags: note.corruption_flags,
+                custom_corruption_flags: note.custom_corruption_flags,
+            })
+            .collect();
+        notes.sort_unstable_by_key(|note| note.id);
+        notes
+    }
+
+    /// Counts of cached notes with a non-rational value of each variable
+    pub fn corruption_summary(&self) -> CorruptionSummary {
+        let mut summary = CorruptionSummary::default();
+        for (_, note) in self.cache.iter() {
+            summary.count(note);
+        }
+        summary
+    }
+
+    /// Get the cached evaluation result for a note
+    pub fn cached_note(&self, note_id: u32) -> Option<&EvaluatedNote> {
+        self.cache.get(&note_id)
+    }
+
+    /// Move every note to its id in `mapping` and rewrite the LOAD_REF
+    /// operands of all registered bytecode to match
+    ///
+    /// Bytecode, cache, dirty and instrument entries move with their note, so
+    /// cached results stay valid without re-evaluation. Macro bodies are
+    /// rewritten too. All blobs are rewritten
+    /// before any are stored, so an error leaves the evaluator untouched.
+    ///
+    /// The mapping must not send two notes to the same id, either by
+    /// mapping two ids onto one or by mapping onto a note that keeps its id.
+    pub fn remap_note_ids(&mut self, mapping: &HashMap<u32, u32>) -> Result<(), String> {
+        let mut sources: HashMap<u32, u32> = HashMap::with_capacity(mapping.len());
+        for (&old, &new) in mapping {
+            if let Some(other) = sources.insert(new, old) {
+                let (a, b) = (old.min(other), old.max(other));
+                return Err(format!("Notes {} and {} both map to {}", a, b, new));
+            }
+        }
+        let known = self.bytecode_store.keys().chain(self.cache.iter().map(|(id, _)| id)).chain(self.instruments.assigned.keys());
+        if let Some(kept) = known.filter(|id| !mapping.contains_key(id)).find(|id| sources.contains_key(id)) {
+            return Err(format!("Note {} maps onto note {}, which keeps its id", sources[kept], kept));
+        }
+
+        let new_id = |id: u32| mapping.get(&id).copied().unwrap_or(id);
+        let mut store = HashMap::with_capacity(self.bytecode_store.len());
+        let mut interned = InternPool::new();
+        for (&id, note) in &self.bytecode_store {
+            let mut remap = |var_index: u8, (bytecode, length): &(Arc<[u8]>, usize)| {
+                let bytecode = remap_note_ids(bytecode, *length, mapping)
+                    .map_err(|e| format!("Note {} variable {}: {}", id, var_index, e))?;
+                let length = bytecode.len();
+                let bytecode = if self.intern {
+                    intern(&mut interned, &bytecode, length)
+                } else {
+                    bytecode.into()
+                };
+                Ok::<_, String>((bytecode, length))
+            };
+            let mut remapped = NoteBytecode::default();
+            for (var_index, expr) in note.expressions.iter().enumerate() {
+                if let Some(expr) = expr {
+                    remapped.expressions[var_index] = Some(remap(var_index as u8, expr)?);
+                }
+            }
+            for (&index, expr) in &note.custom {
+                remapped.custom.insert(index, remap(index, expr)?);
+            }
+            store.insert(new_id(id), remapped);
+        }
+
+        let macros = self.macros.remap_note_ids(mapping)?;
+
+        self.bytecode_store = store;
+        self.interned = interned;
+        self.macros = macros;
+        self.rebuild_graph();
+        // Moved notes count as removed from their old id and stored at the new one
+        let generation = self.generation;
+        let moved: Vec<u32> = self.cached_at.keys().copied().filter(|&id| new_id(id) != id).collect();
+        self.cache = self.cache.drain().map(|(id, note)| (new_id(id), note)).collect();
+        self.cached_at = self.cached_at.drain().map(|(id, stamp)| (new_id(id), stamp)).collect();
+        for id in moved {
+            self.cached_at.insert(new_id(id), generation);
+            self.removed_at.remove(&new_id(id));
+            if !self.cached_at.contains_key(&id) {
+                self.removed_at.insert(id, generation);
+            }
+        }
+        self.dirty = self.dirty.drain().map(new_id).collect();
+        self.instruments.assigned = self.instruments.assigned.drain().map(|(id, instrument)| (new_id(id), instrument)).collect();
+        if let Some(violation) = &mut self.strict_violation {
+            violation.note_id = new_id(violation.note_id);
+        }
+        self.bump_generation();
+        Ok(())
+    }
+
+    /// Get the registered bytecode for one of a note's expressions
+    pub fn expression(&self, note_id: u32, var: Var) -> Option<(&[u8], usize)> {
+        self.bytecode_store.get(&note_id).and_then(|bc| bc.get_expr(var))
+    }
+
+    /// Cached notes whose [startTime, startTime + duration) intersects
+    /// [from, to), sorted by start time, then id
+    ///
+    /// Notes without a start time are left out. A note without a duration,
+    /// or with a zero one, counts when it starts in the window.
+    pub fn notes_in_range(&self, from: f64, to: f64) -> Vec<NoteTiming> {
+        let timeline = self.cache.timeline();
+        timeline.overlapping(from, to).map(|entry| self.cache.timing(entry)).collect()
+    }
+
+    /// Where the cached notes with a start time and a duration start and end
+    pub fn module_extent(&self) -> Option<&ModuleExtentExact> {
+        self.cache.extent()
+    }
+
+    /// Cached notes starting in [from, to), sorted by start time, then id
+    pub fn notes_starting_in_range(&self, from: f64, to: f64) -> Vec<NoteTiming> {
+        let timeline = self.cache.timeline();
+        timeline.starting_in(from, to).iter().map(|entry| self.cache.timing(entry)).collect()
+    }
+
+    /// Start time, duration and frequency of the cached notes stored since
+    /// `generation` was current, sorted by start time, then id
+    ///
+    /// Notes stored without all three, and notes removed from the cache,
+    /// since then are listed in `removed` instead. Values are f64s, the
+    /// float itself for irrational ones.
+    pub fn playback_arrays(&self, generation: u64) -> PlaybackArrays {
+        let mut arrays = PlaybackArrays::default();
+        let mut rows: Vec<(f64, u32, f64, f64)> = Vec::new();
+        let stored = self.cache.iter().filter(|(id, _)| self.cached_at.get(id).copied().unwrap_or(0) >= generation);
+        for (&id, note) in stored {
+            match (&note.start_time, &note.duration, &note.frequency) {
+                (Some(start), Some(duration), Some(frequency)) => {
+                    rows.push((start.to_f64(), id, duration.to_f64(), frequency.to_f64()));
+                }
+                _ => arrays.removed.push(id),
+            }
+        }
+        rows.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        arrays.removed.extend(self.removed_at.iter().filter(|&(_, &stamp)| stamp >= generation).map(|(&id, _)| id));
+        arrays.removed.sort_unstable();
+
+        for (start, id, duration, frequency) in rows {
+            arrays.ids.push(id);
+            arrays.start_times.push(start);
+            arrays.durations.push(duration);
+            arrays.frequencies.push(frequency);
+        }
+        arrays
+    }
+
+    /// Collect playback events for cached notes within a time window
+    ///
+    /// Only notes with a start time, duration and frequency are exported.
+    pub fn export_audio_events(
+        &self,
+        sample_rate: u32,
+        from_time: f64,
+        to_time: f64,
+        clip: bool,
+    ) -> AudioEvents {
+        let window_start = (from_time * sample_rate as f64).round() as i64;
+        let window_end = (to_time * sample_rate as f64).round() as i64;
+
+        let mut rows: Vec<(i64, u32, i64, f64)> = Vec::new();
+        for (&id, note) in self.cache.iter() {
+            let (start, duration, frequency) =
+                match (&note.start_time, &note.duration, &note.frequency) {
+                    (Some(s), Some(d), Some(f)) => (s, d, f),
+                    _ => continue,
+                };
+
+            let end_time = end_time(start, duration);
+            let (mut start_sample, mut end_sample) = match (
+                time_to_sample(start, sample_rate),
+                time_to_sample(&end_time, sample_rate),
+            ) {
+                (Some(s), Some(e)) => (s, e),
+                _ => continue,
+            };
+
+            if clip {
+                if start_sample >= window_end || end_sample <= window_start {
+                    continue;
+                }
+                start_sample = start_sample.max(window_start);
+                end_sample = end_sample.min(window_end);
+            } else if start_sample < window_start || start_sample >= window_end {
+                continue;
+            }
+
+            rows.push((start_sample, id, end_sample - start_sample, frequency.to_f64()));
+        }
+        rows.sort_by_key(|&(start, id, _, _)| (start, id));
+
+        let mut events = AudioEvents::default();
+        for (start, id, duration, frequency) in rows {
+            events.ids.push(id);
+            events.start_samples.push(start as f64);
+            events.duration_samples.push(duration as f64);
+            events.frequencies.push(frequency);
+        }
+        events
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::{validate, write_i32, write_u16, Op};
+
+    fn make_const_bytecode(num: i32, den: i32) -> Vec<u8> {
+        let mut bytecode = Vec::new();
+        bytecode.push(Op::LoadConst as u8);
+        write_i32(&mut bytecode, num);
+        write_i32(&mut bytecode, den);
+        bytecode
+    }
+
+    #[test]
+    fn test_evaluate_constant() {
+        let mut evaluator = Evaluator::new();
+        let bytecode = make_const_bytecode(3, 4);
+        let cache = HashMap::new();
+
+        let result = evaluator.evaluate(&bytecode, bytecode.len(), &cache).unwrap();
+        assert_eq!(result.to_f64(), 0.75);
+        assert!(result.is_rational()); // Should be rational, not corrupted
+    }
+
+    #[test]
+    fn test_evaluate_addition() {
+        let mut evaluator = Evaluator::new();
+        let mut bytecode = Vec::new();
+
+        // Push 1/2
+        bytecode.push(Op::LoadConst as u8);
+        write_i32(&mut bytecode, 1);
+        write_i32(&mut bytecode, 2);
+
+        // Push 1/4
+        bytecode.push(Op::LoadConst as u8);
+        write_i32(&mut bytecode, 1);
+        write_i32(&mut bytecode, 4);
+
+        // Add
+        bytecode.push(Op::Add as u8);
+
+        let cache = HashMap::new();
+        let result = evaluator.evaluate(&bytecode, bytecode.len(), &cache).unwrap();
+        assert_eq!(result.to_f64(), 0.75);
+        assert!(result.is_rational());
+    }
+
+    #[test]
+    fn test_evaluate_with_cache() {
+        let mut evaluator = Evaluator::new();
+        let mut bytecode = Vec::new();
+
+        // LOAD_BASE startTime
+        bytecode.push(Op::LoadBase as u8);
+        bytecode.push(Var::StartTime as u8);
+
+        // Push 1
+        bytecode.push(Op::LoadConst as u8);
+        write_i32(&mut bytecode, 1);
+        write_i32(&mut bytecode, 1);
+
+        // Add
+        bytecode.push(Op::Add as u8);
+
+        // Create cache with base note having startTime = 5
+        let mut cache = HashMap::new();
+        let base_note = EvaluatedNote {
+            start_time: Some(FractionData { s: 1, n: 5, d: 1, f: None, err: None, corrupted: false, symbolic: None }),
+            ..Default::default()
+        };
+        cache.insert(0, base_note);
+
+        let result = evaluator.evaluate(&bytecode, bytecode.len(), &cache).unwrap();
+        assert_eq!(result.to_f64(), 6.0); // 5 + 1 = 6
+        assert!(result.is_rational());
+    }
+
+    #[test]
+    fn test_evaluate_pow_rational() {
+        let mut evaluator = Evaluator::new();
+        let mut bytecode = Vec::new();
+
+        // Push 2
+        bytecode.push(Op::LoadConst as u8);
+        write_i32(&mut bytecode, 2);
+        write_i32(&mut bytecode, 1);
+
+        // Push 3 (exponent)
+        bytecode.push(Op::LoadConst as u8);
+        write_i32(&mut bytecode, 3);
+        write_i32(&mut bytecode, 1);
+
+        // Pow: 2^3 = 8
+        bytecode.push(Op::Pow as u8);
+
+        let cache = HashMap::new();
+        let result = evaluator.evaluate(&bytecode, bytecode.len(), &cache).unwrap();
+        assert_eq!(result.to_f64(), 8.0);
+        assert!(result.is_rational()); // 2^3 is rational
+    }
+
+    #[test]
+    fn test_evaluate_pow_irrational_tet() {
+        let mut evaluator = Evaluator::new();
+        let mut bytecode = Vec::new();
+
+        // Push 2 (base)
+        bytecode.push(Op::LoadConst as u8);
+        write_i32(&mut bytecode, 2);
+        write_i32(&mut bytecode, 1);
+
+        // Push 1/12 (exponent for TET semitone)
+        bytecode.push(Op::LoadConst as u8);
+        write_i32(&mut bytecode, 1);
+        write_i32(&mut bytecode, 12);
+
+        // Pow: 2^(1/12) is irrational
+        bytecode.push(Op::Pow as u8);
+
+        let cache = HashMap::new();
+        let result = evaluator.evaluate(&bytecode, bytecode.len(), &cache).unwrap();
+
+        // Should be approximately 1.059463...
+        let expected = 2.0_f64.powf(1.0 / 12.0);
+        assert!((result.to_f64() - expected).abs() < 1e-10);
+        assert!(result.is_corrupted()); // Should be irrational (corrupted)
+    }
+
+    #[test]
+    fn test_evaluate_pow_perfect_root() {
+        let mut evaluator = Evaluator::new();
+        let mut bytecode = Vec::new();
+
+        // Push 4
+        bytecode.push(Op::LoadConst as u8);
+        write_i32(&mut bytecode, 4);
+        write_i32(&mut bytecode, 1);
+
+        // Push 1/2 (square root)
+        bytecode.push(Op::LoadConst as u8);
+        write_i32(&mut bytecode, 1);
+        write_i32(&mut bytecode, 2);
+
+        // Pow: 4^(1/2) = 2 (perfect square root, stays rational)
+        bytecode.push(Op::Pow as u8);
+
+        let cache = HashMap::new();
+        let result = evaluator.evaluate(&bytecode, bytecode.len(), &cache).unwrap();
+        assert_eq!(result.to_f64(), 2.0);
         assert!(result.is_rational()); // Perfect square root stays rational
     }
+
+    /// Register `count` back-to-back notes of duration num/den, each starting
+    /// where the previous one ends (startTime = [prev].startTime + [prev].duration)
+    fn register_chain(evaluator: &mut PersistentEvaluator, count: u32, num: i32, den: i32) {
+        for id in 1..=count {
+            let start = if id == 1 {
+                make_const_bytecode(0, 1)
+            } else {
+                let mut bc = vec![Op::LoadRef as u8];
+                write_u16(&mut bc, (id - 1) as u16);
+                bc.push(Var::StartTime as u8);
+                bc.push(Op::LoadRef as u8);
+                write_u16(&mut bc, (id - 1) as u16);
+                bc.push(Var::Duration as u8);
+                bc.push(Op::Add as u8);
+                bc
+            };
+            let duration = make_const_bytecode(num, den);
+            let frequency = make_const_bytecode(440, 1);
+            evaluator.register_expression(id, Var::StartTime as u8, &start, start.len());
+            evaluator.register_expression(id, Var::Duration as u8, &duration, duration.len());
+            evaluator.register_expression(id, Var::Frequency as u8, &frequency, frequency.len());
+        }
+        let order: Vec<u32> = (1..=count).collect();
+        evaluator.evaluate_dirty(&order);
+    }
+
+    #[test]
+    fn test_wide_note_ids() {
+        use crate::bytecode::write_load_ref;
+
+        // Note 100,001 starts when note 100,000 ends; 7 starts when 100,001 does
+        let after = |note_id: u32| {
+            let mut bc = Vec::new();
+            write_load_ref(&mut bc, note_id, Var::StartTime as u8);
+            write_load_ref(&mut bc, note_id, Var::Duration as u8);
+            bc.push(Op::Add as u8);
+            bc
+        };
+        let mut evaluator = PersistentEvaluator::new();
+        let register = |evaluator: &mut PersistentEvaluator, note_id: u32, var: Var, bc: &[u8]| {
+            evaluator.register_expression(note_id, var as u8, bc, bc.len());
+            evaluator.mark_dirty(note_id);
+        };
+        register(&mut evaluator, 100_000, Var::StartTime, &make_const_bytecode(1, 1));
+        register(&mut evaluator, 100_000, Var::Duration, &make_const_bytecode(1, 2));
+        register(&mut evaluator, 100_001, Var::StartTime, &after(100_000));
+        let mut same = Vec::new();
+        write_load_ref(&mut same, 100_001, Var::StartTime as u8);
+        register(&mut evaluator, 7, Var::StartTime, &same);
+        assert_eq!(evaluator.graph().get_dependencies(100_001), HashSet::from([100_000]));
+        assert_eq!(evaluator.graph().get_dependencies(7), HashSet::from([100_001]));
+
+        let start = |evaluator: &PersistentEvaluator, id| {
+            evaluator.cached_note(id).and_then(|note| note.start_time.as_ref()).map(FractionData::to_fraction)
+        };
+        let report = evaluator.evaluate_dirty_auto();
+        assert_eq!(report.evaluated, vec![100_000, 100_001, 7]);
+        assert_eq!(start(&evaluator, 7), Some(Fraction::new(3, 2)));
+
+        // Edits propagate through the wide references
+        register(&mut evaluator, 100_000, Var::Duration, &make_const_bytecode(2, 1));
+        let report = evaluator.evaluate_dirty_auto();
+        assert_eq!(report.changed, vec![100_000, 100_001, 7]);
+        assert_eq!(start(&evaluator, 7), Some(Fraction::new(3, 1)));
+
+        // Binary bytecode and cache keep the ids
+        let mut loaded = PersistentEvaluator::new();
+        loaded.register_notes_binary(&evaluator.export_bytecode_binary()).unwrap();
+        loaded.import_cache_binary(&evaluator.export_cache_binary()).unwrap();
+        assert_eq!(start(&loaded, 100_001), Some(Fraction::new(3, 1)));
+        loaded.evaluate_dirty_auto();
+        assert_eq!(start(&loaded, 7), Some(Fraction::new(3, 1)));
+
+        // Remapping narrows references to ids below 65536, and notes may move above it
+        loaded.remap_note_ids(&HashMap::from([(100_000, 5), (7, 200_000)])).unwrap();
+        let (bc, len) = loaded.expression(100_001, Var::StartTime).unwrap();
+        assert_eq!(bc[..len].to_vec(), {
+            let mut narrow = vec![Op::LoadRef as u8, 0, 5, Var::StartTime as u8, Op::LoadRef as u8, 0, 5];
+            narrow.extend([Var::Duration as u8, Op::Add as u8]);
+            narrow
+        });
+        assert_eq!(loaded.graph().get_dependents(100_001), HashSet::from([200_000]));
+        loaded.mark_dirty(5);
+        loaded.evaluate_dirty_auto();
+        assert_eq!(start(&loaded, 200_000), Some(Fraction::new(3, 1)));
+    }
+
+    #[test]
+    fn test_remap_note_ids() {
+        let mut evaluator = PersistentEvaluator::new();
+        register_chain(&mut evaluator, 3, 3, 4);
+        let start = |evaluator: &PersistentEvaluator, id| evaluator.cached_note(id).unwrap().start_time.as_ref().unwrap().to_fraction();
+        let before: Vec<Fraction> = (1..=3).map(|id| start(&evaluator, id)).collect();
+        let generation = evaluator.generation();
+
+        // Reverse the ids: 1 -> 3, 2 stays, 3 -> 1
+        let mapping = HashMap::from([(1, 3), (3, 1)]);
+        evaluator.remap_note_ids(&mapping).unwrap();
+        assert!(evaluator.generation() > generation);
+        let (bytecode, length) = evaluator.expression(1, Var::StartTime).unwrap();
+        assert_eq!(validate(bytecode, length).unwrap().note_ids, vec![2]);
+        let (bytecode, length) = evaluator.expression(2, Var::StartTime).unwrap();
+        assert_eq!(validate(bytecode, length).unwrap().note_ids, vec![3]);
+
+        // Cached results moved with their notes, and re-evaluating agrees
+        for (old_id, new_id) in [(1, 3), (2, 2), (3, 1)] {
+            assert_eq!(start(&evaluator, new_id), before[old_id as usize - 1]);
+        }
+        evaluator.invalidate_note(1);
+        evaluator.evaluate_dirty(&[3, 2, 1]);
+        assert_eq!(start(&evaluator, 1), before[2]);
+
+        // Malformed bytecode is rejected without changing anything
+        evaluator.register_expression(2, Var::Frequency as u8, &[0x7f], 1);
+        let generation = evaluator.generation();
+        assert!(evaluator.remap_note_ids(&HashMap::from([(2, 4)])).is_err());
+        assert_eq!(evaluator.generation(), generation);
+        assert!(evaluator.expression(2, Var::StartTime).is_some());
+
+        // Mappings that would merge two notes are rejected up front
+        let error = evaluator.remap_note_ids(&HashMap::from([(1, 5), (3, 5)])).err();
+        assert_eq!(error.as_deref(), Some("Notes 1 and 3 both map to 5"));
+        let error = evaluator.remap_note_ids(&HashMap::from([(1, 2)])).err();
+        assert_eq!(error.as_deref(), Some("Note 1 maps onto note 2, which keeps its id"));
+        assert_eq!(evaluator.generation(), generation);
+        assert_eq!(start(&evaluator, 1), before[2]);
+        assert!(evaluator.expression(3, Var::StartTime).is_some());
+    }
+
+    #[test]
+    fn test_evaluate_dirty_auto() {
+        let mut evaluator = PersistentEvaluator::new();
+        register_chain(&mut evaluator, 4, 1, 1);
+        let unrelated = make_const_bytecode(7, 1);
+        evaluator.register_expression(5, Var::StartTime as u8, &unrelated, unrelated.len());
+        evaluator.evaluate_dirty(&[5]);
+        assert_eq!(evaluator.graph().get_dependencies(4), HashSet::from([3]));
+
+        // Lengthen note 2: notes 3 and 4 follow it, 1 and 5 are untouched
+        let duration = make_const_bytecode(3, 1);
+        evaluator.register_expression(2, Var::Duration as u8, &duration, duration.len());
+        evaluator.mark_dirty(2);
+        assert_eq!(evaluator.dirty_order(), vec![2, 3, 4]);
+        assert_eq!(evaluator.evaluate_dirty_auto().evaluated.len(), 3);
+        let start = |id| evaluator.cached_note(id).unwrap().start_time.as_ref().unwrap().to_fraction();
+        let starts: Vec<Fraction> = (1..=5).map(start).collect();
+        assert_eq!(starts, [0, 1, 4, 5, 7].map(|n| Fraction::new(n, 1)));
+        assert_eq!(evaluator.evaluate_dirty_auto().evaluated.len(), 0);
+
+        // Removing a note drops its edges
+        evaluator.remove_note(3);
+        evaluator.mark_dirty(2);
+        assert_eq!(evaluator.dirty_order(), vec![2]);
+    }
+
+    #[test]
+    fn test_evaluation_report() {
+        let mut evaluator = PersistentEvaluator::new();
+        register_chain(&mut evaluator, 3, 1, 1);
+        let mut log = make_const_bytecode(3, 1);
+        log.extend(make_const_bytecode(2, 1));
+        log.push(Op::Log as u8);
+        evaluator.register_expression(1, Var::Frequency as u8, &log, log.len());
+        evaluator.mark_dirty(1);
+        let report = evaluator.evaluate_dirty_auto();
+        assert_eq!(report.changed_vars, BTreeMap::from([(1, corruption_flag_for_var(Var::Frequency as u8))]));
+
+        // Re-evaluating unchanged expressions, irrational ones included, changes nothing
+        evaluator.mark_dirty(1);
+        let report = evaluator.evaluate_dirty_auto();
+        assert_eq!(report.evaluated, vec![1, 2, 3]);
+        assert!(report.changed.is_empty() && report.changed_vars.is_empty());
+
+        // A longer note 2 moves only the start of note 3
+        let duration = make_const_bytecode(2, 1);
+        evaluator.register_expression(2, Var::Duration as u8, &duration, duration.len());
+        evaluator.mark_dirty(2);
+        let report = evaluator.evaluate_dirty_auto();
+        assert_eq!(report.evaluated, vec![2, 3]);
+        assert_eq!(report.changed, vec![2, 3]);
+        let mask = |var: Var| corruption_flag_for_var(var as u8);
+        assert_eq!(report.changed_vars, BTreeMap::from([(2, mask(Var::Duration)), (3, mask(Var::StartTime))]));
+
+        // Corrupted values compare within the epsilon, and never equal rational ones
+        let inexact = |f| FractionData::from_value(&Value::inexact(f, 0.0));
+        let (a, b) = (inexact(1.5), inexact(1.5 + 1e-12));
+        assert!(!a.same_value(&b, 0.0));
+        assert!(a.same_value(&b, 1e-9));
+        assert!(!a.same_value(&FractionData::from_fraction(&Fraction::new(3, 2)), 1.0));
+
+        // So do instruments and corruption flags, even when values compare equal
+        evaluator.set_instrument(2, 5);
+        let report = evaluator.evaluate_dirty_auto();
+        assert_eq!(report.changed, vec![2]);
+        assert_eq!(report.changed_vars, BTreeMap::from([(2, CHANGED_INSTRUMENT)]));
+        let note = evaluator.cached_note(3).unwrap().clone();
+        let mut flagged = note.clone();
+        flagged.set_corrupted(Var::StartTime);
+        assert_eq!(flagged.changed_vars(Some(&note), 0.0), mask(Var::StartTime));
+        flagged.custom_corruption_flags = 1;
+        assert_eq!(flagged.changed_vars(Some(&note), 0.0), mask(Var::StartTime) | CHANGED_CUSTOM);
+    }
+
+    #[test]
+    fn test_register_notes_binary() {
+        let mut registered = PersistentEvaluator::new();
+        register_chain(&mut registered, 1000, 1, 4);
+        for id in (3..=1000).step_by(3) {
+            let tempo = make_const_bytecode(id as i32, 7);
+            registered.register_expression(id, Var::Tempo as u8, &tempo, tempo.len());
+        }
+        let buffer = registered.export_bytecode_binary();
+
+        let mut loaded = PersistentEvaluator::new();
+        assert_eq!(loaded.register_notes_binary(&buffer), Ok(1000));
+        assert_eq!(loaded.bytecode_store.len(), 1000);
+        for id in 1..=1000 {
+            for var in (0..6).filter_map(Var::from_byte) {
+                assert_eq!(loaded.expression(id, var), registered.expression(id, var));
+            }
+        }
+        assert_eq!(loaded.export_bytecode_binary(), buffer);
+        assert_eq!(loaded.graph().get_dependencies(1000), HashSet::from([999]));
+        assert_eq!(loaded.evaluate_dirty_auto().evaluated.len(), 1000);
+        let start = |evaluator: &PersistentEvaluator| evaluator.cached_note(1000).unwrap().start_time.as_ref().unwrap().to_fraction();
+        assert_eq!(start(&loaded), start(&registered));
+
+        // Malformed buffers are rejected with the offset, registering nothing
+        let mut empty = PersistentEvaluator::new();
+        let error = empty.register_notes_binary(&buffer[..buffer.len() - 1]).unwrap_err();
+        let (_, last_length) = registered.expression(1000, Var::Frequency).unwrap();
+        assert_eq!(error, format!("Unexpected end of data at offset {}", buffer.len() - last_length));
+        // Magic, version, the two-byte count and the id of note 1 come first
+        let mut bad_bits = buffer.clone();
+        bad_bits[8] = 0x40;
+        assert_eq!(empty.register_notes_binary(&bad_bits).unwrap_err(), "Invalid presence bits 0x40 at offset 8");
+        assert!(empty.register_notes_binary(b"RMTB\x01").is_err());
+        assert_eq!(empty.bytecode_store.len(), 0);
+        assert!(empty.dirty.is_empty());
+    }
+
+    #[test]
+    fn test_notes_in_range() {
+        let mut evaluator = PersistentEvaluator::new();
+        let time = |n, d| Some(FractionData::from_fraction(&Fraction::new(n, d)));
+        let note = |start, duration| EvaluatedNote { start_time: start, duration, frequency: time(440, 1), ..Default::default() };
+        evaluator.cache.insert(1, note(time(0, 1), time(1, 1)));
+        evaluator.cache.insert(2, note(time(1, 2), time(2, 1)));
+        evaluator.cache.insert(3, note(time(1, 1), time(0, 1)));
+        evaluator.cache.insert(4, note(time(3, 1), time(1, 1)));
+        evaluator.cache.insert(5, note(None, time(4, 1)));
+        let ids = |timings: Vec<NoteTiming>| timings.iter().map(|timing| timing.id).collect::<Vec<_>>();
+
+        // Note 1 ends where the window starts; zero-length note 3 starts in it
+        assert_eq!(ids(evaluator.notes_in_range(1.0, 2.0)), vec![2, 3]);
+        assert_eq!(ids(evaluator.notes_in_range(0.75, 1.0)), vec![1, 2]);
+        assert_eq!(ids(evaluator.notes_in_range(-10.0, 10.0)), vec![1, 2, 3, 4]);
+        assert_eq!(ids(evaluator.notes_in_range(2.5, 3.0)), Vec::<u32>::new());
+        assert_eq!(ids(evaluator.notes_starting_in_range(0.0, 1.0)), vec![1, 2]);
+        assert_eq!(ids(evaluator.notes_starting_in_range(1.0, 1.0)), Vec::<u32>::new());
+        assert_eq!(
+            evaluator.notes_starting_in_range(0.5, 0.75),
+            vec![NoteTiming { id: 2, start_time: 0.5, duration: 2.0, frequency: Some(440.0) }]
+        );
+
+        // The index follows the cache
+        evaluator.cache.insert(6, note(time(3, 2), None));
+        evaluator.cache.remove(&2);
+        assert_eq!(ids(evaluator.notes_in_range(1.0, 2.0)), vec![3, 6]);
+
+        // One long note under 10,000 short ones: a late window visits only
+        // the path to it, not everything since it started
+        let mut evaluator = PersistentEvaluator::new();
+        evaluator.cache.insert(0, note(time(0, 1), time(20_000, 1)));
+        for id in 1..=10_000 {
+            evaluator.cache.insert(id, note(time(id as i32, 1), time(id as i32 % 3, 2)));
+        }
+        let visits = |evaluator: &PersistentEvaluator, from: f64, to: f64| {
+            let before = TIMELINE_VISITS.with(|visits| visits.get());
+            let found = ids(evaluator.notes_in_range(from, to));
+            (found, TIMELINE_VISITS.with(|visits| visits.get()) - before)
+        };
+        let (found, count) = visits(&evaluator, 9_000.25, 9_002.0);
+        assert_eq!(found, vec![0, 9_001]);
+        assert!(count <= 4 * 15, "{} nodes visited", count);
+        for (from, to) in [(0.0, 1.0), (0.5, 0.75), (4.25, 7.0), (9_999.5, 20_001.0), (20_000.0, 20_001.0)] {
+            let (found, _) = visits(&evaluator, from, to);
+            let timeline = evaluator.cache.timeline();
+            let expected: Vec<u32> = timeline
+                .entries
+                .iter()
+                .filter(|&&(start, duration, _)| start < to && (start >= from || start + duration > from))
+                .map(|entry| entry.2)
+                .collect();
+            assert_eq!(found, expected, "[{}, {})", from, to);
+        }
+    }
+
+    #[test]
+    fn test_module_extent() {
+        let mut evaluator = PersistentEvaluator::new();
+        assert!(evaluator.module_extent().is_none());
+
+        let time = |n, d| Some(FractionData::from_fraction(&Fraction::new(n, d)));
+        let note = |start, duration| EvaluatedNote { start_time: start, duration, ..Default::default() };
+        evaluator.cache.insert(1, note(time(1, 3), time(1, 3)));
+        let extent = evaluator.module_extent().unwrap();
+        assert_eq!((extent.start.to_fraction(), extent.end.to_fraction()), (Fraction::new(1, 3), Fraction::new(2, 3)));
+        assert_eq!(extent.to_f64(), ModuleExtent { start: 1.0 / 3.0, end: 2.0 / 3.0, last_note_id: 1 });
+
+        // Note 2 starts first and lasts longest; note 3 starts last
+        evaluator.cache.insert(2, note(time(0, 1), time(5, 1)));
+        evaluator.cache.insert(3, note(time(4, 1), time(1, 2)));
+        evaluator.cache.insert(4, note(time(-1, 1), None));
+        let extent = evaluator.module_extent().unwrap();
+        assert_eq!((extent.start.to_fraction(), extent.end.to_fraction()), (Fraction::new(0, 1), Fraction::new(5, 1)));
+        assert_eq!(extent.last_note_id, 2);
+
+        // Of notes ending together the lowest id wins, irrational ends compare by value
+        evaluator.cache.insert(5, note(time(4, 1), time(1, 1)));
+        assert_eq!(evaluator.module_extent().unwrap().last_note_id, 2);
+        let irrational = Some(FractionData::from_value(&Value::from(5.5f64.sqrt())));
+        evaluator.cache.insert(6, note(time(3, 1), irrational));
+        let extent = evaluator.module_extent().unwrap().to_f64();
+        assert_eq!((extent.end, extent.last_note_id), (3.0 + 5.5f64.sqrt(), 6));
+
+        evaluator.cache.clear();
+        assert!(evaluator.module_extent().is_none());
+    }
+
+    #[test]
+    fn test_snapshots() {
+        let mut evaluator = PersistentEvaluator::new();
+        register_chain(&mut evaluator, 4, 1, 2);
+        evaluator.mark_dirty(4);
+        let cached = |evaluator: &PersistentEvaluator| {
+            (1..=4).map(|id| serde_json::to_vec(&evaluator.cached_note(id)).unwrap()).collect::<Vec<_>>()
+        };
+        let before = cached(&evaluator);
+        let id = evaluator.snapshot();
+
+        // Edit note 1, remove note 3 and add note 5
+        let duration = make_const_bytecode(3, 1);
+        evaluator.register_expression(1, Var::Duration as u8, &duration, duration.len());
+        evaluator.mark_dirty(1);
+        evaluator.evaluate_dirty_auto();
+        evaluator.remove_note(3);
+        evaluator.register_expression(5, Var::Duration as u8, &duration, duration.len());
+        assert_ne!(cached(&evaluator), before);
+
+        let generation = evaluator.generation();
+        assert!(evaluator.restore(id));
+        assert!(evaluator.generation() > generation);
+        assert_eq!(cached(&evaluator), before);
+        assert_eq!(evaluator.expression(1, Var::Duration).unwrap().0, &make_const_bytecode(1, 2)[..]);
+        assert!(evaluator.expression(5, Var::Duration).is_none());
+        assert_eq!(evaluator.dirty, HashSet::from([4]));
+        assert_eq!(evaluator.graph().get_dependents(3), HashSet::from([4]));
+        assert!(!evaluator.restore(id + 1));
+
+        // The least recently taken or restored snapshot goes first
+        evaluator.set_max_snapshots(3);
+        let second = evaluator.snapshot();
+        let third = evaluator.snapshot();
+        assert!(evaluator.restore(id));
+        let fourth = evaluator.snapshot();
+        assert_eq!(evaluator.list_snapshots(), vec![id, third, fourth]);
+        assert!(!evaluator.restore(second));
+        evaluator.set_max_snapshots(1);
+        assert_eq!(evaluator.list_snapshots(), vec![fourth]);
+        assert!(evaluator.drop_snapshot(fourth));
+        assert!(!evaluator.drop_snapshot(fourth));
+        assert!(evaluator.list_snapshots().is_empty());
+    }
+
+    #[test]
+    fn test_playback_arrays() {
+        let mut evaluator = PersistentEvaluator::new();
+        register_chain(&mut evaluator, 3, 1, 2);
+        // Note 2 plays 440 * log2(3) Hz, approximated in eighths; note 4 has
+        // no frequency
+        let mut frequency = make_const_bytecode(3, 1);
+        frequency.extend(make_const_bytecode(2, 1));
+        frequency.push(Op::Log as u8);
+        frequency.extend(make_const_bytecode(440, 1));
+        frequency.push(Op::Mul as u8);
+        evaluator.register_expression(2, Var::Frequency as u8, &frequency, frequency.len());
+        let start = make_const_bytecode(-1, 1);
+        evaluator.register_expression(4, Var::StartTime as u8, &start, start.len());
+        evaluator.register_expression(4, Var::Duration as u8, &start, start.len());
+        evaluator.set_approximation_denominator("frequency", 8).unwrap();
+        evaluator.mark_dirty_batch(&[2, 4]);
+        evaluator.evaluate_dirty_auto();
+
+        let arrays = evaluator.playback_arrays(0);
+        assert_eq!(arrays.ids, vec![1, 2, 3]);
+        assert_eq!(arrays.removed, vec![4]);
+        assert_eq!(arrays.start_times, vec![0.0, 0.5, 1.0]);
+        assert_eq!(arrays.durations, vec![0.5; 3]);
+        let cached = evaluator.cached_note(2).unwrap().frequency.clone().unwrap();
+        assert!(cached.corrupted);
+        assert_eq!(arrays.frequencies, vec![440.0, cached.f.unwrap(), 440.0]);
+        assert_ne!(arrays.frequencies[1], cached.n as f64 / cached.d as f64);
+
+        // Only notes stored since the generation was read
+        let generation = evaluator.generation();
+        let duration = make_const_bytecode(2, 1);
+        evaluator.register_expression(3, Var::Duration as u8, &duration, duration.len());
+        evaluator.mark_dirty(3);
+        evaluator.evaluate_dirty_auto();
+        let arrays = evaluator.playback_arrays(generation);
+        assert_eq!((arrays.ids, arrays.durations), (vec![3], vec![2.0]));
+        assert!(evaluator.playback_arrays(evaluator.generation()).ids.is_empty());
+
+        // Restoring replaces every note, and drops those the snapshot lacks
+        let id = evaluator.snapshot();
+        evaluator.register_expression(5, Var::StartTime as u8, &duration, duration.len());
+        evaluator.mark_dirty(5);
+        evaluator.evaluate_dirty_auto();
+        let generation = evaluator.generation();
+        evaluator.restore(id);
+        let arrays = evaluator.playback_arrays(generation);
+        assert_eq!((arrays.ids, arrays.removed), (vec![1, 2, 3], vec![4, 5]));
+        assert!(evaluator.playback_arrays(evaluator.generation()).removed.is_empty());
+
+        // Removed and no longer playable notes are listed until stored again
+        let generation = evaluator.generation();
+        evaluator.remove_note(1);
+        evaluator.invalidate_note(3);
+        let arrays = evaluator.playback_arrays(generation);
+        assert_eq!((arrays.ids, arrays.removed), (vec![], vec![1, 3]));
+        evaluator.evaluate_dirty_auto();
+        let arrays = evaluator.playback_arrays(generation);
+        assert_eq!((arrays.ids, arrays.removed), (vec![3], vec![1]));
+
+        // Imports are stamped like evaluations: before the generation advances
+        let generation = evaluator.generation();
+        let note = serde_json::to_value(evaluator.cached_note(2).unwrap()).unwrap();
+        let merge = evaluator.import_cache_merge(HashMap::from([("1".to_string(), note)]), false);
+        assert_eq!(merge.imported, vec![1]);
+        assert_eq!(evaluator.playback_arrays(generation).ids, vec![1]);
+        assert!(evaluator.playback_arrays(evaluator.generation()).ids.is_empty());
+
+        // Remapping moves a note off its old id
+        let generation = evaluator.generation();
+        evaluator.remap_note_ids(&HashMap::from([(1, 9)])).unwrap();
+        let arrays = evaluator.playback_arrays(generation);
+        assert_eq!((arrays.ids, arrays.removed), (vec![9], vec![1]));
+    }
+
+    #[test]
+    fn test_custom_variables() {
+        use crate::bytecode::CUSTOM_VAR_FIRST;
+        use crate::compiler::ExpressionCompiler;
+
+        const VELOCITY: u8 = CUSTOM_VAR_FIRST;
+        let mut compiler = ExpressionCompiler::new();
+        compiler.register_custom_variable("velocity", VELOCITY).unwrap();
+        let mut evaluator = PersistentEvaluator::new();
+        let mut register = |evaluator: &mut PersistentEvaluator, note_id: u32, var_index: u8, text: &str| {
+            let compiled = compiler.try_compile(text).unwrap();
+            evaluator.register_expression(note_id, var_index, &compiled.bytecode, compiled.bytecode.len());
+            evaluator.mark_dirty(note_id);
+        };
+
+        // Note 2 plays a little softer than note 1, and later
+        register(&mut evaluator, 1, VELOCITY, "new Fraction(100, 127)");
+        register(&mut evaluator, 1, Var::StartTime as u8, "new Fraction(0)");
+        register(
+            &mut evaluator,
+            2,
+            VELOCITY,
+            "module.getNoteById(1).getVariable('custom:velocity').mul(new Fraction(9, 10))",
+        );
+        register(&mut evaluator, 2, Var::StartTime as u8, "new Fraction(1)");
+        assert_eq!(evaluator.graph().get_dependencies(2), HashSet::from([1]));
+        let report = evaluator.evaluate_dirty_auto();
+        assert_eq!(report.evaluated, vec![1, 2]);
+        assert!(report.changed_vars.values().all(|&mask| mask & CHANGED_CUSTOM != 0));
+
+        let velocity = |evaluator: &PersistentEvaluator, id: u32| {
+            evaluator.cached_note(id).unwrap().value_at(VELOCITY).map(FractionData::to_fraction)
+        };
+        assert_eq!(velocity(&evaluator, 2), Some(Fraction::new(90, 127)));
+        let json = serde_json::to_value(evaluator.cached_note(2).unwrap()).unwrap();
+        assert_eq!(json["customVars"]["32"]["n"], 90);
+        assert!(json.get("customCorruptionFlags").is_none());
+
+        // Editing note 1's velocity reaches note 2
+        register(&mut evaluator, 1, VELOCITY, "new Fraction(50, 127)");
+        let report = evaluator.evaluate_dirty_auto();
+        assert_eq!(report.changed, vec![1, 2]);
+        assert_eq!(report.changed_vars[&2], CHANGED_CUSTOM);
+        assert_eq!(velocity(&evaluator, 2), Some(Fraction::new(45, 127)));
+
+        // Unset custom variables read 0, or the base note's when inherited
+        register(&mut evaluator, 0, VELOCITY, "new Fraction(1, 2)");
+        register(&mut evaluator, 3, VELOCITY, "module.getNoteById(4).getVariable('custom:velocity')");
+        evaluator.evaluate_dirty_auto();
+        assert_eq!(velocity(&evaluator, 3), Some(Fraction::new(0, 1)));
+        evaluator.set_custom_inherited(VELOCITY, true).unwrap();
+        evaluator.evaluate_dirty_auto();
+        assert_eq!(velocity(&evaluator, 3), Some(Fraction::new(1, 2)));
+        assert!(evaluator.set_custom_inherited(Var::Tempo as u8, true).is_err());
+
+        // Irrational custom values are flagged, with their source
+        register(&mut evaluator, 1, VELOCITY, "new Fraction(2).sqrt()");
+        evaluator.evaluate_dirty_auto();
+        let note = evaluator.cached_note(2).unwrap();
+        assert!(note.is_custom_corrupted(VELOCITY) && note.corruption_flags == 0);
+        assert_eq!(note.corruption_sources[0].variable, "custom:32");
+
+        // Failures and self-references are recorded like built-in ones
+        evaluator.register_expression(6, VELOCITY, &[Op::Add as u8], 1);
+        evaluator.mark_dirty(6);
+        register(&mut evaluator, 7, VELOCITY, "module.getNoteById(7).getVariable('custom:velocity').add(new Fraction(1))");
+        evaluator.evaluate_dirty_auto();
+        assert_eq!(velocity(&evaluator, 6), None);
+        assert_eq!(velocity(&evaluator, 7), Some(Fraction::new(1, 1)));
+        let mut errors: Vec<(u32, u8, &str)> =
+            evaluator.evaluation_errors().iter().map(|e| (e.note_id, e.var, e.error.code())).collect();
+        errors.sort_unstable();
+        assert_eq!(errors, [(6, VELOCITY, "STACK_UNDERFLOW"), (7, VELOCITY, "SELF_REFERENCE")]);
+        let self_reference = evaluator.evaluation_errors().iter().find(|e| e.note_id == 7).unwrap();
+        let data = self_reference.error.to_data(Some((7, VELOCITY)));
+        assert_eq!(data.var.as_deref(), Some("custom:32"));
+        assert!(data.message.contains("reads custom:32 of note 7"));
+
+        // Custom expressions survive binary export and id remapping
+        let buffer = evaluator.export_bytecode_binary();
+        let mut loaded = PersistentEvaluator::new();
+        loaded.register_notes_binary(&buffer).unwrap();
+        assert_eq!(loaded.export_bytecode_binary(), buffer);
+        loaded.remap_note_ids(&HashMap::from([(1, 11)])).unwrap();
+        assert_eq!(loaded.graph().get_dependencies(2), HashSet::from([11]));
+        loaded.evaluate_dirty_auto();
+        let velocity = loaded.cached_note(2).unwrap().value_at(VELOCITY).unwrap().to_f64();
+        assert!((velocity - 0.9 * 2f64.sqrt()).abs() < 1e-12);
+
+        // The stateless evaluator reads them from the cache it is given
+        let compiled = compiler.try_compile("module.getNoteById(5).getVariable('custom:velocity')").unwrap();
+        let cache = HashMap::from([(0, evaluator.cached_note(0).unwrap().clone())]);
+        let mut stateless = Evaluator::new();
+        let evaluate = |evaluator: &mut Evaluator| {
+            evaluator.evaluate(&compiled.bytecode, compiled.bytecode.len(), &cache).unwrap().to_fraction()
+        };
+        assert_eq!(evaluate(&mut stateless), Fraction::new(0, 1));
+        stateless.set_custom_inherited(VELOCITY, true).unwrap();
+        assert_eq!(evaluate(&mut stateless), Fraction::new(1, 2));
+    }
+
+    #[test]
+    fn test_evaluate_what_if() {
+        use crate::compiler::ExpressionCompiler;
+
+        let mut compiler = ExpressionCompiler::new();
+        let mut evaluator = PersistentEvaluator::new();
+        let mut register = |note_id: u32, var: Var, text: &str| {
+            let compiled = compiler.try_compile(text).unwrap();
+            evaluator.register_expression(note_id, var as u8, &compiled.bytecode, compiled.bytecode.len());
+            evaluator.mark_dirty(note_id);
+        };
+        register(0, Var::StartTime, "new Fraction(0)");
+        register(0, Var::Tempo, "new Fraction(60)");
+        register(0, Var::Frequency, "new Fraction(440)");
+        // Notes 1..=3 are one beat each, back to back
+        for id in 1..=3 {
+            let start = match id {
+                1 => "module.baseNote.getVariable('startTime')".to_string(),
+                _ => format!(
+                    "module.getNoteById({0}).getVariable('startTime').add(module.getNoteById({0}).getVariable('duration'))",
+                    id - 1
+                ),
+            };
+            register(id, Var::StartTime, &start);
+            register(id, Var::Duration, "new Fraction(60).div(module.findTempo(module.baseNote))");
+        }
+        evaluator.evaluate_dirty_auto();
+        let generation = evaluator.generation();
+        let start = |note: &EvaluatedNote| note.start_time.as_ref().unwrap().to_fraction();
+
+        let tempo = DefaultsData { tempo: Some(FractionData::from_fraction(&Fraction::new(120, 1))), ..Default::default() };
+        let ids: Vec<u32> = evaluator.graph().get_all_dependents(0).into_iter().collect();
+        let results = evaluator.evaluate_what_if(&tempo, &ids).unwrap();
+        let starts: Vec<Fraction> = (1..=3).map(|id| start(&results[&id])).collect();
+        assert_eq!(starts, [0, 1, 2].map(|n| Fraction::new(n, 2)));
+        assert!(!results.contains_key(&0));
+
+        // The real cache, dirty set and generation are untouched
+        let starts: Vec<Fraction> = (1..=3).map(|id| start(evaluator.cached_note(id).unwrap())).collect();
+        assert_eq!(starts, [0, 1, 2].map(|n| Fraction::new(n, 1)));
+        assert_eq!(evaluator.generation(), generation);
+        assert!(evaluator.dirty.is_empty());
+
+        // Left-out notes keep their cached values
+        let results = evaluator.evaluate_what_if(&tempo, &[3]).unwrap();
+        assert_eq!(start(&results[&3]), Fraction::new(2, 1));
+        assert_eq!(results[&3].duration.as_ref().unwrap().to_fraction(), Fraction::new(1, 2));
+
+        let irrational = DefaultsData { tempo: Some(FractionData::from_value(&Value::irrational(2f64.sqrt()))), ..Default::default() };
+        let error = evaluator.evaluate_what_if(&irrational, &ids).err();
+        assert_eq!(error.as_deref(), Some("Override tempo must be a rational {n, d}"));
+
+        // The {n, d} shape JavaScript passes reads as a positive tempo
+        let tempo: DefaultsData = serde_json::from_str(r#"{"tempo": {"n": 120, "d": 1}}"#).unwrap();
+        let results = evaluator.evaluate_what_if(&tempo, &ids).unwrap();
+        assert_eq!(results[&2].duration.as_ref().unwrap().to_fraction(), Fraction::new(1, 2));
+        let zero_sign: DefaultsData = serde_json::from_str(r#"{"tempo": {"s": 0, "n": 120, "d": 1}}"#).unwrap();
+        let error = evaluator.evaluate_what_if(&zero_sign, &ids).err();
+        assert_eq!(error.as_deref(), Some("Override tempo has sign 0 for numerator 120"));
+    }
+
+    #[test]
+    fn test_staging() {
+        let mut evaluator = PersistentEvaluator::new();
+        register_chain(&mut evaluator, 3, 1, 1);
+        evaluator.evaluate_dirty_auto();
+        let generation = evaluator.generation();
+        let start = |evaluator: &PersistentEvaluator, id| evaluator.cached_note(id).unwrap().start_time.as_ref().unwrap().to_fraction();
+        let lengthen = |evaluator: &mut PersistentEvaluator| {
+            let duration = make_const_bytecode(5, 1);
+            evaluator.register_expression(1, Var::Duration as u8, &duration, duration.len());
+            evaluator.mark_dirty(1);
+            evaluator.evaluate_dirty_auto();
+        };
+
+        // Staged edits are seen by reads and by later evaluations
+        evaluator.begin_staging().unwrap();
+        assert_eq!(evaluator.begin_staging().unwrap_err(), "Staging is already active");
+        lengthen(&mut evaluator);
+        assert_eq!(start(&evaluator, 3), Fraction::new(6, 1));
+        assert_eq!(evaluator.generation(), generation);
+
+        // Rolling back restores the values, bytecode, graph and generation
+        evaluator.rollback_staging().unwrap();
+        assert!(!evaluator.is_staging());
+        assert_eq!(start(&evaluator, 3), Fraction::new(2, 1));
+        assert_eq!(evaluator.expression(1, Var::Duration).unwrap().0, make_const_bytecode(1, 1).as_slice());
+        assert_eq!(evaluator.generation(), generation);
+        // Notes the staged edits touched are reported again with their old values
+        assert!(evaluator.playback_arrays(generation + 1).ids.is_empty());
+        assert_eq!(evaluator.playback_arrays(generation).ids, vec![1, 2, 3]);
+        evaluator.mark_dirty(1);
+        assert_eq!(evaluator.evaluate_dirty_auto().changed, Vec::<u32>::new());
+        assert_eq!(evaluator.generation(), generation + 1);
+
+        // So are errors, instruments and macros
+        let broken = [Op::Add as u8];
+        evaluator.register_expression(9, Var::Frequency as u8, &broken, 1);
+        evaluator.mark_dirty(9);
+        evaluator.evaluate_dirty_auto();
+        evaluator.begin_staging().unwrap();
+        evaluator.clear_evaluation_errors();
+        evaluator.register_expression(7, Var::Frequency as u8, &broken, 1);
+        evaluator.mark_dirty(7);
+        evaluator.set_instrument(2, 4);
+        let body = make_const_bytecode(1, 1);
+        evaluator.register_macro(3, &body, body.len()).unwrap();
+        evaluator.evaluate_dirty_auto();
+        assert!(evaluator.evaluation_errors().iter().any(|e| e.note_id == 7));
+        evaluator.rollback_staging().unwrap();
+        assert_eq!(evaluator.evaluation_errors().iter().map(|e| e.note_id).collect::<Vec<_>>(), vec![9]);
+        assert_eq!(evaluator.instruments.instrument(2), 0);
+        assert!(evaluator.macros.get(3).is_none());
+        assert!(!evaluator.bytecode_store.contains_key(&7));
+        evaluator.mark_dirty_batch(&[1, 2, 3]);
+        evaluator.evaluate_dirty_auto();
+
+        // Committing keeps them and bumps the generation once
+        let generation = evaluator.generation();
+        evaluator.begin_staging().unwrap();
+        lengthen(&mut evaluator);
+        evaluator.invalidate_note(2);
+        evaluator.commit_staging().unwrap();
+        assert_eq!(evaluator.generation(), generation + 1);
+        assert_eq!(start(&evaluator, 3), Fraction::new(6, 1));
+        assert_eq!(evaluator.commit_staging().unwrap_err(), "Staging is not active");
+        assert_eq!(evaluator.rollback_staging().unwrap_err(), "Staging is not active");
+    }
+
+    #[test]
+    fn test_corrupted_notes() {
+        let mut evaluator = PersistentEvaluator::new();
+        register_chain(&mut evaluator, 3, 1, 1);
+        assert!(evaluator.corrupted_notes().is_empty());
+        assert_eq!(evaluator.corruption_summary(), CorruptionSummary::default());
+
+        // A symbolic frequency on note 1 and an irrational start on note 3
+        let mut semitone = make_const_bytecode(2, 1);
+        semitone.extend(make_const_bytecode(1, 12));
+        semitone.push(Op::Pow as u8);
+        evaluator.register_expression(1, Var::Frequency as u8, &semitone, semitone.len());
+        let mut log = make_const_bytecode(3, 1);
+        log.extend(make_const_bytecode(2, 1));
+        log.push(Op::Log as u8);
+        evaluator.register_expression(3, Var::StartTime as u8, &log, log.len());
+        evaluator.mark_dirty(1);
+        evaluator.mark_dirty(3);
+        evaluator.evaluate_dirty_auto();
+
+        let frequency = corruption_flag_for_var(Var::Frequency as u8);
+        let start_time = corruption_flag_for_var(Var::StartTime as u8);
+        assert_eq!(
+            evaluator.corrupted_notes(),
+            vec![
+                CorruptedNote { id: 1, corruption_flags: frequency, custom_corruption_flags: 0 },
+                CorruptedNote { id: 3, corruption_flags: start_time, custom_corruption_flags: 0 },
+            ]
+        );
+        assert_eq!(
+            evaluator.corruption_summary(),
+            CorruptionSummary { notes: 2, start_time: 1, frequency: 1, ..Default::default() }
+        );
+        assert!(evaluator.is_note_corrupted(1, Var::Frequency as u8));
+        assert!(evaluator.is_note_corrupted(3, Var::StartTime as u8));
+        assert!(!evaluator.is_note_corrupted(2, Var::StartTime as u8));
+        assert!(!evaluator.is_note_corrupted(1, crate::bytecode::CUSTOM_VAR_FIRST));
+        assert!(!evaluator.is_note_corrupted(9, Var::Frequency as u8));
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    fn test_cache_from_js_map_and_object() {
+        let notes: Vec<(u32, EvaluatedNote)> = (0..2000)
+            .map(|id| {
+                let start_time = Some(FractionData::from_fraction(&Fraction::new(id as i32, 4)));
+                (id, EvaluatedNote { start_time, ..Default::default() })
+            })
+            .collect();
+        let map = js_sys::Map::new();
+        let object = js_sys::Object::new();
+        for (id, note) in &notes {
+            let value = serde_wasm_bindgen::to_value(note).unwrap();
+            map.set(&JsValue::from(*id), &value);
+            js_sys::Reflect::set(&object, &JsValue::from_str(&id.to_string()), &value).unwrap();
+        }
+
+        // Both shapes read the same notes
+        let timed = |cache: JsValue| {
+            let start = js_sys::Date::now();
+            let notes = cache_from_js(cache).unwrap();
+            (notes, js_sys::Date::now() - start)
+        };
+        let (from_map, map_ms) = timed(map.clone().into());
+        let (from_object, object_ms) = timed(object.clone().into());
+        wasm_bindgen_test::console_log!("{} notes: Map {} ms, object {} ms", notes.len(), map_ms, object_ms);
+        assert_eq!((from_map.len(), from_object.len()), (notes.len(), notes.len()));
+        for (id, note) in &notes {
+            let expected = serde_json::to_string(note).unwrap();
+            assert_eq!(serde_json::to_string(&from_map[id]).unwrap(), expected);
+            assert_eq!(serde_json::to_string(&from_object[id]).unwrap(), expected);
+        }
+
+        // And evaluate alike
+        let mut bytecode = vec![Op::LoadRef as u8];
+        write_u16(&mut bytecode, 7);
+        bytecode.push(Var::StartTime as u8);
+        let mut evaluator = Evaluator::new();
+        for cache in [JsValue::from(map.clone()), JsValue::from(object.clone())] {
+            let result = evaluator.evaluate_expression_js(&bytecode, bytecode.len(), cache, JsValue::UNDEFINED).unwrap();
+            let data: FractionData = serde_wasm_bindgen::from_value(result).unwrap();
+            assert_eq!(data.to_fraction(), Fraction::new(7, 4));
+        }
+        let mut persistent = PersistentEvaluator::new();
+        persistent.import_cache(map.clone().into()).unwrap();
+        assert_eq!(persistent.cache.len(), notes.len());
+
+        // Malformed keys are reported instead of dropped
+        let fractional = js_sys::Map::new();
+        fractional.set(&JsValue::from(1.5), &serde_wasm_bindgen::to_value(&notes[0].1).unwrap());
+        assert!(cache_from_js(fractional.into()).is_err());
+        map.set(&JsValue::from_str("seven"), &JsValue::NULL);
+        assert!(cache_from_js(map.into()).is_err());
+        let instruments = js_sys::Object::new();
+        js_sys::Reflect::set(&instruments, &JsValue::from_str("7"), &JsValue::from(2)).unwrap();
+        assert_eq!(instruments_from_js(instruments.clone().into()).unwrap(), Some(HashMap::from([(7, 2)])));
+        js_sys::Reflect::set(&instruments, &JsValue::from_str("seven"), &JsValue::from(3)).unwrap();
+        assert!(instruments_from_js(instruments.into()).is_err());
+        js_sys::Reflect::set(&object, &JsValue::from_str("x"), &serde_wasm_bindgen::to_value(&notes[0].1).unwrap()).unwrap();
+        assert!(persistent.import_cache(object.into()).is_err());
+        assert_eq!(persistent.cache.len(), notes.len());
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    fn test_import_cache_merge_js_map() {
+        // Numeric keys, and customVars given as a nested Map
+        let custom = js_sys::Map::new();
+        custom.set(&JsValue::from(40), &serde_wasm_bindgen::to_value(&FractionData::from_fraction(&Fraction::new(3, 2))).unwrap());
+        let note = js_sys::Object::new();
+        js_sys::Reflect::set(&note, &JsValue::from_str("customVars"), &custom).unwrap();
+        let map = js_sys::Map::new();
+        map.set(&JsValue::from(5), &note);
+        map.set(&JsValue::from(1.5), &js_sys::Object::new());
+        map.set(&JsValue::from(6), &JsValue::from_str("soon"));
+
+        let mut evaluator = PersistentEvaluator::new();
+        let merge = evaluator.import_cache_merge_js(map.into(), true).unwrap();
+        let merge: CacheMerge = serde_wasm_bindgen::from_value(merge).unwrap();
+        assert_eq!(merge.imported, vec![5]);
+        assert_eq!(merge.invalid.keys().collect::<Vec<_>>(), vec!["1.5", "6"]);
+        assert_eq!(evaluator.cached_note(5).unwrap().custom[&40].to_fraction(), Fraction::new(3, 2));
+        assert!(evaluator.import_cache_merge_js(JsValue::from(3), true).is_err());
+    }
+
+    #[test]
+    fn test_import_cache_merge() {
+        let mut evaluator = PersistentEvaluator::new();
+        register_chain(&mut evaluator, 10, 1, 1);
+        let before: HashMap<u32, String> =
+            (1..=10).map(|id| (id, serde_json::to_string(evaluator.cached_note(id).unwrap()).unwrap())).collect();
+        let generation = evaluator.generation();
+
+        let entries: HashMap<String, serde_json::Value> = serde_json::from_str(
+            r#"{
+                "3": {"startTime": {"s": 1, "n": 5, "d": 2, "corrupted": false}},
+                "7": {"duration": {"s": 1, "n": 3, "d": 1, "corrupted": false}},
+                "x": {},
+                "12": {"startTime": "soon"}
+            }"#,
+        )
+        .unwrap();
+        let merge = evaluator.import_cache_merge(entries, true);
+        assert_eq!(merge.imported, vec![3, 7]);
+        assert!(merge.skipped.is_empty());
+        assert_eq!(merge.invalid.keys().collect::<Vec<_>>(), vec!["12", "x"]);
+        assert_eq!(evaluator.generation(), generation + 1);
+
+        // The other eight notes are untouched, and the dependents are dirty
+        let start = evaluator.cached_note(3).unwrap().start_time.as_ref().unwrap().to_fraction();
+        assert_eq!(start, Fraction::new(5, 2));
+        for id in (1..=10).filter(|id| ![3, 7].contains(id)) {
+            assert_eq!(serde_json::to_string(evaluator.cached_note(id).unwrap()).unwrap(), before[&id]);
+        }
+        assert_eq!(evaluator.dirty, HashSet::from([4, 5, 6, 8, 9, 10]));
+
+        // Without overwrite, cached notes are skipped
+        let entries = HashMap::from([("2".to_string(), serde_json::json!({})), ("11".to_string(), serde_json::json!({}))]);
+        let merge = evaluator.import_cache_merge(entries, false);
+        assert_eq!((merge.imported, merge.skipped), (vec![11], vec![2]));
+        assert_eq!(serde_json::to_string(evaluator.cached_note(2).unwrap()).unwrap(), before[&2]);
+    }
+
+    #[test]
+    fn test_validate_cache() {
+        let rational = |n: i32, d: i32| Some(FractionData::from_fraction(&Fraction::new(n, d)));
+        // Not through a Value, which would replace non-finite floats
+        let float = |value: f64| Some(FractionData { f: Some(value), corrupted: true, ..FractionData::from_fraction(&Fraction::new(0, 1)) });
+        let mut evaluator = PersistentEvaluator::new();
+        let notes = [
+            (1, EvaluatedNote { start_time: rational(0, 1), duration: rational(1, 2), frequency: rational(440, 1), ..Default::default() }),
+            (2, EvaluatedNote { start_time: rational(-1, 4), duration: rational(-1, 1000), ..Default::default() }),
+            (3, EvaluatedNote { frequency: rational(0, 1), tempo: rational(-60, 1), ..Default::default() }),
+            (4, EvaluatedNote { frequency: float(-1e-9), tempo: float(f64::INFINITY), duration: float(f64::NAN), ..Default::default() }),
+            (5, EvaluatedNote { custom: HashMap::from([(40, float(f64::NEG_INFINITY).unwrap())]), ..Default::default() }),
+        ];
+        for (id, note) in notes {
+            evaluator.cache.insert(id, note);
+        }
+
+        let problem = |id: u32, var: &str, problem: &str| CacheProblem { id, var: var.to_string(), problem: problem.to_string() };
+        assert_eq!(
+            evaluator.validate_cache(),
+            vec![
+                problem(2, "startTime", "NEGATIVE"),
+                problem(2, "duration", "NEGATIVE"),
+                problem(3, "frequency", "NOT_POSITIVE"),
+                problem(3, "tempo", "NOT_POSITIVE"),
+                problem(4, "duration", "NON_FINITE"),
+                problem(4, "frequency", "NOT_POSITIVE"),
+                problem(4, "tempo", "NON_FINITE"),
+                problem(5, "custom:40", "NON_FINITE"),
+            ]
+        );
+
+        // Optionally reported for the notes each evaluation caches
+        let mut evaluator = PersistentEvaluator::new();
+        register_chain(&mut evaluator, 2, -1, 2);
+        evaluator.mark_dirty(1);
+        assert!(evaluator.evaluate_dirty_auto().problems.is_empty());
+        evaluator.set_validate_after_evaluate(true);
+        evaluator.mark_dirty(1);
+        let report = evaluator.evaluate_dirty_auto();
+        assert_eq!(
+            report.problems,
+            vec![problem(1, "duration", "NEGATIVE"), problem(2, "startTime", "NEGATIVE"), problem(2, "duration", "NEGATIVE")]
+        );
+    }
+
+    #[test]
+    fn test_self_reference() {
+        let load = |note_id: u32, var: Var| {
+            let mut bc = vec![Op::LoadRef as u8];
+            write_u16(&mut bc, note_id as u16);
+            bc.push(var as u8);
+            bc
+        };
+        let plus_one = |mut bc: Vec<u8>| {
+            bc.extend(make_const_bytecode(1, 1));
+            bc.push(Op::Add as u8);
+            bc
+        };
+        let mut evaluator = PersistentEvaluator::new();
+        let start = make_const_bytecode(1, 1);
+        // Note 7's duration reads itself; 1 and 2 read each other's duration
+        for (id, duration) in [(7, plus_one(load(7, Var::Duration))), (1, load(2, Var::Duration)), (2, load(1, Var::Duration))] {
+            evaluator.register_expression(id, Var::StartTime as u8, &start, start.len());
+            evaluator.register_expression(id, Var::Duration as u8, &duration, duration.len());
+        }
+        // Note 3's duration reads its own start time, which is fine
+        let mut twice = load(3, Var::StartTime);
+        twice.extend(make_const_bytecode(2, 1));
+        twice.push(Op::Mul as u8);
+        evaluator.register_expression(3, Var::StartTime as u8, &start, start.len());
+        evaluator.register_expression(3, Var::Duration as u8, &twice, twice.len());
+
+        // Permissive: the default is read, the same on every run, with a warning
+        let default = evaluator.machine.defaults.value(Var::Duration).to_fraction();
+        let duration = |evaluator: &PersistentEvaluator, id| evaluator.cached_note(id).unwrap().duration.as_ref().map(|d| d.to_fraction());
+        for _ in 0..2 {
+            evaluator.mark_dirty_batch(&[1, 2, 3, 7]);
+            evaluator.evaluate_dirty_auto();
+            assert_eq!(duration(&evaluator, 7), Some(default.add(&Fraction::new(1, 1))));
+            assert_eq!(duration(&evaluator, 1), Some(default.clone()));
+            assert_eq!(duration(&evaluator, 2), Some(default.clone()));
+            assert_eq!(duration(&evaluator, 3), Some(Fraction::new(2, 1)));
+        }
+        let flagged = |evaluator: &PersistentEvaluator| {
+            let mut flagged: Vec<(u32, u32, u8)> = evaluator
+                .evaluation_errors()
+                .iter()
+                .map(|e| match e.error {
+                    EvalError::SelfReference { note_id, var, .. } => (e.note_id, note_id, var),
+                    _ => panic!("unexpected error {}", e.error),
+                })
+                .collect();
+            flagged.sort_unstable();
+            flagged.dedup();
+            flagged
+        };
+        assert_eq!(flagged(&evaluator), vec![(1, 2, Var::Duration as u8), (2, 1, Var::Duration as u8), (7, 7, Var::Duration as u8)]);
+        assert_eq!(evaluator.evaluation_errors()[0].error.code(), "SELF_REFERENCE");
+
+        // Strict: the properties fail instead
+        evaluator.clear_evaluation_errors();
+        evaluator.set_strict(true);
+        evaluator.mark_dirty_batch(&[1, 2, 3, 7]);
+        evaluator.evaluate_dirty_auto();
+        assert_eq!(duration(&evaluator, 7), None);
+        assert_eq!(duration(&evaluator, 1), None);
+        assert_eq!(duration(&evaluator, 3), Some(Fraction::new(2, 1)));
+        assert_eq!(flagged(&evaluator), vec![(1, 2, Var::Duration as u8), (2, 1, Var::Duration as u8), (7, 7, Var::Duration as u8)]);
+
+        // Breaking the cycle clears it
+        evaluator.clear_evaluation_errors();
+        let constant = make_const_bytecode(3, 1);
+        evaluator.register_expression(2, Var::Duration as u8, &constant, constant.len());
+        evaluator.mark_dirty_batch(&[1, 2]);
+        evaluator.evaluate_dirty_auto();
+        assert_eq!(duration(&evaluator, 1), Some(Fraction::new(3, 1)));
+        assert!(evaluator.evaluation_errors().is_empty());
+
+        // Cycles through macro bodies and custom variables are found too
+        const VELOCITY: u8 = crate::bytecode::CUSTOM_VAR_FIRST;
+        evaluator.set_strict(false);
+        let body = load(5, Var::Duration);
+        evaluator.register_macro(0, &body, body.len()).unwrap();
+        for (id, duration) in [(4, vec![Op::CallMacro as u8, 0, 0]), (5, load(4, Var::Duration))] {
+            evaluator.register_expression(id, Var::StartTime as u8, &start, start.len());
+            evaluator.register_expression(id, Var::Duration as u8, &duration, duration.len());
+        }
+        for (id, other) in [(8, 9), (9, 8)] {
+            let mut velocity = vec![Op::LoadRef as u8];
+            write_u16(&mut velocity, other);
+            velocity.push(VELOCITY);
+            evaluator.register_expression(id, VELOCITY, &velocity, velocity.len());
+        }
+        evaluator.mark_dirty_batch(&[4, 5, 7, 8, 9]);
+        evaluator.evaluate_dirty_auto();
+        let expected = vec![
+            (4, 5, Var::Duration as u8),
+            (5, 4, Var::Duration as u8),
+            (7, 7, Var::Duration as u8),
+            (8, 9, VELOCITY),
+            (9, 8, VELOCITY),
+        ];
+        assert_eq!(flagged(&evaluator), expected);
+        assert_eq!(duration(&evaluator, 4), Some(default.clone()));
+        assert_eq!(duration(&evaluator, 5), Some(default.clone()));
+    }
+
+    #[test]
+    fn test_evaluate_closure() {
+        let mut evaluator = PersistentEvaluator::new();
+        // A cold 5-note chain and 1,000 unrelated notes, all dirty
+        for id in 1..=5u32 {
+            let start = if id == 1 {
+                make_const_bytecode(0, 1)
+            } else {
+                let mut bc = vec![Op::LoadRef as u8];
+                write_u16(&mut bc, (id - 1) as u16);
+                bc.push(Var::StartTime as u8);
+                bc.push(Op::LoadRef as u8);
+                write_u16(&mut bc, (id - 1) as u16);
+                bc.push(Var::Duration as u8);
+                bc.push(Op::Add as u8);
+                bc
+            };
+            let duration = make_const_bytecode(1, 2);
+            evaluator.register_expression(id, Var::StartTime as u8, &start, start.len());
+            evaluator.register_expression(id, Var::Duration as u8, &duration, duration.len());
+        }
+        for id in 100..1100 {
+            let start = make_const_bytecode(id as i32, 1);
+            evaluator.register_expression(id, Var::StartTime as u8, &start, start.len());
+        }
+        evaluator.mark_dirty_batch(&(1..=5).chain(100..1100).collect::<Vec<u32>>());
+
+        let report = evaluator.evaluate_closure(5);
+        assert_eq!(report.evaluated, vec![1, 2, 3, 4, 5]);
+        assert_eq!(evaluator.evaluation_stats().expressions, 10);
+        assert_eq!(evaluator.cached_note(5).unwrap().start_time.as_ref().unwrap().to_fraction(), Fraction::new(2, 1));
+        assert_eq!(evaluator.cache.len(), 5);
+        assert_eq!(evaluator.dirty.len(), 1000);
+        assert!(!evaluator.dirty.contains(&3));
+
+        // Cached clean notes are left alone, dirty ones re-evaluated
+        assert!(evaluator.evaluate_closure(5).evaluated.is_empty());
+        evaluator.mark_dirty(4);
+        assert_eq!(evaluator.evaluate_closure(5).evaluated, vec![4, 5]);
+        assert_eq!(evaluator.evaluate_closure(3).evaluated, Vec::<u32>::new());
+        assert_eq!(evaluator.dirty.len(), 1000);
+    }
+
+    #[test]
+    fn test_find_instrument() {
+        // 440 Hz, doubled when note 1 plays instrument 2 or above:
+        // 440 * (1 + min(1, max(0, instrument(1) - 1)))
+        let program = |note_id: i32| {
+            let mut bc = make_const_bytecode(note_id, 1);
+            bc.push(Op::FindInstrument as u8);
+            for (num, op) in [(1, Op::Sub), (0, Op::Max), (1, Op::Min), (1, Op::Add), (440, Op::Mul)] {
+                bc.extend(make_const_bytecode(num, 1));
+                bc.push(op as u8);
+            }
+            bc
+        };
+        let frequency = |evaluator: &PersistentEvaluator, id| {
+            evaluator.cached_note(id).unwrap().frequency.as_ref().unwrap().to_fraction()
+        };
+
+        let mut evaluator = PersistentEvaluator::new();
+        for id in [1, 2] {
+            let bc = program(id as i32);
+            evaluator.register_expression(id, Var::Frequency as u8, &bc, bc.len());
+        }
+        evaluator.set_instrument(1, 3);
+        evaluator.evaluate_dirty(&[1, 2]);
+        assert_eq!(frequency(&evaluator, 1), Fraction::new(880, 1));
+        assert_eq!(frequency(&evaluator, 2), Fraction::new(440, 1));
+        assert_eq!(evaluator.cached_note(1).unwrap().instrument, Some(3));
+        assert_eq!(evaluator.cached_note(2).unwrap().instrument, Some(0));
+
+        // Notes without an instrument follow the default
+        evaluator.clear_dirty();
+        evaluator.set_instrument_default(2);
+        assert_eq!(evaluator.dirty, HashSet::from([2]));
+        evaluator.evaluate_dirty(&[2]);
+        assert_eq!(frequency(&evaluator, 2), Fraction::new(880, 1));
+
+        // Assignments move with a remap
+        evaluator.remap_note_ids(&HashMap::from([(1, 5)])).unwrap();
+        assert_eq!(evaluator.instruments.instrument(5), 3);
+        assert_eq!(evaluator.instruments.instrument(1), 2);
+
+        // The plain evaluator reads an instrument map, or cached notes, with base-note fallback
+        let bc = program(1);
+        let mut plain = Evaluator::new();
+        let instruments = HashMap::from([(1, 1), (0, 4)]);
+        let value = plain.evaluate_with_instruments(&bc, bc.len(), &HashMap::new(), Some(&instruments)).unwrap();
+        assert_eq!(value.to_fraction(), Fraction::new(440, 1));
+        let base_only = HashMap::from([(0, 4)]);
+        let value = plain.evaluate_with_instruments(&bc, bc.len(), &HashMap::new(), Some(&base_only)).unwrap();
+        assert_eq!(value.to_fraction(), Fraction::new(880, 1));
+        let cache = HashMap::from([(1, EvaluatedNote { instrument: Some(2), ..Default::default() })]);
+        assert_eq!(plain.evaluate(&bc, bc.len(), &cache).unwrap().to_fraction(), Fraction::new(880, 1));
+        assert_eq!(plain.evaluate(&bc, bc.len(), &HashMap::new()).unwrap().to_fraction(), Fraction::new(440, 1));
+
+        // Dependents of a note whose instrument changes are re-evaluated
+        let mut evaluator = PersistentEvaluator::new();
+        let bc = program(1);
+        evaluator.register_expression(3, Var::Frequency as u8, &bc, bc.len());
+        let start = make_const_bytecode(0, 1);
+        evaluator.register_expression(1, Var::StartTime as u8, &start, start.len());
+        evaluator.evaluate_dirty(&[1, 3]);
+        assert_eq!(frequency(&evaluator, 3), Fraction::new(440, 1));
+        evaluator.set_instrument(1, 2);
+        assert!(evaluator.dirty.contains(&3));
+        evaluator.evaluate_dirty_auto();
+        assert_eq!(frequency(&evaluator, 3), Fraction::new(880, 1));
+        evaluator.set_instrument_default(0);
+        evaluator.evaluate_dirty_auto();
+        assert_eq!(frequency(&evaluator, 3), Fraction::new(880, 1));
+    }
+
+    #[test]
+    fn test_instrument_fallback_agrees() {
+        // Each note reports instrument(note) + 10 * instrument(base note)
+        let program = |note_id: i32| {
+            let mut bc = make_const_bytecode(note_id, 1);
+            bc.push(Op::FindInstrument as u8);
+            bc.extend(make_const_bytecode(0, 1));
+            bc.push(Op::FindInstrument as u8);
+            bc.extend(make_const_bytecode(10, 1));
+            bc.extend([Op::Mul as u8, Op::Add as u8]);
+            bc
+        };
+        let mut persistent = PersistentEvaluator::new();
+        for id in 1..=3u32 {
+            let bc = program(id as i32);
+            persistent.register_expression(id, Var::Frequency as u8, &bc, bc.len());
+        }
+        persistent.set_instrument_default(7);
+        persistent.set_instrument(1, 3);
+        persistent.evaluate_dirty(&[1, 2, 3]);
+        let frequency = |evaluator: &PersistentEvaluator, id| {
+            evaluator.cached_note(id).unwrap().frequency.as_ref().unwrap().to_fraction()
+        };
+        assert_eq!(frequency(&persistent, 2), Fraction::new(77, 1));
+
+        // Assigning the base note's instrument reaches unassigned notes
+        persistent.set_instrument(0, 5);
+        assert!(persistent.dirty.is_superset(&HashSet::from([2, 3])));
+        persistent.evaluate_dirty_auto();
+        assert_eq!(frequency(&persistent, 1), Fraction::new(53, 1));
+        assert_eq!(frequency(&persistent, 2), Fraction::new(55, 1));
+
+        // A plain evaluator given the same assignments agrees
+        let instruments = HashMap::from([(0, 5), (1, 3)]);
+        let mut plain = Evaluator::new();
+        for id in 1..=3u32 {
+            let bc = program(id as i32);
+            let value = plain.evaluate_with_instruments(&bc, bc.len(), &HashMap::new(), Some(&instruments)).unwrap();
+            assert_eq!(value.to_fraction(), frequency(&persistent, id), "note {}", id);
+            assert_eq!(persistent.instruments.instrument(id), *instruments.get(&id).unwrap_or(&5));
+        }
+    }
+
+    #[test]
+    fn test_audio_events_have_no_drift() {
+        // 1800 notes of 1/3 s = 10 minutes
+        let mut evaluator = PersistentEvaluator::new();
+        register_chain(&mut evaluator, 1800, 1, 3);
+
+        let events = evaluator.export_audio_events(48000, 0.0, 600.0, false);
+        assert_eq!(events.ids.len(), 1800);
+        for i in 0..events.ids.len() {
+            assert_eq!(events.ids[i], i as u32 + 1);
+            assert_eq!(events.start_samples[i], i as f64 * 16000.0);
+            assert_eq!(events.duration_samples[i], 16000.0);
+        }
+
+        // Durations that don't divide the sample rate still tile exactly
+        let mut evaluator = PersistentEvaluator::new();
+        register_chain(&mut evaluator, 700, 1, 7);
+        let events = evaluator.export_audio_events(48000, 0.0, 100.0, false);
+        for i in 1..events.ids.len() {
+            assert_eq!(
+                events.start_samples[i - 1] + events.duration_samples[i - 1],
+                events.start_samples[i]
+            );
+        }
+        assert_eq!(events.start_samples[699] + events.duration_samples[699], 4_800_000.0);
+    }
+
+    #[test]
+    fn test_audio_events_window_edges() {
+        let mut evaluator = PersistentEvaluator::new();
+        register_chain(&mut evaluator, 6, 1, 2);
+
+        // Notes start at 0, 0.5, 1.0, ...; window [0.75, 1.75)
+        let events = evaluator.export_audio_events(1000, 0.75, 1.75, false);
+        assert_eq!(events.ids, vec![3, 4]);
+        assert_eq!(events.duration_samples, vec![500.0, 500.0]);
+
+        let events = evaluator.export_audio_events(1000, 0.75, 1.75, true);
+        assert_eq!(events.ids, vec![2, 3, 4]);
+        assert_eq!(events.start_samples, vec![750.0, 1000.0, 1500.0]);
+        assert_eq!(events.duration_samples, vec![250.0, 500.0, 250.0]);
+    }
+
+    #[test]
+    fn test_fraction_data_overflow_uses_best_approximation() {
+        // -(1/3 + 1/2^40): denominator overflows u32, closest fitting fraction is 1/3
+        let tiny = Fraction::from_big_ints(1.into(), num_bigint::BigInt::from(1u64 << 40));
+        let value = Fraction::new(1, 3).add(&tiny).neg();
+        let data = FractionData::from_fraction(&value);
+        assert!(data.corrupted);
+        assert_eq!(data.s, -1);
+        assert_eq!((data.n, data.d), (1, 3));
+    }
+
+    #[test]
+    fn test_fraction_data_detects_overflow_beyond_u64() {
+        // 100-digit numerator: previously parsed as 0 and stored saturated without the flag
+        let huge: Fraction = format!("{}/7", "9".repeat(100)).parse().unwrap();
+        let data = FractionData::from_fraction(&huge);
+        assert!(data.corrupted);
+        assert_eq!(data.f, Some(huge.to_f64()));
+
+        let exact = FractionData::from_fraction(&Fraction::new_raw(-(u32::MAX as i64), 2));
+        assert!(!exact.corrupted);
+        assert_eq!((exact.s, exact.n, exact.d), (-1, u32::MAX, 2));
+    }
+
+    #[test]
+    fn test_min_max_rational_and_symbolic() {
+        // 2^(1/12) ≈ 1.0595 against 1 and 11/10
+        let semitone = {
+            let mut bc = make_const_bytecode(2, 1);
+            bc.extend(make_const_bytecode(1, 12));
+            bc.push(Op::Pow as u8);
+            bc
+        };
+        let run = |rational: (i32, i32), op: Op| {
+            let mut bc = make_const_bytecode(rational.0, rational.1);
+            bc.extend(&semitone);
+            bc.push(op as u8);
+            Evaluator::new().evaluate(&bc, bc.len(), &HashMap::new()).unwrap()
+        };
+
+        let result = run((1, 1), Op::Min);
+        assert!(result.is_rational());
+        assert_eq!(result.to_fraction(), Fraction::new(1, 1));
+
+        let result = run((1, 1), Op::Max);
+        assert!(result.is_symbolic(), "symbolic operand must not become irrational");
+        assert_eq!(result.to_f64(), 2f64.powf(1.0 / 12.0));
+
+        let result = run((11, 10), Op::Min);
+        assert!(result.is_symbolic());
+
+        let result = run((11, 10), Op::Max);
+        assert_eq!(result.to_fraction(), Fraction::new(11, 10));
+    }
+
+    #[test]
+    fn test_sqrt_and_root_ops() {
+        let mut bc = make_const_bytecode(9, 4);
+        bc.push(Op::Sqrt as u8);
+        let result = Evaluator::new().evaluate(&bc, bc.len(), &HashMap::new()).unwrap();
+        assert!(result.is_rational());
+        assert_eq!(result.to_fraction(), Fraction::new(3, 2));
+
+        let mut bc = make_const_bytecode(2, 1);
+        bc.push(Op::Sqrt as u8);
+        let result = Evaluator::new().evaluate(&bc, bc.len(), &HashMap::new()).unwrap();
+        assert!(result.is_symbolic());
+
+        // Persistent evaluator: note 2's duration is the cube root of 8
+        let mut evaluator = PersistentEvaluator::new();
+        let mut root = make_const_bytecode(8, 1);
+        root.extend(make_const_bytecode(3, 1));
+        root.push(Op::Root as u8);
+        evaluator.register_expression(2, Var::Duration as u8, &root, root.len());
+        evaluator.evaluate_dirty(&[2]);
+        let duration = evaluator.cached_note(2).and_then(|note| note.duration.as_ref());
+        assert_eq!(duration.map(|f| f.to_fraction()), Some(Fraction::new(2, 1)));
+
+        // The degree must be a positive integer
+        for (num, den) in [(0, 1), (-2, 1), (1, 2)] {
+            let mut bc = make_const_bytecode(8, 1);
+            bc.extend(make_const_bytecode(num, den));
+            bc.push(Op::Root as u8);
+            assert!(Evaluator::new().evaluate(&bc, bc.len(), &HashMap::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_min_clamps_in_persistent_evaluator() {
+        let mut evaluator = PersistentEvaluator::new();
+        register_chain(&mut evaluator, 2, 3, 4);
+
+        // Note 3 lasts min(note 2's duration, 1/2)
+        let mut duration = vec![Op::LoadRef as u8];
+        write_u16(&mut duration, 2);
+        duration.push(Var::Duration as u8);
+        duration.extend(make_const_bytecode(1, 2));
+        duration.push(Op::Min as u8);
+        evaluator.register_expression(3, Var::Duration as u8, &duration, duration.len());
+        evaluator.evaluate_dirty(&[3]);
+
+        let clamped = evaluator.cached_note(3).and_then(|note| note.duration.as_ref());
+        assert_eq!(clamped.map(|f| f.to_fraction()), Some(Fraction::new(1, 2)));
+    }
+
+    /// 2^(1/12) as bytecode
+    fn semitone_bytecode() -> Vec<u8> {
+        let mut bc = make_const_bytecode(2, 1);
+        bc.extend(make_const_bytecode(1, 12));
+        bc.push(Op::Pow as u8);
+        bc
+    }
+
+    /// Frequency of `note_id` times 2^(1/12)
+    fn semitone_above(note_id: u16) -> Vec<u8> {
+        let mut bc = vec![Op::LoadRef as u8];
+        write_u16(&mut bc, note_id);
+        bc.push(Var::Frequency as u8);
+        bc.extend(semitone_bytecode());
+        bc.push(Op::Mul as u8);
+        bc
+    }
+
+    fn assert_whole_tone(data: &FractionData) {
+        let Value::Symbolic(sp) = data.to_value() else { panic!("expected symbolic") };
+        assert_eq!(sp.coefficient, Fraction::new(440, 1));
+        assert_eq!(sp.powers.len(), 1);
+        assert_eq!((sp.powers[0].base, &sp.powers[0].exponent), (2, &Fraction::new(1, 6)));
+    }
+
+    #[test]
+    fn test_symbolic_frequency_survives_note_refs() {
+        let mut a_freq = make_const_bytecode(440, 1);
+        a_freq.extend(semitone_bytecode());
+        a_freq.push(Op::Mul as u8);
+        let b_freq = semitone_above(1);
+
+        // Evaluator: note B reads note A through the evaluation cache
+        let mut evaluator = Evaluator::new();
+        let mut cache = HashMap::new();
+        let a = NoteExpressions { frequency: Some((a_freq.clone(), a_freq.len())), ..Default::default() };
+        cache.insert(1, evaluator.evaluate_note(&a, &cache));
+        let b = NoteExpressions { frequency: Some((b_freq.clone(), b_freq.len())), ..Default::default() };
+        let note_b = evaluator.evaluate_note(&b, &cache);
+        assert_whole_tone(note_b.frequency.as_ref().unwrap());
+        assert!(note_b.is_corrupted(Var::Frequency));
+
+        // PersistentEvaluator
+        let mut persistent = PersistentEvaluator::new();
+        persistent.register_expression(1, Var::Frequency as u8, &a_freq, a_freq.len());
+        persistent.register_expression(2, Var::Frequency as u8, &b_freq, b_freq.len());
+        persistent.evaluate_dirty(&[1, 2]);
+        assert_whole_tone(persistent.cached_note(2).and_then(|n| n.frequency.as_ref()).unwrap());
+
+        // Rational values stay compact on the wire
+        let json = serde_json::to_string(&FractionData::from_fraction(&Fraction::new(3, 2))).unwrap();
+        assert!(!json.contains("symbolic"));
+        let json = serde_json::to_string(persistent.cached_note(2).unwrap()).unwrap();
+        let restored: EvaluatedNote = serde_json::from_str(&json).unwrap();
+        assert_whole_tone(restored.frequency.as_ref().unwrap());
+    }
+
+    #[test]
+    fn test_corruption_sources_locate_nested_pow() {
+        // 440 · (3 + 2^(1/12) · 5): the Pow sits at pc 36, and the Add at
+        // pc 47 falls back to f64
+        let mut freq = make_const_bytecode(440, 1);
+        freq.extend(make_const_bytecode(3, 1));
+        freq.extend(semitone_bytecode());
+        freq.extend(make_const_bytecode(5, 1));
+        freq.push(Op::Mul as u8);
+        freq.push(Op::Add as u8);
+        freq.push(Op::Mul as u8);
+        assert_eq!(freq[36], Op::Pow as u8);
+
+        // Duration of note 2 = 1 / note 1's frequency: inherited at the LoadRef (pc 9)
+        let mut duration = make_const_bytecode(1, 1);
+        duration.push(Op::LoadRef as u8);
+        write_u16(&mut duration, 1);
+        duration.push(Var::Frequency as u8);
+        duration.push(Op::Div as u8);
+
+        let mut evaluator = PersistentEvaluator::new();
+        evaluator.register_expression(1, Var::Frequency as u8, &freq, freq.len());
+        evaluator.register_expression(1, Var::StartTime as u8, &make_const_bytecode(0, 1), 9);
+        evaluator.register_expression(2, Var::Duration as u8, &duration, duration.len());
+        evaluator.evaluate_dirty(&[1, 2]);
+
+        let note = evaluator.cached_note(1).unwrap();
+        let pow = CorruptionSource { variable: "frequency".to_string(), op: "Pow".to_string(), pc: 36, lossy: false };
+        let add = CorruptionSource { variable: "frequency".to_string(), op: "Add".to_string(), pc: 47, lossy: true };
+        assert_eq!(note.corruption_sources, vec![pow.clone(), add.clone()]);
+        let inherited =
+            CorruptionSource { variable: "duration".to_string(), op: "LoadRef".to_string(), pc: 9, lossy: true };
+        assert_eq!(evaluator.cached_note(2).unwrap().corruption_sources, vec![inherited]);
+
+        // Same attribution from the one-shot Evaluator
+        let expressions = NoteExpressions { frequency: Some((freq.clone(), freq.len())), ..Default::default() };
+        let note = Evaluator::new().evaluate_note(&expressions, &HashMap::new());
+        assert_eq!(note.corruption_sources, vec![pow, add]);
+
+        // Rational notes don't carry the field at all
+        let rational = EvaluatedNote { start_time: Some(FractionData::default()), ..Default::default() };
+        assert!(!serde_json::to_string(&rational).unwrap().contains("corruptionSources"));
+    }
+
+    #[test]
+    fn test_corruption_flags_widened() {
+        assert_eq!(corruption_flag_for_var(Var::Frequency as u8), crate::value::CORRUPT_FREQUENCY);
+        assert_eq!(corruption_flag_for_var(Var::MeasureLength as u8), crate::value::CORRUPT_MEASURE_LENGTH);
+        // A seventh variable gets the next bit; indices past 16 bits get none
+        assert_eq!(corruption_flag_for_var(6), 0x40);
+        assert_eq!(corruption_flag_for_var(15), 0x8000);
+        assert_eq!(corruption_flag_for_var(16), 0);
+
+        let mut note = EvaluatedNote::default();
+        note.set_corrupted(Var::Tempo);
+        assert!(note.is_corrupted(Var::Tempo) && !note.is_corrupted(Var::Duration));
+        note.corruption_flags |= corruption_flag_for_var(6);
+        let json = serde_json::to_string(&note).unwrap();
+        assert!(json.contains("\"corruptionFlags\":72"));
+
+        // Snapshots written when the flags were a u8 still load
+        let old = r#"{"frequency":{"s":1,"n":440,"d":1,"corrupted":false},"corruptionFlags":36}"#;
+        let note: EvaluatedNote = serde_json::from_str(old).unwrap();
+        assert!(note.is_corrupted(Var::Frequency) && note.is_corrupted(Var::MeasureLength));
+        assert!(!note.is_corrupted(Var::StartTime));
+        // Same shape importCache reads
+        let cache: HashMap<String, EvaluatedNote> = serde_json::from_str(&format!(r#"{{"7":{}}}"#, old)).unwrap();
+        assert_eq!(cache["7"].corruption_flags, 36);
+    }
+
+    #[test]
+    fn test_even_root_of_negative() {
+        let mut bytecode = make_const_bytecode(-4, 1);
+        bytecode.extend(make_const_bytecode(1, 2));
+        bytecode.push(Op::Pow as u8);
+        let cache = HashMap::new();
+
+        // Non-finite fallback by default
+        let mut evaluator = Evaluator::new();
+        let result = evaluator.evaluate(&bytecode, bytecode.len(), &cache).unwrap();
+        assert!(matches!(result, Value::Irrational { value: v, .. } if v == 0.0));
+
+        // Typed error in strict mode
+        evaluator.set_strict(true);
+        let err = evaluator.evaluate(&bytecode, bytecode.len(), &cache).unwrap_err();
+        assert_eq!(err, EvalError::Strict { pc: 18, op: Op::Pow, pow: Some(PowError::EvenRootOfNegative) });
+        assert_eq!(err.to_string(), "Strict mode: Pow at pc=18: Even root of a negative number");
+
+        // Odd roots stay exact
+        let mut cube = make_const_bytecode(-8, 1);
+        cube.extend(make_const_bytecode(1, 3));
+        cube.push(Op::Pow as u8);
+        let result = evaluator.evaluate(&cube, cube.len(), &cache).unwrap();
+        assert!(result == Value::rational(-2, 1));
+    }
+
+    #[test]
+    fn test_lossy_pow_flagged_in_sources() {
+        // 3^(2^(1/12)): exact at the inner Pow (pc 27), approximated at the outer one
+        let mut freq = make_const_bytecode(3, 1);
+        freq.extend(semitone_bytecode());
+        freq.push(Op::Pow as u8);
+        let expressions = NoteExpressions { frequency: Some((freq.clone(), freq.len())), ..Default::default() };
+        let note = Evaluator::new().evaluate_note(&expressions, &HashMap::new());
+        let source = |pc, lossy| CorruptionSource { variable: "frequency".to_string(), op: "Pow".to_string(), pc, lossy };
+        assert_eq!(note.corruption_sources, vec![source(27, false), source(28, true)]);
+
+        // An approximation from the start is a single lossy source
+        let mut nan = make_const_bytecode(-2, 1);
+        nan.push(Op::Sqrt as u8);
+        let mut persistent = PersistentEvaluator::new();
+        persistent.register_expression(1, Var::Frequency as u8, &freq, freq.len());
+        persistent.register_expression(1, Var::Duration as u8, &nan, nan.len());
+        persistent.evaluate_dirty(&[1]);
+        let sources = &persistent.cached_note(1).unwrap().corruption_sources;
+        let sqrt = CorruptionSource { variable: "duration".to_string(), op: "Sqrt".to_string(), pc: 9, lossy: true };
+        assert_eq!(sources, &vec![source(27, false), source(28, true), sqrt]);
+        let json = serde_json::to_string(sources).unwrap();
+        assert_eq!(json.matches("\"lossy\":true").count(), 2);
+        assert!(!json.contains("\"lossy\":false"));
+    }
+
+    #[test]
+    fn test_strict_mode_rejects_corruption() {
+        // 4^(1/2) is exactly 2
+        let mut root = make_const_bytecode(4, 1);
+        root.extend(make_const_bytecode(1, 2));
+        root.push(Op::Pow as u8);
+        let semitone = semitone_bytecode();
+
+        let mut evaluator = Evaluator::new();
+        evaluator.set_strict(true);
+        let cache = HashMap::new();
+        assert_eq!(evaluator.evaluate(&root, root.len(), &cache).unwrap().to_f64(), 2.0);
+        let err = evaluator.evaluate(&semitone, semitone.len(), &cache).unwrap_err();
+        assert_eq!(err, EvalError::Strict { pc: 18, op: Op::Pow, pow: None });
+        evaluator.set_strict(false);
+        assert!(evaluator.evaluate(&semitone, semitone.len(), &cache).unwrap().is_symbolic());
+
+        // Note 2 is a semitone above note 1; note 3 is never reached
+        let mut persistent = PersistentEvaluator::new();
+        persistent.set_strict(true);
+        persistent.register_expression(1, Var::Frequency as u8, &root, root.len());
+        persistent.register_expression(2, Var::Frequency as u8, &semitone_above(1), semitone_above(1).len());
+        persistent.register_expression(3, Var::Frequency as u8, &root, root.len());
+        assert_eq!(persistent.evaluate_dirty(&[1, 2, 3]).evaluated.len(), 1);
+        let violation = StrictViolation { note_id: 2, variable: "frequency".to_string(), op: "Pow".to_string(), pc: 22 };
+        assert_eq!(persistent.strict_violation(), Some(&violation));
+        assert!(persistent.has_cached_note(1));
+        assert!(!persistent.has_cached_note(2) && !persistent.has_cached_note(3));
+
+        // Without strict mode the same notes evaluate and clear the report
+        persistent.set_strict(false);
+        assert_eq!(persistent.evaluate_dirty(&[1, 2, 3]).evaluated.len(), 3);
+        assert!(persistent.strict_violation().is_none());
+        assert!(persistent.cached_note(2).unwrap().is_corrupted(Var::Frequency));
+    }
+
+    #[test]
+    fn test_non_finite_results() {
+        let cache = HashMap::new();
+
+        // A symbolic divided by zero gives 1, like any other division by zero
+        let mut by_zero = semitone_bytecode();
+        by_zero.extend(make_const_bytecode(0, 1));
+        by_zero.push(Op::Div as u8);
+        let result = Evaluator::new().evaluate(&by_zero, by_zero.len(), &cache).unwrap();
+        assert!(result == Value::one());
+
+        // sqrt(-2) is NaN: replaced by 0 by default, an error on request
+        let mut nan = make_const_bytecode(-2, 1);
+        nan.push(Op::Sqrt as u8);
+        let mut evaluator = Evaluator::new();
+        let result = evaluator.evaluate(&nan, nan.len(), &cache).unwrap();
+        assert!(matches!(result, Value::Irrational { value: v, .. } if v == 0.0));
+        evaluator.non_finite = NonFinitePolicy::Error;
+        let err = evaluator.evaluate(&nan, nan.len(), &cache).unwrap_err();
+        assert!(matches!(err, EvalError::NonFinite { pc: 9, op: Op::Sqrt, .. }), "{}", err);
+
+        // log2(0) is -Infinity: the property is dropped under "error"
+        let mut inf = make_const_bytecode(0, 1);
+        inf.extend(make_const_bytecode(2, 1));
+        inf.push(Op::Log as u8);
+        let mut persistent = PersistentEvaluator::new();
+        persistent.register_expression(1, Var::Duration as u8, &inf, inf.len());
+        persistent.register_expression(1, Var::StartTime as u8, &by_zero, by_zero.len());
+        persistent.evaluate_dirty(&[1]);
+        let note = persistent.cached_note(1).unwrap();
+        let duration = note.duration.as_ref().unwrap();
+        assert_eq!((duration.n, duration.f, duration.corrupted), (0, Some(0.0), true));
+        assert_eq!(note.start_time.as_ref().unwrap().to_fraction(), Fraction::one());
+
+        persistent.machine.non_finite = NonFinitePolicy::Error;
+        persistent.mark_dirty(1);
+        persistent.evaluate_dirty(&[1]);
+        let note = persistent.cached_note(1).unwrap();
+        assert!(note.duration.is_none() && note.start_time.is_some());
+    }
+
+    #[test]
+    fn test_fraction_data_never_garbles_floats() {
+        for v in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let data = FractionData::from_value(&Value::from(v));
+            assert_eq!((data.s, data.n, data.d), (0, 0, 1));
+            assert!(data.corrupted);
+        }
+        // Too large for a millionths numerator: nearest fraction that fits
+        let data = FractionData::from_value(&Value::from(-5000.5));
+        assert_eq!((data.s, data.n, data.d), (-1, 10001, 2));
+        let data = FractionData::from_value(&Value::from(0.25));
+        assert_eq!((data.s, data.n, data.d), (1, 250_000, 1_000_000));
+    }
+
+    #[test]
+    fn test_abs_and_sign_ops() {
+        // -2^(1/12), and -(2^(1/12) + 1) which is irrational
+        let mut minus_semitone = make_const_bytecode(-1, 1);
+        minus_semitone.extend(make_const_bytecode(2, 1));
+        minus_semitone.extend(make_const_bytecode(1, 12));
+        minus_semitone.push(Op::Pow as u8);
+        minus_semitone.push(Op::Mul as u8);
+        let mut minus_irrational = minus_semitone.clone();
+        minus_irrational.extend(make_const_bytecode(-1, 1));
+        minus_irrational.push(Op::Add as u8);
+
+        let run = |bytecode: &[u8], op: Op| {
+            let mut bc = bytecode.to_vec();
+            bc.push(op as u8);
+            Evaluator::new().evaluate(&bc, bc.len(), &HashMap::new()).unwrap()
+        };
+        let semitone = Value::rational(2, 1).pow(&Value::rational(1, 12));
+        let abs = run(&minus_semitone, Op::Abs);
+        assert!(abs.is_symbolic() && abs == semitone);
+        assert!(run(&minus_semitone, Op::Sign) == Value::rational(-1, 1));
+        assert!(run(&minus_irrational, Op::Abs).to_f64() == semitone.to_f64() + 1.0);
+        assert!(run(&minus_irrational, Op::Sign) == Value::rational(-1, 1));
+        assert!(run(&make_const_bytecode(0, 1), Op::Sign) == Value::zero());
+    }
+
+    #[test]
+    fn test_over_rot_drop_ops() {
+        let program = |values: &[i32], ops: &[Op]| {
+            let mut bc = Vec::new();
+            for &v in values {
+                bc.extend(make_const_bytecode(v, 1));
+            }
+            bc.extend(ops.iter().map(|&op| op as u8));
+            bc
+        };
+        // 2 5 OVER -> 2 5 2, then (5 - 2) * 2; 1 2 3 ROT -> 2 3 1, then 2 / (3 - 1);
+        // 7 9 DROP -> 7
+        let cases = [
+            (program(&[2, 5], &[Op::Over, Op::Sub, Op::Mul]), Value::rational(6, 1)),
+            (program(&[1, 2, 3], &[Op::Rot, Op::Sub, Op::Div]), Value::rational(1, 1)),
+            (program(&[1, 2, 3], &[Op::Rot, Op::Rot, Op::Rot, Op::Sub, Op::Sub]), Value::rational(2, 1)),
+            (program(&[7, 9], &[Op::Drop]), Value::rational(7, 1)),
+        ];
+        let mut persistent = PersistentEvaluator::new();
+        for (bc, expected) in &cases {
+            let result = Evaluator::new().evaluate(bc, bc.len(), &HashMap::new()).unwrap();
+            assert!(result == *expected);
+
+            persistent.register_expression(1, Var::Duration as u8, bc, bc.len());
+            persistent.evaluate_dirty(&[1]);
+            let duration = persistent.cached_note(1).unwrap().duration.as_ref().unwrap().to_fraction();
+            assert_eq!(duration, expected.to_fraction());
+        }
+
+        for bc in [program(&[1], &[Op::Over]), program(&[1, 2], &[Op::Rot]), program(&[], &[Op::Drop])] {
+            let err = Evaluator::new().evaluate(&bc, bc.len(), &HashMap::new()).unwrap_err();
+            assert!(matches!(err, EvalError::StackUnderflow { .. }), "{}", err);
+
+            persistent.register_expression(1, Var::Duration as u8, &bc, bc.len());
+            persistent.evaluate_dirty(&[1]);
+            assert!(persistent.cached_note(1).unwrap().duration.is_none());
+        }
+    }
+
+    #[test]
+    fn test_interned_mixed_encodings() {
+        // The same read of note 5's start time, as LOAD_REF_WIDE and LOAD_REF
+        let mut wide = vec![Op::LoadRefWide as u8];
+        crate::bytecode::write_u32(&mut wide, 5);
+        wide.push(Var::StartTime as u8);
+        let mut narrow = vec![Op::LoadRef as u8];
+        write_u16(&mut narrow, 5);
+        narrow.push(Var::StartTime as u8);
+
+        for (first, second) in [(&wide, &narrow), (&narrow, &wide)] {
+            let mut evaluator = PersistentEvaluator::new();
+            evaluator.set_intern_expressions(true);
+            let start = make_const_bytecode(7, 2);
+            evaluator.register_expression(5, Var::StartTime as u8, &start, start.len());
+            evaluator.register_expression(1, Var::StartTime as u8, first, first.len());
+            evaluator.register_expression(2, Var::StartTime as u8, second, second.len());
+            evaluator.mark_dirty_batch(&[1, 2, 5]);
+            evaluator.evaluate_dirty_auto();
+            for id in [1, 2] {
+                let start = evaluator.cached_note(id).unwrap().start_time.as_ref().map(|v| v.to_fraction());
+                assert_eq!(start, Some(Fraction::new(7, 2)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_interned_expressions() {
+        // 20 notes reading note 1's duration times 1/2, with the constant
+        // written as 1/2 or 2/4, plus a distinct start time each
+        let program = |num: i32, den: i32| {
+            let mut bc = vec![Op::LoadRef as u8];
+            write_u16(&mut bc, 1);
+            bc.push(Var::Duration as u8);
+            bc.extend(make_const_bytecode(num, den));
+            bc.push(Op::Mul as u8);
+            bc
+        };
+        let ids: Vec<u32> = (1..=21).collect();
+        let build = |intern: bool| {
+            let mut evaluator = PersistentEvaluator::new();
+            evaluator.set_intern_expressions(intern);
+            let root = make_const_bytecode(3, 2);
+            evaluator.register_expression(1, Var::Duration as u8, &root, root.len());
+            for id in 2..=21 {
+                let duration = if id % 2 == 0 { program(1, 2) } else { program(2, 4) };
+                let start = make_const_bytecode(id as i32, 1);
+                evaluator.register_expression(id, Var::Duration as u8, &duration, duration.len());
+                evaluator.register_expression(id, Var::StartTime as u8, &start, start.len());
+            }
+            evaluator.evaluate_dirty(&ids);
+            evaluator
+        };
+
+        let plain = build(false);
+        let interned = build(true);
+        for &id in &ids {
+            let (a, b) = (plain.cached_note(id).unwrap(), interned.cached_note(id).unwrap());
+            assert_eq!(a.duration.as_ref().unwrap().to_fraction(), b.duration.as_ref().unwrap().to_fraction());
+            assert_eq!(a.start_time.as_ref().map(|v| v.to_fraction()), b.start_time.as_ref().map(|v| v.to_fraction()));
+        }
+        assert_eq!(interned.cached_note(2).unwrap().duration.as_ref().unwrap().to_fraction(), Fraction::new(3, 4));
+
+        let stats = plain.intern_stats();
+        assert_eq!((stats.expressions, stats.unique_programs, stats.saved_bytes), (41, 41, 0));
+        // The root and the 20 start times are distinct; the 20 durations share one blob
+        let stats = interned.intern_stats();
+        let size = program(1, 2).len();
+        assert_eq!((stats.expressions, stats.unique_programs, stats.saved_bytes), (41, 22, 19 * size));
+
+        // Removing every user lets compact drop the pooled blob
+        let mut interned = interned;
+        for id in 2..=21 {
+            interned.remove_note(id);
+        }
+        interned.compact();
+        assert_eq!(interned.interned.len(), 1);
+    }
+
+    #[test]
+    fn test_call_macro() {
+        // Macro 1 scales the caller's value by note 1's duration plus 1/2
+        let mut body = vec![Op::LoadRef as u8];
+        write_u16(&mut body, 1);
+        body.push(Var::Duration as u8);
+        body.extend(make_const_bytecode(1, 2));
+        body.extend([Op::Add as u8, Op::Mul as u8]);
+        let with_body = |value: &[u8], tail: &[u8]| [value, tail].concat();
+        let call = [Op::CallMacro as u8, 0, 1];
+
+        let root = make_const_bytecode(3, 2);
+        let mut persistent = PersistentEvaluator::new();
+        persistent.register_macro(1, &body, body.len()).unwrap();
+        persistent.register_expression(1, Var::Duration as u8, &root, root.len());
+        for (id, num) in [(2, 1), (3, 5)] {
+            let called = with_body(&make_const_bytecode(num, 3), &call);
+            let expanded = with_body(&make_const_bytecode(num, 3), &body);
+            persistent.register_expression(id, Var::Duration as u8, &called, called.len());
+            persistent.register_expression(id, Var::StartTime as u8, &expanded, expanded.len());
+        }
+        persistent.evaluate_dirty(&[1, 2, 3]);
+        for (id, expected) in [(2, Fraction::new(2, 3)), (3, Fraction::new(10, 3))] {
+            let note = persistent.cached_note(id).unwrap();
+            assert_eq!(note.duration.as_ref().unwrap().to_fraction(), expected);
+            assert_eq!(note.start_time.as_ref().unwrap().to_fraction(), expected);
+        }
+
+        // Replacing the body re-evaluates every caller
+        persistent.register_macro(1, &[Op::Neg as u8], 1).unwrap();
+        persistent.evaluate_dirty(&[1, 2, 3]);
+        assert_eq!(persistent.cached_note(3).unwrap().duration.as_ref().unwrap().to_fraction(), Fraction::new(-5, 3));
+
+        // Calls to a missing macro are rejected at registration and at runtime
+        let unknown = with_body(&make_const_bytecode(1, 1), &[Op::CallMacro as u8, 0, 2]);
+        persistent.set_validate_on_register(true);
+        persistent.register_expression(4, Var::Duration as u8, &unknown, unknown.len());
+        assert!(persistent.last_registration_error().unwrap().contains("Unknown macro 2"));
+        assert!(Evaluator::new().evaluate(&unknown, unknown.len(), &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_bytecode_stats() {
+        // Note 1: duration 1/2; note 2: duration note 1 * 3, start -note 1
+        let half = make_const_bytecode(1, 2);
+        let mut triple = vec![Op::LoadRef as u8];
+        write_u16(&mut triple, 1);
+        triple.push(Var::Duration as u8);
+        triple.extend(make_const_bytecode(3, 1));
+        triple.push(Op::Mul as u8);
+        let mut negated = triple[..4].to_vec();
+        negated.push(Op::Neg as u8);
+
+        let mut persistent = PersistentEvaluator::new();
+        persistent.register_expression(1, Var::Duration as u8, &half, half.len());
+        persistent.register_expression(2, Var::Duration as u8, &triple, triple.len());
+        persistent.register_expression(2, Var::StartTime as u8, &negated, negated.len());
+
+        let stats = persistent.bytecode_stats();
+        assert_eq!((stats.expressions, stats.instructions, stats.constants, stats.max_depth), (3, 6, 2, 2));
+        assert_eq!(stats.total_bytes, half.len() + triple.len() + negated.len());
+        let counts: Vec<(&str, usize)> = stats.opcodes.iter().map(|entry| (entry.op.as_str(), entry.count)).collect();
+        assert_eq!(counts, [("LoadConst", 2), ("LoadRef", 2), ("Mul", 1), ("Neg", 1)]);
+
+        let size = persistent.note_bytecode_size(2).unwrap();
+        assert_eq!((size.start_time, size.duration, size.frequency), (Some(5), Some(14), None));
+        assert_eq!(size.total_bytes, 19);
+        assert!(persistent.note_bytecode_size(3).is_none());
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        let with_const = |tail: &[u8]| [make_const_bytecode(8, 1), tail.to_vec()].concat();
+        let mut overflow = with_const(&[]);
+        overflow.extend([Op::Dup as u8; 1024]);
+        let cases = [
+            (vec![0xEE], EvalError::UnknownOpcode { pc: 0, byte: 0xEE }, "UNKNOWN_OPCODE"),
+            (with_const(&[Op::LoadRef as u8, 0]), EvalError::TruncatedOperand { pc: 9, op: Op::LoadRef }, "TRUNCATED_OPERAND"),
+            (with_const(&[Op::Add as u8]), EvalError::StackUnderflow { pc: 9, op: Op::Add }, "STACK_UNDERFLOW"),
+            (overflow, EvalError::StackOverflow { pc: 9 + 1023, op: Op::Dup, limit: 1024 }, "STACK_OVERFLOW"),
+            (with_const(&[Op::LoadBase as u8, 9]), EvalError::InvalidVar { pc: 9, op: Op::LoadBase, index: 9 }, "INVALID_VAR"),
+            (
+                [with_const(&[]), make_const_bytecode(1, 2), vec![Op::Root as u8]].concat(),
+                EvalError::InvalidRootDegree { pc: 18, degree: "1/2".to_string() },
+                "INVALID_ROOT_DEGREE",
+            ),
+            (with_const(&[Op::CallMacro as u8, 0, 1]), EvalError::NoMacroTable { pc: 9 }, "NO_MACRO_TABLE"),
+        ];
+        for (bc, expected, code) in cases {
+            let err = Evaluator::new().evaluate(&bc, bc.len(), &HashMap::new()).unwrap_err();
+            assert_eq!(err, expected);
+            let data = err.to_data(None);
+            assert_eq!((data.code.as_str(), data.pc as usize), (code, err.pc()));
+            assert_eq!(data.op, err.op().map(|op| format!("{:?}", op)));
+            assert_eq!(data.message, err.to_string());
+            assert_eq!((data.note_id, data.var), (None, None));
+        }
+        assert_eq!(EvalError::UnknownOpcode { pc: 0, byte: 0xEE }.op(), None);
+
+        let mut nan = make_const_bytecode(-2, 1);
+        nan.push(Op::Sqrt as u8);
+        let mut evaluator = Evaluator::new();
+        evaluator.non_finite = NonFinitePolicy::Error;
+        let err = evaluator.evaluate(&nan, nan.len(), &HashMap::new()).unwrap_err();
+        assert_eq!((err.code(), err.pc(), err.op()), ("NON_FINITE", 9, Some(Op::Sqrt)));
+        evaluator.set_strict(true);
+        let err = evaluator.evaluate(&semitone_bytecode(), semitone_bytecode().len(), &HashMap::new()).unwrap_err();
+        assert_eq!((err.code(), err.pc(), err.op()), ("STRICT", 18, Some(Op::Pow)));
+
+        // The persistent evaluator records failures with their note and
+        // variable, keeps evaluating, and remembers only the latest ones
+        let mut persistent = PersistentEvaluator::new();
+        let unknown = with_const(&[Op::CallMacro as u8, 0, 4]);
+        let fine = make_const_bytecode(1, 2);
+        persistent.register_expression(3, Var::Duration as u8, &unknown, unknown.len());
+        persistent.register_expression(3, Var::StartTime as u8, &fine, fine.len());
+        persistent.evaluate_dirty(&[3]);
+        let note = persistent.cached_note(3).unwrap();
+        assert!(note.duration.is_none() && note.start_time.is_some());
+        let recorded: Vec<&NoteEvalError> = persistent.evaluation_errors().iter().collect();
+        let error = EvalError::UnknownMacro { pc: 9, macro_id: 4 };
+        assert_eq!(recorded, [&NoteEvalError { note_id: 3, var: Var::Duration as u8, error: error.clone() }]);
+        assert_eq!(
+            error.to_data(Some((3, Var::Duration as u8))),
+            EvalErrorData {
+                code: "UNKNOWN_MACRO".to_string(),
+                message: "Unknown macro 4 at pc=9".to_string(),
+                pc: 9,
+                op: Some("CallMacro".to_string()),
+                note_id: Some(3),
+                var: Some("duration".to_string()),
+            }
+        );
+
+        let underflow = [Op::Neg as u8];
+        let ids: Vec<u32> = (10..10 + MAX_EVALUATION_ERRORS as u32 + 5).collect();
+        for &id in &ids {
+            persistent.register_expression(id, Var::Frequency as u8, &underflow, 1);
+        }
+        persistent.evaluate_dirty(&ids);
+        let recorded = persistent.evaluation_errors();
+        assert_eq!(recorded.len(), MAX_EVALUATION_ERRORS);
+        assert_eq!(recorded.front().unwrap().note_id, 15);
+        assert_eq!(recorded.back().unwrap().error, EvalError::StackUnderflow { pc: 0, op: Op::Neg });
+        persistent.clear_evaluation_errors();
+        assert!(persistent.evaluation_errors().is_empty());
+    }
+
+    #[test]
+    fn test_instruction_limit() {
+        // 2,000,001 instructions: a constant negated two million times
+        let mut bomb = make_const_bytecode(3, 4);
+        bomb.resize(bomb.len() + 2_000_000, Op::Neg as u8);
+        let cache = HashMap::new();
+
+        let mut evaluator = Evaluator::new();
+        let err = evaluator.evaluate(&bomb, bomb.len(), &cache).unwrap_err();
+        assert_eq!(err, EvalError::FuelExhausted { pc: 9 + 999_999, op: Op::Neg, executed: 1_000_000 });
+        assert_eq!(err.code(), "FUEL_EXHAUSTED");
+        assert_eq!(evaluator.instructions_executed(), 1_000_000);
+        evaluator.set_max_instructions(2_000_001);
+        assert!(evaluator.evaluate(&bomb, bomb.len(), &cache).unwrap() == Value::rational(3, 4));
+        assert_eq!(evaluator.instructions_executed(), 2_000_001);
+        evaluator.set_max_instructions(2_000_000);
+        assert!(evaluator.evaluate(&bomb, bomb.len(), &cache).is_err());
+
+        // The bomb's note is skipped; the notes around it still evaluate
+        let fine = make_const_bytecode(1, 2);
+        let mut persistent = PersistentEvaluator::new();
+        persistent.register_expression(1, Var::Duration as u8, &fine, fine.len());
+        persistent.register_expression(2, Var::Duration as u8, &fine, fine.len());
+        persistent.register_expression(2, Var::Frequency as u8, &bomb, bomb.len());
+        persistent.register_expression(3, Var::Duration as u8, &fine, fine.len());
+        assert_eq!(persistent.evaluate_dirty(&[1, 2, 3]).evaluated.len(), 2);
+        assert!(persistent.cached_note(1).is_some() && persistent.cached_note(3).is_some());
+        assert!(persistent.cached_note(2).is_none());
+        let recorded = persistent.evaluation_errors().back().unwrap();
+        assert_eq!((recorded.note_id, recorded.var, recorded.error.code()), (2, Var::Frequency as u8, "FUEL_EXHAUSTED"));
+        assert_eq!(
+            *persistent.evaluation_stats(),
+            EvaluationStats { expressions: 4, instructions: 1_000_003, max_instructions: 1_000_000 }
+        );
+
+        persistent.set_max_instructions(3_000_000);
+        persistent.mark_dirty(2);
+        assert_eq!(persistent.evaluate_dirty(&[2]).evaluated.len(), 1);
+        assert_eq!(persistent.cached_note(2).unwrap().frequency.as_ref().unwrap().to_fraction(), Fraction::new(3, 4));
+        assert_eq!(persistent.evaluation_stats().max_instructions, 2_000_001);
+    }
+
+    #[test]
+    fn test_variable_defaults() {
+        let tempo_of = |note_id: u8| vec![Op::LoadRef as u8, 0, note_id, Var::Tempo as u8];
+        let mut measure = make_const_bytecode(9, 1);
+        measure.push(Op::FindMeasure as u8);
+        let overrides = DefaultsData {
+            tempo: Some(FractionData::from_fraction(&Fraction::new(120, 1))),
+            frequency: Some(FractionData::from_fraction(&Fraction::new(432, 1))),
+            ..Default::default()
+        };
+
+        let mut evaluator = Evaluator::new();
+        evaluator.set_defaults(&overrides).unwrap();
+        let cache = HashMap::new();
+        assert!(evaluator.evaluate(&tempo_of(9), 4, &cache).unwrap() == Value::rational(120, 1));
+        assert!(evaluator.evaluate(&[Op::LoadBase as u8, Var::Frequency as u8], 2, &cache).unwrap() == Value::rational(432, 1));
+        // 4 beats at tempo 120
+        assert!(evaluator.evaluate(&measure, measure.len(), &cache).unwrap() == Value::rational(2, 1));
+        assert_eq!(evaluator.defaults().get(Var::BeatsPerMeasure), &Fraction::new(4, 1));
+
+        // A bad value rejects the whole update
+        let bad = DefaultsData {
+            duration: Some(FractionData::from_fraction(&Fraction::new(1, 2))),
+            tempo: Some(FractionData { d: 0, ..FractionData::from_fraction(&Fraction::new(90, 1)) }),
+            ..Default::default()
+        };
+        assert!(evaluator.set_defaults(&bad).is_err());
+        assert_eq!(evaluator.defaults().get(Var::Duration), &Fraction::one());
+        assert_eq!(evaluator.defaults().data().tempo.unwrap().to_fraction(), Fraction::new(120, 1));
+
+        // The {n, d} shape JavaScript passes: a missing sign follows the
+        // numerator, an explicit one must agree with it
+        let parse = |json: &str| serde_json::from_str::<DefaultsData>(json).unwrap();
+        evaluator.set_defaults(&parse(r#"{"tempo": {"n": 90, "d": 1}, "startTime": {"n": 0, "d": 1}}"#)).unwrap();
+        assert_eq!(evaluator.defaults().get(Var::Tempo), &Fraction::new(90, 1));
+        assert_eq!(evaluator.defaults().get(Var::StartTime), &Fraction::zero());
+        evaluator.set_defaults(&parse(r#"{"startTime": {"s": -1, "n": 3, "d": 2}}"#)).unwrap();
+        assert_eq!(evaluator.defaults().get(Var::StartTime), &Fraction::new(-3, 2));
+        let error = evaluator.set_defaults(&parse(r#"{"tempo": {"s": 0, "n": 100, "d": 1}}"#)).err();
+        assert_eq!(error.as_deref(), Some("Default tempo has sign 0 for numerator 100"));
+        assert_eq!(evaluator.defaults().get(Var::Tempo), &Fraction::new(90, 1));
+        // Numerators past i32::MAX stay exact instead of wrapping
+        evaluator.set_defaults(&parse(r#"{"frequency": {"n": 3000000000, "d": 4000000000}}"#)).unwrap();
+        assert_eq!(evaluator.defaults().get(Var::Frequency), &Fraction::new(3, 4));
+
+        let mut persistent = PersistentEvaluator::new();
+        let measure_ref = make_const_bytecode(1, 1);
+        persistent.register_expression(1, Var::Tempo as u8, &tempo_of(9), 4);
+        persistent.register_expression(1, Var::MeasureLength as u8, &measure, measure.len());
+        persistent.register_expression(2, Var::StartTime as u8, &measure_ref, measure_ref.len());
+        persistent.evaluate_dirty(&[1, 2]);
+        assert_eq!(persistent.cached_note(1).unwrap().tempo.as_ref().unwrap().to_fraction(), Fraction::new(60, 1));
+
+        persistent.set_defaults(&overrides).unwrap();
+        assert_eq!(persistent.evaluate_dirty(&[1, 2]).evaluated.len(), 2);
+        let note = persistent.cached_note(1).unwrap();
+        assert_eq!(note.tempo.as_ref().unwrap().to_fraction(), Fraction::new(120, 1));
+        assert_eq!(note.measure_length.as_ref().unwrap().to_fraction(), Fraction::new(2, 1));
+        // A measure note with no tempo of its own or on the base note
+        assert_eq!(persistent.cached_note(2).unwrap().measure_length.as_ref().unwrap().to_fraction(), Fraction::new(2, 1));
+    }
+
+    #[test]
+    fn test_facades_agree() {
+        // Notes 0-3 with every property set, and notes 4-5 missing
+        let mut persistent = PersistentEvaluator::new();
+        for note_id in 0..4u32 {
+            for (i, var) in [Var::StartTime, Var::Duration, Var::Frequency, Var::Tempo, Var::BeatsPerMeasure].into_iter().enumerate() {
+                let bytecode = make_const_bytecode(note_id as i32 * 7 + i as i32 + 1, 3);
+                persistent.register_expression(note_id, var as u8, &bytecode, bytecode.len());
+            }
+        }
+        persistent.evaluate_dirty(&[0, 1, 2, 3]);
+        let notes = NoteView {
+            cache: &persistent.cache,
+            current: None,
+            instruments: &persistent.instruments,
+            macros: &persistent.macros,
+        };
+        let mut machine = StackMachine::new();
+        let mut evaluator = Evaluator::new();
+
+        let ops = [
+            Op::Add, Op::Sub, Op::Mul, Op::Div, Op::Neg, Op::Pow, Op::Mod, Op::Min, Op::Max, Op::Sqrt, Op::Abs,
+            Op::Sign, Op::Floor, Op::Ceil, Op::Round, Op::Root, Op::Log, Op::FindTempo, Op::FindMeasure,
+            Op::FindBeat, Op::Dup, Op::Swap, Op::Over, Op::Rot, Op::Drop,
+        ];
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = |bound: u64| crate::xorshift(&mut state) % bound;
+        let mut failures = 0;
+        for _ in 0..2_000 {
+            let mut program = Vec::new();
+            for _ in 0..1 + next(12) {
+                match next(4) {
+                    0 => program.extend(make_const_bytecode(next(9) as i32 - 2, 1 + next(4) as i32)),
+                    1 => program.extend([Op::LoadRef as u8, 0, next(6) as u8, next(6) as u8]),
+                    2 => program.extend([Op::LoadBase as u8, next(7) as u8]),
+                    _ => program.push(ops[next(ops.len() as u64) as usize] as u8),
+                }
+            }
+
+            let expected = evaluator.evaluate(&program, program.len(), persistent.cache.notes());
+            let actual = machine.run(&program, program.len(), &notes);
+            match (&expected, &actual) {
+                (Ok(a), Ok(b)) => assert!(a == b, "{:?}", program),
+                (Err(a), Err(b)) => {
+                    assert_eq!(a, b);
+                    failures += 1;
+                }
+                _ => panic!("{:?}: {:?} / {:?}", program, expected, actual),
+            }
+            assert_eq!(evaluator.instructions_executed(), machine.last_run.executed);
+            assert_eq!(evaluator.corruption_sources(Var::Frequency), machine.corruption_sources(Var::Frequency));
+        }
+        // The corpus exercises both outcomes
+        assert!(failures > 100 && failures < 1_900, "{}", failures);
+    }
+
+    #[test]
+    fn test_cached_values_converted_once() {
+        // 5,000 notes reading the base note's frequency and tempo
+        let mut persistent = PersistentEvaluator::new();
+        let base = [(Var::Frequency, 440), (Var::Tempo, 120), (Var::BeatsPerMeasure, 4)];
+        for (var, value) in base {
+            let bytecode = make_const_bytecode(value, 1);
+            persistent.register_expression(0, var as u8, &bytecode, bytecode.len());
+        }
+        let mut expressions = Vec::new();
+        for id in 1..=5_000u16 {
+            let mut frequency = vec![Op::LoadRef as u8, 0, 0, Var::Frequency as u8];
+            frequency.extend(make_const_bytecode(id as i32, id as i32 + 1));
+            frequency.push(Op::Mul as u8);
+            let mut duration = make_const_bytecode(0, 1);
+            duration.push(Op::FindBeat as u8);
+            let tempo = vec![Op::LoadRef as u8, 0, 0, Var::Tempo as u8];
+            for (var, bytecode) in [(Var::Frequency, frequency), (Var::Duration, duration), (Var::Tempo, tempo)] {
+                persistent.register_expression(id as u32, var as u8, &bytecode, bytecode.len());
+                expressions.push(bytecode);
+            }
+        }
+        let order: Vec<u32> = (0..=5_000).collect();
+
+        let conversions = |run: &mut dyn FnMut()| {
+            let before = TO_VALUE_CALLS.with(|calls| calls.get());
+            run();
+            TO_VALUE_CALLS.with(|calls| calls.get()) - before
+        };
+        let persistent_conversions = conversions(&mut || assert_eq!(persistent.evaluate_dirty(&order).evaluated.len(), 5_001));
+        assert_eq!(persistent.cached_note(5_000).unwrap().duration.as_ref().unwrap().to_fraction(), Fraction::new(1, 2));
+        // The base note's measure length reads its own beats and tempo; after
+        // that only the base frequency and tempo are converted, once each
+        assert_eq!(persistent_conversions, 4);
+
+        // The same expressions against a borrowed cache convert on every read
+        let mut evaluator = Evaluator::new();
+        let plain_conversions = conversions(&mut || {
+            for bytecode in &expressions {
+                evaluator.evaluate(bytecode, bytecode.len(), persistent.cache.notes()).unwrap();
+            }
+        });
+        assert_eq!(plain_conversions, 15_000);
+    }
+
+    #[test]
+    fn test_evaluate_batch() {
+        // 500 expressions over one 1000-note cache, every tenth one broken
+        let cache: HashMap<u32, EvaluatedNote> = (0..1000)
+            .map(|id| {
+                let duration = Some(FractionData::from_fraction(&Fraction::new(if id == 3 { 1 } else { id as i32 }, 4)));
+                (id, EvaluatedNote { duration, ..Default::default() })
+            })
+            .collect();
+        let expressions: Vec<Vec<u8>> = (1..=500)
+            .map(|i| {
+                let mut bytecode = make_const_bytecode(i, 7);
+                bytecode.extend([Op::LoadRef as u8, 0, 3, Var::Duration as u8, Op::Mul as u8]);
+                if i % 10 == 0 {
+                    bytecode.push(Op::Add as u8);
+                }
+                bytecode
+            })
+            .collect();
+
+        let mut evaluator = Evaluator::new();
+        let stack = evaluator.stack.as_ptr();
+        let before = TO_VALUE_CALLS.with(|calls| calls.get());
+        let results = evaluator.evaluate_batch(expressions.iter().map(|e| (e.as_slice(), e.len())), &cache, None);
+        // Only the one referenced entry is converted, once per expression
+        assert_eq!(TO_VALUE_CALLS.with(|calls| calls.get()) - before, 500);
+        assert_eq!(evaluator.stack.as_ptr(), stack);
+        assert_eq!(results.len(), 500);
+        assert_eq!(results.iter().filter(|r| r.is_err()).count(), 50);
+
+        let mut single = Evaluator::new();
+        for (expression, result) in expressions.iter().zip(&results) {
+            match (single.evaluate(expression, expression.len(), &cache), result) {
+                (Ok(a), Ok(b)) => assert!(a == *b),
+                (Err(a), Err(b)) => assert_eq!(a, *b),
+                _ => panic!("batch and single evaluation disagree"),
+            }
+        }
+        assert!(results[0].as_ref().unwrap() == &Value::rational(1, 28));
+        assert_eq!(results[9].as_ref().unwrap_err(), &EvalError::StackUnderflow { pc: 14, op: Op::Add });
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    fn test_evaluate_batch_js() {
+        let cache = js_sys::Map::new();
+        for id in 0..1000u32 {
+            let note = EvaluatedNote { duration: Some(FractionData::from_fraction(&Fraction::new(id as i32 + 1, 4))), ..Default::default() };
+            cache.set(&JsValue::from(id), &serde_wasm_bindgen::to_value(&note).unwrap());
+        }
+        let expressions: Vec<Vec<u8>> = (1..=500)
+            .map(|i| {
+                let mut bytecode = make_const_bytecode(i, 7);
+                bytecode.extend([Op::LoadRef as u8, 0, 3, Var::Duration as u8, Op::Mul as u8]);
+                if i % 10 == 0 {
+                    bytecode.push(Op::Add as u8);
+                }
+                bytecode
+            })
+            .collect();
+        let entries = js_sys::Array::new();
+        for expression in &expressions {
+            let entry = js_sys::Object::new();
+            js_sys::Reflect::set(&entry, &JsValue::from_str("bytecode"), &js_sys::Uint8Array::from(expression.as_slice())).unwrap();
+            js_sys::Reflect::set(&entry, &JsValue::from_str("length"), &JsValue::from(expression.len() as u32)).unwrap();
+            entries.push(&entry);
+        }
+
+        let mut evaluator = Evaluator::new();
+        let calls = || CACHE_FROM_JS_CALLS.with(|calls| calls.get());
+        let before = calls();
+        let start = js_sys::Date::now();
+        let results = js_sys::Array::from(&evaluator.evaluate_batch_js(entries.into(), cache.clone().into()).unwrap());
+        let batch_ms = js_sys::Date::now() - start;
+        assert_eq!(calls() - before, 1);
+        assert_eq!(results.length(), 500);
+
+        // Matches one evaluateExpression call per entry, which reads the cache every time
+        let before = calls();
+        let start = js_sys::Date::now();
+        for (i, expression) in expressions.iter().enumerate() {
+            let single = evaluator.evaluate_expression_js(expression, expression.len(), cache.clone().into(), JsValue::UNDEFINED);
+            let entry = results.get(i as u32);
+            match single {
+                Ok(value) => {
+                    let (a, b): (FractionData, FractionData) =
+                        (serde_wasm_bindgen::from_value(value).unwrap(), serde_wasm_bindgen::from_value(entry).unwrap());
+                    assert_eq!(a.to_fraction(), b.to_fraction());
+                }
+                Err(_) => {
+                    let error: EvalErrorData = serde_wasm_bindgen::from_value(entry).unwrap();
+                    assert_eq!(error.code, "STACK_UNDERFLOW");
+                }
+            }
+        }
+        wasm_bindgen_test::console_log!("500 expressions: batch {} ms, single {} ms", batch_ms, js_sys::Date::now() - start);
+        assert_eq!(calls() - before, 500);
+        assert!(evaluator.evaluate_batch_js(JsValue::from(3), JsValue::UNDEFINED).is_err());
+    }
+
+    #[test]
+    fn test_trace() {
+        // (1/2 + note 3's duration) * 2
+        let mut bytecode = make_const_bytecode(1, 2);
+        bytecode.extend([Op::LoadRef as u8, 0, 3, Var::Duration as u8, Op::Add as u8]);
+        bytecode.extend(make_const_bytecode(2, 1));
+        bytecode.push(Op::Mul as u8);
+        let note = EvaluatedNote { duration: Some(FractionData::from_fraction(&Fraction::new(1, 4))), ..Default::default() };
+        let cache = HashMap::from([(3, note)]);
+
+        let mut evaluator = Evaluator::new();
+        let plain = evaluator.evaluate(&bytecode, bytecode.len(), &cache).unwrap();
+        let (traced, trace) = evaluator.evaluate_traced(&bytecode, bytecode.len(), &cache, None);
+        assert!(traced.unwrap() == plain);
+        assert_eq!(trace.len(), evaluator.instructions_executed() as usize);
+        let ops: Vec<&str> = trace.iter().map(|step| step.op.as_str()).collect();
+        assert_eq!(ops, ["LoadConst", "LoadRef", "Add", "LoadConst", "Mul"]);
+        assert_eq!(trace[1].pc, 9);
+        assert_eq!(trace[1].operands, ["3", Var::Duration.name()]);
+        let fraction = |n, d| Value::rational(n, d).to_string();
+        assert_eq!(trace[1].stack, [fraction(1, 2), fraction(1, 4)]);
+        assert_eq!(trace[4].stack, [fraction(3, 2)]);
+
+        // Irrational values read as JavaScript's String(number) would
+        let semitone = 2f64.powf(1.0 / 12.0);
+        let irrational = EvaluatedNote { duration: Some(FractionData::from_value(&Value::from(semitone))), ..Default::default() };
+        let big = EvaluatedNote { duration: Some(FractionData::from_value(&Value::from(1e21))), ..Default::default() };
+        let (_, trace) = evaluator.evaluate_traced(&bytecode, bytecode.len(), &HashMap::from([(3, irrational)]), None);
+        assert_eq!(trace[1].stack, ["1/2", "1.0594630943592953"]);
+        assert_eq!(trace[4].stack, [format_f64_js((0.5 + semitone) * 2.0)]);
+        let (_, trace) = evaluator.evaluate_traced(&bytecode, bytecode.len(), &HashMap::from([(3, big)]), None);
+        assert_eq!(trace[1].stack[1], "1e+21");
+
+        // A failing run's trace stops before the failing instruction
+        let mut failing = make_const_bytecode(1, 1);
+        failing.push(Op::Add as u8);
+        let (result, trace) = evaluator.evaluate_traced(&failing, failing.len(), &cache, None);
+        assert_eq!(result.unwrap_err(), EvalError::StackUnderflow { pc: 9, op: Op::Add });
+        assert_eq!(trace.len(), 1);
+        let traced = TracedEvaluation::new(Err(EvalError::StackUnderflow { pc: 9, op: Op::Add }), trace, Some((5, Var::Duration as u8)));
+        assert!(traced.result.is_none());
+        assert_eq!(traced.error.as_ref().map(|e| (e.code.as_str(), e.note_id)), Some(("STACK_UNDERFLOW", Some(5))));
+
+        // Registered expressions trace against the persistent cache
+        let mut persistent = PersistentEvaluator::new();
+        let quarter = make_const_bytecode(1, 4);
+        persistent.register_expression(3, Var::Duration as u8, &quarter, quarter.len());
+        persistent.register_expression(4, Var::Duration as u8, &bytecode, bytecode.len());
+        persistent.evaluate_dirty(&[3, 4]);
+        let stats = persistent.evaluation_stats().clone();
+        let (result, trace) = persistent.trace_note(4, Var::Duration).unwrap();
+        assert!(result.unwrap() == plain);
+        assert_eq!(trace.len(), 5);
+        assert_eq!(*persistent.evaluation_stats(), stats);
+        assert!(persistent.trace_note(4, Var::Frequency).is_none());
+        assert!(persistent.trace_note(9, Var::Duration).is_none());
+    }
+
+    #[test]
+    fn test_error_tracking() {
+        // frequency = 2^(1/12) + 1 is irrational, startTime = 1/2 exact
+        let mut freq = make_const_bytecode(2, 1);
+        freq.extend(make_const_bytecode(1, 12));
+        freq.push(Op::Pow as u8);
+        freq.extend(make_const_bytecode(1, 1));
+        freq.push(Op::Add as u8);
+        let start = make_const_bytecode(1, 2);
+
+        let mut persistent = PersistentEvaluator::new();
+        persistent.register_expression(1, Var::Frequency as u8, &freq, freq.len());
+        persistent.register_expression(1, Var::StartTime as u8, &start, start.len());
+        persistent.evaluate_dirty(&[1]);
+        assert_eq!(persistent.get_max_error(1, Var::Frequency as u8), None);
+        assert!(persistent.cached_note(1).unwrap().frequency.as_ref().unwrap().err.is_none());
+
+        persistent.set_error_tracking(true);
+        persistent.mark_dirty(1);
+        persistent.evaluate_dirty(&[1]);
+        let bound = persistent.get_max_error(1, Var::Frequency as u8).unwrap();
+        let frequency = persistent.cached_note(1).unwrap().frequency.as_ref().unwrap().to_f64();
+        assert!(bound > 0.0 && bound < 1e-14);
+        assert!((frequency - (2f64.powf(1.0 / 12.0) + 1.0)).abs() <= bound);
+        assert_eq!(persistent.get_max_error(1, Var::StartTime as u8), Some(0.0));
+        assert_eq!(persistent.get_max_error(1, Var::Duration as u8), None);
+        assert_eq!(persistent.get_max_error(2, Var::Frequency as u8), None);
+    }
+
+    #[test]
+    fn test_approximation_denominators() {
+        // A symbolic frequency ratio at 10^8 is within 1e-8 relative
+        let fifth = Value::rational(2, 1).pow(&Value::rational(7, 12));
+        let config = ApproximationConfig::default();
+        assert_eq!(config.denominator(Var::Frequency), 100_000_000);
+        let data = config.data(Var::Frequency, &fifth);
+        let approx = data.n as f64 / data.d as f64;
+        assert!(((approx - fifth.to_f64()) / fifth.to_f64()).abs() < 1e-8);
+        assert!(data.symbolic.is_some() && data.corrupted);
+
+        // Hz values don't fit a 10^8 numerator and take the best fitting fraction
+        let hz = Value::rational(440, 1).mul(&fifth);
+        let data = config.data(Var::Frequency, &hz);
+        assert!(data.d > 1_000_000);
+        assert!(((data.n as f64 / data.d as f64 - hz.to_f64()) / hz.to_f64()).abs() < 1e-12);
+
+        // Times are coarser
+        let data = config.data(Var::StartTime, &Value::from(1.0 / 3.0));
+        assert_eq!((data.n, data.d), (33_333, 100_000));
+
+        // Per-evaluator override, and the shape stays the same
+        let mut persistent = PersistentEvaluator::new();
+        persistent.machine.approximation.set_by_name("frequency", 12).unwrap();
+        assert_eq!(persistent.machine.approximation.set_by_name("pitch", 12), Err("Unknown variable: pitch".to_string()));
+        let mut freq = make_const_bytecode(2, 1);
+        freq.extend(make_const_bytecode(7, 12));
+        freq.push(Op::Pow as u8);
+        persistent.register_expression(1, Var::Frequency as u8, &freq, freq.len());
+        persistent.evaluate_dirty(&[1]);
+        let note = persistent.cached_note(1).unwrap();
+        let frequency = note.frequency.as_ref().unwrap();
+        assert_eq!((frequency.s, frequency.n, frequency.d), (1, 18, 12));
+        let json = serde_json::to_value(frequency).unwrap();
+        let keys: Vec<_> = json.as_object().unwrap().keys().cloned().collect();
+        assert_eq!(keys, ["corrupted", "d", "f", "n", "s", "symbolic"]);
+    }
+
+    #[test]
+    fn test_mod_wraps_start_into_measure() {
+        let mut evaluator = PersistentEvaluator::new();
+        register_chain(&mut evaluator, 4, 5, 12);
+
+        // Note 5 starts at note 4's start (5/4) modulo 1/2
+        let mut start = vec![Op::LoadRef as u8];
+        write_u16(&mut start, 4);
+        start.push(Var::StartTime as u8);
+        start.extend(make_const_bytecode(1, 2));
+        start.push(Op::Mod as u8);
+        evaluator.register_expression(5, Var::StartTime as u8, &start, start.len());
+        evaluator.evaluate_dirty(&[5]);
+
+        let wrapped = evaluator.cached_note(5).and_then(|note| note.start_time.as_ref());
+        assert_eq!(wrapped.map(|f| f.to_fraction()), Some(Fraction::new(1, 4)));
+    }
+
+    #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+    #[test]
+    fn test_evaluate_levels_par_matches_serial() {
+        // Five layers of 160 notes; each note reads random notes from earlier layers
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut random = |n: u32| (crate::xorshift(&mut state) % n as u64) as u32;
+        let reference = |bc: &mut Vec<u8>, id: u32, var: Var| {
+            bc.push(Op::LoadRef as u8);
+            write_u16(bc, id as u16);
+            bc.push(var as u8);
+        };
+
+        let mut evaluator = PersistentEvaluator::new();
+        let base = [(Var::Tempo, 90), (Var::Frequency, 440), (Var::BeatsPerMeasure, 3), (Var::StartTime, 0)];
+        for (var, value) in base {
+            let bc = make_const_bytecode(value, 1);
+            evaluator.register_expression(0, var as u8, &bc, bc.len());
+        }
+
+        let mut levels = vec![vec![0]];
+        for layer in 0..5u32 {
+            let ids: Vec<u32> = (1..=160).map(|i| layer * 160 + i).collect();
+            for &id in &ids {
+                let earlier = layer * 160;
+                let pick = |r: u32| if earlier == 0 { 0 } else { 1 + r % earlier };
+                let (a, b) = (pick(random(1000)), pick(random(1000)));
+
+                let mut start = Vec::new();
+                reference(&mut start, a, Var::StartTime);
+                reference(&mut start, a, Var::MeasureLength);
+                start.push(Op::Add as u8);
+                evaluator.register_expression(id, Var::StartTime as u8, &start, start.len());
+
+                if random(8) == 0 {
+                    continue; // measure note
+                }
+                let duration = make_const_bytecode(1 + random(7) as i32, 1 + random(5) as i32);
+                evaluator.register_expression(id, Var::Duration as u8, &duration, duration.len());
+
+                let mut frequency = Vec::new();
+                reference(&mut frequency, b, Var::Frequency);
+                if random(3) == 0 {
+                    frequency.extend(make_const_bytecode(2, 1));
+                    frequency.extend(make_const_bytecode(random(12) as i32 + 1, 12));
+                    frequency.push(Op::Pow as u8);
+                } else {
+                    frequency.extend(make_const_bytecode(random(8) as i32 + 1, random(8) as i32 + 1));
+                }
+                frequency.push(Op::Mul as u8);
+                evaluator.register_expression(id, Var::Frequency as u8, &frequency, frequency.len());
+            }
+            levels.push(ids);
+        }
+
+        let snapshot = |evaluator: &PersistentEvaluator| {
+            let mut notes: Vec<_> = evaluator.cache.iter().collect();
+            notes.sort_by_key(|(id, _)| **id);
+            serde_json::to_string(&notes).unwrap()
+        };
+
+        // One note in the last layer fails, and is reported the same way
+        evaluator.register_expression(800, Var::Frequency as u8, &[Op::Add as u8], 1);
+
+        let order: Vec<u32> = levels.iter().flatten().copied().collect();
+        evaluator.evaluate_dirty(&order);
+        let serial = snapshot(&evaluator);
+        let serial_errors = evaluator.evaluation_errors().clone();
+        assert_eq!(serial_errors.len(), 1);
+
+        evaluator.cache.clear();
+        evaluator.clear_evaluation_errors();
+        assert_eq!(evaluator.evaluate_levels_par(&levels).evaluated.len(), 801);
+        assert_eq!(snapshot(&evaluator), serial);
+        assert_eq!(evaluator.evaluation_errors(), &serial_errors);
+    }
+
+    #[test]
+    fn test_validate_on_register() {
+        let mut evaluator = PersistentEvaluator::new();
+        let lone_add = [Op::Add as u8];
+
+        // Without validation anything is stored as given
+        evaluator.register_expression(1, Var::Duration as u8, &lone_add, 1);
+        assert!(evaluator.expression(1, Var::Duration).is_some());
+        assert_eq!(evaluator.last_registration_error(), None);
+
+        evaluator.set_validate_on_register(true);
+        evaluator.register_expression(2, Var::Duration as u8, &lone_add, 1);
+        assert!(evaluator.expression(2, Var::Duration).is_none());
+        assert_eq!(
+            evaluator.last_registration_error().as_deref(),
+            Some("Stack underflow at pc=0: Add with 0 values on the stack")
+        );
+
+        let valid = make_const_bytecode(1, 2);
+        evaluator.register_expression(2, Var::Duration as u8, &valid, valid.len());
+        assert!(evaluator.expression(2, Var::Duration).is_some());
+        assert_eq!(evaluator.last_registration_error(), None);
+    }
 }
@@ -0,0 +1,119 @@
+//! JavaScript-compatible number formatting
+//!
+//! `format_f64_js` produces exactly what JS `String(v)` produces, so values
+//! rendered by the WASM path compare equal to values rendered by the JS
+//! evaluator. Rust's `{:e}` formatting already yields the shortest digit
+//! string that round-trips; this module only lays those digits out following
+//! the ECMAScript Number::toString rules.
+
+/// Format an f64 the way JavaScript's `String(v)` does
+pub fn format_f64_js(v: f64) -> String {
+    if v.is_nan() {
+        return "NaN".to_string();
+    }
+    if v.is_infinite() {
+        return if v > 0.0 { "Infinity" } else { "-Infinity" }.to_string();
+    }
+    if v == 0.0 {
+        // Covers -0, which JS prints as "0"
+        return "0".to_string();
+    }
+
+    // Shortest round-trip digits: "d.ddddde<exp>"
+    let sci = format!("{:e}", v.abs());
+    let (mantissa, exp) = sci.split_once('e').unwrap_or((&sci, "0"));
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let exp: i32 = exp.parse().unwrap_or(0);
+
+    // ECMAScript: value = 0.d1d2...dk × 10^n
+    let k = digits.len() as i32;
+    let n = exp + 1;
+
+    let mut out = String::with_capacity(k as usize + 8);
+    if v < 0.0 {
+        out.push('-');
+    }
+
+    if k <= n && n <= 21 {
+        out.push_str(&digits);
+        out.push_str(&"0".repeat((n - k) as usize));
+    } else if 0 < n && n <= 21 {
+        out.push_str(&digits[..n as usize]);
+        out.push('.');
+        out.push_str(&digits[n as usize..]);
+    } else if -6 < n && n <= 0 {
+        out.push_str("0.");
+        out.push_str(&"0".repeat((-n) as usize));
+        out.push_str(&digits);
+    } else {
+        out.push_str(&digits[..1]);
+        if k > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        out.push('e');
+        out.push(if n - 1 < 0 { '-' } else { '+' });
+        out.push_str(&(n - 1).abs().to_string());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_node() {
+        // Expected strings captured from Node's String(v)
+        let cases: &[(f64, &str)] = &[
+            (0.1, "0.1"),
+            (0.1 + 0.2, "0.30000000000000004"),
+            (1.0 / 3.0, "0.3333333333333333"),
+            (1.0594630943592953, "1.0594630943592953"),
+            (1e21, "1e+21"),
+            (999999999999999900000.0, "999999999999999900000"),
+            (123456789012345680000.0, "123456789012345680000"),
+            (1e20, "100000000000000000000"),
+            (1e-6, "0.000001"),
+            (1e-7, "1e-7"),
+            (1.5e-7, "1.5e-7"),
+            (-1e-7, "-1e-7"),
+            (0.000001234, "0.000001234"),
+            (5e-324, "5e-324"),
+            (2.2250738585072014e-308, "2.2250738585072014e-308"),
+            (f64::MAX, "1.7976931348623157e+308"),
+            (9007199254740992.0, "9007199254740992"),
+            (9007199254740994.0, "9007199254740994"),
+            (100.0, "100"),
+            (-1.5, "-1.5"),
+            (123.456, "123.456"),
+            (4.35, "4.35"),
+        ];
+        for &(value, expected) in cases {
+            assert_eq!(format_f64_js(value), expected, "formatting {:e}", value);
+        }
+    }
+
+    #[test]
+    fn test_special_values() {
+        assert_eq!(format_f64_js(0.0), "0");
+        assert_eq!(format_f64_js(-0.0), "0");
+        assert_eq!(format_f64_js(f64::NAN), "NaN");
+        assert_eq!(format_f64_js(f64::INFINITY), "Infinity");
+        assert_eq!(format_f64_js(f64::NEG_INFINITY), "-Infinity");
+    }
+
+    #[test]
+    fn test_round_trips() {
+        // xorshift over raw bit patterns covers subnormals and extreme exponents
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        for _ in 0..10_000 {
+            let x = f64::from_bits(crate::xorshift(&mut state));
+            if !x.is_finite() {
+                continue;
+            }
+            let s = format_f64_js(x);
+            assert_eq!(s.parse::<f64>().unwrap(), x, "{}", s);
+        }
+    }
+}
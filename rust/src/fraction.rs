@@ -3,302 +3,1615 @@
 //! Provides a Fraction type that mirrors the fraction.js API for
 //! seamless interoperability with the JavaScript implementation.
 
-use num_bigint::BigInt;
+use num_bigint::{BigInt, BigUint};
+use num_integer::Integer;
 use num_rational::BigRational;
 use num_traits::{One, Signed, ToPrimitive, Zero};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::sync::OnceLock;
 use wasm_bindgen::prelude::*;
 
 /// Arbitrary-precision rational number
 ///
 /// Wraps num-rational's BigRational to provide a JavaScript-compatible API.
+/// Values whose numerator and denominator fit in an i64 are stored inline
+/// and use allocation-free arithmetic; results are promoted to a BigRational
+/// only when they overflow.
 #[wasm_bindgen]
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Fraction {
-    inner: BigRational,
+    repr: Repr,
+    /// BigRational form of a `Small` value, built by `as_big_rational`
+    big: BigCache,
+}
+
+/// Internal representation of a Fraction
+///
+/// Canonical: `Small` is reduced with a positive denominator, and any value
+/// that fits in `Small` is never stored as `Big`, so derived equality and
+/// hashing compare values.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Repr {
+    Small { num: i64, den: i64 },
+    Big(BigRational),
+}
+
+/// Lazily built BigRational of an inline value
+///
+/// Not part of the value: it compares equal, hashes to nothing and is not
+/// copied by `clone`.
+#[derive(Default)]
+struct BigCache(OnceLock<Box<BigRational>>);
+
+impl Clone for BigCache {
+    fn clone(&self) -> BigCache {
+        BigCache::default()
+    }
+}
+
+impl PartialEq for BigCache {
+    fn eq(&self, _: &BigCache) -> bool {
+        true
+    }
+}
+
+impl Eq for BigCache {}
+
+impl Hash for BigCache {
+    fn hash<H: Hasher>(&self, _: &mut H) {}
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Inline values widened to a BigRational on this thread
+    static WIDENINGS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    /// `from_f64_exact` calls made on this thread
+    pub(crate) static FROM_F64_EXACT_CALLS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Error from the checked Fraction operations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FractionError {
+    /// x/0 for nonzero x
+    DivisionByZero,
+    /// 0/0
+    Indeterminate,
+    /// NaN or ±Infinity where an exact value is required
+    NotFinite,
+}
+
+impl FractionError {
+    /// Machine-readable code, set as `code` on errors thrown to JavaScript
+    pub fn code(&self) -> &'static str {
+        match self {
+            FractionError::DivisionByZero => "DIVISION_BY_ZERO",
+            FractionError::Indeterminate => "INDETERMINATE",
+            FractionError::NotFinite => "NOT_FINITE",
+        }
+    }
+
+    /// Error for `num / 0`
+    fn for_numerator(num_is_zero: bool) -> FractionError {
+        if num_is_zero {
+            FractionError::Indeterminate
+        } else {
+            FractionError::DivisionByZero
+        }
+    }
+}
+
+impl fmt::Display for FractionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FractionError::DivisionByZero => write!(f, "Division by zero"),
+            FractionError::Indeterminate => write!(f, "Indeterminate form 0/0"),
+            FractionError::NotFinite => write!(f, "Non-finite number has no exact fraction"),
+        }
+    }
+}
+
+impl std::error::Error for FractionError {}
+
+impl From<FractionError> for JsValue {
+    fn from(e: FractionError) -> JsValue {
+        let error = js_sys::Error::new(&e.to_string());
+        let _ = js_sys::Reflect::set(&error, &"code".into(), &e.code().into());
+        error.into()
+    }
+}
+
+/// Rounding direction for `Fraction::quantize`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuantizeMode {
+    /// Nearest grid line, halves away from zero
+    Nearest,
+    /// Grid line at or below the value
+    Floor,
+    /// Grid line at or above the value
+    Ceil,
+}
+
+impl FromStr for QuantizeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<QuantizeMode, String> {
+        match s {
+            "nearest" => Ok(QuantizeMode::Nearest),
+            "floor" => Ok(QuantizeMode::Floor),
+            "ceil" => Ok(QuantizeMode::Ceil),
+            _ => Err(format!("Unknown quantize mode '{}' (expected nearest, floor or ceil)", s)),
+        }
+    }
 }
 
 /// Internal representation for serialization
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize)]
 struct FractionRepr {
     n: String, // numerator as string (for big integers)
     d: String, // denominator as string
     s: i8,     // sign: 1 or -1
 }
 
+/// Accepted serialized forms: the `{ n, d, s }` object or a string like "3/4"
+///
+/// `nStr`/`dStr` (written by `toJSON` for parts beyond u32) take precedence
+/// over `n`/`d`, which may then be inexact JS numbers.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FractionInput {
+    Repr {
+        n: IntegerInput,
+        d: IntegerInput,
+        s: i8,
+        #[serde(rename = "nStr")]
+        n_str: Option<String>,
+        #[serde(rename = "dStr")]
+        d_str: Option<String>,
+    },
+    Text(String),
+}
+
+/// `n` and `d` may be strings (as serialized) or plain numbers (as in
+/// fraction.js objects)
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IntegerInput {
+    Number(u64),
+    Text(String),
+    /// Only usable when a string fallback is also given
+    Inexact(f64),
+}
+
+impl IntegerInput {
+    fn parse(&self, what: &str) -> Result<BigInt, String> {
+        match self {
+            IntegerInput::Number(n) => Ok(BigInt::from(*n)),
+            IntegerInput::Text(text) => parse_integer(text, what),
+            IntegerInput::Inexact(v) => Err(format!("{} {} is not an exact integer", what, v)),
+        }
+    }
+
+    /// Parse the exact string fallback if there is one, else this value
+    fn parse_with(&self, fallback: Option<&str>, what: &str) -> Result<BigInt, String> {
+        match fallback {
+            Some(text) => parse_integer(text, what),
+            None => self.parse(what),
+        }
+    }
+}
+
 impl Fraction {
+    /// The fraction 0 (no allocation, usable in constants)
+    pub const fn zero() -> Fraction {
+        Fraction::small(0, 1)
+    }
+
+    /// The fraction 1 (no allocation, usable in constants)
+    pub const fn one() -> Fraction {
+        Fraction::small(1, 1)
+    }
+
     /// Create a new Fraction from numerator and denominator
     pub fn new_raw(num: i64, den: i64) -> Self {
-        let rational = BigRational::new(BigInt::from(num), BigInt::from(den));
-        Fraction { inner: rational }
+        assert!(den != 0, "denominator == 0");
+        Fraction::from_i128(num as i128, den as i128)
     }
 
     /// Create from BigRational directly
     pub fn from_big_rational(r: BigRational) -> Self {
-        Fraction { inner: r }
+        Fraction::from_big(r)
     }
 
     /// Create from BigInt numerator and denominator
     pub fn from_big_ints(num: BigInt, den: BigInt) -> Self {
         if den.is_zero() {
-            return Fraction {
-                inner: BigRational::new(BigInt::from(0), BigInt::from(1)),
-            };
-        }
-        Fraction {
-            inner: BigRational::new(num, den),
+            return Fraction::zero();
         }
+        Fraction::from_big(BigRational::new(num, den))
     }
 
     /// Get the underlying BigRational
+    ///
+    /// An inline value builds it on first use and keeps it; prefer
+    /// `to_big_rational` for a one-off read.
     pub fn as_big_rational(&self) -> &BigRational {
-        &self.inner
+        match &self.repr {
+            Repr::Small { .. } => self.big.0.get_or_init(|| Box::new(self.big().into_owned())),
+            Repr::Big(r) => r,
+        }
+    }
+
+    /// Get the value as a BigRational, borrowed when already stored as one
+    /// and built on the spot otherwise
+    pub fn to_big_rational(&self) -> Cow<'_, BigRational> {
+        self.big()
     }
 
     /// Check if denominator is zero
     pub fn is_nan(&self) -> bool {
-        self.inner.denom().is_zero()
+        match &self.repr {
+            Repr::Small { den, .. } => *den == 0,
+            Repr::Big(r) => r.denom().is_zero(),
+        }
     }
-}
 
-#[wasm_bindgen]
-impl Fraction {
-    /// Create a new Fraction from numerator and denominator
-    #[wasm_bindgen(constructor)]
-    pub fn new(num: i32, den: i32) -> Fraction {
-        if den == 0 {
-            // Return NaN representation (0/0 is treated as invalid)
-            return Fraction {
-                inner: BigRational::new(BigInt::from(0), BigInt::from(1)),
-            };
+    /// Build from a normalized BigRational, storing it inline when it fits
+    fn from_big(r: BigRational) -> Fraction {
+        match (r.numer().to_i64(), r.denom().to_i64()) {
+            (Some(num), Some(den)) => Fraction::small(num, den),
+            _ => Fraction { repr: Repr::Big(r), big: BigCache::default() },
         }
-        Fraction::new_raw(num as i64, den as i64)
     }
 
-    /// Create a Fraction from a single integer
-    #[wasm_bindgen(js_name = fromInt)]
-    pub fn from_int(n: i32) -> Fraction {
-        Fraction::new_raw(n as i64, 1)
+    /// Build from an i128 numerator and nonzero denominator, reducing and
+    /// promoting to a BigRational if the result does not fit in i64s
+    fn from_i128(num: i128, den: i128) -> Fraction {
+        // Operands come from products of i64s, so negation cannot overflow
+        let (num, den) = if den < 0 { (-num, -den) } else { (num, den) };
+        let divisor = num.gcd(&den);
+        let (num, den) = (num / divisor, den / divisor);
+        match (i64::try_from(num), i64::try_from(den)) {
+            (Ok(num), Ok(den)) => Fraction::small(num, den),
+            _ => Fraction::from_big(BigRational::new_raw(BigInt::from(num), BigInt::from(den))),
+        }
     }
 
-    /// Create a Fraction from a string like "3/4" or "1.5"
-    #[wasm_bindgen(js_name = fromString)]
-    pub fn from_string(s: &str) -> Result<Fraction, JsValue> {
-        let s = s.trim();
+    /// An inline value, already reduced with a positive denominator
+    const fn small(num: i64, den: i64) -> Fraction {
+        Fraction { repr: Repr::Small { num, den }, big: BigCache(OnceLock::new()) }
+    }
+
+    /// The value as a BigRational (slow path)
+    fn big(&self) -> Cow<'_, BigRational> {
+        match &self.repr {
+            Repr::Small { num, den } => {
+                #[cfg(test)]
+                WIDENINGS.with(|count| count.set(count.get() + 1));
+                Cow::Owned(BigRational::new_raw(BigInt::from(*num), BigInt::from(*den)))
+            }
+            Repr::Big(r) => Cow::Borrowed(r),
+        }
+    }
+
+    /// Both operands inline, widened for overflow-free products
+    fn small_pair(&self, other: &Fraction) -> Option<(i128, i128, i128, i128)> {
+        match (&self.repr, &other.repr) {
+            (Repr::Small { num: a, den: b }, Repr::Small { num: c, den: d }) => {
+                Some((*a as i128, *b as i128, *c as i128, *d as i128))
+            }
+            _ => None,
+        }
+    }
+
+    /// Round `self / step` with `round`, then scale back by `step`
+    fn snap_to(&self, step: &Fraction, round: fn(&BigRational) -> BigRational) -> Fraction {
+        if step.is_zero() {
+            return self.clone();
+        }
+        let steps = round(&(&*self.big() / &*step.big()));
+        Fraction::from_big(steps * &*step.big())
+    }
 
-        // Try parsing as a fraction "n/d"
-        if let Some(pos) = s.find('/') {
-            let num_str = s[..pos].trim();
-            let den_str = s[pos + 1..].trim();
+    /// Raise to an integer power using repeated squaring
+    ///
+    /// Negative exponents give the reciprocal; 0^n for negative n returns 1,
+    /// following the division-by-zero policy of `div`.
+    pub fn pow_int(&self, n: i64) -> Fraction {
+        if n == 0 {
+            return Fraction::one();
+        }
 
-            let num: BigInt = num_str
-                .parse()
-                .map_err(|e| JsValue::from_str(&format!("Invalid numerator: {}", e)))?;
-            let den: BigInt = den_str
-                .parse()
-                .map_err(|e| JsValue::from_str(&format!("Invalid denominator: {}", e)))?;
+        let mut result = BigRational::one();
+        let mut current = self.big().into_owned();
+        let mut remaining = n.unsigned_abs();
 
-            if den.is_zero() {
-                return Err(JsValue::from_str("Division by zero"));
+        while remaining > 0 {
+            if remaining & 1 == 1 {
+                result = &result * &current;
             }
+            remaining >>= 1;
+            if remaining > 0 {
+                current = &current * &current;
+            }
+        }
 
-            return Ok(Fraction {
-                inner: BigRational::new(num, den),
-            });
+        let result = Fraction::from_big(result);
+        if n < 0 {
+            result.inverse()
+        } else {
+            result
         }
+    }
 
-        // Try parsing as a decimal
-        if let Ok(f) = s.parse::<f64>() {
-            return Ok(Fraction::from_f64(f));
+    /// Simplest fraction within `tolerance` of `value`
+    ///
+    /// Searches the Stern–Brocot tree (via continued fractions) for the
+    /// fraction with the smallest denominator inside
+    /// `[value - tolerance, value + tolerance]`. The interval always covers
+    /// the reals that round to `value`, so a tolerance of 0 gives the simplest
+    /// fraction whose `to_f64()` is exactly `value`. If every fraction in the
+    /// interval needs a denominator above `max_den`, the best approximation
+    /// with a denominator of at most `max_den` is returned instead.
+    /// Non-finite values give 0.
+    pub fn from_f64_with(value: f64, tolerance: f64, max_den: u64) -> Fraction {
+        let exact = match BigRational::from_float(value) {
+            Some(exact) => exact,
+            None => return Fraction::zero(),
+        };
+        let target = exact.abs();
+
+        // Half-way points to the neighbouring f64s bound the reals that round to value
+        let magnitude = value.abs();
+        let below = f64::from_bits(magnitude.to_bits().saturating_sub(1));
+        let above = match f64::from_bits(magnitude.to_bits() + 1) {
+            next if next.is_finite() => next,
+            _ => magnitude + (magnitude - below),
+        };
+        let half = BigRational::new(BigInt::one(), BigInt::from(2));
+        let mut lo = (BigRational::from_float(below).unwrap_or_else(BigRational::zero) + &target) * &half;
+        let mut hi = (BigRational::from_float(above).unwrap_or_else(BigRational::zero) + &target) * &half;
+        if let Some(tolerance) = BigRational::from_float(tolerance.abs()) {
+            lo = lo.min(&target - &tolerance);
+            hi = hi.max(&target + &tolerance);
         }
 
-        // Try parsing as an integer
-        if let Ok(n) = s.parse::<i64>() {
-            return Ok(Fraction::new_raw(n, 1));
+        let simplest = Fraction::from_big(simplest_between(lo, hi));
+        let result = if simplest.d_u64().is_some_and(|d| d <= max_den.max(1)) {
+            simplest
+        } else {
+            Fraction::from_big(target).limit_denominator(max_den)
+        };
+        if value < 0.0 {
+            result.neg()
+        } else {
+            result
         }
+    }
 
-        Err(JsValue::from_str(&format!(
-            "Cannot parse '{}' as a fraction",
-            s
-        )))
+    /// Create a Fraction, reporting a zero denominator instead of returning 0
+    pub fn try_new(num: i32, den: i32) -> Result<Fraction, FractionError> {
+        if den == 0 {
+            return Err(FractionError::for_numerator(num == 0));
+        }
+        Ok(Fraction::new_raw(num as i64, den as i64))
     }
 
-    /// Create a Fraction from a floating-point number
-    #[wasm_bindgen(js_name = fromF64)]
-    pub fn from_f64(value: f64) -> Fraction {
-        if !value.is_finite() {
-            return Fraction::new_raw(0, 1);
+    /// Divide, reporting division by zero instead of returning 1
+    pub fn try_div(&self, other: &Fraction) -> Result<Fraction, FractionError> {
+        if other.is_zero() {
+            return Err(FractionError::for_numerator(self.is_zero()));
         }
+        Ok(self.div(other))
+    }
 
-        // Convert decimal to fraction using continued fractions approximation
-        let tolerance = 1e-10;
-        let max_iterations = 100;
+    /// The exact value of an IEEE-754 double (0.1 is 3602879701896397/2^55)
+    ///
+    /// Unlike `from_f64`, no rounding to a nearby simple fraction is done.
+    /// NaN and infinities are errors.
+    pub fn from_f64_exact(value: f64) -> Result<Fraction, FractionError> {
+        #[cfg(test)]
+        FROM_F64_EXACT_CALLS.with(|calls| calls.set(calls.get() + 1));
+        if !value.is_finite() {
+            return Err(FractionError::NotFinite);
+        }
+        let bits = value.to_bits();
+        let exponent_bits = ((bits >> 52) & 0x7FF) as i64;
+        let fraction_bits = bits & ((1 << 52) - 1);
+        // Subnormals have no implicit leading 1 and share the minimum exponent
+        let (mantissa, exponent) = if exponent_bits == 0 {
+            (fraction_bits, -1074)
+        } else {
+            (fraction_bits | (1 << 52), exponent_bits - 1075)
+        };
+        let mantissa = BigInt::from(mantissa);
+        let mantissa = if bits >> 63 == 1 { -mantissa } else { mantissa };
+        let exact = if exponent >= 0 {
+            BigRational::from_integer(mantissa << exponent as usize)
+        } else {
+            BigRational::new(mantissa, BigInt::one() << (-exponent) as usize)
+        };
+        Ok(Fraction::from_big(exact))
+    }
 
-        let sign = if value < 0.0 { -1i64 } else { 1i64 };
-        let abs_value = value.abs();
+    /// Closest fraction with denominator at most `max_den`
+    ///
+    /// Walks the continued-fraction expansion (the Stern–Brocot path) and
+    /// picks between the last convergent and the best semiconvergent, so the
+    /// result is the exact best approximation rather than a rounding.
+    /// Fractions already within the limit are returned unchanged; a `max_den`
+    /// of 0 is treated as 1.
+    pub fn limit_denominator(&self, max_den: u64) -> Fraction {
+        if self.d_u64().is_some_and(|d| d <= max_den.max(1)) {
+            return self.clone();
+        }
+        let max_den = BigInt::from(max_den.max(1));
 
-        if abs_value == 0.0 {
-            return Fraction::new_raw(0, 1);
+        let target = self.big().abs();
+        let mut convergent = Convergent::new();
+        for a in ContinuedFraction::new(&target) {
+            if convergent.next_denom(&a) > max_den {
+                break;
+            }
+            convergent.push(&a);
         }
+        let Convergent { p0, q0, p1, q1 } = convergent;
+
+        // Semiconvergent with the largest denominator that still fits
+        let k = (&max_den - &q0) / &q1;
+        let semi = BigRational::new(&p0 + &k * &p1, &q0 + &k * &q1);
+        let convergent = BigRational::new(p1, q1);
+        let best = if (&convergent - &target).abs() <= (&semi - &target).abs() {
+            convergent
+        } else {
+            semi
+        };
+
+        Fraction::from_big(if self.is_negative() { -best } else { best })
+    }
 
-        // Handle integers
-        if (abs_value - abs_value.round()).abs() < tolerance {
-            return Fraction::new_raw(sign * abs_value.round() as i64, 1);
+    /// First continued-fraction convergent within `tolerance` of this one,
+    /// as fraction.js `simplify` computes it
+    ///
+    /// The convergents of |self| are tried in order and the first whose
+    /// distance, as an f64, is below the tolerance is returned with the sign
+    /// put back. A tolerance of 0 or NaN means 0.001 (fraction.js reads
+    /// `eps || 0.001`); a negative one matches nothing and returns the value
+    /// unchanged. Use `simplest_between` for the simplest fraction in a range.
+    pub fn simplify(&self, tolerance: f64) -> Fraction {
+        let tolerance = if tolerance == 0.0 || tolerance.is_nan() { 1e-3 } else { tolerance };
+        let target = self.big().abs();
+        let mut convergent = Convergent::new();
+        for a in ContinuedFraction::new(&target) {
+            convergent.push(&a);
+            let candidate = convergent.value();
+            if (&candidate - &target).to_f64().is_some_and(|diff| diff.abs() < tolerance) {
+                let candidate = Fraction::from_big(candidate);
+                return if self.is_negative() { Fraction::neg(&candidate) } else { candidate };
+            }
         }
+        self.clone()
+    }
 
-        // Continued fraction approximation
-        let mut best_num = abs_value.round() as i64;
-        let mut best_den = 1i64;
-        let mut best_error = (abs_value - best_num as f64).abs();
+    /// Fraction with the smallest denominator in `[lo, hi]` (the endpoints
+    /// may come in either order); zero if the interval reaches zero
+    ///
+    /// For positive intervals it also has the smallest numerator, so no
+    /// other fraction in the interval has a lower Tenney height.
+    pub fn simplest_between(lo: &Fraction, hi: &Fraction) -> Fraction {
+        let (lo, hi) = if lo <= hi { (lo, hi) } else { (hi, lo) };
+        Fraction::from_big(simplest_between(lo.big().into_owned(), hi.big().into_owned()))
+    }
 
-        for den in 1..=max_iterations {
-            let num = (abs_value * den as f64).round() as i64;
-            let error = (abs_value - (num as f64 / den as f64)).abs();
+    /// The fraction.js `{ s, n, d }` object for this value
+    ///
+    /// `s` is 1 for zero, as in fraction.js. When a part exceeds u32, `n` and
+    /// `d` are the nearest JS numbers and the exact digits are added as
+    /// `nStr` and `dStr`.
+    pub fn to_json_value(&self) -> serde_json::Value {
+        let big = self.big();
+        let part = |exact: Option<u64>, n: &BigInt| match exact {
+            Some(v) => serde_json::Value::from(v),
+            None => serde_json::Value::from(n.to_f64().unwrap_or(f64::INFINITY)),
+        };
+        let mut json = serde_json::json!({
+            "s": if self.is_negative() { -1 } else { 1 },
+            "n": part(self.n_u64(), &big.numer().abs()),
+            "d": part(self.d_u64(), big.denom()),
+        });
+        if !self.fits_u32() {
+            json["nStr"] = self.numerator_abs_str().into();
+            json["dStr"] = self.denominator_str().into();
+        }
+        json
+    }
 
-            if error < best_error {
-                best_num = num;
-                best_den = den;
-                best_error = error;
+    /// Read a fraction.js `{ s, n, d }` object (numeric or string parts,
+    /// with optional `nStr`/`dStr`) or an "n/d" string
+    pub fn from_json_value(value: &serde_json::Value) -> Result<Fraction, String> {
+        Fraction::deserialize(value).map_err(|e| format!("Invalid fraction JSON: {}", e))
+    }
 
-                if error < tolerance {
-                    break;
-                }
+    /// Closest fractions strictly below and above this one with denominator
+    /// at most `max_den` (its neighbors in the Farey sequence of that order)
+    ///
+    /// Walks the continued-fraction expansion like `limit_denominator`, so
+    /// the cost grows with the number of terms, not with `max_den`. A
+    /// `max_den` of 0 is treated as 1.
+    pub fn farey_neighbors(&self, max_den: u64) -> (Fraction, Fraction) {
+        let max_den = BigInt::from(max_den.max(1));
+        let mut convergent = Convergent::new();
+        let mut terms = ContinuedFraction::new(&self.big());
+        let fits = loop {
+            match terms.next() {
+                None => break true,
+                Some(a) if convergent.next_denom(&a) > max_den => break false,
+                Some(a) => convergent.push(&a),
             }
+        };
+        let Convergent { p0, q0, p1, q1 } = convergent;
+
+        // Add as many copies of p1/q1 to p/q as the denominator limit allows;
+        // this keeps the determinant with p1/q1 at ±1, so the result is
+        // adjacent to p1/q1
+        let extend = |p: BigInt, q: BigInt| {
+            let k = (&max_den - &q) / &q1;
+            Fraction::from_big(BigRational::new(p + &k * &p1, q + &k * &q1))
+        };
+        let (a, b) = if fits {
+            // p1/q1 is the fraction itself; p0/q0 and its complement
+            // (p1-p0)/(q1-q0) are adjacent to it on either side
+            (extend(p0.clone(), q0.clone()), extend(&p1 - &p0, &q1 - &q0))
+        } else {
+            // The last convergent that fits and the best semiconvergent are
+            // adjacent and bracket the value
+            (extend(p0, q0), Fraction::from_big(BigRational::new(p1.clone(), q1.clone())))
+        };
+        if a < b {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    /// Best rational approximation of an interval given in cents, with
+    /// denominator at most `max_den` (see `limit_denominator`)
+    pub fn from_cents(cents: f64, max_den: u64) -> Fraction {
+        crate::tuning::cents_to_ratio(cents, max_den)
+    }
+
+    /// Snap to an exact multiple of `grid` in the given direction
+    ///
+    /// Only the size of the grid matters, so Floor always rounds toward
+    /// negative infinity. A zero grid returns the value unchanged.
+    pub fn quantize(&self, grid: &Fraction, mode: QuantizeMode) -> Fraction {
+        let grid = grid.abs();
+        match mode {
+            QuantizeMode::Nearest => self.round_to(&grid),
+            QuantizeMode::Floor => self.floor_to(&grid),
+            QuantizeMode::Ceil => self.ceil_to(&grid),
         }
+    }
+
+    /// Sum of all values (0 for an empty slice)
+    pub fn sum(values: &[Fraction]) -> Fraction {
+        values.iter().fold(Fraction::zero(), |acc, f| Fraction::add(&acc, f))
+    }
+
+    /// Product of all values (1 for an empty slice)
+    pub fn product(values: &[Fraction]) -> Fraction {
+        values.iter().fold(Fraction::one(), |acc, f| Fraction::mul(&acc, f))
+    }
+
+    /// Running totals: the i-th entry is the sum of `values[..=i]`
+    ///
+    /// For successive durations these are the end times; the start times
+    /// are 0 followed by all but the last entry.
+    pub fn cumulative_sum(values: &[Fraction]) -> Vec<Fraction> {
+        let mut total = Fraction::zero();
+        values
+            .iter()
+            .map(|f| {
+                total = Fraction::add(&total, f);
+                total.clone()
+            })
+            .collect()
+    }
+
+    /// Largest prime factor of the numerator or denominator, searching
+    /// primes up to `bound` by trial division
+    ///
+    /// Returns None for zero, or if some prime factor exceeds `bound`.
+    /// Integers ±1 have prime limit 1.
+    pub fn prime_limit_with(&self, bound: u64) -> Option<u64> {
+        if self.is_zero() {
+            return None;
+        }
+        let big = self.big();
+        let num_limit = largest_prime_factor(big.numer().magnitude(), bound)?;
+        let den_limit = largest_prime_factor(big.denom().magnitude(), bound)?;
+        Some(num_limit.max(den_limit))
+    }
 
-        Fraction::new_raw(sign * best_num, best_den)
+    /// Prime factorization of |n/d| as (prime, exponent) pairs in increasing
+    /// order of prime, with negative exponents for denominator factors
+    /// (45/32 is [(2, -5), (3, 2), (5, 1)]; 1 is empty)
+    ///
+    /// Trial division only searches primes up to `max_prime`, so this returns
+    /// None instead of grinding on a large prime factor. Zero also gives None.
+    pub fn factorize(&self, max_prime: u64) -> Option<Vec<(u64, i32)>> {
+        if self.is_zero() {
+            return None;
+        }
+        let big = self.big();
+        let num = prime_factors(big.numer().magnitude(), max_prime)?;
+        let den = prime_factors(big.denom().magnitude(), max_prime)?;
+        let mut factors = num
+            .into_iter()
+            .map(|(p, e)| Some((p, i32::try_from(e).ok()?)))
+            .chain(den.into_iter().map(|(p, e)| Some((p, -i32::try_from(e).ok()?))))
+            .collect::<Option<Vec<_>>>()?;
+        // Numerator and denominator are coprime, so primes never repeat
+        factors.sort_unstable_by_key(|&(p, _)| p);
+        Some(factors)
+    }
+
+    /// Fold a frequency ratio into the octave [1, 2), returning the folded
+    /// ratio and the number of octaves removed (9/4 is 9/8 and 1 octave,
+    /// 1/3 is 4/3 and -2 octaves)
+    ///
+    /// Returns None unless the fraction is positive.
+    pub fn octave_reduce(&self) -> Option<(Fraction, i64)> {
+        if !self.is_positive() {
+            return None;
+        }
+        let big = self.big();
+        let (num, den) = (big.numer().magnitude(), big.denom().magnitude());
+        // n/d lies in [2^(k-1), 2^(k+1)), so floor(log2) is k or k - 1
+        let k = num.bits() as i64 - den.bits() as i64;
+        let below = if k >= 0 {
+            *num < den << k as u64
+        } else {
+            num << k.unsigned_abs() < *den
+        };
+        let octaves = if below { k - 1 } else { k };
+        let folded = self.mul(&Fraction::new_raw(2, 1).pow_int(-octaves));
+        Some((folded, octaves))
+    }
+
+    /// Sign (-1, 0 or 1), absolute numerator and denominator, with the
+    /// magnitudes as decimal strings so no precision is lost
+    pub fn to_parts(&self) -> (i32, String, String) {
+        (
+            self.s(),
+            self.numerator_abs_str(),
+            self.denominator_str(),
+        )
+    }
+
+    /// Round to the nearest integer (halves away from zero)
+    ///
+    /// Returns None if the result does not fit in an i64.
+    pub fn round_to_i64(&self) -> Option<i64> {
+        self.big().round().to_integer().to_i64()
+    }
+}
+
+/// Largest denominator `from_f64` will produce (fits FractionData's u32)
+const DEFAULT_MAX_DENOMINATOR: u64 = u32::MAX as u64;
+
+/// Largest prime `prime_limit` searches for by default
+const DEFAULT_PRIME_BOUND: u64 = 1 << 16;
+
+/// Prime factors of `n` with their multiplicities, in increasing order,
+/// found by trial division; None if a prime factor exceeds `bound`
+fn prime_factors(n: &BigUint, bound: u64) -> Option<Vec<(u64, u32)>> {
+    let mut rest = n.clone();
+    let mut factors = Vec::new();
+    let mut p = 2u64;
+    while rest > BigUint::one() {
+        if p > bound {
+            return None;
+        }
+        // Whatever remains once p² exceeds it is itself prime
+        if BigUint::from(p) * p > rest {
+            let last = rest.to_u64().filter(|&last| last <= bound)?;
+            factors.push((last, 1));
+            break;
+        }
+        let mut count = 0;
+        while (&rest % p).is_zero() {
+            rest /= p;
+            count += 1;
+        }
+        if count > 0 {
+            factors.push((p, count));
+        }
+        p += if p == 2 { 1 } else { 2 };
+    }
+    Some(factors)
+}
+
+/// Largest prime factor of `n` (1 for n = 1), or None if it exceeds `bound`
+fn largest_prime_factor(n: &BigUint, bound: u64) -> Option<u64> {
+    Some(prime_factors(n, bound)?.last().map_or(1, |&(p, _)| p))
+}
+
+/// If `n` is `base` raised to some power, return the exponent
+fn power_exponent(n: &BigUint, base: u32) -> Option<u32> {
+    let mut rest = n.clone();
+    let mut exponent = 0;
+    while rest > BigUint::one() {
+        let (quotient, remainder) = rest.div_rem(&BigUint::from(base));
+        if !remainder.is_zero() {
+            return None;
+        }
+        rest = quotient;
+        exponent += 1;
+    }
+    Some(exponent)
+}
+
+/// Read a JavaScript BigInt through its decimal string
+fn big_int_from_js(value: &js_sys::BigInt) -> Result<BigInt, JsValue> {
+    let text = String::from(value.to_string(10)?);
+    text.parse().map_err(|_| JsValue::from_str(&format!("Invalid BigInt '{}'", text)))
+}
+
+/// Build a JavaScript BigInt from a decimal integer string
+fn big_int_to_js(digits: &str) -> js_sys::BigInt {
+    // Our own integer strings are always valid BigInt literals
+    js_sys::BigInt::new(&JsValue::from_str(digits)).unwrap_or_default()
+}
+
+/// Deserialize a JS array of fractions for the batch functions
+fn fractions_from_js(values: JsValue) -> Result<Vec<Fraction>, JsValue> {
+    if !js_sys::Array::is_array(&values) {
+        return Err(JsValue::from_str("Expected an array of fractions"));
+    }
+    let entries = js_sys::Array::from(&values);
+    collect_indexed(entries.iter().map(serde_wasm_bindgen::from_value))
+        .map_err(|e| JsValue::from_str(&e))
+}
+
+/// Collect parsed entries, naming the index of the first malformed one
+fn collect_indexed<E: fmt::Display>(
+    entries: impl IntoIterator<Item = Result<Fraction, E>>,
+) -> Result<Vec<Fraction>, String> {
+    entries
+        .into_iter()
+        .enumerate()
+        .map(|(i, entry)| entry.map_err(|e| format!("Invalid fraction at index {}: {}", i, e)))
+        .collect()
+}
+
+/// Terms of the continued-fraction expansion of a rational
+///
+/// Uses floor division, so only the first term can be negative. This and
+/// `Convergent` are the one continued-fraction engine in the crate, shared by
+/// `continued_fraction`, `convergents`, `limit_denominator`, `farey_neighbors`
+/// and `from_f64`.
+struct ContinuedFraction {
+    n: BigInt,
+    d: BigInt,
+}
+
+impl ContinuedFraction {
+    fn new(x: &BigRational) -> ContinuedFraction {
+        ContinuedFraction {
+            n: x.numer().clone(),
+            d: x.denom().clone(),
+        }
+    }
+}
+
+impl Iterator for ContinuedFraction {
+    type Item = BigInt;
+
+    fn next(&mut self) -> Option<BigInt> {
+        if self.d.is_zero() {
+            return None;
+        }
+        let (a, r) = self.n.div_mod_floor(&self.d);
+        self.n = std::mem::replace(&mut self.d, r);
+        Some(a)
+    }
+}
+
+/// Convergent p1/q1 of the continued-fraction terms pushed so far, and the
+/// one before it (p0/q0)
+struct Convergent {
+    p0: BigInt,
+    q0: BigInt,
+    p1: BigInt,
+    q1: BigInt,
+}
+
+impl Convergent {
+    /// Before any terms: p0/q0 = 0/1 and p1/q1 = 1/0
+    fn new() -> Convergent {
+        Convergent {
+            p0: BigInt::zero(),
+            q0: BigInt::one(),
+            p1: BigInt::one(),
+            q1: BigInt::zero(),
+        }
+    }
+
+    /// Denominator the convergent would have after pushing `a`
+    fn next_denom(&self, a: &BigInt) -> BigInt {
+        &self.q0 + a * &self.q1
+    }
+
+    fn push(&mut self, a: &BigInt) {
+        let p2 = &self.p0 + a * &self.p1;
+        let q2 = self.next_denom(a);
+        self.p0 = std::mem::replace(&mut self.p1, p2);
+        self.q0 = std::mem::replace(&mut self.q1, q2);
+    }
+
+    fn value(&self) -> BigRational {
+        BigRational::new(self.p1.clone(), self.q1.clone())
+    }
+}
+
+/// Fraction with the smallest denominator in `[lo, hi]` (requires `lo <= hi`)
+///
+/// Takes shared continued-fraction terms until an integer fits the
+/// remaining interval, which ends the expansion.
+fn simplest_between(mut lo: BigRational, mut hi: BigRational) -> BigRational {
+    if !lo.is_positive() {
+        return BigRational::zero();
+    }
+    let mut convergent = Convergent::new();
+    let last = loop {
+        let floor = lo.floor();
+        if floor == lo {
+            break floor;
+        }
+        let next_integer = &floor + BigRational::one();
+        if next_integer <= hi {
+            break next_integer;
+        }
+        let (next_lo, next_hi) = ((&hi - &floor).recip(), (&lo - &floor).recip());
+        convergent.push(&floor.to_integer());
+        lo = next_lo;
+        hi = next_hi;
+    };
+    convergent.push(&last.to_integer());
+    convergent.value()
+}
+
+#[wasm_bindgen]
+impl Fraction {
+    /// Create a new Fraction from numerator and denominator
+    #[wasm_bindgen(constructor)]
+    pub fn new(num: i32, den: i32) -> Fraction {
+        if den == 0 {
+            // Return NaN representation (0/0 is treated as invalid)
+            return Fraction::from_big(BigRational::new(BigInt::from(0), BigInt::from(1)));
+        }
+        Fraction::new_raw(num as i64, den as i64)
+    }
+
+    /// Like the constructor, but throws on a zero denominator; the error's
+    /// `code` is "DIVISION_BY_ZERO" or "INDETERMINATE" (for 0/0)
+    #[wasm_bindgen(js_name = newChecked)]
+    pub fn new_checked(num: i32, den: i32) -> Result<Fraction, JsValue> {
+        Ok(Fraction::try_new(num, den)?)
+    }
+
+    /// Create a Fraction from a single integer
+    #[wasm_bindgen(js_name = fromInt)]
+    pub fn from_int(n: i32) -> Fraction {
+        Fraction::new_raw(n as i64, 1)
+    }
+
+    /// The fraction 0
+    #[wasm_bindgen(js_name = zero)]
+    pub fn zero_js() -> Fraction {
+        Fraction::zero()
+    }
+
+    /// The fraction 1
+    #[wasm_bindgen(js_name = one)]
+    pub fn one_js() -> Fraction {
+        Fraction::one()
+    }
+
+    /// Create a Fraction from a string
+    ///
+    /// Accepts integers, exact decimals (including repeating decimals such as
+    /// "0.1(6)"), fractions ("3/4", "3 / 4"), mixed numbers
+    /// ("1 1/2", "-2 3/4"), ratios ("3:2"), a leading '+' or '-' that applies
+    /// to the whole value, and '_' between digits. See the `FromStr` impl.
+    #[wasm_bindgen(js_name = fromString)]
+    pub fn from_string(s: &str) -> Result<Fraction, JsValue> {
+        s.parse().map_err(|e: String| JsValue::from_str(&e))
+    }
+
+    /// Create a Fraction from JavaScript BigInt numerator and denominator
+    /// without truncating to i32; a zero denominator throws like `newChecked`
+    #[wasm_bindgen(js_name = fromBigInts)]
+    pub fn from_js_big_ints(num: js_sys::BigInt, den: js_sys::BigInt) -> Result<Fraction, JsValue> {
+        let (num, den) = (big_int_from_js(&num)?, big_int_from_js(&den)?);
+        if den.is_zero() {
+            return Err(FractionError::for_numerator(num.is_zero()).into());
+        }
+        Ok(Fraction::from_big_ints(num, den))
+    }
+
+    /// Read a fraction.js `{ s, n, d }` object or "n/d" string (see
+    /// `from_json_value`)
+    #[wasm_bindgen(js_name = fromJson)]
+    pub fn from_json_js(value: JsValue) -> Result<Fraction, JsValue> {
+        serde_wasm_bindgen::from_value(value)
+            .map_err(|e| JsValue::from_str(&format!("Invalid fraction JSON: {}", e)))
+    }
+
+    /// The fraction.js `{ s, n, d }` object (see `to_json_value`); used by
+    /// `JSON.stringify`
+    #[wasm_bindgen(js_name = toJSON)]
+    pub fn to_json_js(&self) -> JsValue {
+        let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+        self.to_json_value().serialize(&serializer).unwrap_or(JsValue::NULL)
+    }
+
+    /// Simplest fraction within `tolerance` (default 0.001, as in fraction.js)
+    #[wasm_bindgen(js_name = simplify)]
+    pub fn simplify_js(&self, tolerance: Option<f64>) -> Fraction {
+        self.simplify(tolerance.unwrap_or(1e-3))
+    }
+
+    /// Signed numerator as a JavaScript BigInt (lossless, unlike `n`)
+    #[wasm_bindgen(js_name = numeratorBigInt)]
+    pub fn numerator_big_int(&self) -> js_sys::BigInt {
+        big_int_to_js(&self.numerator_str())
+    }
+
+    /// Denominator as a JavaScript BigInt (lossless, unlike `d`)
+    #[wasm_bindgen(js_name = denominatorBigInt)]
+    pub fn denominator_big_int(&self) -> js_sys::BigInt {
+        big_int_to_js(&self.denominator_str())
+    }
+
+    /// Create a Fraction from a floating-point number
+    ///
+    /// Returns the simplest fraction that converts back to exactly `value`
+    /// (denominator at most `u32::MAX`); see `from_f64_with`.
+    #[wasm_bindgen(js_name = fromF64)]
+    pub fn from_f64(value: f64) -> Fraction {
+        Fraction::from_f64_with(value, 0.0, DEFAULT_MAX_DENOMINATOR)
+    }
+
+    /// Create a Fraction from a floating-point number with an explicit
+    /// tolerance and maximum denominator (see `from_f64_with`)
+    #[wasm_bindgen(js_name = fromF64With)]
+    pub fn from_f64_with_js(value: f64, tolerance: f64, max_den: u32) -> Fraction {
+        Fraction::from_f64_with(value, tolerance, max_den as u64)
     }
 
     /// Add two fractions
     pub fn add(&self, other: &Fraction) -> Fraction {
-        Fraction {
-            inner: &self.inner + &other.inner,
+        match self.small_pair(other) {
+            Some((a, b, c, d)) if b == d => Fraction::from_i128(a + c, b),
+            Some((a, b, c, d)) => Fraction::from_i128(a * d + c * b, b * d),
+            None => Fraction::from_big(&*self.big() + &*other.big()),
         }
     }
 
     /// Subtract two fractions
     pub fn sub(&self, other: &Fraction) -> Fraction {
-        Fraction {
-            inner: &self.inner - &other.inner,
+        match self.small_pair(other) {
+            Some((a, b, c, d)) if b == d => Fraction::from_i128(a - c, b),
+            Some((a, b, c, d)) => Fraction::from_i128(a * d - c * b, b * d),
+            None => Fraction::from_big(&*self.big() - &*other.big()),
         }
     }
 
     /// Multiply two fractions
     pub fn mul(&self, other: &Fraction) -> Fraction {
-        Fraction {
-            inner: &self.inner * &other.inner,
+        match self.small_pair(other) {
+            Some((a, b, c, d)) => Fraction::from_i128(a * c, b * d),
+            None => Fraction::from_big(&*self.big() * &*other.big()),
         }
     }
 
     /// Divide two fractions
     pub fn div(&self, other: &Fraction) -> Fraction {
-        if other.inner.is_zero() {
+        if other.is_zero() {
             // Return 1 for division by zero (matches JS behavior)
-            return Fraction::new_raw(1, 1);
+            return Fraction::one();
         }
-        Fraction {
-            inner: &self.inner / &other.inner,
+        match self.small_pair(other) {
+            Some((a, b, c, d)) => Fraction::from_i128(a * d, b * c),
+            None => Fraction::from_big(&*self.big() / &*other.big()),
+        }
+    }
+
+    /// The exact value of a double (see `from_f64_exact`); throws for NaN
+    /// and infinities with code NOT_FINITE
+    #[wasm_bindgen(js_name = fromF64Exact)]
+    pub fn from_f64_exact_js(value: f64) -> Result<Fraction, JsValue> {
+        Ok(Fraction::from_f64_exact(value)?)
+    }
+
+    /// Sum an array of `{ s, n, d }` objects or "n/d" strings inside WASM
+    #[wasm_bindgen(js_name = sumArray)]
+    pub fn sum_array(values: JsValue) -> Result<Fraction, JsValue> {
+        Ok(Fraction::sum(&fractions_from_js(values)?))
+    }
+
+    /// Multiply an array of `{ s, n, d }` objects or "n/d" strings inside WASM
+    #[wasm_bindgen(js_name = productArray)]
+    pub fn product_array(values: JsValue) -> Result<Fraction, JsValue> {
+        Ok(Fraction::product(&fractions_from_js(values)?))
+    }
+
+    /// Running totals of an array of fractions (see `cumulative_sum`)
+    #[wasm_bindgen(js_name = cumulativeSum)]
+    pub fn cumulative_sum_js(values: JsValue) -> Result<Vec<Fraction>, JsValue> {
+        Ok(Fraction::cumulative_sum(&fractions_from_js(values)?))
+    }
+
+    /// True if this fraction is exactly the value of the double `value`
+    #[wasm_bindgen(js_name = isExactly)]
+    pub fn is_exactly(&self, value: f64) -> bool {
+        Fraction::from_f64_exact(value).is_ok_and(|exact| exact == *self)
+    }
+
+    /// Like `div`, but throws on division by zero (see `newChecked`)
+    #[wasm_bindgen(js_name = divChecked)]
+    pub fn div_checked(&self, other: &Fraction) -> Result<Fraction, JsValue> {
+        Ok(self.try_div(other)?)
+    }
+
+    /// Remainder of truncated division; the result has the sign of the dividend
+    /// (like fraction.js `mod`). Modulo by zero returns the dividend unchanged.
+    #[wasm_bindgen(js_name = mod)]
+    pub fn rem(&self, other: &Fraction) -> Fraction {
+        if other.is_zero() {
+            return self.clone();
+        }
+        match self.small_pair(other) {
+            Some((a, b, c, d)) => Fraction::from_i128((a * d) % (c * b), b * d),
+            None => Fraction::from_big(&*self.big() % &*other.big()),
         }
     }
 
     /// Negate the fraction
     pub fn neg(&self) -> Fraction {
-        Fraction {
-            inner: -&self.inner,
+        match &self.repr {
+            Repr::Small { num, den } => Fraction::from_i128(-(*num as i128), *den as i128),
+            Repr::Big(r) => Fraction::from_big(-r),
         }
     }
 
     /// Get the absolute value
     pub fn abs(&self) -> Fraction {
-        Fraction {
-            inner: self.inner.abs(),
+        if self.is_negative() {
+            self.neg()
+        } else {
+            self.clone()
         }
     }
 
     /// Get the reciprocal (1/x)
     pub fn inverse(&self) -> Fraction {
-        if self.inner.is_zero() {
-            return Fraction::new_raw(1, 1);
+        match &self.repr {
+            Repr::Small { num: 0, .. } => Fraction::one(),
+            Repr::Small { num, den } => Fraction::from_i128(*den as i128, *num as i128),
+            Repr::Big(r) => Fraction::from_big(r.recip()),
+        }
+    }
+
+    /// Raise to an integer power (negative exponents give the reciprocal)
+    #[wasm_bindgen(js_name = pow)]
+    pub fn pow(&self, n: i32) -> Fraction {
+        self.pow_int(n as i64)
+    }
+
+    /// Largest integer less than or equal to this fraction
+    #[wasm_bindgen(js_name = floor)]
+    pub fn floor(&self) -> Fraction {
+        Fraction::from_big(self.big().floor())
+    }
+
+    /// Smallest integer greater than or equal to this fraction
+    #[wasm_bindgen(js_name = ceil)]
+    pub fn ceil(&self) -> Fraction {
+        Fraction::from_big(self.big().ceil())
+    }
+
+    /// Nearest integer, rounding halves away from zero (like fraction.js)
+    #[wasm_bindgen(js_name = round)]
+    pub fn round(&self) -> Fraction {
+        Fraction::from_big(self.big().round())
+    }
+
+    /// Integer part, rounding towards zero
+    #[wasm_bindgen(js_name = trunc)]
+    pub fn trunc(&self) -> Fraction {
+        Fraction::from_big(self.big().trunc())
+    }
+
+    /// Largest multiple of `step` less than or equal to this fraction
+    /// (a zero step returns the value unchanged)
+    #[wasm_bindgen(js_name = floorTo)]
+    pub fn floor_to(&self, step: &Fraction) -> Fraction {
+        self.snap_to(step, BigRational::floor)
+    }
+
+    /// Smallest multiple of `step` greater than or equal to this fraction
+    /// (a zero step returns the value unchanged)
+    #[wasm_bindgen(js_name = ceilTo)]
+    pub fn ceil_to(&self, step: &Fraction) -> Fraction {
+        self.snap_to(step, BigRational::ceil)
+    }
+
+    /// Nearest multiple of `step`, rounding halves away from zero
+    /// (a zero step returns the value unchanged)
+    #[wasm_bindgen(js_name = roundTo)]
+    pub fn round_to(&self, step: &Fraction) -> Fraction {
+        self.snap_to(step, BigRational::round)
+    }
+
+    /// Quantize to a grid of gridNum/gridDen with mode "nearest", "floor"
+    /// or "ceil" (see `quantize`)
+    #[wasm_bindgen(js_name = quantize)]
+    pub fn quantize_js(
+        &self,
+        grid_num: i32,
+        grid_den: i32,
+        mode: &str,
+    ) -> Result<Fraction, JsValue> {
+        let grid = Fraction::try_new(grid_num, grid_den)?;
+        let mode = mode.parse().map_err(|e: String| JsValue::from_str(&e))?;
+        Ok(self.quantize(&grid, mode))
+    }
+
+    /// True if this fraction is a whole number of `grid` steps (only zero is
+    /// a multiple of a zero grid)
+    #[wasm_bindgen(js_name = isMultipleOf)]
+    pub fn is_multiple_of(&self, grid: &Fraction) -> bool {
+        if grid.is_zero() {
+            return self.is_zero();
+        }
+        Fraction::div(self, grid).is_integer()
+    }
+
+    /// Absolute difference |self - other|
+    #[wasm_bindgen(js_name = absDiff)]
+    pub fn abs_diff(&self, other: &Fraction) -> Fraction {
+        let diff = Fraction::sub(self, other);
+        if diff.is_negative() {
+            Fraction::neg(&diff)
+        } else {
+            diff
+        }
+    }
+
+    /// True if |self - other| <= epsilon, compared exactly
+    ///
+    /// Small operands are compared by cross-multiplying in i128, without
+    /// building the difference. A negative epsilon never matches.
+    #[wasm_bindgen(js_name = approxEq)]
+    pub fn approx_eq(&self, other: &Fraction, epsilon: &Fraction) -> bool {
+        let pair = self.small_pair(other);
+        if let (Some((a, b, c, d)), Repr::Small { num: e, den: f }) = (pair, &epsilon.repr) {
+            if *e < 0 {
+                return false;
+            }
+            // |a/b - c/d| <= e/f  <=>  |ad - cb| * f <= e * bd
+            let diff = (a * d - c * b).unsigned_abs();
+            let bound = ((b * d) as u128).checked_mul(*e as u128);
+            if let (Some(lhs), Some(rhs)) = (diff.checked_mul(*f as u128), bound) {
+                return lhs <= rhs;
+            }
+        }
+        self.abs_diff(other) <= *epsilon
+    }
+
+    /// Mediant (a+c)/(b+d) of the reduced forms a/b and c/d; it lies
+    /// between the two
+    pub fn mediant(&self, other: &Fraction) -> Fraction {
+        match self.small_pair(other) {
+            Some((a, b, c, d)) => Fraction::from_i128(a + c, b + d),
+            None => {
+                let (x, y) = (self.big(), other.big());
+                Fraction::from_big_ints(x.numer() + y.numer(), x.denom() + y.denom())
+            }
+        }
+    }
+
+    /// Farey neighbors as [below, above] (see `farey_neighbors`)
+    #[wasm_bindgen(js_name = fareyNeighbors)]
+    pub fn farey_neighbors_js(&self, max_den: u32) -> Vec<Fraction> {
+        let (below, above) = self.farey_neighbors(max_den as u64);
+        vec![below, above]
+    }
+
+    /// The smaller of two fractions (`self` when equal)
+    #[wasm_bindgen(js_name = min)]
+    pub fn min(&self, other: &Fraction) -> Fraction {
+        if other.lt(self) {
+            other.clone()
+        } else {
+            self.clone()
+        }
+    }
+
+    /// The larger of two fractions (`self` when equal)
+    #[wasm_bindgen(js_name = max)]
+    pub fn max(&self, other: &Fraction) -> Fraction {
+        if other.gt(self) {
+            other.clone()
+        } else {
+            self.clone()
+        }
+    }
+
+    /// Closest fraction with denominator at most `max_den` (see `limit_denominator`)
+    #[wasm_bindgen(js_name = limitDenominator)]
+    pub fn limit_denominator_js(&self, max_den: u32) -> Fraction {
+        self.limit_denominator(max_den as u64)
+    }
+
+    /// Continued-fraction coefficients [a0; a1, a2, ...] (floor-based, so
+    /// only a0 can be negative: -7/4 is [-2; 4])
+    ///
+    /// The expansion stops before any term that does not fit in an i64,
+    /// which can only happen for fractions outside the i64 range.
+    #[wasm_bindgen(js_name = continuedFraction)]
+    pub fn continued_fraction(&self) -> Vec<i64> {
+        ContinuedFraction::new(&self.big()).map_while(|a| a.to_i64()).collect()
+    }
+
+    /// Successive convergents of the continued fraction, at most `max_count`
+    /// of them; the last one is the fraction itself once the expansion ends
+    pub fn convergents(&self, max_count: usize) -> Vec<Fraction> {
+        let mut convergent = Convergent::new();
+        ContinuedFraction::new(&self.big())
+            .take(max_count)
+            .map(|a| {
+                convergent.push(&a);
+                Fraction::from_big(convergent.value())
+            })
+            .collect()
+    }
+
+    /// Best rational approximation of a cents value with denominator ≤ maxDen
+    #[wasm_bindgen(js_name = fromCents)]
+    pub fn from_cents_js(cents: f64, max_den: u32) -> Fraction {
+        Fraction::from_cents(cents, max_den as u64)
+    }
+
+    /// Size of this frequency ratio in cents (1200·log2(n/d)); NaN unless positive
+    #[wasm_bindgen(js_name = toCents)]
+    pub fn to_cents(&self) -> f64 {
+        crate::tuning::ratio_to_cents(self)
+    }
+
+    /// Tenney height log2(|n·d|), a measure of harmonic complexity
+    ///
+    /// Computed from the exact numerator and denominator, so it stays finite
+    /// for values beyond f64 range; zero gives -Infinity.
+    #[wasm_bindgen(js_name = tenneyHeight)]
+    pub fn tenney_height(&self) -> f64 {
+        let big = self.big();
+        crate::tuning::big_log2(&big.numer().abs()) + crate::tuning::big_log2(big.denom())
+    }
+
+    /// Largest odd factor of the numerator or denominator (81 for 81/64)
+    ///
+    /// Returns None for zero, or if the odd part does not fit in a u64.
+    #[wasm_bindgen(js_name = oddLimit)]
+    pub fn odd_limit(&self) -> Option<u64> {
+        if self.is_zero() {
+            return None;
         }
-        Fraction {
-            inner: self.inner.recip(),
+        let big = self.big();
+        let odd_part = |n: &BigUint| (n >> n.trailing_zeros().unwrap_or(0)).to_u64();
+        Some(odd_part(big.numer().magnitude())?.max(odd_part(big.denom().magnitude())?))
+    }
+
+    /// Largest prime factor of the numerator or denominator (3 for 81/64)
+    ///
+    /// Searches primes up to 65536; returns None for zero or when a larger
+    /// factor remains (see `prime_limit_with`).
+    #[wasm_bindgen(js_name = primeLimit)]
+    pub fn prime_limit(&self) -> Option<u64> {
+        self.prime_limit_with(DEFAULT_PRIME_BOUND)
+    }
+
+    /// Largest prime factor, searching primes up to `bound`
+    #[wasm_bindgen(js_name = primeLimitWith)]
+    pub fn prime_limit_with_js(&self, bound: u32) -> Option<u64> {
+        self.prime_limit_with(bound as u64)
+    }
+
+    /// Prime factorization as an array of [prime, exponent] pairs, or
+    /// undefined if a factor exceeds maxPrime (default 65536); see `factorize`
+    #[wasm_bindgen(js_name = factorize)]
+    pub fn factorize_js(&self, max_prime: Option<u32>) -> JsValue {
+        let max_prime = max_prime.map_or(DEFAULT_PRIME_BOUND, |p| p as u64);
+        let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+        match self.factorize(max_prime) {
+            Some(factors) => factors.serialize(&serializer).unwrap_or(JsValue::UNDEFINED),
+            None => JsValue::UNDEFINED,
+        }
+    }
+
+    /// Fold into the octave [1, 2) as `{ ratio, octaves }`, or undefined
+    /// unless positive; see `octave_reduce`
+    #[wasm_bindgen(js_name = octaveReduce)]
+    pub fn octave_reduce_js(&self) -> JsValue {
+        let Some((ratio, octaves)) = self.octave_reduce() else {
+            return JsValue::UNDEFINED;
+        };
+        let result = js_sys::Object::new();
+        let _ = js_sys::Reflect::set(&result, &"ratio".into(), &ratio.into());
+        let _ = js_sys::Reflect::set(&result, &"octaves".into(), &(octaves as f64).into());
+        result.into()
+    }
+
+    /// True if this is `base` raised to an integer power (negative powers
+    /// included, and 1 = base^0); bases 0 and 1 only have the powers 0 and 1
+    #[wasm_bindgen(js_name = isPowerOf)]
+    pub fn is_power_of(&self, base: u32) -> bool {
+        if base < 2 {
+            return self.is_one() || (base == 0 && self.is_zero());
+        }
+        if !self.is_positive() {
+            return false;
+        }
+        let big = self.big();
+        let (num, den) = (big.numer().magnitude(), big.denom().magnitude());
+        // In lowest terms one side of a power of base must be 1
+        (den.is_one() && power_exponent(num, base).is_some())
+            || (num.is_one() && power_exponent(den, base).is_some())
+    }
+
+    /// True if the denominator is a power of two (a dyadic rational, like
+    /// every finite double)
+    #[wasm_bindgen(js_name = isDyadic)]
+    pub fn is_dyadic(&self) -> bool {
+        match &self.repr {
+            Repr::Small { den, .. } => (*den as u64).is_power_of_two(),
+            Repr::Big(r) => r.denom().magnitude().count_ones() == 1,
         }
     }
 
     /// Check if this fraction equals another
     pub fn equals(&self, other: &Fraction) -> bool {
-        self.inner == other.inner
+        self == other
     }
 
     /// Compare this fraction to another
     /// Returns -1 if self < other, 0 if equal, 1 if self > other
     pub fn compare(&self, other: &Fraction) -> i32 {
-        match self.inner.cmp(&other.inner) {
+        match self.cmp(other) {
             std::cmp::Ordering::Less => -1,
             std::cmp::Ordering::Equal => 0,
             std::cmp::Ordering::Greater => 1,
         }
     }
 
+    /// True if this fraction equals another (alias of `equals`)
+    #[wasm_bindgen(js_name = eq)]
+    pub fn eq_js(&self, other: &Fraction) -> bool {
+        self == other
+    }
+
+    /// True if self < other
+    pub fn lt(&self, other: &Fraction) -> bool {
+        self < other
+    }
+
+    /// True if self <= other
+    pub fn lte(&self, other: &Fraction) -> bool {
+        self <= other
+    }
+
+    /// True if self > other
+    pub fn gt(&self, other: &Fraction) -> bool {
+        self > other
+    }
+
+    /// True if self >= other
+    pub fn gte(&self, other: &Fraction) -> bool {
+        self >= other
+    }
+
+    /// Compare this fraction to a plain number
+    ///
+    /// Returns -1, 0 or 1 like `compare`, or undefined when `value` is NaN.
+    /// The number is converted with `from_f64` and compared exactly, so an
+    /// f64 that is the nearest double to a simple fraction compares as that
+    /// fraction (1/10 equals 0.1, 1/3 equals 1/3 computed in f64). When
+    /// `from_f64` cannot reproduce the number exactly, the comparison uses
+    /// the exact binary value of the f64 instead. Infinities compare as
+    /// larger or smaller than every fraction.
+    #[wasm_bindgen(js_name = compareToNumber)]
+    pub fn compare_to_number(&self, value: f64) -> Option<i32> {
+        if value.is_nan() {
+            return None;
+        }
+        if value.is_infinite() {
+            return Some(if value > 0.0 { -1 } else { 1 });
+        }
+        let simple = Fraction::from_f64(value);
+        if simple.to_f64() == value {
+            return Some(self.compare(&simple));
+        }
+        let exact = BigRational::from_float(value).unwrap_or_else(BigRational::zero);
+        Some(self.compare(&Fraction::from_big(exact)))
+    }
+
     /// Convert to f64
     #[wasm_bindgen(js_name = toF64)]
     pub fn to_f64(&self) -> f64 {
-        self.inner.to_f64().unwrap_or(0.0)
+        const EXACT: u64 = 1 << f64::MANTISSA_DIGITS;
+        match &self.repr {
+            // Both operands exact, so the division rounds correctly
+            Repr::Small { num, den } if num.unsigned_abs() <= EXACT && *den as u64 <= EXACT => {
+                *num as f64 / *den as f64
+            }
+            _ => self.big().to_f64().unwrap_or(0.0),
+        }
     }
 
     /// Get the sign (-1, 0, or 1)
     #[wasm_bindgen(getter)]
     pub fn s(&self) -> i32 {
-        if self.inner.is_zero() {
-            0
-        } else if self.inner.is_positive() {
-            1
-        } else {
-            -1
+        match &self.repr {
+            Repr::Small { num, .. } => num.signum() as i32,
+            Repr::Big(r) => r.numer().signum().to_i32().unwrap_or(0),
         }
     }
 
     /// Get the absolute numerator
     #[wasm_bindgen(getter)]
     pub fn n(&self) -> u32 {
-        self.inner
-            .numer()
-            .abs()
-            .to_u32()
-            .unwrap_or(u32::MAX)
+        self.n_u64().and_then(|n| u32::try_from(n).ok()).unwrap_or(u32::MAX)
     }
 
     /// Get the denominator
     #[wasm_bindgen(getter)]
     pub fn d(&self) -> u32 {
-        self.inner.denom().to_u32().unwrap_or(u32::MAX)
+        self.d_u64().and_then(|d| u32::try_from(d).ok()).unwrap_or(u32::MAX)
+    }
+
+    /// Absolute numerator, or None if it does not fit in a u64
+    #[wasm_bindgen(js_name = nU64)]
+    pub fn n_u64(&self) -> Option<u64> {
+        match &self.repr {
+            Repr::Small { num, .. } => Some(num.unsigned_abs()),
+            Repr::Big(r) => r.numer().magnitude().to_u64(),
+        }
     }
 
-    /// Get the numerator as a string (for large values)
+    /// Denominator, or None if it does not fit in a u64
+    #[wasm_bindgen(js_name = dU64)]
+    pub fn d_u64(&self) -> Option<u64> {
+        match &self.repr {
+            Repr::Small { den, .. } => Some(*den as u64),
+            Repr::Big(r) => r.denom().to_u64(),
+        }
+    }
+
+    /// True if `n` and `d` are exact (neither saturates at u32::MAX)
+    #[wasm_bindgen(js_name = fitsU32)]
+    pub fn fits_u32(&self) -> bool {
+        match &self.repr {
+            Repr::Small { num, den } => {
+                u32::try_from(num.unsigned_abs()).is_ok() && u32::try_from(*den).is_ok()
+            }
+            Repr::Big(_) => false,
+        }
+    }
+
+    /// True if the signed numerator and the denominator both fit in an i32
+    /// (the range of `new` and of LoadConst operands)
+    #[wasm_bindgen(js_name = fitsI32)]
+    pub fn fits_i32(&self) -> bool {
+        match &self.repr {
+            Repr::Small { num, den } => i32::try_from(*num).is_ok() && i32::try_from(*den).is_ok(),
+            Repr::Big(_) => false,
+        }
+    }
+
+    /// Sign and magnitudes as `[s, n, d]` (see `to_parts`)
+    #[wasm_bindgen(js_name = toParts)]
+    pub fn to_parts_js(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.to_parts()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Get the signed numerator as a string ("-3" for -3/4)
     #[wasm_bindgen(js_name = numeratorStr)]
     pub fn numerator_str(&self) -> String {
-        (self.inner.numer() * self.inner.signum().numer()).to_string()
+        match &self.repr {
+            Repr::Small { num, .. } => num.to_string(),
+            Repr::Big(r) => r.numer().to_string(),
+        }
     }
 
-    /// Get the denominator as a string (for large values)
+    /// Get the absolute numerator as a string ("3" for -3/4)
+    #[wasm_bindgen(js_name = numeratorAbsStr)]
+    pub fn numerator_abs_str(&self) -> String {
+        match &self.repr {
+            Repr::Small { num, .. } => num.unsigned_abs().to_string(),
+            Repr::Big(r) => r.numer().magnitude().to_string(),
+        }
+    }
+
+    /// Get the denominator as a string; always positive, since the sign is
+    /// kept on the numerator
     #[wasm_bindgen(js_name = denominatorStr)]
     pub fn denominator_str(&self) -> String {
-        self.inner.denom().to_string()
+        match &self.repr {
+            Repr::Small { den, .. } => den.to_string(),
+            Repr::Big(r) => r.denom().magnitude().to_string(),
+        }
     }
 
     /// Convert to string representation "n/d" or "n" if d=1
     #[wasm_bindgen(js_name = toString)]
     pub fn to_string_repr(&self) -> String {
-        let numer = self.inner.numer();
-        let denom = self.inner.denom();
+        if self.is_integer() {
+            self.numerator_str()
+        } else {
+            format!("{}/{}", self.numerator_str(), self.denominator_str())
+        }
+    }
+
+    /// Decimal form with at most `max_digits` digits after the point
+    ///
+    /// Terminating decimals are exact ("0.375"). A repeating cycle that
+    /// completes within `max_digits` is shown in parentheses ("0.1(6)",
+    /// the form `parse` accepts); otherwise the digits are cut off with "…".
+    #[wasm_bindgen(js_name = toDecimalString)]
+    pub fn to_decimal_string(&self, max_digits: usize) -> String {
+        let big = self.big();
+        let den = big.denom().magnitude();
+        let (whole, mut rem) = big.numer().magnitude().div_rem(den);
+        let sign = if self.is_negative() { "-" } else { "" };
+
+        let mut digits = String::new();
+        let mut seen = std::collections::HashMap::new();
+        while !rem.is_zero() && digits.len() < max_digits && !seen.contains_key(&rem) {
+            seen.insert(rem.clone(), digits.len());
+            let (digit, next) = (rem * 10u32).div_rem(den);
+            digits.push(char::from(b'0' + digit.to_u8().unwrap_or(0)));
+            rem = next;
+        }
 
-        if denom.is_one() {
-            numer.to_string()
+        let fraction = match seen.get(&rem) {
+            _ if rem.is_zero() => digits,
+            Some(&start) => format!("{}({})", &digits[..start], &digits[start..]),
+            None => format!("{}…", digits),
+        };
+        if fraction.is_empty() {
+            format!("{}{}", sign, whole)
         } else {
-            format!("{}/{}", numer, denom)
+            format!("{}{}.{}", sign, whole, fraction)
+        }
+    }
+
+    /// Mixed-number form "w n/d" ("1 1/2", "-2 3/4"); proper fractions and
+    /// integers print as in `toString`
+    #[wasm_bindgen(js_name = toMixedString)]
+    pub fn to_mixed_string(&self) -> String {
+        let whole = self.trunc();
+        if whole.is_zero() || self.is_integer() {
+            return self.to_string_repr();
         }
+        let part = Fraction::sub(self, &whole).abs();
+        format!("{} {}", whole.to_string_repr(), part.to_string_repr())
     }
 
     /// Clone this fraction
@@ -310,25 +1623,56 @@ impl Fraction {
     /// Check if this is zero
     #[wasm_bindgen(js_name = isZero)]
     pub fn is_zero(&self) -> bool {
-        self.inner.is_zero()
+        match &self.repr {
+            Repr::Small { num, .. } => *num == 0,
+            Repr::Big(r) => r.is_zero(),
+        }
     }
 
     /// Check if this is one
     #[wasm_bindgen(js_name = isOne)]
     pub fn is_one(&self) -> bool {
-        self.inner.is_one()
+        match &self.repr {
+            Repr::Small { num, den } => *num == 1 && *den == 1,
+            Repr::Big(r) => r.is_one(),
+        }
+    }
+
+    /// Check if this is a whole number (denominator 1 after reduction)
+    #[wasm_bindgen(js_name = isInteger)]
+    pub fn is_integer(&self) -> bool {
+        match &self.repr {
+            Repr::Small { den, .. } => *den == 1,
+            Repr::Big(r) => r.is_integer(),
+        }
+    }
+
+    /// The value as an i64, or None if it is not an integer or out of range
+    #[wasm_bindgen(js_name = toI64)]
+    pub fn to_i64(&self) -> Option<i64> {
+        match self.repr {
+            Repr::Small { num, den: 1 } => Some(num),
+            // Big values never fit in an i64
+            _ => None,
+        }
     }
 
     /// Check if this is negative
     #[wasm_bindgen(js_name = isNegative)]
     pub fn is_negative(&self) -> bool {
-        self.inner.is_negative()
+        match &self.repr {
+            Repr::Small { num, .. } => *num < 0,
+            Repr::Big(r) => r.is_negative(),
+        }
     }
 
     /// Check if this is positive
     #[wasm_bindgen(js_name = isPositive)]
     pub fn is_positive(&self) -> bool {
-        self.inner.is_positive()
+        match &self.repr {
+            Repr::Small { num, .. } => *num > 0,
+            Repr::Big(r) => r.is_positive(),
+        }
     }
 }
 
@@ -336,7 +1680,7 @@ impl Fraction {
 
 impl Default for Fraction {
     fn default() -> Self {
-        Fraction::new_raw(0, 1)
+        Fraction::zero()
     }
 }
 
@@ -352,13 +1696,203 @@ impl fmt::Display for Fraction {
     }
 }
 
+/// Serializes as `{ "n": "<numerator>", "d": "<denominator>", "s": 1 }`, with
+/// the magnitudes as strings so values of any size survive JSON round trips.
+/// Zero has sign 1, as in fraction.js.
+impl Serialize for Fraction {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        FractionRepr {
+            n: self.numerator_abs_str(),
+            d: self.denominator_str(),
+            s: if self.is_negative() { -1 } else { 1 },
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Accepts the object form written by `Serialize` or any string `from_string`
+/// understands
+impl<'de> Deserialize<'de> for Fraction {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Fraction, D::Error> {
+        use serde::de::Error;
+        match FractionInput::deserialize(deserializer)? {
+            FractionInput::Repr { n, d, s, n_str, d_str } => {
+                let n = n.parse_with(n_str.as_deref(), "numerator").map_err(D::Error::custom)?;
+                let d = d.parse_with(d_str.as_deref(), "denominator").map_err(D::Error::custom)?;
+                if d.is_zero() {
+                    return Err(D::Error::custom("fraction with a zero denominator"));
+                }
+                let magnitude = BigRational::new(n, d);
+                Ok(Fraction::from_big(if s < 0 { -magnitude } else { magnitude }))
+            }
+            FractionInput::Text(text) => text.parse().map_err(D::Error::custom),
+        }
+    }
+}
+
+impl FromStr for Fraction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Fraction, String> {
+        let s = s.trim();
+        parse_signed(s).map_err(|reason| format!("Cannot parse '{}' as a fraction: {}", s, reason))
+    }
+}
+
+/// Parse an optional sign followed by an unsigned fraction, mixed number,
+/// ratio or decimal
+fn parse_signed(s: &str) -> Result<Fraction, String> {
+    if s.is_empty() {
+        return Err("empty input".to_string());
+    }
+    let (negative, body) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+
+    let value = if let Some((num, den)) = body.split_once(':') {
+        let (num, den) = (parse_decimal(num.trim())?, parse_decimal(den.trim())?);
+        if den.is_zero() {
+            return Err("ratio with a zero second term".to_string());
+        }
+        Fraction::div(&num, &den)
+    } else if let Some((left, den)) = body.split_once('/') {
+        let den = parse_integer(den.trim(), "denominator")?;
+        if den.is_zero() {
+            return Err("division by zero".to_string());
+        }
+        let mut parts = left.split_whitespace();
+        let (whole, num) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(num), None, None) => (BigInt::zero(), parse_integer(num, "numerator")?),
+            (Some(whole), Some(num), None) => {
+                (parse_integer(whole, "whole part")?, parse_integer(num, "numerator")?)
+            }
+            (None, _, _) => return Err("missing numerator".to_string()),
+            _ => return Err("expected a whole part and a numerator before '/'".to_string()),
+        };
+        Fraction::from_big(BigRational::from_integer(whole) + BigRational::new(num, den))
+    } else {
+        parse_decimal(body)?
+    };
+
+    Ok(if negative { -value } else { value })
+}
+
+/// Digits with optional '_' separators (only between digits)
+fn strip_digit_separators<'a>(s: &'a str, what: &str) -> Result<std::borrow::Cow<'a, str>, String> {
+    if s.is_empty() {
+        return Err(format!("missing {}", what));
+    }
+    let bytes = s.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'0'..=b'9' => {}
+            b'_' if i > 0 && i + 1 < bytes.len() && bytes[i - 1] != b'_' && bytes[i + 1] != b'_' => {}
+            b'_' => return Err(format!("misplaced '_' in {} '{}'", what, s)),
+            _ => return Err(format!("invalid {} '{}'", what, s)),
+        }
+    }
+    Ok(if s.contains('_') { s.replace('_', "").into() } else { s.into() })
+}
+
+/// Unsigned integer with optional digit separators
+fn parse_integer(s: &str, what: &str) -> Result<BigInt, String> {
+    let digits = strip_digit_separators(s, what)?;
+    digits.parse().map_err(|_| format!("invalid {} '{}'", what, s))
+}
+
+/// Largest decimal exponent accepted when parsing ("1e4096")
+const MAX_DECIMAL_EXPONENT: u32 = 4096;
+
+/// Unsigned integer or decimal, with an optional repetend in parentheses and
+/// an optional exponent ("1.5", "0.1(6)", "2e-3")
+///
+/// The value is built exactly from its digits: I.A(R) is
+/// (IA * (10^r - 1) + R) / (10^a * (10^r - 1)), where a and r are the
+/// lengths of A and R.
+fn parse_decimal(s: &str) -> Result<Fraction, String> {
+    if s.is_empty() {
+        return Err("missing number".to_string());
+    }
+    let (mantissa, exponent) = match s.find(['e', 'E']) {
+        Some(pos) => (&s[..pos], Some(&s[pos + 1..])),
+        None => (s, None),
+    };
+    let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let (fixed_part, repetend) = match frac_part.split_once('(') {
+        Some((fixed, rest)) => match rest.strip_suffix(')') {
+            Some(repetend) if !repetend.is_empty() => (fixed, Some(repetend)),
+            Some(_) => return Err(format!("empty repetend in '{}'", s)),
+            None => return Err(format!("unclosed '(' in '{}'", s)),
+        },
+        None => (frac_part, None),
+    };
+    if int_part.is_empty() && frac_part.is_empty() {
+        return Err(format!("invalid number '{}'", s));
+    }
+
+    let mut digits = String::with_capacity(mantissa.len());
+    if !int_part.is_empty() {
+        digits.push_str(&strip_digit_separators(int_part, "number")?);
+    }
+    let mut fixed_len = 0;
+    if !fixed_part.is_empty() {
+        let fixed = strip_digit_separators(fixed_part, "decimal digits")?;
+        fixed_len = fixed.len();
+        digits.push_str(&fixed);
+    }
+    let ten = BigInt::from(10);
+    let mut numer: BigInt = if digits.is_empty() { BigInt::zero() } else { digits.parse().unwrap_or_default() };
+    let mut denom = num_traits::pow(ten.clone(), fixed_len);
+
+    if let Some(repetend) = repetend {
+        let repetend = strip_digit_separators(repetend, "repetend")?;
+        let nines = num_traits::pow(ten.clone(), repetend.len()) - 1;
+        numer = numer * &nines + repetend.parse::<BigInt>().unwrap_or_default();
+        denom *= nines;
+    }
+
+    if let Some(exponent) = exponent {
+        let (negative, magnitude) = match exponent.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, exponent.strip_prefix('+').unwrap_or(exponent)),
+        };
+        let magnitude = strip_digit_separators(magnitude, "exponent")?;
+        let power = match magnitude.parse::<u32>() {
+            Ok(power) if power <= MAX_DECIMAL_EXPONENT => num_traits::pow(ten, power as usize),
+            _ => return Err(format!("exponent '{}' is too large", exponent)),
+        };
+        if negative {
+            denom *= power;
+        } else {
+            numer *= power;
+        }
+    }
+
+    Ok(Fraction::from_big(BigRational::new(numer, denom)))
+}
+
+impl Ord for Fraction {
+    fn cmp(&self, other: &Fraction) -> std::cmp::Ordering {
+        match self.small_pair(other) {
+            // Denominators are positive, so cross-multiplying keeps the order
+            Some((a, b, c, d)) => (a * d).cmp(&(c * b)),
+            None => self.big().cmp(&other.big()),
+        }
+    }
+}
+
+impl PartialOrd for Fraction {
+    fn partial_cmp(&self, other: &Fraction) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Add for Fraction {
     type Output = Fraction;
 
     fn add(self, rhs: Fraction) -> Fraction {
-        Fraction {
-            inner: self.inner + rhs.inner,
-        }
+        Fraction::add(&self, &rhs)
     }
 }
 
@@ -366,9 +1900,7 @@ impl Sub for Fraction {
     type Output = Fraction;
 
     fn sub(self, rhs: Fraction) -> Fraction {
-        Fraction {
-            inner: self.inner - rhs.inner,
-        }
+        Fraction::sub(&self, &rhs)
     }
 }
 
@@ -376,9 +1908,7 @@ impl Mul for Fraction {
     type Output = Fraction;
 
     fn mul(self, rhs: Fraction) -> Fraction {
-        Fraction {
-            inner: self.inner * rhs.inner,
-        }
+        Fraction::mul(&self, &rhs)
     }
 }
 
@@ -386,9 +1916,8 @@ impl Div for Fraction {
     type Output = Fraction;
 
     fn div(self, rhs: Fraction) -> Fraction {
-        Fraction {
-            inner: self.inner / rhs.inner,
-        }
+        assert!(!rhs.is_zero(), "Division by zero");
+        Fraction::div(&self, &rhs)
     }
 }
 
@@ -396,7 +1925,7 @@ impl Neg for Fraction {
     type Output = Fraction;
 
     fn neg(self) -> Fraction {
-        Fraction { inner: -self.inner }
+        Fraction::neg(&self)
     }
 }
 
@@ -427,16 +1956,16 @@ mod tests {
         let a = Fraction::new(1, 2);
         let b = Fraction::new(1, 4);
 
-        let sum = (&a).add(&b);
+        let sum = Fraction::add(&a, &b);
         assert_eq!(sum.to_string_repr(), "3/4");
 
-        let diff = (&a).sub(&b);
+        let diff = Fraction::sub(&a, &b);
         assert_eq!(diff.to_string_repr(), "1/4");
 
-        let prod = (&a).mul(&b);
+        let prod = Fraction::mul(&a, &b);
         assert_eq!(prod.to_string_repr(), "1/8");
 
-        let quot = (&a).div(&b);
+        let quot = Fraction::div(&a, &b);
         assert_eq!(quot.to_string_repr(), "2");
     }
 
@@ -456,6 +1985,89 @@ mod tests {
         assert_eq!(b.to_f64(), 5.0);
     }
 
+    #[test]
+    fn test_parse_forms() {
+        let accepted: &[(&str, (i64, i64))] = &[
+            ("3/4", (3, 4)),
+            (" 3 / 4 ", (3, 4)),
+            ("-3/4", (-3, 4)),
+            ("+3/4", (3, 4)),
+            ("1 1/2", (3, 2)),
+            ("-1 1/2", (-3, 2)),
+            ("-2 3/4", (-11, 4)),
+            ("- 1/2", (-1, 2)),
+            ("+2  3/4", (11, 4)),
+            ("3:2", (3, 2)),
+            ("-3 : 2", (-3, 2)),
+            ("1.5:1", (3, 2)),
+            ("1_000/3", (1000, 3)),
+            ("1_000_000", (1_000_000, 1)),
+            ("+5", (5, 1)),
+            ("-0.25", (-1, 4)),
+            (".5", (1, 2)),
+            ("2.", (2, 1)),
+            ("1_2.5e-1", (5, 4)),
+        ];
+        for &(text, (num, den)) in accepted {
+            assert_eq!(text.parse::<Fraction>(), Ok(Fraction::new_raw(num, den)), "{:?}", text);
+        }
+
+        let rejected: &[(&str, &str)] = &[
+            ("", "empty input"),
+            ("abc", "invalid number 'abc'"),
+            ("1/0", "division by zero"),
+            ("3:0", "ratio with a zero second term"),
+            ("1//2", "invalid denominator '/2'"),
+            ("1/2/3", "invalid denominator '2/3'"),
+            ("3/-4", "invalid denominator '-4'"),
+            ("3:", "missing number"),
+            ("/2", "missing numerator"),
+            ("1 2 3/4", "expected a whole part and a numerator before '/'"),
+            ("1_", "misplaced '_' in number '1_'"),
+            ("_1", "misplaced '_' in number '_1'"),
+            ("1__0", "misplaced '_' in number '1__0'"),
+            ("--1", "invalid number '-1'"),
+            ("1/2 3", "invalid denominator '2 3'"),
+            ("1.2.3", "invalid decimal digits '2.3'"),
+            ("inf", "invalid number 'inf'"),
+            ("1e5000", "exponent '5000' is too large"),
+            ("0.1(6", "unclosed '(' in '0.1(6'"),
+            ("0.()", "empty repetend in '0.()'"),
+            ("0.(1a)", "invalid repetend '1a'"),
+            ("(3)", "invalid number '(3)'"),
+        ];
+        for &(text, reason) in rejected {
+            assert_eq!(
+                text.parse::<Fraction>(),
+                Err(format!("Cannot parse '{}' as a fraction: {}", text.trim(), reason)),
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_exact_decimals() {
+        let parse = |text: &str| text.parse::<Fraction>().unwrap();
+        assert_eq!(parse("0.(3)"), Fraction::new(1, 3));
+        assert_eq!(parse("0.1(6)"), Fraction::new(1, 6));
+        assert_eq!(parse("0.(142857)"), Fraction::new(1, 7));
+        assert_eq!(parse("-1.(9)"), Fraction::new(-2, 1));
+        assert_eq!(parse("0.(3)e1"), Fraction::new(10, 3));
+        assert_eq!(parse("1_2.3(4_5)"), Fraction::new(12_222, 990));
+
+        // 20-digit repetend: 0.(00000000000000000001) = 1 / (10^20 - 1)
+        let nines = num_traits::pow(BigInt::from(10), 20) - 1;
+        assert_eq!(parse("0.(00000000000000000001)"), Fraction::from_big_ints(BigInt::one(), nines));
+
+        // More digits than an f64 holds stay exact
+        assert_eq!(
+            parse("0.123456789012345678"),
+            Fraction::from_big_ints(BigInt::from(123_456_789_012_345_678i64), BigInt::from(10i64.pow(18)))
+        );
+        assert_eq!(parse("2.5e-3"), Fraction::new(1, 400));
+    }
+
     #[test]
     fn test_from_f64() {
         let a = Fraction::from_f64(0.5);
@@ -468,6 +2080,73 @@ mod tests {
         assert_eq!(c.to_f64(), -1.5);
     }
 
+    #[test]
+    fn test_from_f64_finds_simplest_fraction() {
+        assert_eq!(Fraction::from_f64(1.0 / 7.0), Fraction::new(1, 7));
+        assert_eq!(Fraction::from_f64(-2.0 / 3.0), Fraction::new(-2, 3));
+        assert_eq!(Fraction::from_f64(0.1), Fraction::new(1, 10));
+        assert_eq!(Fraction::from_f64(123_456.0), Fraction::new(123_456, 1));
+        assert_eq!(Fraction::from_f64(f64::NAN), Fraction::new(0, 1));
+
+        // 142857142857/10^12 needs too large a denominator; 1/7 is the best that fits
+        assert_eq!(Fraction::from_f64(0.142857142857), Fraction::new(1, 7));
+        assert_eq!(Fraction::from_f64_with(0.142857142857, 1e-10, 1000), Fraction::new(1, 7));
+        assert_eq!(Fraction::from_f64_with(0.142857, 0.0, 1_000_000), Fraction::new(142_857, 1_000_000));
+        assert_eq!(Fraction::from_f64_with(0.3, 0.05, 1000), Fraction::new(1, 3));
+        // No fraction within tolerance fits max_den: best approximation instead
+        assert_eq!(Fraction::from_f64_with(std::f64::consts::PI, 0.0, 1000), Fraction::new(355, 113));
+    }
+
+    #[test]
+    fn test_from_f64_round_trips() {
+        let mut state = 0xD1B5_4A32_D192_ED03u64;
+        let mut next = || crate::xorshift(&mut state);
+        for _ in 0..2000 {
+            let q = (next() % 1_000_000) as i64 + 1;
+            let p = (next() % 2_000_000_000) as i64 - 1_000_000_000;
+            let x = p as f64 / q as f64;
+            let fraction = Fraction::from_f64(x);
+            assert_eq!(fraction.to_f64(), x, "{}/{}", p, q);
+            if x.abs() < 1000.0 {
+                // Fractions with denominators up to 10^6 are far apart at this magnitude
+                assert_eq!(fraction, Fraction::new_raw(p, q), "{}/{}", p, q);
+            }
+        }
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let half = Fraction::new(1, 2);
+        let third = Fraction::new(1, 3);
+        assert!(third.lt(&half) && third.lte(&half) && !third.gt(&half) && !third.gte(&half));
+        assert!(half.lte(&half) && half.gte(&half) && half.eq_js(&Fraction::new(2, 4)));
+        assert!(!half.lt(&half) && !half.gt(&half));
+    }
+
+    #[test]
+    fn test_compare_to_number() {
+        let three = Fraction::new(3, 1);
+        assert_eq!(three.compare_to_number(3.0), Some(0));
+        assert_eq!(three.compare_to_number(2.0), Some(1));
+        assert_eq!(three.compare_to_number(4.0), Some(-1));
+        assert_eq!(three.compare_to_number(2.999_999_999_9), Some(1));
+        assert_eq!(three.compare_to_number(3.000_000_000_1), Some(-1));
+
+        let half = Fraction::new(-1, 2);
+        assert_eq!(half.compare_to_number(-0.5), Some(0));
+        assert_eq!(half.compare_to_number(-0.499_999_999_999), Some(-1));
+        assert_eq!(half.compare_to_number(-0.500_000_000_001), Some(1));
+
+        let third = Fraction::new(1, 3);
+        assert_eq!(third.compare_to_number(1.0 / 3.0), Some(0));
+        assert_eq!(third.compare_to_number(0.333_333_333_333), Some(1));
+        assert_eq!(Fraction::new(1, 10).compare_to_number(0.1), Some(0));
+
+        assert_eq!(third.compare_to_number(f64::INFINITY), Some(-1));
+        assert_eq!(third.compare_to_number(f64::NEG_INFINITY), Some(1));
+        assert_eq!(third.compare_to_number(f64::NAN), None);
+    }
+
     #[test]
     fn test_sign_components() {
         let pos = Fraction::new(3, 4);
@@ -484,6 +2163,114 @@ mod tests {
         assert_eq!(zero.s(), 0);
     }
 
+    #[test]
+    fn test_wide_accessors() {
+        let small = Fraction::new(-3, 4);
+        assert_eq!((small.n_u64(), small.d_u64()), (Some(3), Some(4)));
+        assert!(small.fits_u32() && small.fits_i32());
+        assert_eq!(small.to_parts(), (-1, "3".to_string(), "4".to_string()));
+
+        let wide = Fraction::new_raw(-5_000_000_000, 7);
+        assert_eq!(wide.n(), u32::MAX);
+        assert_eq!(wide.n_u64(), Some(5_000_000_000));
+        assert!(!wide.fits_u32() && !wide.fits_i32());
+        assert!(Fraction::new_raw(3_000_000_000, 1).fits_u32());
+        assert!(!Fraction::new_raw(3_000_000_000, 1).fits_i32());
+        assert!(Fraction::new_raw(i32::MIN as i64, 1).fits_i32());
+
+        // 100-digit numerator and denominator
+        let digits = "7".repeat(100);
+        let big: Fraction = format!("-{}/{}1", digits, "3".repeat(99)).parse().unwrap();
+        assert_eq!((big.n_u64(), big.d_u64()), (None, None));
+        assert!(!big.fits_u32() && !big.fits_i32());
+        assert_eq!(big.to_parts(), (-1, digits, format!("{}1", "3".repeat(99))));
+    }
+
+    #[test]
+    fn test_numerator_strings_carry_sign() {
+        let neg = Fraction::new(-3, 4);
+        assert_eq!((neg.numerator_str().as_str(), neg.numerator_abs_str().as_str()), ("-3", "3"));
+        assert_eq!(neg.denominator_str(), "4");
+        // Sign written on the denominator is normalized onto the numerator
+        assert_eq!(Fraction::new(3, -4).numerator_str(), "-3");
+        assert_eq!(Fraction::new(3, -4).denominator_str(), "4");
+        assert_eq!(Fraction::new(0, -5).numerator_str(), "0");
+
+        let digits = "12345678901234567890".repeat(3);
+        let big: Fraction = format!("-{}/11", digits).parse().unwrap();
+        assert_eq!(big.numerator_str(), format!("-{}", digits));
+        assert_eq!(big.numerator_abs_str(), digits);
+        assert_eq!(big.denominator_str(), "11");
+        assert_eq!(format!("{}/{}", big.numerator_str(), big.denominator_str()).parse::<Fraction>(), Ok(big));
+    }
+
+    #[test]
+    fn test_serde_json_round_trip() {
+        let big = Fraction::from_big_ints(-(BigInt::one() << 200u32) - 1, BigInt::from(3));
+        let json = serde_json::to_string(&big).unwrap();
+        assert_eq!(json, format!(r#"{{"n":"{}","d":"3","s":-1}}"#, big.numerator_abs_str()));
+        assert_eq!(serde_json::from_str::<Fraction>(&json).unwrap(), big);
+
+        let zero = serde_json::to_string(&Fraction::new(0, 1)).unwrap();
+        assert_eq!(zero, r#"{"n":"0","d":"1","s":1}"#);
+
+        assert_eq!(serde_json::from_str::<Fraction>(r#""-3/4""#).unwrap(), Fraction::new(-3, 4));
+        assert_eq!(serde_json::from_str::<Fraction>(r#"{"n":"6","d":"8","s":1}"#).unwrap(), Fraction::new(3, 4));
+        assert!(serde_json::from_str::<Fraction>(r#"{"n":"1","d":"0","s":1}"#).is_err());
+        assert!(serde_json::from_str::<Fraction>(r#"{"n":"-1","d":"2","s":1}"#).is_err());
+        assert!(serde_json::from_str::<Fraction>(r#""1/0""#).is_err());
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    fn test_serde_wasm_bindgen_round_trip() {
+        let big = Fraction::from_big_ints((BigInt::one() << 200u32) + 1, BigInt::from(7));
+        let js = serde_wasm_bindgen::to_value(&big).unwrap();
+        assert_eq!(serde_wasm_bindgen::from_value::<Fraction>(js).unwrap(), big);
+
+        let text = JsValue::from_str("1 1/2");
+        assert_eq!(serde_wasm_bindgen::from_value::<Fraction>(text).unwrap(), Fraction::new(3, 2));
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    fn test_js_big_int_round_trip() {
+        let num: js_sys::BigInt = "-170141183460469231731687303715884105727".parse().unwrap();
+        let den: js_sys::BigInt = "340282366920938463463374607431768211297".parse().unwrap();
+        let f = Fraction::from_js_big_ints(num.clone(), den.clone()).unwrap();
+        assert_eq!(f.numerator_str(), "-170141183460469231731687303715884105727");
+        assert_eq!(f.numerator_big_int(), num);
+        assert_eq!(f.denominator_big_int(), den);
+
+        // Reduced on construction, and the sign moves to the numerator
+        let (six, minus_four) = (js_sys::BigInt::from(6), js_sys::BigInt::from(-4));
+        let f = Fraction::from_js_big_ints(six, minus_four).unwrap();
+        assert_eq!(f.numerator_big_int(), js_sys::BigInt::from(-3));
+        assert_eq!(f.denominator_big_int(), js_sys::BigInt::from(2));
+
+        let err = Fraction::from_js_big_ints(js_sys::BigInt::from(1), js_sys::BigInt::from(0));
+        let code = js_sys::Reflect::get(&err.unwrap_err(), &"code".into()).unwrap();
+        assert_eq!(code, "DIVISION_BY_ZERO");
+    }
+
+    #[test]
+    fn test_checked_division() {
+        assert_eq!(Fraction::try_new(3, -6), Ok(Fraction::new(-1, 2)));
+        assert_eq!(Fraction::try_new(5, 0), Err(FractionError::DivisionByZero));
+        assert_eq!(Fraction::try_new(0, 0), Err(FractionError::Indeterminate));
+        assert_eq!(FractionError::DivisionByZero.code(), "DIVISION_BY_ZERO");
+
+        let zero = Fraction::new(0, 1);
+        assert_eq!(Fraction::new(1, 2).try_div(&Fraction::new(1, 4)), Ok(Fraction::new(2, 1)));
+        assert_eq!(Fraction::new(1, 2).try_div(&zero), Err(FractionError::DivisionByZero));
+        assert_eq!(zero.try_div(&zero), Err(FractionError::Indeterminate));
+
+        // Permissive paths are unchanged
+        assert_eq!(Fraction::new(5, 0), zero);
+        assert_eq!(Fraction::div(&Fraction::new(1, 2), &zero), Fraction::new(1, 1));
+        assert!("1/0".parse::<Fraction>().unwrap_err().ends_with("division by zero"));
+    }
+
     #[test]
     fn test_auto_reduction() {
         let a = Fraction::new(2, 4);
@@ -497,8 +2284,678 @@ mod tests {
     fn test_division_by_zero() {
         let a = Fraction::new(1, 1);
         let zero = Fraction::new(0, 1);
-        let result = (&a).div(&zero);
+        let result = Fraction::div(&a, &zero);
         // Should return 1 (matching JS behavior)
         assert_eq!(result.to_f64(), 1.0);
     }
+
+    #[test]
+    fn test_pow_int() {
+        let three_halves = Fraction::new(3, 2);
+        assert_eq!(three_halves.pow_int(0), Fraction::new(1, 1));
+        assert_eq!(three_halves.pow_int(3), Fraction::new(27, 8));
+        assert_eq!(three_halves.pow_int(-2), Fraction::new(4, 9));
+        assert_eq!(Fraction::new(-2, 1).pow(3), Fraction::new(-8, 1));
+
+        // 0^negative follows the div-by-zero policy
+        assert_eq!(Fraction::new(0, 1).pow_int(-3), Fraction::new(1, 1));
+        assert_eq!(Fraction::new(0, 1).pow_int(5), Fraction::new(0, 1));
+    }
+
+    #[test]
+    fn test_pow_int_overflows_i64() {
+        // 3^100 / 2^100 needs far more than 64 bits
+        let big = Fraction::new(3, 2).pow_int(100);
+        let expected_num = (0..100).fold(BigInt::from(1), |acc, _| acc * 3);
+        let expected_den = BigInt::from(1) << 100;
+        assert_eq!(big, Fraction::from_big_ints(expected_num, expected_den));
+
+        // Raising and inverting round-trips exactly
+        assert_eq!(Fraction::mul(&big, &Fraction::new(3, 2).pow_int(-100)), Fraction::new(1, 1));
+        assert_eq!(Fraction::new(2, 1).pow_int(64).numerator_str(), "18446744073709551616");
+    }
+
+    #[test]
+    fn test_integer_rounding() {
+        let cases = [
+            // (value, floor, ceil, round, trunc)
+            ((7, 2), 3, 4, 4, 3),
+            ((-7, 2), -4, -3, -4, -3),
+            ((5, 3), 1, 2, 2, 1),
+            ((-5, 3), -2, -1, -2, -1),
+            ((6, 1), 6, 6, 6, 6),
+            ((-1, 4), -1, 0, 0, 0),
+        ];
+        for ((n, d), floor, ceil, round, trunc) in cases {
+            let x = Fraction::new(n, d);
+            assert_eq!(x.floor(), Fraction::from_int(floor), "floor({}/{})", n, d);
+            assert_eq!(x.ceil(), Fraction::from_int(ceil), "ceil({}/{})", n, d);
+            assert_eq!(x.round(), Fraction::from_int(round), "round({}/{})", n, d);
+            assert_eq!(x.trunc(), Fraction::from_int(trunc), "trunc({}/{})", n, d);
+        }
+
+        // Numerators far beyond i64
+        let huge = Fraction::new(7, 3).pow_int(80);
+        assert_eq!(huge.floor().d(), 1);
+        assert_eq!(Fraction::sub(&huge.ceil(), &huge.floor()), Fraction::new(1, 1));
+    }
+
+    #[test]
+    fn test_round_to_step() {
+        let beat = Fraction::new(1, 4);
+        assert_eq!(Fraction::new(5, 12).floor_to(&beat), Fraction::new(1, 4));
+        assert_eq!(Fraction::new(5, 12).ceil_to(&beat), Fraction::new(1, 2));
+        assert_eq!(Fraction::new(5, 12).round_to(&beat), Fraction::new(1, 2));
+        assert_eq!(Fraction::new(-3, 8).round_to(&beat), Fraction::new(-1, 2));
+        assert_eq!(Fraction::new(2, 3).round_to(&Fraction::new(0, 1)), Fraction::new(2, 3));
+    }
+
+    #[test]
+    fn test_rem_sign_follows_dividend() {
+        let third = Fraction::new(1, 3);
+        assert_eq!(Fraction::new(7, 6).rem(&third), Fraction::new(1, 6));
+        assert_eq!(Fraction::new(-7, 6).rem(&third), Fraction::new(-1, 6));
+        assert_eq!(Fraction::new(7, 6).rem(&Fraction::new(-1, 3)), Fraction::new(1, 6));
+        assert_eq!(Fraction::new(-7, 6).rem(&Fraction::new(-1, 3)), Fraction::new(-1, 6));
+        assert_eq!(Fraction::new(2, 3).rem(&third), Fraction::new(0, 1));
+        assert_eq!(Fraction::new(5, 4).rem(&Fraction::new(0, 1)), Fraction::new(5, 4));
+    }
+
+    #[test]
+    fn test_min_max() {
+        let a = Fraction::new(2, 3);
+        let b = Fraction::new(3, 5);
+        assert_eq!(Fraction::min(&a, &b), b);
+        assert_eq!(Fraction::max(&a, &b), a);
+        assert_eq!(Fraction::min(&a.neg(), &b), Fraction::new(-2, 3));
+    }
+
+    #[test]
+    fn test_limit_denominator() {
+        assert_eq!(Fraction::new(4_999_999, 10_000_000).limit_denominator(100), Fraction::new(1, 2));
+        assert_eq!(Fraction::new(-4_999_999, 10_000_000).limit_denominator(100), Fraction::new(-1, 2));
+        // 355/113 is the best approximation of pi's digits below 1000
+        assert_eq!(Fraction::new(314_159_265, 100_000_000).limit_denominator(1000), Fraction::new(355, 113));
+        assert_eq!(Fraction::new(314_159_265, 100_000_000).limit_denominator(10), Fraction::new(22, 7));
+        // Already within the limit: unchanged
+        assert_eq!(Fraction::new(7, 12).limit_denominator(12), Fraction::new(7, 12));
+        assert_eq!(Fraction::new(7, 12).limit_denominator(0), Fraction::new(1, 1));
+    }
+
+    #[test]
+    fn test_limit_denominator_is_best() {
+        // Compare against a brute-force search over every denominator
+        let mut state = 0x5851_F42D_4C95_7F2Du64;
+        let mut next = || crate::xorshift(&mut state);
+        for _ in 0..200 {
+            let x = Fraction::new_raw((next() % 2_000_000) as i64 - 1_000_000, (next() % 99_999) as i64 + 2);
+            let max_den = next() % 60 + 1;
+            let error = |f: &Fraction| Fraction::sub(f, &x).abs();
+
+            let best_error = (1..=max_den as i64)
+                .map(|d| {
+                    let den = Fraction::new_raw(d, 1);
+                    error(&Fraction::div(&Fraction::mul(&x, &den).round(), &den))
+                })
+                .min()
+                .unwrap();
+            let limited = x.limit_denominator(max_den);
+            assert!(limited.d() as u64 <= max_den, "{} -> {}", x, limited);
+            assert_eq!(error(&limited), best_error, "{} limited to {}", x, max_den);
+        }
+    }
+
+    #[test]
+    fn test_floor_ceil_bracket_value() {
+        let mut state = 0x853C_49E6_748F_EA9Bu64;
+        let mut next = || crate::xorshift(&mut state);
+        for _ in 0..1000 {
+            let num = next() as i64 >> 8;
+            let den = (next() % 100_000) as i64 + 1;
+            let x = Fraction::new_raw(num, den);
+            assert!(x.floor() <= x && x <= x.ceil(), "{}", x);
+            assert!(Fraction::sub(&x.ceil(), &x.floor()) <= Fraction::new(1, 1));
+            assert!(Fraction::sub(&x, &x.round()).abs() <= Fraction::new(1, 2));
+        }
+    }
+
+    /// Reference result computed entirely with BigRational
+    fn assert_matches(result: &Fraction, expected: BigRational, what: &str) {
+        let fits = expected.numer().to_i64().is_some() && expected.denom().to_i64().is_some();
+        assert_eq!(*result.as_big_rational(), expected, "{}", what);
+        let small = matches!(result.repr, Repr::Small { .. });
+        assert_eq!(small, fits, "{} stored non-canonically", what);
+    }
+
+    #[test]
+    fn test_small_and_big_paths_agree() {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = || crate::xorshift(&mut state);
+        let sample = |next: &mut dyn FnMut() -> u64| -> Fraction {
+            match next() % 4 {
+                0 => Fraction::new_raw(next() as i64 % 1000, (next() % 1000) as i64 + 1),
+                // Near the i64 limits, where products overflow
+                1 => Fraction::new_raw(i64::MAX - (next() % 8) as i64, (next() % 8) as i64 + 1),
+                2 => {
+                    let num = i64::MIN + (next() % 8) as i64;
+                    Fraction::new_raw(num, i64::MAX - (next() % 8) as i64)
+                }
+                _ => Fraction::from_big_ints(
+                    BigInt::from(next() as i64) * BigInt::from(next()),
+                    BigInt::from(next() | 1),
+                ),
+            }
+        };
+
+        for _ in 0..2000 {
+            let (x, y) = (sample(&mut next), sample(&mut next));
+            let (bx, by) = (x.to_big_rational().into_owned(), y.to_big_rational().into_owned());
+            let pair = format!("{} and {}", x, y);
+            assert_matches(&Fraction::add(&x, &y), &bx + &by, &pair);
+            assert_matches(&Fraction::sub(&x, &y), &bx - &by, &pair);
+            assert_matches(&Fraction::mul(&x, &y), &bx * &by, &pair);
+            assert_matches(&Fraction::neg(&x), -&bx, &pair);
+            if !y.is_zero() {
+                assert_matches(&Fraction::div(&x, &y), &bx / &by, &pair);
+                assert_matches(&x.rem(&y), &bx % &by, &pair);
+            }
+            if !x.is_zero() {
+                assert_matches(&x.inverse(), bx.recip(), &pair);
+            }
+            assert_eq!(x.cmp(&y), bx.cmp(&by), "{}", pair);
+            assert_eq!(x.to_f64(), bx.to_f64().unwrap(), "{}", pair);
+            assert_eq!(x.to_string_repr(), bx.to_string(), "{}", pair);
+        }
+    }
+
+    #[test]
+    fn test_overflow_promotes_and_cancellation_demotes() {
+        let max = Fraction::new_raw(i64::MAX, 1);
+        let one = Fraction::new(1, 1);
+        let promoted = Fraction::add(&max, &one);
+        assert!(matches!(promoted.repr, Repr::Big(_)));
+        assert_eq!(promoted.to_string_repr(), "9223372036854775808");
+
+        let demoted = Fraction::sub(&promoted, &one);
+        assert!(matches!(demoted.repr, Repr::Small { .. }));
+        assert_eq!(demoted, max);
+
+        let min = Fraction::new_raw(i64::MIN, 1);
+        let negated = Fraction::neg(&min);
+        assert_eq!(negated.to_string_repr(), "9223372036854775808");
+        assert_eq!(Fraction::neg(&negated), min);
+        assert_eq!(Fraction::new_raw(1, i64::MIN).to_string_repr(), "-1/9223372036854775808");
+    }
+
+    #[test]
+    fn test_small_path_avoids_big_rational() {
+        let widenings = |run: &mut dyn FnMut()| {
+            let before = WIDENINGS.with(|count| count.get());
+            run();
+            WIDENINGS.with(|count| count.get()) - before
+        };
+
+        // 10,000 additions of 1/3 stay inline
+        let third = Fraction::new(1, 3);
+        let mut sum = Fraction::zero();
+        let count = widenings(&mut || {
+            for _ in 0..10_000 {
+                sum = Fraction::add(&sum, &third);
+                sum = Fraction::mul(&sum, &Fraction::one());
+                sum = Fraction::sub(&sum, &Fraction::zero());
+            }
+        });
+        assert_eq!(count, 0);
+        assert_eq!(sum, Fraction::new(10_000, 3));
+
+        // A sum that outgrows i64 is promoted without widening; only inline
+        // operands added to it afterwards are widened, once each
+        let primes = [1_000_003, 1_000_033, 1_000_037, 1_000_039, 1_000_081, 1_000_099];
+        let mut big_sum = Fraction::zero();
+        let count = widenings(&mut || {
+            for prime in primes {
+                big_sum = Fraction::add(&big_sum, &Fraction::new(1, prime));
+            }
+        });
+        assert_eq!(count, 2);
+        let mut expected = BigRational::zero();
+        for prime in primes {
+            expected += BigRational::new(BigInt::one(), BigInt::from(prime));
+        }
+        assert_eq!(*big_sum.as_big_rational(), expected);
+    }
+
+    #[test]
+    fn test_as_big_rational_is_built_once() {
+        let half = Fraction::new(1, 2);
+        let first: *const BigRational = half.as_big_rational();
+        let count = WIDENINGS.with(|count| count.get());
+        assert_eq!(half.as_big_rational() as *const BigRational, first);
+        assert_eq!(WIDENINGS.with(|count| count.get()), count);
+        assert_eq!(*half.as_big_rational(), BigRational::new(BigInt::one(), BigInt::from(2)));
+
+        // The kept copy is not part of the value
+        assert_eq!(half.clone(), half);
+        assert_eq!(half, Fraction::new(2, 4));
+        let hash = |f: &Fraction| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            f.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&half), hash(&Fraction::new(2, 4)));
+        assert!(matches!(half.to_big_rational(), Cow::Owned(_)));
+        let huge = Fraction::from_big_ints(BigInt::from(u64::MAX) * 3, BigInt::one());
+        assert!(std::ptr::eq(huge.as_big_rational(), &*huge.to_big_rational()));
+    }
+
+    #[test]
+    fn test_complexity_metrics() {
+        let cases = [((3, 2), 3, 3), ((7, 4), 7, 7), ((81, 64), 81, 3), ((45, 32), 45, 5)];
+        for ((n, d), odd, prime) in cases {
+            let f = Fraction::new(n, d);
+            assert_eq!(f.odd_limit(), Some(odd), "{}", f);
+            assert_eq!(f.prime_limit(), Some(prime), "{}", f);
+            assert_eq!(f.tenney_height(), ((n * d) as f64).log2(), "{}", f);
+        }
+        assert_eq!(Fraction::new(-10, 9).prime_limit(), Some(5));
+        assert_eq!(Fraction::new(1, 1).prime_limit(), Some(1));
+        assert_eq!(Fraction::new(1, 1).tenney_height(), 0.0);
+        assert_eq!(Fraction::new(0, 1).odd_limit(), None);
+        assert_eq!(Fraction::new(0, 1).prime_limit(), None);
+    }
+
+    #[test]
+    fn test_prime_limit_bound() {
+        // 65537 is prime
+        let f = Fraction::new(65537, 2);
+        assert_eq!(f.prime_limit(), None);
+        assert_eq!(f.prime_limit_with(70_000), Some(65537));
+        assert_eq!(Fraction::new(11 * 13, 7).prime_limit_with(11), None);
+        assert_eq!(Fraction::new(11 * 13, 7).prime_limit_with(13), Some(13));
+
+        let power = Fraction::new(3, 2).pow_int(200);
+        assert_eq!(power.prime_limit(), Some(3));
+        assert_eq!(power.odd_limit(), None);
+        assert!((power.tenney_height() - 200.0 * 6f64.log2()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_from_f64_exact() {
+        let tenth = Fraction::from_f64_exact(0.1).unwrap();
+        assert_eq!(tenth.d_u64(), Some(1 << 55));
+        assert_eq!(tenth.numerator_str(), "3602879701896397");
+        assert!(tenth.is_exactly(0.1));
+        assert!(!Fraction::from_f64(0.1).is_exactly(0.1));
+        assert_eq!(Fraction::from_f64(0.1), Fraction::new(1, 10));
+
+        assert_eq!(Fraction::from_f64_exact(-0.75).unwrap(), Fraction::new(-3, 4));
+        assert_eq!(Fraction::from_f64_exact(-0.0).unwrap(), Fraction::new(0, 1));
+        assert_eq!(Fraction::from_f64_exact(1e20).unwrap().to_string_repr(), "100000000000000000000");
+        let tiny = Fraction::from_f64_exact(f64::from_bits(1)).unwrap();
+        assert_eq!(tiny.denominator_str(), (BigInt::one() << 1074usize).to_string());
+        assert!(tiny.is_exactly(5e-324));
+
+        assert_eq!(Fraction::from_f64_exact(f64::NAN), Err(FractionError::NotFinite));
+        assert_eq!(Fraction::from_f64_exact(f64::NEG_INFINITY), Err(FractionError::NotFinite));
+        assert!(!Fraction::new(0, 1).is_exactly(f64::NAN));
+    }
+
+    #[test]
+    fn test_from_f64_exact_round_trips() {
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let mut next = || crate::xorshift(&mut state);
+        for _ in 0..1000 {
+            let value = f64::from_bits(next());
+            match Fraction::from_f64_exact(value) {
+                Ok(exact) => {
+                    assert_eq!(exact.to_f64(), value, "{:e}", value);
+                    assert!(exact.is_exactly(value), "{:e}", value);
+                }
+                Err(_) => assert!(!value.is_finite()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_continued_fraction() {
+        assert_eq!(Fraction::new(355, 113).continued_fraction(), vec![3, 7, 16]);
+        assert_eq!(Fraction::new(-7, 4).continued_fraction(), vec![-2, 4]);
+        assert_eq!(Fraction::new(5, 1).continued_fraction(), vec![5]);
+        assert_eq!(Fraction::new(0, 1).continued_fraction(), vec![0]);
+    }
+
+    #[test]
+    fn test_convergents() {
+        let text = |fs: Vec<Fraction>| fs.iter().map(|f| f.to_string_repr()).collect::<Vec<_>>();
+        assert_eq!(text(Fraction::new(355, 113).convergents(10)), ["3", "22/7", "355/113"]);
+        assert_eq!(text(Fraction::new(-7, 4).convergents(10)), ["-2", "-7/4"]);
+
+        let pi = Fraction::from_f64_exact(std::f64::consts::PI).unwrap();
+        let convergents = text(pi.convergents(5));
+        assert_eq!(convergents, ["3", "22/7", "333/106", "355/113", "103993/33102"]);
+        assert_eq!(pi.convergents(usize::MAX).last(), Some(&pi));
+        assert!(pi.convergents(0).is_empty());
+    }
+
+    #[test]
+    fn test_convergents_match_limit_denominator() {
+        // Every convergent is a best approximation for its own denominator
+        let pi = Fraction::from_f64_exact(std::f64::consts::PI).unwrap();
+        for c in pi.convergents(12) {
+            assert_eq!(pi.limit_denominator(c.d_u64().unwrap()), c);
+        }
+    }
+
+    #[test]
+    fn test_batch_arithmetic() {
+        let thirds = vec![Fraction::new(1, 3); 10_000];
+        assert_eq!(Fraction::sum(&thirds), Fraction::new(10_000, 3));
+        assert_eq!(Fraction::product(&thirds[..4]), Fraction::new(1, 81));
+        assert_eq!(Fraction::sum(&[]), Fraction::new(0, 1));
+        assert_eq!(Fraction::product(&[]), Fraction::new(1, 1));
+
+        let durations = [Fraction::new(1, 4), Fraction::new(1, 8), Fraction::new(3, 8)];
+        let ends: Vec<String> =
+            Fraction::cumulative_sum(&durations).iter().map(|f| f.to_string_repr()).collect();
+        assert_eq!(ends, ["1/4", "3/8", "3/4"]);
+    }
+
+    #[test]
+    fn test_batch_entries_name_bad_index() {
+        let parse = |json: &str| {
+            let entries: Vec<serde_json::Value> = serde_json::from_str(json).unwrap();
+            collect_indexed(entries.into_iter().map(serde_json::from_value::<Fraction>))
+        };
+
+        let mixed = parse(r#"[{"s":1,"n":1,"d":3}, "2/3", {"s":-1,"n":"1","d":"6"}]"#).unwrap();
+        assert_eq!(Fraction::sum(&mixed), Fraction::new(5, 6));
+
+        let thirds = format!("[{}]", vec![r#"{"s":1,"n":1,"d":3}"#; 10_000].join(","));
+        assert_eq!(Fraction::sum(&parse(&thirds).unwrap()), Fraction::new(10_000, 3));
+
+        let err = parse(r#"["1/2", "3/4", "x/5"]"#).unwrap_err();
+        assert!(err.starts_with("Invalid fraction at index 2:"), "{}", err);
+        let err = parse(r#"["1/2", {"s":1,"n":1}]"#).unwrap_err();
+        assert!(err.starts_with("Invalid fraction at index 1:"), "{}", err);
+    }
+
+    #[test]
+    fn test_zero_one_and_integers() {
+        assert!(Fraction::zero().is_zero());
+        assert!(Fraction::one().is_one());
+        assert_eq!(Fraction::zero(), Fraction::new(0, 5));
+        assert_eq!(Fraction::one(), Fraction::new(-3, -3));
+
+        let two = Fraction::new(6, 3);
+        assert!(two.is_integer());
+        assert_eq!(two.to_i64(), Some(2));
+        assert_eq!(Fraction::new(-8, 4).to_i64(), Some(-2));
+        assert!(!Fraction::new(7, 4).is_integer());
+        assert_eq!(Fraction::new(7, 4).to_i64(), None);
+
+        let huge = Fraction::from_big_ints(BigInt::one() << 100u32, BigInt::one());
+        assert!(huge.is_integer());
+        assert_eq!(huge.to_i64(), None);
+        let edge = Fraction::new_raw(i64::MIN, 1);
+        assert_eq!(edge.to_i64(), Some(i64::MIN));
+        assert_eq!(Fraction::neg(&edge).to_i64(), None);
+        assert!(!Fraction::from_big_ints(BigInt::one(), BigInt::one() << 100u32).is_integer());
+    }
+
+    #[test]
+    fn test_quantize_modes() {
+        let x = Fraction::new(7, 24);
+        let eighth = Fraction::new(1, 8);
+        assert_eq!(x.quantize(&eighth, QuantizeMode::Nearest), Fraction::new(1, 4));
+        assert_eq!(x.quantize(&eighth, QuantizeMode::Floor), Fraction::new(1, 4));
+        assert_eq!(x.quantize(&eighth, QuantizeMode::Ceil), Fraction::new(3, 8));
+
+        let neg = Fraction::new(-7, 24);
+        assert_eq!(neg.quantize(&eighth, QuantizeMode::Nearest), Fraction::new(-1, 4));
+        assert_eq!(neg.quantize(&eighth, QuantizeMode::Floor), Fraction::new(-3, 8));
+        assert_eq!(neg.quantize(&eighth, QuantizeMode::Ceil), Fraction::new(-1, 4));
+        assert_eq!(x.quantize(&Fraction::new(-1, 8), QuantizeMode::Floor), Fraction::new(1, 4));
+
+        // Triplet grid stays exact
+        let triplet = Fraction::new(1, 12);
+        let snapped = Fraction::new(3, 10).quantize(&triplet, QuantizeMode::Nearest);
+        assert_eq!(snapped, Fraction::new(1, 3));
+        assert!(snapped.is_multiple_of(&triplet));
+        assert_eq!(x.quantize(&Fraction::zero(), QuantizeMode::Ceil), x);
+    }
+
+    #[test]
+    fn test_is_multiple_of() {
+        let triplet = Fraction::new(1, 12);
+        assert!(Fraction::new(5, 12).is_multiple_of(&triplet));
+        assert!(Fraction::new(-1, 4).is_multiple_of(&triplet));
+        assert!(!Fraction::new(1, 8).is_multiple_of(&triplet));
+        assert!(Fraction::zero().is_multiple_of(&Fraction::zero()));
+        assert!(!Fraction::one().is_multiple_of(&Fraction::zero()));
+
+        assert_eq!("floor".parse(), Ok(QuantizeMode::Floor));
+        assert!("up".parse::<QuantizeMode>().unwrap_err().contains("'up'"));
+    }
+
+    #[test]
+    fn test_decimal_string() {
+        let decimal = |n, d, digits| Fraction::new(n, d).to_decimal_string(digits);
+        assert_eq!(decimal(1, 3, 10), "0.(3)");
+        assert_eq!(decimal(1, 6, 10), "0.1(6)");
+        assert_eq!(decimal(1, 7, 10), "0.(142857)");
+        assert_eq!(decimal(22, 7, 10), "3.(142857)");
+        assert_eq!(decimal(1, 7, 4), "0.1428…");
+        assert_eq!(decimal(-1, 3, 10), "-0.(3)");
+        assert_eq!(decimal(-22, 7, 3), "-3.142…");
+        assert_eq!(decimal(3, 8, 10), "0.375");
+        assert_eq!(decimal(3, 8, 2), "0.37…");
+        assert_eq!(decimal(5, 1, 10), "5");
+        assert_eq!(decimal(2, 3, 0), "0.…");
+
+        // Repetends round-trip through the parser
+        for (n, d) in [(1, 3), (1, 6), (22, 7), (-5, 12), (3, 8)] {
+            let f = Fraction::new(n, d);
+            assert_eq!(f.to_decimal_string(20).parse::<Fraction>(), Ok(f));
+        }
+    }
+
+    #[test]
+    fn test_mixed_string() {
+        assert_eq!(Fraction::new(3, 2).to_mixed_string(), "1 1/2");
+        assert_eq!(Fraction::new(-11, 4).to_mixed_string(), "-2 3/4");
+        assert_eq!(Fraction::new(1, 2).to_mixed_string(), "1/2");
+        assert_eq!(Fraction::new(-1, 2).to_mixed_string(), "-1/2");
+        assert_eq!(Fraction::new(4, 1).to_mixed_string(), "4");
+        assert_eq!("-2 3/4".parse::<Fraction>(), Ok(Fraction::new(-11, 4)));
+    }
+
+    #[test]
+    fn test_mediant() {
+        assert_eq!(Fraction::new(1, 2).mediant(&Fraction::new(2, 3)), Fraction::new(3, 5));
+        // Uses reduced forms: 2/4 is 1/2
+        assert_eq!(Fraction::new(2, 4).mediant(&Fraction::new(1, 1)), Fraction::new(2, 3));
+        assert_eq!(Fraction::new(-1, 2).mediant(&Fraction::new(1, 3)), Fraction::zero());
+    }
+
+    #[test]
+    fn test_farey_neighbors() {
+        let pair = |f: &Fraction, max_den| {
+            let (below, above) = f.farey_neighbors(max_den);
+            (below.to_string_repr(), above.to_string_repr())
+        };
+        let s = |a: &str, b: &str| (a.to_string(), b.to_string());
+        assert_eq!(pair(&Fraction::new(5, 8), 5), s("3/5", "2/3"));
+        // Members of the sequence get their strict neighbors
+        assert_eq!(pair(&Fraction::new(3, 5), 5), s("1/2", "2/3"));
+        assert_eq!(pair(&Fraction::new(2, 1), 4), s("7/4", "9/4"));
+        assert_eq!(pair(&Fraction::new(-5, 8), 5), s("-2/3", "-3/5"));
+        assert_eq!(pair(&Fraction::new(1, 3), 1), s("0", "1"));
+    }
+
+    #[test]
+    fn test_farey_neighbors_large_orders() {
+        let pi = Fraction::from_f64_exact(std::f64::consts::PI).unwrap();
+        for max_den in [1u64, 7, 113, 1_000, 1_000_000_000] {
+            let (below, above) = pi.farey_neighbors(max_den);
+            assert!(below < pi && pi < above, "{}", max_den);
+            assert!(below.d_u64().unwrap() <= max_den && above.d_u64().unwrap() <= max_den);
+            // Adjacent: a consecutive Farey pair has determinant 1 and a
+            // mediant beyond the order
+            let dens = Fraction::new_raw((below.d() as i64) * (above.d() as i64), 1);
+            assert_eq!(Fraction::mul(&Fraction::sub(&above, &below), &dens), Fraction::one());
+            assert!(below.mediant(&above).d_u64().unwrap() > max_den);
+        }
+    }
+
+    #[test]
+    fn test_abs_diff_and_approx_eq() {
+        let (a, b) = (Fraction::new(1, 3), Fraction::new(3, 8));
+        assert_eq!(a.abs_diff(&b), Fraction::new(1, 24));
+        assert_eq!(b.abs_diff(&a), Fraction::new(1, 24));
+
+        assert!(a.approx_eq(&b, &Fraction::new(1, 24)));
+        assert!(!a.approx_eq(&b, &Fraction::new(1, 25)));
+        assert!(a.approx_eq(&a, &Fraction::zero()));
+        assert!(!a.approx_eq(&a, &Fraction::new(-1, 100)));
+
+        // Near the i64 limits the products overflow and the exact path is used
+        let big = Fraction::new_raw(i64::MAX, i64::MAX - 1);
+        let next = Fraction::new_raw(i64::MAX - 2, i64::MAX - 3);
+        let gap = big.abs_diff(&next);
+        assert!(big.approx_eq(&next, &gap));
+        assert!(!big.approx_eq(&next, &Fraction::div(&gap, &Fraction::new(2, 1))));
+        let huge = Fraction::from_big_ints(BigInt::one() << 100u32, BigInt::from(3));
+        let tenth = Fraction::new(1, 10);
+        assert!(huge.approx_eq(&Fraction::add(&huge, &tenth), &tenth));
+    }
+
+    #[test]
+    fn test_fraction_js_json_fixture() {
+        const FIXTURE: &str = include_str!("../fixtures/fraction_js_objects.json");
+        let fixture: serde_json::Value = serde_json::from_str(FIXTURE).unwrap();
+        for case in fixture["cases"].as_array().unwrap() {
+            let source = case["source"].as_str().unwrap();
+            let expected: Fraction = case["value"].as_str().unwrap().parse().unwrap();
+
+            let parsed = Fraction::from_json_value(&case["fractionJs"]).unwrap();
+            assert_eq!(parsed, expected, "{}", source);
+
+            let json = parsed.to_json_value();
+            let wasm = if case["wasm"].is_null() { &case["fractionJs"] } else { &case["wasm"] };
+            assert_eq!(&json, wasm, "{}", source);
+            assert_eq!(Fraction::from_json_value(&json).unwrap(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_from_json_value_errors() {
+        let parse = |json: &str| Fraction::from_json_value(&serde_json::from_str(json).unwrap());
+        assert_eq!(parse(r#""1 1/2""#), Ok(Fraction::new(3, 2)));
+        assert!(parse(r#"{"s":1,"n":1,"d":0}"#).is_err());
+        // An inexact number needs its string fallback
+        assert!(parse(r#"{"s":1,"n":1.5e20,"d":1}"#).is_err());
+        let exact = parse(r#"{"s":-1,"n":1.5e20,"d":1,"nStr":"150000000000000000001"}"#);
+        assert_eq!(exact.unwrap().to_string_repr(), "-150000000000000000001");
+    }
+
+    #[test]
+    fn test_simplify() {
+        // fraction.js results for the same inputs
+        let pi = Fraction::from_f64_exact(std::f64::consts::PI).unwrap();
+        let e = Fraction::from_f64_exact(std::f64::consts::E).unwrap();
+        let cases = [
+            (pi.clone(), 1e-3, Fraction::new(333, 106)),
+            (pi.clone(), 1e-2, Fraction::new(22, 7)),
+            (pi.clone(), 1e-6, Fraction::new(355, 113)),
+            (Fraction::neg(&pi), 1e-2, Fraction::new(-22, 7)),
+            (Fraction::from_f64_exact(std::f64::consts::SQRT_2).unwrap(), 1e-3, Fraction::new(41, 29)),
+            (e.clone(), 1e-3, Fraction::new(87, 32)),
+            (e, 1e-4, Fraction::new(193, 71)),
+            (Fraction::new(1, 1000), 0.01, Fraction::zero()),
+            (Fraction::new(333, 1000), 0.001, Fraction::new(1, 3)),
+            (Fraction::new(-7, 4), 10.0, Fraction::new(-1, 1)),
+            (Fraction::new(5, 1), 0.5, Fraction::new(5, 1)),
+            (Fraction::zero(), 0.5, Fraction::zero()),
+        ];
+        for (value, tolerance, expected) in cases {
+            assert_eq!(value.simplify(tolerance), expected, "{} within {}", value, tolerance);
+        }
+
+        // eps || 0.001, and a negative tolerance matches nothing
+        assert_eq!(pi.simplify(0.0), Fraction::new(333, 106));
+        assert_eq!(pi.simplify(f64::NAN), Fraction::new(333, 106));
+        assert_eq!(pi.simplify(-1.0), pi);
+    }
+
+    #[test]
+    fn test_factorize() {
+        let factors = |n, d| Fraction::new(n, d).factorize(DEFAULT_PRIME_BOUND);
+        assert_eq!(factors(45, 32), Some(vec![(2, -5), (3, 2), (5, 1)]));
+        assert_eq!(factors(-81, 64), Some(vec![(2, -6), (3, 4)]));
+        assert_eq!(factors(7, 12), Some(vec![(2, -2), (3, -1), (7, 1)]));
+        assert_eq!(factors(1, 1), Some(vec![]));
+        assert_eq!(factors(0, 1), None);
+        assert_eq!(Fraction::new(26, 1).factorize(7), None);
+        assert_eq!(Fraction::new(26, 1).factorize(13), Some(vec![(2, 1), (13, 1)]));
+
+        // A 60-digit prime stops at the bound instead of being factored
+        let prime: BigInt =
+            "622288097498926496141095869268883999563096063592498055290461".parse().unwrap();
+        let huge = Fraction::from_big_ints(prime * 6, BigInt::one());
+        assert_eq!(huge.factorize(1000), None);
+
+        let power = Fraction::new(3, 2).pow_int(100);
+        assert_eq!(power.factorize(10), Some(vec![(2, -100), (3, 100)]));
+    }
+
+    #[test]
+    fn test_power_predicates() {
+        assert!(Fraction::new(8, 1).is_power_of(2));
+        assert!(Fraction::new(1, 16).is_power_of(2));
+        assert!(Fraction::new(1, 1).is_power_of(2));
+        assert!(!Fraction::new(6, 1).is_power_of(2));
+        assert!(!Fraction::new(3, 2).is_power_of(2));
+        assert!(!Fraction::new(-8, 1).is_power_of(2));
+        assert!(Fraction::new(1, 81).is_power_of(3));
+        assert!(Fraction::new(1, 81).is_power_of(9));
+        assert!(!Fraction::new(1, 27).is_power_of(9));
+        assert!(Fraction::new(2, 1).pow_int(200).is_power_of(2));
+        assert!(Fraction::zero().is_power_of(0) && !Fraction::zero().is_power_of(2));
+
+        assert!(Fraction::new(3, 8).is_dyadic());
+        assert!(Fraction::new(5, 1).is_dyadic());
+        assert!(!Fraction::new(1, 3).is_dyadic());
+        assert!(Fraction::from_f64_exact(0.1).unwrap().is_dyadic());
+        assert!(Fraction::new(1, 2).pow_int(300).is_dyadic());
+        assert!(!Fraction::new(1, 6).pow_int(300).is_dyadic());
+    }
+
+    #[test]
+    fn test_simplest_between() {
+        let simplest = |a: (i32, i32), b: (i32, i32)| {
+            Fraction::simplest_between(&Fraction::new(a.0, a.1), &Fraction::new(b.0, b.1))
+        };
+        assert_eq!(simplest((1493, 1000), (1504, 1000)), Fraction::new(3, 2));
+        assert_eq!(simplest((1504, 1000), (1493, 1000)), Fraction::new(3, 2));
+        assert_eq!(simplest((5, 7), (5, 7)), Fraction::new(5, 7));
+        assert_eq!(simplest((7, 2), (9, 2)), Fraction::new(4, 1));
+        assert_eq!(simplest((-1, 2), (1, 2)), Fraction::zero());
+    }
+
+    #[test]
+    fn test_octave_reduce() {
+        let reduce = |n, d| Fraction::new(n, d).octave_reduce();
+        assert_eq!(reduce(9, 4), Some((Fraction::new(9, 8), 1)));
+        assert_eq!(reduce(1, 3), Some((Fraction::new(4, 3), -2)));
+        assert_eq!(reduce(3, 2), Some((Fraction::new(3, 2), 0)));
+        assert_eq!(reduce(1, 1), Some((Fraction::one(), 0)));
+        assert_eq!(reduce(2, 1), Some((Fraction::one(), 1)));
+        assert_eq!(reduce(1, 2), Some((Fraction::one(), -1)));
+        assert_eq!(reduce(0, 1), None);
+        assert_eq!(reduce(-3, 2), None);
+
+        let (ratio, octaves) = Fraction::new(3, 1).pow_int(100).octave_reduce().unwrap();
+        assert_eq!(octaves, 158);
+        assert!(ratio >= Fraction::one() && ratio < Fraction::new(2, 1));
+    }
 }
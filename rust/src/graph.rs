@@ -4,6 +4,7 @@
 //! with efficient BFS traversal and topological sorting.
 
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -89,7 +90,7 @@ impl DependencyGraph {
             if !old_deps.contains(new_dep) {
                 self.dependents
                     .entry(*new_dep)
-                    .or_insert_with(HashSet::new)
+                    .or_default()
                     .insert(note_id);
             }
         }
@@ -279,6 +280,21 @@ impl DependencyGraph {
         cycles
     }
 
+    /// Groups of notes that depend on each other, directly or through other
+    /// notes of the group, each sorted
+    ///
+    /// Unlike `detect_cycles`, every note on a cycle is in exactly one group.
+    pub fn cyclic_components(&self) -> Vec<Vec<u32>> {
+        let mut components = cyclic_components(self.dependencies.keys().copied(), |id| {
+            self.dependencies.get(&id).into_iter().flatten().copied().collect()
+        });
+        for component in &mut components {
+            component.sort_unstable();
+        }
+        components.sort_unstable();
+        components
+    }
+
     /// Get evaluation order (topological sort of given notes)
     pub fn get_evaluation_order(&self, note_ids: &HashSet<u32>) -> Vec<u32> {
         let mut in_degree: HashMap<u32, usize> = HashMap::new();
@@ -321,6 +337,82 @@ impl DependencyGraph {
         result
     }
 
+    /// Group notes into evaluation levels (topological layers)
+    ///
+    /// Every note's dependencies within `note_ids` are in earlier levels, so
+    /// the notes of one level can be evaluated independently. Each level is
+    /// sorted; notes on a cycle are omitted, as in `get_evaluation_order`.
+    pub fn get_evaluation_levels(&self, note_ids: &HashSet<u32>) -> Vec<Vec<u32>> {
+        let mut in_degree: HashMap<u32, usize> = note_ids
+            .iter()
+            .map(|id| {
+                let deps = self.dependencies.get(id);
+                let count = deps.map_or(0, |d| d.iter().filter(|d| note_ids.contains(d)).count());
+                (*id, count)
+            })
+            .collect();
+
+        let mut level: Vec<u32> = in_degree
+            .iter()
+            .filter(|(_, &deg)| deg == 0)
+            .map(|(&id, _)| id)
+            .collect();
+        let mut levels = Vec::new();
+
+        while !level.is_empty() {
+            level.sort();
+            let mut next = Vec::new();
+            for id in &level {
+                for dep in self.dependents.get(id).into_iter().flatten() {
+                    if let Some(deg) = in_degree.get_mut(dep) {
+                        *deg -= 1;
+                        if *deg == 0 {
+                            next.push(*dep);
+                        }
+                    }
+                }
+            }
+            levels.push(std::mem::replace(&mut level, next));
+        }
+
+        levels
+    }
+
+    /// Estimate memory held by the graph's indexes
+    ///
+    /// Estimates are based on allocated capacity, so they drop after `compact()`.
+    pub fn memory_stats(&self) -> GraphMemoryStats {
+        let set_bytes = |set: &HashSet<u32>| set.capacity() * (std::mem::size_of::<u32>() + 1);
+        let index_bytes = |map: &HashMap<u32, HashSet<u32>>| {
+            map.capacity() * (std::mem::size_of::<(u32, HashSet<u32>)>() + 1)
+                + map.values().map(set_bytes).sum::<usize>()
+        };
+
+        let edge_count = self.dependencies.values().map(|deps| deps.len()).sum();
+        let estimated_bytes = index_bytes(&self.dependencies)
+            + index_bytes(&self.dependents)
+            + set_bytes(&self.base_note_dependents);
+
+        GraphMemoryStats {
+            node_count: self.dependencies.len(),
+            edge_count,
+            estimated_bytes,
+        }
+    }
+
+    /// Release unused capacity in all indexes
+    pub fn compact(&mut self) {
+        for deps in self.dependencies.values_mut() {
+            deps.shrink_to_fit();
+        }
+        for deps in self.dependents.values_mut() {
+            deps.shrink_to_fit();
+        }
+        self.dependencies.shrink_to_fit();
+        self.dependents.shrink_to_fit();
+        self.base_note_dependents.shrink_to_fit();
+    }
+
     /// Get statistics about the graph
     pub fn stats(&self) -> GraphStats {
         let mut total_deps = 0;
@@ -351,6 +443,75 @@ impl DependencyGraph {
     }
 }
 
+/// Strongly connected components with more than one node of the graph
+/// with `nodes` and `edges` (Tarjan's algorithm, without recursion so long
+/// chains cannot overflow the stack)
+pub(crate) fn cyclic_components<N: Copy + Eq + Hash>(
+    nodes: impl IntoIterator<Item = N>,
+    edges: impl Fn(N) -> Vec<N>,
+) -> Vec<Vec<N>> {
+    // Visit index and lowest index reachable, by node
+    let mut indices: HashMap<N, (usize, usize)> = HashMap::new();
+    let mut stack = Vec::new();
+    let mut on_stack = HashSet::new();
+    let mut components = Vec::new();
+
+    for root in nodes {
+        if indices.contains_key(&root) {
+            continue;
+        }
+        // Nodes being visited, with their successors and the next to follow
+        let index = indices.len();
+        indices.insert(root, (index, index));
+        let mut work = vec![(root, edges(root), 0)];
+        stack.push(root);
+        on_stack.insert(root);
+
+        while let Some((node, successors, next)) = work.last_mut() {
+            let node = *node;
+            if let Some(&successor) = successors.get(*next) {
+                *next += 1;
+                match indices.get(&successor) {
+                    None => {
+                        let index = indices.len();
+                        indices.insert(successor, (index, index));
+                        stack.push(successor);
+                        on_stack.insert(successor);
+                        work.push((successor, edges(successor), 0));
+                    }
+                    Some(&(index, _)) if on_stack.contains(&successor) => {
+                        let low = &mut indices.get_mut(&node).unwrap().1;
+                        *low = (*low).min(index);
+                    }
+                    Some(_) => {}
+                }
+                continue;
+            }
+
+            work.pop();
+            let (index, low) = indices[&node];
+            if let Some(&(parent, _, _)) = work.last() {
+                let parent_low = &mut indices.get_mut(&parent).unwrap().1;
+                *parent_low = (*parent_low).min(low);
+            }
+            if low == index {
+                let mut component = Vec::new();
+                while let Some(member) = stack.pop() {
+                    on_stack.remove(&member);
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                if component.len() > 1 {
+                    components.push(component);
+                }
+            }
+        }
+    }
+    components
+}
+
 /// Statistics about the dependency graph
 #[derive(Clone, Serialize, Deserialize)]
 pub struct GraphStats {
@@ -368,6 +529,32 @@ pub struct GraphStats {
     pub base_note_dependents: usize,
 }
 
+/// Estimated memory usage of the dependency graph
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct GraphMemoryStats {
+    #[serde(rename = "nodeCount")]
+    pub node_count: usize,
+    #[serde(rename = "edgeCount")]
+    pub edge_count: usize,
+    #[serde(rename = "estimatedBytes")]
+    pub estimated_bytes: usize,
+}
+
+/// Bulk sync input from JavaScript
+#[derive(Deserialize)]
+pub(crate) struct GraphSyncData {
+    pub(crate) notes: Vec<GraphSyncNote>,
+}
+
+/// A single note's dependencies in a bulk sync
+#[derive(Deserialize)]
+pub(crate) struct GraphSyncNote {
+    pub(crate) id: u32,
+    pub(crate) deps: Vec<u32>,
+    #[serde(rename = "referencesBase")]
+    pub(crate) references_base: bool,
+}
+
 // WASM bindings for JavaScript interop
 
 #[wasm_bindgen]
@@ -422,6 +609,13 @@ impl DependencyGraph {
         self.get_evaluation_order(&note_set)
     }
 
+    /// Get evaluation levels for given note IDs as an array of arrays
+    #[wasm_bindgen(js_name = getEvaluationLevels)]
+    pub fn get_evaluation_levels_js(&self, note_ids: &[u32]) -> JsValue {
+        let note_set: HashSet<u32> = note_ids.iter().copied().collect();
+        serde_wasm_bindgen::to_value(&self.get_evaluation_levels(&note_set)).unwrap_or(JsValue::NULL)
+    }
+
     /// Detect cycles and return them as a serialized value
     #[wasm_bindgen(js_name = detectCycles)]
     pub fn detect_cycles_js(&self) -> JsValue {
@@ -435,6 +629,18 @@ impl DependencyGraph {
         self.has_dependency_path(source, target)
     }
 
+    /// Get estimated memory usage as a JavaScript object
+    #[wasm_bindgen(js_name = memoryStats)]
+    pub fn memory_stats_js(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.memory_stats()).unwrap_or(JsValue::NULL)
+    }
+
+    /// Release unused capacity from JavaScript
+    #[wasm_bindgen(js_name = compact)]
+    pub fn compact_js(&mut self) {
+        self.compact();
+    }
+
     /// Get graph statistics as a JavaScript object
     #[wasm_bindgen(js_name = getStats)]
     pub fn get_stats_js(&self) -> JsValue {
@@ -445,20 +651,7 @@ impl DependencyGraph {
     /// Bulk sync from JavaScript data
     #[wasm_bindgen(js_name = syncFromJs)]
     pub fn sync_from_js(&mut self, data: JsValue) -> Result<(), JsValue> {
-        #[derive(Deserialize)]
-        struct SyncData {
-            notes: Vec<NoteData>,
-        }
-
-        #[derive(Deserialize)]
-        struct NoteData {
-            id: u32,
-            deps: Vec<u32>,
-            #[serde(rename = "referencesBase")]
-            references_base: bool,
-        }
-
-        let sync_data: SyncData = serde_wasm_bindgen::from_value(data)
+        let sync_data: GraphSyncData = serde_wasm_bindgen::from_value(data)
             .map_err(|e| JsValue::from_str(&e.to_string()))?;
 
         // Clear existing data
@@ -544,6 +737,22 @@ mod tests {
         assert!(pos_2 < pos_3);
     }
 
+    #[test]
+    fn test_evaluation_levels() {
+        let mut graph = DependencyGraph::new();
+
+        // Diamond 1 -> {2, 3} -> 4, plus 5 independent and 6 on a cycle with 7
+        graph.update_dependencies(2, [1].into_iter().collect(), false);
+        graph.update_dependencies(3, [1].into_iter().collect(), false);
+        graph.update_dependencies(4, [2, 3].into_iter().collect(), false);
+        graph.update_dependencies(6, [7].into_iter().collect(), false);
+        graph.update_dependencies(7, [6].into_iter().collect(), false);
+
+        let note_ids: HashSet<u32> = (1..=7).collect();
+        let levels = graph.get_evaluation_levels(&note_ids);
+        assert_eq!(levels, vec![vec![1, 5], vec![2, 3], vec![4]]);
+    }
+
     #[test]
     fn test_cycle_detection() {
         let mut graph = DependencyGraph::new();
@@ -557,6 +766,28 @@ mod tests {
         assert!(!cycles.is_empty());
     }
 
+    #[test]
+    fn test_cyclic_components() {
+        let mut graph = DependencyGraph::new();
+
+        // 1 <-> 2, 3 -> 4 -> 5 -> 3 with 6 hanging off 5, and an acyclic 7 -> 1
+        graph.update_dependencies(1, [2].into_iter().collect(), false);
+        graph.update_dependencies(2, [1].into_iter().collect(), false);
+        graph.update_dependencies(3, [5].into_iter().collect(), false);
+        graph.update_dependencies(4, [3].into_iter().collect(), false);
+        graph.update_dependencies(5, [4, 6].into_iter().collect(), false);
+        graph.update_dependencies(7, [1].into_iter().collect(), false);
+
+        assert_eq!(graph.cyclic_components(), vec![vec![1, 2], vec![3, 4, 5]]);
+
+        // A long acyclic chain has none, without exhausting the stack
+        let mut chain = DependencyGraph::new();
+        for id in 1..100_000 {
+            chain.update_dependencies(id, [id - 1].into_iter().collect(), false);
+        }
+        assert!(chain.cyclic_components().is_empty());
+    }
+
     #[test]
     fn test_remove_note() {
         let mut graph = DependencyGraph::new();
@@ -5,15 +5,37 @@
 //! - Binary expression evaluation (stack-based bytecode interpreter)
 //! - Dependency graph algorithms (BFS, topological sort)
 //! - Expression compilation (text to bytecode)
+//! - Pluggable logging (see `log`)
+//! - JavaScript-compatible number formatting (see `format`)
+//! - Memory usage reporting (see `memory`)
+//! - Frequency, MIDI and cents conversions (see `tuning`)
+//! - Note names for frequencies (see `pitch`)
+//! - Transpose and time-stretch over registered bytecode (see `transform`)
+//! - Module file import (see `module_json`)
+//! - Compact binary cache snapshots (see `codec`)
+//! - Built-in self-test and micro-benchmark (see `diagnostics`)
+//! - TypeScript declarations for serialized data (see `ts`, behind the `ts` feature)
 
 use wasm_bindgen::prelude::*;
 
+#[macro_use]
+pub mod log;
 pub mod fraction;
 pub mod bytecode;
 pub mod evaluator;
 pub mod graph;
 pub mod compiler;
 pub mod value;
+pub mod format;
+pub mod memory;
+pub mod tuning;
+pub mod pitch;
+pub mod transform;
+pub mod module_json;
+pub mod codec;
+pub mod diagnostics;
+#[cfg(any(test, feature = "ts"))]
+pub mod ts;
 
 // Re-export main types for convenience
 pub use fraction::Fraction;
@@ -22,6 +44,15 @@ pub use graph::DependencyGraph;
 pub use compiler::ExpressionCompiler;
 pub use value::{Value, ValueData};
 
+/// Advance a xorshift64 state and return it (deterministic randomized tests)
+#[cfg(test)]
+pub(crate) fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
 /// Initialize the WASM module
 /// Call this once when loading the module to set up panic hooks
 #[wasm_bindgen(start)]
@@ -36,6 +67,45 @@ pub fn version() -> String {
     env!("CARGO_PKG_VERSION").to_string()
 }
 
+/// Run the built-in self-test suite and micro-benchmark
+/// Returns { version, passed, failures, timingsMs }
+#[wasm_bindgen(js_name = runDiagnostics)]
+pub fn run_diagnostics() -> JsValue {
+    serde_wasm_bindgen::to_value(&diagnostics::run_diagnostics()).unwrap_or(JsValue::NULL)
+}
+
+/// Forward log messages to a JavaScript callback
+/// The callback receives (level: string, message: string)
+#[wasm_bindgen(js_name = setLogCallback)]
+pub fn set_log_callback(f: js_sys::Function) {
+    log::set_sink(Box::new(move |level, message| {
+        let _ = f.call2(
+            &JsValue::NULL,
+            &JsValue::from_str(level.as_str()),
+            &JsValue::from_str(message),
+        );
+    }));
+}
+
+/// Remove the JavaScript log callback (messages are discarded again)
+#[wasm_bindgen(js_name = clearLogCallback)]
+pub fn clear_log_callback() {
+    log::clear_sink();
+}
+
+/// Set the most verbose log level: "error", "warn", "debug", or "off"
+#[wasm_bindgen(js_name = setLogLevel)]
+pub fn set_log_level(level: &str) -> Result<(), JsValue> {
+    if level.trim().eq_ignore_ascii_case("off") {
+        log::set_max_level(None);
+        return Ok(());
+    }
+    let parsed = log::LogLevel::from_name(level)
+        .ok_or_else(|| JsValue::from_str(&format!("Unknown log level: {}", level)))?;
+    log::set_max_level(Some(parsed));
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,188 @@
+//! Lightweight logging facade
+//!
+//! Routes diagnostic messages from the compiler and evaluator to a
+//! pluggable sink instead of writing to stderr (which is lost in the browser).
+//!
+//! - The default sink is a no-op, so nothing is printed unless a sink is installed
+//! - In WASM builds, `setLogCallback` forwards messages to a JavaScript function
+//! - In native builds, `stderr_sink` can be installed to restore console output
+//!
+//! State is thread-local: WASM is single-threaded, and native tests can
+//! install their own capturing sink without interfering with each other.
+
+use std::cell::{Cell, RefCell};
+
+/// Severity of a log message, ordered from most to least severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+    /// Something failed and a fallback value was used
+    Error = 0,
+    /// Unexpected input that was handled
+    Warn = 1,
+    /// Verbose diagnostics for development
+    Debug = 2,
+}
+
+impl LogLevel {
+    /// Get the level name as a string
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Debug => "debug",
+        }
+    }
+
+    /// Parse a level name ("error", "warn", "debug")
+    pub fn from_name(name: &str) -> Option<LogLevel> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "error" => Some(LogLevel::Error),
+            "warn" | "warning" => Some(LogLevel::Warn),
+            "debug" => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
+}
+
+/// A log sink receives every message at or above the current level
+pub type LogSink = Box<dyn Fn(LogLevel, &str)>;
+
+thread_local! {
+    static SINK: RefCell<Option<LogSink>> = const { RefCell::new(None) };
+    static MAX_LEVEL: Cell<Option<LogLevel>> = const { Cell::new(Some(LogLevel::Warn)) };
+}
+
+/// Install a sink, replacing any previous one
+pub fn set_sink(sink: LogSink) {
+    SINK.with(|s| *s.borrow_mut() = Some(sink));
+}
+
+/// Remove the current sink (restores the default no-op behavior)
+pub fn clear_sink() {
+    SINK.with(|s| *s.borrow_mut() = None);
+}
+
+/// Set the most verbose level that will be emitted (None silences everything)
+pub fn set_max_level(level: Option<LogLevel>) {
+    MAX_LEVEL.with(|l| l.set(level));
+}
+
+/// Get the most verbose level that will be emitted
+pub fn max_level() -> Option<LogLevel> {
+    MAX_LEVEL.with(|l| l.get())
+}
+
+/// Check whether a message at `level` would be emitted
+///
+/// Used by the logging macros to skip formatting when nobody is listening.
+pub fn enabled(level: LogLevel) -> bool {
+    match max_level() {
+        Some(max) if level <= max => SINK.with(|s| s.borrow().is_some()),
+        _ => false,
+    }
+}
+
+/// Send a message to the current sink
+pub fn emit(level: LogLevel, message: &str) {
+    if !enabled(level) {
+        return;
+    }
+    SINK.with(|s| {
+        if let Some(sink) = s.borrow().as_ref() {
+            sink(level, message);
+        }
+    });
+}
+
+/// Sink that writes "[rmt-core level] message" lines to stderr
+#[cfg(not(target_arch = "wasm32"))]
+pub fn stderr_sink() -> LogSink {
+    Box::new(|level, message| eprintln!("[rmt-core {}] {}", level.as_str(), message))
+}
+
+/// Log an error-level message
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::LogLevel::Error) {
+            $crate::log::emit($crate::log::LogLevel::Error, &format!($($arg)*));
+        }
+    };
+}
+
+/// Log a warning-level message
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::LogLevel::Warn) {
+            $crate::log::emit($crate::log::LogLevel::Warn, &format!($($arg)*));
+        }
+    };
+}
+
+/// Log a debug-level message
+#[allow(unused_macros)]
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        if $crate::log::enabled($crate::log::LogLevel::Debug) {
+            $crate::log::emit($crate::log::LogLevel::Debug, &format!($($arg)*));
+        }
+    };
+}
+
+#[cfg(test)]
+pub(crate) mod test_support {
+    use super::*;
+    use std::rc::Rc;
+
+    /// Install a sink that records every message, returning the shared buffer
+    pub fn capture() -> Rc<RefCell<Vec<(LogLevel, String)>>> {
+        let records = Rc::new(RefCell::new(Vec::new()));
+        let sink_records = Rc::clone(&records);
+        set_sink(Box::new(move |level, message| {
+            sink_records.borrow_mut().push((level, message.to_string()));
+        }));
+        records
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_sink_is_silent() {
+        clear_sink();
+        assert!(!enabled(LogLevel::Error));
+        // Must not panic without a sink
+        emit(LogLevel::Error, "nobody is listening");
+    }
+
+    #[test]
+    fn test_level_filtering() {
+        let records = test_support::capture();
+        set_max_level(Some(LogLevel::Warn));
+
+        log_error!("e {}", 1);
+        log_warn!("w {}", 2);
+        log_debug!("d {}", 3);
+        assert_eq!(records.borrow().len(), 2);
+
+        set_max_level(Some(LogLevel::Debug));
+        log_debug!("d {}", 4);
+        assert_eq!(records.borrow().last().unwrap(), &(LogLevel::Debug, "d 4".to_string()));
+
+        set_max_level(None);
+        log_error!("silenced");
+        assert_eq!(records.borrow().len(), 3);
+
+        set_max_level(Some(LogLevel::Warn));
+        clear_sink();
+    }
+
+    #[test]
+    fn test_level_names() {
+        assert_eq!(LogLevel::from_name("WARN"), Some(LogLevel::Warn));
+        assert_eq!(LogLevel::from_name("debug"), Some(LogLevel::Debug));
+        assert_eq!(LogLevel::from_name("verbose"), None);
+        assert_eq!(LogLevel::Error.as_str(), "error");
+    }
+}
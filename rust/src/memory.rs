@@ -0,0 +1,142 @@
+//! Memory usage reporting
+//!
+//! Combines the WASM linear memory size with per-component estimates from
+//! PersistentEvaluator and DependencyGraph. Estimates are capacity-based and
+//! computed without evaluating anything, so the report is cheap enough to
+//! poll from a UI timer.
+
+use crate::evaluator::{EvaluatorMemoryStats, PersistentEvaluator};
+use crate::graph::{DependencyGraph, GraphMemoryStats};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// Size of a WASM memory page in bytes
+pub const WASM_PAGE_SIZE: usize = 65536;
+
+/// Combined memory report
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct MemoryReport {
+    /// WASM memory pages currently allocated (0 in native builds)
+    #[serde(rename = "wasmPages")]
+    pub wasm_pages: usize,
+    /// WASM linear memory size in bytes (0 in native builds)
+    #[serde(rename = "wasmBytes")]
+    pub wasm_bytes: usize,
+    /// Estimated usage of the persistent evaluator
+    pub evaluator: EvaluatorMemoryStats,
+    /// Estimated usage of the dependency graph
+    pub graph: GraphMemoryStats,
+    /// Sum of the component estimates
+    #[serde(rename = "totalEstimatedBytes")]
+    pub total_estimated_bytes: usize,
+}
+
+impl MemoryReport {
+    /// Build a report from the given component handles
+    pub fn collect(evaluator: &PersistentEvaluator, graph: &DependencyGraph) -> Self {
+        let wasm_pages = wasm_memory_pages();
+        let evaluator = evaluator.memory_stats();
+        let graph = graph.memory_stats();
+        let total_estimated_bytes = evaluator.total_bytes + graph.estimated_bytes;
+
+        MemoryReport {
+            wasm_pages,
+            wasm_bytes: wasm_pages * WASM_PAGE_SIZE,
+            evaluator,
+            graph,
+            total_estimated_bytes,
+        }
+    }
+}
+
+/// Number of WASM memory pages in use
+#[cfg(target_arch = "wasm32")]
+pub fn wasm_memory_pages() -> usize {
+    core::arch::wasm32::memory_size::<0>()
+}
+
+/// Number of WASM memory pages in use (always 0 outside WASM)
+#[cfg(not(target_arch = "wasm32"))]
+pub fn wasm_memory_pages() -> usize {
+    0
+}
+
+/// Get a memory report for an evaluator and graph as a JavaScript object
+#[wasm_bindgen(js_name = memoryReport)]
+pub fn memory_report(evaluator: &PersistentEvaluator, graph: &DependencyGraph) -> JsValue {
+    serde_wasm_bindgen::to_value(&MemoryReport::collect(evaluator, graph)).unwrap_or(JsValue::NULL)
+}
+
+/// Release unused capacity held by an evaluator and graph
+///
+/// WASM linear memory never shrinks, but freed capacity is reused by later
+/// allocations instead of growing the memory further.
+#[wasm_bindgen]
+pub fn compact(evaluator: &mut PersistentEvaluator, graph: &mut DependencyGraph) {
+    evaluator.compact();
+    graph.compact();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::{write_i32, write_u16, Op, Var};
+    use std::collections::HashSet;
+
+    fn ref_bytecode(dep: u32) -> Vec<u8> {
+        let mut bytecode = vec![Op::LoadRef as u8];
+        write_u16(&mut bytecode, dep as u16);
+        bytecode.push(Var::StartTime as u8);
+        bytecode.push(Op::LoadConst as u8);
+        write_i32(&mut bytecode, 1);
+        write_i32(&mut bytecode, 1);
+        bytecode.push(Op::Add as u8);
+        bytecode
+    }
+
+    #[test]
+    fn test_report_shape() {
+        let evaluator = PersistentEvaluator::new();
+        let graph = DependencyGraph::new();
+        let report = MemoryReport::collect(&evaluator, &graph);
+
+        assert_eq!(report.wasm_bytes, report.wasm_pages * WASM_PAGE_SIZE);
+        assert_eq!(report.evaluator.cache_entries, 0);
+        assert_eq!(report.graph.node_count, 0);
+        assert_eq!(
+            report.total_estimated_bytes,
+            report.evaluator.total_bytes + report.graph.estimated_bytes
+        );
+    }
+
+    #[test]
+    fn test_report_tracks_growth_and_compaction() {
+        let mut evaluator = PersistentEvaluator::new();
+        let mut graph = DependencyGraph::new();
+        let empty = MemoryReport::collect(&evaluator, &graph);
+
+        for id in 1..=2000u32 {
+            let bytecode = ref_bytecode(id - 1);
+            evaluator.register_expression(id, Var::StartTime as u8, &bytecode, bytecode.len());
+            graph.update_dependencies(id, [id - 1].into_iter().collect::<HashSet<u32>>(), false);
+        }
+        let order: Vec<u32> = (1..=2000).collect();
+        evaluator.evaluate_dirty(&order);
+
+        let loaded = MemoryReport::collect(&evaluator, &graph);
+        assert_eq!(loaded.evaluator.bytecode_entries, 2000);
+        assert_eq!(loaded.graph.edge_count, 2000);
+        assert!(loaded.evaluator.total_bytes > empty.evaluator.total_bytes);
+        assert!(loaded.graph.estimated_bytes > empty.graph.estimated_bytes);
+
+        for id in 1..=2000u32 {
+            evaluator.remove_note(id);
+            graph.remove_note(id);
+        }
+        compact(&mut evaluator, &mut graph);
+
+        let compacted = MemoryReport::collect(&evaluator, &graph);
+        assert!(compacted.evaluator.total_bytes < loaded.evaluator.total_bytes);
+        assert!(compacted.graph.estimated_bytes < loaded.graph.estimated_bytes);
+    }
+}
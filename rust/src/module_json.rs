@@ -0,0 +1,403 @@
+//! Module file import
+//!
+//! Parses a saved `.rmt` module (JSON with a `baseNote` object and a `notes`
+//! array of per-variable expression strings) in one pass, so a whole file can
+//! be handed to WASM instead of being fed in note by note from JS.
+//!
+//! Problems with individual entries (a bad id, a non-string expression, an
+//! expression that doesn't compile) are collected as errors instead of
+//! aborting the load; only a malformed document or an unsupported schema
+//! version fails outright. Unknown fields are ignored so files written by
+//! newer versions still load.
+
+use crate::bytecode::{validate, Var};
+use crate::compiler::ExpressionCompiler;
+use crate::evaluator::PersistentEvaluator;
+use crate::graph::DependencyGraph;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+use std::collections::HashSet;
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+/// Newest module schema version this build understands
+pub const MODULE_SCHEMA_VERSION: u32 = 1;
+
+/// Highest note id that fits in a LOAD_REF_WIDE operand
+const MAX_NOTE_ID: u64 = u32::MAX as u64;
+
+/// Expression variables in the order they are written to module files
+const EXPRESSION_VARS: [Var; 6] = [
+    Var::StartTime,
+    Var::Duration,
+    Var::Frequency,
+    Var::Tempo,
+    Var::BeatsPerMeasure,
+    Var::MeasureLength,
+];
+
+/// A problem found while loading a module
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModuleError {
+    /// Note the problem belongs to (0 for the base note), if known
+    #[serde(rename = "noteId")]
+    pub note_id: Option<u32>,
+    /// Variable the problem belongs to, if any
+    pub variable: Option<String>,
+    pub message: String,
+}
+
+impl ModuleError {
+    fn document(message: impl Into<String>) -> Self {
+        ModuleError { note_id: None, variable: None, message: message.into() }
+    }
+
+    fn note(note_id: Option<u32>, message: impl Into<String>) -> Self {
+        ModuleError { note_id, variable: None, message: message.into() }
+    }
+
+    fn expression(note_id: u32, var: Var, message: impl Into<String>) -> Self {
+        ModuleError {
+            note_id: Some(note_id),
+            variable: Some(var.name().to_string()),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.note_id, &self.variable) {
+            (Some(id), Some(var)) => write!(f, "note {} {}: {}", id, var, self.message),
+            (Some(id), None) => write!(f, "note {}: {}", id, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ModuleError {}
+
+/// An expression text with its (note, variable) coordinates
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModuleExpression {
+    #[serde(rename = "noteId")]
+    pub note_id: u32,
+    /// Variable name (e.g. "startTime")
+    pub variable: String,
+    pub text: String,
+}
+
+/// Non-expression properties of a note
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ModuleNote {
+    pub id: u32,
+    pub color: Option<String>,
+    pub instrument: Option<String>,
+}
+
+/// A parsed module file, ready to compile and register
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ParsedModule {
+    /// Schema version declared by the file (1 when absent)
+    pub version: u32,
+    /// Base note (id 0) followed by the notes in file order
+    pub notes: Vec<ModuleNote>,
+    pub expressions: Vec<ModuleExpression>,
+    /// Entries that were skipped
+    pub errors: Vec<ModuleError>,
+}
+
+/// Parse a module file
+///
+/// Fails only if the document isn't a JSON object with a `baseNote` object
+/// and a `notes` array, or declares a newer schema version.
+pub fn parse_module_json(json: &str) -> Result<ParsedModule, ModuleError> {
+    let root: JsonValue = serde_json::from_str(json)
+        .map_err(|e| ModuleError::document(format!("Invalid module JSON: {}", e)))?;
+    let root = root
+        .as_object()
+        .ok_or_else(|| ModuleError::document("Module JSON must be an object"))?;
+
+    let version = match root.get("version") {
+        None => 1,
+        Some(v) => v
+            .as_u64()
+            .filter(|&v| v >= 1)
+            .ok_or_else(|| ModuleError::document(format!("Invalid module version: {}", v)))?
+            as u32,
+    };
+    if version > MODULE_SCHEMA_VERSION {
+        return Err(ModuleError::document(format!(
+            "Module version {} is newer than supported version {}",
+            version, MODULE_SCHEMA_VERSION
+        )));
+    }
+
+    let base = root
+        .get("baseNote")
+        .and_then(JsonValue::as_object)
+        .ok_or_else(|| ModuleError::document("Module is missing a baseNote object"))?;
+    let notes = root
+        .get("notes")
+        .and_then(JsonValue::as_array)
+        .ok_or_else(|| ModuleError::document("Module is missing a notes array"))?;
+
+    let mut module = ParsedModule { version, ..Default::default() };
+    module.add_note(0, base);
+
+    let mut seen = HashSet::from([0]);
+    for entry in notes {
+        let fields = match entry.as_object() {
+            Some(fields) => fields,
+            None => {
+                module.errors.push(ModuleError::note(None, "Note entry is not an object"));
+                continue;
+            }
+        };
+        let id = match parse_note_id(fields.get("id")) {
+            Ok(id) => id,
+            Err(message) => {
+                module.errors.push(ModuleError::note(None, message));
+                continue;
+            }
+        };
+        if !seen.insert(id) {
+            module.errors.push(ModuleError::note(Some(id), "Duplicate note id"));
+            continue;
+        }
+        module.add_note(id, fields);
+    }
+
+    Ok(module)
+}
+
+/// Read a note id, accepting integers and integer strings (as the JS loader does)
+fn parse_note_id(value: Option<&JsonValue>) -> Result<u32, String> {
+    let id = match value {
+        Some(JsonValue::Number(n)) => n.as_u64(),
+        Some(JsonValue::String(s)) => s.trim().parse::<u64>().ok(),
+        None => return Err("Note entry has no id".to_string()),
+        _ => None,
+    };
+    match id {
+        Some(id) if (1..=MAX_NOTE_ID).contains(&id) => Ok(id as u32),
+        _ => Err(format!(
+            "Invalid note id {} (must be 1-{})",
+            value.map(|v| v.to_string()).unwrap_or_default(),
+            MAX_NOTE_ID
+        )),
+    }
+}
+
+impl ParsedModule {
+    fn add_note(&mut self, id: u32, fields: &Map<String, JsonValue>) {
+        for var in EXPRESSION_VARS {
+            let text = match fields.get(var.name()) {
+                None | Some(JsonValue::Null) => continue,
+                Some(JsonValue::String(s)) => s.clone(),
+                Some(JsonValue::Number(n)) => n.to_string(),
+                Some(other) => {
+                    self.errors.push(ModuleError::expression(
+                        id,
+                        var,
+                        format!("Expression must be a string, got {}", other),
+                    ));
+                    continue;
+                }
+            };
+            self.expressions.push(ModuleExpression {
+                note_id: id,
+                variable: var.name().to_string(),
+                text,
+            });
+        }
+
+        let text_field = |name: &str| fields.get(name).and_then(JsonValue::as_str).map(str::to_string);
+        self.notes.push(ModuleNote {
+            id,
+            color: text_field("color"),
+            instrument: text_field("instrument"),
+        });
+    }
+
+    /// Compile every expression and register it with the evaluator and graph
+    ///
+    /// Expressions that fail to compile are skipped and reported; everything
+    /// else is registered and marked dirty. Returns the errors from this step
+    /// (parse errors stay in `self.errors`).
+    pub fn register(
+        &self,
+        evaluator: &mut PersistentEvaluator,
+        graph: &mut DependencyGraph,
+    ) -> Vec<ModuleError> {
+        let mut compiler = ExpressionCompiler::new();
+        let mut errors = Vec::new();
+
+        for note in &self.notes {
+            let mut deps = HashSet::new();
+            let mut references_base = false;
+
+            for expr in self.expressions.iter().filter(|e| e.note_id == note.id) {
+                let var = match Var::from_name(&expr.variable) {
+                    Some(var) => var,
+                    None => continue,
+                };
+                let compiled = match compiler.try_compile(&expr.text) {
+                    Ok(compiled) => compiled,
+                    Err(e) => {
+                        errors.push(ModuleError::expression(note.id, var, e));
+                        continue;
+                    }
+                };
+                if let Err(e) = validate(&compiled.bytecode, compiled.bytecode.len()) {
+                    errors.push(ModuleError::expression(note.id, var, e.to_string()));
+                    continue;
+                }
+                evaluator.register_expression(
+                    note.id,
+                    var as u8,
+                    &compiled.bytecode,
+                    compiled.bytecode.len(),
+                );
+                deps.extend(compiled.dependencies);
+                references_base |= compiled.references_base;
+            }
+
+            evaluator.mark_dirty(note.id);
+            if note.id != 0 {
+                deps.remove(&note.id);
+                graph.update_dependencies(note.id, deps, references_base);
+            }
+        }
+
+        errors
+    }
+
+    /// Note ids in evaluation order (base note first)
+    pub fn evaluation_order(&self, graph: &DependencyGraph) -> Vec<u32> {
+        let ids: HashSet<u32> = self.notes.iter().map(|n| n.id).filter(|&id| id != 0).collect();
+        let mut order = vec![0];
+        order.extend(graph.get_evaluation_order(&ids));
+        order
+    }
+}
+
+/// Parse a module file into a JavaScript object
+/// ({ version, notes, expressions, errors }); throws only for fatal errors
+#[wasm_bindgen(js_name = parseModuleJson)]
+pub fn parse_module_json_js(json: &str) -> Result<JsValue, JsValue> {
+    let module = parse_module_json(json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    Ok(serde_wasm_bindgen::to_value(&module).unwrap_or(JsValue::NULL))
+}
+
+/// Load a module file into an evaluator and graph and evaluate it
+///
+/// Returns every per-entry error (parse and compile) as an array; throws only
+/// for fatal errors.
+#[wasm_bindgen(js_name = loadModuleJson)]
+pub fn load_module_json_js(
+    json: &str,
+    evaluator: &mut PersistentEvaluator,
+    graph: &mut DependencyGraph,
+) -> Result<JsValue, JsValue> {
+    let module = parse_module_json(json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+    evaluator.invalidate_all();
+    graph.clear();
+
+    let mut errors = module.errors.clone();
+    errors.extend(module.register(evaluator, graph));
+    evaluator.evaluate_dirty(&module.evaluation_order(graph));
+
+    Ok(serde_wasm_bindgen::to_value(&errors).unwrap_or(JsValue::NULL))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fraction::Fraction;
+
+    const FIXTURE: &str = include_str!("../fixtures/module_with_error.json");
+
+    fn value(evaluator: &PersistentEvaluator, id: u32, var: Var) -> Fraction {
+        evaluator
+            .cached_note(id)
+            .and_then(|note| note.get_var(var))
+            .expect("value evaluated")
+            .to_fraction()
+    }
+
+    #[test]
+    fn test_fixture_loads_around_errors() {
+        let module = parse_module_json(FIXTURE).unwrap();
+        assert_eq!(module.version, 1);
+        let ids: Vec<u32> = module.notes.iter().map(|n| n.id).collect();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4]);
+        assert_eq!(module.notes[1].color.as_deref(), Some("rgba(255,0,0,0.5)"));
+        assert_eq!(module.notes[3].instrument.as_deref(), Some("sine-wave"));
+
+        // Bad id and non-string expression
+        assert_eq!(module.errors.len(), 2);
+        assert_eq!(module.errors[0].note_id, None);
+        assert_eq!(module.errors[1].note_id, Some(4));
+        assert_eq!(module.errors[1].variable.as_deref(), Some("startTime"));
+
+        let mut evaluator = PersistentEvaluator::new();
+        let mut graph = DependencyGraph::new();
+        let errors = module.register(&mut evaluator, &mut graph);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].note_id, Some(2));
+        assert_eq!(errors[0].variable.as_deref(), Some("frequency"));
+
+        let order = module.evaluation_order(&graph);
+        assert_eq!(order[0], 0);
+        assert!(order.iter().position(|&id| id == 1) < order.iter().position(|&id| id == 3));
+        evaluator.evaluate_dirty(&order);
+
+        assert_eq!(value(&evaluator, 1, Var::Frequency), Fraction::new(660, 1));
+        assert_eq!(value(&evaluator, 2, Var::StartTime), Fraction::new(1, 2));
+        assert!(evaluator.cached_note(2).unwrap().frequency.is_none());
+        assert_eq!(value(&evaluator, 3, Var::StartTime), Fraction::new(1, 1));
+        assert_eq!(value(&evaluator, 3, Var::Duration), Fraction::new(1, 4));
+        assert_eq!(value(&evaluator, 3, Var::Frequency), Fraction::new(825, 1));
+        assert_eq!(value(&evaluator, 4, Var::Duration), Fraction::new(1, 1));
+    }
+
+    #[test]
+    fn test_wide_note_ids() {
+        let json = r#"{
+            "baseNote": { "frequency": "new Fraction(440)", "startTime": "new Fraction(0)" },
+            "notes": [
+                { "id": 100000, "startTime": "new Fraction(2)", "duration": "new Fraction(1)" },
+                { "id": 7, "startTime": "module.getNoteById(100000).getVariable('startTime').add(new Fraction(1))" },
+                { "id": 4294967296, "startTime": "new Fraction(0)" }
+            ]
+        }"#;
+        let module = parse_module_json(json).unwrap();
+        let ids: Vec<u32> = module.notes.iter().map(|n| n.id).collect();
+        assert_eq!(ids, vec![0, 100000, 7]);
+        assert_eq!(module.errors.len(), 1);
+        assert!(module.errors[0].message.contains("4294967296"));
+
+        let mut evaluator = PersistentEvaluator::new();
+        let mut graph = DependencyGraph::new();
+        assert!(module.register(&mut evaluator, &mut graph).is_empty());
+        evaluator.evaluate_dirty(&module.evaluation_order(&graph));
+        assert_eq!(value(&evaluator, 100000, Var::StartTime), Fraction::new(2, 1));
+        assert_eq!(value(&evaluator, 7, Var::StartTime), Fraction::new(3, 1));
+    }
+
+    #[test]
+    fn test_fatal_errors() {
+        assert!(parse_module_json("not json").is_err());
+        assert!(parse_module_json("[]").is_err());
+        assert!(parse_module_json(r#"{"notes": []}"#).is_err());
+        assert!(parse_module_json(r#"{"baseNote": {}}"#).is_err());
+
+        let newer = parse_module_json(r#"{"version": 2, "baseNote": {}, "notes": []}"#);
+        assert!(newer.unwrap_err().message.contains("newer"));
+
+        let empty = parse_module_json(r#"{"baseNote": {}, "notes": []}"#).unwrap();
+        assert_eq!(empty.version, 1);
+        assert!(empty.errors.is_empty());
+    }
+}
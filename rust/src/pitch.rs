@@ -0,0 +1,109 @@
+//! Note names for frequencies
+//!
+//! Labels such as "E5 +2¢" for the piano-roll overlay, computed with the
+//! same MIDI mapping as `tuning::value_to_midi`, so base-2 symbolic
+//! frequencies get exact offsets: 440·2^(7/12) is E5 with 0 cents.
+
+use crate::tuning::{a4_value, value_to_midi};
+use crate::value::{value_from_js, Value};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+/// Pitch class names, starting from C
+pub const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// Nearest named note to a frequency and the offset from it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PitchDescription {
+    /// Nearest equal-tempered MIDI note
+    #[serde(rename = "midiNote")]
+    pub midi_note: i32,
+    /// Scientific pitch name, e.g. "E5" (MIDI 60 is C4)
+    pub name: String,
+    /// Offset from that note in cents, in [-50, 50]
+    #[serde(rename = "centsOffset")]
+    pub cents_offset: f64,
+}
+
+/// "E5 +2¢": the name and the offset rounded to whole cents
+impl fmt::Display for PitchDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:+.0}¢", self.name, self.cents_offset)
+    }
+}
+
+/// Scientific pitch name of a MIDI note ("A4" for 69, "C-1" for 0)
+pub fn note_name(midi_note: i32) -> String {
+    let pitch_class = midi_note.rem_euclid(12) as usize;
+    let octave = midi_note.div_euclid(12) - 1;
+    format!("{}{}", NOTE_NAMES[pitch_class], octave)
+}
+
+/// Describe a frequency relative to the given A4
+///
+/// Exact for frequencies whose ratio to A4 is a power of two, including
+/// symbolic TET values. Non-positive frequencies give note 0 with NaN cents,
+/// as `tuning::freq_to_midi` does.
+pub fn describe_frequency(value: &Value, a4: &Value) -> PitchDescription {
+    let (midi_note, cents_offset) = value_to_midi(value, a4);
+    PitchDescription { midi_note, name: note_name(midi_note), cents_offset }
+}
+
+/// Describe a ValueData frequency as { midiNote, name, centsOffset }
+/// A4 is given in Hz (uses the default A4 when omitted)
+#[wasm_bindgen(js_name = describeFrequency)]
+pub fn describe_frequency_js(value: JsValue, a4_hz: Option<f64>) -> Result<JsValue, JsValue> {
+    let description = describe_frequency(&value_from_js(value)?, &a4_value(a4_hz));
+    serde_wasm_bindgen::to_value(&description).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn semitones_above(a4: i32, steps: i32) -> Value {
+        Value::rational(a4, 1).mul(&Value::rational(2, 1).pow(&Value::rational(steps, 12)))
+    }
+
+    #[test]
+    fn test_describe_frequency() {
+        let a4 = Value::rational(440, 1);
+
+        let a = describe_frequency(&a4, &a4);
+        assert_eq!((a.midi_note, a.name.as_str(), a.cents_offset), (69, "A4", 0.0));
+        assert_eq!(a.to_string(), "A4 +0¢");
+
+        let fifth = describe_frequency(&Value::rational(660, 1), &a4);
+        assert_eq!((fifth.midi_note, fifth.name.as_str()), (76, "E5"));
+        assert!((fifth.cents_offset - 1.955).abs() < 1e-3);
+        assert_eq!(fifth.to_string(), "E5 +2¢");
+
+        // Exact for symbolic TET values
+        let e5 = describe_frequency(&semitones_above(440, 7), &a4);
+        assert_eq!((e5.midi_note, e5.name.as_str(), e5.cents_offset), (76, "E5", 0.0));
+        let low = describe_frequency(&semitones_above(440, -58), &a4);
+        assert_eq!((low.name.as_str(), low.cents_offset), ("B-1", 0.0));
+    }
+
+    #[test]
+    fn test_configurable_a4() {
+        let a432 = Value::rational(432, 1);
+        let e5 = describe_frequency(&semitones_above(432, 7), &a432);
+        assert_eq!((e5.name.as_str(), e5.cents_offset), ("E5", 0.0));
+
+        let a = describe_frequency(&Value::rational(440, 1), &a432);
+        assert_eq!(a.name, "A4");
+        assert!((a.cents_offset - 31.767).abs() < 1e-3);
+        assert_eq!(a.to_string(), "A4 +32¢");
+    }
+
+    #[test]
+    fn test_note_names() {
+        assert_eq!(note_name(60), "C4");
+        assert_eq!(note_name(61), "C#4");
+        assert_eq!(note_name(0), "C-1");
+        assert_eq!(note_name(-1), "B-2");
+        assert_eq!(note_name(127), "G9");
+    }
+}
@@ -0,0 +1,551 @@
+//! Module-level transforms over registered bytecode
+//!
+//! Transpose and time-stretch work directly on the bytecode held by a
+//! PersistentEvaluator instead of rewriting expression text in JS. Each
+//! transform `T` maps a target expression `e` to `T(e(T⁻¹(refs)))`: every
+//! read of a transformed variable on a target note is wrapped in the
+//! inverse suffix, and the forward suffix is appended to the whole
+//! expression:
+//!
+//! - transpose: `expr * ratio`
+//! - time-stretch: `anchor + (startTime - anchor) * factor`, `duration * factor`
+//!
+//! That keeps every term of the expression correct, including constant
+//! offsets added to a reference (`[2].f + 10`, `[2].t + 1`) and durations
+//! scaled around an anchor other than 0. Expressions the transform already
+//! commutes with (`[2].f * 3/2`, `[2].t + [2].d`) follow their references
+//! unchanged and are left as-is.
+//!
+//! The undo operations strip the wrappers and suffix again when they are
+//! all still in place, restoring the original bytecode exactly; otherwise
+//! they apply the inverse transform the same way.
+//!
+//! Only bytecode changes: callers holding source text for the returned ids
+//! should refresh it (e.g. with `decompileBytecode`).
+
+use crate::bytecode::{instruction_len, note_ref_operands, validate, write_const, Op, Var};
+use crate::evaluator::PersistentEvaluator;
+use crate::fraction::Fraction;
+use std::collections::HashSet;
+use wasm_bindgen::prelude::*;
+
+/// How a value changes under a transform
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Unaffected (constants, untransformed variables)
+    Fixed,
+    /// A time point: `anchor + (x - anchor) * factor`
+    Point,
+    /// A scaled quantity: `x * factor`
+    Span,
+    /// Anything else
+    Other,
+}
+
+impl Kind {
+    fn add(self, other: Kind) -> Kind {
+        match (self, other) {
+            (Kind::Fixed, Kind::Fixed) => Kind::Fixed,
+            (Kind::Span, Kind::Span) => Kind::Span,
+            (Kind::Point, Kind::Span) | (Kind::Span, Kind::Point) => Kind::Point,
+            _ => Kind::Other,
+        }
+    }
+
+    fn sub(self, other: Kind) -> Kind {
+        match (self, other) {
+            (Kind::Fixed, Kind::Fixed) => Kind::Fixed,
+            (Kind::Span, Kind::Span) | (Kind::Point, Kind::Point) => Kind::Span,
+            (Kind::Point, Kind::Span) => Kind::Point,
+            _ => Kind::Other,
+        }
+    }
+
+    /// Result of `op` on `args` (bottom of the stack first) for arithmetic
+    /// instructions; transforms always scale by a positive factor
+    fn apply(op: Op, args: &[Kind]) -> Kind {
+        match (op, args) {
+            (Op::Add, [a, b]) => a.add(*b),
+            (Op::Sub, [a, b]) => a.sub(*b),
+            (Op::Mul, [Kind::Span, Kind::Fixed] | [Kind::Fixed, Kind::Span]) => Kind::Span,
+            (Op::Div, [Kind::Span, Kind::Fixed]) => Kind::Span,
+            (Op::Neg | Op::Abs, [Kind::Span]) => Kind::Span,
+            (Op::Min | Op::Max, [a, b]) if a == b && *a != Kind::Other => *a,
+            _ if args.iter().all(|k| *k == Kind::Fixed) => Kind::Fixed,
+            _ => Kind::Other,
+        }
+    }
+}
+
+/// Instructions appended to one variable's expression
+struct Edit {
+    var: Var,
+    /// How the variable's value changes
+    kind: Kind,
+    suffix: Vec<u8>,
+    inverse: Vec<u8>,
+}
+
+/// Note id and variable read by the instruction at `pc`, if it is a
+/// LOAD_REF, LOAD_REF_WIDE or LOAD_BASE (the base note is note 0)
+fn read_var(bytecode: &[u8], pc: usize, len: usize) -> Option<(u32, Var)> {
+    let op = Op::from_byte(bytecode[pc])?;
+    let operands = &bytecode[pc + 1..pc + len];
+    let (id, index) = match op {
+        Op::LoadBase => (0, *operands.first()?),
+        _ => note_ref_operands(op, operands)?,
+    };
+    Some((id, Var::from_byte(index)?))
+}
+
+/// The edit for a variable read from one of the target notes
+fn edit_for<'a>(edits: &'a [Edit], targets: &HashSet<u32>, read: Option<(u32, Var)>) -> Option<&'a Edit> {
+    let (id, var) = read?;
+    edits.iter().find(|edit| edit.var == var && targets.contains(&id))
+}
+
+/// Kind of the expression's result, or None when it can't be tracked
+/// (macro calls)
+fn classify(bytecode: &[u8], edits: &[Edit], targets: &HashSet<u32>) -> Result<Option<Kind>, String> {
+    let mut stack: Vec<Kind> = Vec::new();
+    let mut pc = 0;
+    while pc < bytecode.len() {
+        let len = instruction_len(bytecode, pc)?;
+        let op = Op::from_byte(bytecode[pc]).ok_or("Unknown opcode")?;
+        let (pops, pushes) = op.stack_effect();
+        if op == Op::CallMacro {
+            return Ok(None);
+        }
+        if stack.len() < pops {
+            return Err(format!("Stack underflow at pc={}", pc));
+        }
+        let args = stack.split_off(stack.len() - pops);
+        match op {
+            Op::LoadConst | Op::LoadConstBig | Op::LoadRef | Op::LoadRefWide | Op::LoadBase => {
+                let read = read_var(bytecode, pc, len);
+                stack.push(edit_for(edits, targets, read).map_or(Kind::Fixed, |edit| edit.kind));
+            }
+            Op::Dup => stack.extend([args[0], args[0]]),
+            Op::Swap => stack.extend([args[1], args[0]]),
+            Op::Over => stack.extend([args[0], args[1], args[0]]),
+            Op::Rot => stack.extend([args[1], args[2], args[0]]),
+            Op::Drop => {}
+            _ => {
+                debug_assert_eq!(pushes, 1);
+                stack.push(Kind::apply(op, &args));
+            }
+        }
+        pc += len;
+    }
+    Ok(stack.pop())
+}
+
+/// `bytecode` with `wrapper(edit)` inserted after every read of a
+/// transformed variable on a target note
+fn wrap_reads(
+    bytecode: &[u8],
+    edits: &[Edit],
+    targets: &HashSet<u32>,
+    wrapper: impl Fn(&Edit) -> &[u8],
+) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(bytecode.len());
+    let mut pc = 0;
+    while pc < bytecode.len() {
+        let len = instruction_len(bytecode, pc)?;
+        out.extend_from_slice(&bytecode[pc..pc + len]);
+        if let Some(edit) = edit_for(edits, targets, read_var(bytecode, pc, len)) {
+            out.extend_from_slice(wrapper(edit));
+        }
+        pc += len;
+    }
+    Ok(out)
+}
+
+/// `bytecode` without the inverse wrappers a forward transform inserted,
+/// or None when any of them (or the trailing suffix) is missing
+fn unwrap_reads(bytecode: &[u8], edits: &[Edit], targets: &HashSet<u32>, suffix: &[u8]) -> Option<Vec<u8>> {
+    let body = bytecode.strip_suffix(suffix)?;
+    let mut out = Vec::with_capacity(body.len());
+    let mut pc = 0;
+    while pc < body.len() {
+        let len = instruction_len(body, pc).ok()?;
+        out.extend_from_slice(&body[pc..pc + len]);
+        pc += len;
+        if let Some(edit) = edit_for(edits, targets, read_var(body, pc - len, len)) {
+            if !body[pc..].starts_with(&edit.inverse) {
+                return None;
+            }
+            pc += edit.inverse.len();
+        }
+    }
+    Some(out)
+}
+
+/// Suffix computing `expr * factor`
+fn scale_suffix(factor: &Fraction) -> Result<Vec<u8>, String> {
+    let mut suffix = Vec::new();
+    write_const(&mut suffix, factor)?;
+    suffix.push(Op::Mul as u8);
+    Ok(suffix)
+}
+
+/// Suffix computing `anchor + (expr - anchor) * factor`
+fn stretch_suffix(factor: &Fraction, anchor: &Fraction) -> Result<Vec<u8>, String> {
+    let mut suffix = Vec::new();
+    write_const(&mut suffix, anchor)?;
+    suffix.push(Op::Sub as u8);
+    write_const(&mut suffix, factor)?;
+    suffix.push(Op::Mul as u8);
+    write_const(&mut suffix, anchor)?;
+    suffix.push(Op::Add as u8);
+    Ok(suffix)
+}
+
+fn transpose_edits(ratio: &Fraction) -> Result<Vec<Edit>, String> {
+    if !ratio.is_positive() {
+        return Err(format!("Transpose ratio must be positive, got {}", ratio));
+    }
+    Ok(vec![Edit {
+        var: Var::Frequency,
+        kind: Kind::Span,
+        suffix: scale_suffix(ratio)?,
+        inverse: scale_suffix(&ratio.inverse())?,
+    }])
+}
+
+fn stretch_edits(factor: &Fraction, anchor: &Fraction) -> Result<Vec<Edit>, String> {
+    if !factor.is_positive() {
+        return Err(format!("Stretch factor must be positive, got {}", factor));
+    }
+    let inverse = factor.inverse();
+    Ok(vec![
+        Edit {
+            var: Var::StartTime,
+            kind: Kind::Point,
+            suffix: stretch_suffix(factor, anchor)?,
+            inverse: stretch_suffix(&inverse, anchor)?,
+        },
+        Edit {
+            var: Var::Duration,
+            kind: Kind::Span,
+            suffix: scale_suffix(factor)?,
+            inverse: scale_suffix(&inverse)?,
+        },
+    ])
+}
+
+/// Rewrite the target expressions, returning the ids of notes that changed
+///
+/// All new blobs are built and validated before any are stored, so an error
+/// leaves the evaluator untouched.
+fn apply(
+    evaluator: &mut PersistentEvaluator,
+    ids: &[u32],
+    edits: &[Edit],
+    undo: bool,
+) -> Result<Vec<u32>, String> {
+    let targets: HashSet<u32> = ids.iter().copied().collect();
+    let mut updates: Vec<(u32, Var, Vec<u8>)> = Vec::new();
+
+    let mut seen = HashSet::new();
+    for &id in ids.iter().filter(|id| seen.insert(**id)) {
+        for edit in edits {
+            let (bytecode, length) = match evaluator.expression(id, edit.var) {
+                Some(expr) => expr,
+                None => continue,
+            };
+            let bytecode = &bytecode[..length.min(bytecode.len())];
+            let context = |e: String| format!("Note {} {}: {}", id, edit.var.name(), e);
+            validate(bytecode, bytecode.len()).map_err(|e| context(e.to_string()))?;
+
+            let restored = if undo { unwrap_reads(bytecode, edits, &targets, &edit.suffix) } else { None };
+            let updated = match restored {
+                Some(original) => original,
+                None => {
+                    if classify(bytecode, edits, &targets).map_err(context)? == Some(edit.kind) {
+                        // Already follows its references
+                        continue;
+                    }
+                    let body = wrap_reads(bytecode, edits, &targets, |e| if undo { &e.suffix } else { &e.inverse })
+                        .map_err(context)?;
+                    let suffix = if undo { &edit.inverse } else { &edit.suffix };
+                    [body.as_slice(), suffix].concat()
+                }
+            };
+            validate(&updated, updated.len()).map_err(|e| context(e.to_string()))?;
+            updates.push((id, edit.var, updated));
+        }
+    }
+
+    let mut changed = Vec::new();
+    for (id, var, bytecode) in updates {
+        evaluator.register_expression(id, var as u8, &bytecode, bytecode.len());
+        evaluator.mark_dirty(id);
+        if changed.last() != Some(&id) {
+            changed.push(id);
+        }
+    }
+    Ok(changed)
+}
+
+/// Multiply the frequency of each target note by `ratio`
+pub fn transpose(
+    evaluator: &mut PersistentEvaluator,
+    ids: &[u32],
+    ratio: &Fraction,
+) -> Result<Vec<u32>, String> {
+    apply(evaluator, ids, &transpose_edits(ratio)?, false)
+}
+
+/// Undo `transpose` with the same ids and ratio
+pub fn undo_transpose(
+    evaluator: &mut PersistentEvaluator,
+    ids: &[u32],
+    ratio: &Fraction,
+) -> Result<Vec<u32>, String> {
+    apply(evaluator, ids, &transpose_edits(ratio)?, true)
+}
+
+/// Scale start times (relative to `anchor`) and durations by `factor`
+pub fn time_stretch(
+    evaluator: &mut PersistentEvaluator,
+    ids: &[u32],
+    factor: &Fraction,
+    anchor: &Fraction,
+) -> Result<Vec<u32>, String> {
+    apply(evaluator, ids, &stretch_edits(factor, anchor)?, false)
+}
+
+/// Undo `time_stretch` with the same ids, factor and anchor
+pub fn undo_time_stretch(
+    evaluator: &mut PersistentEvaluator,
+    ids: &[u32],
+    factor: &Fraction,
+    anchor: &Fraction,
+) -> Result<Vec<u32>, String> {
+    apply(evaluator, ids, &stretch_edits(factor, anchor)?, true)
+}
+
+// WASM bindings for JavaScript interop
+
+/// Transpose notes by a ratio; returns the ids whose bytecode changed
+#[wasm_bindgen(js_name = transpose)]
+pub fn transpose_js(
+    evaluator: &mut PersistentEvaluator,
+    ids: &[u32],
+    ratio: &Fraction,
+) -> Result<Vec<u32>, JsValue> {
+    transpose(evaluator, ids, ratio).map_err(|e| JsValue::from_str(&e))
+}
+
+/// Undo a transpose; returns the ids whose bytecode changed
+#[wasm_bindgen(js_name = undoTranspose)]
+pub fn undo_transpose_js(
+    evaluator: &mut PersistentEvaluator,
+    ids: &[u32],
+    ratio: &Fraction,
+) -> Result<Vec<u32>, JsValue> {
+    undo_transpose(evaluator, ids, ratio).map_err(|e| JsValue::from_str(&e))
+}
+
+/// Time-stretch notes around an anchor time; returns the ids whose bytecode changed
+#[wasm_bindgen(js_name = timeStretch)]
+pub fn time_stretch_js(
+    evaluator: &mut PersistentEvaluator,
+    ids: &[u32],
+    factor: &Fraction,
+    anchor: &Fraction,
+) -> Result<Vec<u32>, JsValue> {
+    time_stretch(evaluator, ids, factor, anchor).map_err(|e| JsValue::from_str(&e))
+}
+
+/// Undo a time-stretch; returns the ids whose bytecode changed
+#[wasm_bindgen(js_name = undoTimeStretch)]
+pub fn undo_time_stretch_js(
+    evaluator: &mut PersistentEvaluator,
+    ids: &[u32],
+    factor: &Fraction,
+    anchor: &Fraction,
+) -> Result<Vec<u32>, JsValue> {
+    undo_time_stretch(evaluator, ids, factor, anchor).map_err(|e| JsValue::from_str(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::{write_i32, write_u16};
+    use num_bigint::BigInt;
+
+    fn constant(num: i32, den: i32) -> Vec<u8> {
+        let mut bc = vec![Op::LoadConst as u8];
+        write_i32(&mut bc, num);
+        write_i32(&mut bc, den);
+        bc
+    }
+
+    fn reference(id: u32, var: Var) -> Vec<u8> {
+        let mut bc = vec![Op::LoadRef as u8];
+        write_u16(&mut bc, id as u16);
+        bc.push(var as u8);
+        bc
+    }
+
+    fn register(evaluator: &mut PersistentEvaluator, id: u32, var: Var, bytecode: Vec<u8>) {
+        evaluator.register_expression(id, var as u8, &bytecode, bytecode.len());
+    }
+
+    fn value(evaluator: &PersistentEvaluator, id: u32, var: Var) -> Fraction {
+        let note = evaluator.cached_note(id).expect("note evaluated");
+        note.get_var(var).expect("variable set").to_fraction()
+    }
+
+    /// Three notes: 1 at t=1 (440 Hz, 1s), 2 at t=3 (550 Hz, 2s),
+    /// 3 follows 2 with a fifth above it
+    fn module() -> PersistentEvaluator {
+        let mut evaluator = PersistentEvaluator::new();
+        register(&mut evaluator, 1, Var::StartTime, constant(1, 1));
+        register(&mut evaluator, 1, Var::Duration, constant(1, 1));
+        register(&mut evaluator, 1, Var::Frequency, constant(440, 1));
+        register(&mut evaluator, 2, Var::StartTime, constant(3, 1));
+        register(&mut evaluator, 2, Var::Duration, constant(2, 1));
+        register(&mut evaluator, 2, Var::Frequency, constant(550, 1));
+        let start = [reference(2, Var::StartTime), reference(2, Var::Duration), vec![Op::Add as u8]].concat();
+        let freq = [reference(2, Var::Frequency), constant(3, 2), vec![Op::Mul as u8]].concat();
+        register(&mut evaluator, 3, Var::StartTime, start);
+        register(&mut evaluator, 3, Var::Duration, constant(1, 2));
+        register(&mut evaluator, 3, Var::Frequency, freq);
+        evaluator.evaluate_dirty(&[1, 2, 3]);
+        evaluator
+    }
+
+    #[test]
+    fn test_transpose_by_fifth() {
+        let mut evaluator = module();
+        let before: Vec<Fraction> = (1..=3).map(|id| value(&evaluator, id, Var::Frequency)).collect();
+
+        let ratio = Fraction::new(3, 2);
+        let changed = transpose(&mut evaluator, &[1, 2, 3], &ratio).unwrap();
+        // Note 3 is relative to note 2 and follows it without its own suffix
+        assert_eq!(changed, vec![1, 2]);
+        evaluator.evaluate_dirty(&[1, 2, 3]);
+
+        for (id, old) in (1..=3).zip(&before) {
+            assert_eq!(value(&evaluator, id, Var::Frequency), old.mul(&ratio));
+        }
+
+        undo_transpose(&mut evaluator, &[1, 2, 3], &ratio).unwrap();
+        assert_eq!(evaluator.expression(1, Var::Frequency).unwrap().0, constant(440, 1).as_slice());
+        evaluator.evaluate_dirty(&[1, 2, 3]);
+        for (id, old) in (1..=3).zip(&before) {
+            assert_eq!(&value(&evaluator, id, Var::Frequency), old);
+        }
+    }
+
+    #[test]
+    fn test_transpose_rejects_oversized_ratio() {
+        let mut evaluator = module();
+        let ratio = Fraction::from_big_ints(BigInt::from(1) << (8 * 65536usize), BigInt::from(1));
+        assert!(transpose(&mut evaluator, &[1], &ratio).is_err());
+        assert_eq!(evaluator.expression(1, Var::Frequency).unwrap().0, constant(440, 1).as_slice());
+    }
+
+    #[test]
+    fn test_time_stretch_keeps_anchor() {
+        let mut evaluator = module();
+        let half = Fraction::new(1, 2);
+        let anchor = Fraction::new(1, 1);
+        time_stretch(&mut evaluator, &[1, 2, 3], &half, &anchor).unwrap();
+        evaluator.evaluate_dirty(&[1, 2, 3]);
+
+        // Anchor note stays put, everything else moves towards it
+        assert_eq!(value(&evaluator, 1, Var::StartTime), Fraction::new(1, 1));
+        assert_eq!(value(&evaluator, 1, Var::Duration), Fraction::new(1, 2));
+        assert_eq!(value(&evaluator, 2, Var::StartTime), Fraction::new(2, 1));
+        assert_eq!(value(&evaluator, 2, Var::Duration), Fraction::new(1, 1));
+        assert_eq!(value(&evaluator, 3, Var::StartTime), Fraction::new(3, 1));
+        assert_eq!(value(&evaluator, 3, Var::Duration), Fraction::new(1, 4));
+
+        undo_time_stretch(&mut evaluator, &[1, 2, 3], &half, &anchor).unwrap();
+        evaluator.evaluate_dirty(&[1, 2, 3]);
+        assert_eq!(value(&evaluator, 3, Var::StartTime), Fraction::new(5, 1));
+        assert_eq!(value(&evaluator, 3, Var::Duration), Fraction::new(1, 2));
+    }
+
+    #[test]
+    fn test_transpose_additive_frequency() {
+        let mut evaluator = module();
+        let freq = [reference(2, Var::Frequency), constant(10, 1), vec![Op::Add as u8]].concat();
+        register(&mut evaluator, 4, Var::Frequency, freq.clone());
+        evaluator.evaluate_dirty(&[4]);
+        assert_eq!(value(&evaluator, 4, Var::Frequency), Fraction::new(560, 1));
+
+        let ratio = Fraction::new(3, 2);
+        let changed = transpose(&mut evaluator, &[2, 4], &ratio).unwrap();
+        assert_eq!(changed, vec![2, 4]);
+        evaluator.evaluate_dirty(&[1, 2, 3, 4]);
+        assert_eq!(value(&evaluator, 2, Var::Frequency), Fraction::new(825, 1));
+        assert_eq!(value(&evaluator, 4, Var::Frequency), Fraction::new(840, 1));
+
+        // Transposing only note 4 leaves its reference to note 2 alone
+        let mut evaluator = module();
+        register(&mut evaluator, 4, Var::Frequency, freq.clone());
+        transpose(&mut evaluator, &[4], &ratio).unwrap();
+        evaluator.evaluate_dirty(&[4]);
+        assert_eq!(value(&evaluator, 4, Var::Frequency), Fraction::new(840, 1));
+
+        undo_transpose(&mut evaluator, &[4], &ratio).unwrap();
+        assert_eq!(evaluator.expression(4, Var::Frequency).unwrap().0, freq.as_slice());
+    }
+
+    #[test]
+    fn test_time_stretch_offsets_with_anchor() {
+        let mut evaluator = module();
+        // 4 starts 1s after note 2 and lasts three of its durations,
+        // 5 starts at twice note 2's duration
+        let start = [reference(2, Var::StartTime), constant(1, 1), vec![Op::Add as u8]].concat();
+        let duration = [constant(3, 1), reference(2, Var::Duration), vec![Op::Mul as u8]].concat();
+        let offset = [reference(2, Var::Duration), constant(2, 1), vec![Op::Mul as u8]].concat();
+        register(&mut evaluator, 4, Var::StartTime, start.clone());
+        register(&mut evaluator, 4, Var::Duration, duration.clone());
+        register(&mut evaluator, 5, Var::StartTime, offset.clone());
+        register(&mut evaluator, 5, Var::Duration, constant(1, 1));
+        let ids = [1, 2, 3, 4, 5];
+        evaluator.evaluate_dirty(&ids);
+        let before: Vec<(Fraction, Fraction)> = ids
+            .iter()
+            .map(|&id| (value(&evaluator, id, Var::StartTime), value(&evaluator, id, Var::Duration)))
+            .collect();
+
+        let factor = Fraction::new(1, 2);
+        let anchor = Fraction::new(1, 1);
+        let changed = time_stretch(&mut evaluator, &ids, &factor, &anchor).unwrap();
+        assert_eq!(changed, vec![1, 2, 3, 4, 5]);
+        evaluator.evaluate_dirty(&ids);
+        for (&id, (start, duration)) in ids.iter().zip(&before) {
+            let stretched = anchor.add(&start.sub(&anchor).mul(&factor));
+            assert_eq!(value(&evaluator, id, Var::StartTime), stretched, "note {} start", id);
+            assert_eq!(value(&evaluator, id, Var::Duration), duration.mul(&factor), "note {} duration", id);
+        }
+        // Relative durations follow their reference unchanged
+        assert_eq!(evaluator.expression(4, Var::Duration).unwrap().0, duration.as_slice());
+
+        undo_time_stretch(&mut evaluator, &ids, &factor, &anchor).unwrap();
+        assert_eq!(evaluator.expression(4, Var::StartTime).unwrap().0, start.as_slice());
+        assert_eq!(evaluator.expression(5, Var::StartTime).unwrap().0, offset.as_slice());
+        evaluator.evaluate_dirty(&ids);
+        for (&id, (start, duration)) in ids.iter().zip(&before) {
+            assert_eq!(&value(&evaluator, id, Var::StartTime), start);
+            assert_eq!(&value(&evaluator, id, Var::Duration), duration);
+        }
+    }
+
+    #[test]
+    fn test_invalid_transform_leaves_bytecode_untouched() {
+        let mut evaluator = module();
+        register(&mut evaluator, 2, Var::Frequency, vec![Op::Add as u8]);
+
+        assert!(transpose(&mut evaluator, &[1, 2], &Fraction::new(2, 1)).is_err());
+        assert_eq!(evaluator.expression(1, Var::Frequency).unwrap().0, constant(440, 1).as_slice());
+        assert!(transpose(&mut evaluator, &[1], &Fraction::new(0, 1)).is_err());
+    }
+}
@@ -0,0 +1,571 @@
+//! TypeScript declaration generation for serialized data shapes
+//!
+//! Generates `.d.ts` interfaces for every serde struct that crosses the
+//! JS/WASM boundary, directly from the Rust definitions. Instead of a derive
+//! macro, each type's `Deserialize` impl is driven by a tracing deserializer
+//! that records the field names (after serde renames) and the type requested
+//! for each field, so the declarations always match what serde_wasm_bindgen
+//! actually produces and accepts.
+//!
+//! The generated file is committed at `bindings/rmt-core-types.d.ts`; a test
+//! fails if regeneration would change it. Regenerate with:
+//!
+//! ```text
+//! UPDATE_TS_BINDINGS=1 cargo test ts::
+//! ```
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::fmt;
+
+/// Path of the committed declarations, relative to the crate root
+pub const BINDINGS_PATH: &str = "bindings/rmt-core-types.d.ts";
+
+/// A traced TypeScript type
+#[derive(Clone, Debug, PartialEq)]
+enum TsType {
+    Number,
+    String,
+    Boolean,
+    Null,
+    Optional(Box<TsType>),
+    Array(Box<TsType>),
+    Tuple(Vec<TsType>),
+    Map(Box<TsType>, Box<TsType>),
+    Named(String),
+}
+
+impl TsType {
+    fn render(&self) -> String {
+        match self {
+            TsType::Number => "number".to_string(),
+            TsType::String => "string".to_string(),
+            TsType::Boolean => "boolean".to_string(),
+            TsType::Null => "null".to_string(),
+            TsType::Optional(inner) => format!("{} | undefined", inner.render()),
+            TsType::Array(inner) => match inner.as_ref() {
+                TsType::Optional(_) => format!("({})[]", inner.render()),
+                _ => format!("{}[]", inner.render()),
+            },
+            TsType::Tuple(items) => {
+                let items: Vec<String> = items.iter().map(|t| t.render()).collect();
+                format!("[{}]", items.join(", "))
+            }
+            TsType::Map(key, value) => format!("Map<{}, {}>", key.render(), value.render()),
+            TsType::Named(name) => name.clone(),
+        }
+    }
+}
+
+/// A traced struct declaration
+struct Declaration {
+    name: String,
+    fields: Vec<(String, TsType)>,
+}
+
+impl Declaration {
+    fn render(&self) -> String {
+        let mut out = format!("export interface {} {{\n", self.name);
+        for (name, ty) in &self.fields {
+            match ty {
+                TsType::Optional(inner) => out.push_str(&format!("  {}?: {};\n", name, inner.render())),
+                _ => out.push_str(&format!("  {}: {};\n", name, ty.render())),
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    declarations: Vec<Declaration>,
+    last: Option<TsType>,
+}
+
+impl Registry {
+    fn record(&mut self, ty: TsType) {
+        self.last = Some(ty);
+    }
+
+    fn take_last(&mut self) -> TsType {
+        self.last.take().unwrap_or(TsType::Null)
+    }
+}
+
+/// Error produced when a type can't be traced
+#[derive(Debug)]
+pub struct TraceError(String);
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TypeScript trace failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl de::Error for TraceError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        TraceError(msg.to_string())
+    }
+}
+
+/// Deserializer that records the requested shape instead of reading data
+struct Tracer<'a> {
+    registry: &'a RefCell<Registry>,
+}
+
+impl<'a> Tracer<'a> {
+    fn record(&self, ty: TsType) {
+        self.registry.borrow_mut().record(ty);
+    }
+}
+
+macro_rules! trace_number {
+    ($($method:ident => $visit:ident($value:expr)),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+                self.record(TsType::Number);
+                visitor.$visit($value)
+            }
+        )*
+    };
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Tracer<'a> {
+    type Error = TraceError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, TraceError> {
+        Err(TraceError("self-describing (untagged) types are not supported".to_string()))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.record(TsType::Boolean);
+        visitor.visit_bool(false)
+    }
+
+    trace_number! {
+        deserialize_i8 => visit_i8(0),
+        deserialize_i16 => visit_i16(0),
+        deserialize_i32 => visit_i32(0),
+        deserialize_i64 => visit_i64(0),
+        deserialize_u8 => visit_u8(0),
+        deserialize_u16 => visit_u16(0),
+        deserialize_u32 => visit_u32(0),
+        deserialize_u64 => visit_u64(0),
+        deserialize_f32 => visit_f32(0.0),
+        deserialize_f64 => visit_f64(0.0),
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.record(TsType::String);
+        visitor.visit_char('0')
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.record(TsType::String);
+        visitor.visit_str("")
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.record(TsType::String);
+        visitor.visit_string(String::new())
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.record(TsType::Array(Box::new(TsType::Number)));
+        visitor.visit_bytes(&[])
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.record(TsType::Array(Box::new(TsType::Number)));
+        visitor.visit_byte_buf(Vec::new())
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        let registry = self.registry;
+        let value = visitor.visit_some(Tracer { registry })?;
+        let inner = registry.borrow_mut().take_last();
+        registry.borrow_mut().record(TsType::Optional(Box::new(inner)));
+        Ok(value)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.record(TsType::Null);
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        let registry = self.registry;
+        let mut access = TupleAccess { registry, remaining: 1, types: Vec::new() };
+        let value = visitor.visit_seq(&mut access)?;
+        let element = access.types.pop().unwrap_or(TsType::Null);
+        registry.borrow_mut().record(TsType::Array(Box::new(element)));
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, TraceError> {
+        let registry = self.registry;
+        let mut access = TupleAccess { registry, remaining: len, types: Vec::new() };
+        let value = visitor.visit_seq(&mut access)?;
+        let types = access.types;
+        // Fixed-size arrays of one type are plain arrays in JS
+        let ty = match types.first() {
+            Some(first) if types.len() > 1 && types.iter().all(|t| t == first) => {
+                TsType::Array(Box::new(first.clone()))
+            }
+            _ => TsType::Tuple(types),
+        };
+        registry.borrow_mut().record(ty);
+        Ok(value)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        let registry = self.registry;
+        let mut access = MapEntryAccess { registry, remaining: true, key: None };
+        let value = visitor.visit_map(&mut access)?;
+        let key = access.key.take().unwrap_or(TsType::String);
+        let value_ty = registry.borrow_mut().take_last();
+        registry.borrow_mut().record(TsType::Map(Box::new(key), Box::new(value_ty)));
+        Ok(value)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let registry = self.registry;
+        let mut access = StructAccess { registry, fields, index: 0, traced: Vec::new() };
+        let value = visitor.visit_map(&mut access)?;
+
+        let mut reg = registry.borrow_mut();
+        if !reg.declarations.iter().any(|d| d.name == name) {
+            reg.declarations.push(Declaration {
+                name: name.to_string(),
+                fields: access.traced,
+            });
+        }
+        reg.record(TsType::Named(name.to_string()));
+        Ok(value)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        _variants: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        Err(TraceError(format!("enum {} is not supported", name)))
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        visitor.visit_unit()
+    }
+}
+
+/// Yields `remaining` traced elements for sequences and tuples
+struct TupleAccess<'a> {
+    registry: &'a RefCell<Registry>,
+    remaining: usize,
+    types: Vec<TsType>,
+}
+
+impl<'de, 'a> SeqAccess<'de> for &mut TupleAccess<'a> {
+    type Error = TraceError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, TraceError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        let value = seed.deserialize(Tracer { registry: self.registry })?;
+        self.types.push(self.registry.borrow_mut().take_last());
+        Ok(Some(value))
+    }
+}
+
+/// Yields a single traced key/value entry for maps
+struct MapEntryAccess<'a> {
+    registry: &'a RefCell<Registry>,
+    remaining: bool,
+    key: Option<TsType>,
+}
+
+impl<'de, 'a> MapAccess<'de> for &mut MapEntryAccess<'a> {
+    type Error = TraceError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, TraceError> {
+        if !self.remaining {
+            return Ok(None);
+        }
+        self.remaining = false;
+        let key = seed.deserialize(Tracer { registry: self.registry })?;
+        self.key = Some(self.registry.borrow_mut().take_last());
+        Ok(Some(key))
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, TraceError> {
+        seed.deserialize(Tracer { registry: self.registry })
+    }
+}
+
+/// Yields every declared field of a struct, tracing each value
+struct StructAccess<'a> {
+    registry: &'a RefCell<Registry>,
+    fields: &'static [&'static str],
+    index: usize,
+    traced: Vec<(String, TsType)>,
+}
+
+impl<'de, 'a> MapAccess<'de> for &mut StructAccess<'a> {
+    type Error = TraceError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, TraceError> {
+        match self.fields.get(self.index) {
+            Some(field) => seed.deserialize((*field).into_deserializer()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, TraceError> {
+        let value = seed.deserialize(Tracer { registry: self.registry })?;
+        let ty = self.registry.borrow_mut().take_last();
+        self.traced.push((self.fields[self.index].to_string(), ty));
+        self.index += 1;
+        Ok(value)
+    }
+}
+
+/// Collects declarations for a set of root types
+#[derive(Default)]
+pub struct Bindings {
+    registry: RefCell<Registry>,
+}
+
+impl Bindings {
+    /// Create an empty set of bindings
+    pub fn new() -> Self {
+        Bindings::default()
+    }
+
+    /// Trace a type (and every struct it contains) into the bindings
+    pub fn add<T: DeserializeOwned>(&mut self) -> Result<&mut Self, TraceError> {
+        T::deserialize(Tracer { registry: &self.registry })?;
+        self.registry.borrow_mut().last = None;
+        Ok(self)
+    }
+
+    /// Like `add`, but fields that the default value leaves out when
+    /// serialized (`skip_serializing_if`) are declared optional too
+    ///
+    /// Only the fields of `T` itself are checked; add nested types this way
+    /// as well where they skip fields.
+    pub fn add_with_defaults<T: DeserializeOwned + Serialize + Default>(&mut self) -> Result<&mut Self, TraceError> {
+        T::deserialize(Tracer { registry: &self.registry })?;
+        let default = serde_json::to_value(T::default()).map_err(|e| TraceError(e.to_string()))?;
+        let mut registry = self.registry.borrow_mut();
+        let Some(TsType::Named(name)) = registry.last.take() else {
+            return Err(TraceError(format!("{} is not a struct", std::any::type_name::<T>())));
+        };
+        if let Some(declaration) = registry.declarations.iter_mut().find(|d| d.name == name) {
+            for (field, ty) in &mut declaration.fields {
+                if !matches!(ty, TsType::Optional(_)) && default.get(field.as_str()).is_none() {
+                    *ty = TsType::Optional(Box::new(ty.clone()));
+                }
+            }
+        }
+        drop(registry);
+        Ok(self)
+    }
+
+    /// Render all traced declarations as a .d.ts file
+    pub fn render(&self) -> String {
+        let mut out = String::from(
+            "// Generated from the Rust definitions by rmt-core (src/ts.rs). Do not edit by hand.\n\
+             // Regenerate with: UPDATE_TS_BINDINGS=1 cargo test ts::\n",
+        );
+        for declaration in &self.registry.borrow().declarations {
+            out.push('\n');
+            out.push_str(&declaration.render());
+        }
+        out
+    }
+}
+
+/// Generate declarations for every serialized data shape in the crate
+pub fn generate_bindings() -> Result<String, TraceError> {
+    use crate::bytecode::{BytecodeStats, ValidationInfo};
+    use crate::compiler::CompiledExpression;
+    use crate::evaluator::{
+        AudioEvents, CacheMerge, CacheProblem, CorruptedNote, CorruptionSource, CorruptionSummary, DefaultsData, EvalErrorData, EvaluatedNote,
+        EvaluationStats, EvaluatorMemoryStats, FractionData, InternStats, JsExpressions, ModuleExtent, ModuleExtentExact,
+        NoteBytecodeSize, NoteTiming, StrictViolation, TracedEvaluation,
+    };
+    use crate::graph::{GraphMemoryStats, GraphStats, GraphSyncData};
+    use crate::memory::MemoryReport;
+    use crate::diagnostics::{DiagnosticTimings, DiagnosticsReport};
+    use crate::module_json::{ModuleError, ModuleExpression, ModuleNote, ParsedModule};
+    use crate::pitch::PitchDescription;
+    use crate::tuning::MidiPosition;
+    use crate::value::ValueData;
+
+    let mut bindings = Bindings::new();
+    bindings
+        .add::<FractionData>()?
+        .add_with_defaults::<EvaluatedNote>()?
+        .add_with_defaults::<CorruptionSource>()?
+        .add::<ValueData>()?
+        .add::<CompiledExpression>()?
+        .add::<ValidationInfo>()?
+        .add::<BytecodeStats>()?
+        .add::<JsExpressions>()?
+        .add::<GraphStats>()?
+        .add::<GraphSyncData>()?
+        .add::<GraphMemoryStats>()?
+        .add::<EvaluatorMemoryStats>()?
+        .add::<InternStats>()?
+        .add::<NoteBytecodeSize>()?
+        .add::<MemoryReport>()?
+        .add::<MidiPosition>()?
+        .add::<PitchDescription>()?
+        .add::<AudioEvents>()?
+        .add::<NoteTiming>()?
+        .add::<CacheMerge>()?
+        .add::<CacheProblem>()?
+        .add_with_defaults::<CorruptedNote>()?
+        .add::<CorruptionSummary>()?
+        .add::<ModuleExtent>()?
+        .add::<ModuleExtentExact>()?
+        .add::<StrictViolation>()?
+        .add::<EvalErrorData>()?
+        .add::<EvaluationStats>()?
+        .add::<DefaultsData>()?
+        .add::<TracedEvaluation>()?
+        .add::<ModuleError>()?
+        .add::<ModuleExpression>()?
+        .add::<ModuleNote>()?
+        .add::<ParsedModule>()?
+        .add::<DiagnosticTimings>()?
+        .add::<DiagnosticsReport>()?;
+    Ok(bindings.render())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Inner {
+        #[serde(rename = "someValue")]
+        some_value: u32,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize)]
+    struct Outer {
+        name: String,
+        #[serde(default)]
+        maybe: Option<f64>,
+        items: Vec<Inner>,
+        pair: (u8, String),
+        fixed: [bool; 3],
+    }
+
+    #[test]
+    fn test_trace_shapes() {
+        let mut bindings = Bindings::new();
+        bindings.add::<Outer>().unwrap();
+        let out = bindings.render();
+
+        assert!(out.contains("export interface Inner {\n  someValue: number;\n}\n"));
+        assert!(out.contains("  name: string;\n"));
+        assert!(out.contains("  maybe?: number;\n"));
+        assert!(out.contains("  items: Inner[];\n"));
+        assert!(out.contains("  pair: [number, string];\n"));
+        assert!(out.contains("  fixed: boolean[];\n"));
+        // Nested declarations come before their users
+        assert!(out.find("interface Inner").unwrap() < out.find("interface Outer").unwrap());
+    }
+
+    #[allow(dead_code)]
+    #[derive(Default, Deserialize, Serialize)]
+    struct Skipping {
+        always: u32,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        items: Vec<u32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        maybe: Option<u32>,
+    }
+
+    #[test]
+    fn test_skipped_fields_are_optional() {
+        let mut bindings = Bindings::new();
+        bindings.add_with_defaults::<Skipping>().unwrap();
+        let out = bindings.render();
+
+        assert!(out.contains("  always: number;\n"));
+        assert!(out.contains("  items?: number[];\n"));
+        assert!(out.contains("  maybe?: number;\n"));
+        assert!(Bindings::new().add_with_defaults::<u32>().is_err());
+
+        let generated = generate_bindings().unwrap();
+        assert!(generated.contains("  customVars?: Map<number, FractionData>;\n"));
+        assert!(generated.contains("  customCorruptionFlags?: number;\n"));
+        assert!(generated.contains("  corruptionFlags: number;\n"));
+        assert!(generated.contains("  corruptionSources?: CorruptionSource[];\n"));
+        assert!(generated.contains("  lossy?: boolean;\n"));
+    }
+
+    #[test]
+    fn test_committed_bindings_are_current() {
+        let generated = generate_bindings().unwrap();
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(BINDINGS_PATH);
+
+        if std::env::var_os("UPDATE_TS_BINDINGS").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, &generated).unwrap();
+        }
+
+        let committed = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            committed == generated,
+            "{} is out of date; regenerate with UPDATE_TS_BINDINGS=1 cargo test ts::",
+            BINDINGS_PATH
+        );
+    }
+}
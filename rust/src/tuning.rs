@@ -0,0 +1,317 @@
+//! Frequency, MIDI and cents conversion utilities
+//!
+//! Shared tuning math so every caller uses the same reference pitch.
+//! MIDI note 69 is A4; the A4 frequency defaults to 440 Hz and can be
+//! changed with `setDefaultA4`.
+//!
+//! `value_to_midi` stays exact for base-2 symbolic values: the ratio to A4
+//! is computed symbolically and its log2 read off the exponents, so
+//! 440·2^(1/12) is exactly MIDI 70 with a 0-cent offset.
+
+use crate::fraction::Fraction;
+use crate::value::{value_from_js, Value};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{Signed, ToPrimitive, Zero};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use wasm_bindgen::prelude::*;
+
+/// MIDI note number of A4
+pub const A4_MIDI: i32 = 69;
+
+/// Standard concert pitch for A4 in Hz
+pub const DEFAULT_A4: f64 = 440.0;
+
+thread_local! {
+    static A4_HZ: Cell<f64> = const { Cell::new(DEFAULT_A4) };
+}
+
+/// Get the default A4 frequency used when none is supplied
+pub fn default_a4() -> f64 {
+    A4_HZ.with(|a4| a4.get())
+}
+
+/// Set the default A4 frequency (ignored unless positive and finite)
+pub fn set_default_a4(hz: f64) {
+    if hz.is_finite() && hz > 0.0 {
+        A4_HZ.with(|a4| a4.set(hz));
+    }
+}
+
+/// Nearest MIDI note and the offset from it in cents
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MidiPosition {
+    /// Nearest equal-tempered MIDI note
+    pub note: i32,
+    /// Offset from that note in cents, in [-50, 50]
+    pub cents: f64,
+}
+
+/// Convert a frequency to the nearest MIDI note and cents offset
+///
+/// Non-positive or non-finite frequencies return note 0 with NaN cents.
+pub fn freq_to_midi(freq: f64, a4: f64) -> (i32, f64) {
+    if !(freq.is_finite() && freq > 0.0 && a4.is_finite() && a4 > 0.0) {
+        return (0, f64::NAN);
+    }
+    let semitones = 12.0 * (freq / a4).log2();
+    let nearest = semitones.round();
+    (A4_MIDI + nearest as i32, (semitones - nearest) * 100.0)
+}
+
+/// Convert a MIDI note to its equal-tempered frequency
+pub fn midi_to_freq(note: i32, a4: f64) -> f64 {
+    a4 * 2f64.powf((note - A4_MIDI) as f64 / 12.0)
+}
+
+/// Size of a frequency ratio in cents (1200·log2(ratio))
+///
+/// Non-positive ratios return NaN.
+pub fn ratio_to_cents(ratio: &Fraction) -> f64 {
+    if !ratio.is_positive() {
+        return f64::NAN;
+    }
+    if let Some(exact) = exact_log2_fraction(ratio) {
+        return exact.to_f64() * 1200.0;
+    }
+    // Split into log2(n) - log2(d) so huge numerators don't overflow f64
+    let big = ratio.to_big_rational();
+    (big_log2(big.numer()) - big_log2(big.denom())) * 1200.0
+}
+
+/// Best rational approximation of an interval given in cents
+pub fn cents_to_ratio(cents: f64, max_den: u64) -> Fraction {
+    let ratio = 2f64.powf(cents / 1200.0);
+    match BigRational::from_float(ratio) {
+        Some(exact) => Fraction::from_big_rational(exact).limit_denominator(max_den),
+        None => Fraction::zero(),
+    }
+}
+
+/// Convert a frequency Value to the nearest MIDI note and cents offset
+///
+/// When the ratio to A4 is an exact power of two (including symbolic values
+/// built only from base-2 powers), the result is computed with exact rational
+/// arithmetic; other values fall back to f64.
+pub fn value_to_midi(value: &Value, a4: &Value) -> (i32, f64) {
+    let ratio = value.div(a4);
+    match exact_log2(&ratio) {
+        Some(octaves) => {
+            let semitones = octaves.mul(&Fraction::new(12, 1));
+            let nearest = semitones.to_f64().round() as i32;
+            let offset = semitones.sub(&Fraction::new(nearest, 1));
+            (A4_MIDI + nearest, offset.to_f64() * 100.0)
+        }
+        None => freq_to_midi(value.to_f64(), a4.to_f64()),
+    }
+}
+
+/// Exact log2 of a value, if it is a (symbolic) power of two
+pub(crate) fn exact_log2(value: &Value) -> Option<Fraction> {
+    match value {
+        Value::Rational(f) => exact_log2_fraction(f),
+        Value::Irrational { .. } => None,
+        Value::Symbolic(sp) => {
+            let mut total = exact_log2_fraction(&sp.coefficient)?;
+            for p in &sp.powers {
+                let base_log = exact_log2_fraction(&Fraction::new_raw(p.base as i64, 1))?;
+                total = total.add(&base_log.mul(&p.exponent));
+            }
+            Some(total)
+        }
+    }
+}
+
+/// Exact log2 of a positive fraction whose numerator and denominator are powers of two
+fn exact_log2_fraction(f: &Fraction) -> Option<Fraction> {
+    if !f.is_positive() {
+        return None;
+    }
+    let big = f.to_big_rational();
+    let num_log = power_of_two_exponent(big.numer())?;
+    let den_log = power_of_two_exponent(big.denom())?;
+    Some(Fraction::new_raw(num_log - den_log, 1))
+}
+
+/// If `n` is a positive power of two, return its exponent
+fn power_of_two_exponent(n: &BigInt) -> Option<i64> {
+    if !n.is_positive() {
+        return None;
+    }
+    let zeros = n.trailing_zeros()?;
+    if n.bits() == zeros + 1 {
+        Some(zeros as i64)
+    } else {
+        None
+    }
+}
+
+/// log2 of a positive BigInt without overflowing f64
+pub(crate) fn big_log2(n: &BigInt) -> f64 {
+    if n.is_zero() {
+        return f64::NEG_INFINITY;
+    }
+    let bits = n.bits();
+    if bits <= 1000 {
+        return n.to_f64().unwrap_or(f64::INFINITY).log2();
+    }
+    // Keep the top 64 bits and add the shifted-out exponent back
+    let shift = bits - 64;
+    let top: BigInt = n >> shift;
+    top.to_f64().unwrap_or(0.0).log2() + shift as f64
+}
+
+/// A4 in Hz as an exact value (the default A4 when omitted)
+pub(crate) fn a4_value(a4: Option<f64>) -> Value {
+    let a4_hz = a4.unwrap_or_else(default_a4);
+    if a4_hz.fract() == 0.0 && a4_hz.abs() <= i32::MAX as f64 {
+        Value::rational(a4_hz as i32, 1)
+    } else {
+        Value::Rational(Fraction::from_f64(a4_hz))
+    }
+}
+
+// WASM bindings for JavaScript interop
+
+/// Set the default A4 frequency used when no A4 is passed
+#[wasm_bindgen(js_name = setDefaultA4)]
+pub fn set_default_a4_js(hz: f64) {
+    set_default_a4(hz);
+}
+
+/// Get the default A4 frequency
+#[wasm_bindgen(js_name = getDefaultA4)]
+pub fn get_default_a4_js() -> f64 {
+    default_a4()
+}
+
+/// Convert a frequency to { note, cents } (uses the default A4 when omitted)
+#[wasm_bindgen(js_name = freqToMidi)]
+pub fn freq_to_midi_js(freq: f64, a4: Option<f64>) -> JsValue {
+    let (note, cents) = freq_to_midi(freq, a4.unwrap_or_else(default_a4));
+    serde_wasm_bindgen::to_value(&MidiPosition { note, cents }).unwrap_or(JsValue::NULL)
+}
+
+/// Convert a MIDI note to a frequency (uses the default A4 when omitted)
+#[wasm_bindgen(js_name = midiToFreq)]
+pub fn midi_to_freq_js(note: i32, a4: Option<f64>) -> f64 {
+    midi_to_freq(note, a4.unwrap_or_else(default_a4))
+}
+
+/// Size of a frequency ratio in cents
+#[wasm_bindgen(js_name = ratioToCents)]
+pub fn ratio_to_cents_js(ratio: &Fraction) -> f64 {
+    ratio_to_cents(ratio)
+}
+
+/// Best rational approximation of a cents value with denominator ≤ maxDen
+#[wasm_bindgen(js_name = centsToRatio)]
+pub fn cents_to_ratio_js(cents: f64, max_den: u32) -> Fraction {
+    cents_to_ratio(cents, max_den as u64)
+}
+
+/// Size of a ValueData frequency ratio in cents, exact for base-2 symbolic values
+#[wasm_bindgen(js_name = valueToCents)]
+pub fn value_to_cents_js(value: JsValue) -> Result<f64, JsValue> {
+    Ok(value_from_js(value)?.to_cents())
+}
+
+/// Interval in cents from one ValueData to another
+#[wasm_bindgen(js_name = centsBetween)]
+pub fn cents_between_js(from: JsValue, to: JsValue) -> Result<f64, JsValue> {
+    Ok(value_from_js(from)?.cents_between(&value_from_js(to)?))
+}
+
+/// Convert a ValueData frequency to { note, cents }, exact for base-2 symbolic values
+/// A4 is given in Hz (uses the default A4 when omitted)
+#[wasm_bindgen(js_name = valueToMidi)]
+pub fn value_to_midi_js(value: JsValue, a4: Option<f64>) -> Result<JsValue, JsValue> {
+    let value = value_from_js(value)?;
+    let (note, cents) = value_to_midi(&value, &a4_value(a4));
+    serde_wasm_bindgen::to_value(&MidiPosition { note, cents })
+        .map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_freq_to_midi_a4() {
+        assert_eq!(freq_to_midi(440.0, 440.0), (69, 0.0));
+        let (note, cents) = freq_to_midi(880.0, 440.0);
+        assert_eq!(note, 81);
+        assert!(cents.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_midi_to_freq() {
+        assert_eq!(midi_to_freq(69, 440.0), 440.0);
+        assert!((midi_to_freq(60, 440.0) - 261.6255653).abs() < 1e-6);
+        assert!((midi_to_freq(69, 432.0) - 432.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_value_to_midi_symbolic_exact() {
+        let semitone = Value::rational(2, 1).pow(&Value::rational(1, 12));
+        let freq = Value::rational(440, 1).mul(&semitone);
+        assert!(freq.is_symbolic());
+
+        let (note, cents) = value_to_midi(&freq, &Value::rational(440, 1));
+        assert_eq!(note, 70);
+        assert_eq!(cents, 0.0);
+    }
+
+    #[test]
+    fn test_just_major_third_offset() {
+        let freq = Value::rational(440, 1).mul(&Value::rational(5, 4));
+        let (note, cents) = value_to_midi(&freq, &Value::rational(440, 1));
+        assert_eq!(note, 73);
+        assert!((cents - (-13.686)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_ratio_to_cents() {
+        assert!((ratio_to_cents(&Fraction::new(3, 2)) - 701.955).abs() < 1e-3);
+        assert_eq!(ratio_to_cents(&Fraction::new(4, 1)), 2400.0);
+        assert!(ratio_to_cents(&Fraction::new(-1, 2)).is_nan());
+    }
+
+    #[test]
+    fn test_cents_to_ratio() {
+        assert_eq!(cents_to_ratio(701.955, 10), Fraction::new(3, 2));
+        assert_eq!(cents_to_ratio(1200.0, 100), Fraction::new(2, 1));
+        assert_eq!(cents_to_ratio(386.3137, 16), Fraction::new(5, 4));
+    }
+
+    #[test]
+    fn test_cents_helpers() {
+        assert!((Fraction::new(3, 2).to_cents() - 701.955).abs() < 1e-3);
+        assert_eq!(Fraction::from_cents(701.955, 10), Fraction::new(3, 2));
+
+        let fifth = Value::rational(2, 1).pow(&Value::rational(7, 12));
+        assert!(fifth.is_symbolic());
+        assert_eq!(fifth.to_cents(), 700.0);
+
+        let tritave_step = Value::rational(3, 1).pow(&Value::rational(1, 13));
+        assert!((tritave_step.to_cents() - 1901.955 / 13.0).abs() < 1e-3);
+        assert!((Value::irrational(1.5).to_cents() - 701.955).abs() < 1e-3);
+        assert!(Value::irrational(-1.5).to_cents().is_nan());
+
+        let a4 = Value::rational(440, 1);
+        let e5 = a4.mul(&fifth);
+        assert_eq!(a4.cents_between(&e5), 700.0);
+        assert_eq!(e5.cents_between(&a4), -700.0);
+    }
+
+    #[test]
+    fn test_default_a4() {
+        assert_eq!(default_a4(), DEFAULT_A4);
+        set_default_a4(432.0);
+        assert_eq!(default_a4(), 432.0);
+        set_default_a4(-1.0);
+        assert_eq!(default_a4(), 432.0);
+        set_default_a4(DEFAULT_A4);
+    }
+}
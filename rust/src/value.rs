@@ -2,16 +2,23 @@
 //!
 //! Provides a Value enum that can hold:
 //! - Rational: exact rational (Fraction)
-//! - Irrational: f64 approximation (legacy)
+//! - Irrational: f64 approximation (legacy), with an optional error bound
 //! - Symbolic: algebraic structure preserving base^exponent form
 //!
 //! This enables multi-base TET scale support via expressions like 2^(1/12), 3^(1/13)
 //! while preserving exact rational arithmetic and symbolic form when possible.
 
+use crate::bytecode::{is_custom_var, CUSTOM_VAR_FIRST};
+use crate::format::format_f64_js;
 use crate::fraction::Fraction;
-use num_traits::ToPrimitive;
+use num_bigint::{BigInt, BigUint};
+use num_integer::Integer;
+use num_traits::{One, Signed, ToPrimitive, Zero};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
+use std::iter::{Product, Sum};
+use wasm_bindgen::prelude::*;
 
 // ============================================================================
 // SymbolicPower - preserves algebraic structure of power expressions
@@ -48,7 +55,7 @@ impl SymbolicPower {
     /// Create from a single base^exponent
     pub fn from_power(base: u32, exponent: Fraction) -> Self {
         SymbolicPower {
-            coefficient: Fraction::new(1, 1),
+            coefficient: Fraction::one(),
             powers: vec![PowerTerm { base, exponent }],
         }
     }
@@ -72,7 +79,7 @@ impl SymbolicPower {
 
     /// Check if this is purely rational (no irrational power terms)
     pub fn is_rational(&self) -> bool {
-        self.powers.is_empty() || self.powers.iter().all(|p| p.exponent.d() == 1)
+        self.powers.iter().all(|p| p.exponent.is_integer())
     }
 
     /// If rational, convert to Fraction; otherwise return None
@@ -83,28 +90,43 @@ impl SymbolicPower {
 
         let mut result = self.coefficient.clone();
         for p in &self.powers {
-            // exp.d() is 1, so this is an integer power
-            let int_exp = p.exponent.s() * (p.exponent.n() as i32);
-            if int_exp >= 0 {
-                let base_pow = (p.base as i64).pow(int_exp as u32);
-                result = result.mul(&Fraction::new(base_pow as i32, 1));
-            } else {
-                let base_pow = (p.base as i64).pow((-int_exp) as u32);
-                result = result.div(&Fraction::new(base_pow as i32, 1));
-            }
+            let exp = p.exponent.to_big_rational();
+            let factor = bounded_power(&Fraction::new_raw(p.base as i64, 1), exp.numer())?;
+            result = result.mul(&factor);
         }
         Some(result)
     }
 
-    /// Normalize: sort powers by base, remove zero exponents
+    /// Normalize: move integer powers into the coefficient, sort powers by
+    /// base, remove zero exponents
     pub fn normalize(mut self) -> Self {
+        self.canonicalize();
         // Filter out zero exponents
-        self.powers.retain(|p| p.exponent.n() != 0);
+        self.powers.retain(|p| !p.exponent.is_zero());
         // Sort by base
         self.powers.sort_by_key(|p| p.base);
         self
     }
 
+    /// Split each exponent into its integer part (truncated toward zero),
+    /// multiplied exactly into the coefficient, and a proper fraction kept
+    /// as the exponent: 2^(13/12) becomes 2 × 2^(1/12) and 2^(-13/12)
+    /// becomes 1/2 × 2^(-1/12). Integer parts too large to multiply out
+    /// exactly (see `MAX_EXACT_POWER_BITS`) stay in the exponent.
+    pub fn canonicalize(&mut self) {
+        for p in &mut self.powers {
+            let whole = p.exponent.trunc();
+            if whole.is_zero() {
+                continue;
+            }
+            let Some(factor) = bounded_power(&Fraction::new_raw(p.base as i64, 1), whole.to_big_rational().numer()) else {
+                continue;
+            };
+            self.coefficient = self.coefficient.mul(&factor);
+            p.exponent = p.exponent.sub(&whole);
+        }
+    }
+
     /// Multiply two SymbolicPower values
     /// Combines like-base powers: base^a × base^b = base^(a+b)
     pub fn mul(&self, other: &SymbolicPower) -> SymbolicPower {
@@ -128,7 +150,7 @@ impl SymbolicPower {
         // Filter out zero exponents
         let new_powers: Vec<PowerTerm> = power_map
             .into_iter()
-            .filter(|(_, exp)| exp.n() != 0)
+            .filter(|(_, exp)| !exp.is_zero())
             .map(|(base, exponent)| PowerTerm { base, exponent })
             .collect();
 
@@ -157,7 +179,7 @@ impl SymbolicPower {
 
         let new_powers: Vec<PowerTerm> = power_map
             .into_iter()
-            .filter(|(_, exp)| exp.n() != 0)
+            .filter(|(_, exp)| !exp.is_zero())
             .map(|(base, exponent)| PowerTerm { base, exponent })
             .collect();
 
@@ -171,7 +193,7 @@ impl SymbolicPower {
         let new_coeff = if let Some(result) = try_rational_power(&self.coefficient, exponent) {
             result
         } else {
-            Fraction::from_f64(self.coefficient.to_f64().powf(exponent.to_f64()))
+            Fraction::from_f64(real_powf(self.coefficient.to_f64(), exponent))
         };
 
         let new_powers: Vec<PowerTerm> = self
@@ -186,6 +208,16 @@ impl SymbolicPower {
         SymbolicPower::new(new_coeff, new_powers).normalize()
     }
 
+    /// Check if both have identical power terms (compare after `normalize`)
+    pub fn same_powers(&self, other: &SymbolicPower) -> bool {
+        self.powers.len() == other.powers.len()
+            && self
+                .powers
+                .iter()
+                .zip(&other.powers)
+                .all(|(p, q)| p.base == q.base && p.exponent == q.exponent)
+    }
+
     /// Multiply by a rational Fraction
     pub fn mul_rational(&self, frac: &Fraction) -> SymbolicPower {
         SymbolicPower::new(
@@ -205,20 +237,115 @@ pub enum Value {
     /// Exact rational number (no precision loss)
     Rational(Fraction),
     /// Irrational number (f64 approximation) - legacy
-    Irrational(f64),
+    ///
+    /// `err` bounds the distance from the exact result when error tracking
+    /// is on (see `track_error`); 0 means untracked.
+    Irrational { value: f64, err: f64 },
     /// Symbolic power expression (preserves algebraic structure)
     Symbolic(SymbolicPower),
 }
 
+/// What evaluation does with a NaN or infinite irrational result
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NonFinitePolicy {
+    /// Replace the value with an irrational 0
+    #[default]
+    Zero,
+    /// Fail the evaluation
+    Error,
+}
+
+impl std::str::FromStr for NonFinitePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<NonFinitePolicy, String> {
+        match s {
+            "zero" => Ok(NonFinitePolicy::Zero),
+            "error" => Ok(NonFinitePolicy::Error),
+            _ => Err(format!("Unknown non-finite policy '{}' (expected zero or error)", s)),
+        }
+    }
+}
+
 impl Value {
     /// Create a rational value from numerator and denominator
     pub fn rational(num: i32, den: i32) -> Value {
         Value::Rational(Fraction::new(num, den))
     }
 
-    /// Create an irrational value from f64
+    /// Rational zero (the default value)
+    pub fn zero() -> Value {
+        Value::Rational(Fraction::zero())
+    }
+
+    /// Rational one
+    pub fn one() -> Value {
+        Value::Rational(Fraction::one())
+    }
+
+    /// Create an irrational value from f64; NaN and infinities become 0
+    /// (the `NonFinitePolicy::Zero` fallback)
     pub fn irrational(v: f64) -> Value {
-        Value::Irrational(v)
+        Value::inexact(if v.is_finite() { v } else { 0.0 }, 0.0)
+    }
+
+    /// An irrational value with error bound `err`, kept as given
+    pub fn inexact(value: f64, err: f64) -> Value {
+        Value::Irrational { value, err }
+    }
+
+    /// Error bound of an irrational value: 0 for exact values and for
+    /// irrationals that are not tracked
+    pub fn error_bound(&self) -> f64 {
+        match self {
+            Value::Irrational { err, .. } => *err,
+            _ => 0.0,
+        }
+    }
+
+    /// Start tracking the error of an untracked irrational value
+    ///
+    /// The bound is `SEED_RELATIVE_ERROR` of the value, enough for the few
+    /// roundings of the operation that produced it. From then on add, sub,
+    /// mul and div propagate the bound with interval rules, and other
+    /// operations on a tracked value give an infinite bound. Exact and
+    /// already tracked values are returned unchanged.
+    pub fn track_error(self) -> Value {
+        match self {
+            Value::Irrational { value, err: 0.0 } => {
+                Value::inexact(value, (value.abs() * SEED_RELATIVE_ERROR).max(f64::MIN_POSITIVE))
+            }
+            other => other,
+        }
+    }
+
+    /// Error of this value as an f64 operand: its bound if irrational, and
+    /// the rounding of `to_f64` for exact values
+    fn operand_error(&self) -> f64 {
+        match self {
+            Value::Irrational { err, .. } => *err,
+            _ => self.to_f64().abs() * SEED_RELATIVE_ERROR,
+        }
+    }
+
+    /// Irrational result of an f64 operation on `operands`, with a bound
+    /// from `bound(|a|, err_a, |b|, err_b)` plus the rounding of the result
+    /// when any operand is tracked
+    fn propagate(operands: (&Value, &Value), value: f64, bound: fn(f64, f64, f64, f64) -> f64) -> Value {
+        let (a, b) = operands;
+        if a.error_bound() == 0.0 && b.error_bound() == 0.0 {
+            return Value::inexact(value, 0.0);
+        }
+        let err = bound(a.to_f64().abs(), a.operand_error(), b.to_f64().abs(), b.operand_error());
+        Value::inexact(value, err + value.abs() * UNIT_ROUNDOFF)
+    }
+
+    /// Irrational result of an operation without a propagation rule: an
+    /// infinite bound when either operand is tracked
+    fn unbounded(operands: (&Value, &Value), value: f64) -> Value {
+        let tracked = operands.0.error_bound() != 0.0 || operands.1.error_bound() != 0.0;
+        let err = if tracked { f64::INFINITY } else { 0.0 };
+        Value::inexact(value, err)
     }
 
     /// Create a symbolic value from a SymbolicPower
@@ -231,9 +358,27 @@ impl Value {
         Value::Rational(f)
     }
 
+    /// False only for a NaN or infinite irrational (rational and symbolic
+    /// values are exact)
+    pub fn is_finite(&self) -> bool {
+        match self {
+            Value::Irrational { value, .. } => value.is_finite(),
+            _ => true,
+        }
+    }
+
+    /// Apply `policy` to a non-finite value; finite values pass through
+    pub fn enforce_finite(self, policy: NonFinitePolicy) -> Result<Value, String> {
+        match (self.is_finite(), policy) {
+            (true, _) => Ok(self),
+            (false, NonFinitePolicy::Zero) => Ok(Value::inexact(0.0, 0.0)),
+            (false, NonFinitePolicy::Error) => Err(format!("Non-finite result {}", self)),
+        }
+    }
+
     /// Check if this value is corrupted (irrational or symbolic)
     pub fn is_corrupted(&self) -> bool {
-        matches!(self, Value::Irrational(_) | Value::Symbolic(_))
+        matches!(self, Value::Irrational { .. } | Value::Symbolic(_))
     }
 
     /// Check if this value is rational (not corrupted)
@@ -251,7 +396,7 @@ impl Value {
         match self {
             Value::Symbolic(sp) => sp.clone(),
             Value::Rational(f) => SymbolicPower::from_rational(f.clone()),
-            Value::Irrational(v) => SymbolicPower::from_rational(Fraction::from_f64(*v)),
+            Value::Irrational { value, .. } => SymbolicPower::from_rational(Fraction::from_f64(*value)),
         }
     }
 
@@ -259,7 +404,7 @@ impl Value {
     pub fn to_f64(&self) -> f64 {
         match self {
             Value::Rational(f) => f.to_f64(),
-            Value::Irrational(v) => *v,
+            Value::Irrational { value, .. } => *value,
             Value::Symbolic(sp) => sp.to_f64(),
         }
     }
@@ -268,7 +413,7 @@ impl Value {
     pub fn as_fraction(&self) -> Option<&Fraction> {
         match self {
             Value::Rational(f) => Some(f),
-            Value::Irrational(_) => None,
+            Value::Irrational { .. } => None,
             Value::Symbolic(_) => None,
         }
     }
@@ -277,7 +422,7 @@ impl Value {
     pub fn to_fraction(&self) -> Fraction {
         match self {
             Value::Rational(f) => f.clone(),
-            Value::Irrational(v) => Fraction::from_f64(*v),
+            Value::Irrational { value, .. } => Fraction::from_f64(*value),
             Value::Symbolic(sp) => {
                 // If symbolic is actually rational, return exact value
                 if let Some(rational) = sp.to_rational_fraction() {
@@ -290,20 +435,64 @@ impl Value {
     }
 
     /// Add two values
-    /// Note: Addition of different symbolic forms falls back to irrational
+    /// Like symbolic terms combine exactly: 5·2^(1/12) + 3·2^(1/12) = 8·2^(1/12)
+    /// Note: Addition of different symbolic forms falls back to irrational,
+    /// but adding an exact 0 leaves any value as it is
     pub fn add(&self, other: &Value) -> Value {
         match (self, other) {
             (Value::Rational(a), Value::Rational(b)) => Value::Rational(a.add(b)),
-            // Symbolic addition is complex - fall back to irrational for now
-            _ => Value::Irrational(self.to_f64() + other.to_f64()),
+            (x, Value::Rational(zero)) | (Value::Rational(zero), x) if zero.is_zero() => x.clone(),
+            _ => self
+                .combine_like_terms(other, Fraction::add)
+                .unwrap_or_else(|| {
+                    Value::propagate((self, other), self.to_f64() + other.to_f64(), |_, ea, _, eb| ea + eb)
+                }),
         }
     }
 
     /// Subtract two values
+    /// Like symbolic terms combine exactly, as in `add`
     pub fn sub(&self, other: &Value) -> Value {
         match (self, other) {
             (Value::Rational(a), Value::Rational(b)) => Value::Rational(a.sub(b)),
-            _ => Value::Irrational(self.to_f64() - other.to_f64()),
+            (x, Value::Rational(zero)) if zero.is_zero() => x.clone(),
+            _ => self
+                .combine_like_terms(other, Fraction::sub)
+                .unwrap_or_else(|| {
+                    Value::propagate((self, other), self.to_f64() - other.to_f64(), |_, ea, _, eb| ea + eb)
+                }),
+        }
+    }
+
+    /// Combine the coefficients of two values with identical power terms
+    /// (a rational counts as a coefficient with no powers); None if the
+    /// powers differ or either value is irrational
+    fn combine_like_terms(
+        &self,
+        other: &Value,
+        op: fn(&Fraction, &Fraction) -> Fraction,
+    ) -> Option<Value> {
+        let (a, b) = (self.exact_terms()?, other.exact_terms()?);
+        if !a.same_powers(&b) {
+            return None;
+        }
+        let coefficient = op(&a.coefficient, &b.coefficient);
+        if a.powers.is_empty() || coefficient.is_zero() {
+            return Some(Value::Rational(coefficient));
+        }
+        Some(Value::Symbolic(SymbolicPower::new(coefficient, a.powers)))
+    }
+
+    /// Normalized symbolic form of an exact value, with symbolic values that
+    /// are actually rational folded into the coefficient
+    fn exact_terms(&self) -> Option<SymbolicPower> {
+        match self {
+            Value::Rational(f) => Some(SymbolicPower::from_rational(f.clone())),
+            Value::Irrational { .. } => None,
+            Value::Symbolic(sp) => Some(match sp.to_rational_fraction() {
+                Some(rational) => SymbolicPower::from_rational(rational),
+                None => sp.clone().normalize(),
+            }),
         }
     }
 
@@ -335,7 +524,9 @@ impl Value {
             }
 
             // Rational * irrational or irrational * irrational: fall back to f64
-            _ => Value::Irrational(self.to_f64() * other.to_f64()),
+            _ => Value::propagate((self, other), self.to_f64() * other.to_f64(), |a, ea, b, eb| {
+                a * eb + b * ea + ea * eb
+            }),
         }
     }
 
@@ -356,6 +547,8 @@ impl Value {
                 }
                 Value::Symbolic(result)
             }
+            // Division by zero gives 1, as for rationals
+            (Value::Symbolic(_), Value::Rational(f)) if f.is_zero() => Value::Rational(Fraction::one()),
             (Value::Symbolic(sp), Value::Rational(f)) => {
                 let result = sp.mul_rational(&f.inverse());
                 if result.is_rational() {
@@ -380,19 +573,71 @@ impl Value {
             _ => {
                 let divisor = other.to_f64();
                 if divisor == 0.0 {
-                    Value::Rational(Fraction::new(1, 1))
+                    Value::Rational(Fraction::one())
                 } else {
-                    Value::Irrational(self.to_f64() / divisor)
+                    Value::propagate((self, other), self.to_f64() / divisor, |a, ea, b, eb| {
+                        // Unbounded once the divisor's interval reaches zero
+                        if eb >= b {
+                            f64::INFINITY
+                        } else {
+                            (a * eb + b * ea) / (b * (b - eb))
+                        }
+                    })
                 }
             }
         }
     }
 
+    /// Remainder with the sign of the dividend (see `Fraction::rem`)
+    /// Exact when both operands are rational; modulo by zero returns the dividend
+    pub fn rem(&self, other: &Value) -> Value {
+        match (self, other) {
+            (Value::Rational(a), Value::Rational(b)) => Value::Rational(a.rem(b)),
+
+            // Any symbolic or irrational operand: fall back to f64 (Rust's % matches JS)
+            _ => {
+                let divisor = other.to_f64();
+                if divisor == 0.0 {
+                    self.clone()
+                } else {
+                    Value::unbounded((self, other), self.to_f64() % divisor)
+                }
+            }
+        }
+    }
+
+    /// True if `self` is strictly less than `other`
+    /// Exact when both are rational, via f64 otherwise
+    fn less_than(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Rational(a), Value::Rational(b)) => a < b,
+            _ => self.to_f64() < other.to_f64(),
+        }
+    }
+
+    /// The smaller operand, returned unchanged (`self` when equal)
+    pub fn min(&self, other: &Value) -> Value {
+        if other.less_than(self) {
+            other.clone()
+        } else {
+            self.clone()
+        }
+    }
+
+    /// The larger operand, returned unchanged (`self` when equal)
+    pub fn max(&self, other: &Value) -> Value {
+        if self.less_than(other) {
+            other.clone()
+        } else {
+            self.clone()
+        }
+    }
+
     /// Negate the value
     pub fn neg(&self) -> Value {
         match self {
             Value::Rational(f) => Value::Rational(f.neg()),
-            Value::Irrational(v) => Value::Irrational(-v),
+            Value::Irrational { value, err } => Value::inexact(-value, *err),
             Value::Symbolic(sp) => Value::Symbolic(sp.mul_rational(&Fraction::new(-1, 1))),
         }
     }
@@ -403,41 +648,163 @@ impl Value {
     /// - 2^(2/1) = 4 (rational)
     /// - 2^(1/12) = symbolic (preserves base and exponent)
     /// - 4^(1/2) = 2 (rational, perfect square root)
+    ///
+    /// A symbolic exponent that is actually rational (1/2 · 2^2) is used as
+    /// that rational; other symbolic and irrational exponents give an f64
+    /// result.
+    ///
+    /// Negative bases take real odd roots: (-8)^(1/3) = -2 and
+    /// (-2)^(1/3) = -2^(1/3). 0^0 = 1 and 0 to a positive power is 0. Even
+    /// roots of negatives and 0 to a negative power have no real value and
+    /// give NaN or infinity, which evaluation replaces according to its
+    /// `NonFinitePolicy` (see `checked_pow` for a typed error instead).
     pub fn pow(&self, exponent: &Value) -> Value {
+        if let Value::Symbolic(sp) = exponent {
+            if let Some(exp) = sp.to_rational_fraction() {
+                return self.pow(&Value::Rational(exp));
+            }
+        }
         match (self, exponent) {
             (Value::Rational(base), Value::Rational(exp)) => {
                 // Check if result can be rational
                 if let Some(result) = try_rational_power(base, exp) {
                     return Value::Rational(result);
                 }
-                // Irrational result: return symbolic for positive integer bases
-                let base_val = base.to_f64();
-                if base_val > 0.0 && base_val == base_val.floor() && base_val <= (u32::MAX as f64) {
-                    return Value::Symbolic(SymbolicPower::from_power(base_val as u32, exp.clone()));
+                // Irrational result: return symbolic for integer bases, with
+                // negative ones only for odd roots: (-b)^(p/q) = (-1)^p · b^(p/q)
+                let exp_big = exp.to_big_rational();
+                let odd_root = exp_big.denom().is_odd();
+                // An integer exponent only gets here when the exact power is
+                // too large to compute, and a symbolic power would try again
+                let base_int = base
+                    .to_i64()
+                    .filter(|_| !exp.is_integer())
+                    .filter(|&b| b > 0 || (b < 0 && odd_root))
+                    .and_then(|b| u32::try_from(b.unsigned_abs()).ok());
+                if let Some(base_int) = base_int {
+                    let mut sp = SymbolicPower::from_power(base_int, exp.clone());
+                    if base.is_negative() && exp_big.numer().is_odd() {
+                        sp.coefficient = -Fraction::one();
+                    }
+                    return Value::Symbolic(sp);
                 }
-                // Non-integer or negative base: fall back to irrational
-                Value::Irrational(base.to_f64().powf(exp.to_f64()))
+                // Non-integer base: fall back to irrational
+                Value::inexact(real_powf(base.to_f64(), exp), 0.0)
             }
             // Symbolic base with rational exponent: raise symbolic to power
             (Value::Symbolic(sp), Value::Rational(exp)) => {
+                // The powers are positive, so a negative coefficient has no
+                // real even root
+                if sp.coefficient.is_negative() && exp.to_big_rational().denom().is_even() {
+                    return Value::inexact(f64::NAN, 0.0);
+                }
                 let result = sp.pow(exp);
                 if result.is_rational() {
-                    if let Some(rational) = result.to_rational_fraction() {
-                        return Value::Rational(rational);
-                    }
+                    return match result.to_rational_fraction() {
+                        Some(rational) => Value::Rational(rational),
+                        None => Value::inexact(result.to_f64(), 0.0),
+                    };
                 }
                 Value::Symbolic(result)
             }
             // Fall back to irrational for other cases
-            _ => Value::Irrational(self.to_f64().powf(exponent.to_f64())),
+            _ => Value::unbounded((self, exponent), self.to_f64().powf(exponent.to_f64())),
         }
     }
 
+    /// `pow`, but an error when the result has no real value
+    ///
+    /// The error is read from the exact signs of the operands and the
+    /// exponent's denominator, not from the f64 result, so a negative base
+    /// too small or too large for an f64 is still `EvenRootOfNegative`. A
+    /// negative base to an irrational power counts as an even root; a
+    /// non-finite result otherwise is `Overflow`.
+    pub fn checked_pow(&self, exponent: &Value) -> Result<Value, PowError> {
+        let exact_exponent = match exponent {
+            Value::Rational(exp) => Some(exp.clone()),
+            Value::Symbolic(sp) => sp.to_rational_fraction(),
+            Value::Irrational { .. } => None,
+        };
+        let is_zero = match self {
+            Value::Rational(f) => f.is_zero(),
+            Value::Irrational { value, .. } => *value == 0.0,
+            Value::Symbolic(sp) => sp.coefficient.is_zero(),
+        };
+        if is_zero && exponent.sign() < 0 {
+            return Err(PowError::ZeroToNegativePower);
+        }
+        let even_root = exact_exponent.is_none_or(|exp| exp.to_big_rational().denom().is_even());
+        if self.sign() < 0 && even_root {
+            return Err(PowError::EvenRootOfNegative);
+        }
+        let result = self.pow(exponent);
+        if result.is_finite() {
+            Ok(result)
+        } else {
+            Err(PowError::Overflow)
+        }
+    }
+
+    /// Square root (see `nth_root`)
+    pub fn sqrt(&self) -> Value {
+        self.nth_root(2)
+    }
+
+    /// n-th root, computed as `pow(1/n)`: perfect roots stay rational
+    /// (root(8, 3) = 2) and other roots of positive integers stay symbolic
+    ///
+    /// The 0th root is undefined and gives NaN.
+    pub fn nth_root(&self, n: u32) -> Value {
+        if n == 0 {
+            return Value::inexact(f64::NAN, 0.0);
+        }
+        self.pow(&Value::Rational(Fraction::new_raw(1, n as i64)))
+    }
+
+    /// Logarithm of this value to `base`
+    ///
+    /// Exact whenever this value is a rational power of the base, so
+    /// log2(2^(7/12)) is the Rational 7/12, log_(3/2)(27/8) is 3 and the
+    /// log of 2^(7/12) to the base 2^(1/12) is 7. Anything else falls back
+    /// to an f64 logarithm (NaN unless both are positive and base ≠ 1).
+    pub fn log(&self, base: &Value) -> Value {
+        if let (Some(x), Some(b)) = (self.prime_exponents(), base.prime_exponents()) {
+            if let Some(exact) = exponent_ratio(&x, &b) {
+                return Value::Rational(exact);
+            }
+        }
+        Value::unbounded((self, base), self.to_f64().ln() / base.to_f64().ln())
+    }
+
+    /// Exponent of each prime in a positive exact value, with rational
+    /// exponents for symbolic powers (2^(1/12)·3 is {2: 1/12, 3: 1}); None for
+    /// irrational or non-positive values and for factors beyond `LOG_PRIME_BOUND`
+    fn prime_exponents(&self) -> Option<BTreeMap<u64, Fraction>> {
+        let terms = self.exact_terms()?;
+        if !terms.coefficient.is_positive() {
+            return None;
+        }
+        let mut exponents = BTreeMap::new();
+        let mut add = |factor: &Fraction, power: &Fraction| -> Option<()> {
+            for (prime, e) in factor.factorize(LOG_PRIME_BOUND)? {
+                let total = exponents.entry(prime).or_insert_with(Fraction::zero);
+                *total = total.add(&power.mul(&Fraction::new(e, 1)));
+            }
+            Some(())
+        };
+        add(&terms.coefficient, &Fraction::one())?;
+        for p in &terms.powers {
+            add(&Fraction::new_raw(p.base as i64, 1), &p.exponent)?;
+        }
+        exponents.retain(|_, e| !e.is_zero());
+        Some(exponents)
+    }
+
     /// Get the absolute value
     pub fn abs(&self) -> Value {
         match self {
             Value::Rational(f) => Value::Rational(f.abs()),
-            Value::Irrational(v) => Value::Irrational(v.abs()),
+            Value::Irrational { value, err } => Value::inexact(value.abs(), *err),
             Value::Symbolic(sp) => {
                 // For symbolic, if coefficient is negative, negate it
                 if sp.coefficient.s() < 0 {
@@ -449,76 +816,470 @@ impl Value {
         }
     }
 
+    /// Sign as a Rational: -1, 0 or 1
+    ///
+    /// Symbolic powers have positive bases, so their sign is the
+    /// coefficient's. A NaN irrational gives 0.
+    pub fn signum(&self) -> Value {
+        Value::rational(self.sign(), 1)
+    }
+
+    /// -1, 0 or 1 (see `signum`)
+    fn sign(&self) -> i32 {
+        match self {
+            Value::Rational(f) => f.s(),
+            Value::Irrational { value, .. } if *value > 0.0 => 1,
+            Value::Irrational { value, .. } if *value < 0.0 => -1,
+            Value::Irrational { .. } => 0,
+            Value::Symbolic(sp) => sp.coefficient.s(),
+        }
+    }
+
+    /// Largest integer less than or equal to this value (see `to_integer`)
+    pub fn floor(&self) -> Value {
+        self.to_integer(Fraction::floor, f64::floor)
+    }
+
+    /// Smallest integer greater than or equal to this value (see `to_integer`)
+    pub fn ceil(&self) -> Value {
+        self.to_integer(Fraction::ceil, f64::ceil)
+    }
+
+    /// Nearest integer, rounding halves away from zero (see `to_integer`)
+    pub fn round(&self) -> Value {
+        self.to_integer(Fraction::round, f64::round)
+    }
+
+    /// An integer picked by `exact` for rational values (including symbolic
+    /// values that are actually rational) and by `approx` on the f64 of the
+    /// rest; the result is always Rational, except NaN for a non-finite f64
+    fn to_integer(&self, exact: fn(&Fraction) -> Fraction, approx: fn(f64) -> f64) -> Value {
+        if let Some(terms) = self.exact_terms().filter(|terms| terms.powers.is_empty()) {
+            return Value::Rational(exact(&terms.coefficient));
+        }
+        match Fraction::from_f64_exact(approx(self.to_f64())) {
+            Ok(integer) => Value::Rational(integer),
+            Err(_) => Value::inexact(f64::NAN, 0.0),
+        }
+    }
+
     /// Get the reciprocal (1/x)
     pub fn inverse(&self) -> Value {
         match self {
             Value::Rational(f) => Value::Rational(f.inverse()),
-            Value::Irrational(v) => {
-                if *v == 0.0 {
-                    Value::Rational(Fraction::new(1, 1))
+            Value::Irrational { value, .. } => {
+                if *value == 0.0 {
+                    Value::Rational(Fraction::one())
                 } else {
-                    Value::Irrational(1.0 / v)
+                    Value::one().div(self)
                 }
             }
             Value::Symbolic(sp) => {
-                let one = SymbolicPower::from_rational(Fraction::new(1, 1));
+                let one = SymbolicPower::from_rational(Fraction::one());
                 Value::Symbolic(one.div(sp))
             }
         }
     }
-}
 
-/// Try to compute base^(num/den) as a rational if possible
-fn try_rational_power(base: &Fraction, exp: &Fraction) -> Option<Fraction> {
-    let exp_num = exp.as_big_rational().numer().to_i64()?;
-    let exp_den = exp.as_big_rational().denom().to_i64()?;
+    /// Size of this frequency ratio in cents (1200·log2(value)); NaN unless positive
+    ///
+    /// Symbolic values are summed term by term (exponent·1200·log2(base)) so
+    /// powers are never evaluated in f64, and powers of two are exact:
+    /// 2^(7/12) is exactly 700 cents.
+    pub fn to_cents(&self) -> f64 {
+        if let Some(octaves) = crate::tuning::exact_log2(self) {
+            return octaves.mul(&Fraction::new(1200, 1)).to_f64();
+        }
+        match self {
+            Value::Rational(f) => f.to_cents(),
+            Value::Irrational { value, .. } if *value > 0.0 => value.log2() * 1200.0,
+            Value::Irrational { .. } => f64::NAN,
+            Value::Symbolic(sp) => {
+                let mut cents = sp.coefficient.to_cents();
+                for p in &sp.powers {
+                    let exponent_cents = p.exponent.mul(&Fraction::new(1200, 1)).to_f64();
+                    cents += exponent_cents * (p.base as f64).log2();
+                }
+                cents
+            }
+        }
+    }
+
+    /// Interval in cents from this value up to `other`
+    pub fn cents_between(&self, other: &Value) -> f64 {
+        other.div(self).to_cents()
+    }
+
+    /// True if the values differ by at most `epsilon`
+    ///
+    /// Two rationals are compared exactly against the exact value of
+    /// `epsilon`; f64 is used only when a symbolic or irrational operand is
+    /// involved. A NaN epsilon never matches.
+    pub fn approx_eq(&self, other: &Value, epsilon: f64) -> bool {
+        match (self, other) {
+            (Value::Rational(a), Value::Rational(b)) => {
+                // Settle it in f64 when the difference is clear of epsilon
+                // by more than rounding, and build the exact epsilon only
+                // when it is not
+                let (x, y) = (a.to_f64(), b.to_f64());
+                if x.is_finite() && y.is_finite() && !epsilon.is_nan() {
+                    let diff = (x - y).abs();
+                    let slack = (x.abs() + y.abs()) * 4.0 * f64::EPSILON + f64::MIN_POSITIVE;
+                    if diff + slack < epsilon {
+                        return true;
+                    }
+                    if diff - slack > epsilon {
+                        return false;
+                    }
+                }
+                match Fraction::from_f64_exact(epsilon) {
+                    Ok(eps) => a.approx_eq(b, &eps),
+                    Err(_) => epsilon == f64::INFINITY,
+                }
+            }
+            _ => (self.to_f64() - other.to_f64()).abs() <= epsilon,
+        }
+    }
+
+    /// Compare two values, returning -1, 0 or 1
+    ///
+    /// Exact when both are rational, or when both have the same power terms
+    /// (only the coefficients differ). Otherwise the f64 values are compared
+    /// and treated as equal when within a relative `COMPARE_EPSILON`, so
+    /// 2^(1/2) and 99/70 still compare as different. NaN sorts after every
+    /// number and equal to itself, so sorting is deterministic.
+    pub fn compare(&self, other: &Value) -> i32 {
+        if let (Value::Rational(a), Value::Rational(b)) = (self, other) {
+            return a.compare(b);
+        }
+        if let (Some(a), Some(b)) = (self.exact_terms(), other.exact_terms()) {
+            // The shared power product is positive, so it keeps the order
+            if a.same_powers(&b) {
+                return a.coefficient.compare(&b.coefficient);
+            }
+        }
+        let (a, b) = (self.to_f64(), other.to_f64());
+        match (a.is_nan(), b.is_nan()) {
+            (true, true) => return 0,
+            (true, false) => return 1,
+            (false, true) => return -1,
+            (false, false) => {}
+        }
+        if a == b || (a - b).abs() <= COMPARE_EPSILON * a.abs().max(b.abs()) {
+            0
+        } else if a < b {
+            -1
+        } else {
+            1
+        }
+    }
+
+    /// Stable 64-bit hash for cache keys, consistent with `==`
+    ///
+    /// FNV-1a over the exact parts of the normalized value, so it does not
+    /// depend on the platform or process: equal values (including a symbolic
+    /// value and the rational it reduces to) always hash the same.
+    pub fn content_hash(&self) -> u64 {
+        let mut hash = FNV_OFFSET;
+        let Some(terms) = self.exact_terms() else {
+            // -0.0 == 0.0, so hash them alike
+            let v = self.to_f64();
+            let bits = if v == 0.0 { 0 } else { v.to_bits() };
+            return fnv1a(fnv1a(hash, b"f"), &bits.to_le_bytes());
+        };
+        let write_fraction = |hash: u64, f: &Fraction| {
+            let (sign, num, den) = f.to_parts();
+            let hash = fnv1a(hash, &sign.to_le_bytes());
+            let hash = fnv1a(fnv1a(hash, num.as_bytes()), b"/");
+            fnv1a(fnv1a(hash, den.as_bytes()), b";")
+        };
+        hash = write_fraction(fnv1a(hash, b"q"), &terms.coefficient);
+        for p in &terms.powers {
+            hash = fnv1a(hash, &p.base.to_le_bytes());
+            hash = write_fraction(fnv1a(hash, b"^"), &p.exponent);
+        }
+        hash
+    }
+
+    /// Simplest rational within `max_cents` of this frequency ratio, with
+    /// denominator at most `max_den` (2^(7/12) snaps to 3/2 within 6 cents)
+    ///
+    /// The result is the fraction with the smallest denominator and numerator
+    /// in the tolerance window, so it also has the lowest Tenney height.
+    /// A rational that already fits `max_den` is returned unchanged. None
+    /// if nothing simple enough is close enough, or if the value is not
+    /// positive.
+    pub fn snap_to_rational(&self, max_cents: f64, max_den: u64) -> Option<Fraction> {
+        if let Value::Rational(f) = self {
+            if f.is_positive() && f.d_u64().is_some_and(|d| d <= max_den) {
+                return Some(f.clone());
+            }
+        }
+        let cents = self.to_cents();
+        if !cents.is_finite() || max_cents.is_nan() || max_cents < 0.0 {
+            return None;
+        }
+        let bound = |c: f64| Fraction::from_f64_exact(2f64.powf(c / 1200.0)).ok();
+        let (lo, hi) = (bound(cents - max_cents)?, bound(cents + max_cents)?);
+        let simplest = Fraction::simplest_between(&lo, &hi);
+        let fits = simplest.d_u64().is_some_and(|d| d <= max_den)
+            && (simplest.to_cents() - cents).abs() <= max_cents;
+        fits.then_some(simplest)
+    }
+
+    /// Fold a frequency ratio into the octave [1, 2), returning the folded
+    /// value and the number of octaves removed; None unless positive
+    ///
+    /// Symbolic values are folded exactly by moving whole octaves out of the
+    /// base-2 exponent (2^(25/12) is 2^(1/12) and 2 octaves), or out of the
+    /// coefficient when there is no base-2 term.
+    pub fn octave_reduce(&self) -> Option<(Value, i64)> {
+        match self {
+            Value::Rational(f) => {
+                let (folded, octaves) = f.octave_reduce()?;
+                Some((Value::Rational(folded), octaves))
+            }
+            Value::Irrational { value, err } => {
+                if !(value.is_finite() && *value > 0.0) {
+                    return None;
+                }
+                // Scaling by a power of two is exact, bound included
+                let octaves = value.log2().floor();
+                Some((Value::inexact(value / octaves.exp2(), err / octaves.exp2()), octaves as i64))
+            }
+            Value::Symbolic(sp) => {
+                let octaves = match crate::tuning::exact_log2(self) {
+                    Some(exact) => exact.floor().to_i64()?,
+                    None => {
+                        let cents = self.to_cents();
+                        if !cents.is_finite() {
+                            return None;
+                        }
+                        (cents / 1200.0).floor() as i64
+                    }
+                };
+                let shift = Fraction::new_raw(octaves, 1);
+                let mut folded = sp.clone();
+                match folded.powers.iter_mut().find(|p| p.base == 2) {
+                    Some(two) => two.exponent = two.exponent.sub(&shift),
+                    None => {
+                        let scale = Fraction::new_raw(2, 1).pow_int(-octaves);
+                        folded.coefficient = folded.coefficient.mul(&scale);
+                    }
+                }
+                Some((Value::Symbolic(folded.normalize()), octaves))
+            }
+        }
+    }
 
-    // Zero exponent: always 1
-    if exp_num == 0 {
-        return Some(Fraction::new(1, 1));
+    /// The `Display` form followed by a decimal approximation with `digits`
+    /// places, as in "440 · 2^(7/12) (≈ 659.255)"
+    ///
+    /// Integers and irrational values are already exact or decimal, so they
+    /// are shown without an approximation.
+    pub fn format_with_approx(&self, digits: usize) -> String {
+        match self {
+            Value::Rational(f) if f.is_integer() => self.to_string(),
+            Value::Irrational { .. } => self.to_string(),
+            _ => format!("{} (≈ {:.*})", self, digits, self.to_f64()),
+        }
     }
 
-    // Integer exponent: always rational
-    if exp_den == 1 {
-        return Some(rational_int_power(base, exp_num));
+    /// Parse an expression such as "2^(7/12)" or "3/2 * 5^(1/4)"
+    ///
+    /// The expression is a `*`-separated product of rational factors (any
+    /// form `Fraction::from_string` accepts) and `base^exponent` terms, where
+    /// the base is a positive integer and the exponent is a rational, in
+    /// parentheses unless it is a plain number ("2^(-1/12)", "3^2").
+    /// Results that are rational reduce to `Rational`. Errors give the
+    /// character position of the offending factor.
+    pub fn from_string(s: &str) -> Result<Value, String> {
+        let mut value = Value::one();
+        let mut start = 0;
+        for factor in s.split('*') {
+            let parsed = parse_factor(factor, start)
+                .map_err(|(pos, reason)| {
+                    let column = s[..pos].chars().count();
+                    format!("Cannot parse '{}' at position {}: {}", s, column, reason)
+                })?;
+            value = value.mul(&parsed);
+            start += factor.len() + 1;
+        }
+        Ok(match value {
+            Value::Symbolic(sp) => {
+                let sp = sp.normalize();
+                match sp.to_rational_fraction() {
+                    Some(rational) if sp.is_rational() => Value::Rational(rational),
+                    None if sp.is_rational() => Value::inexact(sp.to_f64(), 0.0),
+                    _ => Value::Symbolic(sp),
+                }
+            }
+            other => other,
+        })
     }
 
-    // Fractional exponent: check for perfect n-th root
-    // base^(p/q) = (base^p)^(1/q)
-    let base_powered = rational_int_power(base, exp_num);
-    try_perfect_nth_root(&base_powered, exp_den as u64)
+    /// The expression syntax read by `from_string`: "2^(7/12)",
+    /// "3/2 * 5^(1/4)" or a plain fraction
+    ///
+    /// Irrational values are written as decimals, which parse back as the
+    /// exact rational of those digits.
+    pub fn to_expr_string(&self) -> String {
+        match self {
+            Value::Rational(f) => f.to_string(),
+            Value::Irrational { value, .. } => format_f64_js(*value),
+            Value::Symbolic(sp) => {
+                let mut parts = Vec::with_capacity(sp.powers.len() + 1);
+                if !sp.coefficient.is_one() || sp.powers.is_empty() {
+                    parts.push(sp.coefficient.to_string());
+                }
+                parts.extend(sp.powers.iter().map(|p| format!("{}^({})", p.base, p.exponent)));
+                parts.join(" * ")
+            }
+        }
+    }
 }
 
-/// Compute base^n for integer n (negative n gives reciprocal)
-fn rational_int_power(base: &Fraction, n: i64) -> Fraction {
-    if n == 0 {
-        return Fraction::new(1, 1);
+/// Parse one factor of a `Value::from_string` product, starting at byte
+/// `offset`; errors carry the byte position they refer to
+fn parse_factor(factor: &str, offset: usize) -> Result<Value, (usize, String)> {
+    let Some((base, exponent)) = factor.split_once('^') else {
+        let (pos, text) = trimmed_at(factor, offset);
+        if text.is_empty() {
+            return Err((pos, "missing factor".to_string()));
+        }
+        return parse_operand(text, pos).map(Value::Rational);
+    };
+
+    let (base_pos, base_text) = trimmed_at(base, offset);
+    if base_text.is_empty() {
+        return Err((base_pos, "missing base before '^'".to_string()));
+    }
+    let base_value = parse_operand(base_text, base_pos)?;
+    if base_value.to_i64().is_none_or(|b| b <= 0 || b > u32::MAX as i64) {
+        return Err((base_pos, format!("base '{}' is not a positive 32-bit integer", base_text)));
     }
 
-    let abs_n = n.unsigned_abs();
-    let mut result = Fraction::new(1, 1);
+    let (exp_pos, exp_text) = trimmed_at(exponent, offset + base.len() + 1);
+    if exp_text.is_empty() {
+        return Err((exp_pos, "missing exponent after '^'".to_string()));
+    }
+    let exp_value = parse_operand(exp_text, exp_pos)?;
+    Ok(Value::Rational(base_value).pow(&Value::Rational(exp_value)))
+}
 
-    // Use repeated squaring for efficiency
-    let mut current = base.clone();
-    let mut remaining = abs_n;
+/// `text` with surrounding whitespace removed, and the byte position it
+/// now starts at
+fn trimmed_at(text: &str, offset: usize) -> (usize, &str) {
+    let start = text.len() - text.trim_start().len();
+    (offset + start, text.trim())
+}
 
-    while remaining > 0 {
-        if remaining & 1 == 1 {
-            result = result.mul(&current);
+/// A fraction, optionally wrapped in one pair of parentheses
+fn parse_operand(text: &str, pos: usize) -> Result<Fraction, (usize, String)> {
+    let inner = match text.strip_prefix('(') {
+        Some(rest) => rest.strip_suffix(')').ok_or((pos, "unclosed '('".to_string()))?,
+        None if text.ends_with(')') => return Err((pos + text.len() - 1, "unmatched ')'".to_string())),
+        None => text,
+    };
+    if inner.contains(['(', ')', '^']) {
+        return Err((pos, format!("unexpected nesting in '{}'", text)));
+    }
+    inner.trim().parse::<Fraction>().map_err(|_| (pos, format!("invalid number '{}'", text)))
+}
+
+/// Largest prime `Value::log` factors when looking for an exact result
+const LOG_PRIME_BOUND: u64 = 1 << 16;
+
+/// The q with x = q·b for prime exponent vectors, if there is one (None
+/// when b is empty, i.e. the base is 1)
+fn exponent_ratio(x: &BTreeMap<u64, Fraction>, b: &BTreeMap<u64, Fraction>) -> Option<Fraction> {
+    let (prime, b_exp) = b.iter().next()?;
+    let q = x.get(prime).map_or_else(Fraction::zero, |e| e.div(b_exp));
+    let matches = x.len() == b.len()
+        && b.iter().all(|(p, e)| x.get(p).is_some_and(|x_exp| *x_exp == e.mul(&q)));
+    // log of 1 is 0 for any base
+    (matches || x.is_empty()).then_some(q)
+}
+
+/// Largest exact power `try_rational_power` computes, in bits of numerator
+/// plus denominator; larger results are left irrational instead of
+/// grinding through a huge BigInt
+const MAX_EXACT_POWER_BITS: u64 = 1 << 16;
+
+/// Why `Value::checked_pow` has no result
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowError {
+    /// An even root of a negative number, e.g. (-4)^(1/2)
+    EvenRootOfNegative,
+    /// Zero raised to a negative power
+    ZeroToNegativePower,
+    /// The result is too large for an f64
+    Overflow,
+}
+
+impl fmt::Display for PowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PowError::EvenRootOfNegative => write!(f, "Even root of a negative number"),
+            PowError::ZeroToNegativePower => write!(f, "Zero raised to a negative power"),
+            PowError::Overflow => write!(f, "Power too large"),
         }
-        current = current.mul(&current);
-        remaining >>= 1;
     }
+}
 
-    if n < 0 {
-        result.inverse()
-    } else {
-        result
+impl std::error::Error for PowError {}
+
+/// f64 power that takes real odd roots of negative bases, so
+/// (-1.5)^(1/3) is -(1.5^(1/3)) rather than NaN
+fn real_powf(base: f64, exp: &Fraction) -> f64 {
+    let exp_big = exp.to_big_rational();
+    if base < 0.0 && exp_big.denom().is_odd() {
+        let magnitude = (-base).powf(exp.to_f64());
+        return if exp_big.numer().is_odd() { -magnitude } else { magnitude };
+    }
+    base.powf(exp.to_f64())
+}
+
+/// Try to compute base^(num/den) as a rational if possible
+///
+/// Takes the exact den-th root first and then raises it to num, so the
+/// exponent may have BigInt parts: 1^(10^30/7) is still 1. A zero base
+/// gives 1 for a zero exponent, 0 for a positive one and None for a
+/// negative one.
+fn try_rational_power(base: &Fraction, exp: &Fraction) -> Option<Fraction> {
+    if base.is_zero() {
+        return match exp.s() {
+            0 => Some(Fraction::one()),
+            1 => Some(Fraction::zero()),
+            _ => None,
+        };
+    }
+
+    // base^(p/q) = (base^(1/q))^p, and with p/q in lowest terms the power
+    // is only rational when the root is (integer exponents have q = 1)
+    let exp = exp.to_big_rational();
+    let root = try_perfect_nth_root(base, exp.denom().to_u32()?)?;
+    bounded_power(&root, exp.numer())
+}
+
+/// `base^exp` if the result stays within `MAX_EXACT_POWER_BITS`
+fn bounded_power(base: &Fraction, exp: &BigInt) -> Option<Fraction> {
+    let big = base.to_big_rational();
+    // 0, 1 and -1 stay small for any exponent
+    if (big.numer().magnitude().is_one() && big.denom().is_one()) || big.is_zero() {
+        let parity = if exp.is_odd() { 1 } else { 2 };
+        return Some(base.pow_int(if exp.is_negative() { -parity } else { parity }));
+    }
+    let exp = exp.to_i64()?;
+    let bits = big.numer().bits() + big.denom().bits();
+    if bits.saturating_mul(exp.unsigned_abs()) > MAX_EXACT_POWER_BITS {
+        return None;
     }
+    Some(base.pow_int(exp))
 }
 
 /// Check if `value` has a perfect n-th root that is rational
-fn try_perfect_nth_root(value: &Fraction, n: u64) -> Option<Fraction> {
+fn try_perfect_nth_root(value: &Fraction, n: u32) -> Option<Fraction> {
     if n == 0 {
         return None;
     }
@@ -526,58 +1287,84 @@ fn try_perfect_nth_root(value: &Fraction, n: u64) -> Option<Fraction> {
         return Some(value.clone());
     }
 
-    let num = value.as_big_rational().numer().to_i64()?;
-    let den = value.as_big_rational().denom().to_i64()?;
+    // Odd roots preserve sign, even roots of negatives are not real
+    let big = value.to_big_rational();
+    if big.is_negative() && n.is_multiple_of(2) {
+        return None;
+    }
+    let num_root = integer_nth_root(big.numer().magnitude(), n)?;
+    let den_root = integer_nth_root(big.denom().magnitude(), n)?;
+    let num_root = BigInt::from_biguint(big.numer().sign(), num_root);
+    Some(Fraction::from_big_ints(num_root, BigInt::from(den_root)))
+}
 
-    let num_abs = num.unsigned_abs();
-    let den_abs = den.unsigned_abs();
+/// Integer n-th root if exact, None otherwise
+fn integer_nth_root(value: &BigUint, n: u32) -> Option<BigUint> {
+    let root = value.nth_root(n);
+    (root.pow(n) == *value).then_some(root)
+}
 
-    let num_root = integer_nth_root(num_abs, n)?;
-    let den_root = integer_nth_root(den_abs, n)?;
+/// FNV-1a 64-bit offset basis and prime, for `Value::content_hash`
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
 
-    // Verify it's exact
-    if num_root.pow(n as u32) == num_abs && den_root.pow(n as u32) == den_abs {
-        // Handle sign: odd roots preserve sign, even roots of negatives are not real
-        let sign = if num < 0 {
-            if n % 2 == 1 {
-                -1i64
-            } else {
-                return None; // Even root of negative is not real
-            }
-        } else {
-            1i64
-        };
-        Some(Fraction::new_raw(sign * num_root as i64, den_root as i64))
-    } else {
-        None
-    }
+/// Fold `bytes` into an FNV-1a hash
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |h, &b| (h ^ b as u64).wrapping_mul(FNV_PRIME))
 }
 
-/// Integer n-th root if exact, None otherwise
-fn integer_nth_root(value: u64, n: u64) -> Option<u64> {
-    if value == 0 {
-        return Some(0);
-    }
-    if value == 1 || n == 1 {
-        return Some(value);
-    }
+/// Relative tolerance within which `Value::compare` treats f64 values as equal
+pub const COMPARE_EPSILON: f64 = 1e-12;
+
+/// Largest relative error of one correctly rounded f64 operation
+pub const UNIT_ROUNDOFF: f64 = f64::EPSILON / 2.0;
 
-    // Use floating point approximation, then verify
-    let root = (value as f64).powf(1.0 / n as f64).round() as u64;
+/// Relative error assumed for an f64 computed from exact values (`to_f64`
+/// of a rational or symbolic value, or the first irrational result): a few
+/// roundings, since powf and the products in `SymbolicPower::to_f64` are
+/// not correctly rounded
+pub const SEED_RELATIVE_ERROR: f64 = 4.0 * f64::EPSILON;
 
-    // Check root and neighbors (floating point might be slightly off)
-    for candidate in root.saturating_sub(1)..=root.saturating_add(1) {
-        if candidate.checked_pow(n as u32) == Some(value) {
-            return Some(candidate);
+/// Structural equality after normalization: 2^(13/12) equals 2 × 2^(1/12)
+impl PartialEq for SymbolicPower {
+    fn eq(&self, other: &SymbolicPower) -> bool {
+        let (a, b) = (self.clone().normalize(), other.clone().normalize());
+        a.coefficient == b.coefficient && a.same_powers(&b)
+    }
+}
+
+/// Exact equality: rationals and symbolic values compare structurally (a
+/// symbolic value that is actually rational equals that rational), and
+/// irrationals only equal irrationals with the same f64. Use `compare` or
+/// `approx_eq` for tolerant comparisons.
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Rational(a), Value::Rational(b)) => a == b,
+            (Value::Irrational { value: a, .. }, Value::Irrational { value: b, .. }) => a == b,
+            (Value::Irrational { .. }, _) | (_, Value::Irrational { .. }) => false,
+            _ => match (self.exact_terms(), other.exact_terms()) {
+                (Some(a), Some(b)) => a.coefficient == b.coefficient && a.same_powers(&b),
+                _ => false,
+            },
         }
     }
+}
 
-    None
+/// Ordered by `compare`; values that compare as equal within its tolerance
+/// without being exactly equal are unordered
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<std::cmp::Ordering> {
+        match self.compare(other) {
+            0 if self != other => None,
+            order => Some(order.cmp(&0)),
+        }
+    }
 }
 
 impl Default for Value {
     fn default() -> Self {
-        Value::Rational(Fraction::new(0, 1))
+        Value::zero()
     }
 }
 
@@ -585,7 +1372,10 @@ impl fmt::Debug for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Value::Rational(frac) => write!(f, "Rational({})", frac),
-            Value::Irrational(v) => write!(f, "Irrational({})", v),
+            Value::Irrational { value, err } if *err > 0.0 => {
+                write!(f, "Irrational({} ± {})", format_f64_js(*value), format_f64_js(*err))
+            }
+            Value::Irrational { value, .. } => write!(f, "Irrational({})", format_f64_js(*value)),
             Value::Symbolic(sp) => write!(f, "Symbolic({:?})", sp),
         }
     }
@@ -595,15 +1385,39 @@ impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Value::Rational(frac) => write!(f, "{}", frac),
-            Value::Irrational(v) => write!(f, "{:.10}", v),
-            Value::Symbolic(sp) => {
-                write!(f, "{}", sp.coefficient)?;
-                for p in &sp.powers {
-                    write!(f, " * {}^({}/{})", p.base, p.exponent.s() * (p.exponent.n() as i32), p.exponent.d())?;
-                }
-                Ok(())
+            Value::Irrational { value, .. } => write!(f, "{}", format_f64_js(*value)),
+            Value::Symbolic(sp) => write!(f, "{}", sp),
+        }
+    }
+}
+
+/// Compact form for the UI: "440 · 2^(7/12)", "-2^(1/12)", "3 · 2^2"
+///
+/// A unit coefficient is omitted when there are powers (-1 becomes a
+/// leading sign), integer exponents are written inline and zero exponents
+/// are skipped.
+impl fmt::Display for SymbolicPower {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let powers: Vec<&PowerTerm> = self.powers.iter().filter(|p| !p.exponent.is_zero()).collect();
+        if powers.is_empty() {
+            return write!(f, "{}", self.coefficient);
+        }
+        if self.coefficient == -Fraction::one() {
+            write!(f, "-")?;
+        } else if !self.coefficient.is_one() {
+            write!(f, "{} · ", self.coefficient)?;
+        }
+        for (i, p) in powers.iter().enumerate() {
+            if i > 0 {
+                write!(f, " · ")?;
+            }
+            if p.exponent.is_integer() {
+                write!(f, "{}^{}", p.base, p.exponent)?;
+            } else {
+                write!(f, "{}^({})", p.base, p.exponent)?;
             }
         }
+        Ok(())
     }
 }
 
@@ -615,7 +1429,7 @@ impl From<Fraction> for Value {
 
 impl From<f64> for Value {
     fn from(v: f64) -> Self {
-        Value::Irrational(v)
+        Value::inexact(v, 0.0)
     }
 }
 
@@ -625,6 +1439,79 @@ impl From<i32> for Value {
     }
 }
 
+/// Operator traits for owned values and references, delegating to the
+/// methods above so symbolic forms are preserved
+macro_rules! impl_value_op {
+    ($trait:ident, $method:ident) => {
+        impl std::ops::$trait for Value {
+            type Output = Value;
+
+            fn $method(self, rhs: Value) -> Value {
+                Value::$method(&self, &rhs)
+            }
+        }
+
+        impl std::ops::$trait<&Value> for &Value {
+            type Output = Value;
+
+            fn $method(self, rhs: &Value) -> Value {
+                Value::$method(self, rhs)
+            }
+        }
+    };
+}
+
+impl_value_op!(Add, add);
+impl_value_op!(Sub, sub);
+impl_value_op!(Mul, mul);
+impl_value_op!(Div, div);
+
+impl std::ops::Neg for Value {
+    type Output = Value;
+
+    fn neg(self) -> Value {
+        Value::neg(&self)
+    }
+}
+
+impl std::ops::Neg for &Value {
+    type Output = Value;
+
+    fn neg(self) -> Value {
+        Value::neg(self)
+    }
+}
+
+impl Sum for Value {
+    fn sum<I: Iterator<Item = Value>>(iter: I) -> Value {
+        // Seed with the first term so a lone symbolic sum stays symbolic
+        iter.reduce(|acc, v| Value::add(&acc, &v))
+            .unwrap_or_else(Value::zero)
+    }
+}
+
+impl<'a> Sum<&'a Value> for Value {
+    fn sum<I: Iterator<Item = &'a Value>>(iter: I) -> Value {
+        let mut iter = iter;
+        match iter.next() {
+            Some(first) => iter.fold(first.clone(), |acc, v| Value::add(&acc, v)),
+            None => Value::zero(),
+        }
+    }
+}
+
+impl Product for Value {
+    fn product<I: Iterator<Item = Value>>(iter: I) -> Value {
+        iter.fold(Value::one(), |acc, v| Value::mul(&acc, &v))
+    }
+}
+
+impl<'a> Product<&'a Value> for Value {
+    fn product<I: Iterator<Item = &'a Value>>(iter: I) -> Value {
+        iter.fold(Value::one(), |acc, v| Value::mul(&acc, v))
+    }
+}
+
 // ============================================================================
 // Serialization support for WASM interop
 // ============================================================================
@@ -702,6 +1589,10 @@ pub struct ValueData {
     /// Float value (for irrational/symbolic)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub f: Option<f64>,
+    /// Error bound of `f` (for tracked irrational values, see
+    /// `Value::track_error`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub err: Option<f64>,
     /// Is this value corrupted (irrational or symbolic)?
     pub corrupted: bool,
     /// Symbolic power data (if symbolic)
@@ -709,6 +1600,11 @@ pub struct ValueData {
     pub symbolic: Option<SymbolicPowerData>,
 }
 
+/// The `err` field for an error bound: absent unless tracked
+pub(crate) fn error_field(err: f64) -> Option<f64> {
+    (err != 0.0).then_some(err)
+}
+
 impl ValueData {
     /// Create from a Value
     pub fn from_value(v: &Value) -> Self {
@@ -718,14 +1614,16 @@ impl ValueData {
                 n: Some(frac.n()),
                 d: Some(frac.d()),
                 f: None,
+                err: None,
                 corrupted: false,
                 symbolic: None,
             },
-            Value::Irrational(val) => ValueData {
+            Value::Irrational { value, err } => ValueData {
                 s: None,
                 n: None,
                 d: None,
-                f: Some(*val),
+                f: Some(*value),
+                err: error_field(*err),
                 corrupted: true,
                 symbolic: None,
             },
@@ -734,6 +1632,7 @@ impl ValueData {
                 n: None,
                 d: None,
                 f: Some(sp.to_f64()),  // Include f64 for immediate use
+                err: None,
                 corrupted: true,
                 symbolic: Some(SymbolicPowerData::from_symbolic(sp)),
             },
@@ -748,7 +1647,7 @@ impl ValueData {
         }
         // Then check for corrupted (legacy irrational)
         if self.corrupted {
-            Value::Irrational(self.f.unwrap_or(0.0))
+            Value::inexact(self.f.unwrap_or(0.0), self.err.unwrap_or(0.0))
         } else if let (Some(s), Some(n), Some(d)) = (self.s, self.n, self.d) {
             let num = s * (n as i32);
             Value::Rational(Fraction::new(num, d as i32))
@@ -775,6 +1674,7 @@ impl ValueData {
             n: Some(f.n()),
             d: Some(f.d()),
             f: None,
+            err: None,
             corrupted: false,
             symbolic: None,
         }
@@ -799,6 +1699,7 @@ impl Default for ValueData {
             n: Some(0),
             d: Some(1),
             f: None,
+            err: None,
             corrupted: false,
             symbolic: None,
         }
@@ -810,28 +1711,205 @@ impl Default for ValueData {
 // ============================================================================
 
 /// Corruption flag for startTime property
-pub const CORRUPT_START_TIME: u8 = 0x01;
+pub const CORRUPT_START_TIME: u16 = 1 << 0;
 /// Corruption flag for duration property
-pub const CORRUPT_DURATION: u8 = 0x02;
+pub const CORRUPT_DURATION: u16 = 1 << 1;
 /// Corruption flag for frequency property
-pub const CORRUPT_FREQUENCY: u8 = 0x04;
+pub const CORRUPT_FREQUENCY: u16 = 1 << 2;
 /// Corruption flag for tempo property
-pub const CORRUPT_TEMPO: u8 = 0x08;
+pub const CORRUPT_TEMPO: u16 = 1 << 3;
 /// Corruption flag for beatsPerMeasure property
-pub const CORRUPT_BEATS_PER_MEASURE: u8 = 0x10;
+pub const CORRUPT_BEATS_PER_MEASURE: u16 = 1 << 4;
 /// Corruption flag for measureLength property
-pub const CORRUPT_MEASURE_LENGTH: u8 = 0x20;
-
-/// Get corruption flag for a variable index
-pub fn corruption_flag_for_var(var_index: u8) -> u8 {
-    match var_index {
-        0 => CORRUPT_START_TIME,
-        1 => CORRUPT_DURATION,
-        2 => CORRUPT_FREQUENCY,
-        3 => CORRUPT_TEMPO,
-        4 => CORRUPT_BEATS_PER_MEASURE,
-        5 => CORRUPT_MEASURE_LENGTH,
-        _ => 0,
+pub const CORRUPT_MEASURE_LENGTH: u16 = 1 << 5;
+
+/// Get corruption flag for a variable index: bit `var_index`, or 0 for an
+/// index beyond the 16 bits of the flags
+pub fn corruption_flag_for_var(var_index: u8) -> u16 {
+    1u16.checked_shl(var_index as u32).unwrap_or(0)
+}
+
+/// Flag of a custom variable in `custom_corruption_flags` and other masks
+/// of custom variables: bit `var_index - CUSTOM_VAR_FIRST`, or 0 for an
+/// index outside the custom range
+pub fn custom_flag_for_var(var_index: u8) -> u32 {
+    if is_custom_var(var_index) {
+        1 << (var_index - CUSTOM_VAR_FIRST)
+    } else {
+        0
+    }
+}
+
+// ============================================================================
+// WASM bindings for JavaScript interop
+// ============================================================================
+
+/// Display string for a ValueData object (see `Value::format_with_approx`)
+#[wasm_bindgen(js_name = valueToDisplayString)]
+pub fn value_to_display_string_js(value: JsValue, digits: u32) -> Result<String, JsValue> {
+    Ok(value_from_js(value)?.format_with_approx(digits as usize))
+}
+
+/// Deserialize a ValueData object passed from JavaScript
+pub(crate) fn value_from_js(value: JsValue) -> Result<Value, JsValue> {
+    let data: ValueData = serde_wasm_bindgen::from_value(value)
+        .map_err(|e| JsValue::from_str(&format!("Invalid value: {}", e)))?;
+    Ok(data.to_value())
+}
+
+/// Tolerant equality of two ValueData objects (see `Value::approx_eq`)
+#[wasm_bindgen(js_name = valueApproxEq)]
+pub fn value_approx_eq_js(a: JsValue, b: JsValue, epsilon: f64) -> Result<bool, JsValue> {
+    Ok(value_from_js(a)?.approx_eq(&value_from_js(b)?, epsilon))
+}
+
+/// Compare two ValueData objects, returning -1, 0 or 1 (see `Value::compare`)
+#[wasm_bindgen(js_name = valueCompare)]
+pub fn value_compare_js(a: JsValue, b: JsValue) -> Result<i32, JsValue> {
+    Ok(value_from_js(a)?.compare(&value_from_js(b)?))
+}
+
+/// Simplest Fraction within maxCents of a ValueData frequency ratio with
+/// denominator at most maxDen, or undefined (see `Value::snap_to_rational`)
+#[wasm_bindgen(js_name = valueSnapToRational)]
+pub fn value_snap_to_rational_js(value: JsValue, max_cents: f64, max_den: u32) -> Result<Option<Fraction>, JsValue> {
+    Ok(value_from_js(value)?.snap_to_rational(max_cents, max_den as u64))
+}
+
+/// Stable content hash of a ValueData object (see `Value::content_hash`)
+///
+/// Returned as a BigInt so all 64 bits survive.
+#[wasm_bindgen(js_name = valueContentHash)]
+pub fn value_content_hash_js(value: JsValue) -> Result<u64, JsValue> {
+    Ok(value_from_js(value)?.content_hash())
+}
+
+/// Parse an expression such as "3/2 * 2^(7/12)" into a ValueData object
+/// (see `Value::from_string`)
+#[wasm_bindgen(js_name = valueFromString)]
+pub fn value_from_string_js(s: &str) -> Result<JsValue, JsValue> {
+    let value = Value::from_string(s).map_err(|e| JsValue::from_str(&e))?;
+    serde_wasm_bindgen::to_value(&ValueData::from_value(&value)).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Write a ValueData object in the syntax `valueFromString` reads (see
+/// `Value::to_expr_string`)
+#[wasm_bindgen(js_name = valueToExprString)]
+pub fn value_to_expr_string_js(value: JsValue) -> Result<String, JsValue> {
+    Ok(value_from_js(value)?.to_expr_string())
+}
+
+/// A value folded into the octave [1, 2)
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OctaveReduced {
+    /// Folded value in [1, 2)
+    pub ratio: ValueData,
+    /// Number of octaves removed (negative when the value was below 1)
+    pub octaves: i64,
+}
+
+/// Fold a ValueData frequency ratio into [1, 2) as `{ ratio, octaves }`,
+/// or undefined unless positive (see `Value::octave_reduce`)
+#[wasm_bindgen(js_name = valueOctaveReduce)]
+pub fn value_octave_reduce_js(value: JsValue) -> Result<JsValue, JsValue> {
+    let Some((ratio, octaves)) = value_from_js(value)?.octave_reduce() else {
+        return Ok(JsValue::UNDEFINED);
+    };
+    let reduced = OctaveReduced { ratio: ValueData::from_value(&ratio), octaves };
+    serde_wasm_bindgen::to_value(&reduced).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// A Value owned by JavaScript, for building symbolic values in UI code
+/// without going through the evaluator
+///
+/// Operations borrow their operands and return a new object, so every
+/// wrapper stays usable (and must still be `free()`d) after being passed in.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct JsSymbolicValue {
+    value: Value,
+}
+
+impl JsSymbolicValue {
+    /// Wrap a Value
+    pub fn new(value: Value) -> Self {
+        JsSymbolicValue { value }
+    }
+
+    /// The wrapped Value
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+}
+
+#[wasm_bindgen]
+impl JsSymbolicValue {
+    /// The rational n/d; throws on a zero denominator like
+    /// `Fraction.newChecked`
+    #[wasm_bindgen(js_name = fromRational)]
+    pub fn from_rational(n: i32, d: i32) -> Result<JsSymbolicValue, JsValue> {
+        Ok(JsSymbolicValue::new(Value::Rational(Fraction::try_new(n, d)?)))
+    }
+
+    /// base^(expNum/expDen), exact when the power is rational (4^(1/2) is 2)
+    #[wasm_bindgen(js_name = fromPower)]
+    pub fn from_power(base: u32, exp_num: i32, exp_den: i32) -> Result<JsSymbolicValue, JsValue> {
+        let exponent = Value::Rational(Fraction::try_new(exp_num, exp_den)?);
+        Ok(JsSymbolicValue::new(Value::Rational(Fraction::from(base as i64)).pow(&exponent)))
+    }
+
+    /// Wrap a ValueData object
+    #[wasm_bindgen(js_name = fromData)]
+    pub fn from_data(data: JsValue) -> Result<JsSymbolicValue, JsValue> {
+        Ok(JsSymbolicValue::new(value_from_js(data)?))
+    }
+
+    /// This value as a ValueData object
+    #[wasm_bindgen(js_name = toData)]
+    pub fn to_data(&self) -> Result<JsValue, JsValue> {
+        serde_wasm_bindgen::to_value(&ValueData::from_value(&self.value)).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// self + other
+    #[wasm_bindgen(js_name = add)]
+    pub fn add_js(&self, other: &JsSymbolicValue) -> JsSymbolicValue {
+        JsSymbolicValue::new(self.value.add(&other.value))
+    }
+
+    /// self * other
+    #[wasm_bindgen(js_name = mul)]
+    pub fn mul_js(&self, other: &JsSymbolicValue) -> JsSymbolicValue {
+        JsSymbolicValue::new(self.value.mul(&other.value))
+    }
+
+    /// self / other (1 when other is zero, see `Value::div`)
+    #[wasm_bindgen(js_name = div)]
+    pub fn div_js(&self, other: &JsSymbolicValue) -> JsSymbolicValue {
+        JsSymbolicValue::new(self.value.div(&other.value))
+    }
+
+    /// self ^ exponent (see `Value::pow`)
+    #[wasm_bindgen(js_name = pow)]
+    pub fn pow_js(&self, exponent: &JsSymbolicValue) -> JsSymbolicValue {
+        JsSymbolicValue::new(self.value.pow(&exponent.value))
+    }
+
+    /// Is this value exactly rational?
+    #[wasm_bindgen(getter, js_name = isRational)]
+    pub fn is_rational_js(&self) -> bool {
+        self.value.is_rational()
+    }
+
+    /// f64 approximation
+    #[wasm_bindgen(js_name = toF64)]
+    pub fn to_f64_js(&self) -> f64 {
+        self.value.to_f64()
+    }
+
+    /// Display string (see `Value::format_with_approx`)
+    #[wasm_bindgen(js_name = toDisplayString)]
+    pub fn to_display_string(&self, digits: u32) -> String {
+        self.value.format_with_approx(digits as usize)
     }
 }
 
@@ -962,10 +2040,416 @@ mod tests {
 
     #[test]
     fn test_integer_nth_root() {
-        assert_eq!(integer_nth_root(8, 3), Some(2)); // cube root of 8
-        assert_eq!(integer_nth_root(16, 4), Some(2)); // 4th root of 16
-        assert_eq!(integer_nth_root(27, 3), Some(3)); // cube root of 27
-        assert_eq!(integer_nth_root(10, 2), None); // sqrt(10) is not integer
+        let root = |value: u64, n| integer_nth_root(&BigUint::from(value), n);
+        assert_eq!(root(8, 3), Some(BigUint::from(2u32))); // cube root of 8
+        assert_eq!(root(16, 4), Some(BigUint::from(2u32))); // 4th root of 16
+        assert_eq!(root(27, 3), Some(BigUint::from(3u32))); // cube root of 27
+        assert_eq!(root(10, 2), None); // sqrt(10) is not integer
+    }
+
+    #[test]
+    fn test_sqrt_and_nth_root() {
+        let root = Value::rational(9, 4).sqrt();
+        assert!(root.is_rational());
+        assert_eq!(root.to_fraction(), Fraction::new(3, 2));
+
+        let sqrt2 = Value::rational(2, 1).sqrt();
+        let Value::Symbolic(sp) = &sqrt2 else { panic!("expected symbolic") };
+        assert_eq!((sp.powers[0].base, &sp.powers[0].exponent), (2, &Fraction::new(1, 2)));
+
+        let cube = Value::rational(8, 1).nth_root(3);
+        assert!(cube.is_rational());
+        assert_eq!(cube.to_fraction(), Fraction::new(2, 1));
+        assert_eq!(Value::rational(-27, 8).nth_root(3).to_fraction(), Fraction::new(-3, 2));
+        assert!(Value::rational(5, 1).nth_root(1) == Value::rational(5, 1));
+        assert!(Value::rational(8, 1).nth_root(0).to_f64().is_nan());
+    }
+
+    #[test]
+    fn test_log() {
+        let fifth = Value::rational(2, 1).pow(&Value::rational(7, 12));
+        let octaves = fifth.log(&Value::rational(2, 1));
+        assert!(octaves.is_rational());
+        assert_eq!(octaves.to_fraction(), Fraction::new(7, 12));
+
+        // Semitones: log to the base 2^(1/12)
+        let semitone = Value::rational(2, 1).pow(&Value::rational(1, 12));
+        assert_eq!(fifth.log(&semitone).to_fraction(), Fraction::new(7, 1));
+        let down = Value::rational(1, 2).mul(&semitone);
+        assert_eq!(down.log(&semitone).to_fraction(), Fraction::new(-11, 1));
+
+        // Rational powers of rational bases, and powers of powers
+        let two = Value::rational(2, 1);
+        assert_eq!(Value::rational(27, 8).log(&Value::rational(3, 2)).to_fraction(), Fraction::new(3, 1));
+        assert_eq!(Value::rational(4, 1).nth_root(3).log(&two).to_fraction(), Fraction::new(2, 3));
+        assert_eq!(Value::rational(1, 1).log(&Value::rational(7, 1)).to_fraction(), Fraction::zero());
+
+        // Not a power of the base: f64 fallback
+        let mixed = Value::rational(3, 2).log(&Value::rational(2, 1));
+        assert!(matches!(mixed, Value::Irrational { .. }));
+        assert!((mixed.to_f64() - 1.5f64.log2()).abs() < 1e-12);
+        let cross = Value::rational(3, 1).sqrt().log(&Value::rational(2, 1));
+        assert!(matches!(cross, Value::Irrational { .. }));
+        assert!(Value::rational(-8, 1).log(&Value::rational(2, 1)).to_f64().is_nan());
+        assert!(Value::rational(2, 1).log(&Value::rational(1, 1)).to_f64().is_infinite());
+    }
+
+    #[test]
+    fn test_snap_to_rational() {
+        let fifth = Value::rational(2, 1).pow(&Value::rational(7, 12));
+        assert_eq!(fifth.snap_to_rational(6.0, 100), Some(Fraction::new(3, 2)));
+        assert_eq!(fifth.snap_to_rational(1.0, 100), None);
+
+        let semitone = Value::rational(2, 1).pow(&Value::rational(1, 12));
+        assert_eq!(semitone.snap_to_rational(1.0, 32), None);
+        assert_eq!(semitone.snap_to_rational(1.0, 64), Some(Fraction::new(53, 50)));
+
+        // Just major third from an f64 frequency ratio
+        let third = Value::irrational(2f64.powf(400.0 / 1200.0));
+        assert_eq!(third.snap_to_rational(14.0, 16), Some(Fraction::new(5, 4)));
+
+        // Rationals already simple enough are returned as is
+        assert_eq!(Value::rational(7, 4).snap_to_rational(0.0, 4), Some(Fraction::new(7, 4)));
+        assert_eq!(Value::rational(301, 200).snap_to_rational(6.0, 100), Some(Fraction::new(3, 2)));
+
+        assert_eq!(Value::rational(-3, 2).snap_to_rational(10.0, 100), None);
+        assert_eq!(fifth.snap_to_rational(f64::NAN, 100), None);
+    }
+
+    #[test]
+    fn test_operator_traits() {
+        let semitone = Value::rational(2, 1).pow(&Value::rational(1, 12));
+        let half = Value::rational(1, 2);
+
+        // Owned and borrowed operators take the symbolic-preserving paths
+        let product = &semitone * &semitone;
+        let Value::Symbolic(sp) = &product else { panic!("expected symbolic") };
+        assert_eq!(sp.powers[0].exponent, Fraction::new(1, 6));
+        assert!(semitone.clone() / semitone.clone() == Value::one());
+        assert!((&half + &half) == Value::one());
+        assert!((half.clone() - Value::one()) == -&half);
+        assert!(-(Value::rational(3, 1)) == Value::rational(-3, 1));
+        assert!(Value::default() == Value::zero());
+
+        // Sum and product match the explicit folds
+        let values = [
+            Value::rational(1, 3),
+            semitone.mul(&Value::rational(5, 1)),
+            Value::rational(1, 6),
+            semitone.mul(&Value::rational(3, 1)),
+        ];
+        let folded = values[1..].iter().fold(values[0].clone(), |acc, v| acc.add(v));
+        let summed: Value = values.iter().sum();
+        assert_eq!(summed.to_f64(), folded.to_f64());
+        let owned: Value = values.iter().cloned().sum();
+        assert_eq!(owned.to_f64(), folded.to_f64());
+
+        let like = [semitone.mul(&Value::rational(5, 1)), semitone.mul(&Value::rational(3, 1))];
+        let summed: Value = like.iter().sum();
+        assert!(summed == semitone.mul(&Value::rational(8, 1)));
+
+        let product: Value = [semitone.clone(), half.clone(), semitone.clone()].iter().product();
+        let folded = semitone.mul(&half).mul(&semitone);
+        assert!(product == folded);
+        assert!(product.is_symbolic());
+        let empty: Value = std::iter::empty::<Value>().product();
+        assert!(empty == Value::one());
+    }
+
+    #[test]
+    fn test_expr_string_round_trip() {
+        // Canonical strings come back unchanged
+        let canonical = [
+            "0", "1", "-3", "3/2", "-5/4", "81/80", "12345678901234567890/7",
+            "2^(1/12)", "2^(7/12)", "2^(-1/12)", "2^(11/12)", "3^(1/2)", "5^(1/4)",
+            "3/2 * 5^(1/4)", "-1/3 * 2^(1/12)", "2^(1/12) * 3^(1/2)", "7 * 2^(5/12) * 3^(1/3)",
+            "2^(1/53)", "4294967295^(1/2)", "1/1200 * 2^(1/1200)",
+        ];
+        for s in canonical {
+            let value = Value::from_string(s).unwrap_or_else(|e| panic!("{}", e));
+            assert_eq!(value.to_expr_string(), s);
+        }
+
+        // Other spellings parse to the same value, and their canonical form
+        // parses back to it
+        let variants = [
+            ("  2 ^ ( 7 / 12 ) ", "2^(7/12)"),
+            ("2^-1", "1/2"),
+            ("(3/2)", "3/2"),
+            ("2^(13/12)", "2 * 2^(1/12)"),
+            ("4^(1/2)", "2"),
+            ("8^(2/3)", "4"),
+            ("2^(1/2) * 2^(1/2)", "2"),
+            ("1.5 * 2^(1/12)", "3/2 * 2^(1/12)"),
+            ("2^(7/12)*3/2", "3/2 * 2^(7/12)"),
+            ("3:2", "3/2"),
+        ];
+        for (s, expected) in variants {
+            let value = Value::from_string(s).unwrap_or_else(|e| panic!("{}", e));
+            assert_eq!(value.to_expr_string(), expected, "parsing {}", s);
+            assert!(Value::from_string(&value.to_expr_string()).unwrap() == value);
+        }
+
+        let semitone = Value::rational(2, 1).pow(&Value::rational(1, 12));
+        assert!(Value::from_string("2^(1/12)").unwrap() == semitone);
+        assert!(Value::from_string("4/3").unwrap().is_rational());
+    }
+
+    #[test]
+    fn test_expr_string_errors() {
+        let position = |s: &str| {
+            let err = Value::from_string(s).unwrap_err();
+            let tail = err.split("at position ").nth(1).unwrap();
+            tail.split(':').next().unwrap().parse::<usize>().unwrap()
+        };
+        assert_eq!(position(""), 0);
+        assert_eq!(position("3/2 * "), 6);
+        assert_eq!(position("2^(1/12"), 2);
+        assert_eq!(position("2^1/12)"), 6);
+        assert_eq!(position("3/2 * x^(1/2)"), 6);
+        assert_eq!(position("0^(1/2)"), 0);
+        assert_eq!(position("(3/2)^(1/2)"), 0);
+        assert_eq!(position("2^"), 2);
+        assert_eq!(position("2^(1/2)^2"), 2);
+        assert_eq!(position("2 * 3/0"), 4);
+    }
+
+    #[test]
+    fn test_pow_symbolic_exponent() {
+        // 1/2 · 2^2 is the rational 2 in symbolic form
+        let two = Value::symbolic(SymbolicPower::new(
+            Fraction::new(1, 2),
+            vec![PowerTerm { base: 2, exponent: Fraction::new(2, 1) }],
+        ));
+        assert!(Value::rational(4, 1).pow(&two) == Value::rational(16, 1));
+        let half = Value::symbolic(SymbolicPower::new(
+            Fraction::new(1, 8),
+            vec![PowerTerm { base: 2, exponent: Fraction::new(2, 1) }],
+        ));
+        assert!(Value::rational(9, 4).pow(&half) == Value::rational(3, 2));
+
+        // Symbolic bases keep their structure
+        let semitone = Value::rational(2, 1).pow(&Value::rational(1, 12));
+        let Value::Symbolic(sp) = semitone.pow(&two) else { panic!("expected symbolic") };
+        assert_eq!(sp.powers[0].exponent, Fraction::new(1, 6));
+        let twelfth = Value::symbolic(SymbolicPower::new(Fraction::new(1, 12), vec![]));
+        assert!(Value::rational(2, 1).pow(&twelfth) == semitone);
+
+        // A genuinely irrational exponent still degrades to f64
+        let sqrt2 = Value::rational(2, 1).sqrt();
+        let result = Value::rational(2, 1).pow(&sqrt2);
+        assert!(matches!(result, Value::Irrational { .. }));
+        assert!((result.to_f64() - 2f64.powf(std::f64::consts::SQRT_2)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_error_bounds_contain_exact_result() {
+        // 1,000 multiplications of a tracked irrational, against the exact
+        // product of its f64 value computed with Fraction
+        let start = std::f64::consts::SQRT_2;
+        let mut value = Value::irrational(start).track_error();
+        let mut exact = Fraction::from_f64_exact(start).unwrap();
+        let factor = Fraction::new(1001, 1000);
+        for _ in 0..1000 {
+            value = value.mul(&Value::Rational(factor.clone()));
+            exact = exact.mul(&factor);
+        }
+        let Value::Irrational { value: v, err } = value else { panic!("expected irrational") };
+        assert!(err > 0.0 && err < 1e-11 * v);
+        let distance = Fraction::from_f64_exact(v).unwrap().sub(&exact).abs();
+        assert!(distance <= Fraction::from_f64_exact(err).unwrap());
+
+        // add, sub and div propagate too; symbolic operands count as inexact
+        let semitone = Value::rational(2, 1).pow(&Value::rational(1, 12));
+        let tracked = Value::irrational(0.1).track_error();
+        let mixed = tracked.add(&semitone).sub(&Value::rational(1, 3)).div(&tracked);
+        assert!(mixed.error_bound() > tracked.error_bound());
+        assert!(mixed.error_bound() < 1e-12);
+
+        // A divisor interval containing zero, and ops without a rule, are unbounded
+        let tiny = Value::inexact(1e-20, 1e-19);
+        assert_eq!(Value::one().add(&tracked).div(&tiny).error_bound(), f64::INFINITY);
+        assert_eq!(tracked.pow(&Value::rational(1, 3)).error_bound(), f64::INFINITY);
+
+        // Untracked values keep a zero bound and serialize without `err`
+        let untracked = Value::irrational(0.1).mul(&semitone);
+        assert_eq!(untracked.error_bound(), 0.0);
+        assert!(ValueData::from_value(&untracked).err.is_none());
+        let data = ValueData::from_value(&mixed);
+        assert_eq!(data.err, Some(mixed.error_bound()));
+        assert_eq!(data.to_value().error_bound(), mixed.error_bound());
+    }
+
+    #[test]
+    fn test_floor_ceil_round() {
+        let check = |v: Value, expected: (i32, i32, i32)| {
+            for (result, e) in [(v.floor(), expected.0), (v.ceil(), expected.1), (v.round(), expected.2)] {
+                assert!(result.is_rational(), "{:?}", v);
+                assert!(result == Value::rational(e, 1), "{:?} gave {:?}", v, result);
+            }
+        };
+        check(Value::rational(7, 2), (3, 4, 4));
+        check(Value::rational(-7, 2), (-4, -3, -4));
+        check(Value::rational(-5, 1), (-5, -5, -5));
+        check(Value::rational(-1, 3), (-1, 0, 0));
+
+        // 10·2^(1/12) ≈ 10.59 and -10·2^(1/12)
+        let semitone = Value::rational(2, 1).pow(&Value::rational(1, 12));
+        check(semitone.mul(&Value::rational(10, 1)), (10, 11, 11));
+        check(semitone.mul(&Value::rational(-10, 1)), (-11, -10, -11));
+        // Symbolic but exactly an integer: no f64 involved
+        check(Value::Symbolic(SymbolicPower::from_power(4, Fraction::new(3, 2))), (8, 8, 8));
+        check(Value::irrational(-2.5), (-3, -2, -3));
+
+        assert!(Value::irrational(1e300).floor().to_f64() == 1e300);
+        assert!(Value::inexact(f64::NAN, 0.0).floor().to_f64().is_nan());
+    }
+
+    #[test]
+    fn test_js_symbolic_value_chain() {
+        // A major triad on 2^(7/12): root, root * 5/4, root * 3/2
+        let root = JsSymbolicValue::from_power(2, 7, 12).unwrap();
+        let triad: Vec<_> = [(1, 1), (5, 4), (3, 2)]
+            .iter()
+            .map(|&(n, d)| root.mul_js(&JsSymbolicValue::from_rational(n, d).unwrap()))
+            .collect();
+        assert_eq!(triad[2].to_display_string(3), "3/2 · 2^(7/12) (≈ 2.247)");
+        assert!(!root.is_rational_js());
+
+        // Operands stay usable, and exact powers collapse back to rationals
+        let octave = root.pow_js(&JsSymbolicValue::from_rational(12, 7).unwrap());
+        assert!(octave.is_rational_js());
+        assert!(octave.value() == &Value::rational(2, 1));
+        assert!(triad[1].div_js(&root).value() == &Value::rational(5, 4));
+        assert!(JsSymbolicValue::from_power(4, 1, 2).unwrap().value() == &Value::rational(2, 1));
+
+        let sum = root.add_js(&root);
+        assert!((sum.to_f64_js() - 2.0 * 2f64.powf(7.0 / 12.0)).abs() < 1e-12);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    #[wasm_bindgen_test::wasm_bindgen_test]
+    fn test_js_symbolic_value_data_round_trip() {
+        let fifth = JsSymbolicValue::from_power(3, 1, 2).unwrap().mul_js(&JsSymbolicValue::from_rational(2, 3).unwrap());
+        let data = fifth.to_data().unwrap();
+        let back = JsSymbolicValue::from_data(data).unwrap();
+        assert!(back.value() == fifth.value());
+        assert_eq!(back.to_display_string(4), fifth.to_display_string(4));
+
+        let rational = JsSymbolicValue::from_rational(-3, 4).unwrap().to_data().unwrap();
+        assert!(JsSymbolicValue::from_data(rational).unwrap().value() == &Value::rational(-3, 4));
+        assert!(JsSymbolicValue::from_rational(1, 0).is_err());
+        assert!(JsSymbolicValue::from_data(JsValue::from_str("nope")).is_err());
+    }
+
+    #[test]
+    fn test_pow_negative_and_zero_bases() {
+        let pow = |n: i32, d: i32, p: i32, q: i32| Value::rational(n, d).pow(&Value::rational(p, q));
+
+        // Odd roots of negatives are real
+        assert!(pow(-8, 1, 1, 3) == Value::rational(-2, 1));
+        assert!(pow(-8, 1, 2, 3) == Value::rational(4, 1));
+        assert!(pow(-27, 8, -1, 3) == Value::rational(-2, 3));
+        let Value::Symbolic(sp) = pow(-2, 1, 1, 3) else { panic!("expected symbolic") };
+        assert_eq!(sp.coefficient, Fraction::new(-1, 1));
+        assert_eq!((sp.powers[0].base, &sp.powers[0].exponent), (2, &Fraction::new(1, 3)));
+        let Value::Symbolic(sp) = pow(-2, 1, 2, 3) else { panic!("expected symbolic") };
+        assert_eq!(sp.coefficient, Fraction::one());
+        assert!((pow(-3, 2, 1, 3).to_f64() + 1.5f64.cbrt()).abs() < 1e-12);
+
+        // Zero bases
+        assert!(pow(0, 1, 5, 3) == Value::zero());
+        assert!(pow(0, 1, 0, 1) == Value::one());
+        assert!(pow(0, 1, 1, 2) == Value::zero());
+        assert_eq!(Value::zero().checked_pow(&Value::rational(-1, 2)).unwrap_err(), PowError::ZeroToNegativePower);
+
+        // Even roots of negatives have no real value
+        assert!(pow(-4, 1, 1, 2).to_f64().is_nan());
+        let err = Value::rational(-4, 1).checked_pow(&Value::rational(1, 2)).unwrap_err();
+        assert_eq!(err, PowError::EvenRootOfNegative);
+        assert_eq!(err.to_string(), "Even root of a negative number");
+        assert!(Value::rational(-8, 1).checked_pow(&Value::rational(1, 3)).is_ok());
+
+        // Classified exactly, whatever the f64 of the base
+        let tiny = Value::Rational(Fraction::from_big_ints(-BigInt::one(), BigInt::from(10u8).pow(400)));
+        let huge = Value::Rational(Fraction::from_big_ints(-BigInt::from(10u8).pow(400), BigInt::one()));
+        assert_eq!(tiny.to_f64(), 0.0);
+        for base in [&tiny, &huge, &Value::irrational(-2.5)] {
+            assert_eq!(base.checked_pow(&Value::rational(1, 2)).unwrap_err(), PowError::EvenRootOfNegative);
+            assert_eq!(base.checked_pow(&Value::rational(-3, 4)).unwrap_err(), PowError::EvenRootOfNegative);
+            assert_eq!(base.checked_pow(&Value::irrational(0.3)).unwrap_err(), PowError::EvenRootOfNegative);
+        }
+        assert_eq!(huge.neg().checked_pow(&Value::rational(1, 3)).unwrap_err(), PowError::Overflow);
+        assert_eq!(huge.checked_pow(&Value::rational(1, 3)).unwrap_err(), PowError::Overflow);
+        assert!(huge.checked_pow(&Value::rational(2, 1)).unwrap().is_rational());
+        assert!(tiny.checked_pow(&Value::rational(1, 3)).is_ok());
+        assert_eq!(Value::irrational(0.0).checked_pow(&Value::irrational(-0.5)).unwrap_err(), PowError::ZeroToNegativePower);
+        assert!(Value::zero().checked_pow(&Value::rational(3, 2)).unwrap() == Value::zero());
+
+        // Symbolic bases follow the sign of their coefficient
+        let minus_cbrt2 = pow(-2, 1, 1, 3);
+        let even = minus_cbrt2.pow(&Value::rational(1, 2));
+        assert!(even.to_f64().is_nan());
+        assert_eq!(minus_cbrt2.checked_pow(&Value::rational(1, 2)).unwrap_err(), PowError::EvenRootOfNegative);
+        let odd = Value::rational(3, 1).pow(&Value::rational(1, 2)).neg().pow(&Value::rational(1, 3));
+        assert!((odd.to_f64() + 3f64.powf(1.0 / 6.0)).abs() < 1e-12, "{}", odd.to_f64());
+        assert!(minus_cbrt2.checked_pow(&Value::rational(3, 1)).unwrap() == Value::rational(-2, 1));
+    }
+
+    #[test]
+    fn test_big_perfect_roots() {
+        // (10^20)^(1/2) = 10^10, beyond i64 before the root
+        let base = Fraction::new_raw(10, 1).pow_int(20);
+        let result = Value::Rational(base).pow(&Value::rational(1, 2));
+        assert!(result.is_rational());
+        assert_eq!(result.to_fraction(), Fraction::new_raw(10_000_000_000, 1));
+
+        // 40-digit squares and cubes
+        let root: BigInt = "12345678901234567890123".parse().unwrap();
+        let square = Fraction::from_big_ints(root.pow(2u32), BigInt::one());
+        let cube = Fraction::from_big_ints(-root.pow(3u32), BigInt::from(27));
+        assert!(square.to_string().len() >= 40);
+        let exact = Fraction::from_big_ints(root.clone(), BigInt::one());
+        assert_eq!(try_rational_power(&square, &Fraction::new(1, 2)), Some(exact.clone()));
+        assert_eq!(try_rational_power(&square, &Fraction::new(-3, 2)), Some(exact.pow_int(-3)));
+        let third = Fraction::from_big_ints(-root, BigInt::from(3));
+        assert_eq!(try_rational_power(&cube, &Fraction::new(1, 3)), Some(third.clone()));
+        assert_eq!(try_rational_power(&cube, &Fraction::new(2, 3)), Some(third.pow_int(2)));
+        assert_eq!(try_rational_power(&cube, &Fraction::new(1, 2)), None);
+        let off_by_one = Fraction::from_big_ints(square.to_big_rational().numer() + 1, BigInt::one());
+        assert_eq!(try_rational_power(&off_by_one, &Fraction::new(1, 2)), None);
+
+        // BigInt exponent numerators: trivial bases stay exact, others give up quickly
+        let huge: BigInt = "1000000000000000000000000000000".parse().unwrap();
+        let exp = Fraction::from_big_ints(huge.clone() + 1, BigInt::from(7));
+        assert_eq!(try_rational_power(&Fraction::one(), &exp), Some(Fraction::one()));
+        assert_eq!(try_rational_power(&Fraction::new(-1, 1), &exp), Some(Fraction::new(-1, 1)));
+        assert_eq!(try_rational_power(&Fraction::new(128, 1), &exp), None);
+        let exp = Fraction::from_big_ints(BigInt::from(1_000_000_001), BigInt::from(2));
+        assert_eq!(try_rational_power(&Fraction::new(4, 1), &exp), None);
+        let sym = Value::rational(4, 1).pow(&Value::Rational(exp));
+        assert!(sym.is_symbolic());
+    }
+
+    #[test]
+    fn test_huge_integer_powers_stay_bounded() {
+        let billion = Fraction::new(1_000_000_000, 1);
+        assert_eq!(try_rational_power(&Fraction::new(2, 1), &billion), None);
+        assert_eq!(try_rational_power(&Fraction::new(-1, 1), &billion.add(&Fraction::one())), Some(Fraction::new(-1, 1)));
+        assert_eq!(try_rational_power(&Fraction::new(2, 1), &Fraction::new(10, 1)), Some(Fraction::new(1024, 1)));
+
+        let power = Value::rational(2, 1).pow(&Value::Rational(billion.clone()));
+        assert!(matches!(power, Value::Irrational { .. }));
+        assert_eq!(Value::rational(1, 1).pow(&Value::Rational(billion.clone())), Value::one());
+
+        // Symbolic bases and canonicalization take the same bounded path
+        let root = Value::rational(2, 1).pow(&Value::rational(1, 2));
+        assert!(matches!(root.pow(&Value::Rational(billion.clone())), Value::Irrational { .. }));
+        let sp = SymbolicPower::from_power(2, billion.add(&Fraction::new(1, 2))).normalize();
+        assert_eq!(sp.coefficient, Fraction::one());
+
+        assert!(matches!(Value::from_string("2^(1000000000)").unwrap(), Value::Irrational { .. }));
+        assert_eq!(Value::from_string("2^10").unwrap(), Value::rational(1024, 1));
     }
 
     // ============================================================================
@@ -987,6 +2471,22 @@ mod tests {
         assert!((result.to_f64() - expected).abs() < 1e-10);
     }
 
+    #[test]
+    fn test_symbolic_to_rational_large_powers() {
+        // 3^40 and 2^-70 overflow i32; the product must stay exact
+        let sp = SymbolicPower::new(
+            Fraction::new(5, 1),
+            vec![
+                PowerTerm { base: 3, exponent: Fraction::new(40, 1) },
+                PowerTerm { base: 2, exponent: Fraction::new(-70, 1) },
+            ],
+        );
+        let expected = Fraction::new(5, 1)
+            .mul(&Fraction::new(3, 1).pow_int(40))
+            .div(&Fraction::new(2, 1).pow_int(70));
+        assert_eq!(sp.to_rational_fraction(), Some(expected));
+    }
+
     #[test]
     fn test_symbolic_like_base_multiplication() {
         let two = Value::rational(2, 1);
@@ -1103,4 +2603,284 @@ mod tests {
         let expected = 5.0 * 2.0_f64.powf(1.0 / 12.0);
         assert!((result.to_f64() - expected).abs() < 1e-10);
     }
+
+    #[test]
+    fn test_irrational_display_matches_js() {
+        let semitone = Value::irrational(1.0594630943592953);
+        assert_eq!(semitone.to_string(), "1.0594630943592953");
+        assert_eq!(Value::irrational(1e-7).to_string(), "1e-7");
+        assert_eq!(Value::rational(3, 4).to_string(), "3/4");
+    }
+
+    #[test]
+    fn test_symbolic_display() {
+        let power = |base, n, d| PowerTerm { base, exponent: Fraction::new(n, d) };
+        let show = |coefficient: Fraction, powers: Vec<PowerTerm>| {
+            Value::symbolic(SymbolicPower::new(coefficient, powers)).to_string()
+        };
+
+        assert_eq!(show(Fraction::one(), vec![power(2, 1, 12)]), "2^(1/12)");
+        assert_eq!(show(Fraction::new(440, 1), vec![power(2, 7, 12)]), "440 · 2^(7/12)");
+        assert_eq!(show(Fraction::new(-1, 1), vec![power(2, 1, 12)]), "-2^(1/12)");
+        assert_eq!(show(Fraction::new(-3, 2), vec![power(2, -1, 12)]), "-3/2 · 2^(-1/12)");
+        assert_eq!(show(Fraction::new(3, 1), vec![power(2, 2, 1), power(3, -1, 1)]), "3 · 2^2 · 3^-1");
+        assert_eq!(show(Fraction::new(5, 4), vec![]), "5/4");
+        assert_eq!(show(Fraction::new(5, 4), vec![power(2, 0, 1)]), "5/4");
+        assert_eq!(
+            show(Fraction::one(), vec![power(2, 1, 12), power(3, 1, 2), power(5, 1, 4)]),
+            "2^(1/12) · 3^(1/2) · 5^(1/4)"
+        );
+
+        let a5 = Value::rational(440, 1).mul(&Value::rational(2, 1).pow(&Value::rational(7, 12)));
+        assert_eq!(a5.format_with_approx(3), "440 · 2^(7/12) (≈ 659.255)");
+        assert_eq!(Value::rational(3, 2).format_with_approx(2), "3/2 (≈ 1.50)");
+        assert_eq!(Value::rational(440, 1).format_with_approx(3), "440");
+        assert_eq!(Value::irrational(0.5).format_with_approx(3), "0.5");
+    }
+
+    #[test]
+    fn test_non_finite_policy() {
+        assert!(matches!(Value::irrational(f64::NAN), Value::Irrational { value: v, .. } if v == 0.0));
+        assert!(matches!(Value::irrational(f64::NEG_INFINITY), Value::Irrational { value: v, .. } if v == 0.0));
+        assert!(matches!(Value::irrational(1.5), Value::Irrational { value: v, .. } if v == 1.5));
+
+        let nan = Value::inexact(f64::NAN, 0.0);
+        assert!(!nan.is_finite() && Value::rational(1, 3).is_finite());
+        let zero = nan.clone().enforce_finite(NonFinitePolicy::Zero).unwrap();
+        assert!(matches!(zero, Value::Irrational { value: v, .. } if v == 0.0));
+        assert!(nan.enforce_finite(NonFinitePolicy::Error).is_err());
+        assert!(Value::irrational(2.0).enforce_finite(NonFinitePolicy::Error).is_ok());
+
+        assert_eq!("error".parse::<NonFinitePolicy>(), Ok(NonFinitePolicy::Error));
+        assert_eq!("zero".parse::<NonFinitePolicy>(), Ok(NonFinitePolicy::Zero));
+        assert!("clamp".parse::<NonFinitePolicy>().is_err());
+    }
+
+    #[test]
+    fn test_approx_eq() {
+        let third = Value::rational(1, 3);
+        assert!(third.approx_eq(&Value::rational(333, 1000), 1e-3));
+        assert!(!third.approx_eq(&Value::rational(333, 1000), 1e-4));
+        // Exact: 0.1 as a double is slightly above 1/10
+        assert!(Value::rational(0, 1).approx_eq(&Value::rational(1, 10), 0.1));
+        assert!(!Value::rational(0, 1).approx_eq(&Value::rational(1, 10), f64::NAN));
+        assert!(Value::rational(0, 1).approx_eq(&Value::rational(1, 10), f64::INFINITY));
+        assert!(Value::rational(1, 3).approx_eq(&Value::rational(1, 3), 0.0));
+        assert!(!Value::rational(1, 3).approx_eq(&Value::rational(1, 3), -1e-9));
+
+        // Only differences within rounding of epsilon build it exactly
+        let calls = || crate::fraction::FROM_F64_EXACT_CALLS.with(|calls| calls.get());
+        let before = calls();
+        for i in 1..1_000 {
+            let value = Value::rational(i, 997);
+            let close = Fraction::new_raw(i as i64 * 1_000_000_000 + 1, 997_000_000_000);
+            assert!(value.approx_eq(&Value::Rational(close), 1e-9));
+            assert!(!value.approx_eq(&Value::rational(i + 1, 997), 1e-9));
+        }
+        assert_eq!(calls(), before);
+        assert!(!Value::rational(0, 1).approx_eq(&Value::rational(1, 10), f64::from_bits(0.1f64.to_bits() - 1)));
+        assert_eq!(calls(), before + 1);
+        let huge = Value::Rational(Fraction::from_big_ints(num_bigint::BigInt::from(10u8).pow(30) + 1, 3.into()));
+        assert!(huge.approx_eq(&Value::Rational(Fraction::from_big_ints(num_bigint::BigInt::from(10u8).pow(30), 3.into())), 0.5));
+
+        let semitone = Value::rational(2, 1).pow(&Value::rational(1, 12));
+        assert!(semitone.approx_eq(&Value::irrational(2f64.powf(1.0 / 12.0)), 1e-12));
+        assert!(!semitone.approx_eq(&Value::rational(1, 1), 1e-3));
+    }
+
+    #[test]
+    fn test_rem() {
+        let r = Value::rational(-7, 6).rem(&Value::rational(1, 3));
+        assert_eq!(r.to_fraction(), Fraction::new(-1, 6));
+        assert!(r.is_rational());
+
+        // Symbolic operand: f64 remainder with the sign of the dividend
+        let semitone = Value::rational(2, 1).pow(&Value::rational(1, 12));
+        let r = Value::rational(-5, 2).rem(&semitone);
+        assert!((r.to_f64() - (-2.5f64 % 2f64.powf(1.0 / 12.0))).abs() < 1e-12);
+        assert!(r.to_f64() < 0.0);
+
+        // Modulo by zero returns the dividend
+        assert_eq!(semitone.rem(&Value::rational(0, 1)).to_f64(), semitone.to_f64());
+    }
+
+    #[test]
+    fn test_symbolic_like_terms() {
+        let semitone = Value::rational(2, 1).pow(&Value::rational(1, 12));
+        let five = semitone.mul(&Value::rational(5, 1));
+        let three = semitone.mul(&Value::rational(3, 1));
+
+        // Like terms add and subtract exactly
+        let sum = five.add(&three);
+        let Value::Symbolic(sp) = &sum else { panic!("expected symbolic") };
+        assert_eq!(sp.coefficient, Fraction::new(8, 1));
+        assert_eq!((sp.powers[0].base, &sp.powers[0].exponent), (2, &Fraction::new(1, 12)));
+        let Value::Symbolic(sp) = five.sub(&three) else { panic!("expected symbolic") };
+        assert_eq!(sp.coefficient, Fraction::new(2, 1));
+        assert!(five.sub(&five).is_rational());
+        assert_eq!(five.sub(&five).to_fraction(), Fraction::zero());
+
+        // Unlike terms still fall back to irrational
+        let fifth = Value::rational(3, 1).pow(&Value::rational(1, 2));
+        let mixed = semitone.add(&fifth);
+        assert!(matches!(mixed, Value::Irrational { .. }));
+        assert!((mixed.to_f64() - (2f64.powf(1.0 / 12.0) + 3f64.sqrt())).abs() < 1e-12);
+        assert!(matches!(semitone.add(&Value::rational(1, 1)), Value::Irrational { .. }));
+        assert!(matches!(semitone.add(&Value::irrational(1.0)), Value::Irrational { .. }));
+
+        // A symbolic value that is actually rational adds to a rational exactly
+        let four = Value::symbolic(SymbolicPower::new(
+            Fraction::new(1, 2),
+            vec![PowerTerm { base: 2, exponent: Fraction::new(3, 1) }],
+        ));
+        let sum = four.add(&Value::rational(1, 3));
+        assert!(sum.is_rational());
+        assert_eq!(sum.to_fraction(), Fraction::new(13, 3));
+        assert_eq!(Value::rational(5, 1).sub(&four).to_fraction(), Fraction::new(1, 1));
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let above = SymbolicPower::from_power(2, Fraction::new(13, 12)).normalize();
+        assert_eq!(above.coefficient, Fraction::new(2, 1));
+        assert_eq!(above.powers[0].exponent, Fraction::new(1, 12));
+        assert!((above.to_f64() - 2f64.powf(13.0 / 12.0)).abs() < 1e-12);
+
+        let below = SymbolicPower::from_power(2, Fraction::new(-13, 12)).normalize();
+        assert_eq!(below.coefficient, Fraction::new(1, 2));
+        assert_eq!(below.powers[0].exponent, Fraction::new(-1, 12));
+        assert!((below.to_f64() - 2f64.powf(-13.0 / 12.0)).abs() < 1e-12);
+
+        // Structurally equal to 2·2^(1/12)
+        let scaled = SymbolicPower::from_power(2, Fraction::new(1, 12))
+            .mul_rational(&Fraction::new(2, 1))
+            .normalize();
+        assert_eq!(above.coefficient, scaled.coefficient);
+        assert!(above.same_powers(&scaled));
+        assert_eq!(Value::symbolic(above).compare(&Value::symbolic(scaled)), 0);
+
+        // Integer exponents leave no power terms; large ones stay exact
+        let mut mixed = SymbolicPower::new(
+            Fraction::one(),
+            vec![
+                PowerTerm { base: 3, exponent: Fraction::new(200, 1) },
+                PowerTerm { base: 5, exponent: Fraction::new(7, 2) },
+            ],
+        );
+        mixed.canonicalize();
+        assert_eq!(mixed.coefficient, Fraction::new(3, 1).pow_int(200).mul(&Fraction::new(125, 1)));
+        let mixed = mixed.normalize();
+        assert_eq!(mixed.powers.len(), 1);
+        assert_eq!((mixed.powers[0].base, &mixed.powers[0].exponent), (5, &Fraction::new(1, 2)));
+    }
+
+    #[test]
+    fn test_exact_equality() {
+        let semitone = Value::rational(2, 1).pow(&Value::rational(1, 12));
+        let rest = Value::rational(2, 1).pow(&Value::rational(11, 12));
+        let octave = semitone.mul(&rest);
+        assert!(octave == Value::rational(2, 1));
+        assert!(Value::rational(2, 1) == octave);
+
+        let fifth = Value::rational(440, 1).mul(&Value::rational(2, 1).pow(&Value::rational(7, 12)));
+        let same = Value::rational(2, 1).pow(&Value::rational(7, 12)).mul(&Value::rational(440, 1));
+        assert!(fifth == same);
+        assert_eq!(fifth.content_hash(), same.content_hash());
+        assert!(fifth != semitone);
+
+        // Structural after canonicalization
+        let above = Value::symbolic(SymbolicPower::from_power(2, Fraction::new(13, 12)));
+        assert!(above == semitone.mul(&Value::rational(2, 1)));
+        assert_eq!(above.content_hash(), semitone.mul(&Value::rational(2, 1)).content_hash());
+        assert_eq!(octave.content_hash(), Value::rational(2, 1).content_hash());
+
+        // Irrationals are never exactly equal to other kinds
+        let sqrt2 = Value::rational(2, 1).pow(&Value::rational(1, 2));
+        assert!(sqrt2 != Value::irrational(std::f64::consts::SQRT_2));
+        assert!(Value::irrational(2.0) != Value::rational(2, 1));
+        assert!(Value::irrational(0.5) == Value::irrational(0.5));
+        assert_eq!(Value::irrational(0.0).content_hash(), Value::irrational(-0.0).content_hash());
+        assert_eq!(sqrt2.partial_cmp(&Value::irrational(std::f64::consts::SQRT_2)), None);
+    }
+
+    #[test]
+    fn test_content_hash_sweep() {
+        let mut seen = std::collections::HashMap::new();
+        for base in [2, 3, 5, 7] {
+            for num in -1250..1250 {
+                let value = Value::symbolic(SymbolicPower::from_power(base, Fraction::new(num, 53)));
+                let key = (base, num);
+                if let Some(previous) = seen.insert(value.content_hash(), key) {
+                    // Only genuinely equal values may share a hash
+                    let (b, n) = previous;
+                    let earlier = Value::symbolic(SymbolicPower::from_power(b, Fraction::new(n, 53)));
+                    assert!(earlier == value, "{:?} and {:?} collide", previous, key);
+                }
+            }
+        }
+        assert!(seen.len() > 9_900);
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(Value::rational(1, 3).compare(&Value::rational(1, 2)), -1);
+        assert_eq!(Value::rational(2, 4).compare(&Value::rational(1, 2)), 0);
+        assert!(Value::rational(3, 2) > Value::rational(4, 3));
+
+        // Same powers: exact comparison of coefficients
+        let semitone = Value::rational(2, 1).pow(&Value::rational(1, 12));
+        let a = semitone.mul(&Value::rational(1_000_000_000, 1_000_000_001));
+        assert_eq!(a.compare(&semitone), -1);
+        assert_eq!(semitone.compare(&semitone.clone()), 0);
+
+        // 2^(1/2) is close to 99/70 but not equal
+        let sqrt2 = Value::rational(2, 1).pow(&Value::rational(1, 2));
+        assert_eq!(sqrt2.compare(&Value::rational(99, 70)), -1);
+        assert_eq!(Value::rational(99, 70).compare(&sqrt2), 1);
+        assert!(sqrt2 != Value::rational(99, 70));
+        assert_eq!(sqrt2.compare(&Value::irrational(std::f64::consts::SQRT_2)), 0);
+
+        // NaN sorts last and equal to itself
+        let nan = Value::inexact(f64::NAN, 0.0);
+        assert_eq!(nan.compare(&Value::inexact(f64::NAN, 0.0)), 0);
+        assert_eq!(nan.compare(&Value::inexact(f64::INFINITY, 0.0)), 1);
+        assert_eq!(Value::rational(-5, 1).compare(&nan), -1);
+        let mut values = [nan.clone(), Value::rational(2, 1), sqrt2.clone(), Value::rational(1, 1)];
+        values.sort_by(|a, b| a.compare(b).cmp(&0));
+        assert_eq!(values[0].to_f64(), 1.0);
+        assert!(values[1].is_symbolic());
+        assert!(values[3].to_f64().is_nan());
+    }
+
+    #[test]
+    fn test_octave_reduce() {
+        let (folded, octaves) = Value::rational(9, 4).octave_reduce().unwrap();
+        assert_eq!(folded.to_fraction(), Fraction::new(9, 8));
+        assert_eq!(octaves, 1);
+        let (folded, octaves) = Value::rational(1, 3).octave_reduce().unwrap();
+        assert_eq!(folded.to_fraction(), Fraction::new(4, 3));
+        assert_eq!(octaves, -2);
+
+        // Whole octaves come out of the base-2 exponent exactly
+        let tone = Value::rational(2, 1).pow(&Value::rational(25, 12));
+        let (folded, octaves) = tone.octave_reduce().unwrap();
+        assert_eq!(octaves, 2);
+        let Value::Symbolic(sp) = &folded else { panic!("expected symbolic") };
+        assert_eq!(sp.coefficient, Fraction::one());
+        assert_eq!(sp.powers.len(), 1);
+        assert_eq!((sp.powers[0].base, &sp.powers[0].exponent), (2, &Fraction::new(1, 12)));
+
+        // Without a base-2 term the coefficient absorbs the octaves
+        let fifth = Value::rational(3, 1).pow(&Value::rational(1, 2)).mul(&Value::rational(1, 4));
+        let (folded, octaves) = fifth.octave_reduce().unwrap();
+        assert_eq!(octaves, -2);
+        assert!(folded.is_symbolic());
+        assert!((folded.to_f64() - 3f64.sqrt()).abs() < 1e-12);
+
+        let (folded, octaves) = Value::irrational(0.75).octave_reduce().unwrap();
+        assert_eq!((folded.to_f64(), octaves), (1.5, -1));
+        assert!(Value::rational(0, 1).octave_reduce().is_none());
+        assert!(Value::irrational(-2.0).octave_reduce().is_none());
+        assert!(tone.neg().octave_reduce().is_none());
+    }
 }
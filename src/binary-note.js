@@ -12,6 +12,7 @@ export const OP = {
   LOAD_REF:       0x02,  // Push note reference: [noteId_hi, noteId_lo, varIndex]
   LOAD_BASE:      0x03,  // Push baseNote variable: [varIndex]
   LOAD_CONST_BIG: 0x04,  // Push BigInt Fraction: [sign(1), num_len(2), num_bytes(n), den_len(2), den_bytes(n)]
+  LOAD_REF_WIDE:  0x05,  // Push note reference for ids above 65535: [noteId (4 bytes, big-endian), varIndex]
 
   // Arithmetic operations
   ADD:            0x10,  // Pop 2, push sum
@@ -20,15 +21,33 @@ export const OP = {
   DIV:            0x13,  // Pop 2, push quotient
   NEG:            0x14,  // Pop 1, push negation
   POW:            0x15,  // Pop 2 (base, exponent), push base^exponent (may corrupt to irrational)
+  MOD:            0x16,  // Pop 2, push remainder (sign of dividend)
+  MIN:            0x1F,  // Pop 2, push the smaller
+  MAX:            0x24,  // Pop 2, push the larger
+  SQRT:           0x17,  // Pop 1, push square root (stays rational for perfect squares)
+  ROOT:           0x18,  // Pop 2 (value, integer n), push n-th root
+  LOG:            0x19,  // Pop 2 (value, base), push log_base(value)
+  ABS:            0x1A,  // Pop 1, push absolute value
+  SIGN:           0x1B,  // Pop 1, push -1, 0 or 1
 
   // Module lookup operations
   FIND_TEMPO:     0x20,  // Pop noteRef, push tempo lookup result
   FIND_MEASURE:   0x21,  // Pop noteRef, push measureLength lookup result
   FIND_INSTRUMENT: 0x22, // Pop noteRef, push instrument lookup result
+  FIND_BEAT:      0x23,  // Pop noteRef, push 60/tempo (one beat in seconds)
 
   // Stack operations
   DUP:            0x30,  // Duplicate top of stack
   SWAP:           0x31,  // Swap top two stack values
+  OVER:           0x33,  // Copy second value to top (a b -> a b a)
+  ROT:            0x34,  // Rotate top three values (a b c -> b c a)
+  DROP:           0x35,  // Discard top of stack
+  CALL_MACRO:     0x36,  // Run a registered macro on the stack: [macroId_hi, macroId_lo]
+
+  // Rounding operations (results are always rational integers)
+  FLOOR:          0x1C,  // Pop 1, push largest integer <= value
+  CEIL:           0x1D,  // Pop 1, push smallest integer >= value
+  ROUND:          0x1E,  // Pop 1, push nearest integer (halves away from zero)
 };
 
 // Variable indices for compact storage
@@ -257,10 +276,27 @@ export class BinaryExpression {
           break;
         }
 
+        case OP.LOAD_REF_WIDE: {
+          // [noteId (4 bytes), varIndex]
+          const noteId = ((bytecode[pc] << 24) | (bytecode[pc + 1] << 16) | (bytecode[pc + 2] << 8) | bytecode[pc + 3]) >>> 0;
+          pc += 4;
+          const varIdx = bytecode[pc++];
+
+          if (!deps.has(noteId)) {
+            deps.set(noteId, new Set());
+          }
+          deps.get(noteId).add(varIdx);
+          break;
+        }
+
         case OP.LOAD_BASE:
           pc += 1; // Skip varIndex
           break;
 
+        case OP.CALL_MACRO:
+          pc += 2; // Skip macroId
+          break;
+
         // All other ops have no operands
         default:
           break;
@@ -57,13 +57,18 @@ export function toFractionJS(wasmFraction) {
     return wasmFraction;
   }
 
-  // Convert WASM Fraction to fraction.js. numeratorStr()/denominatorStr()
-  // are exact at any magnitude (the .n/.d getters saturate at u32::MAX);
-  // numeratorStr() is the absolute value, so the sign comes from .s.
+  // Convert WASM Fraction to fraction.js. The string accessors are exact at
+  // any magnitude (the .n/.d getters saturate at u32::MAX). numeratorAbsStr()
+  // is the magnitude; builds that predate it return the magnitude from
+  // numeratorStr() instead. Either way the sign comes from .s.
   const sign = wasmFraction.s < 0 ? '-' : '';
-  if (typeof wasmFraction.numeratorStr === 'function' &&
-      typeof wasmFraction.denominatorStr === 'function') {
-    return new FractionJS(`${sign}${wasmFraction.numeratorStr()}/${wasmFraction.denominatorStr()}`);
+  if (typeof wasmFraction.denominatorStr === 'function') {
+    if (typeof wasmFraction.numeratorAbsStr === 'function') {
+      return new FractionJS(`${sign}${wasmFraction.numeratorAbsStr()}/${wasmFraction.denominatorStr()}`);
+    }
+    if (typeof wasmFraction.numeratorStr === 'function') {
+      return new FractionJS(`${sign}${wasmFraction.numeratorStr()}/${wasmFraction.denominatorStr()}`);
+    }
   }
   return new FractionJS(`${sign}${wasmFraction.n}/${wasmFraction.d}`);
 }